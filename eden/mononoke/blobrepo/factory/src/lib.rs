@@ -145,6 +145,7 @@ impl<'a> BlobrepoBuilder<'a> {
             storage_config.dbconfig,
             mysql_options,
             readonly_storage,
+            None,
             // FIXME: remove clone when mysql_sql_factory is async-await
             logger.clone(),
         )
@@ -44,7 +44,8 @@ use mercurial_types::{
         ChangesetMetadata, ContentBlobMeta, HgBlobChangeset, HgBlobEntry, HgBlobEnvelope,
         HgChangesetContent, UploadHgFileContents, UploadHgFileEntry, UploadHgNodeHash,
     },
-    Globalrev, HgChangesetId, HgFileNodeId, HgManifestId, HgNodeHash, HgParents, RepoPath, Type,
+    Globalrev, GlobalrevPrefix, GlobalrevsResolvedFromPrefix, HgChangesetId, HgFileNodeId,
+    HgManifestId, HgNodeHash, HgParents, RepoPath, Type,
 };
 use metaconfig_types::DerivedDataConfig;
 use mononoke_types::{
@@ -380,6 +381,15 @@ impl BlobRepo {
             .get_globalrev_from_bonsai(self.repoid, bcs)
     }
 
+    pub fn get_globalrevs_by_prefix(
+        &self,
+        prefix: GlobalrevPrefix,
+        limit: usize,
+    ) -> BoxFuture<GlobalrevsResolvedFromPrefix, Error> {
+        self.bonsai_globalrev_mapping
+            .get_many_by_prefix(self.repoid, prefix, limit)
+    }
+
     pub fn get_bonsai_globalrev_mapping(
         &self,
         bonsai_or_globalrev_ids: impl Into<BonsaisOrGlobalrevs>,
@@ -2095,6 +2105,19 @@ impl DangerousOverride<Arc<dyn Changesets>> for BlobRepo {
     }
 }
 
+impl DangerousOverride<Arc<dyn BonsaiHgMapping>> for BlobRepo {
+    fn dangerous_override<F>(&self, modify: F) -> Self
+    where
+        F: FnOnce(Arc<dyn BonsaiHgMapping>) -> Arc<dyn BonsaiHgMapping>,
+    {
+        let bonsai_hg_mapping = modify(self.bonsai_hg_mapping.clone());
+        BlobRepo {
+            bonsai_hg_mapping,
+            ..self.clone()
+        }
+    }
+}
+
 impl DangerousOverride<DerivedDataConfig> for BlobRepo {
     fn dangerous_override<F>(&self, modify: F) -> Self
     where
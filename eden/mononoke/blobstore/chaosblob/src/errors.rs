@@ -15,4 +15,6 @@ pub enum ErrorKind {
     InjectedChaosPut(String),
     #[error("Injected failure in is_present to ChaosBlobstore for key {0}")]
     InjectedChaosIsPresent(String),
+    #[error("Invalid chaos error kind {0}, expected 'error' or 'timeout'")]
+    InvalidChaosErrorKind(String),
 }
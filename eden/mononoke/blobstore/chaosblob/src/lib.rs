@@ -8,11 +8,14 @@
 use anyhow::Error;
 use blobstore::Blobstore;
 use context::CoreContext;
-use futures::future;
+use futures::{future, Future};
 use futures_ext::{BoxFuture, FutureExt};
+use futures_util::future::{FutureExt as _, TryFutureExt};
 use mononoke_types::BlobstoreBytes;
-use rand::{thread_rng, Rng};
+use rand::{distributions::Uniform, thread_rng, Rng};
 use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::time::Duration;
 
 mod errors;
 pub use crate::errors::ErrorKind;
@@ -20,10 +23,40 @@ pub use crate::errors::ErrorKind;
 const NEVER_CHAOS_THRESHOLD: f32 = 1.0;
 const ALWAYS_CHAOS_THRESHOLD: f32 = -1.0;
 
-#[derive(Clone, Copy, Debug)]
+/// How an injected failure shows up to the caller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChaosErrorKind {
+    /// The operation fails immediately with an error.
+    Error,
+    /// The operation never completes, as if the underlying store stopped responding.
+    Timeout,
+}
+
+impl Default for ChaosErrorKind {
+    fn default() -> Self {
+        ChaosErrorKind::Error
+    }
+}
+
+impl FromStr for ChaosErrorKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(ChaosErrorKind::Error),
+            "timeout" => Ok(ChaosErrorKind::Timeout),
+            _ => Err(ErrorKind::InvalidChaosErrorKind(s.to_string()).into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct ChaosOptions {
     error_sample_read: Option<NonZeroU32>,
     error_sample_write: Option<NonZeroU32>,
+    error_kind: ChaosErrorKind,
+    latency_ms: Option<(u64, u64)>,
+    key_prefix_filter: Option<String>,
 }
 
 impl ChaosOptions {
@@ -36,11 +69,36 @@ impl ChaosOptions {
         Self {
             error_sample_read,
             error_sample_write,
+            error_kind: ChaosErrorKind::default(),
+            latency_ms: None,
+            key_prefix_filter: None,
+        }
+    }
+
+    /// Set how injected failures show up. Defaults to `ChaosErrorKind::Error`.
+    pub fn with_error_kind(self, error_kind: ChaosErrorKind) -> Self {
+        Self { error_kind, ..self }
+    }
+
+    /// Delay every operation by a duration picked uniformly from the inclusive range between
+    /// the two bounds of `latency_ms`, which may be given in either order.
+    pub fn with_latency_ms(self, latency_ms: Option<(u64, u64)>) -> Self {
+        let latency_ms = latency_ms.map(|(a, b)| (a.min(b), a.max(b)));
+        Self { latency_ms, ..self }
+    }
+
+    /// Only inject failures and latency for keys starting with `key_prefix_filter`.
+    pub fn with_key_prefix_filter(self, key_prefix_filter: Option<String>) -> Self {
+        Self {
+            key_prefix_filter,
+            ..self
         }
     }
 
     pub fn has_chaos(&self) -> bool {
-        self.error_sample_read.is_some() || self.error_sample_write.is_some()
+        self.error_sample_read.is_some()
+            || self.error_sample_write.is_some()
+            || self.latency_ms.is_some()
     }
 }
 
@@ -77,37 +135,89 @@ impl<T: Blobstore + Clone> ChaosBlobstore<T> {
             options,
         }
     }
+
+    fn matches_key(&self, key: &str) -> bool {
+        match &self.options.key_prefix_filter {
+            Some(prefix) => key.starts_with(prefix.as_str()),
+            None => true,
+        }
+    }
+
+    /// Run `op` for `key`, injecting latency and failures as configured. `error` builds the
+    /// error to return for an injected failure.
+    fn with_chaos<R: Send + 'static>(
+        &self,
+        key: String,
+        sample_threshold: f32,
+        error: impl FnOnce(String) -> ErrorKind,
+        op: impl FnOnce(String) -> BoxFuture<R, Error>,
+    ) -> BoxFuture<R, Error> {
+        if !self.matches_key(&key) {
+            return op(key);
+        }
+
+        let should_error = thread_rng().gen::<f32>() > sample_threshold;
+        let fut = if should_error {
+            match self.options.error_kind {
+                ChaosErrorKind::Error => future::err(error(key).into()).boxify(),
+                ChaosErrorKind::Timeout => future::empty().boxify(),
+            }
+        } else {
+            op(key)
+        };
+
+        match self.options.latency_ms {
+            Some((min_ms, max_ms)) => {
+                let latency = thread_rng().sample(Uniform::new_inclusive(min_ms, max_ms));
+                delay(Duration::from_millis(latency), fut).boxify()
+            }
+            None => fut,
+        }
+    }
+}
+
+fn delay<F>(latency: Duration, target: F) -> impl Future<Item = F::Item, Error = Error>
+where
+    F: Future<Error = Error>,
+{
+    async move {
+        tokio::time::delay_for(latency).await;
+        Ok(())
+    }
+    .boxed()
+    .compat()
+    .and_then(|()| target)
 }
 
 impl<T: Blobstore + Clone> Blobstore for ChaosBlobstore<T> {
     #[inline]
     fn get(&self, ctx: CoreContext, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
-        let should_error = thread_rng().gen::<f32>() > self.sample_threshold_read;
-        if should_error {
-            future::err(ErrorKind::InjectedChaosGet(key).into()).boxify()
-        } else {
-            self.blobstore.get(ctx, key)
-        }
+        self.with_chaos(
+            key,
+            self.sample_threshold_read,
+            ErrorKind::InjectedChaosGet,
+            |key| self.blobstore.get(ctx, key),
+        )
     }
 
     #[inline]
     fn put(&self, ctx: CoreContext, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
-        let should_error = thread_rng().gen::<f32>() > self.sample_threshold_write;
-        if should_error {
-            future::err(ErrorKind::InjectedChaosPut(key).into()).boxify()
-        } else {
-            self.blobstore.put(ctx, key, value)
-        }
+        self.with_chaos(
+            key,
+            self.sample_threshold_write,
+            ErrorKind::InjectedChaosPut,
+            |key| self.blobstore.put(ctx, key, value),
+        )
     }
 
     #[inline]
     fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
-        let should_error = thread_rng().gen::<f32>() > self.sample_threshold_read;
-        if should_error {
-            future::err(ErrorKind::InjectedChaosIsPresent(key).into()).boxify()
-        } else {
-            self.blobstore.is_present(ctx, key)
-        }
+        self.with_chaos(
+            key,
+            self.sample_threshold_read,
+            ErrorKind::InjectedChaosIsPresent,
+            |key| self.blobstore.is_present(ctx, key),
+        )
     }
 }
 
@@ -116,6 +226,8 @@ mod test {
     use super::*;
     use fbinit::FacebookInit;
     use futures::Future;
+    use futures_util::compat::Future01CompatExt;
+    use std::time::Instant;
 
     use memblob::EagerMemblob;
 
@@ -162,4 +274,130 @@ mod test {
         let r = wrapper.get(ctx.clone(), key.clone()).wait();
         assert!(!r.is_ok());
     }
+
+    #[fbinit::test]
+    async fn test_latency(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let wrapper = ChaosBlobstore::new(
+            EagerMemblob::new(),
+            ChaosOptions::new(None, None).with_latency_ms(Some((60, 50))),
+        );
+        assert_eq!(wrapper.options.latency_ms, Some((50, 60)));
+        let key = "foobar".to_string();
+
+        let start = Instant::now();
+        wrapper
+            .put(
+                ctx.clone(),
+                key.clone(),
+                BlobstoreBytes::from_bytes("test foobar"),
+            )
+            .compat()
+            .await?;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let start = Instant::now();
+        assert!(wrapper.get(ctx, key).compat().await?.is_some());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_key_prefix_filter(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let wrapper = ChaosBlobstore::new(
+            EagerMemblob::new(),
+            ChaosOptions::new(NonZeroU32::new(1), NonZeroU32::new(1))
+                .with_key_prefix_filter(Some("chaos.".to_string())),
+        );
+        let bytes = BlobstoreBytes::from_bytes("test foobar");
+
+        wrapper
+            .put(ctx.clone(), "calm.foo".to_string(), bytes.clone())
+            .compat()
+            .await?;
+        assert!(wrapper
+            .get(ctx.clone(), "calm.foo".to_string())
+            .compat()
+            .await?
+            .is_some());
+
+        assert!(wrapper
+            .put(ctx.clone(), "chaos.foo".to_string(), bytes)
+            .compat()
+            .await
+            .is_err());
+        assert!(wrapper
+            .get(ctx.clone(), "chaos.foo".to_string())
+            .compat()
+            .await
+            .is_err());
+        assert!(wrapper
+            .is_present(ctx, "chaos.foo".to_string())
+            .compat()
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_target_gets_and_puts(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let base = EagerMemblob::new();
+        let bytes = BlobstoreBytes::from_bytes("test foobar");
+        let key = "foobar".to_string();
+        base.put(ctx.clone(), key.clone(), bytes.clone())
+            .compat()
+            .await?;
+
+        let gets_only =
+            ChaosBlobstore::new(base.clone(), ChaosOptions::new(NonZeroU32::new(1), None));
+        assert!(gets_only
+            .put(ctx.clone(), key.clone(), bytes.clone())
+            .compat()
+            .await
+            .is_ok());
+        assert!(gets_only
+            .get(ctx.clone(), key.clone())
+            .compat()
+            .await
+            .is_err());
+
+        let puts_only =
+            ChaosBlobstore::new(base.clone(), ChaosOptions::new(None, NonZeroU32::new(1)));
+        assert!(puts_only
+            .put(ctx.clone(), key.clone(), bytes)
+            .compat()
+            .await
+            .is_err());
+        assert!(puts_only.get(ctx, key).compat().await?.is_some());
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_timeout(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let wrapper = ChaosBlobstore::new(
+            EagerMemblob::new(),
+            ChaosOptions::new(NonZeroU32::new(1), None).with_error_kind(ChaosErrorKind::Timeout),
+        );
+
+        let res = tokio::time::timeout(
+            Duration::from_millis(100),
+            wrapper.get(ctx, "foobar".to_string()).compat(),
+        )
+        .await;
+        assert!(res.is_err());
+
+        assert_eq!(
+            "timeout".parse::<ChaosErrorKind>()?,
+            ChaosErrorKind::Timeout
+        );
+        assert!("sometimes".parse::<ChaosErrorKind>().is_err());
+
+        Ok(())
+    }
 }
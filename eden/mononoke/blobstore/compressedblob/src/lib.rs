@@ -0,0 +1,194 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+#![deny(warnings)]
+
+use anyhow::{Error, Result};
+use bytes::{BufMut, BytesMut};
+use futures::Future;
+use futures_ext::{try_boxfuture, BoxFuture, FutureExt};
+
+use blobstore::Blobstore;
+use context::CoreContext;
+use mononoke_types::BlobstoreBytes;
+
+mod errors;
+pub use crate::errors::ErrorKind;
+
+/// First byte of every compressed blob
+const COMPRESSED_ZSTD: u8 = 0xc5;
+/// Magic number starting every zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Let zstd pick its default compression level
+const ZSTD_LEVEL: i32 = 0;
+
+/// Whether `bytes` starts like a blob compressed by `CompressedBlobstore`
+fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.len() > ZSTD_MAGIC.len()
+        && bytes[0] == COMPRESSED_ZSTD
+        && bytes[1..1 + ZSTD_MAGIC.len()] == ZSTD_MAGIC
+}
+
+/// A layer over an existing blobstore that compresses blobs with zstd on `put`, and
+/// decompresses them on `get`. Compressed blobs are stored as a header byte followed by a zstd
+/// frame. Blobs that don't start that way are returned as they are, so that blobs stored
+/// before compression was enabled still read. Blobs that compression doesn't make smaller are
+/// stored uncompressed, unless they would be mistaken for compressed blobs.
+#[derive(Clone, Debug)]
+pub struct CompressedBlobstore<T: Blobstore + Clone> {
+    blobstore: T,
+}
+
+impl<T: Blobstore + Clone> CompressedBlobstore<T> {
+    pub fn new(blobstore: T) -> Self {
+        Self { blobstore }
+    }
+}
+
+fn compress(value: BlobstoreBytes) -> Result<BlobstoreBytes> {
+    let bytes = value.as_bytes();
+    let compressed = zstd::encode_all(&bytes[..], ZSTD_LEVEL)?;
+    if compressed.len() + 1 >= bytes.len() && !is_compressed(bytes) {
+        return Ok(value);
+    }
+
+    let mut stored = BytesMut::with_capacity(1 + compressed.len());
+    stored.put_u8(COMPRESSED_ZSTD);
+    stored.put_slice(&compressed);
+    Ok(BlobstoreBytes::from_bytes(stored.freeze()))
+}
+
+fn decompress(key: &str, value: BlobstoreBytes) -> Result<BlobstoreBytes> {
+    let bytes = value.as_bytes();
+    if !is_compressed(bytes) {
+        return Ok(value);
+    }
+    let decompressed = zstd::decode_all(&bytes[1..])
+        .map_err(|_| ErrorKind::DecompressionFailed(key.to_string()))?;
+    Ok(BlobstoreBytes::from_bytes(decompressed))
+}
+
+impl<T: Blobstore + Clone> Blobstore for CompressedBlobstore<T> {
+    fn get(&self, ctx: CoreContext, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        self.blobstore
+            .get(ctx, key.clone())
+            .and_then(move |value| value.map(|value| decompress(&key, value)).transpose())
+            .boxify()
+    }
+
+    fn put(&self, ctx: CoreContext, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        let compressed = try_boxfuture!(compress(value));
+        self.blobstore.put(ctx, key, compressed)
+    }
+
+    fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
+        self.blobstore.is_present(ctx, key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fbinit::FacebookInit;
+
+    use memblob::EagerMemblob;
+
+    #[fbinit::test]
+    fn test_roundtrip(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let base = EagerMemblob::new();
+        let compressed = CompressedBlobstore::new(base.clone());
+        let value = BlobstoreBytes::from_bytes("foobar".repeat(100));
+
+        // This is EagerMemblob (immediate future completion) so calling wait() is fine.
+        compressed
+            .put(ctx.clone(), "foobar".to_string(), value.clone())
+            .wait()?;
+        assert_eq!(
+            compressed.get(ctx.clone(), "foobar".to_string()).wait()?,
+            Some(value.clone())
+        );
+        assert!(compressed
+            .is_present(ctx.clone(), "foobar".to_string())
+            .wait()?);
+        assert_eq!(
+            compressed.get(ctx.clone(), "missing".to_string()).wait()?,
+            None
+        );
+
+        // The underlying store only sees the compressed blob.
+        let stored = base
+            .get(ctx.clone(), "foobar".to_string())
+            .wait()?
+            .expect("value should be present");
+        assert!(is_compressed(stored.as_bytes()));
+        assert!(stored.len() < value.len());
+
+        // Blobs that look compressed are stored compressed, so they read back unchanged.
+        let lookalike = BlobstoreBytes::from_bytes(
+            [&[COMPRESSED_ZSTD][..], &ZSTD_MAGIC[..], &b"not zstd"[..]].concat(),
+        );
+        compressed
+            .put(ctx.clone(), "lookalike".to_string(), lookalike.clone())
+            .wait()?;
+        assert_eq!(
+            compressed.get(ctx, "lookalike".to_string()).wait()?,
+            Some(lookalike)
+        );
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_uncompressed_blobs(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let base = EagerMemblob::new();
+        let compressed = CompressedBlobstore::new(base.clone());
+
+        // Blobs stored before compression was enabled read as they are.
+        let value = BlobstoreBytes::from_bytes("foobar".repeat(100));
+        base.put(ctx.clone(), "old".to_string(), value.clone())
+            .wait()?;
+        assert_eq!(
+            compressed.get(ctx.clone(), "old".to_string()).wait()?,
+            Some(value)
+        );
+
+        // Blobs that don't compress are stored uncompressed.
+        let value = BlobstoreBytes::from_bytes("abc");
+        compressed
+            .put(ctx.clone(), "small".to_string(), value.clone())
+            .wait()?;
+        assert_eq!(
+            base.get(ctx.clone(), "small".to_string()).wait()?,
+            Some(value.clone())
+        );
+        assert_eq!(
+            compressed.get(ctx.clone(), "small".to_string()).wait()?,
+            Some(value)
+        );
+
+        // Corrupt compressed blobs fail to read.
+        base.put(
+            ctx.clone(),
+            "corrupt".to_string(),
+            BlobstoreBytes::from_bytes(
+                [&[COMPRESSED_ZSTD][..], &ZSTD_MAGIC[..], &b"junk"[..]].concat(),
+            ),
+        )
+        .wait()?;
+        let err = compressed
+            .get(ctx, "corrupt".to_string())
+            .wait()
+            .expect_err("decompressing a corrupt blob should fail");
+        assert!(matches!(
+            err.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::DecompressionFailed(_))
+        ));
+        Ok(())
+    }
+}
@@ -13,6 +13,7 @@ use futures::{compat::Future01CompatExt, FutureExt as _, TryFutureExt};
 use futures_ext::{BoxFuture, FutureExt};
 use mononoke_types::BlobstoreBytes;
 use scopeguard::defer;
+use std::collections::HashMap;
 
 /// A layer over an existing blobstore that respects a CoreContext's blobstore concurrency
 #[derive(Clone, Debug)]
@@ -95,6 +96,31 @@ impl<T: Blobstore + Clone> Blobstore for ContextConcurrencyBlobstore<T> {
         .compat()
         .boxify()
     }
+
+    fn is_present_many(
+        &self,
+        ctx: CoreContext,
+        keys: Vec<String>,
+    ) -> BoxFuture<HashMap<String, bool>, Error> {
+        cloned!(self.blobstore);
+        async move {
+            let session = ctx.session().clone();
+
+            let permit = match session.blobstore_semaphore() {
+                Some(sem) => Some(sem.acquire().await),
+                None => None,
+            };
+
+            defer!({
+                drop(permit);
+            });
+
+            blobstore.is_present_many(ctx, keys).compat().await
+        }
+        .boxed()
+        .compat()
+        .boxify()
+    }
 }
 
 #[cfg(test)]
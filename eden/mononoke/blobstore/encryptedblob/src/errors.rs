@@ -0,0 +1,18 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ErrorKind {
+    #[error("Encryption keys must be 32 bytes long, got {0} bytes")]
+    InvalidKeyLength(usize),
+    #[error("Blob {0} is not in a supported encrypted format")]
+    UnsupportedFormat(String),
+    #[error("Failed to decrypt blob {0}: wrong key or corrupt blob")]
+    DecryptionFailed(String),
+}
@@ -0,0 +1,247 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+#![deny(warnings)]
+
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{Context, Error, Result};
+use bytes::{BufMut, BytesMut};
+use futures::Future;
+use futures_ext::{try_boxfuture, BoxFuture, FutureExt};
+use openssl::{
+    rand::rand_bytes,
+    symm::{decrypt_aead, encrypt_aead, Cipher},
+};
+
+use blobstore::Blobstore;
+use context::CoreContext;
+use mononoke_types::BlobstoreBytes;
+
+mod errors;
+pub use crate::errors::ErrorKind;
+
+const KEY_LEN: usize = 32;
+/// First byte of every encrypted blob, identifying the format of the rest
+const FORMAT_VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = 1 + NONCE_LEN + TAG_LEN;
+
+/// A 256-bit AES key. It is not printed by `Debug`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey(Arc<[u8; KEY_LEN]>);
+
+impl EncryptionKey {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != KEY_LEN {
+            return Err(ErrorKind::InvalidKeyLength(bytes.len()).into());
+        }
+        let mut key = [0; KEY_LEN];
+        key.copy_from_slice(bytes);
+        Ok(Self(Arc::new(key)))
+    }
+
+    /// Parse a hex-encoded key, ignoring surrounding whitespace
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let bytes = hex::decode(hex.trim()).context("Encryption key is not valid hex")?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EncryptionKey(<redacted>)")
+    }
+}
+
+/// A layer over an existing blobstore that encrypts blobs with AES-256-GCM on `put`, and
+/// decrypts them on `get`. Blob keys are authenticated along with the contents, so a blob
+/// copied to another key fails to decrypt. Stored blobs are laid out as a format version
+/// byte, the nonce, the authentication tag and the ciphertext.
+#[derive(Clone, Debug)]
+pub struct EncryptedBlobstore<T: Blobstore + Clone> {
+    blobstore: T,
+    key: EncryptionKey,
+}
+
+impl<T: Blobstore + Clone> EncryptedBlobstore<T> {
+    pub fn new(blobstore: T, key: EncryptionKey) -> Self {
+        Self { blobstore, key }
+    }
+
+    fn encrypt(&self, blob_key: &str, value: BlobstoreBytes) -> Result<BlobstoreBytes> {
+        let mut nonce = [0; NONCE_LEN];
+        rand_bytes(&mut nonce)?;
+        let mut tag = [0; TAG_LEN];
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key.0[..],
+            Some(&nonce[..]),
+            blob_key.as_bytes(),
+            value.as_bytes(),
+            &mut tag,
+        )?;
+
+        let mut encrypted = BytesMut::with_capacity(HEADER_LEN + ciphertext.len());
+        encrypted.put_u8(FORMAT_VERSION);
+        encrypted.put_slice(&nonce);
+        encrypted.put_slice(&tag);
+        encrypted.put_slice(&ciphertext);
+        Ok(BlobstoreBytes::from_bytes(encrypted.freeze()))
+    }
+
+    fn decrypt(&self, blob_key: &str, value: BlobstoreBytes) -> Result<BlobstoreBytes> {
+        let bytes = value.as_bytes();
+        if bytes.len() < HEADER_LEN || bytes[0] != FORMAT_VERSION {
+            return Err(ErrorKind::UnsupportedFormat(blob_key.to_string()).into());
+        }
+        let nonce = &bytes[1..1 + NONCE_LEN];
+        let tag = &bytes[1 + NONCE_LEN..HEADER_LEN];
+        let plaintext = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key.0[..],
+            Some(nonce),
+            blob_key.as_bytes(),
+            &bytes[HEADER_LEN..],
+            tag,
+        )
+        .map_err(|_| ErrorKind::DecryptionFailed(blob_key.to_string()))?;
+        Ok(BlobstoreBytes::from_bytes(plaintext))
+    }
+}
+
+impl<T: Blobstore + Clone> Blobstore for EncryptedBlobstore<T> {
+    fn get(&self, ctx: CoreContext, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        let this = self.clone();
+        self.blobstore
+            .get(ctx, key.clone())
+            .and_then(move |value| value.map(|value| this.decrypt(&key, value)).transpose())
+            .boxify()
+    }
+
+    fn put(&self, ctx: CoreContext, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        let encrypted = try_boxfuture!(self.encrypt(&key, value));
+        self.blobstore.put(ctx, key, encrypted)
+    }
+
+    fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
+        self.blobstore.is_present(ctx, key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Bytes;
+    use fbinit::FacebookInit;
+
+    use memblob::EagerMemblob;
+
+    fn key(byte: u8) -> EncryptionKey {
+        EncryptionKey::from_bytes(&[byte; KEY_LEN]).unwrap()
+    }
+
+    #[fbinit::test]
+    fn test_roundtrip(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let base = EagerMemblob::new();
+        let encrypted = EncryptedBlobstore::new(base.clone(), key(1));
+        let value = BlobstoreBytes::from_bytes("test foobar");
+
+        // This is EagerMemblob (immediate future completion) so calling wait() is fine.
+        encrypted
+            .put(ctx.clone(), "foobar".to_string(), value.clone())
+            .wait()?;
+        assert_eq!(
+            encrypted.get(ctx.clone(), "foobar".to_string()).wait()?,
+            Some(value)
+        );
+        assert!(encrypted
+            .is_present(ctx.clone(), "foobar".to_string())
+            .wait()?);
+        assert_eq!(
+            encrypted.get(ctx.clone(), "missing".to_string()).wait()?,
+            None
+        );
+
+        // The underlying store only sees the encrypted blob.
+        let stored = base
+            .get(ctx.clone(), "foobar".to_string())
+            .wait()?
+            .expect("value should be present");
+        assert_eq!(stored.len(), HEADER_LEN + "test foobar".len());
+        assert_ne!(
+            stored.as_bytes().slice(HEADER_LEN..),
+            Bytes::from("test foobar")
+        );
+
+        // The blob only decrypts under the key it was stored at.
+        base.put(ctx.clone(), "moved".to_string(), stored).wait()?;
+        let err = encrypted
+            .get(ctx, "moved".to_string())
+            .wait()
+            .expect_err("decrypting a moved blob should fail");
+        assert!(matches!(
+            err.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::DecryptionFailed(_))
+        ));
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_wrong_key(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let base = EagerMemblob::new();
+        EncryptedBlobstore::new(base.clone(), key(1))
+            .put(
+                ctx.clone(),
+                "foobar".to_string(),
+                BlobstoreBytes::from_bytes("test foobar"),
+            )
+            .wait()?;
+        base.put(
+            ctx.clone(),
+            "plain".to_string(),
+            BlobstoreBytes::from_bytes("not encrypted"),
+        )
+        .wait()?;
+
+        let encrypted = EncryptedBlobstore::new(base, key(2));
+        let err = encrypted
+            .get(ctx.clone(), "foobar".to_string())
+            .wait()
+            .expect_err("decrypting with the wrong key should fail");
+        assert!(matches!(
+            err.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::DecryptionFailed(_))
+        ));
+
+        let err = encrypted
+            .get(ctx, "plain".to_string())
+            .wait()
+            .expect_err("decrypting an unencrypted blob should fail");
+        assert!(matches!(
+            err.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::UnsupportedFormat(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_parsing() {
+        assert_eq!(
+            EncryptionKey::from_hex(&"01".repeat(KEY_LEN)).unwrap(),
+            key(1)
+        );
+        assert!(EncryptionKey::from_hex("0101").is_err());
+        assert!(EncryptionKey::from_hex("not hex").is_err());
+        assert_eq!(format!("{:?}", key(1)), "EncryptionKey(<redacted>)");
+    }
+}
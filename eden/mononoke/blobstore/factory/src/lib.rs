@@ -5,8 +5,11 @@
  * GNU General Public License version 2.
  */
 
-use std::num::NonZeroU64;
-use std::{path::PathBuf, sync::Arc};
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::Error;
 use cloned::cloned;
@@ -20,9 +23,12 @@ use futures_ext::{try_boxfuture, BoxFuture, FutureExt};
 
 use blobstore::ErrorKind;
 use blobstore::{Blobstore, DisabledBlob};
-use blobstore_sync_queue::SqlBlobstoreSyncQueue;
+use blobstore_sync_queue::{BlobstoreSyncQueue, NoopBlobstoreSyncQueue, SqlBlobstoreSyncQueue};
 use chaosblob::ChaosBlobstore;
+use compressedblob::CompressedBlobstore;
+use encryptedblob::EncryptedBlobstore;
 use fileblob::Fileblob;
+use gcsblob::GcsBlobstore;
 use itertools::Either;
 use manifoldblob::ThriftManifoldBlob;
 use metaconfig_types::{
@@ -33,7 +39,8 @@ use multiplexedblob::{LoggingScrubHandler, MultiplexedBlobstore, ScrubBlobstore,
 use prefixblob::PrefixBlobstore;
 use readonlyblob::ReadOnlyBlobstore;
 use scuba::ScubaSampleBuilder;
-use slog::Logger;
+use sizeguardblob::SizeGuardBlobstore;
+use slog::{error, Logger};
 use sql_ext::{
     create_sqlite_connections,
     facebook::{
@@ -47,6 +54,10 @@ use sqlblob::Sqlblob;
 use newfilenodes::NewFilenodesBuilder;
 use throttledblob::ThrottledBlob;
 
+mod spec;
+
+pub use spec::{make_blobstore_from_spec, parse_blobstore_spec};
+
 #[derive(Copy, Clone, PartialEq)]
 pub struct ReadOnlyStorage(pub bool);
 
@@ -56,7 +67,17 @@ pub enum Scrubbing {
     Disabled,
 }
 
-pub use chaosblob::ChaosOptions;
+/// What to do when the sync queue of a multiplexed blobstore can't be opened
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum QueueFallbackPolicy {
+    /// Fail to open the blobstore
+    Fail,
+    /// Open the blobstore without a queue, refusing writes. Scrubbing blobstores always fail.
+    ReadOnlyWithoutQueue,
+}
+
+pub use chaosblob::{ChaosErrorKind, ChaosOptions};
+pub use encryptedblob::EncryptionKey;
 pub use throttledblob::ThrottleOptions;
 
 #[derive(Clone, Debug)]
@@ -64,6 +85,14 @@ pub struct BlobstoreOptions {
     pub chaos_options: ChaosOptions,
     pub throttle_options: ThrottleOptions,
     pub manifold_api_key: Option<String>,
+    /// Encrypt blobs at rest with this key
+    pub encryption_key: Option<EncryptionKey>,
+    /// Reject puts of blobs larger than this many bytes
+    pub max_blob_size: Option<u64>,
+    /// Compress blobs at rest with zstd
+    pub compress: bool,
+    /// What multiplexed blobstores do when their sync queue can't be opened
+    pub queue_fallback: QueueFallbackPolicy,
 }
 
 impl BlobstoreOptions {
@@ -76,6 +105,35 @@ impl BlobstoreOptions {
             chaos_options,
             throttle_options,
             manifold_api_key,
+            encryption_key: None,
+            max_blob_size: None,
+            compress: false,
+            queue_fallback: QueueFallbackPolicy::Fail,
+        }
+    }
+
+    pub fn with_encryption_key(self, encryption_key: Option<EncryptionKey>) -> Self {
+        Self {
+            encryption_key,
+            ..self
+        }
+    }
+
+    pub fn with_max_blob_size(self, max_blob_size: Option<u64>) -> Self {
+        Self {
+            max_blob_size,
+            ..self
+        }
+    }
+
+    pub fn with_compression(self, compress: bool) -> Self {
+        Self { compress, ..self }
+    }
+
+    pub fn with_queue_fallback(self, queue_fallback: QueueFallbackPolicy) -> Self {
+        Self {
+            queue_fallback,
+            ..self
         }
     }
 }
@@ -102,8 +160,16 @@ trait SqlFactoryBase: Send + Sync {
     /// Open NewFilenodesBuilder, and return a tier name and the struct.
     fn open_filenodes(&self) -> BoxFuture<(String, NewFilenodesBuilder), Error>;
 
-    /// Creates connections to the db.
-    fn create_connections(&self, label: String) -> BoxFuture<SqlConnections, Error>;
+    /// Creates connections to the db. `pool_config` overrides the pool sizes the factory was
+    /// created with, if the connections use a pool at all.
+    fn create_connections(
+        &self,
+        label: String,
+        pool_config: Option<PoolSizeConfig>,
+    ) -> BoxFuture<SqlConnections, Error>;
+
+    /// Name of the db the factory connects to.
+    fn tier_name(&self) -> String;
 }
 
 struct XdbFactory {
@@ -112,6 +178,7 @@ struct XdbFactory {
     readonly: bool,
     mysql_options: MysqlOptions,
     sharded_filenodes: Option<ShardedFilenodesParams>,
+    pool_config: Option<PoolSizeConfig>,
 }
 
 impl XdbFactory {
@@ -121,6 +188,7 @@ impl XdbFactory {
         mysql_options: MysqlOptions,
         sharded_filenodes: Option<ShardedFilenodesParams>,
         readonly: bool,
+        pool_config: Option<PoolSizeConfig>,
     ) -> Self {
         XdbFactory {
             fb,
@@ -128,8 +196,17 @@ impl XdbFactory {
             readonly,
             mysql_options,
             sharded_filenodes,
+            pool_config,
         }
     }
+
+    /// Pool sizes to use for connections created by this factory: `pool_config` if given, then
+    /// the factory's own, falling back to the regular connection sizes.
+    fn pool_size_config(&self, pool_config: Option<PoolSizeConfig>) -> PoolSizeConfig {
+        pool_config
+            .or(self.pool_config)
+            .unwrap_or_else(PoolSizeConfig::for_regular_connection)
+    }
 }
 
 impl SqlFactoryBase for XdbFactory {
@@ -172,14 +249,18 @@ impl SqlFactoryBase for XdbFactory {
         filenodes.map(move |filenodes| (tier, filenodes)).boxify()
     }
 
-    fn create_connections(&self, label: String) -> BoxFuture<SqlConnections, Error> {
+    fn create_connections(
+        &self,
+        label: String,
+        pool_config: Option<PoolSizeConfig>,
+    ) -> BoxFuture<SqlConnections, Error> {
         match self.mysql_options.myrouter_port {
             Some(mysql_options) => future::ok(create_myrouter_connections(
                 self.db_address.clone(),
                 None,
                 mysql_options,
                 self.mysql_options.myrouter_read_service_type(),
-                PoolSizeConfig::for_regular_connection(),
+                self.pool_size_config(pool_config),
                 label,
                 self.readonly,
             ))
@@ -193,6 +274,10 @@ impl SqlFactoryBase for XdbFactory {
             .boxify(),
         }
     }
+
+    fn tier_name(&self) -> String {
+        self.db_address.clone()
+    }
 }
 
 struct SqliteFactory {
@@ -222,11 +307,19 @@ impl SqlFactoryBase for SqliteFactory {
             .boxify()
     }
 
-    fn create_connections(&self, _label: String) -> BoxFuture<SqlConnections, Error> {
+    fn create_connections(
+        &self,
+        _label: String,
+        _pool_config: Option<PoolSizeConfig>,
+    ) -> BoxFuture<SqlConnections, Error> {
         create_sqlite_connections(&self.path.join("sqlite_dbs"), self.readonly)
             .into_future()
             .boxify()
     }
+
+    fn tier_name(&self) -> String {
+        "sqlite".to_string()
+    }
 }
 
 pub struct SqlFactory {
@@ -250,22 +343,37 @@ impl SqlFactory {
             .either(|l| l.open_filenodes(), |r| r.open_filenodes())
     }
 
-    pub fn create_connections(&self, label: String) -> BoxFuture<SqlConnections, Error> {
+    pub fn create_connections(
+        &self,
+        label: String,
+        pool_config: Option<PoolSizeConfig>,
+    ) -> BoxFuture<SqlConnections, Error> {
         self.underlying.as_ref().either(
             {
                 cloned!(label);
-                move |l| l.create_connections(label)
+                move |l| l.create_connections(label, pool_config)
             },
-            |r| r.create_connections(label),
+            |r| r.create_connections(label, pool_config),
         )
     }
+
+    /// Name of the db this factory connects to, for logging.
+    pub fn tier_name(&self) -> String {
+        self.underlying
+            .as_ref()
+            .either(|l| l.tier_name(), |r| r.tier_name())
+    }
 }
 
+/// Create a `SqlFactory` for `dbconfig`. Connections created through it use pools sized by
+/// `pool_config`, or `PoolSizeConfig::for_regular_connection()` if that is `None`. Sqlite
+/// connections are not pooled, so `pool_config` does not affect them.
 pub fn make_sql_factory(
     fb: FacebookInit,
     dbconfig: MetadataDBConfig,
     mysql_options: MysqlOptions,
     readonly: ReadOnlyStorage,
+    pool_config: Option<PoolSizeConfig>,
     logger: Logger,
 ) -> impl Future<Item = SqlFactory, Error = Error> {
     match dbconfig {
@@ -286,6 +394,7 @@ pub fn make_sql_factory(
                 mysql_options,
                 sharded_filenodes,
                 readonly.0,
+                pool_config,
             );
             myrouter_ready(Some(db_address), mysql_options, logger)
                 .map(move |()| SqlFactory {
@@ -299,7 +408,14 @@ pub fn make_sql_factory(
 /// Construct a blobstore according to the specification. The multiplexed blobstore
 /// needs an SQL DB for its queue, as does the MySQL blobstore.
 /// If `throttling.read_qps` or `throttling.write_qps` are Some then ThrottledBlob will be used to limit
-/// QPS to the underlying blobstore
+/// QPS to the underlying blobstore, letting through bursts up to `throttling.read_burst` and
+/// `throttling.write_burst` when those are set.
+/// If `max_blob_size` is Some then SizeGuardBlobstore will be used to reject puts of blobs whose
+/// uncompressed, unencrypted size is larger, before they are throttled
+/// If `compress` is set then CompressedBlobstore will be used to compress blobs before they are
+/// encrypted
+/// If `queue_fallback` is ReadOnlyWithoutQueue then multiplexed blobstores whose sync queue can't
+/// be opened are opened read-only without it
 pub fn make_blobstore(
     fb: FacebookInit,
     blobconfig: BlobConfig,
@@ -398,6 +514,7 @@ pub fn make_blobstore(
             scuba_sample_rate,
             blobstores,
             scrub_action,
+            scrub_repair_concurrency,
             queue_db,
         } => {
             has_components = true;
@@ -413,6 +530,7 @@ pub fn make_blobstore(
                 Some((
                     Arc::new(LoggingScrubHandler::new(false)) as Arc<dyn ScrubHandler>,
                     scrub_action,
+                    scrub_repair_concurrency,
                 )),
                 blobstore_options.clone(),
                 logger,
@@ -437,6 +555,17 @@ pub fn make_blobstore(
         .map(|store| Arc::new(store) as Arc<dyn Blobstore>)
         .into_future()
         .boxify(),
+
+        Gcs {
+            bucket,
+            prefix,
+            credentials,
+        } => make_gcs_blobstore(bucket, &prefix, credentials.as_deref())
+            .chain_err(ErrorKind::StateOpen)
+            .map_err(Error::from)
+            .map(|store| Arc::new(store) as Arc<dyn Blobstore>)
+            .into_future()
+            .boxify(),
     };
 
     let store = if readonly_storage.0 {
@@ -463,18 +592,52 @@ pub fn make_blobstore(
         store
     };
 
+    let encryption_key = blobstore_options.encryption_key.clone();
+    let compress = blobstore_options.compress;
+    let max_blob_size = blobstore_options.max_blob_size;
+
     // For stores with components only set chaos on their components
     let store = if !has_components && blobstore_options.chaos_options.has_chaos() {
         store
             .map(move |inner| {
-                Arc::new(ChaosBlobstore::new(inner, blobstore_options.chaos_options))
-                    as Arc<dyn Blobstore>
+                Arc::new(ChaosBlobstore::new(
+                    inner,
+                    blobstore_options.chaos_options.clone(),
+                )) as Arc<dyn Blobstore>
             })
             .boxify()
     } else {
         store
     };
 
+    // Stores with components are encrypted as a whole, their components are not encrypted again
+    let store = match encryption_key {
+        Some(key) => store
+            .map(move |inner| Arc::new(EncryptedBlobstore::new(inner, key)) as Arc<dyn Blobstore>)
+            .boxify(),
+        None => store,
+    };
+
+    // Blobs are compressed before they are encrypted, as encrypted blobs don't compress
+    let store = if compress {
+        store
+            .map(|inner| Arc::new(CompressedBlobstore::new(inner)) as Arc<dyn Blobstore>)
+            .boxify()
+    } else {
+        store
+    };
+
+    // Blobs are size checked before compression and encryption, so that the limit applies to
+    // their logical size rather than to the bytes that end up stored
+    let store = match max_blob_size {
+        Some(max_size) => store
+            .map(move |inner| {
+                Arc::new(SizeGuardBlobstore::new(inner, max_size)) as Arc<dyn Blobstore>
+            })
+            .boxify(),
+        None => store,
+    };
+
     // NOTE: Do not add wrappers here that should only be added once per repository, since this
     // function will get called recursively for each member of a Multiplex! For those, use
     // RepoBlobstoreArgs::new instead.
@@ -482,6 +645,15 @@ pub fn make_blobstore(
     store
 }
 
+fn make_gcs_blobstore(
+    bucket: String,
+    prefix: &str,
+    credentials: Option<&Path>,
+) -> Result<PrefixBlobstore<GcsBlobstore>, Error> {
+    let gcs = GcsBlobstore::new(bucket, credentials.map(Path::to_path_buf))?;
+    Ok(PrefixBlobstore::new(gcs, prefix))
+}
+
 pub fn make_blobstore_multiplexed(
     fb: FacebookInit,
     multiplex_id: MultiplexId,
@@ -491,16 +663,27 @@ pub fn make_blobstore_multiplexed(
     inner_config: Vec<(BlobstoreId, BlobConfig)>,
     mysql_options: MysqlOptions,
     readonly_storage: ReadOnlyStorage,
-    scrub_args: Option<(Arc<dyn ScrubHandler>, ScrubAction)>,
+    scrub_args: Option<(Arc<dyn ScrubHandler>, ScrubAction, Option<NonZeroUsize>)>,
     blobstore_options: BlobstoreOptions,
     logger: Logger,
 ) -> BoxFuture<Arc<dyn Blobstore>, Error> {
     let component_readonly = match &scrub_args {
         // Need to write to components to repair them.
-        Some((_, ScrubAction::Repair)) => ReadOnlyStorage(false),
+        Some((_, ScrubAction::Repair, _)) => ReadOnlyStorage(false),
         _ => readonly_storage,
     };
 
+    let queue_fallback = match &scrub_args {
+        // Scrubbing relies on the queue to tell missing blobs from ones still being written.
+        Some(_) => QueueFallbackPolicy::Fail,
+        None => blobstore_options.queue_fallback,
+    };
+
+    // The multiplexed store is compressed, encrypted and size guarded by the caller
+    let blobstore_options = blobstore_options
+        .with_encryption_key(None)
+        .with_max_blob_size(None)
+        .with_compression(false);
     let mut applied_chaos = false;
     let components: Vec<_> = inner_config
         .into_iter()
@@ -532,38 +715,406 @@ pub fn make_blobstore_multiplexed(
         })
         .collect();
 
-    let queue = make_sql_factory(fb, queue_db, mysql_options, readonly_storage, logger)
-        .and_then(|sql_factory| sql_factory.open::<SqlBlobstoreSyncQueue>());
+    let queue = make_sql_factory(
+        fb,
+        queue_db,
+        mysql_options,
+        readonly_storage,
+        None,
+        logger.clone(),
+    )
+    .and_then(|sql_factory| sql_factory.open::<SqlBlobstoreSyncQueue>())
+    .map(|queue| (queue as Arc<dyn BlobstoreSyncQueue>, false))
+    .or_else(move |err| match queue_fallback {
+        QueueFallbackPolicy::Fail => Err(err),
+        QueueFallbackPolicy::ReadOnlyWithoutQueue => {
+            error!(
+                logger,
+                "Failed to open the sync queue of multiplexed blobstore {}, it is READ-ONLY until \
+                 the queue is available: {:?}",
+                multiplex_id,
+                err
+            );
+            let queue = Arc::new(NoopBlobstoreSyncQueue::new()) as Arc<dyn BlobstoreSyncQueue>;
+            Ok((queue, true))
+        }
+    });
 
     queue
         .and_then({
-            move |queue| {
+            move |(queue, without_queue)| {
                 future::join_all(components).map({
-                    move |components| match scrub_args {
-                        Some((scrub_handler, scrub_action)) => Arc::new(ScrubBlobstore::new(
-                            multiplex_id,
-                            components,
-                            queue,
-                            scuba_table.map_or(ScubaSampleBuilder::with_discard(), |table| {
-                                ScubaSampleBuilder::new(fb, table)
-                            }),
-                            scuba_sample_rate,
-                            scrub_handler,
-                            scrub_action,
-                        ))
-                            as Arc<dyn Blobstore>,
-                        None => Arc::new(MultiplexedBlobstore::new(
-                            multiplex_id,
-                            components,
-                            queue,
-                            scuba_table.map_or(ScubaSampleBuilder::with_discard(), |table| {
-                                ScubaSampleBuilder::new(fb, table)
-                            }),
-                            scuba_sample_rate,
-                        )) as Arc<dyn Blobstore>,
+                    move |components| {
+                        let store = match scrub_args {
+                            Some((scrub_handler, scrub_action, scrub_repair_concurrency)) => {
+                                Arc::new(ScrubBlobstore::new(
+                                    multiplex_id,
+                                    components,
+                                    queue,
+                                    scuba_table
+                                        .map_or(ScubaSampleBuilder::with_discard(), |table| {
+                                            ScubaSampleBuilder::new(fb, table)
+                                        }),
+                                    scuba_sample_rate,
+                                    scrub_handler,
+                                    scrub_action,
+                                    scrub_repair_concurrency,
+                                )) as Arc<dyn Blobstore>
+                            }
+                            None => Arc::new(MultiplexedBlobstore::new(
+                                multiplex_id,
+                                components,
+                                queue,
+                                scuba_table.map_or(ScubaSampleBuilder::with_discard(), |table| {
+                                    ScubaSampleBuilder::new(fb, table)
+                                }),
+                                scuba_sample_rate,
+                            )) as Arc<dyn Blobstore>,
+                        };
+                        if without_queue {
+                            Arc::new(ReadOnlyBlobstore::new(store)) as Arc<dyn Blobstore>
+                        } else {
+                            store
+                        }
                     }
                 })
             }
         })
         .boxify()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use context::CoreContext;
+    use mononoke_types::BlobstoreBytes;
+    use slog::{o, Discard};
+    use tempdir::TempDir;
+
+    fn mysql_options() -> MysqlOptions {
+        MysqlOptions {
+            myrouter_port: Some(0),
+            master_only: false,
+        }
+    }
+
+    #[fbinit::test]
+    fn test_xdb_pool_size_config(fb: FacebookInit) {
+        let regular = PoolSizeConfig::for_regular_connection();
+        let sharded = PoolSizeConfig::for_sharded_connection();
+
+        let factory = XdbFactory::new(fb, "db".to_string(), mysql_options(), None, false, None);
+        let config = factory.pool_size_config(None);
+        assert_eq!(config.read_pool_size, regular.read_pool_size);
+        assert_eq!(config.write_pool_size, regular.write_pool_size);
+        let config = factory.pool_size_config(Some(sharded));
+        assert_eq!(config.read_pool_size, sharded.read_pool_size);
+        assert_eq!(config.write_pool_size, sharded.write_pool_size);
+
+        let factory = XdbFactory::new(
+            fb,
+            "db".to_string(),
+            mysql_options(),
+            None,
+            false,
+            Some(sharded),
+        );
+        let config = factory.pool_size_config(None);
+        assert_eq!(config.read_pool_size, sharded.read_pool_size);
+        assert_eq!(config.write_pool_size, sharded.write_pool_size);
+        let config = factory.pool_size_config(Some(regular));
+        assert_eq!(config.read_pool_size, regular.read_pool_size);
+        assert_eq!(config.write_pool_size, regular.write_pool_size);
+        assert_eq!(factory.tier_name(), "db");
+    }
+
+    #[fbinit::test]
+    fn test_gcs_blobstore(fb: FacebookInit) -> Result<(), Error> {
+        let store = make_gcs_blobstore("bucket".to_string(), "repo0/", None)?;
+        assert_eq!(store.prepend("key".to_string()), "repo0/key");
+
+        // Creating the blobstore doesn't talk to GCS yet.
+        let logger = Logger::root(Discard, o!());
+        make_blobstore(
+            fb,
+            BlobConfig::Gcs {
+                bucket: "bucket".to_string(),
+                prefix: "repo0/".to_string(),
+                credentials: Some(PathBuf::from("/nonexistent/token")),
+            },
+            mysql_options(),
+            ReadOnlyStorage(true),
+            BlobstoreOptions::default(),
+            logger,
+        )
+        .wait()?;
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_encrypted_blobstore(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = TempDir::new("encrypted_blobstore")?;
+        let logger = Logger::root(Discard, o!());
+        let config = BlobConfig::Files {
+            path: dir.path().to_path_buf(),
+        };
+        let make = |blobstore_options| {
+            make_blobstore(
+                fb,
+                config.clone(),
+                mysql_options(),
+                ReadOnlyStorage(false),
+                blobstore_options,
+                logger.clone(),
+            )
+        };
+
+        let key = EncryptionKey::from_bytes(&[1; 32])?;
+        let encrypted = make(BlobstoreOptions::default().with_encryption_key(Some(key))).wait()?;
+        let value = BlobstoreBytes::from_bytes("value");
+        encrypted
+            .put(ctx.clone(), "key".to_string(), value.clone())
+            .wait()?;
+        assert_eq!(
+            encrypted.get(ctx.clone(), "key".to_string()).wait()?,
+            Some(value.clone())
+        );
+
+        let plain = make(BlobstoreOptions::default()).wait()?;
+        assert!(plain.is_present(ctx.clone(), "key".to_string()).wait()?);
+        assert_ne!(plain.get(ctx, "key".to_string()).wait()?, Some(value));
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_compressed_blobstore(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = TempDir::new("compressed_blobstore")?;
+        let logger = Logger::root(Discard, o!());
+        let config = BlobConfig::Files {
+            path: dir.path().to_path_buf(),
+        };
+        let make = |blobstore_options| {
+            make_blobstore(
+                fb,
+                config.clone(),
+                mysql_options(),
+                ReadOnlyStorage(false),
+                blobstore_options,
+                logger.clone(),
+            )
+        };
+
+        let plain = make(BlobstoreOptions::default()).wait()?;
+        let compressed = make(BlobstoreOptions::default().with_compression(true)).wait()?;
+
+        let old_value = BlobstoreBytes::from_bytes("old".repeat(100));
+        plain
+            .put(ctx.clone(), "old".to_string(), old_value.clone())
+            .wait()?;
+        assert_eq!(
+            compressed.get(ctx.clone(), "old".to_string()).wait()?,
+            Some(old_value)
+        );
+
+        let value = BlobstoreBytes::from_bytes("value".repeat(100));
+        compressed
+            .put(ctx.clone(), "key".to_string(), value.clone())
+            .wait()?;
+        assert_eq!(
+            compressed.get(ctx.clone(), "key".to_string()).wait()?,
+            Some(value.clone())
+        );
+        let stored = plain
+            .get(ctx, "key".to_string())
+            .wait()?
+            .expect("value should be present");
+        assert!(stored.len() < value.len());
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_queue_fallback(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = TempDir::new("queue_fallback")?;
+        let logger = Logger::root(Discard, o!());
+        // The queue can't be opened, as its directory is under a file.
+        let not_a_dir = dir.path().join("not_a_dir");
+        std::fs::write(&not_a_dir, "")?;
+        let queue_db = MetadataDBConfig::LocalDB {
+            path: not_a_dir.join("queue"),
+        };
+        let components: Vec<_> = (0..2)
+            .map(|id| {
+                (
+                    BlobstoreId::new(id),
+                    BlobConfig::Files {
+                        path: dir.path().join(format!("blobstore_{}", id)),
+                    },
+                )
+            })
+            .collect();
+        let make = |config, blobstore_options| {
+            make_blobstore(
+                fb,
+                config,
+                mysql_options(),
+                ReadOnlyStorage(false),
+                blobstore_options,
+                logger.clone(),
+            )
+        };
+
+        let value = BlobstoreBytes::from_bytes("value");
+        for (_, config) in &components {
+            make(config.clone(), BlobstoreOptions::default())
+                .wait()?
+                .put(ctx.clone(), "key".to_string(), value.clone())
+                .wait()?;
+        }
+
+        let multiplexed = BlobConfig::Multiplexed {
+            multiplex_id: MultiplexId::new(1),
+            scuba_table: None,
+            scuba_sample_rate: NonZeroU64::new(1).unwrap(),
+            blobstores: components.clone(),
+            queue_db: queue_db.clone(),
+        };
+        let fallback = BlobstoreOptions::default()
+            .with_queue_fallback(QueueFallbackPolicy::ReadOnlyWithoutQueue);
+
+        assert!(make(multiplexed.clone(), BlobstoreOptions::default())
+            .wait()
+            .is_err());
+
+        let store = make(multiplexed, fallback.clone()).wait()?;
+        assert_eq!(
+            store.get(ctx.clone(), "key".to_string()).wait()?,
+            Some(value.clone())
+        );
+        assert_eq!(store.get(ctx.clone(), "missing".to_string()).wait()?, None);
+        let err = store
+            .put(ctx.clone(), "new".to_string(), value)
+            .wait()
+            .expect_err("writes without a queue should be refused");
+        assert!(matches!(
+            err.downcast_ref::<readonlyblob::ErrorKind>(),
+            Some(readonlyblob::ErrorKind::ReadOnlyPut(_))
+        ));
+
+        // Scrubbing needs the queue whatever the policy.
+        let scrub = BlobConfig::Scrub {
+            multiplex_id: MultiplexId::new(1),
+            scuba_table: None,
+            scuba_sample_rate: NonZeroU64::new(1).unwrap(),
+            blobstores: components,
+            scrub_action: ScrubAction::ReportOnly,
+            scrub_repair_concurrency: None,
+            queue_db,
+        };
+        assert!(make(scrub, fallback).wait().is_err());
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_max_blob_size(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let dir = TempDir::new("max_blob_size")?;
+        let logger = Logger::root(Discard, o!());
+        let store = make_blobstore(
+            fb,
+            BlobConfig::Files {
+                path: dir.path().to_path_buf(),
+            },
+            mysql_options(),
+            ReadOnlyStorage(false),
+            BlobstoreOptions::default().with_max_blob_size(Some(5)),
+            logger,
+        )
+        .wait()?;
+
+        let value = BlobstoreBytes::from_bytes("value");
+        store
+            .put(ctx.clone(), "small".to_string(), value.clone())
+            .wait()?;
+        assert_eq!(
+            store.get(ctx.clone(), "small".to_string()).wait()?,
+            Some(value)
+        );
+
+        let err = store
+            .put(
+                ctx.clone(),
+                "large".to_string(),
+                BlobstoreBytes::from_bytes("values"),
+            )
+            .wait()
+            .expect_err("oversized put should be rejected");
+        assert!(matches!(
+            err.downcast_ref::<sizeguardblob::ErrorKind>(),
+            Some(sizeguardblob::ErrorKind::BlobTooLarge { .. })
+        ));
+        assert!(!store.is_present(ctx.clone(), "large".to_string()).wait()?);
+
+        // The limit applies to the logical size, even if the stored blob would be smaller.
+        let compressed = make_blobstore(
+            fb,
+            BlobConfig::Files {
+                path: dir.path().join("compressed"),
+            },
+            mysql_options(),
+            ReadOnlyStorage(false),
+            BlobstoreOptions::default()
+                .with_max_blob_size(Some(100))
+                .with_compression(true),
+            Logger::root(Discard, o!()),
+        )
+        .wait()?;
+        let err = compressed
+            .put(
+                ctx.clone(),
+                "compressible".to_string(),
+                BlobstoreBytes::from_bytes(vec![b'a'; 1000]),
+            )
+            .wait()
+            .expect_err("oversized put should be rejected before compression");
+        assert!(matches!(
+            err.downcast_ref::<sizeguardblob::ErrorKind>(),
+            Some(sizeguardblob::ErrorKind::BlobTooLarge { .. })
+        ));
+        assert!(!compressed
+            .is_present(ctx, "compressible".to_string())
+            .wait()?);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_sqlite_ignores_pool_config(fb: FacebookInit) -> Result<(), Error> {
+        let dir = TempDir::new("sql_factory")?;
+        let logger = Logger::root(Discard, o!());
+        let factory = make_sql_factory(
+            fb,
+            MetadataDBConfig::LocalDB {
+                path: dir.path().to_path_buf(),
+            },
+            mysql_options(),
+            ReadOnlyStorage(false),
+            Some(PoolSizeConfig::for_sharded_connection()),
+            logger,
+        )
+        .wait()?;
+        assert_eq!(factory.tier_name(), "sqlite");
+
+        factory
+            .create_connections("test".to_string(), None)
+            .wait()?;
+        factory
+            .create_connections(
+                "test".to_string(),
+                Some(PoolSizeConfig::for_regular_connection()),
+            )
+            .wait()?;
+        Ok(())
+    }
+}
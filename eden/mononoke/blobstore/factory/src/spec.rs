@@ -0,0 +1,218 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error, Result};
+use blobstore::Blobstore;
+use fbinit::FacebookInit;
+use futures_ext::{try_boxfuture, BoxFuture};
+use metaconfig_types::BlobConfig;
+use slog::Logger;
+use sql_ext::facebook::MysqlOptions;
+
+use crate::{make_blobstore, BlobstoreOptions, ReadOnlyStorage};
+
+const SUPPORTED_SPECS: &str =
+    "file:<path>, sqlite:<path>, manifold:<bucket>[/<prefix>] or disabled:";
+
+/// Parse a short description of a blobstore, as taken by command line tools, into the
+/// corresponding config. The supported forms are:
+///
+///  - `file:<path>` for a blobstore in on-disk files
+///  - `sqlite:<path>` for a blobstore in a SQLite database
+///  - `manifold:<bucket>[/<prefix>]` for a Manifold bucket, with an optional key prefix
+///  - `disabled:` for an administratively disabled blobstore
+///
+/// Trailing slashes in paths are ignored, while Manifold prefixes are kept as given, since
+/// they are prepended to keys as they are.
+pub fn parse_blobstore_spec(spec: &str) -> Result<BlobConfig> {
+    let (scheme, rest) = match spec.find(':') {
+        Some(idx) => (&spec[..idx], &spec[idx + 1..]),
+        None => bail!(
+            "malformed blobstore spec '{}', expected one of {}",
+            spec,
+            SUPPORTED_SPECS
+        ),
+    };
+
+    match scheme {
+        "file" => Ok(BlobConfig::Files {
+            path: parse_path(spec, rest)?,
+        }),
+        "sqlite" => Ok(BlobConfig::Sqlite {
+            path: parse_path(spec, rest)?,
+        }),
+        "manifold" => {
+            let (bucket, prefix) = match rest.find('/') {
+                Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+                None => (rest, ""),
+            };
+            if bucket.is_empty() {
+                bail!(
+                    "missing bucket in blobstore spec '{}', expected one of {}",
+                    spec,
+                    SUPPORTED_SPECS
+                );
+            }
+            Ok(BlobConfig::Manifold {
+                bucket: bucket.to_string(),
+                prefix: prefix.to_string(),
+            })
+        }
+        "disabled" if rest.is_empty() => Ok(BlobConfig::Disabled),
+        "disabled" => bail!(
+            "unexpected '{}' after 'disabled:' in blobstore spec, expected one of {}",
+            rest,
+            SUPPORTED_SPECS
+        ),
+        _ => bail!(
+            "unsupported blobstore scheme '{}' in spec '{}', expected one of {}",
+            scheme,
+            spec,
+            SUPPORTED_SPECS
+        ),
+    }
+}
+
+fn parse_path(spec: &str, path: &str) -> Result<PathBuf> {
+    let trimmed = path.trim_end_matches('/');
+    match (trimmed.is_empty(), path.is_empty()) {
+        (false, _) => Ok(PathBuf::from(trimmed)),
+        // The path was just slashes, so it's the root directory.
+        (true, false) => Ok(PathBuf::from("/")),
+        (true, true) => Err(format_err!(
+            "missing path in blobstore spec '{}', expected one of {}",
+            spec,
+            SUPPORTED_SPECS
+        )),
+    }
+}
+
+/// Construct the blobstore described by `spec` (see `parse_blobstore_spec`) with the default
+/// blobstore options.
+pub fn make_blobstore_from_spec(
+    fb: FacebookInit,
+    spec: &str,
+    readonly_storage: ReadOnlyStorage,
+    logger: Logger,
+) -> BoxFuture<Arc<dyn Blobstore>, Error> {
+    let blobconfig = try_boxfuture!(parse_blobstore_spec(spec));
+    // None of the supported specs are backed by MySQL.
+    let mysql_options = MysqlOptions {
+        myrouter_port: None,
+        master_only: false,
+    };
+    make_blobstore(
+        fb,
+        blobconfig,
+        mysql_options,
+        readonly_storage,
+        BlobstoreOptions::default(),
+        logger,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_err(spec: &str) -> String {
+        parse_blobstore_spec(spec)
+            .expect_err("spec should not parse")
+            .to_string()
+    }
+
+    #[test]
+    fn test_parse_file() {
+        assert_eq!(
+            parse_blobstore_spec("file:/var/repo").unwrap(),
+            BlobConfig::Files {
+                path: PathBuf::from("/var/repo")
+            }
+        );
+        assert_eq!(
+            parse_blobstore_spec("file:/var/repo//").unwrap(),
+            BlobConfig::Files {
+                path: PathBuf::from("/var/repo")
+            }
+        );
+        assert_eq!(
+            parse_blobstore_spec("file:relative/repo").unwrap(),
+            BlobConfig::Files {
+                path: PathBuf::from("relative/repo")
+            }
+        );
+        assert_eq!(
+            parse_blobstore_spec("file:/").unwrap(),
+            BlobConfig::Files {
+                path: PathBuf::from("/")
+            }
+        );
+        assert!(parse_err("file:").contains("missing path"));
+    }
+
+    #[test]
+    fn test_parse_sqlite() {
+        assert_eq!(
+            parse_blobstore_spec("sqlite:/var/repo/").unwrap(),
+            BlobConfig::Sqlite {
+                path: PathBuf::from("/var/repo")
+            }
+        );
+        assert!(parse_err("sqlite:").contains("missing path"));
+    }
+
+    #[test]
+    fn test_parse_manifold() {
+        assert_eq!(
+            parse_blobstore_spec("manifold:bucket/repo0/").unwrap(),
+            BlobConfig::Manifold {
+                bucket: "bucket".to_string(),
+                prefix: "repo0/".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_blobstore_spec("manifold:bucket/nested/prefix").unwrap(),
+            BlobConfig::Manifold {
+                bucket: "bucket".to_string(),
+                prefix: "nested/prefix".to_string(),
+            }
+        );
+        for spec in &["manifold:bucket", "manifold:bucket/"] {
+            assert_eq!(
+                parse_blobstore_spec(spec).unwrap(),
+                BlobConfig::Manifold {
+                    bucket: "bucket".to_string(),
+                    prefix: "".to_string(),
+                }
+            );
+        }
+        assert!(parse_err("manifold:").contains("missing bucket"));
+        assert!(parse_err("manifold:/prefix").contains("missing bucket"));
+    }
+
+    #[test]
+    fn test_parse_disabled() {
+        assert_eq!(
+            parse_blobstore_spec("disabled:").unwrap(),
+            BlobConfig::Disabled
+        );
+        assert!(parse_err("disabled:now").contains("after 'disabled:'"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for spec in &["", "file", "/var/repo", "multiplexed:1,2", "FILE:/var/repo"] {
+            let err = parse_err(spec);
+            assert!(err.contains(SUPPORTED_SPECS), "{}", err);
+        }
+        assert!(parse_err("multiplexed:1,2").contains("unsupported blobstore scheme"));
+        assert!(parse_err("/var/repo").contains("malformed blobstore spec"));
+    }
+}
@@ -0,0 +1,234 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+#![deny(warnings)]
+
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::{bail, format_err, Context, Error, Result};
+use futures::future::{FutureExt, TryFutureExt};
+use futures_ext::{BoxFuture, FutureExt as OldFutureExt};
+use hyper::{
+    client::HttpConnector, header::AUTHORIZATION, http::request::Builder, Body, Client, Request,
+    Response, StatusCode,
+};
+use hyper_openssl::HttpsConnector;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+use blobstore::Blobstore;
+use context::CoreContext;
+use mononoke_types::BlobstoreBytes;
+
+const DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
+/// Environment variable pointing the client at a GCS emulator, e.g. `localhost:4443`
+const EMULATOR_HOST_VAR: &str = "STORAGE_EMULATOR_HOST";
+
+type HttpsClient = Client<HttpsConnector<HttpConnector>>;
+
+/// A blobstore backed by a Google Cloud Storage bucket, accessed through the JSON API.
+#[derive(Clone)]
+pub struct GcsBlobstore {
+    bucket: String,
+    endpoint: String,
+    credentials: Option<PathBuf>,
+    client: HttpsClient,
+}
+
+impl fmt::Debug for GcsBlobstore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GcsBlobstore")
+            .field("bucket", &self.bucket)
+            .field("endpoint", &self.endpoint)
+            .field("credentials", &self.credentials)
+            .finish()
+    }
+}
+
+impl GcsBlobstore {
+    /// Store blobs in `bucket`. Requests are authenticated with the OAuth2 access token in the
+    /// file at `credentials`, which is read again for every request so that it can be refreshed
+    /// externally. Without credentials, requests are anonymous. If `STORAGE_EMULATOR_HOST` is
+    /// set, requests go to the emulator it points to instead of GCS.
+    pub fn new(bucket: String, credentials: Option<PathBuf>) -> Result<Self> {
+        let endpoint = match env::var(EMULATOR_HOST_VAR) {
+            Ok(host) if host.starts_with("http://") || host.starts_with("https://") => host,
+            Ok(host) => format!("http://{}", host),
+            Err(_) => DEFAULT_ENDPOINT.to_string(),
+        };
+        Self::with_endpoint(bucket, endpoint, credentials)
+    }
+
+    pub fn with_endpoint(
+        bucket: String,
+        endpoint: String,
+        credentials: Option<PathBuf>,
+    ) -> Result<Self> {
+        if bucket.is_empty() {
+            bail!("GCS bucket name must not be empty");
+        }
+
+        let connector = HttpsConnector::new()?;
+        let client = Client::builder().build(connector);
+
+        Ok(Self {
+            bucket,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            credentials,
+            client,
+        })
+    }
+
+    fn object_uri(&self, key: &str) -> String {
+        format!(
+            "{}/storage/v1/b/{}/o/{}",
+            self.endpoint,
+            utf8_percent_encode(&self.bucket, NON_ALPHANUMERIC),
+            utf8_percent_encode(key, NON_ALPHANUMERIC),
+        )
+    }
+
+    fn upload_uri(&self, key: &str) -> String {
+        format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType=media&name={}",
+            self.endpoint,
+            utf8_percent_encode(&self.bucket, NON_ALPHANUMERIC),
+            utf8_percent_encode(key, NON_ALPHANUMERIC),
+        )
+    }
+
+    async fn request(&self, builder: Builder, body: Body) -> Result<Response<Body>> {
+        let builder = match &self.credentials {
+            Some(path) => {
+                let token = tokio::fs::read_to_string(path).await.with_context(|| {
+                    format!("Failed to read GCS credentials from {}", path.display())
+                })?;
+                builder.header(AUTHORIZATION, format!("Bearer {}", token.trim()))
+            }
+            None => builder,
+        };
+        Ok(self.client.request(builder.body(body)?).await?)
+    }
+
+    fn unexpected_status(&self, op: &str, key: &str, status: StatusCode) -> Error {
+        format_err!(
+            "GCS {} of {} in bucket {} failed with status {}",
+            op,
+            key,
+            self.bucket,
+            status
+        )
+    }
+
+    async fn get_object(&self, key: String) -> Result<Option<BlobstoreBytes>> {
+        let uri = format!("{}?alt=media", self.object_uri(&key));
+        let response = self.request(Request::get(uri), Body::empty()).await?;
+        match response.status() {
+            StatusCode::OK => {
+                let bytes = hyper::body::to_bytes(response.into_body()).await?;
+                Ok(Some(BlobstoreBytes::from_bytes(bytes)))
+            }
+            StatusCode::NOT_FOUND => Ok(None),
+            status => Err(self.unexpected_status("get", &key, status)),
+        }
+    }
+
+    async fn put_object(&self, key: String, value: BlobstoreBytes) -> Result<()> {
+        let uri = self.upload_uri(&key);
+        let response = self
+            .request(Request::post(uri), Body::from(value.into_bytes()))
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(()),
+            status => Err(self.unexpected_status("put", &key, status)),
+        }
+    }
+
+    async fn object_exists(&self, key: String) -> Result<bool> {
+        let uri = self.object_uri(&key);
+        let response = self.request(Request::get(uri), Body::empty()).await?;
+        match response.status() {
+            StatusCode::OK => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(self.unexpected_status("presence check", &key, status)),
+        }
+    }
+}
+
+impl Blobstore for GcsBlobstore {
+    fn get(&self, _ctx: CoreContext, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        let this = self.clone();
+        async move { this.get_object(key).await }
+            .boxed()
+            .compat()
+            .boxify()
+    }
+
+    fn put(&self, _ctx: CoreContext, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        let this = self.clone();
+        async move { this.put_object(key, value).await }
+            .boxed()
+            .compat()
+            .boxify()
+    }
+
+    fn is_present(&self, _ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
+        let this = self.clone();
+        async move { this.object_exists(key).await }
+            .boxed()
+            .compat()
+            .boxify()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_uris() -> Result<()> {
+        let store = GcsBlobstore::with_endpoint(
+            "bucket".to_string(),
+            "http://localhost:4443/".to_string(),
+            None,
+        )?;
+        assert_eq!(
+            store.object_uri("repo0000.content.blake2.01"),
+            "http://localhost:4443/storage/v1/b/bucket/o/repo0000%2Econtent%2Eblake2%2E01"
+        );
+        assert_eq!(
+            store.upload_uri("a/b"),
+            "http://localhost:4443/upload/storage/v1/b/bucket/o?uploadType=media&name=a%2Fb"
+        );
+        assert!(
+            GcsBlobstore::with_endpoint(String::new(), DEFAULT_ENDPOINT.to_string(), None).is_err()
+        );
+        Ok(())
+    }
+
+    /// Needs a GCS emulator with an existing bucket named by `GCS_TEST_BUCKET`, e.g.
+    /// `STORAGE_EMULATOR_HOST=localhost:4443 GCS_TEST_BUCKET=test cargo test --features
+    /// emulator_tests`.
+    #[cfg(feature = "emulator_tests")]
+    #[fbinit::test]
+    fn test_emulator_roundtrip(fb: fbinit::FacebookInit) -> Result<()> {
+        let mut rt = tokio_compat::runtime::Runtime::new()?;
+        let ctx = CoreContext::test_mock(fb);
+        let bucket = env::var("GCS_TEST_BUCKET")?;
+        let store = GcsBlobstore::new(bucket, None)?;
+        let key = format!("test_emulator_roundtrip.{}", std::process::id());
+        let value = BlobstoreBytes::from_bytes("value");
+
+        assert!(!rt.block_on(store.is_present(ctx.clone(), key.clone()))?);
+        assert_eq!(rt.block_on(store.get(ctx.clone(), key.clone()))?, None);
+        rt.block_on(store.put(ctx.clone(), key.clone(), value.clone()))?;
+        assert!(rt.block_on(store.is_present(ctx.clone(), key.clone()))?);
+        assert_eq!(rt.block_on(store.get(ctx, key))?, Some(value));
+        Ok(())
+    }
+}
@@ -26,9 +26,9 @@ use std::sync::{
 use std::time::Duration;
 use thiserror::Error;
 use time_ext::DurationExt;
-use tokio::executor::spawn;
-use tokio::prelude::FutureExt as TokioFutureExt;
-use tokio::timer::timeout::Error as TimeoutError;
+use tokio_old::executor::spawn;
+use tokio_old::prelude::FutureExt as TokioFutureExt;
+use tokio_old::timer::timeout::Error as TimeoutError;
 
 const SLOW_REQUEST_THRESHOLD: Duration = Duration::from_secs(5);
 const REQUEST_TIMEOUT: Duration = Duration::from_secs(600);
@@ -363,6 +363,69 @@ impl Blobstore for MultiplexedBlobstoreBase {
         })
         .boxify()
     }
+
+    /// Checks all the keys in every blobstore at once, using the batched check of each of them.
+    /// A key is present if any blobstore has it. If a blobstore failed and a key wasn't found in
+    /// any other blobstore, the whole check fails the same way `is_present` would for that key.
+    fn is_present_many(
+        &self,
+        ctx: CoreContext,
+        keys: Vec<String>,
+    ) -> BoxFuture<HashMap<String, bool>, Error> {
+        ctx.perf_counters()
+            .add_to_counter(PerfCounterType::BlobPresenceChecks, keys.len() as i64);
+        let requests = self
+            .blobstores
+            .iter()
+            .map(|&(blobstore_id, ref blobstore)| {
+                blobstore
+                    .is_present_many(ctx.clone(), keys.clone())
+                    .then(move |result| Ok::<_, Error>((blobstore_id, result)))
+            });
+        let blobstores_count = self.blobstores.len();
+        future::join_all(requests)
+            .and_then(move |results| {
+                let mut present = HashSet::new();
+                let mut errors = HashMap::<BlobstoreId, Error>::new();
+                for (blobstore_id, result) in results {
+                    match result {
+                        Ok(checked) => {
+                            for (key, is_present) in checked {
+                                if is_present {
+                                    present.insert(key);
+                                }
+                            }
+                        }
+                        Err(error) => {
+                            errors.insert(blobstore_id, error);
+                        }
+                    }
+                }
+                if !errors.is_empty() && keys.iter().any(|key| !present.contains(key)) {
+                    let error = if errors.len() == blobstores_count {
+                        ErrorKind::AllFailed(errors.into())
+                    } else {
+                        ErrorKind::SomeFailedOthersNone(errors.into())
+                    };
+                    return Err(error.into());
+                }
+                Ok(keys
+                    .into_iter()
+                    .map(|key| {
+                        let is_present = present.contains(&key);
+                        (key, is_present)
+                    })
+                    .collect())
+            })
+            .timed(move |stats, _| {
+                ctx.perf_counters().set_max_counter(
+                    PerfCounterType::BlobPresenceChecksMaxLatency,
+                    stats.completion_time.as_millis_unchecked() as i64,
+                );
+                Ok(())
+            })
+            .boxify()
+    }
 }
 
 impl fmt::Debug for MultiplexedBlobstoreBase {
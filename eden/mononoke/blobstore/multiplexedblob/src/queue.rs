@@ -16,6 +16,7 @@ use futures_old::future::{self, Future};
 use metaconfig_types::{BlobstoreId, MultiplexId};
 use mononoke_types::{BlobstoreBytes, DateTime};
 use scuba::ScubaSampleBuilder;
+use std::collections::HashMap;
 use std::fmt;
 use std::num::NonZeroU64;
 use std::sync::Arc;
@@ -143,4 +144,31 @@ impl Blobstore for MultiplexedBlobstore {
             })
             .boxify()
     }
+
+    fn is_present_many(
+        &self,
+        ctx: CoreContext,
+        keys: Vec<String>,
+    ) -> BoxFuture<HashMap<String, bool>, Error> {
+        self.blobstore
+            .is_present_many(ctx.clone(), keys.clone())
+            .or_else({
+                let this = self.clone();
+                move |error| {
+                    if let Some(ErrorKind::AllFailed(_)) = error.downcast_ref() {
+                        return future::err(error).left_future();
+                    }
+                    // Some blobstore failed, so the keys that weren't found need the sync queue
+                    // to tell missing blobs from unsynced ones. Check them one by one.
+                    let checks = keys.into_iter().map(move |key| {
+                        this.is_present(ctx.clone(), key.clone())
+                            .map(move |present| (key, present))
+                    });
+                    future::join_all(checks)
+                        .map(|checks| checks.into_iter().collect())
+                        .right_future()
+                }
+            })
+            .boxify()
+    }
 }
@@ -13,6 +13,7 @@ use blobstore::Blobstore;
 use blobstore_sync_queue::BlobstoreSyncQueue;
 use cloned::cloned;
 use context::CoreContext;
+use futures::{compat::Future01CompatExt, FutureExt as _, TryFutureExt};
 use futures_ext::{BoxFuture, FutureExt};
 use futures_old::future::{self, Future};
 use metaconfig_types::{BlobstoreId, MultiplexId, ScrubAction};
@@ -21,8 +22,9 @@ use scuba::ScubaSampleBuilder;
 use slog::{info, warn};
 use std::collections::HashMap;
 use std::fmt;
-use std::num::NonZeroU64;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::sync::{atomic::AtomicUsize, Arc};
+use tokio::sync::Semaphore;
 
 pub trait ScrubHandler: Send + Sync {
     /// Called when one of the inner stores required repair.
@@ -71,6 +73,8 @@ pub struct ScrubBlobstore {
     scuba: ScubaSampleBuilder,
     scrub_stores: Arc<HashMap<BlobstoreId, Arc<dyn Blobstore>>>,
     queue: Arc<dyn BlobstoreSyncQueue>,
+    /// Bounds the number of repairs in flight at once, if set
+    repair_semaphore: Option<Arc<Semaphore>>,
 }
 
 impl ScrubBlobstore {
@@ -82,6 +86,7 @@ impl ScrubBlobstore {
         scuba_sample_rate: NonZeroU64,
         scrub_handler: Arc<dyn ScrubHandler>,
         scrub_action: ScrubAction,
+        scrub_repair_concurrency: Option<NonZeroUsize>,
     ) -> Self {
         let inner = MultiplexedBlobstore::new(
             multiplex_id,
@@ -101,6 +106,8 @@ impl ScrubBlobstore {
                     .collect::<HashMap<BlobstoreId, Arc<dyn Blobstore>>>(),
             ),
             queue,
+            repair_semaphore: scrub_repair_concurrency
+                .map(|concurrency| Arc::new(Semaphore::new(concurrency.get()))),
         }
     }
 }
@@ -126,6 +133,7 @@ impl Blobstore for ScrubBlobstore {
                     self.scuba,
                     self.scrub_action,
                     self.queue,
+                    self.repair_semaphore,
                 );
                 move |result| {
                     let needs_repair = match result {
@@ -194,16 +202,21 @@ impl Blobstore for ScrubBlobstore {
                                 let order = Arc::new(AtomicUsize::new(0));
                                 let mut repair_puts = vec![];
                                 for (id, store) in needs_repair.into_iter() {
-                                    cloned!(ctx, scuba, key, value, order);
-                                    let repair = inner_put(
-                                        ctx.clone(),
-                                        scuba,
-                                        order,
-                                        id,
-                                        store,
-                                        key.clone(),
-                                        value,
-                                    )
+                                    let repair = {
+                                        cloned!(ctx, scuba, key, value, order, repair_semaphore);
+                                        async move {
+                                            // Only start the put once a permit is acquired
+                                            let _permit = match &repair_semaphore {
+                                                Some(semaphore) => Some(semaphore.acquire().await),
+                                                None => None,
+                                            };
+                                            inner_put(ctx, scuba, order, id, store, key, value)
+                                                .compat()
+                                                .await
+                                        }
+                                    }
+                                    .boxed()
+                                    .compat()
                                     .then({
                                         cloned!(ctx, scrub_handler, key);
                                         move |res| {
@@ -233,4 +246,12 @@ impl Blobstore for ScrubBlobstore {
     fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
         self.inner.is_present(ctx, key)
     }
+
+    fn is_present_many(
+        &self,
+        ctx: CoreContext,
+        keys: Vec<String>,
+    ) -> BoxFuture<HashMap<String, bool>, Error> {
+        self.inner.is_present_many(ctx, keys)
+    }
 }
@@ -28,6 +28,7 @@ use futures_ext::{BoxFuture, FutureExt};
 use futures_old::future::{Future, IntoFuture};
 use futures_old::sync::oneshot;
 use lock_ext::LockExt;
+use memblob::EagerMemblob;
 use metaconfig_types::{BlobstoreId, MultiplexId, ScrubAction};
 use mononoke_types::BlobstoreBytes;
 use nonzero_ext::nonzero;
@@ -415,6 +416,7 @@ fn scrubbed(fb: FacebookInit) {
             nonzero!(1u64),
             scrub_handler.clone(),
             ScrubAction::ReportOnly,
+            None,
         );
 
         // non-existing key when one blobstore failing
@@ -502,6 +504,7 @@ fn scrubbed(fb: FacebookInit) {
             nonzero!(1u64),
             scrub_handler,
             ScrubAction::Repair,
+            None,
         );
 
         // Non-existing key in both blobstores, new blobstore failing
@@ -587,6 +590,91 @@ fn scrubbed(fb: FacebookInit) {
     });
 }
 
+#[fbinit::test]
+fn scrub_repair_concurrency(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let waker = futures::task::noop_waker();
+        let mut task_ctx = Context::from_waker(&waker);
+
+        let ctx = CoreContext::test_mock(fb);
+        let queue = Arc::new(SqlBlobstoreSyncQueue::with_sqlite_in_memory().unwrap());
+        let scrub_handler = Arc::new(LoggingScrubHandler::new(false)) as Arc<dyn ScrubHandler>;
+        let bid0 = BlobstoreId::new(0);
+        let bs0 = Arc::new(Tickable::new());
+        let bid1 = BlobstoreId::new(1);
+        let bs1 = Arc::new(Tickable::new());
+        let bs = ScrubBlobstore::new(
+            MultiplexId::new(1),
+            vec![(bid0, bs0.clone()), (bid1, bs1.clone())],
+            queue,
+            ScubaSampleBuilder::with_discard(),
+            nonzero!(1u64),
+            scrub_handler,
+            ScrubAction::Repair,
+            Some(nonzero!(1usize)),
+        );
+
+        // All the keys are only in bs0, so every read needs a repair of bs1
+        let keys: Vec<_> = (0..3).map(|i| format!("k{}", i)).collect();
+        for key in &keys {
+            bs0.storage.with(|s| s.insert(key.clone(), make_value(key)));
+        }
+
+        let mut get_futs: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                bs.get(ctx.clone(), key.clone())
+                    .map_err(|_| ())
+                    .compat()
+                    .boxed()
+            })
+            .collect();
+        for get_fut in get_futs.iter_mut() {
+            assert_eq!(get_fut.poll_unpin(&mut task_ctx), Poll::Pending);
+        }
+        // tick the gets
+        bs0.tick(None);
+        for get_fut in get_futs.iter_mut() {
+            assert_eq!(get_fut.poll_unpin(&mut task_ctx), Poll::Pending);
+        }
+        bs1.tick(None);
+
+        // Only one repair is in flight at a time, the others wait for it to finish
+        let mut repaired = 0;
+        while !get_futs.is_empty() {
+            // Poll twice, as a finished repair frees its permit for the reads polled before it
+            for _ in 0..2 {
+                let mut i = 0;
+                while i < get_futs.len() {
+                    match get_futs[i].poll_unpin(&mut task_ctx) {
+                        Poll::Pending => i += 1,
+                        Poll::Ready(value) => {
+                            assert!(value.unwrap().is_some());
+                            get_futs.remove(i);
+                        }
+                    }
+                }
+            }
+            if get_futs.is_empty() {
+                break;
+            }
+            assert_eq!(bs1.queue.with(|q| q.len()), 1);
+            assert_eq!(bs1.storage.with(|s| s.len()), repaired);
+            // Tick the repair
+            bs1.tick(None);
+            repaired += 1;
+        }
+
+        assert_eq!(repaired, keys.len());
+        for key in &keys {
+            assert_eq!(
+                bs1.storage.with(|s| s.get(key).cloned()),
+                Some(make_value(key))
+            );
+        }
+    });
+}
+
 #[fbinit::test]
 fn queue_waits(fb: FacebookInit) {
     async_unit::tokio_unit_test(async move {
@@ -704,3 +792,53 @@ fn queue_waits(fb: FacebookInit) {
         }
     });
 }
+
+#[fbinit::test]
+fn is_present_many(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let queue = Arc::new(SqlBlobstoreSyncQueue::with_sqlite_in_memory().unwrap());
+
+        let bs0 = Arc::new(EagerMemblob::new());
+        let bs1 = Arc::new(EagerMemblob::new());
+        let bs = MultiplexedBlobstore::new(
+            MultiplexId::new(1),
+            vec![
+                (BlobstoreId::new(0), bs0.clone()),
+                (BlobstoreId::new(1), bs1.clone()),
+            ],
+            queue,
+            ScubaSampleBuilder::with_discard(),
+            nonzero!(1u64),
+        );
+
+        // k0 is only in the first blobstore, k1 only in the second one and k2 in both
+        for (blobstore, key) in &[(&bs0, "k0"), (&bs1, "k1"), (&bs0, "k2"), (&bs1, "k2")] {
+            blobstore
+                .put(ctx.clone(), key.to_string(), make_value(key))
+                .compat()
+                .await
+                .unwrap();
+        }
+
+        let keys: Vec<String> = vec!["k0", "k1", "k2", "k3"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let present = bs
+            .is_present_many(ctx.clone(), keys.clone())
+            .compat()
+            .await
+            .unwrap();
+        assert_eq!(present.len(), keys.len());
+        for key in keys {
+            let expected = bs
+                .is_present(ctx.clone(), key.clone())
+                .compat()
+                .await
+                .unwrap();
+            assert_eq!(present.get(&key), Some(&expected), "key {}", key);
+        }
+        assert_eq!(present.get("k3"), Some(&false));
+    });
+}
@@ -7,8 +7,10 @@
 
 use anyhow::Error;
 use inlinable_string::InlinableString;
+use std::collections::HashMap;
 
-use futures_ext::BoxFuture;
+use futures::Future;
+use futures_ext::{BoxFuture, FutureExt};
 
 use context::CoreContext;
 
@@ -60,6 +62,24 @@ impl<T: Blobstore + Clone> Blobstore for PrefixBlobstore<T> {
     fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
         self.blobstore.is_present(ctx, self.prepend(key))
     }
+
+    fn is_present_many(
+        &self,
+        ctx: CoreContext,
+        keys: Vec<String>,
+    ) -> BoxFuture<HashMap<String, bool>, Error> {
+        let prefix_len = self.prefix.len();
+        let keys = keys.into_iter().map(|key| self.prepend(key)).collect();
+        self.blobstore
+            .is_present_many(ctx, keys)
+            .map(move |present| {
+                present
+                    .into_iter()
+                    .map(|(key, is_present)| (key[prefix_len..].to_string(), is_present))
+                    .collect()
+            })
+            .boxify()
+    }
 }
 
 #[cfg(test)]
@@ -118,5 +138,17 @@ mod test {
             .is_present(ctx.clone(), prefixed_key.clone())
             .wait()
             .expect("is_present should succeed"));
+
+        // is_present_many returns unprefixed keys.
+        let absent_key = "absent".to_string();
+        let present = prefixed
+            .is_present_many(
+                ctx.clone(),
+                vec![unprefixed_key.clone(), absent_key.clone()],
+            )
+            .wait()
+            .expect("is_present_many should succeed");
+        assert_eq!(present.get(&unprefixed_key), Some(&true));
+        assert_eq!(present.get(&absent_key), Some(&false));
     }
 }
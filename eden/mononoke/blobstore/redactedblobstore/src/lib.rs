@@ -207,6 +207,14 @@ impl<T: Blobstore + Clone> Blobstore for RedactedBlobstoreInner<T> {
         self.blobstore.is_present(ctx, key)
     }
 
+    fn is_present_many(
+        &self,
+        ctx: CoreContext,
+        keys: Vec<String>,
+    ) -> BoxFuture<HashMap<String, bool>, Error> {
+        self.blobstore.is_present_many(ctx, keys)
+    }
+
     fn assert_present(&self, ctx: CoreContext, key: String) -> BoxFuture<(), Error> {
         self.blobstore.assert_present(ctx, key)
     }
@@ -225,6 +233,13 @@ where
     fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
         self.inner.is_present(ctx, key)
     }
+    fn is_present_many(
+        &self,
+        ctx: CoreContext,
+        keys: Vec<String>,
+    ) -> BoxFuture<HashMap<String, bool>, Error> {
+        self.inner.is_present_many(ctx, keys)
+    }
     fn assert_present(&self, ctx: CoreContext, key: String) -> BoxFuture<(), Error> {
         self.inner.assert_present(ctx, key)
     }
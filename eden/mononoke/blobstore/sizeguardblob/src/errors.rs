@@ -0,0 +1,18 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ErrorKind {
+    #[error("Blob {key} is {size} bytes, which is over the limit of {max_size} bytes")]
+    BlobTooLarge {
+        key: String,
+        size: usize,
+        max_size: u64,
+    },
+}
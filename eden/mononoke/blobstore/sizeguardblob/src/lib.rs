@@ -0,0 +1,115 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+#![deny(warnings)]
+
+use anyhow::Error;
+use blobstore::Blobstore;
+use context::CoreContext;
+use futures::future;
+use futures_ext::{BoxFuture, FutureExt};
+use mononoke_types::BlobstoreBytes;
+mod errors;
+pub use crate::errors::ErrorKind;
+
+/// A layer over an existing blobstore that rejects writes of blobs larger than a maximum size.
+/// Reads are passed through, whatever the size of the blob.
+#[derive(Clone, Debug)]
+pub struct SizeGuardBlobstore<T: Blobstore + Clone> {
+    blobstore: T,
+    max_size: u64,
+}
+
+impl<T: Blobstore + Clone> SizeGuardBlobstore<T> {
+    pub fn new(blobstore: T, max_size: u64) -> Self {
+        Self {
+            blobstore,
+            max_size,
+        }
+    }
+}
+
+impl<T: Blobstore + Clone> Blobstore for SizeGuardBlobstore<T> {
+    #[inline]
+    fn get(&self, ctx: CoreContext, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        self.blobstore.get(ctx, key)
+    }
+
+    #[inline]
+    fn put(&self, ctx: CoreContext, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        let size = value.len();
+        if size as u64 > self.max_size {
+            return future::err(
+                ErrorKind::BlobTooLarge {
+                    key,
+                    size,
+                    max_size: self.max_size,
+                }
+                .into(),
+            )
+            .boxify();
+        }
+        self.blobstore.put(ctx, key, value)
+    }
+
+    #[inline]
+    fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
+        self.blobstore.is_present(ctx, key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fbinit::FacebookInit;
+    use futures::Future;
+
+    use memblob::EagerMemblob;
+
+    #[fbinit::test]
+    fn test_size_guard(fb: FacebookInit) {
+        let ctx = CoreContext::test_mock(fb);
+        let base = EagerMemblob::new();
+        let wrapper = SizeGuardBlobstore::new(base.clone(), 11);
+
+        // We're using EagerMemblob (immediate future completion) so calling wait() is fine.
+        let value = BlobstoreBytes::from_bytes("test foobar");
+        wrapper
+            .put(ctx.clone(), "small".to_string(), value.clone())
+            .wait()
+            .expect("put within the limit should succeed");
+        assert_eq!(
+            wrapper
+                .get(ctx.clone(), "small".to_string())
+                .wait()
+                .unwrap(),
+            Some(value)
+        );
+
+        let err = wrapper
+            .put(
+                ctx.clone(),
+                "large".to_string(),
+                BlobstoreBytes::from_bytes("test foobar!"),
+            )
+            .wait()
+            .expect_err("put over the limit should fail");
+        match err.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::BlobTooLarge {
+                key,
+                size,
+                max_size,
+            }) => {
+                assert_eq!(key, "large");
+                assert_eq!(*size, 12);
+                assert_eq!(*max_size, 11);
+            }
+            _ => panic!("unexpected error: {:?}", err),
+        }
+        assert!(!base.is_present(ctx, "large".to_string()).wait().unwrap());
+    }
+}
@@ -8,6 +8,7 @@
 #![deny(warnings)]
 
 use bytes::Bytes;
+use std::collections::HashMap;
 use std::fmt;
 use std::sync::Arc;
 
@@ -96,6 +97,22 @@ pub trait Blobstore: fmt::Debug + Send + Sync + 'static {
     fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
         self.get(ctx, key).map(|opt| opt.is_some()).boxify()
     }
+    /// Check which of the given `keys` are present, with the same semantics as calling
+    /// `is_present` for each of them. The provided implementation does exactly that; blobstores
+    /// that can check many keys in fewer round trips should override it.
+    fn is_present_many(
+        &self,
+        ctx: CoreContext,
+        keys: Vec<String>,
+    ) -> BoxFuture<HashMap<String, bool>, Error> {
+        let checks = keys.into_iter().map(|key| {
+            self.is_present(ctx.clone(), key.clone())
+                .map(move |present| (key, present))
+        });
+        future::join_all(checks)
+            .map(|checks| checks.into_iter().collect())
+            .boxify()
+    }
     /// Errors if a given `key` is not present in the blob store. Useful to abort a chained
     /// future computation early if it cannot succeed unless the `key` is present
     fn assert_present(&self, ctx: CoreContext, key: String) -> BoxFuture<(), Error> {
@@ -121,6 +138,13 @@ impl Blobstore for Arc<dyn Blobstore> {
     fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
         self.as_ref().is_present(ctx, key)
     }
+    fn is_present_many(
+        &self,
+        ctx: CoreContext,
+        keys: Vec<String>,
+    ) -> BoxFuture<HashMap<String, bool>, Error> {
+        self.as_ref().is_present_many(ctx, keys)
+    }
     fn assert_present(&self, ctx: CoreContext, key: String) -> BoxFuture<(), Error> {
         self.as_ref().assert_present(ctx, key)
     }
@@ -136,6 +160,13 @@ impl<T: Blobstore> Blobstore for Arc<T> {
     fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
         self.as_ref().is_present(ctx, key)
     }
+    fn is_present_many(
+        &self,
+        ctx: CoreContext,
+        keys: Vec<String>,
+    ) -> BoxFuture<HashMap<String, bool>, Error> {
+        self.as_ref().is_present_many(ctx, keys)
+    }
     fn assert_present(&self, ctx: CoreContext, key: String) -> BoxFuture<(), Error> {
         self.as_ref().assert_present(ctx, key)
     }
@@ -151,6 +182,13 @@ impl Blobstore for Box<dyn Blobstore> {
     fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
         self.as_ref().is_present(ctx, key)
     }
+    fn is_present_many(
+        &self,
+        ctx: CoreContext,
+        keys: Vec<String>,
+    ) -> BoxFuture<HashMap<String, bool>, Error> {
+        self.as_ref().is_present_many(ctx, keys)
+    }
     fn assert_present(&self, ctx: CoreContext, key: String) -> BoxFuture<(), Error> {
         self.as_ref().assert_present(ctx, key)
     }
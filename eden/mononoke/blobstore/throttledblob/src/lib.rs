@@ -10,6 +10,7 @@
 use anyhow::Error;
 use std::fmt;
 use std::num::NonZeroU32;
+use std::time::Duration;
 
 use async_limiter::{AsyncLimiter, TokioFlavor};
 use futures::future::Future;
@@ -25,6 +26,8 @@ use mononoke_types::BlobstoreBytes;
 pub struct ThrottleOptions {
     read_qps: Option<NonZeroU32>,
     write_qps: Option<NonZeroU32>,
+    read_burst: Option<NonZeroU32>,
+    write_burst: Option<NonZeroU32>,
 }
 
 impl ThrottleOptions {
@@ -32,6 +35,23 @@ impl ThrottleOptions {
         Self {
             read_qps,
             write_qps,
+            read_burst: None,
+            write_burst: None,
+        }
+    }
+
+    /// Allow up to `read_burst` reads to go through at once before throttling to `read_qps`
+    /// kicks in. The allowance refills at `read_qps`. Has no effect without `read_qps`.
+    pub fn with_read_burst(self, read_burst: Option<NonZeroU32>) -> Self {
+        Self { read_burst, ..self }
+    }
+
+    /// Allow up to `write_burst` writes to go through at once before throttling to `write_qps`
+    /// kicks in. The allowance refills at `write_qps`. Has no effect without `write_qps`.
+    pub fn with_write_burst(self, write_burst: Option<NonZeroU32>) -> Self {
+        Self {
+            write_burst,
+            ..self
         }
     }
 
@@ -50,13 +70,23 @@ pub struct ThrottledBlob<T: Blobstore + Clone> {
     options: ThrottleOptions,
 }
 
-fn limiter(qps: Option<NonZeroU32>) -> AsyncLimiter {
-    match qps {
-        Some(qps) => AsyncLimiter::new(
+fn limiter(qps: Option<NonZeroU32>, burst: Option<NonZeroU32>) -> AsyncLimiter {
+    match (qps, burst) {
+        // `per_second(qps)` is a bucket of `qps` cells over one second, so its burst is always
+        // `qps`. A different burst needs a bucket of `burst` cells, whose window is scaled to
+        // `burst / qps` seconds to keep the rate at `qps`.
+        (Some(qps), Some(burst)) if burst != qps => AsyncLimiter::new(
+            DirectRateLimiter::<LeakyBucket>::new(
+                burst,
+                Duration::from_secs(burst.get().into()) / qps.get(),
+            ),
+            TokioFlavor::V01,
+        ),
+        (Some(qps), _) => AsyncLimiter::new(
             DirectRateLimiter::<LeakyBucket>::per_second(qps),
             TokioFlavor::V01,
         ),
-        None => AsyncLimiter::new(Allower::ratelimiter(), TokioFlavor::V01),
+        (None, _) => AsyncLimiter::new(Allower::ratelimiter(), TokioFlavor::V01),
     }
 }
 
@@ -64,8 +94,8 @@ impl<T: Blobstore + Clone> ThrottledBlob<T> {
     pub fn new(blobstore: T, options: ThrottleOptions) -> Self {
         Self {
             blobstore,
-            read_limiter: limiter(options.read_qps),
-            write_limiter: limiter(options.write_qps),
+            read_limiter: limiter(options.read_qps, options.read_burst),
+            write_limiter: limiter(options.write_qps, options.write_burst),
             options,
         }
     }
@@ -123,3 +153,36 @@ impl<T: Blobstore + Clone> fmt::Debug for ThrottledBlob<T> {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fbinit::FacebookInit;
+    use memblob::EagerMemblob;
+    use nonzero_ext::nonzero;
+    use std::time::Instant;
+    use tokio_compat::runtime::Runtime;
+
+    #[fbinit::test]
+    fn test_read_burst(fb: FacebookInit) {
+        let mut rt = Runtime::new().unwrap();
+        let ctx = CoreContext::test_mock(fb);
+        let options = ThrottleOptions::new(Some(nonzero!(10u32)), None)
+            .with_read_burst(Some(nonzero!(20u32)));
+        let blob = ThrottledBlob::new(EagerMemblob::new(), options);
+        let key = "foobar".to_string();
+
+        let start = Instant::now();
+        for _ in 0..20 {
+            rt.block_on(blob.get(ctx.clone(), key.clone())).unwrap();
+        }
+        // The whole burst fits in the allowance, so none of it waits on the limiter.
+        assert!(start.elapsed() < Duration::from_millis(300));
+
+        // The allowance is used up, so further reads proceed at 10 qps.
+        for _ in 0..5 {
+            rt.block_on(blob.get(ctx.clone(), key.clone())).unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+}
@@ -116,6 +116,59 @@ impl BlobstoreSyncQueue for Arc<dyn BlobstoreSyncQueue> {
     }
 }
 
+/// A queue standing in for one that couldn't be opened, for stores that are only read from.
+/// Adding and deleting entries does nothing, and iterating finds no entries. Looking up a key
+/// fails, as the queue can't tell whether it was written to only some of the blobstores.
+#[derive(Clone, Debug, Default)]
+pub struct NoopBlobstoreSyncQueue;
+
+impl NoopBlobstoreSyncQueue {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl BlobstoreSyncQueue for NoopBlobstoreSyncQueue {
+    fn add_many(
+        &self,
+        _ctx: CoreContext,
+        _entries: Box<dyn Iterator<Item = BlobstoreSyncQueueEntry> + Send>,
+    ) -> BoxFuture<(), Error> {
+        future::ok(()).boxify()
+    }
+
+    fn iter(
+        &self,
+        _ctx: CoreContext,
+        _key_like: Option<String>,
+        _multiplex_id: MultiplexId,
+        _older_than: DateTime,
+        _limit: usize,
+    ) -> BoxFuture<Vec<BlobstoreSyncQueueEntry>, Error> {
+        future::ok(vec![]).boxify()
+    }
+
+    fn del(
+        &self,
+        _ctx: CoreContext,
+        _entries: Vec<BlobstoreSyncQueueEntry>,
+    ) -> BoxFuture<(), Error> {
+        future::ok(()).boxify()
+    }
+
+    fn get(
+        &self,
+        _ctx: CoreContext,
+        key: String,
+    ) -> BoxFuture<Vec<BlobstoreSyncQueueEntry>, Error> {
+        future::err(format_err!(
+            "Blobstore sync queue is unavailable, cannot look up {}",
+            key
+        ))
+        .boxify()
+    }
+}
+
 #[derive(Clone)]
 pub struct SqlBlobstoreSyncQueue {
     write_connection: Arc<Connection>,
@@ -22,7 +22,7 @@ use futures::{
 use futures_ext::{BoxFuture, FutureExt};
 use futures_old::future::Future;
 use futures_old::{future, IntoFuture};
-use mercurial_types::Globalrev;
+use mercurial_types::{Globalrev, GlobalrevPrefix, GlobalrevsResolvedFromPrefix};
 use mononoke_types::{BonsaiChangeset, ChangesetId, RepositoryId};
 use slog::warn;
 use sql::queries;
@@ -107,6 +107,14 @@ pub trait BonsaiGlobalrevMapping: Send + Sync {
     /// Read the most recent Globalrev. This produces the freshest data possible, and is meant to
     /// be used for Globalrev assignment.
     fn get_max(&self, repo_id: RepositoryId) -> BoxFuture<Option<Globalrev>, Error>;
+
+    /// Find the Globalrevs starting with `prefix`, returning at most `limit` of them
+    fn get_many_by_prefix(
+        &self,
+        repo_id: RepositoryId,
+        prefix: GlobalrevPrefix,
+        limit: usize,
+    ) -> BoxFuture<GlobalrevsResolvedFromPrefix, Error>;
 }
 
 impl BonsaiGlobalrevMapping for Arc<dyn BonsaiGlobalrevMapping> {
@@ -141,6 +149,15 @@ impl BonsaiGlobalrevMapping for Arc<dyn BonsaiGlobalrevMapping> {
     fn get_max(&self, repo_id: RepositoryId) -> BoxFuture<Option<Globalrev>, Error> {
         (**self).get_max(repo_id)
     }
+
+    fn get_many_by_prefix(
+        &self,
+        repo_id: RepositoryId,
+        prefix: GlobalrevPrefix,
+        limit: usize,
+    ) -> BoxFuture<GlobalrevsResolvedFromPrefix, Error> {
+        (**self).get_many_by_prefix(repo_id, prefix, limit)
+    }
 }
 
 queries! {
@@ -180,6 +197,19 @@ queries! {
         LIMIT 1
         "
     }
+
+    read SelectGlobalrevsByRange(
+        repo_id: RepositoryId,
+        min: Globalrev,
+        max: Globalrev,
+        limit: usize
+    ) -> (Globalrev,) {
+        "SELECT globalrev
+         FROM bonsai_globalrev_mapping
+         WHERE repo_id = {repo_id} AND globalrev >= {min} AND globalrev <= {max}
+         ORDER BY globalrev
+         LIMIT {limit}"
+    }
 }
 
 #[derive(Clone)]
@@ -289,6 +319,60 @@ impl BonsaiGlobalrevMapping for SqlBonsaiGlobalrevMapping {
         .compat()
         .boxify()
     }
+
+    fn get_many_by_prefix(
+        &self,
+        repo_id: RepositoryId,
+        prefix: GlobalrevPrefix,
+        limit: usize,
+    ) -> BoxFuture<GlobalrevsResolvedFromPrefix, Error> {
+        cloned!(self.read_connection, self.read_master_connection);
+
+        async move {
+            let resolved = fetch_many_by_prefix(&read_connection, repo_id, prefix, limit).await?;
+            match resolved {
+                GlobalrevsResolvedFromPrefix::NoMatch => {
+                    fetch_many_by_prefix(&read_master_connection, repo_id, prefix, limit).await
+                }
+                _ => Ok(resolved),
+            }
+        }
+        .boxed()
+        .compat()
+        .boxify()
+    }
+}
+
+/// Look up the Globalrevs starting with `prefix` one number of digits at a time, shortest first,
+/// stopping as soon as there are more than `limit` of them.
+async fn fetch_many_by_prefix(
+    connection: &Connection,
+    repo_id: RepositoryId,
+    prefix: GlobalrevPrefix,
+    limit: usize,
+) -> Result<GlobalrevsResolvedFromPrefix, Error> {
+    let mut fetched = vec![];
+    for (min, max) in prefix.ranges() {
+        let remaining = limit + 1 - fetched.len();
+        let rows = SelectGlobalrevsByRange::query(connection, &repo_id, &min, &max, &remaining)
+            .compat()
+            .await?;
+        fetched.extend(rows.into_iter().map(|row| row.0));
+        if fetched.len() > limit {
+            break;
+        }
+    }
+
+    let resolved = match fetched.len() {
+        0 => GlobalrevsResolvedFromPrefix::NoMatch,
+        1 => GlobalrevsResolvedFromPrefix::Single(fetched[0]),
+        l if l <= limit => GlobalrevsResolvedFromPrefix::Multiple(fetched),
+        _ => {
+            fetched.pop();
+            GlobalrevsResolvedFromPrefix::TooMany(fetched)
+        }
+    };
+    Ok(resolved)
 }
 
 fn filter_fetched_objects(
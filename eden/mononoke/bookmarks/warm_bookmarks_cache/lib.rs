@@ -123,6 +123,14 @@ impl WarmBookmarksCache {
         .compat()
     }
 
+    /// Create a cache that holds the given bookmarks and is never updated.
+    pub fn new_static(bookmarks: HashMap<BookmarkName, ChangesetId>) -> Self {
+        Self {
+            bookmarks: Arc::new(RwLock::new(bookmarks)),
+            terminate: None,
+        }
+    }
+
     pub fn get(&self, bookmark: &BookmarkName) -> Option<ChangesetId> {
         self.bookmarks.read().unwrap().get(bookmark).cloned()
     }
@@ -6,6 +6,7 @@
  */
 
 use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::io;
 use std::num::NonZeroU32;
 use std::path::Path;
@@ -32,7 +33,9 @@ use slog_glog_fmt::{kv_categorizer::FacebookCategorizer, kv_defaults::FacebookKV
 
 use blobrepo::BlobRepo;
 use blobrepo_factory::{BlobrepoBuilder, Caching, ReadOnlyStorage};
-use blobstore_factory::{BlobstoreOptions, ChaosOptions, Scrubbing, ThrottleOptions};
+use blobstore_factory::{
+    BlobstoreOptions, ChaosErrorKind, ChaosOptions, EncryptionKey, Scrubbing, ThrottleOptions,
+};
 use changesets::SqlConstructors;
 use metaconfig_parser::RepoConfigs;
 use metaconfig_types::{
@@ -72,9 +75,15 @@ const READONLY_STORAGE: &str = "readonly-storage";
 
 const READ_QPS_ARG: &str = "blobstore-read-qps";
 const WRITE_QPS_ARG: &str = "blobstore-write-qps";
+const READ_BURST_ARG: &str = "blobstore-read-burst";
+const WRITE_BURST_ARG: &str = "blobstore-write-burst";
 const READ_CHAOS_ARG: &str = "blobstore-read-chaos-rate";
 const WRITE_CHAOS_ARG: &str = "blobstore-write-chaos-rate";
+const CHAOS_ERROR_KIND_ARG: &str = "blobstore-chaos-error-kind";
+const CHAOS_LATENCY_ARG: &str = "blobstore-chaos-latency-ms";
+const CHAOS_KEY_PREFIX_ARG: &str = "blobstore-chaos-key-prefix";
 const MANIFOLD_API_KEY_ARG: &str = "manifold-api-key";
+const ENCRYPTION_KEY_FILE_ARG: &str = "blobstore-encryption-key-file";
 
 const PHASES_CACHE_SIZE: &str = "phases-cache-size";
 const BUCKETS_POWER: &str = "buckets-power";
@@ -816,6 +825,22 @@ pub fn add_blobstore_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
             .required(false)
             .help("Write QPS limit to ThrottledBlob"),
     )
+    .arg(
+        Arg::with_name(READ_BURST_ARG)
+            .long(READ_BURST_ARG)
+            .takes_value(true)
+            .required(false)
+            .requires(READ_QPS_ARG)
+            .help("Number of reads ThrottledBlob lets through at once before applying the read QPS limit"),
+    )
+    .arg(
+        Arg::with_name(WRITE_BURST_ARG)
+            .long(WRITE_BURST_ARG)
+            .takes_value(true)
+            .required(false)
+            .requires(WRITE_QPS_ARG)
+            .help("Number of writes ThrottledBlob lets through at once before applying the write QPS limit"),
+    )
     .arg(
         Arg::with_name(READ_CHAOS_ARG)
             .long(READ_CHAOS_ARG)
@@ -830,6 +855,30 @@ pub fn add_blobstore_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
             .required(false)
             .help("Rate of errors on writes. Pass N,  it will error randomly 1/N times. For multiplexed stores will only apply to the first store in the multiplex."),
     )
+    .arg(
+        Arg::with_name(CHAOS_ERROR_KIND_ARG)
+            .long(CHAOS_ERROR_KIND_ARG)
+            .takes_value(true)
+            .possible_values(&["error", "timeout"])
+            .required(false)
+            .help("How errors injected by the chaos rates show up: fail immediately, or never complete."),
+    )
+    .arg(
+        Arg::with_name(CHAOS_LATENCY_ARG)
+            .long(CHAOS_LATENCY_ARG)
+            .takes_value(true)
+            .number_of_values(2)
+            .value_names(&["MIN", "MAX"])
+            .required(false)
+            .help("Add latency picked uniformly between MIN and MAX milliseconds to blobstore operations. For multiplexed stores will only apply to the first store in the multiplex."),
+    )
+    .arg(
+        Arg::with_name(CHAOS_KEY_PREFIX_ARG)
+            .long(CHAOS_KEY_PREFIX_ARG)
+            .takes_value(true)
+            .required(false)
+            .help("Only inject chaos errors and latency for keys with this prefix."),
+    )
     .arg(
         Arg::with_name(MANIFOLD_API_KEY_ARG)
             .long(MANIFOLD_API_KEY_ARG)
@@ -837,6 +886,13 @@ pub fn add_blobstore_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
             .required(false)
             .help("Manifold API key"),
     )
+    .arg(
+        Arg::with_name(ENCRYPTION_KEY_FILE_ARG)
+            .long(ENCRYPTION_KEY_FILE_ARG)
+            .takes_value(true)
+            .required(false)
+            .help("File holding a hex-encoded 256-bit key to encrypt blobs at rest with"),
+    )
 }
 
 pub fn add_mcrouter_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
@@ -1109,6 +1165,14 @@ pub fn parse_blobstore_options<'a>(matches: &ArgMatches<'a>) -> BlobstoreOptions
         .value_of(WRITE_QPS_ARG)
         .map(|v| v.parse().expect("Provided qps is not u32"));
 
+    let read_burst: Option<NonZeroU32> = matches
+        .value_of(READ_BURST_ARG)
+        .map(|v| v.parse().expect("Provided burst is not u32"));
+
+    let write_burst: Option<NonZeroU32> = matches
+        .value_of(WRITE_BURST_ARG)
+        .map(|v| v.parse().expect("Provided burst is not u32"));
+
     let read_chaos: Option<NonZeroU32> = matches
         .value_of(READ_CHAOS_ARG)
         .map(|v| v.parse().expect("Provided chaos is not u32"));
@@ -1117,15 +1181,43 @@ pub fn parse_blobstore_options<'a>(matches: &ArgMatches<'a>) -> BlobstoreOptions
         .value_of(WRITE_CHAOS_ARG)
         .map(|v| v.parse().expect("Provided chaos is not u32"));
 
+    let chaos_error_kind: ChaosErrorKind = matches
+        .value_of(CHAOS_ERROR_KIND_ARG)
+        .map(|v| v.parse().expect("Provided chaos error kind is not valid"))
+        .unwrap_or_default();
+
+    let chaos_latency_ms: Option<(u64, u64)> = matches.values_of(CHAOS_LATENCY_ARG).map(|v| {
+        let v: Vec<u64> = v
+            .map(|v| v.parse().expect("Provided chaos latency is not u64"))
+            .collect();
+        (v[0], v[1])
+    });
+
+    let chaos_key_prefix: Option<String> = matches
+        .value_of(CHAOS_KEY_PREFIX_ARG)
+        .map(|prefix| prefix.to_string());
+
     let manifold_api_key: Option<String> = matches
         .value_of(MANIFOLD_API_KEY_ARG)
         .map(|api_key| api_key.to_string());
 
+    let encryption_key: Option<EncryptionKey> =
+        matches.value_of(ENCRYPTION_KEY_FILE_ARG).map(|path| {
+            let hex = fs::read_to_string(path).expect("Failed to read encryption key file");
+            EncryptionKey::from_hex(&hex).expect("Provided encryption key is not valid")
+        });
+
     BlobstoreOptions::new(
-        ChaosOptions::new(read_chaos, write_chaos),
-        ThrottleOptions::new(read_qps, write_qps),
+        ChaosOptions::new(read_chaos, write_chaos)
+            .with_error_kind(chaos_error_kind)
+            .with_latency_ms(chaos_latency_ms)
+            .with_key_prefix_filter(chaos_key_prefix),
+        ThrottleOptions::new(read_qps, write_qps)
+            .with_read_burst(read_burst)
+            .with_write_burst(write_burst),
         manifold_api_key,
     )
+    .with_encryption_key(encryption_key)
 }
 
 pub fn maybe_enable_mcrouter<'a>(fb: FacebookInit, matches: &ArgMatches<'a>) {
@@ -17,7 +17,7 @@ use futures_ext::{try_boxfuture, BoxFuture, FutureExt};
 use futures_old::prelude::*;
 
 use blobstore::Blobstore;
-use blobstore_factory::{make_blobstore, BlobstoreOptions, ReadOnlyStorage};
+use blobstore_factory::{make_blobstore, parse_blobstore_spec, BlobstoreOptions, ReadOnlyStorage};
 use cacheblob::{new_memcache_blobstore, CacheBlobstoreExt};
 use cloned::cloned;
 use cmdlib::args;
@@ -39,6 +39,7 @@ use std::str::FromStr;
 use crate::error::SubcommandError;
 
 pub const SCRUB_BLOBSTORE_ACTION_ARG: &'static str = "scrub-blobstore-action";
+pub const BLOBSTORE_SPEC_ARG: &'static str = "blobstore-spec";
 
 fn get_blobconfig(
     blob_config: BlobConfig,
@@ -110,7 +111,10 @@ pub async fn subcommand_blobstore_fetch<'a>(
     let repo_id = args::get_repo_id(fb, &matches)?;
     let (_, config) = args::get_config(fb, &matches)?;
     let redaction = config.redaction;
-    let storage_config = config.storage_config;
+    let mut storage_config = config.storage_config;
+    if let Some(spec) = sub_m.value_of(BLOBSTORE_SPEC_ARG) {
+        storage_config.blobstore = parse_blobstore_spec(spec)?;
+    }
     let inner_blobstore_id = args::get_u64_opt(&sub_m, "inner-blobstore-id");
     let scrub_action = sub_m
         .value_of(SCRUB_BLOBSTORE_ACTION_ARG)
@@ -95,6 +95,14 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
                 .required(false)
                 .help("If main blobstore in the storage config is a multiplexed one, use inner blobstore with this id")
         )
+        .arg(
+            Arg::with_name(blobstore_fetch::BLOBSTORE_SPEC_ARG)
+                .long(blobstore_fetch::BLOBSTORE_SPEC_ARG)
+                .takes_value(true)
+                .required(false)
+                .conflicts_with("inner-blobstore-id")
+                .help("Fetch from this blobstore instead of the repo's, e.g. file:/path, sqlite:/path, manifold:bucket/prefix"),
+        )
         .arg(
             Arg::with_name(blobstore_fetch::SCRUB_BLOBSTORE_ACTION_ARG)
                 .long(blobstore_fetch::SCRUB_BLOBSTORE_ACTION_ARG)
@@ -137,6 +137,7 @@ fn maybe_schedule_healer_for_storage(
         queue_db,
         mysql_options,
         readonly_storage,
+        None,
         ctx.logger().clone(),
     )
     .and_then(|sql_factory| sql_factory.open::<SqlBlobstoreSyncQueue>())
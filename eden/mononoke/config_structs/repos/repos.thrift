@@ -152,6 +152,13 @@ struct RawBlobstoreManifoldWithTtl {
     2: string manifold_prefix,
     3: i64 ttl_secs,
 }
+struct RawBlobstoreGcs {
+    1: string gcs_bucket,
+    2: string gcs_prefix,
+    // Path to a file holding an OAuth2 access token. Requests are
+    // anonymous if unset.
+    3: optional string gcs_credentials,
+}
 
 // Configuration for a single blobstore. These are intended to be defined in a
 // separate blobstore.toml config file, and then referenced by name from a
@@ -168,6 +175,7 @@ union RawBlobstoreConfig {
     6: RawBlobstoreMysql mysql,
     7: RawBlobstoreMultiplexed multiplexed,
     8: RawBlobstoreManifoldWithTtl manifold_with_ttl,
+    9: RawBlobstoreGcs gcs,
 }
 
 struct RawBlobstoreIdConfig {
@@ -251,6 +259,8 @@ struct RawBookmarkHook {
 struct RawHookManagerParams {
     /// Wether to disable the acl checker or not (intended for testing purposes)
     1: bool disable_acl_checker,
+    /// Whether pushes run hooks on bonsai changesets rather than on hg changesets
+    2: bool run_hooks_on_bonsai,
 }
 
 struct RawHookConfig {
@@ -261,6 +271,7 @@ struct RawHookConfig {
     5: optional string bypass_pushvar,
     6: optional map<string, string> (rust.type = "HashMap") config_strings,
     7: optional map<string, i32> (rust.type = "HashMap") config_ints,
+    8: optional list<string> depends_on,
 }
 
 struct RawLfsParams {
@@ -269,6 +280,9 @@ struct RawLfsParams {
     2: optional i32 rollout_percentage,
     // Whether to generate lfs blobs in hg sync job
     3: optional bool generate_lfs_blob_in_hg_sync_job,
+    // Whether clients that get lfs pointers get them for every file,
+    // including empty ones, rather than only files above the threshold
+    4: optional bool always_lfs,
 }
 
 struct RawBundle2ReplayParams {
@@ -315,6 +329,8 @@ struct RawCommitSyncConfig {
 
 struct RawSourceControlServiceParams {
     1: bool permit_writes;
+    // Number of changeset specifier lookups to cache (0 disables the cache)
+    2: optional i64 resolve_cache_size;
 }
 
 // Raw configuration for health monitoring of the
@@ -199,7 +199,9 @@ pub(crate) async fn save_fastlog_batch_by_unode_id(
         .await
 }
 
-fn generate_fastlog_batch_key(unode_entry: Entry<ManifestUnodeId, FileUnodeId>) -> String {
+pub(crate) fn generate_fastlog_batch_key(
+    unode_entry: Entry<ManifestUnodeId, FileUnodeId>,
+) -> String {
     let key_part = match unode_entry {
         Entry::Leaf(file_unode_id) => format!("fileunode.{}", file_unode_id),
         Entry::Tree(mf_unode_id) => format!("manifestunode.{}", mf_unode_id),
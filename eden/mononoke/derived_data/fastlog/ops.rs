@@ -9,26 +9,35 @@ use anyhow::{format_err, Error};
 use blobrepo::BlobRepo;
 use blobstore::{Blobstore, Loadable};
 use cloned::cloned;
-use context::CoreContext;
+use context::{CoreContext, PerfCounterType};
 use derived_data::BonsaiDerived;
-use futures::{compat::Future01CompatExt, future::TryFutureExt, FutureExt as NewFutureExt};
-use futures_ext::{bounded_traversal::bounded_traversal_stream, BoxFuture, FutureExt};
-use futures_old::{
-    future,
-    stream::{iter_ok, FuturesUnordered},
-    Future, Stream,
+use futures::{
+    compat::Future01CompatExt,
+    future::{try_join_all, TryFutureExt},
+    stream::{self as new_stream, StreamExt as NewStreamExt, TryStreamExt},
+    FutureExt as NewFutureExt,
 };
+use futures_ext::{bounded_traversal::bounded_traversal_stream, FutureExt};
+use futures_old::{future, stream::iter_ok, Future, Stream};
 use manifest::{Entry, ManifestOps};
 use maplit::{hashmap, hashset};
 use mononoke_types::{ChangesetId, FileUnodeId, MPath, ManifestUnodeId};
+use std::cmp::Reverse;
 use std::collections::{HashMap, HashSet};
-use std::iter::FromIterator;
 use std::sync::Arc;
 use unodes::RootUnodeManifestId;
 
 use crate::fastlog_impl::{fetch_fastlog_batch_by_unode_id, fetch_flattened};
 use crate::mapping::{FastlogParent, RootFastlog};
 
+/// Number of consecutive changesets without fastlog data after which the traversal switches
+/// to backfilling: fastlog data for a whole BFS layer is derived with higher concurrency.
+const BACKFILL_THRESHOLD: usize = 10;
+/// How many changesets of a BFS layer fastlog data is derived for concurrently.
+const DERIVE_CONCURRENCY: usize = 10;
+/// How many changesets of a BFS layer fastlog data is derived for concurrently when backfilling.
+const BACKFILL_CONCURRENCY: usize = 100;
+
 /// Returns a full history of the given path starting from the given unode in BFS order.
 ///
 /// This is the public API of this crate i.e. what clients should use if they want to
@@ -57,12 +66,23 @@ use crate::mapping::{FastlogParent, RootFastlog};
 ///   1 - prefetch parents for the already processed nodes
 ///   2 - construct new BFS layer from already processed nodes
 ///   3 - if there was no processed nodes - it's the first iteration - use starting node
-///   4 - return the new nodes and contruct new state
+///   4 - sort the new nodes by generation number descending, then by changeset id
+///   5 - return the new nodes and contruct new state
 /// The stream stops when there is nothing to return.
 ///
+/// Sorting each layer makes the order deterministic: it doesn't depend on the order in which
+/// the parents were prefetched. For the graph above the history is E, D, B, C, A: B comes
+/// before C because its generation number is higher.
+///
 /// Why to pop all nodes on the same depth and not just one commit at a time?
 /// Because if history contains merges and parents for more than one node on the current depth
 /// haven't been fetched yet, we can fetch them at the same time using FuturesUnordered.
+///
+/// Fastlog data that is missing for a changeset is derived on the fly. If more than
+/// BACKFILL_THRESHOLD changesets in a row turn out to have no fastlog data the traversal is
+/// walking through a range of history that was never derived (or was lost), so the whole layer
+/// is derived with higher concurrency and the backfilled changesets are counted in the
+/// FastlogBackfilledChangesets perf counter.
 pub fn list_file_history(
     ctx: CoreContext,
     repo: BlobRepo,
@@ -89,6 +109,7 @@ pub fn list_file_history(
                     visited,
                     starting_node: Some(changeset_id),
                     processed_nodes: vec![],
+                    missing_streak: 0,
                 }),
                 // unfold
                 {
@@ -98,6 +119,7 @@ pub fn list_file_history(
                               visited,
                               starting_node,
                               processed_nodes,
+                              missing_streak,
                           }| {
                         do_history_unfold(
                             ctx.clone(),
@@ -107,6 +129,7 @@ pub fn list_file_history(
                             processed_nodes,
                             visited,
                             history_graph,
+                            missing_streak,
                         )
                     }
                 },
@@ -151,6 +174,8 @@ struct TraversalState {
     starting_node: Option<ChangesetId>,
     // nodes that were already used and needed to construct next BFS layer
     processed_nodes: Vec<ChangesetId>,
+    // number of consecutive changesets that had no fastlog data when they were prefetched
+    missing_streak: usize,
 }
 
 fn do_history_unfold(
@@ -162,6 +187,7 @@ fn do_history_unfold(
     mut visited: HashSet<ChangesetId>,
     // commit graph: changesets -> parents
     mut history_graph: HashMap<ChangesetId, Option<Vec<ChangesetId>>>,
+    missing_streak: usize,
 ) -> impl Future<Item = (Vec<ChangesetId>, Option<TraversalState>), Error = Error> {
     let mut prefetch_parents = vec![];
     for cs_id in &processed_nodes {
@@ -172,32 +198,39 @@ fn do_history_unfold(
     }
 
     // if prefetch_parents is empty the function doesn't do anything and just returns an empty vector
-    prefetch_unodes_for_changesets(ctx.clone(), repo.clone(), path.clone(), prefetch_parents).map(
-        move |unode_batches| {
-            // fill the commit graph
-            for unode_batch in unode_batches {
-                process_unode_batch(unode_batch, &mut history_graph);
-            }
+    prefetch_unodes_for_changesets(
+        ctx.clone(),
+        repo.clone(),
+        path.clone(),
+        prefetch_parents,
+        missing_streak,
+    )
+    .and_then(move |(unode_batches, missing_streak)| {
+        // fill the commit graph
+        for unode_batch in unode_batches {
+            process_unode_batch(unode_batch, &mut history_graph);
+        }
 
-            // generate next BFS stage
-            let mut next_to_yield = vec![];
-            for cs_id in &processed_nodes {
-                if let Some(Some(parents)) = history_graph.get(&cs_id) {
-                    // parents are fetched, ready to process
-                    for p in parents {
-                        if visited.insert(*p) {
-                            next_to_yield.push(*p);
-                        }
+        // generate next BFS stage
+        let mut next_to_yield = vec![];
+        for cs_id in &processed_nodes {
+            if let Some(Some(parents)) = history_graph.get(&cs_id) {
+                // parents are fetched, ready to process
+                for p in parents {
+                    if visited.insert(*p) {
+                        next_to_yield.push(*p);
                     }
                 }
             }
+        }
 
-            if next_to_yield.is_empty() {
-                if let Some(node) = starting_node {
-                    next_to_yield = vec![node];
-                }
+        if next_to_yield.is_empty() {
+            if let Some(node) = starting_node {
+                next_to_yield = vec![node];
             }
+        }
 
+        sort_bfs_layer(ctx, repo, next_to_yield).map(move |next_to_yield| {
             let new_state = if next_to_yield.is_empty() {
                 None
             } else {
@@ -207,34 +240,105 @@ fn do_history_unfold(
                     starting_node: None,
                     // nodes that were just used are needed to generate the next BFS layer
                     processed_nodes: next_to_yield.clone(),
+                    missing_streak,
                 })
             };
             (next_to_yield, new_state)
-        },
-    )
+        })
+    })
 }
 
-/// prefetches unode batches for each given changeset id
-fn prefetch_unodes_for_changesets(
+/// sorts nodes of a BFS layer by generation number descending, then by changeset id
+fn sort_bfs_layer(
     ctx: CoreContext,
     repo: BlobRepo,
-    path: Option<MPath>,
-    changeset_ids: Vec<ChangesetId>,
-) -> impl Future<Item = Vec<Vec<(ChangesetId, Vec<FastlogParent>)>>, Error = Error> {
-    if changeset_ids.is_empty() {
-        return future::ok(vec![]).left_future();
+    nodes: Vec<ChangesetId>,
+) -> impl Future<Item = Vec<ChangesetId>, Error = Error> {
+    if nodes.len() < 2 {
+        return future::ok(nodes).left_future();
     }
 
-    let prefetch_futs = changeset_ids.into_iter().map({
-        cloned!(ctx, repo);
-        move |cs_id| prefetch_history_by_changeset(ctx.clone(), repo.clone(), cs_id, path.clone())
+    let generation_futs = nodes.into_iter().map(move |cs_id| {
+        repo.get_generation_number(ctx.clone(), cs_id)
+            .and_then(move |maybe_gen| {
+                maybe_gen.ok_or_else(|| format_err!("Generation number is not found {:?}", cs_id))
+            })
+            .map(move |gen| (gen, cs_id))
     });
 
-    FuturesUnordered::from_iter(prefetch_futs)
-        .collect()
+    future::join_all(generation_futs)
+        .map(|mut nodes| {
+            nodes.sort_by_key(|(gen, cs_id)| (Reverse(*gen), *cs_id));
+            nodes.into_iter().map(|(_, cs_id)| cs_id).collect()
+        })
         .right_future()
 }
 
+/// prefetches unode batches for each given changeset id, deriving fastlog data for the
+/// changesets that don't have it yet.
+///
+/// `missing_streak` is the number of consecutive changesets without fastlog data seen so far,
+/// the updated value is returned together with the batches.
+fn prefetch_unodes_for_changesets(
+    ctx: CoreContext,
+    repo: BlobRepo,
+    path: Option<MPath>,
+    changeset_ids: Vec<ChangesetId>,
+    missing_streak: usize,
+) -> impl Future<Item = (Vec<Vec<(ChangesetId, Vec<FastlogParent>)>>, usize), Error = Error> {
+    async move {
+        if changeset_ids.is_empty() {
+            return Ok((vec![], missing_streak));
+        }
+
+        // optimistically try to fetch history for all the changesets
+        let prefetched = try_join_all(changeset_ids.into_iter().map(|cs_id| {
+            cloned!(ctx, repo, path);
+            async move {
+                let entry = find_unode_entry(&ctx, &repo, cs_id, &path).await?;
+                let maybe_history = prefetch_history(ctx, repo, entry).compat().await?;
+                Ok::<_, Error>((cs_id, entry, maybe_history))
+            }
+        }))
+        .await?;
+
+        let mut unode_batches = vec![];
+        let mut missing = vec![];
+        for (cs_id, entry, maybe_history) in prefetched {
+            match maybe_history {
+                Some(history) => unode_batches.push(history),
+                None => missing.push((cs_id, entry)),
+            }
+        }
+
+        if missing.is_empty() {
+            return Ok((unode_batches, 0));
+        }
+
+        let missing_streak = missing_streak + missing.len();
+        let concurrency = if missing_streak > BACKFILL_THRESHOLD {
+            ctx.perf_counters().add_to_counter(
+                PerfCounterType::FastlogBackfilledChangesets,
+                missing.len() as i64,
+            );
+            BACKFILL_CONCURRENCY
+        } else {
+            DERIVE_CONCURRENCY
+        };
+
+        let derived: Vec<_> = new_stream::iter(missing)
+            .map(|(cs_id, entry)| derive_history(&ctx, &repo, cs_id, &path, entry))
+            .buffer_unordered(concurrency)
+            .try_collect()
+            .await?;
+        unode_batches.extend(derived);
+
+        Ok((unode_batches, missing_streak))
+    }
+    .boxed()
+    .compat()
+}
+
 fn process_unode_batch(
     unode_batch: Vec<(ChangesetId, Vec<FastlogParent>)>,
     graph: &mut HashMap<ChangesetId, Option<Vec<ChangesetId>>>,
@@ -275,84 +379,90 @@ fn process_unode_batch(
     }
 }
 
-fn prefetch_history_by_changeset(
-    ctx: CoreContext,
-    repo: BlobRepo,
+async fn find_unode_entry(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
     changeset_id: ChangesetId,
-    path: Option<MPath>,
-) -> BoxFuture<Vec<(ChangesetId, Vec<FastlogParent>)>, Error> {
-    cloned!(ctx, repo);
+    path: &Option<MPath>,
+) -> Result<Entry<ManifestUnodeId, FileUnodeId>, Error> {
+    let root_unode_mf_id = RootUnodeManifestId::derive(ctx.clone(), repo.clone(), changeset_id)
+        .compat()
+        .await?;
+    let entry_opt = root_unode_mf_id
+        .manifest_unode_id()
+        .find_entry(ctx.clone(), repo.get_blobstore(), path.clone())
+        .compat()
+        .await?;
+    entry_opt.ok_or_else(|| format_err!("Unode entry is not found {:?} {:?}", changeset_id, path))
+}
+
+/// derives fastlog data for a changeset that doesn't have it and fetches the history.
+///
+/// If the changeset is already marked as derived but the fastlog batch of the unode is still
+/// missing (e.g. the batch was lost from the blobstore) the batch is regenerated once more
+/// before giving up.
+async fn derive_history(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    changeset_id: ChangesetId,
+    path: &Option<MPath>,
+    entry: Entry<ManifestUnodeId, FileUnodeId>,
+) -> Result<Vec<(ChangesetId, Vec<FastlogParent>)>, Error> {
+    RootFastlog::derive(ctx.clone(), repo.clone(), changeset_id)
+        .compat()
+        .await?;
+    if let Some(history) = prefetch_history(ctx.clone(), repo.clone(), entry)
+        .compat()
+        .await?
+    {
+        return Ok(history);
+    }
+
+    rederive_fastlog_batch(ctx, repo, entry).await?;
+    let history_opt = prefetch_history(ctx.clone(), repo.clone(), entry)
+        .compat()
+        .await?;
+    history_opt
+        .ok_or_else(|| format_err!("Fastlog data is not found {:?} {:?}", changeset_id, path))
+}
+
+/// regenerates fastlog batches of the changeset that introduced the unode, regardless of
+/// whether the changeset is marked as derived
+async fn rederive_fastlog_batch(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    entry: Entry<ManifestUnodeId, FileUnodeId>,
+) -> Result<(), Error> {
     let blobstore = repo.get_blobstore();
-    RootUnodeManifestId::derive(ctx.clone(), repo.clone(), changeset_id.clone())
-        .from_err()
-        .and_then({
-            cloned!(blobstore, ctx, path);
-            move |root_unode_mf_id| {
-                root_unode_mf_id
-                    .manifest_unode_id()
-                    .find_entry(ctx, blobstore, path)
-            }
-        })
-        .and_then({
-            cloned!(path);
-            move |entry_opt| {
-                entry_opt.ok_or_else(|| {
-                    format_err!(
-                        "Unode entry is not found {:?} {:?}",
-                        changeset_id.clone(),
-                        path,
-                    )
-                })
-            }
-        })
-        .and_then({
-            cloned!(ctx, repo, path);
-            move |entry| {
-                // optimistically try to fetch history for a unode
-                prefetch_history(ctx.clone(), repo.clone(), entry).and_then({
-                    move |maybe_history| match maybe_history {
-                        Some(history) => future::ok(history).left_future(),
-                        // if there is no history, let's try to derive batched fastlog data
-                        // and fetch history again
-                        None => RootFastlog::derive(ctx.clone(), repo.clone(), changeset_id)
-                            .from_err()
-                            .and_then({
-                                cloned!(ctx, repo);
-                                move |_| {
-                                    prefetch_history(ctx.clone(), repo.clone(), entry).and_then(
-                                        move |history_opt| {
-                                            history_opt.ok_or_else(|| {
-                                                format_err!(
-                                                    "Fastlog data is not found {:?} {:?}",
-                                                    changeset_id,
-                                                    path
-                                                )
-                                            })
-                                        },
-                                    )
-                                }
-                            })
-                            .right_future(),
-                    }
-                })
-            }
-        })
-        .boxify()
+    let linknode = match entry.load(ctx.clone(), &blobstore).compat().await? {
+        Entry::Tree(mf_unode) => *mf_unode.linknode(),
+        Entry::Leaf(file_unode) => *file_unode.linknode(),
+    };
+    let bonsai = linknode.load(ctx.clone(), &blobstore).compat().await?;
+    RootFastlog::derive_from_parents(ctx.clone(), repo.clone(), bonsai, vec![])
+        .compat()
+        .await?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::fastlog_impl::generate_fastlog_batch_key;
     use crate::mapping::RootFastlog;
-    use blobrepo::save_bonsai_changesets;
+    use blobrepo::{save_bonsai_changesets, DangerousOverride};
     use blobrepo_factory::new_memblob_empty;
+    use blobstore::BlobstoreBytes;
     use context::CoreContext;
     use fbinit::FacebookInit;
     use fixtures::{create_bonsai_changeset_with_files, store_files};
+    use futures_ext::BoxFuture;
     use manifest::{Entry, ManifestOps};
     use maplit::btreemap;
     use mononoke_types::{ChangesetId, FileUnodeId, MPath, ManifestUnodeId};
-    use std::collections::{HashMap, HashSet, VecDeque};
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Mutex;
+    use tests_utils::{create_dag, Dag};
     use tokio_compat::runtime::Runtime;
 
     #[fbinit::test]
@@ -443,40 +553,110 @@ mod test {
         let filename = "1";
         let filepath = path(filename);
 
-        let mut bonsais = vec![];
-        let mut graph = HashMap::new();
-        let mut create_branch = |branch, number, mut parents: Vec<_>| {
-            for i in 0..number {
-                let content = format!("{} - {}", branch, i);
-                let stored_files = rt.block_on_std(store_files(
-                    ctx.clone(),
-                    btreemap! { filename => Some(content.as_str()) },
-                    repo.clone(),
-                ));
+        let all: Vec<_> = (0..105).map(|i| format!("All{}", i)).collect();
+        let all_top = &all[all.len() - 1];
+        let desc = format!(
+            "A0-A1-A2-A3-AB0; B0-AB0;
+             C0-C1-CD0; D0-D1-CD0; CD0-CD1;
+             AB0,CD1-{};
+             {}-L0-Top0; {}-M0-Top0; Top0-Top1",
+            all.join("-"),
+            all_top,
+            all_top,
+        );
+
+        // Every commit modifies the file, so that all of them are in its history
+        let dag = Dag::parse(&desc).unwrap();
+        let names: Vec<_> = dag.names().map(String::from).collect();
+        let files = names
+            .iter()
+            .map(|name| (name.as_str(), btreemap! { filename => Some(name.as_str()) }))
+            .collect();
+        let commits = rt
+            .block_on_std(create_dag(&ctx, &repo, &desc, files))
+            .unwrap();
 
-                let bcs = create_bonsai_changeset_with_files(parents.clone(), stored_files);
-                let bcs_id = bcs.get_changeset_id();
-                bonsais.push(bcs);
+        let graph: HashMap<_, Vec<_>> = names
+            .iter()
+            .map(|name| {
+                let parents = dag.parents(name).unwrap();
+                (commits[name], parents.iter().map(|p| commits[p]).collect())
+            })
+            .collect();
+        let top = commits["Top1"];
 
-                graph.insert(bcs_id.clone(), parents);
-                parents = vec![bcs_id];
-            }
-            parents.get(0).unwrap().clone()
-        };
+        let unode_entry = derive_and_get_unode_entry(
+            ctx.clone(),
+            repo.clone(),
+            &mut rt,
+            top.clone(),
+            filepath.clone(),
+        );
+        derive_fastlog(ctx.clone(), repo.clone(), &mut rt, top);
+
+        let history = rt
+            .block_on(list_file_history(ctx.clone(), repo.clone(), filepath, unode_entry).collect())
+            .unwrap();
+
+        let expected = bfs(&graph, top);
+        assert_eq!(history, expected);
+    }
 
-        let a_top = create_branch("A", 4, vec![]);
-        let b_top = create_branch("B", 1, vec![]);
-        let ab_top = create_branch("A+B", 1, vec![a_top, b_top]);
+    #[fbinit::test]
+    fn test_list_history_deterministic(fb: FacebookInit) {
+        // test generates commit graph where every BFS layer has nodes with the same generation
+        // number and checks that the history is the same every time it's listed
+        //
+        //                o - top
+        //             /     \
+        //           o         o
+        //         /   \     /   \
+        //        o     o   o     o
+        //       / \   / \ / \   / \
+        //      o  o  o  o o  o  o  o
+        //       \  \  \  \ /  /  /  /
+        //                o - root
+        //
 
-        let c_top = create_branch("C", 2, vec![]);
-        let d_top = create_branch("D", 2, vec![]);
-        let cd_top = create_branch("C+D", 2, vec![c_top, d_top]);
+        let repo = new_memblob_empty(None).unwrap();
+        let mut rt = Runtime::new().unwrap();
+        let ctx = CoreContext::test_mock(fb);
 
-        let all_top = create_branch("A+B+C+D", 105, vec![ab_top, cd_top]);
+        let filename = "1";
+        let filepath = path(filename);
 
-        let l_top = create_branch("L", 1, vec![all_top.clone()]);
-        let m_top = create_branch("M", 1, vec![all_top.clone()]);
-        let top = create_branch("Top", 2, vec![l_top, m_top]);
+        let mut bonsais = vec![];
+        let mut graph = HashMap::new();
+        let mut create_changeset = |content: String, parents: Vec<ChangesetId>| {
+            let stored_files = rt.block_on_std(store_files(
+                ctx.clone(),
+                btreemap! { filename => Some(content.as_str()) },
+                repo.clone(),
+            ));
+
+            let bcs = create_bonsai_changeset_with_files(parents.clone(), stored_files);
+            let bcs_id = bcs.get_changeset_id();
+            bonsais.push(bcs);
+            graph.insert(bcs_id, parents);
+            bcs_id
+        };
+
+        let root = create_changeset("root".to_string(), vec![]);
+        let mut layer: Vec<_> = (0..8)
+            .map(|i| create_changeset(format!("0 - {}", i), vec![root]))
+            .collect();
+        let mut depth = 1;
+        while layer.len() > 1 {
+            layer = layer
+                .chunks(2)
+                .enumerate()
+                .map(|(i, parents)| {
+                    create_changeset(format!("{} - {}", depth, i), parents.to_vec())
+                })
+                .collect();
+            depth += 1;
+        }
+        let top = layer[0];
 
         rt.block_on(save_bonsai_changesets(bonsais, ctx.clone(), repo.clone()))
             .unwrap();
@@ -490,12 +670,23 @@ mod test {
         );
         derive_fastlog(ctx.clone(), repo.clone(), &mut rt, top);
 
-        let history = rt
+        let first = rt
+            .block_on(
+                list_file_history(
+                    ctx.clone(),
+                    repo.clone(),
+                    filepath.clone(),
+                    unode_entry.clone(),
+                )
+                .collect(),
+            )
+            .unwrap();
+        let second = rt
             .block_on(list_file_history(ctx.clone(), repo.clone(), filepath, unode_entry).collect())
             .unwrap();
 
-        let expected = bfs(&graph, top);
-        assert_eq!(history, expected);
+        assert_eq!(first, second);
+        assert_eq!(first, bfs(&graph, top));
     }
 
     #[fbinit::test]
@@ -566,13 +757,15 @@ mod test {
             let bcs = rt.block_on_std(create_changeset(format!("R - {}", number), vec![bottom_id]));
             let right_id = bcs.get_changeset_id();
             bonsais.push(bcs);
-            expected.push(right_id.clone());
 
             // left
             let bcs = rt.block_on_std(create_changeset(format!("L - {}", number), vec![bottom_id]));
             let left_id = bcs.get_changeset_id();
             bonsais.push(bcs);
-            expected.push(left_id.clone());
+
+            // left and right have the same generation number, so they're ordered by changeset id
+            expected.push(std::cmp::max(left_id, right_id));
+            expected.push(std::cmp::min(left_id, right_id));
 
             // up
             let bcs = rt.block_on_std(create_changeset(
@@ -611,26 +804,211 @@ mod test {
         assert_eq!(history, expected);
     }
 
+    /// BFS over the graph where every layer is sorted by generation number descending, then
+    /// by changeset id, which is the order `list_file_history` guarantees.
+    #[fbinit::test]
+    fn test_list_history_with_wiped_fastlog_range(fb: FacebookInit) {
+        let repo = new_memblob_empty(None).unwrap();
+        let mut rt = Runtime::new().unwrap();
+        let ctx = CoreContext::test_mock(fb);
+
+        let filepath = path("1");
+        let (all, expected) = create_linear_history(ctx.clone(), repo.clone(), &mut rt);
+        let latest = all.last().unwrap().clone();
+        derive_fastlog(ctx.clone(), repo.clone(), &mut rt, latest);
+
+        // wipe both the derived data mapping and the fastlog batches for a range
+        // in the middle of the history
+        let mut wiped = HashSet::new();
+        for cs_id in &all[50..150] {
+            wiped.insert(format!("derived_rootfastlog.{}", cs_id));
+            if !expected.contains(cs_id) {
+                // file wasn't changed in this commit, so the batch belongs to an older one
+                continue;
+            }
+            let entry = derive_and_get_unode_entry(
+                ctx.clone(),
+                repo.clone(),
+                &mut rt,
+                *cs_id,
+                filepath.clone(),
+            );
+            wiped.insert(generate_fastlog_batch_key(entry));
+        }
+        let repo = wipe_keys(&repo, wiped);
+
+        let unode_entry = derive_and_get_unode_entry(
+            ctx.clone(),
+            repo.clone(),
+            &mut rt,
+            latest,
+            filepath.clone(),
+        );
+        let history = rt
+            .block_on(list_file_history(ctx.clone(), repo.clone(), filepath, unode_entry).collect())
+            .unwrap();
+        assert_eq!(history, expected);
+    }
+
+    #[fbinit::test]
+    fn test_list_history_with_lost_fastlog_batch(fb: FacebookInit) {
+        let repo = new_memblob_empty(None).unwrap();
+        let mut rt = Runtime::new().unwrap();
+        let ctx = CoreContext::test_mock(fb);
+
+        let filepath = path("1");
+        let (all, expected) = create_linear_history(ctx.clone(), repo.clone(), &mut rt);
+        let latest = all.last().unwrap().clone();
+        derive_fastlog(ctx.clone(), repo.clone(), &mut rt, latest);
+
+        // the changeset is still marked as derived, but its fastlog batch is gone
+        let unode_entry = derive_and_get_unode_entry(
+            ctx.clone(),
+            repo.clone(),
+            &mut rt,
+            latest,
+            filepath.clone(),
+        );
+        let repo = wipe_keys(&repo, hashset! { generate_fastlog_batch_key(unode_entry) });
+
+        let history = rt
+            .block_on(list_file_history(ctx.clone(), repo.clone(), filepath, unode_entry).collect())
+            .unwrap();
+        assert_eq!(history, expected);
+    }
+
+    /// Blobstore that pretends keys ending with any of the wiped suffixes are missing
+    /// until they are written again
+    #[derive(Debug)]
+    struct WipedKeysBlobstore {
+        inner: Arc<dyn Blobstore>,
+        wiped: Mutex<HashSet<String>>,
+    }
+
+    impl WipedKeysBlobstore {
+        fn is_wiped(&self, key: &str) -> bool {
+            self.wiped
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|suffix| key.ends_with(suffix))
+        }
+    }
+
+    impl Blobstore for WipedKeysBlobstore {
+        fn get(&self, ctx: CoreContext, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+            if self.is_wiped(&key) {
+                return future::ok(None).boxify();
+            }
+            self.inner.get(ctx, key)
+        }
+
+        fn put(
+            &self,
+            ctx: CoreContext,
+            key: String,
+            value: BlobstoreBytes,
+        ) -> BoxFuture<(), Error> {
+            self.wiped
+                .lock()
+                .unwrap()
+                .retain(|suffix| !key.ends_with(suffix));
+            self.inner.put(ctx, key, value)
+        }
+    }
+
+    fn wipe_keys(repo: &BlobRepo, wiped: HashSet<String>) -> BlobRepo {
+        repo.dangerous_override(|inner| -> Arc<dyn Blobstore> {
+            Arc::new(WipedKeysBlobstore {
+                inner,
+                wiped: Mutex::new(wiped),
+            })
+        })
+    }
+
+    /// creates a linear history where file "1" is changed in every other commit.
+    /// Returns all the commits from the oldest and the expected history of "1".
+    fn create_linear_history(
+        ctx: CoreContext,
+        repo: BlobRepo,
+        rt: &mut Runtime,
+    ) -> (Vec<ChangesetId>, Vec<ChangesetId>) {
+        let mut bonsais = vec![];
+        let mut parents = vec![];
+        let mut all = vec![];
+        let mut expected = vec![];
+        for i in 1..300 {
+            let file = if i % 2 == 1 { "2" } else { "1" };
+            let content = format!("{}", i);
+            let stored_files = rt.block_on_std(store_files(
+                ctx.clone(),
+                btreemap! { file => Some(content.as_str()) },
+                repo.clone(),
+            ));
+
+            let bcs = create_bonsai_changeset_with_files(parents, stored_files);
+            let bcs_id = bcs.get_changeset_id();
+            bonsais.push(bcs);
+            all.push(bcs_id);
+            if i % 2 != 1 {
+                expected.push(bcs_id);
+            }
+            parents = vec![bcs_id];
+        }
+        rt.block_on(save_bonsai_changesets(bonsais, ctx, repo))
+            .unwrap();
+
+        expected.reverse();
+        (all, expected)
+    }
+
     fn bfs(graph: &HashMap<ChangesetId, Vec<ChangesetId>>, node: ChangesetId) -> Vec<ChangesetId> {
+        let mut generations = HashMap::new();
         let mut response = vec![];
-        let mut queue = VecDeque::new();
+        let mut layer = vec![node];
         let mut visited = HashSet::new();
-        queue.push_back(node.clone());
         visited.insert(node);
 
-        while let Some(node) = queue.pop_front() {
-            if let Some(parents) = graph.get(&node) {
-                for p in parents {
-                    if visited.insert(*p) {
-                        queue.push_back(*p);
+        while !layer.is_empty() {
+            let mut next_layer = vec![];
+            for node in &layer {
+                if let Some(parents) = graph.get(node) {
+                    for p in parents {
+                        if visited.insert(*p) {
+                            next_layer.push(*p);
+                        }
                     }
                 }
             }
-            response.push(node);
+            next_layer.sort_by_key(|cs_id| {
+                (Reverse(generation(graph, &mut generations, *cs_id)), *cs_id)
+            });
+            response.extend(layer);
+            layer = next_layer;
         }
         response
     }
 
+    fn generation(
+        graph: &HashMap<ChangesetId, Vec<ChangesetId>>,
+        generations: &mut HashMap<ChangesetId, u64>,
+        node: ChangesetId,
+    ) -> u64 {
+        if let Some(gen) = generations.get(&node) {
+            return *gen;
+        }
+        let gen = graph
+            .get(&node)
+            .into_iter()
+            .flatten()
+            .map(|p| generation(graph, generations, *p))
+            .max()
+            .unwrap_or(0)
+            + 1;
+        generations.insert(node, gen);
+        gen
+    }
+
     fn derive_and_get_unode_entry(
         ctx: CoreContext,
         repo: BlobRepo,
@@ -29,7 +29,7 @@ use hgproto::HgCommands;
 use hooks::HookManager;
 use hooks_content_stores::{InMemoryChangesetStore, InMemoryFileContentStore};
 use metaconfig_types::{BlobConfig, HookManagerParams};
-use mononoke_types::Timestamp;
+use mononoke_types::{RepositoryId, Timestamp};
 use nonzero_ext::nonzero;
 use rand::{thread_rng, Rng};
 use repo_client::MononokeRepoBuilder;
@@ -192,12 +192,16 @@ async fn dispatch(
 }
 
 fn build_noop_hook_manager(fb: FacebookInit) -> HookManager {
+    // No hooks are registered, so this can be shared between all repos.
     HookManager::new(
         fb,
+        "noop".to_string(),
+        RepositoryId::new(0),
         Box::new(InMemoryChangesetStore::new()),
         Arc::new(InMemoryFileContentStore::new()),
         HookManagerParams {
             disable_acl_checker: true,
+            run_hooks_on_bonsai: false,
         },
         ScubaSampleBuilder::with_discard(),
     )
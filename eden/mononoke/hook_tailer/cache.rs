@@ -0,0 +1,308 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+
+use anyhow::{Error, Result};
+use bookmarks::BookmarkName;
+use futures::compat::Future01CompatExt;
+use hooks::{
+    ChangesetHookExecutionID, FileHookExecutionID, HookExecution, HookManager, HookOutcome,
+    HookRejectionInfo, HookSuggestion,
+};
+use hooks_content_stores::ChangedFileType;
+use lazy_static::lazy_static;
+use mercurial_types::HgChangesetId;
+use metaconfig_types::HookConfig;
+use mononoke_types::{hash, ChangesetId, RepositoryId};
+use serde::{Deserialize, Serialize};
+use sql::{queries, Connection};
+use sql_ext::SqlConstructors;
+
+/// Outcomes of hooks on changesets, kept across tailer runs so that hooks aren't run again on
+/// changesets they already ran on with the same config. Changes to the code of a hook aren't
+/// noticed, so its outcomes have to be invalidated by hand.
+#[derive(Clone)]
+pub struct HookOutcomeCache {
+    read_connection: Connection,
+    write_connection: Connection,
+}
+
+queries! {
+    read SelectOutcomes(repo_id: RepositoryId, cs_id: ChangesetId, >list hook_names: String) -> (String, String, Vec<u8>) {
+        "SELECT hook_name, config_hash, outcomes
+         FROM hook_outcome_cache
+         WHERE repo_id = {repo_id} AND cs_id = {cs_id} AND hook_name IN {hook_names}"
+    }
+
+    write ReplaceOutcomes(values: (repo_id: RepositoryId, hook_name: String, config_hash: String, cs_id: ChangesetId, outcomes: Vec<u8>)) {
+        none,
+        "REPLACE INTO hook_outcome_cache (repo_id, hook_name, config_hash, cs_id, outcomes)
+         VALUES {values}"
+    }
+
+    write DeleteHookOutcomes(>list hook_names: String) {
+        none,
+        "DELETE FROM hook_outcome_cache WHERE hook_name IN {hook_names}"
+    }
+}
+
+impl SqlConstructors for HookOutcomeCache {
+    const LABEL: &'static str = "hook_outcome_cache";
+
+    fn from_connections(
+        write_connection: Connection,
+        read_connection: Connection,
+        _read_master_connection: Connection,
+    ) -> Self {
+        Self {
+            write_connection,
+            read_connection,
+        }
+    }
+
+    fn get_up_query() -> &'static str {
+        include_str!("schemas/sqlite-hook-outcome-cache.sql")
+    }
+}
+
+impl HookOutcomeCache {
+    /// The cached outcomes of the hooks that `hook_manager` runs for `bookmark` on `cs_id`, or
+    /// None unless all of those hooks have outcomes cached with their current config.
+    pub async fn get(
+        &self,
+        hook_manager: &HookManager,
+        repo_id: RepositoryId,
+        bookmark: &BookmarkName,
+        cs_id: ChangesetId,
+        hg_cs_id: HgChangesetId,
+    ) -> Result<Option<Vec<HookOutcome>>> {
+        let config_hashes = config_hashes(hook_manager, bookmark);
+        if config_hashes.is_empty() {
+            return Ok(None);
+        }
+        let hook_names: Vec<_> = config_hashes.keys().cloned().collect();
+        let rows = SelectOutcomes::query(&self.read_connection, &repo_id, &cs_id, &hook_names[..])
+            .compat()
+            .await?;
+        let cached: BTreeMap<_, _> = rows
+            .into_iter()
+            .filter(|(hook_name, config_hash, _)| config_hashes.get(hook_name) == Some(config_hash))
+            .map(|(hook_name, _, outcomes)| (hook_name, outcomes))
+            .collect();
+        if cached.len() < config_hashes.len() {
+            return Ok(None);
+        }
+
+        let mut outcomes = Vec::new();
+        for (hook_name, serialized) in cached {
+            let entries: Vec<CachedOutcome> = serde_json::from_slice(&serialized)?;
+            outcomes.extend(entries.into_iter().map(|entry| {
+                entry.into_outcome(hook_manager, &hook_name, bookmark, cs_id, hg_cs_id)
+            }));
+        }
+        Ok(Some(outcomes))
+    }
+
+    /// Cache `outcomes`, the outcomes of running the hooks that `hook_manager` runs for
+    /// `bookmark` on `cs_id`. Hooks without outcomes, e.g. file hooks on a changeset without
+    /// files, are cached as such.
+    pub async fn put(
+        &self,
+        hook_manager: &HookManager,
+        repo_id: RepositoryId,
+        bookmark: &BookmarkName,
+        cs_id: ChangesetId,
+        outcomes: &[HookOutcome],
+    ) -> Result<()> {
+        let config_hashes = config_hashes(hook_manager, bookmark);
+        let mut by_hook: HashMap<&str, Vec<CachedOutcome>> = config_hashes
+            .keys()
+            .map(|hook_name| (hook_name.as_str(), Vec::new()))
+            .collect();
+        for outcome in outcomes {
+            if let Some(entries) = by_hook.get_mut(outcome.get_hook_name()) {
+                entries.push(CachedOutcome::new(outcome));
+            }
+        }
+
+        let rows = by_hook
+            .into_iter()
+            .map(|(hook_name, entries)| {
+                Ok((
+                    hook_name.to_string(),
+                    config_hashes[hook_name].clone(),
+                    serde_json::to_vec(&entries)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let values: Vec<_> = rows
+            .iter()
+            .map(|(hook_name, config_hash, serialized)| {
+                (&repo_id, hook_name, config_hash, &cs_id, serialized)
+            })
+            .collect();
+        ReplaceOutcomes::query(&self.write_connection, &values[..])
+            .compat()
+            .await?;
+        Ok(())
+    }
+
+    /// Forget the cached outcomes of these hooks, in all repos.
+    pub async fn invalidate_hooks(&self, hook_names: &[String]) -> Result<()> {
+        if !hook_names.is_empty() {
+            DeleteHookOutcomes::query(&self.write_connection, hook_names)
+                .compat()
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Hashes of the configs of the hooks run for `bookmark`, by hook name
+fn config_hashes(hook_manager: &HookManager, bookmark: &BookmarkName) -> HashMap<String, String> {
+    hook_manager
+        .hook_configs_for_bookmark(bookmark)
+        .into_iter()
+        .map(|(hook_name, config)| (hook_name, config_hash(&config)))
+        .collect()
+}
+
+fn config_hash(config: &HookConfig) -> String {
+    // Sort the maps, so that equal configs hash the same.
+    let strings: BTreeMap<_, _> = config.strings.iter().collect();
+    let ints: BTreeMap<_, _> = config.ints.iter().collect();
+    let mut context = hash::Context::new(b"hook_config");
+    context.update(format!("{:?}", (&config.bypass, strings, ints)));
+    context.finish().to_hex().to_string()
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedOutcome {
+    /// The file the outcome is for, for file hooks
+    file: Option<(String, CachedChange)>,
+    execution: CachedExecution,
+}
+
+#[derive(Serialize, Deserialize)]
+enum CachedChange {
+    Added,
+    Deleted,
+    Modified,
+}
+
+#[derive(Serialize, Deserialize)]
+enum CachedExecution {
+    Accepted,
+    Rejected {
+        description: String,
+        long_description: String,
+        suggestions: Vec<HookSuggestion>,
+        policy_url: Option<String>,
+    },
+    Skipped,
+}
+
+impl CachedOutcome {
+    fn new(outcome: &HookOutcome) -> Self {
+        let file = match outcome {
+            HookOutcome::ChangesetHook(..) => None,
+            HookOutcome::FileHook(id, _) => {
+                let change = match id.file.changed_file_type() {
+                    ChangedFileType::Added => CachedChange::Added,
+                    ChangedFileType::Deleted => CachedChange::Deleted,
+                    ChangedFileType::Modified => CachedChange::Modified,
+                };
+                Some((id.file.path.clone(), change))
+            }
+        };
+        let execution = match outcome.get_execution() {
+            HookExecution::Accepted => CachedExecution::Accepted,
+            HookExecution::Rejected(info) => CachedExecution::Rejected {
+                description: info.description.to_string(),
+                long_description: info.long_description.clone(),
+                suggestions: info.suggestions.clone(),
+                policy_url: info.policy_url.clone(),
+            },
+            HookExecution::Skipped => CachedExecution::Skipped,
+        };
+        Self { file, execution }
+    }
+
+    fn into_outcome(
+        self,
+        hook_manager: &HookManager,
+        hook_name: &str,
+        bookmark: &BookmarkName,
+        cs_id: ChangesetId,
+        hg_cs_id: HgChangesetId,
+    ) -> HookOutcome {
+        let execution = match self.execution {
+            CachedExecution::Accepted => HookExecution::Accepted,
+            CachedExecution::Rejected {
+                description,
+                long_description,
+                suggestions,
+                policy_url,
+            } => {
+                let mut info =
+                    HookRejectionInfo::new_long(intern_description(description), long_description);
+                info.suggestions = suggestions;
+                info.policy_url = policy_url;
+                HookExecution::Rejected(info)
+            }
+            CachedExecution::Skipped => HookExecution::Skipped,
+        };
+        match self.file {
+            None => HookOutcome::ChangesetHook(
+                ChangesetHookExecutionID {
+                    cs_id: hg_cs_id.into(),
+                    bonsai_cs_id: Some(cs_id),
+                    hook_name: hook_name.to_string(),
+                    bookmark: bookmark.clone(),
+                },
+                execution,
+            ),
+            Some((path, change)) => {
+                let ty = match change {
+                    CachedChange::Added => ChangedFileType::Added,
+                    CachedChange::Deleted => ChangedFileType::Deleted,
+                    CachedChange::Modified => ChangedFileType::Modified,
+                };
+                HookOutcome::FileHook(
+                    FileHookExecutionID {
+                        cs_id: hg_cs_id.into(),
+                        bonsai_cs_id: Some(cs_id),
+                        hook_name: hook_name.to_string(),
+                        file: hook_manager.hook_file(path, hg_cs_id.into(), ty),
+                        bookmark: bookmark.clone(),
+                    },
+                    execution,
+                )
+            }
+        }
+    }
+}
+
+/// `HookRejectionInfo::description` is static, as hooks only use literals for it. There are
+/// few distinct descriptions, so each of them is leaked once.
+fn intern_description(description: String) -> &'static str {
+    lazy_static! {
+        static ref DESCRIPTIONS: Mutex<HashSet<&'static str>> = Mutex::new(HashSet::new());
+    }
+    let mut descriptions = DESCRIPTIONS.lock().expect("lock poisoned");
+    if let Some(description) = descriptions.get(description.as_str()) {
+        return description;
+    }
+    let description: &'static str = Box::leak(description.into_boxed_str());
+    descriptions.insert(description);
+    description
+}
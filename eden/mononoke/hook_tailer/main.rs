@@ -8,11 +8,13 @@
 #![deny(warnings)]
 #![feature(never_type)]
 
+pub mod cache;
 pub mod tailer;
 
 use anyhow::{format_err, Error, Result};
 use blobrepo_factory::BlobrepoBuilder;
 use bookmarks::BookmarkName;
+use cache::HookOutcomeCache;
 use clap::{App, Arg, ArgMatches};
 use cloned::cloned;
 use cmdlib::helpers::block_execute;
@@ -28,18 +30,25 @@ use futures_old::{
     stream::repeat,
     Future, Stream,
 };
-use hooks::HookOutcome;
+use globset::{Glob, GlobSetBuilder};
+use hooks::{format_rejections, HookOutcome};
 use manifold::{ManifoldHttpClient, RequestContext};
 use mercurial_types::{HgChangesetId, HgNodeHash};
+use metaconfig_types::HookType;
+use regex::Regex;
+use scuba_ext::ScubaSampleBuilder;
 use slog::{debug, info, o, Drain, Level, Logger};
 use slog_glog_fmt::{kv_categorizer, kv_defaults, GlogFormat};
+use sql_ext::SqlConstructors;
+use std::collections::HashSet;
 use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::{BufRead, BufReader};
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
-use tailer::Tailer;
+use tailer::{ChangesetExcludes, PathExcludes, ProgressReporter, Tailer};
 use thiserror::Error;
 use tokio_timer::sleep;
 
@@ -57,6 +66,8 @@ fn main(fb: FacebookInit) -> Result<()> {
     let init_revision = matches.value_of("init_revision").map(String::from);
     let continuous = matches.is_present("continuous");
     let limit = cmdlib::args::get_u64(&matches, "limit", 1000);
+    let progress_every = cmdlib::args::get_u64(&matches, "progress_every", 100);
+    let scuba_table = matches.value_of("scuba-table").map(String::from);
     let changeset = matches.value_of("changeset").map_or(None, |cs| {
         Some(HgChangesetId::from_str(cs).expect("Invalid changesetid"))
     });
@@ -84,6 +95,54 @@ fn main(fb: FacebookInit) -> Result<()> {
     }
 
     let disabled_hooks = cmdlib::args::parse_disabled_hooks_no_repo_prefix(&matches, &logger);
+    let hook_type = match matches.value_of("hook_type") {
+        Some("file") => Some(HookType::PerAddedOrModifiedFile),
+        Some("changeset") => Some(HookType::PerChangeset),
+        _ => None,
+    };
+    let only_hooks: HashSet<String> = matches
+        .values_of("only_hook")
+        .into_iter()
+        .flatten()
+        .map(|name| name.to_string())
+        .collect();
+
+    let mut excluded_paths = GlobSetBuilder::new();
+    for path in matches.values_of("exclude_path").into_iter().flatten() {
+        excluded_paths.add(Glob::new(path)?);
+    }
+    let excluded_paths = excluded_paths.build()?;
+
+    let changeset_excludes = ChangesetExcludes::new(
+        matches
+            .value_of("exclude_author")
+            .map(Regex::new)
+            .transpose()?,
+        matches
+            .value_of("exclude_message")
+            .map(Regex::new)
+            .transpose()?,
+    );
+
+    let outcome_cache = matches
+        .value_of("cache_path")
+        .map(|path| HookOutcomeCache::with_sqlite_path(path, false))
+        .transpose()?;
+    let invalidated_hooks: Vec<String> = matches
+        .values_of("invalidate_hook")
+        .into_iter()
+        .flatten()
+        .map(|name| name.to_string())
+        .collect();
+    let invalidate = {
+        cloned!(outcome_cache);
+        async move {
+            if let Some(cache) = outcome_cache {
+                cache.invalidate_hooks(&invalidated_hooks).await?;
+            }
+            Result::<_, Error>::Ok(())
+        }
+    };
 
     let caching = cmdlib::args::init_cachelib(fb, &matches, None);
     let readonly_storage = cmdlib::args::parse_readonly_storage(&matches);
@@ -99,7 +158,10 @@ fn main(fb: FacebookInit) -> Result<()> {
         &logger,
     );
 
-    let blobrepo = builder.build().boxed().compat();
+    let blobrepo = invalidate
+        .and_then(move |()| builder.build())
+        .boxed()
+        .compat();
 
     let rc = RequestContext {
         bucket_name: "mononoke_prod".into(),
@@ -110,7 +172,8 @@ fn main(fb: FacebookInit) -> Result<()> {
     let id = "ManifoldBlob";
 
     let manifold_client = ManifoldHttpClient::new(fb, id, rc)?;
-    let ctx = CoreContext::new_with_logger(fb, logger.clone());
+    let ctx = CoreContext::new_with_logger(fb, logger.clone())
+        .with_mutated_scuba(|_| ScubaSampleBuilder::with_opt_table(fb, scuba_table));
     let fut = blobrepo.and_then({
         cloned!(logger, config);
         move |blobrepo| {
@@ -127,6 +190,11 @@ fn main(fb: FacebookInit) -> Result<()> {
                             manifold_client.clone(),
                             excl.into_iter().map(|(_, cs)| cs).collect(),
                             &disabled_hooks,
+                            hook_type,
+                            &only_hooks,
+                            excluded_paths,
+                            changeset_excludes,
+                            outcome_cache,
                         )
                     }
                 })
@@ -156,7 +224,14 @@ fn main(fb: FacebookInit) -> Result<()> {
                                 f.then(|_| {
                                     repeat(()).for_each(move |()| {
                                         let fut = tail.run();
-                                        process_hook_results(fut, logger.clone()).and_then(|_| {
+                                        process_hook_results(
+                                            fut,
+                                            tail.path_excludes().clone(),
+                                            tail.changeset_excludes().clone(),
+                                            tail.hook_filter().map(String::from),
+                                            logger.clone(),
+                                        )
+                                        .and_then(|_| {
                                             sleep(Duration::new(10, 0)).map_err(|err| {
                                                 format_err!("Tokio timer error {:?}", err)
                                             })
@@ -167,13 +242,30 @@ fn main(fb: FacebookInit) -> Result<()> {
                             }
                             (_, Some(changeset)) => {
                                 let fut = tail.run_single_changeset(changeset);
-                                process_hook_results(fut, logger)
+                                process_hook_results(
+                                    fut,
+                                    tail.path_excludes().clone(),
+                                    tail.changeset_excludes().clone(),
+                                    tail.hook_filter().map(String::from),
+                                    logger,
+                                )
                             }
                             _ => {
                                 let logger = logger.clone();
                                 f.then(move |_| {
-                                    let fut = tail.run_with_limit(limit);
-                                    process_hook_results(fut, logger)
+                                    let progress = ProgressReporter::logging(
+                                        logger.clone(),
+                                        progress_every,
+                                        limit,
+                                    );
+                                    let fut = tail.run_with_limit(limit, progress);
+                                    process_hook_results(
+                                        fut,
+                                        tail.path_excludes().clone(),
+                                        tail.changeset_excludes().clone(),
+                                        tail.hook_filter().map(String::from),
+                                        logger,
+                                    )
                                 })
                                 .boxify()
                             }
@@ -195,21 +287,27 @@ fn main(fb: FacebookInit) -> Result<()> {
 
 fn process_hook_results(
     fut: BoxFuture<Vec<HookOutcome>, Error>,
+    path_excludes: Arc<PathExcludes>,
+    changeset_excludes: Arc<ChangesetExcludes>,
+    hook_filter: Option<String>,
     logger: Logger,
 ) -> BoxFuture<(), Error> {
     fut.and_then(move |res| {
         let mut hooks_stat = HookExecutionStat::new();
+        hooks_stat.skipped = path_excludes.take_skipped_count();
+        hooks_stat.skipped_changesets = changeset_excludes.take_skipped_count();
+        hooks_stat.filter = hook_filter;
 
         debug!(logger, "==== Hooks results ====");
-        res.into_iter().for_each(|outcome| {
-            hooks_stat.record_hook_execution(&outcome);
-
-            if outcome.is_rejection() {
-                info!(logger, "{}", outcome);
-            } else {
-                debug!(logger, "{}", outcome);
-            }
+        res.iter().for_each(|outcome| {
+            hooks_stat.record_hook_execution(outcome);
+            // Includes both the hg and the bonsai changeset id, which the rejections summary
+            // below doesn't.
+            debug!(logger, "{}", outcome);
         });
+        if let Some(rejections) = format_rejections(&res, usize::MAX) {
+            info!(logger, "{}", rejections);
+        }
 
         info!(logger, "==== Hooks stat: {} ====", hooks_stat);
 
@@ -225,6 +323,9 @@ fn process_hook_results(
 struct HookExecutionStat {
     accepted: usize,
     rejected: usize,
+    skipped: usize,
+    skipped_changesets: usize,
+    filter: Option<String>,
 }
 
 impl HookExecutionStat {
@@ -232,6 +333,9 @@ impl HookExecutionStat {
         Self {
             accepted: 0,
             rejected: 0,
+            skipped: 0,
+            skipped_changesets: 0,
+            filter: None,
         }
     }
 
@@ -248,9 +352,13 @@ impl fmt::Display for HookExecutionStat {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "accepted: {}, rejected: {}",
-            self.accepted, self.rejected
-        )
+            "accepted: {}, rejected: {}, skipped files: {}, skipped changesets: {}",
+            self.accepted, self.rejected, self.skipped, self.skipped_changesets
+        )?;
+        if let Some(filter) = &self.filter {
+            write!(f, ", filter: {}", filter)?;
+        }
+        Ok(())
     }
 }
 
@@ -289,12 +397,40 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
                 .help("a file containing changesets to exclude that is separated by new lines")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("exclude_author")
+                .long("exclude-author")
+                .takes_value(true)
+                .help("skip changesets whose author matches this regex"),
+        )
+        .arg(
+            Arg::with_name("exclude_message")
+                .long("exclude-message")
+                .takes_value(true)
+                .help("skip changesets whose commit message matches this regex"),
+        )
+        .arg(
+            Arg::with_name("exclude_path")
+                .long("exclude-path")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+                .help("skip file hooks on files whose path matches this glob"),
+        )
         .arg(
             Arg::with_name("limit")
                 .long("limit")
                 .takes_value(true)
                 .help("limit number of commits to process (non-continuous only). Default: 1000"),
         )
+        .arg(
+            Arg::with_name("progress_every")
+                .long("progress-every")
+                .takes_value(true)
+                .help(
+                    "log progress every N commits (non-continuous only), 0 to disable. Default: 100",
+                ),
+        )
         .arg(
             Arg::with_name("continuous")
                 .long("continuous")
@@ -306,6 +442,52 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
                 .takes_value(true)
                 .help("the initial revision to start at"),
         )
+        .arg(
+            Arg::with_name("hook_type")
+                .long("hook-type")
+                .takes_value(true)
+                .possible_values(&["file", "changeset", "all"])
+                .default_value("all")
+                .help("only run hooks of this type"),
+        )
+        .arg(
+            Arg::with_name("only_hook")
+                .long("only-hook")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("only run this hook, which must be configured for the bookmark"),
+        )
+        .arg(
+            Arg::with_name("scuba-table")
+                .long("scuba-table")
+                .takes_value(true)
+                .help("log per-changeset hook timings to this Scuba table"),
+        )
+        .arg(
+            Arg::with_name("cache_path")
+                .long("cache-path")
+                .takes_value(true)
+                // Cached outcomes are keyed by hook config only, so they would be wrong for
+                // changesets some of whose files file hooks skipped.
+                .conflicts_with("exclude_path")
+                .help("SQLite database to cache hook outcomes in across runs, off unless given"),
+        )
+        .arg(
+            Arg::with_name("no_cache")
+                .long("no-cache")
+                .conflicts_with("cache_path")
+                .help("run all hooks, without reading or writing cached outcomes (the default)"),
+        )
+        .arg(
+            Arg::with_name("invalidate_hook")
+                .long("invalidate-hook")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("cache_path")
+                .help("forget the cached outcomes of this hook, e.g. after changing its code"),
+        )
         .arg(
             Arg::with_name("debug")
                 .long("debug")
@@ -0,0 +1,17 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+CREATE TABLE hook_outcome_cache (
+  repo_id INTEGER(11) NOT NULL,
+  hook_name VARCHAR(255) NOT NULL,
+  -- Hash of the hook config the outcomes were produced with
+  config_hash VARCHAR(64) NOT NULL,
+  cs_id VARBINARY(32) NOT NULL,
+  -- JSON list of the outcomes of the hook on the changeset
+  outcomes BLOB NOT NULL,
+  PRIMARY KEY (repo_id, hook_name, cs_id)
+);
@@ -7,24 +7,31 @@
 
 #![deny(warnings)]
 
+use crate::cache::HookOutcomeCache;
 use anyhow::{format_err, Error, Result};
 use blobrepo::BlobRepo;
+use blobstore::Loadable;
 use bookmarks::BookmarkName;
 use cloned::cloned;
 use context::CoreContext;
-use futures::{FutureExt, TryFutureExt};
+use fbinit::FacebookInit;
+use futures::{compat::Future01CompatExt, FutureExt, TryFutureExt};
 use futures_ext::{spawn_future, BoxFuture, FutureExt as OldFutureExt};
 use futures_old::{Future, Stream};
-use hooks::{hook_loader::load_hooks, HookManager, HookOutcome};
-use hooks_content_stores::{blobrepo_text_only_store, BlobRepoChangesetStore};
+use futures_stats::TimedFutureExt;
+use globset::GlobSet;
+use hooks::{hook_loader::load_hooks, FileHookFilter, HookExecutionMode, HookManager, HookOutcome};
+use hooks_content_stores::{blobrepo_text_only_store, BlobRepoChangesetStore, HookChangesetId};
 use manifold::{ManifoldHttpClient, PayloadRange};
 use mercurial_types::HgChangesetId;
-use metaconfig_types::RepoConfig;
-use mononoke_types::ChangesetId;
+use metaconfig_types::{HookType, RepoConfig};
+use mononoke_types::{BonsaiChangeset, ChangesetId};
+use regex::Regex;
 use revset::AncestorsNodeStream;
 use scuba_ext::ScubaSampleBuilder;
-use slog::{debug, info};
+use slog::{debug, info, Logger};
 use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use thiserror::Error;
 
@@ -36,6 +43,138 @@ pub struct Tailer {
     last_rev_key: String,
     manifold_client: ManifoldHttpClient,
     excludes: HashSet<ChangesetId>,
+    changeset_excludes: Arc<ChangesetExcludes>,
+    path_excludes: Arc<PathExcludes>,
+    hook_filter: Option<String>,
+    outcome_cache: Option<HookOutcomeCache>,
+}
+
+/// Excludes changesets from hook runs based on their contents rather than their hash.
+#[derive(Default)]
+pub struct ChangesetExcludes {
+    author: Option<Regex>,
+    message: Option<Regex>,
+    skipped: AtomicUsize,
+}
+
+impl ChangesetExcludes {
+    pub fn new(author: Option<Regex>, message: Option<Regex>) -> Self {
+        Self {
+            author,
+            message,
+            skipped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether the changeset's author or message matches one of the exclusion patterns.
+    pub fn is_excluded(&self, cs: &BonsaiChangeset) -> bool {
+        let author_matches = self
+            .author
+            .as_ref()
+            .map_or(false, |re| re.is_match(cs.author()));
+        let message_matches = self
+            .message
+            .as_ref()
+            .map_or(false, |re| re.is_match(cs.message()));
+        author_matches || message_matches
+    }
+
+    fn is_empty(&self) -> bool {
+        self.author.is_none() && self.message.is_none()
+    }
+
+    /// Number of changesets that were skipped since the last call to this function.
+    pub fn take_skipped_count(&self) -> usize {
+        self.skipped.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// Excludes changed files from file hook runs based on their path.
+pub struct PathExcludes {
+    paths: GlobSet,
+    skipped: AtomicUsize,
+}
+
+impl PathExcludes {
+    pub fn new(paths: GlobSet) -> Self {
+        Self {
+            paths,
+            skipped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of files that were skipped since the last call to this function.
+    pub fn take_skipped_count(&self) -> usize {
+        self.skipped.swap(0, Ordering::Relaxed)
+    }
+}
+
+impl FileHookFilter for PathExcludes {
+    fn skips(&self, _changeset_id: HookChangesetId, path: &str) -> bool {
+        let skips = self.paths.is_match(path);
+        if skips {
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+        }
+        skips
+    }
+}
+
+/// How far a run over a known number of changesets got
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Progress {
+    pub processed: u64,
+    pub total: u64,
+    pub rejections: usize,
+}
+
+/// Reports the progress of a run every `every` changesets, or never if `every` is 0.
+pub struct ProgressReporter {
+    every: u64,
+    progress: Progress,
+    report: Box<dyn FnMut(Progress) + Send>,
+}
+
+impl ProgressReporter {
+    pub fn new(every: u64, total: u64, report: Box<dyn FnMut(Progress) + Send>) -> Self {
+        Self {
+            every,
+            progress: Progress {
+                processed: 0,
+                total,
+                rejections: 0,
+            },
+            report,
+        }
+    }
+
+    /// Report progress at info level.
+    pub fn logging(logger: Logger, every: u64, total: u64) -> Self {
+        Self::new(
+            every,
+            total,
+            Box::new(move |progress| {
+                info!(
+                    logger,
+                    "Processed {}/{} changesets, {} rejections so far",
+                    progress.processed,
+                    progress.total,
+                    progress.rejections
+                );
+            }),
+        )
+    }
+
+    /// Record the outcomes of the hooks on one more changeset.
+    pub fn record(&mut self, outcomes: &[HookOutcome]) {
+        self.progress.processed += 1;
+        self.progress.rejections += outcomes
+            .iter()
+            .filter(|outcome| outcome.is_rejection())
+            .count();
+        if self.every > 0 && self.progress.processed % self.every == 0 {
+            (self.report)(self.progress);
+        }
+    }
 }
 
 impl Tailer {
@@ -47,19 +186,39 @@ impl Tailer {
         manifold_client: ManifoldHttpClient,
         excludes: HashSet<ChangesetId>,
         disabled_hooks: &HashSet<String>,
+        hook_type: Option<HookType>,
+        only_hooks: &HashSet<String>,
+        excluded_paths: GlobSet,
+        changeset_excludes: ChangesetExcludes,
+        outcome_cache: Option<HookOutcomeCache>,
     ) -> Result<Tailer> {
+        let path_excludes = Arc::new(PathExcludes::new(excluded_paths));
         let changeset_store = BlobRepoChangesetStore::new(repo.clone());
         let content_store = blobrepo_text_only_store(repo.clone(), config.hook_max_file_size);
 
         let mut hook_manager = HookManager::new(
             ctx.fb,
+            repo.name().clone(),
+            repo.get_repoid(),
             Box::new(changeset_store),
             content_store,
             Default::default(),
             ScubaSampleBuilder::with_discard(),
         );
 
-        load_hooks(ctx.fb, &mut hook_manager, config, disabled_hooks)?;
+        hook_manager.set_hook_max_file_size(config.hook_max_file_size);
+        hook_manager.set_file_hook_filter(path_excludes.clone());
+
+        let hook_filter = describe_hook_filter(hook_type.as_ref(), only_hooks);
+        load_filtered_hooks(
+            ctx.fb,
+            &mut hook_manager,
+            config,
+            &bookmark,
+            disabled_hooks,
+            hook_type.as_ref(),
+            only_hooks,
+        )?;
 
         let repo_id = repo.get_repoid().id();
         let last_rev_key = format!("{}{}", "__mononoke_hook_tailer_last_rev.", repo_id).to_string();
@@ -72,9 +231,26 @@ impl Tailer {
             last_rev_key,
             manifold_client,
             excludes,
+            changeset_excludes: Arc::new(changeset_excludes),
+            path_excludes,
+            hook_filter,
+            outcome_cache,
         })
     }
 
+    pub fn changeset_excludes(&self) -> &Arc<ChangesetExcludes> {
+        &self.changeset_excludes
+    }
+
+    pub fn path_excludes(&self) -> &Arc<PathExcludes> {
+        &self.path_excludes
+    }
+
+    /// Description of the filter restricting which hooks run, if any
+    pub fn hook_filter(&self) -> Option<&str> {
+        self.hook_filter.as_deref()
+    }
+
     pub fn get_last_rev_key(&self) -> String {
         self.last_rev_key.clone()
     }
@@ -87,6 +263,8 @@ impl Tailer {
         end_rev: HgChangesetId,
         bm: BookmarkName,
         excludes: HashSet<ChangesetId>,
+        changeset_excludes: Arc<ChangesetExcludes>,
+        outcome_cache: Option<HookOutcomeCache>,
     ) -> BoxFuture<Vec<HookOutcome>, Error> {
         debug!(ctx.logger(), "Running in range {} to {}", last_rev, end_rev);
         nodehash_to_bonsai(ctx.clone(), &repo, end_rev)
@@ -96,8 +274,16 @@ impl Tailer {
                     .filter(move |cs| !excludes.contains(cs))
                     .map({
                         move |cs| {
-                            cloned!(ctx, bm, hm, repo);
-                            run_hooks_for_changeset(ctx, repo, hm, bm, cs)
+                            cloned!(ctx, bm, hm, repo, changeset_excludes, outcome_cache);
+                            run_hooks_for_changeset(
+                                ctx,
+                                repo,
+                                hm,
+                                bm,
+                                cs,
+                                changeset_excludes,
+                                outcome_cache,
+                            )
                         }
                     })
                     .map(spawn_future)
@@ -121,7 +307,9 @@ impl Tailer {
             self.repo,
             self.hook_manager,
             self.bookmark,
-            self.excludes
+            self.excludes,
+            self.changeset_excludes,
+            self.outcome_cache
         );
         Tailer::run_in_range0(
             ctx,
@@ -131,6 +319,8 @@ impl Tailer {
             end_rev,
             bookmark,
             excludes,
+            changeset_excludes,
+            outcome_cache,
         )
     }
 
@@ -138,7 +328,14 @@ impl Tailer {
         &self,
         changeset: HgChangesetId,
     ) -> BoxFuture<Vec<HookOutcome>, Error> {
-        cloned!(self.ctx, self.repo, self.hook_manager, self.bookmark,);
+        cloned!(
+            self.ctx,
+            self.repo,
+            self.hook_manager,
+            self.bookmark,
+            self.changeset_excludes,
+            self.outcome_cache
+        );
         repo.get_bonsai_from_hg(ctx, changeset)
             .and_then(move |maybe_bonsai| {
                 maybe_bonsai.ok_or(format_err!(
@@ -148,17 +345,35 @@ impl Tailer {
             })
             .and_then({
                 cloned!(self.ctx);
-                move |bonsai| run_hooks_for_changeset(ctx, repo, hook_manager, bookmark, bonsai)
+                move |bonsai| {
+                    run_hooks_for_changeset(
+                        ctx,
+                        repo,
+                        hook_manager,
+                        bookmark,
+                        bonsai,
+                        changeset_excludes,
+                        outcome_cache,
+                    )
+                }
             })
             .map(|(_, result)| result)
             .boxify()
     }
 
-    pub fn run_with_limit(&self, limit: u64) -> BoxFuture<Vec<HookOutcome>, Error> {
+    /// Run hooks on at most `limit` ancestors of the bookmark, reporting the progress to
+    /// `progress`.
+    pub fn run_with_limit(
+        &self,
+        limit: u64,
+        mut progress: ProgressReporter,
+    ) -> BoxFuture<Vec<HookOutcome>, Error> {
         let ctx = self.ctx.clone();
         let bm = self.bookmark.clone();
         let hm = self.hook_manager.clone();
         let excludes = self.excludes.clone();
+        let changeset_excludes = self.changeset_excludes.clone();
+        let outcome_cache = self.outcome_cache.clone();
 
         let bm_rev = self
             .repo
@@ -180,13 +395,22 @@ impl Tailer {
                     .filter(move |cs| !excludes.contains(cs))
                     .map({
                         move |cs| {
-                            cloned!(ctx, bm, hm, repo);
-                            run_hooks_for_changeset(ctx, repo, hm, bm, cs)
+                            cloned!(ctx, bm, hm, repo, changeset_excludes, outcome_cache);
+                            run_hooks_for_changeset(
+                                ctx,
+                                repo,
+                                hm,
+                                bm,
+                                cs,
+                                changeset_excludes,
+                                outcome_cache,
+                            )
                         }
                     })
                     .map(spawn_future)
                     .buffered(100)
                     .map(|(_, res)| res)
+                    .inspect(move |res| progress.record(res))
                     .concat2()
             })
             .boxify()
@@ -225,6 +449,8 @@ impl Tailer {
                 cloned!(
                     self.bookmark,
                     self.excludes,
+                    self.changeset_excludes,
+                    self.outcome_cache,
                     self.hook_manager,
                     self.repo,
                     self.ctx
@@ -248,6 +474,8 @@ impl Tailer {
                         end_rev,
                         bookmark,
                         excludes,
+                        changeset_excludes,
+                        outcome_cache,
                     )
                     .map(move |res| (end_rev, res))
                 }
@@ -267,6 +495,70 @@ impl Tailer {
     }
 }
 
+/// Add all the hooks from `config` that are not of `hook_type` to `disabled_hooks`, so that only
+/// hooks of that type are loaded. If `hook_type` is `None`, hooks of all types are loaded.
+fn disabled_hooks_for_type(
+    config: &RepoConfig,
+    hook_type: Option<&HookType>,
+    disabled_hooks: &HashSet<String>,
+) -> HashSet<String> {
+    let mut disabled_hooks = disabled_hooks.clone();
+    if let Some(hook_type) = hook_type {
+        disabled_hooks.extend(
+            config
+                .hooks
+                .iter()
+                .filter(|hook| &hook.hook_type != hook_type)
+                .map(|hook| hook.name.clone()),
+        );
+    }
+    disabled_hooks
+}
+
+/// Load the hooks from `config` into `hook_manager`, keeping only the ones of `hook_type` (if
+/// set), and, if `only_hooks` isn't empty, only running those for `bookmark`. Fails if any of
+/// `only_hooks` isn't configured for `bookmark` once hooks of other types are left out.
+fn load_filtered_hooks(
+    fb: FacebookInit,
+    hook_manager: &mut HookManager,
+    config: RepoConfig,
+    bookmark: &BookmarkName,
+    disabled_hooks: &HashSet<String>,
+    hook_type: Option<&HookType>,
+    only_hooks: &HashSet<String>,
+) -> Result<()> {
+    let disabled_hooks = disabled_hooks_for_type(&config, hook_type, disabled_hooks);
+    load_hooks(fb, hook_manager, config, &disabled_hooks)?;
+    if !only_hooks.is_empty() {
+        hook_manager.restrict_hooks_for_bookmark(bookmark, only_hooks)?;
+    }
+    Ok(())
+}
+
+/// Describe the hooks selected by `hook_type` and `only_hooks`, or None if all hooks run
+fn describe_hook_filter(
+    hook_type: Option<&HookType>,
+    only_hooks: &HashSet<String>,
+) -> Option<String> {
+    let type_filter = hook_type.map(|hook_type| match hook_type {
+        HookType::PerChangeset => "changeset hooks",
+        HookType::PerAddedOrModifiedFile => "file hooks",
+    });
+    let mut names: Vec<_> = only_hooks.iter().map(|name| name.as_str()).collect();
+    names.sort();
+
+    match (type_filter, names.is_empty()) {
+        (None, true) => None,
+        (Some(type_filter), true) => Some(format!("only {}", type_filter)),
+        (None, false) => Some(format!("only hooks {}", names.join(", "))),
+        (Some(type_filter), false) => Some(format!(
+            "only hooks {} among {}",
+            names.join(", "),
+            type_filter
+        )),
+    }
+}
+
 fn nodehash_to_bonsai(
     ctx: CoreContext,
     repo: &BlobRepo,
@@ -282,6 +574,8 @@ fn run_hooks_for_changeset(
     hm: Arc<HookManager>,
     bm: BookmarkName,
     cs: ChangesetId,
+    changeset_excludes: Arc<ChangesetExcludes>,
+    outcome_cache: Option<HookOutcomeCache>,
 ) -> impl Future<Item = (HgChangesetId, Vec<HookOutcome>), Error = Error> {
     repo.get_hg_from_bonsai_changeset(ctx.clone(), cs)
         .and_then(move |hg_cs| {
@@ -289,10 +583,52 @@ fn run_hooks_for_changeset(
             let hm = hm.clone();
             let bm = bm.clone();
             async move {
+                if !changeset_excludes.is_empty() {
+                    let bcs = cs.load(ctx.clone(), repo.blobstore()).compat().await?;
+                    if changeset_excludes.is_excluded(&bcs) {
+                        debug!(ctx.logger(), "Skipping excluded changeset {:?}", hg_cs);
+                        changeset_excludes.skipped.fetch_add(1, Ordering::Relaxed);
+                        return Ok((hg_cs, vec![]));
+                    }
+                }
+
+                let repo_id = repo.get_repoid();
+                if let Some(cache) = &outcome_cache {
+                    if let Some(outcomes) = cache.get(&hm, repo_id, &bm, cs, hg_cs).await? {
+                        debug!(ctx.logger(), "Using cached outcomes for {:?}", hg_cs);
+                        return Ok((hg_cs, outcomes));
+                    }
+                }
+
                 debug!(ctx.logger(), "Running hooks for changeset {:?}", hg_cs);
-                let hook_results = hm
-                    .run_hooks_for_bookmark(&ctx, vec![hg_cs], &bm, None)
-                    .await?;
+                let (stats, hook_results) = hm
+                    .run_hooks_for_bookmark(
+                        &ctx,
+                        vec![(hg_cs, Some(cs))],
+                        &bm,
+                        None,
+                        HookExecutionMode::CompleteResults,
+                    )
+                    .timed()
+                    .await;
+                let hook_results = hook_results?;
+                if let Some(cache) = &outcome_cache {
+                    cache.put(&hm, repo_id, &bm, cs, &hook_results).await?;
+                }
+
+                let rejections = hook_results
+                    .iter()
+                    .filter(|outcome| outcome.is_rejection())
+                    .count();
+                let mut scuba = ctx.scuba().clone();
+                scuba
+                    .add("hash", hg_cs.to_hex().to_string())
+                    .add("bookmark", bm.to_string())
+                    .add("hooks_run", hook_results.len() as i64)
+                    .add("rejections", rejections as i64)
+                    .add("elapsed", stats.completion_time.as_millis() as i64)
+                    .log();
+
                 Ok((hg_cs, hook_results))
             }
             .boxed()
@@ -309,3 +645,583 @@ pub enum ErrorKind {
     #[error("Cannot find bonsai for {0}")]
     BonsaiNotFound(HgChangesetId),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_trait::async_trait;
+    use fbinit::FacebookInit;
+    use fixtures::linear;
+    use futures::compat::Future01CompatExt;
+    use globset::{Glob, GlobSetBuilder};
+    use hooks::{
+        ChangesetHookExecutionID, Hook, HookChangeset, HookContext, HookExecution, HookFile,
+        HookRejectionInfo,
+    };
+    use hooks_content_stores::BlobRepoFileContentStore;
+    use maplit::hashset;
+    use metaconfig_types::{BookmarkParams, HookParams};
+    use mononoke_types::{BonsaiChangesetMut, DateTime};
+    use sql_ext::SqlConstructors;
+    use std::str::FromStr;
+    use std::sync::Mutex;
+
+    #[fbinit::compat_test]
+    async fn test_run_hooks_for_changeset_logs_to_scuba(fb: FacebookInit) -> Result<(), Error> {
+        // `test_mock` uses a discard Scuba builder, so this only checks that logging the
+        // per-changeset sample doesn't get in the way of running hooks.
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let hm = HookManager::new(
+            fb,
+            repo.name().clone(),
+            repo.get_repoid(),
+            Box::new(BlobRepoChangesetStore::new(repo.clone())),
+            Arc::new(BlobRepoFileContentStore::new(repo.clone())),
+            Default::default(),
+            ScubaSampleBuilder::with_discard(),
+        );
+
+        let hg_cs = HgChangesetId::from_str("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")?;
+        let cs = nodehash_to_bonsai(ctx.clone(), &repo, hg_cs)
+            .compat()
+            .await?;
+        let (res_cs, outcomes) = run_hooks_for_changeset(
+            ctx,
+            repo,
+            Arc::new(hm),
+            BookmarkName::new("master")?,
+            cs,
+            Default::default(),
+            None,
+        )
+        .compat()
+        .await?;
+
+        assert_eq!(res_cs, hg_cs);
+        assert!(outcomes.is_empty());
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_hook_type_filter(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let hg_cs = HgChangesetId::from_str("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")?;
+        let cs = nodehash_to_bonsai(ctx.clone(), &repo, hg_cs)
+            .compat()
+            .await?;
+
+        let mut config = RepoConfig::default();
+        config.bookmarks = vec![BookmarkParams {
+            bookmark: BookmarkName::new("master")?.into(),
+            hooks: vec!["always_fail_changeset".into(), "conflict_markers".into()],
+            only_fast_forward: false,
+            allowed_users: None,
+            rewrite_dates: None,
+        }];
+        config.hooks = vec![
+            HookParams {
+                name: "always_fail_changeset".into(),
+                hook_type: HookType::PerChangeset,
+                config: Default::default(),
+                depends_on: vec![],
+            },
+            HookParams {
+                name: "conflict_markers".into(),
+                hook_type: HookType::PerAddedOrModifiedFile,
+                config: Default::default(),
+                depends_on: vec![],
+            },
+        ];
+
+        let run = |hook_type: Option<HookType>| {
+            let ctx = ctx.clone();
+            let repo = repo.clone();
+            let config = config.clone();
+            async move {
+                let mut hm = HookManager::new(
+                    fb,
+                    repo.name().clone(),
+                    repo.get_repoid(),
+                    Box::new(BlobRepoChangesetStore::new(repo.clone())),
+                    Arc::new(BlobRepoFileContentStore::new(repo.clone())),
+                    Default::default(),
+                    ScubaSampleBuilder::with_discard(),
+                );
+                let disabled_hooks =
+                    disabled_hooks_for_type(&config, hook_type.as_ref(), &hashset! {});
+                load_hooks(fb, &mut hm, config, &disabled_hooks)?;
+                let (_, outcomes) = run_hooks_for_changeset(
+                    ctx,
+                    repo,
+                    Arc::new(hm),
+                    BookmarkName::new("master")?,
+                    cs,
+                    Default::default(),
+                    None,
+                )
+                .compat()
+                .await?;
+                Result::<_, Error>::Ok(outcomes)
+            }
+        };
+
+        let outcomes = run(Some(HookType::PerChangeset)).await?;
+        assert_eq!(outcomes.len(), 1);
+        assert!(outcomes.iter().all(|outcome| match outcome {
+            HookOutcome::ChangesetHook(..) => true,
+            HookOutcome::FileHook(..) => false,
+        }));
+
+        let outcomes = run(Some(HookType::PerAddedOrModifiedFile)).await?;
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(|outcome| match outcome {
+            HookOutcome::ChangesetHook(..) => false,
+            HookOutcome::FileHook(..) => true,
+        }));
+
+        let outcomes = run(None).await?;
+        assert_eq!(outcomes.len(), 3);
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_only_hooks_filter(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let hg_cs = HgChangesetId::from_str("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")?;
+        let cs = nodehash_to_bonsai(ctx.clone(), &repo, hg_cs)
+            .compat()
+            .await?;
+
+        let hook = |name: &str, hook_type| HookParams {
+            name: name.into(),
+            hook_type,
+            config: Default::default(),
+            depends_on: vec![],
+        };
+        let mut config = RepoConfig::default();
+        config.bookmarks = vec![BookmarkParams {
+            bookmark: BookmarkName::new("master")?.into(),
+            hooks: vec![
+                "always_fail_changeset".into(),
+                "block_empty_commit".into(),
+                "conflict_markers".into(),
+                "no_bad_filenames".into(),
+            ],
+            only_fast_forward: false,
+            allowed_users: None,
+            rewrite_dates: None,
+        }];
+        config.hooks = vec![
+            hook("always_fail_changeset", HookType::PerChangeset),
+            hook("block_empty_commit", HookType::PerChangeset),
+            hook("conflict_markers", HookType::PerAddedOrModifiedFile),
+            hook("no_bad_filenames", HookType::PerAddedOrModifiedFile),
+            hook("limit_commitsize", HookType::PerChangeset),
+        ];
+
+        let run = |hook_type: Option<HookType>, only_hooks: HashSet<String>| {
+            let ctx = ctx.clone();
+            let repo = repo.clone();
+            let config = config.clone();
+            async move {
+                let mut hm = HookManager::new(
+                    fb,
+                    repo.name().clone(),
+                    repo.get_repoid(),
+                    Box::new(BlobRepoChangesetStore::new(repo.clone())),
+                    Arc::new(BlobRepoFileContentStore::new(repo.clone())),
+                    Default::default(),
+                    ScubaSampleBuilder::with_discard(),
+                );
+                let bookmark = BookmarkName::new("master")?;
+                load_filtered_hooks(
+                    fb,
+                    &mut hm,
+                    config,
+                    &bookmark,
+                    &hashset! {},
+                    hook_type.as_ref(),
+                    &only_hooks,
+                )?;
+                let (_, outcomes) = run_hooks_for_changeset(
+                    ctx,
+                    repo,
+                    Arc::new(hm),
+                    bookmark,
+                    cs,
+                    Default::default(),
+                    None,
+                )
+                .compat()
+                .await?;
+                let mut names: Vec<_> = outcomes
+                    .iter()
+                    .map(|outcome| outcome.get_hook_name().to_string())
+                    .collect();
+                names.sort();
+                names.dedup();
+                Result::<_, Error>::Ok(names)
+            }
+        };
+
+        assert_eq!(
+            run(None, hashset! {}).await?,
+            vec![
+                "always_fail_changeset",
+                "block_empty_commit",
+                "conflict_markers",
+                "no_bad_filenames",
+            ]
+        );
+        assert_eq!(
+            run(
+                None,
+                hashset! {"block_empty_commit".to_string(), "conflict_markers".to_string()}
+            )
+            .await?,
+            vec!["block_empty_commit", "conflict_markers"]
+        );
+        assert_eq!(
+            run(
+                Some(HookType::PerAddedOrModifiedFile),
+                hashset! {"no_bad_filenames".to_string()}
+            )
+            .await?,
+            vec!["no_bad_filenames"]
+        );
+
+        // Hooks that are missing, not run for the bookmark, or of another type are errors.
+        for (hook_type, name) in vec![
+            (None, "no_such_hook"),
+            (None, "limit_commitsize"),
+            (Some(HookType::PerChangeset), "conflict_markers"),
+        ] {
+            let err = run(hook_type, hashset! {name.to_string()})
+                .await
+                .expect_err("filtering on a hook not configured for the bookmark should fail");
+            match err.downcast_ref::<hooks::ErrorKind>() {
+                Some(hooks::ErrorKind::HooksNotConfiguredForBookmark(bookmark, names)) => {
+                    assert_eq!(bookmark, &BookmarkName::new("master")?);
+                    assert_eq!(names, &vec![name.to_string()]);
+                }
+                _ => panic!("unexpected error: {:?}", err),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_describe_hook_filter() {
+        assert_eq!(describe_hook_filter(None, &hashset! {}), None);
+        assert_eq!(
+            describe_hook_filter(Some(&HookType::PerChangeset), &hashset! {}),
+            Some("only changeset hooks".to_string())
+        );
+        assert_eq!(
+            describe_hook_filter(
+                Some(&HookType::PerAddedOrModifiedFile),
+                &hashset! {"b".to_string(), "a".to_string()}
+            ),
+            Some("only hooks a, b among file hooks".to_string())
+        );
+    }
+
+    struct FilesCountHook {
+        count: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Hook<HookChangeset> for FilesCountHook {
+        async fn run<'a, 'b: 'a>(
+            &'a self,
+            ctx: &'b CoreContext,
+            context: HookContext<HookChangeset>,
+        ) -> Result<HookExecution, Error> {
+            let count = context.data.files_count(ctx).await?;
+            self.count.store(count, Ordering::Relaxed);
+            Ok(HookExecution::Accepted)
+        }
+    }
+
+    #[fbinit::compat_test]
+    async fn test_excluded_paths(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let hg_cs = HgChangesetId::from_str("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")?;
+        let cs = nodehash_to_bonsai(ctx.clone(), &repo, hg_cs)
+            .compat()
+            .await?;
+
+        let mut config = RepoConfig::default();
+        config.bookmarks = vec![BookmarkParams {
+            bookmark: BookmarkName::new("master")?.into(),
+            hooks: vec!["conflict_markers".into()],
+            only_fast_forward: false,
+            allowed_users: None,
+            rewrite_dates: None,
+        }];
+        config.hooks = vec![HookParams {
+            name: "conflict_markers".into(),
+            hook_type: HookType::PerAddedOrModifiedFile,
+            config: Default::default(),
+            depends_on: vec![],
+        }];
+
+        let mut excluded_paths = GlobSetBuilder::new();
+        excluded_paths.add(Glob::new("fil*")?);
+        let path_excludes = Arc::new(PathExcludes::new(excluded_paths.build()?));
+        let mut hm = HookManager::new(
+            fb,
+            repo.name().clone(),
+            repo.get_repoid(),
+            Box::new(BlobRepoChangesetStore::new(repo.clone())),
+            Arc::new(BlobRepoFileContentStore::new(repo.clone())),
+            Default::default(),
+            ScubaSampleBuilder::with_discard(),
+        );
+        hm.set_file_hook_filter(path_excludes.clone());
+        load_hooks(fb, &mut hm, config, &hashset! {})?;
+        // Changeset hooks see the excluded files too.
+        let files_count = Arc::new(AtomicUsize::new(0));
+        hm.register_changeset_hook(
+            "files_count",
+            Arc::new(FilesCountHook {
+                count: files_count.clone(),
+            }),
+            Default::default(),
+        );
+        hm.set_hooks_for_bookmark(
+            BookmarkName::new("master")?.into(),
+            vec!["conflict_markers".to_string(), "files_count".to_string()],
+        )?;
+
+        let (_, outcomes) = run_hooks_for_changeset(
+            ctx,
+            repo,
+            Arc::new(hm),
+            BookmarkName::new("master")?,
+            cs,
+            Default::default(),
+            None,
+        )
+        .compat()
+        .await?;
+
+        let paths: Vec<_> = outcomes
+            .iter()
+            .filter_map(|outcome| outcome.get_file_path().map(|path| path.to_string()))
+            .collect();
+        assert_eq!(paths, vec!["10".to_string()]);
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(files_count.load(Ordering::Relaxed), 2);
+        assert_eq!(path_excludes.take_skipped_count(), 1);
+        assert_eq!(path_excludes.take_skipped_count(), 0);
+
+        Ok(())
+    }
+
+    struct CountingHook {
+        runs: Arc<AtomicUsize>,
+        accept: bool,
+    }
+
+    #[async_trait]
+    impl<T: Send + Clone + 'static> Hook<T> for CountingHook {
+        async fn run<'a, 'b: 'a>(
+            &'a self,
+            _ctx: &'b CoreContext,
+            _context: HookContext<T>,
+        ) -> Result<HookExecution, Error> {
+            self.runs.fetch_add(1, Ordering::Relaxed);
+            if self.accept {
+                Ok(HookExecution::Accepted)
+            } else {
+                Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                    "counted",
+                    "Counted and rejected".to_string(),
+                )))
+            }
+        }
+    }
+
+    #[fbinit::compat_test]
+    async fn test_outcome_cache(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let cs_runs = Arc::new(AtomicUsize::new(0));
+        let file_runs = Arc::new(AtomicUsize::new(0));
+        let mut hm = HookManager::new(
+            fb,
+            repo.name().clone(),
+            repo.get_repoid(),
+            Box::new(BlobRepoChangesetStore::new(repo.clone())),
+            Arc::new(BlobRepoFileContentStore::new(repo.clone())),
+            Default::default(),
+            ScubaSampleBuilder::with_discard(),
+        );
+        let cs_hook: Arc<dyn Hook<HookChangeset>> = Arc::new(CountingHook {
+            runs: cs_runs.clone(),
+            accept: false,
+        });
+        let file_hook: Arc<dyn Hook<HookFile>> = Arc::new(CountingHook {
+            runs: file_runs.clone(),
+            accept: true,
+        });
+        hm.register_changeset_hook("cs_hook", cs_hook, Default::default());
+        hm.register_file_hook("file_hook", file_hook, Default::default());
+        hm.set_hooks_for_bookmark(
+            BookmarkName::new("master")?.into(),
+            vec!["cs_hook".to_string(), "file_hook".to_string()],
+        )?;
+        let hm = Arc::new(hm);
+        let cache = HookOutcomeCache::with_sqlite_in_memory()?;
+
+        let mut css = vec![];
+        for hg_cs in &[
+            "79a13814c5ce7330173ec04d279bf95ab3f652fb",
+            "a5ffa77602a066db7d5cfb9fb5823a0895717c5a",
+            "3c15267ebf11807f3d772eb891272b911ec68759",
+        ] {
+            let hg_cs = HgChangesetId::from_str(hg_cs)?;
+            css.push(
+                nodehash_to_bonsai(ctx.clone(), &repo, hg_cs)
+                    .compat()
+                    .await?,
+            );
+        }
+        let run = || async {
+            let mut outcomes = vec![];
+            for cs in &css {
+                let (_, cs_outcomes) = run_hooks_for_changeset(
+                    ctx.clone(),
+                    repo.clone(),
+                    hm.clone(),
+                    BookmarkName::new("master")?,
+                    *cs,
+                    Default::default(),
+                    Some(cache.clone()),
+                )
+                .compat()
+                .await?;
+                outcomes.extend(cs_outcomes.iter().map(|outcome| outcome.to_string()));
+            }
+            outcomes.sort();
+            Result::<_, Error>::Ok(outcomes)
+        };
+
+        let first = run().await?;
+        assert_eq!(cs_runs.load(Ordering::Relaxed), css.len());
+        let file_count = file_runs.load(Ordering::Relaxed);
+        assert!(file_count > 0);
+        assert!(first
+            .iter()
+            .any(|outcome| outcome.contains("Counted and rejected")));
+
+        let second = run().await?;
+        assert_eq!(cs_runs.load(Ordering::Relaxed), css.len());
+        assert_eq!(file_runs.load(Ordering::Relaxed), file_count);
+        assert_eq!(first, second);
+
+        cache.invalidate_hooks(&["file_hook".to_string()]).await?;
+        let third = run().await?;
+        assert_eq!(cs_runs.load(Ordering::Relaxed), 2 * css.len());
+        assert_eq!(file_runs.load(Ordering::Relaxed), 2 * file_count);
+        assert_eq!(first, third);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress_reporter() {
+        let rejection = || {
+            HookOutcome::ChangesetHook(
+                ChangesetHookExecutionID {
+                    cs_id: HgChangesetId::from_str("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")
+                        .unwrap()
+                        .into(),
+                    bonsai_cs_id: None,
+                    hook_name: "hook".to_string(),
+                    bookmark: BookmarkName::new("master").unwrap(),
+                },
+                HookExecution::Rejected(HookRejectionInfo::new("rejected")),
+            )
+        };
+
+        let run = |every: u64, total: u64| {
+            let reports = Arc::new(Mutex::new(vec![]));
+            let mut progress = ProgressReporter::new(
+                every,
+                total,
+                Box::new({
+                    cloned!(reports);
+                    move |progress| reports.lock().unwrap().push(progress)
+                }),
+            );
+            // Every third changeset has a rejection.
+            for i in 0..total {
+                if i % 3 == 0 {
+                    progress.record(&[rejection()]);
+                } else {
+                    progress.record(&[]);
+                }
+            }
+            let mut reports = reports.lock().unwrap();
+            std::mem::take(&mut *reports)
+        };
+
+        let reports = run(100, 250);
+        assert_eq!(
+            reports,
+            vec![
+                Progress {
+                    processed: 100,
+                    total: 250,
+                    rejections: 34,
+                },
+                Progress {
+                    processed: 200,
+                    total: 250,
+                    rejections: 67,
+                },
+            ]
+        );
+        assert_eq!(run(1, 5).len(), 5);
+        assert_eq!(run(0, 250), vec![]);
+    }
+
+    #[test]
+    fn test_changeset_excludes() -> Result<(), Error> {
+        let make_cs = |author: &str, message: &str| {
+            BonsaiChangesetMut {
+                parents: vec![],
+                author: author.to_string(),
+                author_date: DateTime::from_timestamp(0, 0).unwrap(),
+                committer: None,
+                committer_date: None,
+                message: message.to_string(),
+                extra: Default::default(),
+                file_changes: Default::default(),
+            }
+            .freeze()
+            .unwrap()
+        };
+
+        let excludes = ChangesetExcludes::new(
+            Some(Regex::new("^svcscm")?),
+            Some(Regex::new(r"\[automated\]")?),
+        );
+        assert!(excludes.is_excluded(&make_cs("svcscm <svcscm@fb.com>", "sync")));
+        assert!(excludes.is_excluded(&make_cs("jsmith", "[automated] update deps")));
+        assert!(!excludes.is_excluded(&make_cs("jsmith", "fix svcscm handling")));
+
+        let no_excludes = ChangesetExcludes::default();
+        assert!(!no_excludes.is_excluded(&make_cs("svcscm", "[automated] sync")));
+
+        Ok(())
+    }
+}
@@ -10,20 +10,26 @@ use async_trait::async_trait;
 use blobrepo::BlobRepo;
 use blobstore::Loadable;
 use context::CoreContext;
+use filestore::FetchKey;
 use futures::{
     compat::{Future01CompatExt, Stream01CompatExt},
-    future,
-    stream::TryStreamExt,
+    future::{self, TryFutureExt},
+    stream::{self, BoxStream, StreamExt, TryStreamExt},
 };
 use manifest::{Diff, Entry, ManifestOps};
 use mercurial_types::{blobs::HgBlobChangeset, FileBytes, HgChangesetId, HgFileNodeId, MPath};
-use mononoke_types::FileType;
+use mononoke_types::{
+    hash::Sha256, BonsaiChangeset, ChangesetId, ContentId, ContentMetadata, FileType,
+};
+use std::collections::HashSet;
+
+use crate::{
+    ChangedFileType, ChangesetStore, ErrorKind, FileContentId, FileContentStore, HookChangesetId,
+};
 
-use crate::{ChangedFileType, ChangesetStore, FileContentStore};
+/// Number of paths of a page of bonsai file changes looked up in the parent at once
+const BONSAI_PARENT_LOOKUP_CONCURRENCY: usize = 100;
 
-// TODO this can cache file content locally to prevent unnecessary lookup of changeset,
-// manifest and walk of manifest each time
-// It's likely that multiple hooks will want to see the same content for the same changeset
 pub struct BlobRepoFileContentStore {
     pub repo: BlobRepo,
 }
@@ -34,32 +40,31 @@ pub struct BlobRepoChangesetStore {
 
 #[async_trait]
 impl FileContentStore for BlobRepoFileContentStore {
+    /// Paths in bonsai changesets are resolved with `resolve_bonsai_path`, so that nothing
+    /// has to be derived for them.
     async fn resolve_path<'a, 'b: 'a>(
         &'a self,
         ctx: &'b CoreContext,
-        changeset_id: HgChangesetId,
+        changeset_id: HookChangesetId,
         path: MPath,
-    ) -> Result<Option<HgFileNodeId>, Error> {
-        let cs = changeset_id
-            .load(ctx.clone(), self.repo.blobstore())
-            .compat()
-            .await?;
-        let entry = cs
-            .manifestid()
-            .find_entry(ctx.clone(), self.repo.get_blobstore(), Some(path))
-            .compat()
-            .await?;
-        Ok(entry.and_then(|entry| entry.into_leaf()).map(|leaf| leaf.1))
+    ) -> Result<Option<FileContentId>, Error> {
+        match changeset_id {
+            HookChangesetId::Hg(changeset_id) => {
+                resolve_hg_path(ctx, &self.repo, changeset_id, &path).await
+            }
+            HookChangesetId::Bonsai(changeset_id) => {
+                resolve_bonsai_path(ctx, &self.repo, changeset_id, &path).await
+            }
+        }
     }
 
     async fn get_file_text<'a, 'b: 'a>(
         &'a self,
         ctx: &'b CoreContext,
-        id: HgFileNodeId,
+        id: FileContentId,
     ) -> Result<Option<FileBytes>, Error> {
-        let store = self.repo.get_blobstore();
-        let envelope = id.load(ctx.clone(), &store).compat().await?;
-        let content = filestore::fetch_concat(&store, ctx.clone(), envelope.content_id())
+        let content_id = self.get_file_content_id(ctx, id).await?;
+        let content = filestore::fetch_concat(&self.repo.get_blobstore(), ctx.clone(), content_id)
             .compat()
             .await?;
         Ok(Some(FileBytes(content)))
@@ -68,10 +73,44 @@ impl FileContentStore for BlobRepoFileContentStore {
     async fn get_file_size<'a, 'b: 'a>(
         &'a self,
         ctx: &'b CoreContext,
-        id: HgFileNodeId,
+        id: FileContentId,
     ) -> Result<u64, Error> {
-        let envelope = id.load(ctx.clone(), self.repo.blobstore()).compat().await?;
-        Ok(envelope.content_size())
+        match id {
+            FileContentId::Filenode(id) => {
+                let envelope = id.load(ctx.clone(), self.repo.blobstore()).compat().await?;
+                Ok(envelope.content_size())
+            }
+            FileContentId::Content(content_id) => {
+                let metadata = self.get_content_metadata(ctx, content_id).await?;
+                Ok(metadata.total_size)
+            }
+        }
+    }
+
+    async fn get_file_content_id<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<ContentId, Error> {
+        match id {
+            FileContentId::Filenode(id) => {
+                let envelope = id.load(ctx.clone(), self.repo.blobstore()).compat().await?;
+                Ok(envelope.content_id())
+            }
+            FileContentId::Content(content_id) => Ok(content_id),
+        }
+    }
+
+    /// Looks up the hash in the content metadata stored by the filestore, which avoids fetching
+    /// the content unless the metadata has to be computed.
+    async fn get_file_sha256<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<Sha256, Error> {
+        let content_id = self.get_file_content_id(ctx, id).await?;
+        let metadata = self.get_content_metadata(ctx, content_id).await?;
+        Ok(metadata.sha256)
     }
 }
 
@@ -79,6 +118,22 @@ impl BlobRepoFileContentStore {
     pub fn new(repo: BlobRepo) -> BlobRepoFileContentStore {
         BlobRepoFileContentStore { repo }
     }
+
+    async fn get_content_metadata(
+        &self,
+        ctx: &CoreContext,
+        content_id: ContentId,
+    ) -> Result<ContentMetadata, Error> {
+        let metadata = filestore::get_metadata(
+            self.repo.blobstore(),
+            ctx.clone(),
+            &FetchKey::Canonical(content_id),
+        )
+        .compat()
+        .await?
+        .ok_or(ErrorKind::NoSuchContent(content_id))?;
+        Ok(metadata)
+    }
 }
 
 #[async_trait]
@@ -95,61 +150,166 @@ impl ChangesetStore for BlobRepoChangesetStore {
             .map_err(|e| e.into())
     }
 
+    async fn get_bonsai_changeset<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        changesetid: ChangesetId,
+    ) -> Result<BonsaiChangeset, Error> {
+        changesetid
+            .load(ctx.clone(), self.repo.blobstore())
+            .compat()
+            .await
+            .map_err(|e| e.into())
+    }
+
     async fn get_changed_files<'a, 'b: 'a>(
         &'a self,
         ctx: &'b CoreContext,
         changesetid: HgChangesetId,
     ) -> Result<Vec<(String, ChangedFileType, Option<(HgFileNodeId, FileType)>)>, Error> {
-        let cs = changesetid
-            .load(ctx.clone(), self.repo.blobstore())
-            .compat()
-            .await?;
-        let mf_id = cs.manifestid();
-        let parents = cs.parents();
-        let (maybe_p1, _) = parents.get_nodes();
-        match maybe_p1 {
-            Some(p1) => {
-                let p1 = HgChangesetId::new(p1)
-                    .load(ctx.clone(), self.repo.blobstore())
-                    .compat()
-                    .await?;
-                let p_mf_id = p1.manifestid();
-                p_mf_id
-                    .diff(ctx.clone(), self.repo.get_blobstore(), mf_id)
-                    .compat()
-                    .try_filter_map(|diff| {
-                        let (path, change_type, entry) = match diff {
-                            Diff::Added(path, entry) => (path, ChangedFileType::Added, entry),
-                            Diff::Removed(path, entry) => (path, ChangedFileType::Deleted, entry),
-                            Diff::Changed(path, .., entry) => {
-                                (path, ChangedFileType::Modified, entry)
-                            }
-                        };
+        self.changed_files_stream(ctx, changesetid)
+            .try_collect()
+            .await
+    }
 
-                        match (change_type, entry) {
-                            (ChangedFileType::Deleted, Entry::Leaf(_)) => {
-                                future::ok(Some((path, ChangedFileType::Deleted, None)))
-                            }
-                            (change_type, Entry::Leaf((ty, hash))) => {
-                                future::ok(Some((path, change_type, Some((hash, ty)))))
-                            }
-                            (_, Entry::Tree(_)) => future::ok(None),
+    fn get_changed_files_paged<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changesetid: HgChangesetId,
+        page_size: usize,
+    ) -> BoxStream<
+        'a,
+        Result<Vec<(String, ChangedFileType, Option<(HgFileNodeId, FileType)>)>, Error>,
+    > {
+        self.changed_files_stream(ctx, changesetid)
+            .chunks(page_size.max(1))
+            .map(|page| page.into_iter().collect())
+            .boxed()
+    }
+
+    /// Files with new content are looked up in the first parent with `resolve_bonsai_path`
+    /// to tell additions from modifications, like the manifest diff does for hg changesets.
+    fn get_bonsai_changed_files_paged<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changesetid: ChangesetId,
+        page_size: usize,
+    ) -> BoxStream<'a, Result<Vec<(String, ChangedFileType, Option<(ContentId, FileType)>)>, Error>>
+    {
+        async move {
+            let cs = changesetid
+                .load(ctx.clone(), self.repo.blobstore())
+                .compat()
+                .await?;
+            let p1 = cs.parents().next();
+            let changes: Vec<_> = cs
+                .file_changes()
+                .map(|(path, change)| {
+                    let change = change.map(|change| (change.content_id(), change.file_type()));
+                    (path.clone(), change)
+                })
+                .collect();
+            let pages: Vec<_> = changes
+                .chunks(page_size.max(1))
+                .map(|page| page.to_vec())
+                .collect();
+            Ok::<_, Error>(
+                stream::iter(pages).then(move |page| self.bonsai_changed_files(ctx, p1, page)),
+            )
+        }
+        .try_flatten_stream()
+        .boxed()
+    }
+}
+
+impl BlobRepoChangesetStore {
+    pub fn new(repo: BlobRepo) -> BlobRepoChangesetStore {
+        BlobRepoChangesetStore { repo }
+    }
+
+    async fn bonsai_changed_files(
+        &self,
+        ctx: &CoreContext,
+        p1: Option<ChangesetId>,
+        changes: Vec<(MPath, Option<(ContentId, FileType)>)>,
+    ) -> Result<Vec<(String, ChangedFileType, Option<(ContentId, FileType)>)>, Error> {
+        stream::iter(changes)
+            .map(|(path, change)| async move {
+                let ty = match (change, p1) {
+                    (None, _) => ChangedFileType::Deleted,
+                    (Some(_), None) => ChangedFileType::Added,
+                    (Some(_), Some(p1)) => {
+                        match resolve_bonsai_path(ctx, &self.repo, p1, &path).await? {
+                            Some(_) => ChangedFileType::Modified,
+                            None => ChangedFileType::Added,
                         }
-                    })
-                    .try_filter_map(|(maybe_path, ty, hash_and_type)| {
-                        future::ok(maybe_path.map(|path| {
-                            (
-                                String::from_utf8_lossy(&path.to_vec()).into_owned(),
-                                ty,
-                                hash_and_type,
-                            )
-                        }))
-                    })
-                    .try_collect()
-                    .await
-            }
-            None => {
-                mf_id
+                    }
+                };
+                let path = String::from_utf8_lossy(&path.to_vec()).into_owned();
+                Ok::<_, Error>((path, ty, change))
+            })
+            .buffered(BONSAI_PARENT_LOOKUP_CONCURRENCY)
+            .try_collect()
+            .await
+    }
+
+    fn changed_files_stream<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changesetid: HgChangesetId,
+    ) -> BoxStream<'a, Result<(String, ChangedFileType, Option<(HgFileNodeId, FileType)>), Error>>
+    {
+        async move {
+            let cs = changesetid
+                .load(ctx.clone(), self.repo.blobstore())
+                .compat()
+                .await?;
+            let mf_id = cs.manifestid();
+            let parents = cs.parents();
+            let (maybe_p1, _) = parents.get_nodes();
+            let files = match maybe_p1 {
+                Some(p1) => {
+                    let p1 = HgChangesetId::new(p1)
+                        .load(ctx.clone(), self.repo.blobstore())
+                        .compat()
+                        .await?;
+                    let p_mf_id = p1.manifestid();
+                    p_mf_id
+                        .diff(ctx.clone(), self.repo.get_blobstore(), mf_id)
+                        .compat()
+                        .try_filter_map(|diff| {
+                            let (path, change_type, entry) = match diff {
+                                Diff::Added(path, entry) => (path, ChangedFileType::Added, entry),
+                                Diff::Removed(path, entry) => {
+                                    (path, ChangedFileType::Deleted, entry)
+                                }
+                                Diff::Changed(path, .., entry) => {
+                                    (path, ChangedFileType::Modified, entry)
+                                }
+                            };
+
+                            match (change_type, entry) {
+                                (ChangedFileType::Deleted, Entry::Leaf(_)) => {
+                                    future::ok(Some((path, ChangedFileType::Deleted, None)))
+                                }
+                                (change_type, Entry::Leaf((ty, hash))) => {
+                                    future::ok(Some((path, change_type, Some((hash, ty)))))
+                                }
+                                (_, Entry::Tree(_)) => future::ok(None),
+                            }
+                        })
+                        .try_filter_map(|(maybe_path, ty, hash_and_type)| {
+                            future::ok(maybe_path.map(|path| {
+                                (
+                                    String::from_utf8_lossy(&path.to_vec()).into_owned(),
+                                    ty,
+                                    hash_and_type,
+                                )
+                            }))
+                        })
+                        .boxed()
+                }
+                None => mf_id
                     .list_leaf_entries(ctx.clone(), self.repo.get_blobstore())
                     .compat()
                     .map_ok(|(path, (ty, filenode))| {
@@ -159,15 +319,78 @@ impl ChangesetStore for BlobRepoChangesetStore {
                             Some((filenode, ty)),
                         )
                     })
-                    .try_collect()
-                    .await
-            }
+                    .boxed(),
+            };
+            Ok::<_, Error>(files)
         }
+        .try_flatten_stream()
+        .boxed()
     }
 }
 
-impl BlobRepoChangesetStore {
-    pub fn new(repo: BlobRepo) -> BlobRepoChangesetStore {
-        BlobRepoChangesetStore { repo }
+async fn resolve_hg_path(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    changeset_id: HgChangesetId,
+    path: &MPath,
+) -> Result<Option<FileContentId>, Error> {
+    let cs = changeset_id
+        .load(ctx.clone(), repo.blobstore())
+        .compat()
+        .await?;
+    let entry = cs
+        .manifestid()
+        .find_entry(ctx.clone(), repo.get_blobstore(), Some(path.clone()))
+        .compat()
+        .await?;
+    Ok(entry
+        .and_then(|entry| entry.into_leaf())
+        .map(|leaf| FileContentId::Filenode(leaf.1)))
+}
+
+/// Find `path` in a bonsai changeset from the file changes of the changeset and of its
+/// ancestors, down to the first ancestors that have hg changesets, whose manifests are used.
+/// Unlike looking the path up in the fsnodes of the changeset, this doesn't derive anything,
+/// which matters for changesets that are being pushed.
+async fn resolve_bonsai_path(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    changeset_id: ChangesetId,
+    path: &MPath,
+) -> Result<Option<FileContentId>, Error> {
+    let mut to_visit = vec![changeset_id];
+    let mut visited = HashSet::new();
+    while let Some(changeset_id) = to_visit.pop() {
+        if !visited.insert(changeset_id) {
+            continue;
+        }
+
+        let maybe_hg_cs_id = repo
+            .get_bonsai_hg_mapping()
+            .get_hg_from_bonsai(ctx.clone(), repo.get_repoid(), changeset_id)
+            .compat()
+            .await?;
+        if let Some(hg_cs_id) = maybe_hg_cs_id {
+            match resolve_hg_path(ctx, repo, hg_cs_id, path).await? {
+                Some(id) => return Ok(Some(id)),
+                None => continue,
+            }
+        }
+
+        let cs = changeset_id
+            .load(ctx.clone(), repo.blobstore())
+            .compat()
+            .await?;
+        match cs.file_changes_map().get(path) {
+            Some(Some(change)) => return Ok(Some(FileContentId::Content(change.content_id()))),
+            // Deleted in this changeset, but a later parent of a merge may still have it.
+            Some(None) => continue,
+            None => {
+                // Unchanged: look in the parents, first parent first.
+                let parents: Vec<_> = cs.parents().collect();
+                to_visit.extend(parents.into_iter().rev());
+            }
+        }
     }
+    Ok(None)
 }
@@ -5,10 +5,13 @@
  * GNU General Public License version 2.
  */
 
+use mononoke_types::ContentId;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum ErrorKind {
     #[error("No changeset with id '{0}'")]
     NoSuchChangeset(String),
+    #[error("No content with id '{0}'")]
+    NoSuchContent(ContentId),
 }
@@ -11,13 +11,17 @@ use std::sync::Arc;
 mod blobrepo;
 mod errors;
 mod memory;
+mod prefetch;
 mod store;
 mod text_only;
 
 pub use crate::blobrepo::{BlobRepoChangesetStore, BlobRepoFileContentStore};
 pub use crate::memory::{InMemoryChangesetStore, InMemoryFileContentStore, InMemoryFileText};
+pub use crate::prefetch::PrefetchedFileContentStore;
 pub use crate::text_only::TextOnlyFileContentStore;
-pub use store::{ChangedFileType, ChangesetStore, FileContentStore};
+pub use store::{
+    ChangedFileType, ChangesetStore, FileContentId, FileContentStore, HookChangesetId,
+};
 
 use errors::ErrorKind;
 
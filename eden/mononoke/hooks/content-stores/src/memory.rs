@@ -9,11 +9,17 @@ use anyhow::Error;
 use async_trait::async_trait;
 use bytes::Bytes;
 use context::CoreContext;
+use futures::stream::{self, BoxStream, StreamExt};
 use mercurial_types::{blobs::HgBlobChangeset, FileBytes, HgChangesetId, HgFileNodeId, MPath};
-use mononoke_types::FileType;
+use mononoke_types::{
+    hash::Sha256, typed_hash::ContentIdContext, BonsaiChangeset, ChangesetId, ContentId, FileType,
+};
+use sha2::Digest;
 use std::collections::HashMap;
 
-use crate::{ChangedFileType, ChangesetStore, ErrorKind, FileContentStore};
+use crate::{
+    ChangedFileType, ChangesetStore, ErrorKind, FileContentId, FileContentStore, HookChangesetId,
+};
 
 pub struct InMemoryChangesetStore {
     map_files:
@@ -34,6 +40,14 @@ impl ChangesetStore for InMemoryChangesetStore {
         }
     }
 
+    async fn get_bonsai_changeset<'a, 'b: 'a>(
+        &'a self,
+        _ctx: &'b CoreContext,
+        changesetid: ChangesetId,
+    ) -> Result<BonsaiChangeset, Error> {
+        Err(ErrorKind::NoSuchChangeset(changesetid.to_string()).into())
+    }
+
     async fn get_changed_files<'a, 'b: 'a>(
         &'a self,
         _ctx: &'b CoreContext,
@@ -44,6 +58,37 @@ impl ChangesetStore for InMemoryChangesetStore {
             None => Err(ErrorKind::NoSuchChangeset(changesetid.to_string()).into()),
         }
     }
+
+    fn get_changed_files_paged<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        changesetid: HgChangesetId,
+        page_size: usize,
+    ) -> BoxStream<
+        'a,
+        Result<Vec<(String, ChangedFileType, Option<(HgFileNodeId, FileType)>)>, Error>,
+    > {
+        match self.map_files.get(&changesetid) {
+            Some(files) => stream::iter(files.chunks(page_size.max(1)))
+                .map(|page| Ok(page.to_vec()))
+                .boxed(),
+            None => stream::once(async move {
+                Err(ErrorKind::NoSuchChangeset(changesetid.to_string()).into())
+            })
+            .boxed(),
+        }
+    }
+
+    fn get_bonsai_changed_files_paged<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        changesetid: ChangesetId,
+        _page_size: usize,
+    ) -> BoxStream<'a, Result<Vec<(String, ChangedFileType, Option<(ContentId, FileType)>)>, Error>>
+    {
+        stream::once(async move { Err(ErrorKind::NoSuchChangeset(changesetid.to_string()).into()) })
+            .boxed()
+    }
 }
 
 impl InMemoryChangesetStore {
@@ -94,8 +139,8 @@ impl Into<InMemoryFileText> for u64 {
 
 #[derive(Clone)]
 pub struct InMemoryFileContentStore {
-    id_to_text: HashMap<HgFileNodeId, InMemoryFileText>,
-    path_to_filenode: HashMap<(HgChangesetId, MPath), HgFileNodeId>,
+    id_to_text: HashMap<FileContentId, InMemoryFileText>,
+    path_to_filenode: HashMap<(HookChangesetId, MPath), FileContentId>,
 }
 
 #[async_trait]
@@ -103,16 +148,16 @@ impl FileContentStore for InMemoryFileContentStore {
     async fn resolve_path<'a, 'b: 'a>(
         &'a self,
         _ctx: &'b CoreContext,
-        cs_id: HgChangesetId,
+        cs_id: HookChangesetId,
         path: MPath,
-    ) -> Result<Option<HgFileNodeId>, Error> {
+    ) -> Result<Option<FileContentId>, Error> {
         Ok(self.path_to_filenode.get(&(cs_id, path)).cloned())
     }
 
     async fn get_file_text<'a, 'b: 'a>(
         &'a self,
         _ctx: &'b CoreContext,
-        id: HgFileNodeId,
+        id: FileContentId,
     ) -> Result<Option<FileBytes>, Error> {
         self.id_to_text
             .get(&id)
@@ -126,7 +171,7 @@ impl FileContentStore for InMemoryFileContentStore {
     async fn get_file_size<'a, 'b: 'a>(
         &'a self,
         _ctx: &'b CoreContext,
-        id: HgFileNodeId,
+        id: FileContentId,
     ) -> Result<u64, Error> {
         self.id_to_text
             .get(&id)
@@ -136,6 +181,27 @@ impl FileContentStore for InMemoryFileContentStore {
                 InMemoryFileText::Elided(size) => *size,
             })
     }
+
+    async fn get_file_content_id<'a, 'b: 'a>(
+        &'a self,
+        _ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<ContentId, Error> {
+        let bytes = self.present_bytes(id)?;
+        let mut context = ContentIdContext::new();
+        context.update(bytes.as_bytes());
+        Ok(context.finish())
+    }
+
+    async fn get_file_sha256<'a, 'b: 'a>(
+        &'a self,
+        _ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<Sha256, Error> {
+        let bytes = self.present_bytes(id)?;
+        let digest = sha2::Sha256::digest(bytes.as_bytes());
+        Ok(Sha256::from_byte_array(digest.into()))
+    }
 }
 
 impl InMemoryFileContentStore {
@@ -153,7 +219,17 @@ impl InMemoryFileContentStore {
         key: HgFileNodeId,
         text: impl Into<InMemoryFileText>,
     ) {
-        self.id_to_text.insert(key, text.into());
-        self.path_to_filenode.insert((cs_id, path), key);
+        self.id_to_text.insert(key.into(), text.into());
+        self.path_to_filenode
+            .insert((cs_id.into(), path), key.into());
+    }
+
+    /// Content of a file that was inserted with its text, which hashes are computed from
+    fn present_bytes(&self, id: FileContentId) -> Result<&FileBytes, Error> {
+        match self.id_to_text.get(&id) {
+            Some(InMemoryFileText::Present(bytes)) => Ok(bytes),
+            Some(InMemoryFileText::Elided(_)) => Err(Error::msg("file content was elided")),
+            None => Err(Error::msg("file not found")),
+        }
     }
 }
@@ -0,0 +1,188 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Error;
+use async_trait::async_trait;
+use context::CoreContext;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use mercurial_types::{FileBytes, MPath};
+use mononoke_types::{hash::Sha256, ContentId};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::{FileContentId, FileContentStore, HookChangesetId};
+
+/// Number of files `PrefetchedFileContentStore::prefetch` fetches at once
+const PREFETCH_CONCURRENCY: usize = 100;
+
+/// A `FileContentStore` that caches sizes of the files read through it, and contents of the
+/// files fetched up front with `prefetch`, so that several hooks reading the same file only
+/// fetch it from the inner store once. Contents of other files are read from the inner store
+/// every time, so that memory use is bounded by what was prefetched. Contents of files larger
+/// than `max_size` are not cached.
+pub struct PrefetchedFileContentStore {
+    inner: Arc<dyn FileContentStore>,
+    max_size: u64,
+    sizes: Mutex<HashMap<FileContentId, u64>>,
+    texts: Mutex<HashMap<FileContentId, Option<FileBytes>>>,
+}
+
+impl PrefetchedFileContentStore {
+    pub fn new(inner: Arc<dyn FileContentStore>, max_size: u64) -> Self {
+        Self {
+            inner,
+            max_size,
+            sizes: Mutex::new(HashMap::new()),
+            texts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fetch the sizes of the given files, and the contents of those that are no larger than
+    /// `max_size`, into the cache.
+    pub async fn prefetch(
+        &self,
+        ctx: &CoreContext,
+        ids: impl IntoIterator<Item = FileContentId>,
+    ) -> Result<(), Error> {
+        let ids: HashSet<_> = {
+            let texts = self.texts.lock().expect("poisoned lock");
+            ids.into_iter()
+                .filter(|id| !texts.contains_key(id))
+                .collect()
+        };
+
+        stream::iter(ids)
+            .map(|id| async move {
+                let size = self.get_file_size(ctx, id).await?;
+                if size <= self.max_size {
+                    let text = self.inner.get_file_text(ctx, id).await?;
+                    self.texts.lock().expect("poisoned lock").insert(id, text);
+                }
+                Ok::<_, Error>(())
+            })
+            .buffer_unordered(PREFETCH_CONCURRENCY)
+            .try_collect()
+            .await
+    }
+
+    /// Drop the cached contents of the given files. Their sizes stay cached.
+    pub fn evict_texts(&self, ids: impl IntoIterator<Item = FileContentId>) {
+        let mut texts = self.texts.lock().expect("poisoned lock");
+        for id in ids {
+            texts.remove(&id);
+        }
+    }
+}
+
+#[async_trait]
+impl FileContentStore for PrefetchedFileContentStore {
+    async fn resolve_path<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        changeset_id: HookChangesetId,
+        path: MPath,
+    ) -> Result<Option<FileContentId>, Error> {
+        self.inner.resolve_path(ctx, changeset_id, path).await
+    }
+
+    async fn get_file_text<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<Option<FileBytes>, Error> {
+        if let Some(text) = self.texts.lock().expect("poisoned lock").get(&id) {
+            return Ok(text.clone());
+        }
+
+        self.inner.get_file_text(ctx, id).await
+    }
+
+    async fn get_file_size<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<u64, Error> {
+        if let Some(size) = self.sizes.lock().expect("poisoned lock").get(&id) {
+            return Ok(*size);
+        }
+
+        let size = self.inner.get_file_size(ctx, id).await?;
+        self.sizes.lock().expect("poisoned lock").insert(id, size);
+        Ok(size)
+    }
+
+    async fn get_file_content_id<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<ContentId, Error> {
+        self.inner.get_file_content_id(ctx, id).await
+    }
+
+    async fn get_file_sha256<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<Sha256, Error> {
+        self.inner.get_file_sha256(ctx, id).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::InMemoryFileContentStore;
+    use fbinit::FacebookInit;
+    use mercurial_types_mocks::nodehash::{ONES_CSID, THREES_FNID, TWOS_FNID};
+    use tokio_compat::runtime::Runtime;
+
+    #[fbinit::test]
+    fn test_prefetch_elides_large_files(fb: FacebookInit) {
+        let mut rt = Runtime::new().unwrap();
+        let ctx = CoreContext::test_mock(fb);
+
+        let mut inner = InMemoryFileContentStore::new();
+        inner.insert(ONES_CSID, MPath::new("f1").unwrap(), TWOS_FNID, "foo");
+        inner.insert(ONES_CSID, MPath::new("f2").unwrap(), THREES_FNID, "foobar");
+
+        let store = PrefetchedFileContentStore::new(Arc::new(inner), 4);
+        rt.block_on_std(store.prefetch(&ctx, vec![TWOS_FNID.into(), THREES_FNID.into()]))
+            .unwrap();
+
+        let texts = store.texts.lock().unwrap();
+        assert_eq!(
+            texts.get(&TWOS_FNID.into()),
+            Some(&Some(FileBytes("foo".into())))
+        );
+        assert!(!texts.contains_key(&THREES_FNID.into()));
+        assert_eq!(
+            store.sizes.lock().unwrap().get(&THREES_FNID.into()),
+            Some(&6)
+        );
+    }
+
+    #[fbinit::test]
+    fn test_unprefetched_texts_not_cached(fb: FacebookInit) {
+        let mut rt = Runtime::new().unwrap();
+        let ctx = CoreContext::test_mock(fb);
+
+        let mut inner = InMemoryFileContentStore::new();
+        inner.insert(ONES_CSID, MPath::new("f1").unwrap(), TWOS_FNID, "foo");
+        inner.insert(ONES_CSID, MPath::new("f2").unwrap(), THREES_FNID, "bar");
+
+        // Read every file, as a changeset hook would, without prefetching any of them.
+        let store = PrefetchedFileContentStore::new(Arc::new(inner), 4);
+        for id in vec![TWOS_FNID, THREES_FNID] {
+            let text = rt
+                .block_on_std(store.get_file_text(&ctx, id.into()))
+                .unwrap();
+            assert!(text.is_some());
+        }
+
+        assert!(store.texts.lock().unwrap().is_empty());
+    }
+}
@@ -8,8 +8,10 @@
 use anyhow::Error;
 use async_trait::async_trait;
 use context::CoreContext;
+use futures::stream::BoxStream;
 use mercurial_types::{blobs::HgBlobChangeset, FileBytes, HgChangesetId, HgFileNodeId, MPath};
-use mononoke_types::FileType;
+use mononoke_types::{hash::Sha256, BonsaiChangeset, ChangesetId, ContentId, FileType};
+use std::fmt;
 
 #[derive(Clone, PartialEq, Eq)]
 pub enum ChangedFileType {
@@ -18,26 +20,89 @@ pub enum ChangedFileType {
     Modified,
 }
 
+/// The changeset hooks run on. Hooks run on hg changesets, or on bonsai changesets directly
+/// when hg changesets don't need to be derived first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HookChangesetId {
+    Hg(HgChangesetId),
+    Bonsai(ChangesetId),
+}
+
+impl fmt::Display for HookChangesetId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HookChangesetId::Hg(id) => write!(f, "{}", id),
+            HookChangesetId::Bonsai(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+impl From<HgChangesetId> for HookChangesetId {
+    fn from(id: HgChangesetId) -> Self {
+        HookChangesetId::Hg(id)
+    }
+}
+
+impl From<ChangesetId> for HookChangesetId {
+    fn from(id: ChangesetId) -> Self {
+        HookChangesetId::Bonsai(id)
+    }
+}
+
+/// How the content of a file is found: through its filenode for files of hg changesets, or
+/// by its content id for files of bonsai changesets, which have no filenodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileContentId {
+    Filenode(HgFileNodeId),
+    Content(ContentId),
+}
+
+impl From<HgFileNodeId> for FileContentId {
+    fn from(id: HgFileNodeId) -> Self {
+        FileContentId::Filenode(id)
+    }
+}
+
+impl From<ContentId> for FileContentId {
+    fn from(id: ContentId) -> Self {
+        FileContentId::Content(id)
+    }
+}
+
 #[async_trait]
 pub trait FileContentStore: Send + Sync {
     async fn resolve_path<'a, 'b: 'a>(
         &'a self,
         ctx: &'b CoreContext,
-        changeset_id: HgChangesetId,
+        changeset_id: HookChangesetId,
         path: MPath,
-    ) -> Result<Option<HgFileNodeId>, Error>;
+    ) -> Result<Option<FileContentId>, Error>;
 
     async fn get_file_text<'a, 'b: 'a>(
         &'a self,
         ctx: &'b CoreContext,
-        id: HgFileNodeId,
+        id: FileContentId,
     ) -> Result<Option<FileBytes>, Error>;
 
     async fn get_file_size<'a, 'b: 'a>(
         &'a self,
         ctx: &'b CoreContext,
-        id: HgFileNodeId,
+        id: FileContentId,
     ) -> Result<u64, Error>;
+
+    async fn get_file_content_id<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<ContentId, Error>;
+
+    /// SHA-256 of the file content. Stores should look it up without fetching the content if
+    /// they can.
+    async fn get_file_sha256<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<Sha256, Error>;
 }
 
 #[async_trait]
@@ -48,9 +113,38 @@ pub trait ChangesetStore: Send + Sync {
         changesetid: HgChangesetId,
     ) -> Result<HgBlobChangeset, Error>;
 
+    /// The bonsai changeset with this id, for running hooks on bonsai changesets
+    async fn get_bonsai_changeset<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        changesetid: ChangesetId,
+    ) -> Result<BonsaiChangeset, Error>;
+
     async fn get_changed_files<'a, 'b: 'a>(
         &'a self,
         ctx: &'b CoreContext,
         changesetid: HgChangesetId,
     ) -> Result<Vec<(String, ChangedFileType, Option<(HgFileNodeId, FileType)>)>, Error>;
+
+    /// Same as `get_changed_files`, but yields the changed files in pages of at most `page_size`
+    /// entries, so that huge changesets don't have to be held in memory all at once.
+    fn get_changed_files_paged<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changesetid: HgChangesetId,
+        page_size: usize,
+    ) -> BoxStream<
+        'a,
+        Result<Vec<(String, ChangedFileType, Option<(HgFileNodeId, FileType)>)>, Error>,
+    >;
+
+    /// Same as `get_changed_files_paged`, for bonsai changesets, whose files are found by
+    /// their content ids. Files are `ChangedFileType::Added` if their first parent doesn't
+    /// have them, like for hg changesets.
+    fn get_bonsai_changed_files_paged<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changesetid: ChangesetId,
+        page_size: usize,
+    ) -> BoxStream<'a, Result<Vec<(String, ChangedFileType, Option<(ContentId, FileType)>)>, Error>>;
 }
@@ -8,10 +8,11 @@
 use anyhow::Error;
 use async_trait::async_trait;
 use context::CoreContext;
-use mercurial_types::{FileBytes, HgChangesetId, HgFileNodeId, MPath};
+use mercurial_types::{FileBytes, MPath};
+use mononoke_types::{hash::Sha256, ContentId};
 use std::sync::Arc;
 
-use crate::FileContentStore;
+use crate::{FileContentId, FileContentStore, HookChangesetId};
 
 const NULL: u8 = 0;
 
@@ -34,9 +35,9 @@ impl<T: FileContentStore + 'static> FileContentStore for TextOnlyFileContentStor
     async fn resolve_path<'a, 'b: 'a>(
         &'a self,
         ctx: &'b CoreContext,
-        changeset_id: HgChangesetId,
+        changeset_id: HookChangesetId,
         path: MPath,
-    ) -> Result<Option<HgFileNodeId>, Error> {
+    ) -> Result<Option<FileContentId>, Error> {
         self.inner.resolve_path(ctx, changeset_id, path).await
     }
 
@@ -45,7 +46,7 @@ impl<T: FileContentStore + 'static> FileContentStore for TextOnlyFileContentStor
     async fn get_file_text<'a, 'b: 'a>(
         &'a self,
         ctx: &'b CoreContext,
-        id: HgFileNodeId,
+        id: FileContentId,
     ) -> Result<Option<FileBytes>, Error> {
         let file_size = self.get_file_size(ctx, id).await?;
         if file_size > self.max_size {
@@ -62,10 +63,26 @@ impl<T: FileContentStore + 'static> FileContentStore for TextOnlyFileContentStor
     async fn get_file_size<'a, 'b: 'a>(
         &'a self,
         ctx: &'b CoreContext,
-        id: HgFileNodeId,
+        id: FileContentId,
     ) -> Result<u64, Error> {
         self.inner.get_file_size(ctx, id).await
     }
+
+    async fn get_file_content_id<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<ContentId, Error> {
+        self.inner.get_file_content_id(ctx, id).await
+    }
+
+    async fn get_file_sha256<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<Sha256, Error> {
+        self.inner.get_file_sha256(ctx, id).await
+    }
 }
 
 fn looks_like_binary(file_bytes: &FileBytes) -> bool {
@@ -90,7 +107,7 @@ mod test {
 
         let store = TextOnlyFileContentStore::new(inner, 10);
         let ret = rt
-            .block_on_std(store.get_file_text(&ctx, TWOS_FNID))
+            .block_on_std(store.get_file_text(&ctx, TWOS_FNID.into()))
             .unwrap();
         assert_eq!(ret, Some(FileBytes("foobar".into())));
     }
@@ -105,7 +122,7 @@ mod test {
 
         let store = TextOnlyFileContentStore::new(inner, 2);
         let ret = rt
-            .block_on_std(store.get_file_text(&ctx, TWOS_FNID))
+            .block_on_std(store.get_file_text(&ctx, TWOS_FNID.into()))
             .unwrap();
         assert_eq!(ret, None);
     }
@@ -120,7 +137,7 @@ mod test {
 
         let store = TextOnlyFileContentStore::new(inner, 10);
         let ret = rt
-            .block_on_std(store.get_file_text(&ctx, TWOS_FNID))
+            .block_on_std(store.get_file_text(&ctx, TWOS_FNID.into()))
             .unwrap();
         assert_eq!(ret, None);
     }
@@ -9,8 +9,8 @@
 
 use anyhow::Error;
 use async_trait::async_trait;
-use blobrepo::BlobRepo;
-use blobstore::Loadable;
+use blobrepo::{BlobRepo, DangerousOverride};
+use blobstore::{Blobstore, Loadable};
 use bookmarks::{BookmarkName, BookmarkUpdateReason};
 use context::CoreContext;
 use fbinit::FacebookInit;
@@ -20,29 +20,48 @@ use futures::{
     future,
     stream::{futures_unordered, TryStreamExt},
 };
+use futures_ext::BoxFuture;
 use hooks::{
-    hook_loader::load_hooks, ErrorKind, Hook, HookChangeset, HookChangesetParents, HookContext,
-    HookExecution, HookFile, HookManager, HookRejectionInfo,
+    format_rejections,
+    hook_loader::load_hooks,
+    rust_hooks::{
+        check_commit_message_format::CheckCommitMessageFormat,
+        deny_known_bad_blobs::DenyKnownBadBlobs,
+        limit_filesize_by_extension::LimitFilesizeByExtension,
+        max_message_length::MaxMessageLength, reverts_need_task::RevertsNeedTask,
+        verify_author::VerifyAuthor,
+    },
+    ChangesetHookExecutionID, ErrorKind, FileHookExecutionID, Hook, HookChangeset,
+    HookChangesetParents, HookContext, HookExecution, HookExecutionMode, HookFile, HookManager,
+    HookOutcome, HookRejectionInfo, HookSuggestion, IdentityVerifier, DEFAULT_HOOK_MAX_FILE_SIZE,
 };
 use hooks_content_stores::{
-    BlobRepoChangesetStore, BlobRepoFileContentStore, ChangedFileType, InMemoryChangesetStore,
-    InMemoryFileContentStore,
+    BlobRepoChangesetStore, BlobRepoFileContentStore, ChangedFileType, FileContentId,
+    FileContentStore, HookChangesetId, InMemoryChangesetStore, InMemoryFileContentStore,
 };
 use maplit::{btreemap, hashmap, hashset};
-use mercurial_types::{HgChangesetId, MPath};
-use mercurial_types_mocks::nodehash::{ONES_FNID, THREES_FNID, TWOS_FNID};
+use mercurial_types::{FileBytes, HgChangesetId, HgFileNodeId, MPath};
+use mercurial_types_mocks::nodehash::{ONES_CSID, ONES_FNID, THREES_FNID, TWOS_CSID, TWOS_FNID};
 use metaconfig_types::{
-    BlobConfig, BookmarkParams, Bundle2ReplayParams, DerivedDataConfig, HookConfig, HookParams,
-    HookType, InfinitepushParams, MetadataDBConfig, Redaction, RepoConfig, RepoReadOnly,
-    SourceControlServiceParams, StorageConfig,
+    BlobConfig, BookmarkOrRegex, BookmarkParams, Bundle2ReplayParams, DerivedDataConfig,
+    HookConfig, HookParams, HookType, InfinitepushParams, MetadataDBConfig, Redaction, RepoConfig,
+    RepoReadOnly, SourceControlServiceParams, StorageConfig,
+};
+use mononoke_types::{
+    hash::Sha256, typed_hash::ContentIdContext, BlobstoreBytes, ContentId, FileType, RepositoryId,
 };
-use mononoke_types::{FileType, RepositoryId};
 use regex::Regex;
 use scuba_ext::ScubaSampleBuilder;
+use sha2::Digest;
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use tempfile::NamedTempFile;
 use tests_utils::{create_commit, store_files};
 
 #[derive(Clone, Debug)]
@@ -100,6 +119,37 @@ fn context_matching_changeset_hook(
     Box::new(ContextMatchingChangesetHook { expected_context })
 }
 
+#[derive(Clone, Debug)]
+struct RepoMatchingChangesetHook {
+    repo_name: String,
+    repo_id: RepositoryId,
+}
+
+#[async_trait]
+impl Hook<HookChangeset> for RepoMatchingChangesetHook {
+    async fn run(
+        &self,
+        _ctx: &CoreContext,
+        context: HookContext<HookChangeset>,
+    ) -> Result<HookExecution, Error> {
+        if context.repo_name == self.repo_name && context.repo_id == self.repo_id {
+            Ok(HookExecution::Accepted)
+        } else {
+            Ok(default_rejection())
+        }
+    }
+}
+
+fn repo_matching_changeset_hook(
+    repo_name: &str,
+    repo_id: RepositoryId,
+) -> Box<dyn Hook<HookChangeset>> {
+    Box::new(RepoMatchingChangesetHook {
+        repo_name: repo_name.to_string(),
+        repo_id,
+    })
+}
+
 #[derive(Clone, Debug)]
 struct FileContentMatchingChangesetHook {
     expected_content: HashMap<String, Option<String>>,
@@ -113,7 +163,7 @@ impl Hook<HookChangeset> for FileContentMatchingChangesetHook {
         context: HookContext<HookChangeset>,
     ) -> Result<HookExecution, Error> {
         let futs = futures_unordered::FuturesUnordered::new();
-        for file in context.data.files {
+        for file in context.data.files(ctx).await? {
             let fut = async move {
                 match self.expected_content.get(&file.path) {
                     Some(expected_content) => {
@@ -169,7 +219,7 @@ impl Hook<HookChangeset> for LengthMatchingChangesetHook {
         context: HookContext<HookChangeset>,
     ) -> Result<HookExecution, Error> {
         let futs = futures_unordered::FuturesUnordered::new();
-        for file in context.data.files {
+        for file in context.data.files(ctx).await? {
             let fut = async move {
                 match self.expected_lengths.get(&file.path) {
                     Some(expected_length) => {
@@ -268,6 +318,34 @@ fn always_rejecting_file_hook() -> Box<dyn Hook<HookFile>> {
     Box::new(FnFileHook::new(f))
 }
 
+#[derive(Clone, Debug)]
+struct RepoMatchingFileHook {
+    repo_name: String,
+    repo_id: RepositoryId,
+}
+
+#[async_trait]
+impl Hook<HookFile> for RepoMatchingFileHook {
+    async fn run(
+        &self,
+        _ctx: &CoreContext,
+        context: HookContext<HookFile>,
+    ) -> Result<HookExecution, Error> {
+        if context.repo_name == self.repo_name && context.repo_id == self.repo_id {
+            Ok(HookExecution::Accepted)
+        } else {
+            Ok(default_rejection())
+        }
+    }
+}
+
+fn repo_matching_file_hook(repo_name: &str, repo_id: RepositoryId) -> Box<dyn Hook<HookFile>> {
+    Box::new(RepoMatchingFileHook {
+        repo_name: repo_name.to_string(),
+        repo_id,
+    })
+}
+
 #[derive(Clone, Debug)]
 struct PathMatchingFileHook {
     paths: HashSet<String>,
@@ -378,6 +456,30 @@ fn length_matching_file_hook(length: u64) -> Box<dyn Hook<HookFile>> {
     Box::new(LengthMatchingFileHook { length })
 }
 
+#[derive(Clone)]
+struct ChangedFileTypeMatchingFileHook {
+    ty: ChangedFileType,
+}
+
+#[async_trait]
+impl Hook<HookFile> for ChangedFileTypeMatchingFileHook {
+    async fn run(
+        &self,
+        _ctx: &CoreContext,
+        context: HookContext<HookFile>,
+    ) -> Result<HookExecution, Error> {
+        Ok(if context.data.changed_file_type() == self.ty {
+            HookExecution::Accepted
+        } else {
+            default_rejection()
+        })
+    }
+}
+
+fn changed_file_type_matching_file_hook(ty: ChangedFileType) -> Box<dyn Hook<HookFile>> {
+    Box::new(ChangedFileTypeMatchingFileHook { ty })
+}
+
 #[fbinit::test]
 fn test_changeset_hook_accepted(fb: FacebookInit) {
     async_unit::tokio_unit_test(async move {
@@ -455,9 +557,10 @@ fn test_changeset_hook_context(fb: FacebookInit) {
                 HookFile::new(
                     path.clone(),
                     content_store.clone(),
-                    cs_id,
+                    cs_id.into(),
                     ChangedFileType::Added,
-                    Some((*entry_id, FileType::Regular)),
+                    Some(((*entry_id).into(), FileType::Regular)),
+                    DEFAULT_HOOK_MAX_FILE_SIZE,
                 )
             })
             .collect();
@@ -468,15 +571,19 @@ fn test_changeset_hook_context(fb: FacebookInit) {
             hook_files,
             "3".into(),
             parents,
-            cs_id,
+            cs_id.into(),
             content_store,
             reviewers_acl_checker,
+            DEFAULT_HOOK_MAX_FILE_SIZE,
         );
         let expected_context = HookContext {
             hook_name: "hook1".into(),
             config: Default::default(),
             data,
             bookmark: BookmarkName::new("bm1").unwrap(),
+            repo_name: INMEM_REPO_NAME.to_string(),
+            repo_id: INMEM_REPO_ID,
+            dependency_outcomes: HashMap::new(),
         };
         let hooks: HashMap<String, Box<dyn Hook<HookChangeset>>> = hashmap! {
             "hook1".to_string() => context_matching_changeset_hook(expected_context)
@@ -492,6 +599,77 @@ fn test_changeset_hook_context(fb: FacebookInit) {
     });
 }
 
+#[fbinit::test]
+fn test_hook_context_repo(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let blob_repo = many_files_dirs::getrepo_with_id(fb, RepositoryId::new(123)).await;
+        let blob_repo_name = blob_repo.name().clone();
+        let cases = vec![
+            (
+                INMEM_REPO_NAME.to_string(),
+                INMEM_REPO_ID,
+                ContentStoreType::InMemory,
+                ContentStoreType::InMemory,
+            ),
+            (
+                blob_repo_name,
+                RepositoryId::new(123),
+                ContentStoreType::Blob(blob_repo.clone()),
+                ContentStoreType::Blob(blob_repo),
+            ),
+        ];
+
+        for (repo_name, repo_id, cs_store_type, file_store_type) in cases {
+            let hooks: HashMap<String, Box<dyn Hook<HookChangeset>>> = hashmap! {
+                "hook1".to_string() => repo_matching_changeset_hook(&repo_name, repo_id),
+                "hook2".to_string() => repo_matching_changeset_hook("other_repo", repo_id),
+            };
+            let bookmarks = hashmap! {
+                "bm1".to_string() => vec!["hook1".to_string(), "hook2".to_string()]
+            };
+            let expected = hashmap! {
+                "hook1".to_string() => HookExecution::Accepted,
+                "hook2".to_string() => default_rejection(),
+            };
+            run_changeset_hooks_with_mgr(
+                ctx.clone(),
+                "bm1",
+                hooks,
+                bookmarks,
+                hashmap! {},
+                expected,
+                cs_store_type,
+            )
+            .await;
+
+            let hooks: HashMap<String, Box<dyn Hook<HookFile>>> = hashmap! {
+                "hook1".to_string() => repo_matching_file_hook(&repo_name, repo_id),
+            };
+            let bookmarks = hashmap! {
+                "bm1".to_string() => vec!["hook1".to_string()]
+            };
+            let expected = hashmap! {
+                "hook1".to_string() => hashmap! {
+                    "dir1/subdir1/subsubdir1/file_1".to_string() => HookExecution::Accepted,
+                    "dir1/subdir1/subsubdir2/file_1".to_string() => HookExecution::Accepted,
+                    "dir1/subdir1/subsubdir2/file_2".to_string() => HookExecution::Accepted,
+                },
+            };
+            run_file_hooks(
+                ctx.clone(),
+                "bm1",
+                hooks,
+                bookmarks,
+                hashmap! {},
+                expected,
+                file_store_type,
+            )
+            .await;
+        }
+    });
+}
+
 #[fbinit::test]
 fn test_changeset_hook_other_file_text(fb: FacebookInit) {
     async_unit::tokio_unit_test(async move {
@@ -1039,86 +1217,724 @@ fn test_file_hooks_with_blob_store(fb: FacebookInit) {
     })
 }
 
-async fn run_changeset_hooks(
-    ctx: CoreContext,
-    bookmark_name: &str,
-    hooks: HashMap<String, Box<dyn Hook<HookChangeset>>>,
-    bookmarks: HashMap<String, Vec<String>>,
-    regexes: HashMap<String, Vec<String>>,
-    expected: HashMap<String, HookExecution>,
-) {
-    run_changeset_hooks_with_mgr(
-        ctx,
-        bookmark_name,
-        hooks,
-        bookmarks,
-        regexes,
-        expected,
-        ContentStoreType::InMemory,
-    )
-    .await
+/// Counts how many times the content and size of each file is fetched.
+struct CountingFileContentStore {
+    inner: InMemoryFileContentStore,
+    text_fetches: Arc<Mutex<HashMap<FileContentId, usize>>>,
+    size_fetches: Arc<Mutex<HashMap<FileContentId, usize>>>,
 }
 
-async fn run_changeset_hooks_with_mgr(
-    ctx: CoreContext,
-    bookmark_name: &str,
-    hooks: HashMap<String, Box<dyn Hook<HookChangeset>>>,
-    bookmarks: HashMap<String, Vec<String>>,
-    regexes: HashMap<String, Vec<String>>,
-    expected: HashMap<String, HookExecution>,
-    content_store_type: ContentStoreType,
-) {
-    let mut hook_manager = setup_hook_manager(ctx.fb, bookmarks, regexes, content_store_type).await;
-    for (hook_name, hook) in hooks {
-        hook_manager.register_changeset_hook(&hook_name, hook.into(), Default::default());
+#[async_trait]
+impl FileContentStore for CountingFileContentStore {
+    async fn resolve_path<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        changeset_id: HookChangesetId,
+        path: MPath,
+    ) -> Result<Option<FileContentId>, Error> {
+        self.inner.resolve_path(ctx, changeset_id, path).await
+    }
+
+    async fn get_file_text<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<Option<FileBytes>, Error> {
+        *self.text_fetches.lock().unwrap().entry(id).or_insert(0) += 1;
+        self.inner.get_file_text(ctx, id).await
+    }
+
+    async fn get_file_size<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<u64, Error> {
+        *self.size_fetches.lock().unwrap().entry(id).or_insert(0) += 1;
+        self.inner.get_file_size(ctx, id).await
+    }
+
+    async fn get_file_content_id<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<ContentId, Error> {
+        self.inner.get_file_content_id(ctx, id).await
+    }
+
+    async fn get_file_sha256<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        id: FileContentId,
+    ) -> Result<Sha256, Error> {
+        self.inner.get_file_sha256(ctx, id).await
+    }
+}
+
+/// Reads the content of every file it's run on.
+struct ReadContentFileHook;
+
+#[async_trait]
+impl Hook<HookFile> for ReadContentFileHook {
+    async fn run(
+        &self,
+        ctx: &CoreContext,
+        context: HookContext<HookFile>,
+    ) -> Result<HookExecution, Error> {
+        context.data.file_text(ctx).await?;
+        context.data.len(ctx).await?;
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[fbinit::test]
+fn test_file_content_fetched_once(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let text_fetches = Arc::new(Mutex::new(HashMap::new()));
+        let mut hook_manager = hook_manager_inmem_with_content_store(fb, {
+            let text_fetches = text_fetches.clone();
+            move |inner| {
+                Arc::new(CountingFileContentStore {
+                    inner,
+                    text_fetches,
+                    size_fetches: Default::default(),
+                })
+            }
+        })
+        .await;
+        hook_manager.register_file_hook("read1", Arc::new(ReadContentFileHook), Default::default());
+        hook_manager.register_file_hook("read2", Arc::new(ReadContentFileHook), Default::default());
+        hook_manager
+            .set_hooks_for_bookmark(
+                BookmarkName::new("bm1").unwrap().into(),
+                vec!["read1".to_string(), "read2".to_string()],
+            )
+            .unwrap();
+
+        let res = hook_manager
+            .run_hooks_for_bookmark(
+                &ctx,
+                vec![(default_changeset_id(), None)],
+                &BookmarkName::new("bm1").unwrap(),
+                None,
+                HookExecutionMode::CompleteResults,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 6);
+        assert!(res.iter().all(|outcome| !outcome.is_rejection()));
+
+        let expected = hashmap! {
+            ONES_FNID.into() => 1,
+            TWOS_FNID.into() => 1,
+            THREES_FNID.into() => 1,
+        };
+        assert_eq!(*text_fetches.lock().unwrap(), expected);
+    });
+}
+
+/// Reads the size of every file it's run on, but not their content.
+struct FileSizeHook;
+
+#[async_trait]
+impl Hook<HookFile> for FileSizeHook {
+    async fn run(
+        &self,
+        ctx: &CoreContext,
+        context: HookContext<HookFile>,
+    ) -> Result<HookExecution, Error> {
+        context.data.len(ctx).await?;
+        Ok(HookExecution::Accepted)
+    }
+
+    fn reads_file_text(&self) -> bool {
+        false
+    }
+}
+
+/// Reads the size of every file in the changeset.
+struct FileSizesChangesetHook;
+
+#[async_trait]
+impl Hook<HookChangeset> for FileSizesChangesetHook {
+    async fn run(
+        &self,
+        ctx: &CoreContext,
+        context: HookContext<HookChangeset>,
+    ) -> Result<HookExecution, Error> {
+        for file in context.data.files(ctx).await? {
+            file.len(ctx).await?;
+        }
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[fbinit::test]
+fn test_file_content_not_prefetched_for_size_hooks(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let text_fetches = Arc::new(Mutex::new(HashMap::new()));
+        let size_fetches = Arc::new(Mutex::new(HashMap::new()));
+        let mut hook_manager = hook_manager_inmem_with_content_store(fb, {
+            let text_fetches = text_fetches.clone();
+            let size_fetches = size_fetches.clone();
+            move |inner| {
+                Arc::new(CountingFileContentStore {
+                    inner,
+                    text_fetches,
+                    size_fetches,
+                })
+            }
+        })
+        .await;
+        hook_manager.register_changeset_hook(
+            "sizes",
+            Arc::new(FileSizesChangesetHook),
+            Default::default(),
+        );
+        hook_manager.register_file_hook("size", Arc::new(FileSizeHook), Default::default());
+        hook_manager
+            .set_hooks_for_bookmark(
+                BookmarkName::new("bm1").unwrap().into(),
+                vec!["sizes".to_string(), "size".to_string()],
+            )
+            .unwrap();
+
+        let res = hook_manager
+            .run_hooks_for_bookmark(
+                &ctx,
+                vec![(default_changeset_id(), None)],
+                &BookmarkName::new("bm1").unwrap(),
+                None,
+                HookExecutionMode::CompleteResults,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 4);
+        assert!(res.iter().all(|outcome| !outcome.is_rejection()));
+
+        assert!(text_fetches.lock().unwrap().is_empty());
+        // Changeset and file hooks share the sizes they fetched.
+        let expected = hashmap! {
+            ONES_FNID.into() => 1,
+            TWOS_FNID.into() => 1,
+            THREES_FNID.into() => 1,
+        };
+        assert_eq!(*size_fetches.lock().unwrap(), expected);
+    });
+}
+
+#[derive(Clone)]
+struct ConcurrencyTrackingFileHook {
+    // (currently running, max running at once)
+    running: Arc<Mutex<(usize, usize)>>,
+}
+
+#[async_trait]
+impl Hook<HookFile> for ConcurrencyTrackingFileHook {
+    async fn run(
+        &self,
+        _ctx: &CoreContext,
+        context: HookContext<HookFile>,
+    ) -> Result<HookExecution, Error> {
+        {
+            let mut running = self.running.lock().unwrap();
+            running.0 += 1;
+            running.1 = std::cmp::max(running.0, running.1);
+        }
+        tokio::task::yield_now().await;
+        self.running.lock().unwrap().0 -= 1;
+
+        Ok(if context.data.path.ends_with('7') {
+            default_rejection()
+        } else {
+            HookExecution::Accepted
+        })
     }
+}
+
+async fn run_file_hooks_on_many_files(
+    fb: FacebookInit,
+    num_files: usize,
+    chunk_size: usize,
+) -> (HashMap<String, HookExecution>, usize) {
+    let ctx = CoreContext::test_mock(fb);
+    let running = Arc::new(Mutex::new((0, 0)));
+    let mut hook_manager = hook_manager_inmem_many_files(fb, num_files).await;
+    hook_manager.set_file_hooks_chunk_size(chunk_size);
+    hook_manager.register_file_hook(
+        "hook1",
+        Arc::new(ConcurrencyTrackingFileHook {
+            running: running.clone(),
+        }),
+        Default::default(),
+    );
+    hook_manager
+        .set_hooks_for_bookmark(
+            BookmarkName::new("bm1").unwrap().into(),
+            vec!["hook1".to_string()],
+        )
+        .unwrap();
+
     let res = hook_manager
         .run_hooks_for_bookmark(
             &ctx,
-            vec![default_changeset_id()],
-            &BookmarkName::new(bookmark_name).unwrap(),
+            vec![(default_changeset_id(), None)],
+            &BookmarkName::new("bm1").unwrap(),
             None,
+            None,
+            HookExecutionMode::CompleteResults,
         )
         .await
         .unwrap();
-    let map: HashMap<String, HookExecution> = res
+    let outcomes = res
         .into_iter()
-        .map(|outcome| (outcome.get_hook_name().to_string(), outcome.into()))
+        .map(|outcome| {
+            (
+                outcome.get_file_path().expect("Changeset hook").to_string(),
+                outcome.into(),
+            )
+        })
         .collect();
-    assert_eq!(expected, map);
+    let max_running = running.lock().unwrap().1;
+    (outcomes, max_running)
 }
 
-enum ContentStoreType {
-    InMemory,
-    Blob(BlobRepo),
+#[fbinit::test]
+fn test_file_hooks_chunked_many_files(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let num_files = 10000;
+        let (unchunked, _) = run_file_hooks_on_many_files(fb, num_files, num_files).await;
+        let (chunked, max_running) = run_file_hooks_on_many_files(fb, num_files, 100).await;
+
+        assert_eq!(unchunked.len(), num_files);
+        assert_eq!(
+            unchunked
+                .values()
+                .filter(|exec| **exec != HookExecution::Accepted)
+                .count(),
+            num_files / 10
+        );
+        assert_eq!(unchunked, chunked);
+        assert!(max_running > 0);
+        assert!(
+            max_running <= 100,
+            "{} hooks were running at once",
+            max_running
+        );
+    });
 }
 
-async fn run_file_hooks(
-    ctx: CoreContext,
-    bookmark_name: &str,
-    hooks: HashMap<String, Box<dyn Hook<HookFile>>>,
-    bookmarks: HashMap<String, Vec<String>>,
-    regexes: HashMap<String, Vec<String>>,
-    expected: HashMap<String, HashMap<String, HookExecution>>,
-    content_store_type: ContentStoreType,
-) {
-    run_file_hooks_with_mgr(
-        ctx,
-        bookmark_name,
-        hooks,
-        bookmarks,
-        regexes,
-        expected,
-        content_store_type,
-        default_changeset_id(),
-    )
-    .await
+/// Checks that the chunks of files of the changesets it's run on add up to all of their
+/// files, and records the sizes of the chunks.
+struct ChunkedFilesChangesetHook {
+    chunk_sizes: Arc<Mutex<Vec<usize>>>,
 }
 
-async fn run_file_hooks_for_cs(
-    ctx: CoreContext,
-    bookmark_name: &str,
-    hooks: HashMap<String, Box<dyn Hook<HookFile>>>,
+#[async_trait]
+impl Hook<HookChangeset> for ChunkedFilesChangesetHook {
+    async fn run(
+        &self,
+        ctx: &CoreContext,
+        context: HookContext<HookChangeset>,
+    ) -> Result<HookExecution, Error> {
+        let chunks: Vec<Vec<HookFile>> = context.data.files_chunked(ctx, 100).try_collect().await?;
+        self.chunk_sizes
+            .lock()
+            .unwrap()
+            .extend(chunks.iter().map(Vec::len));
+        let chunked: Vec<_> = chunks.into_iter().flatten().collect();
+        let files = context.data.files(ctx).await?;
+        let count = context.data.files_count(ctx).await?;
+        Ok(if chunked == files && count == files.len() {
+            HookExecution::Accepted
+        } else {
+            default_rejection()
+        })
+    }
+}
+
+#[fbinit::test]
+fn test_changeset_files_chunked(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let chunk_sizes = Arc::new(Mutex::new(Vec::new()));
+        let mut hook_manager = hook_manager_inmem_many_files(fb, 250).await;
+        hook_manager.register_changeset_hook(
+            "chunked",
+            Arc::new(ChunkedFilesChangesetHook {
+                chunk_sizes: chunk_sizes.clone(),
+            }),
+            Default::default(),
+        );
+        hook_manager
+            .set_hooks_for_bookmark(
+                BookmarkName::new("bm1").unwrap().into(),
+                vec!["chunked".to_string()],
+            )
+            .unwrap();
+
+        let res = hook_manager
+            .run_hooks_for_bookmark(
+                &ctx,
+                vec![(default_changeset_id(), None)],
+                &BookmarkName::new("bm1").unwrap(),
+                None,
+                HookExecutionMode::CompleteResults,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        assert!(!res[0].is_rejection());
+        assert_eq!(*chunk_sizes.lock().unwrap(), vec![100, 100, 50]);
+    });
+}
+
+#[fbinit::test]
+fn test_file_text_too_large(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let cs_id = default_changeset_id();
+        let mut content_store = InMemoryFileContentStore::new();
+        content_store.insert(cs_id, to_mpath("small"), ONES_FNID, "eels");
+        content_store.insert(cs_id, to_mpath("large"), TWOS_FNID, "hippopatami");
+        let content_store = Arc::new(content_store);
+
+        let hook_file = |path: &str, id: HgFileNodeId| {
+            HookFile::new(
+                path.to_string(),
+                content_store.clone(),
+                cs_id.into(),
+                ChangedFileType::Added,
+                Some((id.into(), FileType::Regular)),
+                5,
+            )
+        };
+        let small = hook_file("small", ONES_FNID);
+        let large = hook_file("large", TWOS_FNID);
+        let changeset = HookChangeset::new(
+            "Stanislau Hlebik <stash@fb.com>".into(),
+            vec![small.clone(), large.clone()],
+            "3".into(),
+            HookChangesetParents::None,
+            cs_id.into(),
+            content_store.clone(),
+            Arc::new(None),
+            5,
+        );
+
+        let assert_too_large = |res: Result<Option<FileBytes>, Error>| match res
+            .unwrap_err()
+            .downcast_ref::<ErrorKind>(
+        ) {
+            Some(ErrorKind::FileTooLarge(id, path, 11, 5)) => {
+                assert_eq!(*id, HookChangesetId::Hg(cs_id));
+                assert_eq!(*path, to_mpath("large"));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        };
+
+        assert_eq!(
+            small.file_text(&ctx).await.unwrap(),
+            Some(FileBytes("eels".into()))
+        );
+        assert_eq!(large.file_text(&ctx).await.unwrap(), None);
+        assert_too_large(large.file_text_checked(&ctx).await);
+        assert_eq!(
+            changeset
+                .file_text(&ctx, "small".to_string())
+                .await
+                .unwrap(),
+            Some(FileBytes("eels".into()))
+        );
+        assert_eq!(
+            changeset
+                .file_text(&ctx, "large".to_string())
+                .await
+                .unwrap(),
+            None
+        );
+        assert_too_large(changeset.file_text_checked(&ctx, "large".to_string()).await);
+
+        // The size is still available, so hooks can report on large files.
+        assert_eq!(large.len(&ctx).await.unwrap(), 11);
+    });
+}
+
+/// Accepts every changeset, counting how many times it was run.
+struct CountingChangesetHook {
+    runs: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl Hook<HookChangeset> for CountingChangesetHook {
+    async fn run(
+        &self,
+        _ctx: &CoreContext,
+        _context: HookContext<HookChangeset>,
+    ) -> Result<HookExecution, Error> {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        Ok(HookExecution::Accepted)
+    }
+}
+
+async fn run_hooks_with_mode(
+    fb: FacebookInit,
+    mode: HookExecutionMode,
+) -> (HashMap<(HgChangesetId, String), HookExecution>, usize) {
+    let ctx = CoreContext::test_mock(fb);
+    let runs = Arc::new(AtomicUsize::new(0));
+    let mut hook_manager = hook_manager_many_files_dirs_blobrepo(fb).await;
+    // Rejects only the changeset with message "3".
+    let reject: fn(HookContext<HookChangeset>) -> HookExecution = |context| {
+        if context.data.comments == "3" {
+            default_rejection()
+        } else {
+            HookExecution::Accepted
+        }
+    };
+    hook_manager.register_changeset_hook(
+        "reject",
+        Arc::new(FnChangesetHook::new(reject)),
+        Default::default(),
+    );
+    hook_manager.register_changeset_hook(
+        "count",
+        Arc::new(CountingChangesetHook { runs: runs.clone() }),
+        Default::default(),
+    );
+    // Hooks are started in the order they're listed for the bookmark.
+    hook_manager
+        .set_hooks_for_bookmark(
+            BookmarkName::new("bm1").unwrap().into(),
+            vec!["reject".to_string(), "count".to_string()],
+        )
+        .unwrap();
+
+    let res = hook_manager
+        .run_hooks_for_bookmark(
+            &ctx,
+            vec![
+                (
+                    HgChangesetId::from_str("2f866e7e549760934e31bf0420a873f65100ad63").unwrap(),
+                    None,
+                ),
+                (default_changeset_id(), None),
+            ],
+            &BookmarkName::new("bm1").unwrap(),
+            None,
+            mode,
+        )
+        .await
+        .unwrap();
+    let outcomes = res
+        .into_iter()
+        .map(|outcome| {
+            (
+                (outcome.get_cs_id(), outcome.get_hook_name().to_string()),
+                outcome.into(),
+            )
+        })
+        .collect();
+    (outcomes, runs.load(Ordering::Relaxed))
+}
+
+#[fbinit::test]
+fn test_stop_at_first_failure(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let accepted_cs: HookChangesetId =
+            HgChangesetId::from_str("2f866e7e549760934e31bf0420a873f65100ad63")
+                .unwrap()
+                .into();
+        let rejected_cs: HookChangesetId = default_changeset_id().into();
+
+        let (outcomes, runs) = run_hooks_with_mode(fb, HookExecutionMode::StopAtFirstFailure).await;
+        let expected = hashmap! {
+            (accepted_cs, "reject".to_string()) => HookExecution::Accepted,
+            (accepted_cs, "count".to_string()) => HookExecution::Accepted,
+            (rejected_cs, "reject".to_string()) => default_rejection(),
+            (rejected_cs, "count".to_string()) => HookExecution::Skipped,
+        };
+        assert_eq!(outcomes, expected);
+        // Only the changeset that wasn't rejected ran the second hook.
+        assert_eq!(runs, 1);
+        assert_eq!(HookExecution::Skipped.to_string(), "Skipped");
+
+        let (outcomes, runs) = run_hooks_with_mode(fb, HookExecutionMode::CompleteResults).await;
+        let expected = hashmap! {
+            (accepted_cs, "reject".to_string()) => HookExecution::Accepted,
+            (accepted_cs, "count".to_string()) => HookExecution::Accepted,
+            (rejected_cs, "reject".to_string()) => default_rejection(),
+            (rejected_cs, "count".to_string()) => HookExecution::Accepted,
+        };
+        assert_eq!(outcomes, expected);
+        assert_eq!(runs, 2);
+    });
+}
+
+#[fbinit::test]
+fn test_hook_dependencies(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let mut hook_manager = hook_manager_inmem(fb).await;
+        // Accepts only if it was told that "accept" accepted.
+        let after_accept: fn(HookContext<HookChangeset>) -> HookExecution = |context| {
+            let expected = hashmap! { "accept".to_string() => HookExecution::Accepted };
+            if context.dependency_outcomes == expected {
+                HookExecution::Accepted
+            } else {
+                default_rejection()
+            }
+        };
+        hook_manager.register_changeset_hook(
+            "accept",
+            always_accepting_changeset_hook().into(),
+            Default::default(),
+        );
+        hook_manager.register_changeset_hook(
+            "reject",
+            always_rejecting_changeset_hook().into(),
+            Default::default(),
+        );
+        hook_manager.register_changeset_hook(
+            "after_accept",
+            Arc::new(FnChangesetHook::new(after_accept)),
+            Default::default(),
+        );
+        hook_manager.register_changeset_hook(
+            "after_reject",
+            always_accepting_changeset_hook().into(),
+            Default::default(),
+        );
+        hook_manager.register_changeset_hook(
+            "after_disabled",
+            always_accepting_changeset_hook().into(),
+            Default::default(),
+        );
+        hook_manager.set_hook_dependencies("after_accept", vec!["accept".to_string()]);
+        hook_manager.set_hook_dependencies("after_reject", vec!["reject".to_string()]);
+        // "disabled" isn't enabled for the bookmark, so it doesn't hold anything back.
+        hook_manager.set_hook_dependencies("after_disabled", vec!["disabled".to_string()]);
+        hook_manager
+            .set_hooks_for_bookmark(
+                BookmarkName::new("bm1").unwrap().into(),
+                vec![
+                    "after_accept".to_string(),
+                    "after_reject".to_string(),
+                    "after_disabled".to_string(),
+                    "accept".to_string(),
+                    "reject".to_string(),
+                ],
+            )
+            .unwrap();
+
+        let res = hook_manager
+            .run_hooks_for_bookmark(
+                &ctx,
+                vec![(default_changeset_id(), None)],
+                &BookmarkName::new("bm1").unwrap(),
+                None,
+                HookExecutionMode::CompleteResults,
+            )
+            .await
+            .unwrap();
+        let outcomes: HashMap<String, HookExecution> = res
+            .into_iter()
+            .map(|outcome| (outcome.get_hook_name().to_string(), outcome.into()))
+            .collect();
+        let expected = hashmap! {
+            "accept".to_string() => HookExecution::Accepted,
+            "reject".to_string() => default_rejection(),
+            "after_accept".to_string() => HookExecution::Accepted,
+            "after_reject".to_string() => HookExecution::Skipped,
+            "after_disabled".to_string() => HookExecution::Accepted,
+        };
+        assert_eq!(outcomes, expected);
+    });
+}
+
+async fn run_changeset_hooks(
+    ctx: CoreContext,
+    bookmark_name: &str,
+    hooks: HashMap<String, Box<dyn Hook<HookChangeset>>>,
+    bookmarks: HashMap<String, Vec<String>>,
+    regexes: HashMap<String, Vec<String>>,
+    expected: HashMap<String, HookExecution>,
+) {
+    run_changeset_hooks_with_mgr(
+        ctx,
+        bookmark_name,
+        hooks,
+        bookmarks,
+        regexes,
+        expected,
+        ContentStoreType::InMemory,
+    )
+    .await
+}
+
+async fn run_changeset_hooks_with_mgr(
+    ctx: CoreContext,
+    bookmark_name: &str,
+    hooks: HashMap<String, Box<dyn Hook<HookChangeset>>>,
+    bookmarks: HashMap<String, Vec<String>>,
+    regexes: HashMap<String, Vec<String>>,
+    expected: HashMap<String, HookExecution>,
+    content_store_type: ContentStoreType,
+) {
+    let mut hook_manager = setup_hook_manager(ctx.fb, bookmarks, regexes, content_store_type).await;
+    for (hook_name, hook) in hooks {
+        hook_manager.register_changeset_hook(&hook_name, hook.into(), Default::default());
+    }
+    let res = hook_manager
+        .run_hooks_for_bookmark(
+            &ctx,
+            vec![(default_changeset_id(), None)],
+            &BookmarkName::new(bookmark_name).unwrap(),
+            None,
+            None,
+            HookExecutionMode::CompleteResults,
+        )
+        .await
+        .unwrap();
+    let map: HashMap<String, HookExecution> = res
+        .into_iter()
+        .map(|outcome| (outcome.get_hook_name().to_string(), outcome.into()))
+        .collect();
+    assert_eq!(expected, map);
+}
+
+enum ContentStoreType {
+    InMemory,
+    Blob(BlobRepo),
+}
+
+async fn run_file_hooks(
+    ctx: CoreContext,
+    bookmark_name: &str,
+    hooks: HashMap<String, Box<dyn Hook<HookFile>>>,
+    bookmarks: HashMap<String, Vec<String>>,
+    regexes: HashMap<String, Vec<String>>,
+    expected: HashMap<String, HashMap<String, HookExecution>>,
+    content_store_type: ContentStoreType,
+) {
+    run_file_hooks_with_mgr(
+        ctx,
+        bookmark_name,
+        hooks,
+        bookmarks,
+        regexes,
+        expected,
+        content_store_type,
+        default_changeset_id(),
+    )
+    .await
+}
+
+async fn run_file_hooks_for_cs(
+    ctx: CoreContext,
+    bookmark_name: &str,
+    hooks: HashMap<String, Box<dyn Hook<HookFile>>>,
     bookmarks: HashMap<String, Vec<String>>,
     regexes: HashMap<String, Vec<String>>,
     expected: HashMap<String, HashMap<String, HookExecution>>,
@@ -1155,9 +1971,11 @@ async fn run_file_hooks_with_mgr(
     let res = hook_manager
         .run_hooks_for_bookmark(
             &ctx,
-            vec![hg_cs_id],
+            vec![(hg_cs_id, None)],
             &BookmarkName::new(bookmark_name).unwrap(),
             None,
+            None,
+            HookExecutionMode::CompleteResults,
         )
         .await
         .unwrap();
@@ -1185,54 +2003,653 @@ async fn setup_hook_manager(
     };
     for (bookmark_name, hook_names) in bookmarks {
         hook_manager
-            .set_hooks_for_bookmark(BookmarkName::new(bookmark_name).unwrap().into(), hook_names);
+            .set_hooks_for_bookmark(BookmarkName::new(bookmark_name).unwrap().into(), hook_names)
+            .unwrap();
     }
     for (regx, hook_names) in regexes {
-        hook_manager.set_hooks_for_bookmark(Regex::new(&regx).unwrap().into(), hook_names);
+        hook_manager
+            .set_hooks_for_bookmark(Regex::new(&regx).unwrap().into(), hook_names)
+            .unwrap();
     }
     hook_manager
 }
 
-fn default_rejection() -> HookExecution {
-    HookExecution::Rejected(HookRejectionInfo::new_long(
-        "desc".into(),
-        "long_desc".to_string(),
-    ))
+#[test]
+fn test_rejection_suggestions_display() {
+    let outcome = |info: HookRejectionInfo| {
+        HookOutcome::ChangesetHook(
+            ChangesetHookExecutionID {
+                cs_id: default_changeset_id().into(),
+                bonsai_cs_id: None,
+                hook_name: "hook".to_string(),
+                bookmark: BookmarkName::new("bm1").unwrap(),
+            },
+            HookExecution::Rejected(info),
+        )
+        .to_string()
+    };
+    let prefix = format!("hook for {}: Rejected: long_desc", default_changeset_id());
+
+    let info = HookRejectionInfo::new_long("desc", "long_desc".to_string());
+    assert_eq!(outcome(info.clone()), prefix);
+
+    let info = info.with_suggestion(
+        HookSuggestion::new("Fix lint errors")
+            .with_command("arc lint --apply")
+            .with_paths(vec!["dir/a", "dir/b"]),
+    );
+    assert_eq!(
+        outcome(info.clone()),
+        format!(
+            "{}\n  * Fix lint errors: run `arc lint --apply` (dir/a, dir/b)",
+            prefix
+        )
+    );
+
+    let info = info
+        .with_suggestion(HookSuggestion::new("Remove the file").with_paths(vec!["dir/c"]))
+        .with_suggestion(HookSuggestion::new("Amend the commit message"));
+    assert_eq!(
+        outcome(info),
+        format!(
+            "{}\n  * Fix lint errors: run `arc lint --apply` (dir/a, dir/b)\
+             \n  * Remove the file (dir/c)\
+             \n  * Amend the commit message",
+            prefix
+        )
+    );
 }
 
-fn default_changeset_id() -> HgChangesetId {
-    HgChangesetId::from_str("d261bc7900818dea7c86935b3fb17a33b2e3a6b4").unwrap()
+#[test]
+fn test_suggestions_serde_round_trip() -> Result<(), Error> {
+    let suggestions = vec![
+        HookSuggestion::new("Fix lint errors")
+            .with_command("arc lint --apply")
+            .with_paths(vec!["dir/a"]),
+        HookSuggestion::new("Amend the commit message"),
+    ];
+    let json = serde_json::to_string(&suggestions)?;
+    let parsed: Vec<HookSuggestion> = serde_json::from_str(&json)?;
+    assert_eq!(parsed, suggestions);
+    Ok(())
 }
 
-fn hook_manager_blobrepo(fb: FacebookInit, repo: BlobRepo) -> HookManager {
-    let ctx = CoreContext::test_mock(fb);
-    let changeset_store = BlobRepoChangesetStore::new(repo.clone());
-    let content_store = BlobRepoFileContentStore::new(repo);
-    HookManager::new(
-        ctx.fb,
-        Box::new(changeset_store),
-        Arc::new(content_store),
-        Default::default(),
-        ScubaSampleBuilder::with_discard(),
+const INMEM_REPO_NAME: &str = "inmem_repo";
+const INMEM_REPO_ID: RepositoryId = RepositoryId::new(42);
+
+fn changeset_outcome(hook_name: &str, cs_id: HgChangesetId, exec: HookExecution) -> HookOutcome {
+    HookOutcome::ChangesetHook(
+        ChangesetHookExecutionID {
+            cs_id: cs_id.into(),
+            bonsai_cs_id: None,
+            hook_name: hook_name.to_string(),
+            bookmark: BookmarkName::new("bm1").unwrap(),
+        },
+        exec,
     )
 }
 
-async fn hook_manager_many_files_dirs_blobrepo(fb: FacebookInit) -> HookManager {
-    hook_manager_blobrepo(fb, many_files_dirs::getrepo(fb).await)
+fn file_outcome(
+    hook_name: &str,
+    cs_id: HgChangesetId,
+    path: &str,
+    exec: HookExecution,
+) -> HookOutcome {
+    let file = HookFile::new(
+        path.to_string(),
+        Arc::new(InMemoryFileContentStore::new()),
+        cs_id.into(),
+        ChangedFileType::Added,
+        None,
+        DEFAULT_HOOK_MAX_FILE_SIZE,
+    );
+    HookOutcome::FileHook(
+        FileHookExecutionID {
+            cs_id: cs_id.into(),
+            bonsai_cs_id: None,
+            hook_name: hook_name.to_string(),
+            file,
+            bookmark: BookmarkName::new("bm1").unwrap(),
+        },
+        exec,
+    )
 }
 
-fn to_mpath(string: &str) -> MPath {
-    // Please... avert your eyes
-    MPath::new(string.to_string().as_bytes().to_vec()).unwrap()
+fn rejection(long_description: &str) -> HookExecution {
+    HookExecution::Rejected(HookRejectionInfo::new_long(
+        "desc",
+        long_description.to_string(),
+    ))
 }
 
-async fn hook_manager_inmem(fb: FacebookInit) -> HookManager {
-    let ctx = CoreContext::test_mock(fb);
-    let repo = many_files_dirs::getrepo(fb).await;
-    // Load up an in memory store with a single commit from the many_files_dirs store
-    let cs_id = HgChangesetId::from_str("d261bc7900818dea7c86935b3fb17a33b2e3a6b4").unwrap();
-    let cs = cs_id
-        .load(ctx.clone(), repo.blobstore())
+#[test]
+fn test_format_rejections_none() {
+    assert_eq!(format_rejections(&[], 10), None);
+    let outcomes = vec![
+        changeset_outcome("hook1", ONES_CSID, HookExecution::Accepted),
+        file_outcome("hook2", ONES_CSID, "a", HookExecution::Skipped),
+    ];
+    assert_eq!(format_rejections(&outcomes, 10), None);
+}
+
+#[test]
+fn test_format_rejections_grouped_and_sorted() {
+    let outcomes = vec![
+        file_outcome("hook_b", TWOS_CSID, "dir/b", rejection("b is bad")),
+        changeset_outcome("hook_b", ONES_CSID, HookExecution::Accepted),
+        file_outcome("hook_b", ONES_CSID, "dir/a", rejection("a is bad")),
+        changeset_outcome("hook_a", TWOS_CSID, rejection("twos is bad")),
+        file_outcome("hook_a", ONES_CSID, "c", rejection("c is bad")),
+        changeset_outcome("hook_a", ONES_CSID, rejection("ones is bad")),
+    ];
+    assert_eq!(
+        format_rejections(&outcomes, 10).unwrap(),
+        format!(
+            "hook_a:\
+             \n  {ones}: ones is bad\
+             \n  {twos}: twos is bad\
+             \n  {ones} c: c is bad\
+             \nhook_b:\
+             \n  {ones} dir/a: a is bad\
+             \n  {twos} dir/b: b is bad",
+            ones = ONES_CSID,
+            twos = TWOS_CSID,
+        )
+    );
+}
+
+#[test]
+fn test_format_rejections_max_per_hook() {
+    let mut outcomes: Vec<_> = (0..5)
+        .map(|i| {
+            file_outcome(
+                "hook1",
+                ONES_CSID,
+                &format!("file{}", i),
+                rejection("too big"),
+            )
+        })
+        .collect();
+    outcomes.push(changeset_outcome(
+        "hook2",
+        ONES_CSID,
+        rejection("bad message"),
+    ));
+    assert_eq!(
+        format_rejections(&outcomes, 2).unwrap(),
+        format!(
+            "hook1:\
+             \n  {ones} file0: too big\
+             \n  {ones} file1: too big\
+             \n  and 3 more\
+             \nhook2:\
+             \n  {ones}: bad message",
+            ones = ONES_CSID,
+        )
+    );
+    assert_eq!(
+        format_rejections(&outcomes, 0).unwrap(),
+        "hook1:\n  and 5 more\nhook2:\n  and 1 more",
+    );
+}
+
+#[test]
+fn test_format_rejections_suggestions_and_policy() {
+    let lint = HookSuggestion::new("Fix lint errors").with_command("arc lint --apply");
+    let info = |path: &str| {
+        HookRejectionInfo::new_long("desc", format!("{} has lint errors", path))
+            .with_suggestion(lint.clone())
+            .with_suggestion(HookSuggestion::new("Remove the file").with_paths(vec![path]))
+            .with_policy_url("https://example.com/lint")
+    };
+    let outcomes = vec![
+        file_outcome("lint", ONES_CSID, "b", HookExecution::Rejected(info("b"))),
+        file_outcome("lint", ONES_CSID, "a", HookExecution::Rejected(info("a"))),
+        changeset_outcome(
+            "message",
+            ONES_CSID,
+            HookExecution::Rejected(
+                HookRejectionInfo::new("Bad message")
+                    .with_suggestion(HookSuggestion::new("Amend the commit message")),
+            ),
+        ),
+    ];
+    // Suggestions and policies are listed once per hook, even for rejections that aren't
+    // listed themselves.
+    assert_eq!(
+        format_rejections(&outcomes, 1).unwrap(),
+        format!(
+            "lint:\
+             \n  {ones} a: a has lint errors\
+             \n  and 1 more\
+             \n  * Fix lint errors: run `arc lint --apply`\
+             \n  * Remove the file (a)\
+             \n  * Remove the file (b)\
+             \n  Policy: https://example.com/lint\
+             \nmessage:\
+             \n  {ones}: Bad message\
+             \n  * Amend the commit message",
+            ones = ONES_CSID,
+        )
+    );
+}
+
+#[test]
+fn test_hook_outcome_log_to_scuba() -> Result<(), Error> {
+    let log_file = NamedTempFile::new()?;
+    let scuba = ScubaSampleBuilder::with_discard().with_log_file(log_file.path())?;
+
+    file_outcome(
+        "lint",
+        ONES_CSID,
+        "dir/a",
+        HookExecution::Rejected(HookRejectionInfo::new_long(
+            "Lint errors",
+            "dir/a has lint errors".to_string(),
+        )),
+    )
+    .log_to_scuba(&scuba);
+    changeset_outcome("message", TWOS_CSID, HookExecution::Accepted).log_to_scuba(&scuba);
+
+    let samples: Vec<serde_json::Value> = fs::read_to_string(log_file.path())?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(samples.len(), 2);
+
+    let rejection = &samples[0];
+    assert_eq!(rejection["normal"]["hook"], "lint");
+    assert_eq!(rejection["normal"]["bookmark"], "bm1");
+    assert_eq!(rejection["normal"]["hash"], ONES_CSID.to_hex().to_string());
+    assert_eq!(rejection["normal"]["path"], "dir/a");
+    assert_eq!(rejection["normal"]["outcome"], "rejected");
+    assert_eq!(rejection["normal"]["reason"], "Lint errors");
+    assert_eq!(rejection["int"]["code"], 1);
+
+    let acceptance = &samples[1];
+    assert_eq!(acceptance["normal"]["hook"], "message");
+    assert_eq!(acceptance["normal"]["hash"], TWOS_CSID.to_hex().to_string());
+    assert_eq!(acceptance["normal"]["outcome"], "accepted");
+    assert_eq!(acceptance["int"]["code"], 0);
+    assert!(acceptance["normal"].get("path").is_none());
+    assert!(acceptance["normal"].get("reason").is_none());
+    Ok(())
+}
+
+#[fbinit::test]
+fn test_run_hooks_logs_one_sample_per_hook(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = many_files_dirs::getrepo(fb).await;
+        let log_file = NamedTempFile::new().unwrap();
+        let mut hook_manager = HookManager::new(
+            ctx.fb,
+            repo.name().clone(),
+            repo.get_repoid(),
+            Box::new(BlobRepoChangesetStore::new(repo.clone())),
+            Arc::new(BlobRepoFileContentStore::new(repo.clone())),
+            Default::default(),
+            ScubaSampleBuilder::with_discard()
+                .with_log_file(log_file.path())
+                .unwrap(),
+        );
+        hook_manager.register_changeset_hook(
+            "rejecting",
+            always_rejecting_changeset_hook().into(),
+            Default::default(),
+        );
+        hook_manager.register_file_hook(
+            "accepting_file",
+            always_accepting_file_hook().into(),
+            Default::default(),
+        );
+        hook_manager
+            .set_hooks_for_bookmark(
+                BookmarkName::new("bm1").unwrap().into(),
+                vec!["rejecting".to_string(), "accepting_file".to_string()],
+            )
+            .unwrap();
+
+        let cs_id = default_changeset_id();
+        let bonsai_cs_id = repo
+            .get_bonsai_from_hg(ctx.clone(), cs_id)
+            .compat()
+            .await
+            .unwrap()
+            .expect("changeset has no bonsai");
+        let outcomes = hook_manager
+            .run_hooks_for_bookmark(
+                &ctx,
+                vec![(cs_id, Some(bonsai_cs_id))],
+                &BookmarkName::new("bm1").unwrap(),
+                None,
+                HookExecutionMode::CompleteResults,
+            )
+            .await
+            .unwrap();
+
+        // The outcome columns are on the sample logged for each hook run, rather than on
+        // samples of their own.
+        let samples: Vec<serde_json::Value> = fs::read_to_string(log_file.path())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(samples.len(), outcomes.len());
+        for sample in &samples {
+            assert_eq!(sample["normal"]["bookmark"], "bm1");
+            assert_eq!(sample["normal"]["hash"], cs_id.to_hex().to_string());
+            assert_eq!(sample["normal"]["bonsai_hash"], bonsai_cs_id.to_string());
+            assert!(sample["int"].get("elapsed").is_some());
+            if sample["normal"]["hook"] == "rejecting" {
+                assert_eq!(sample["normal"]["outcome"], "rejected");
+                assert_eq!(sample["normal"]["reason"], "desc");
+                assert_eq!(sample["int"]["code"], 1);
+                assert!(sample["normal"].get("path").is_none());
+            } else {
+                assert_eq!(sample["normal"]["hook"], "accepting_file");
+                assert_eq!(sample["normal"]["outcome"], "accepted");
+                assert_eq!(sample["int"]["code"], 0);
+                assert!(sample["normal"].get("path").is_some());
+            }
+        }
+    });
+}
+
+#[fbinit::test]
+fn test_hook_outcome_bonsai_cs_id(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = many_files_dirs::getrepo(fb).await;
+        let mut hook_manager = hook_manager_blobrepo(fb, repo.clone());
+        hook_manager.register_changeset_hook(
+            "always_accepting",
+            always_accepting_changeset_hook().into(),
+            Default::default(),
+        );
+        hook_manager.register_file_hook(
+            "always_accepting_file",
+            always_accepting_file_hook().into(),
+            Default::default(),
+        );
+        hook_manager
+            .set_hooks_for_bookmark(
+                BookmarkName::new("bm1").unwrap().into(),
+                vec![
+                    "always_accepting".to_string(),
+                    "always_accepting_file".to_string(),
+                ],
+            )
+            .unwrap();
+
+        let cs_id = default_changeset_id();
+        let expected = repo
+            .get_bonsai_from_hg(ctx.clone(), cs_id)
+            .compat()
+            .await
+            .unwrap()
+            .expect("changeset has no bonsai");
+        let outcomes = hook_manager
+            .run_hooks_for_bookmark(
+                &ctx,
+                vec![(cs_id, Some(expected))],
+                &BookmarkName::new("bm1").unwrap(),
+                None,
+                HookExecutionMode::CompleteResults,
+            )
+            .await
+            .unwrap();
+        assert!(outcomes.len() > 1);
+        for outcome in &outcomes {
+            assert_eq!(outcome.get_bonsai_cs_id(), Some(expected));
+            assert!(outcome.to_string().contains(&expected.to_string()));
+        }
+    });
+}
+
+#[fbinit::test]
+fn test_hook_outcome_bonsai_cs_id_inmem(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let mut hook_manager = hook_manager_inmem(fb).await;
+        hook_manager.register_changeset_hook(
+            "always_accepting",
+            always_accepting_changeset_hook().into(),
+            Default::default(),
+        );
+        hook_manager
+            .set_hooks_for_bookmark(
+                BookmarkName::new("bm1").unwrap().into(),
+                vec!["always_accepting".to_string()],
+            )
+            .unwrap();
+
+        let outcomes = hook_manager
+            .run_hooks_for_bookmark(
+                &ctx,
+                vec![(default_changeset_id(), None)],
+                &BookmarkName::new("bm1").unwrap(),
+                None,
+                HookExecutionMode::CompleteResults,
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].get_bonsai_cs_id(), None);
+    });
+}
+
+#[fbinit::test]
+fn test_bonsai_hooks_match_hg_hooks(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = blobrepo_factory::new_memblob_empty(None).unwrap();
+        let root = create_commit(
+            ctx.clone(),
+            repo.clone(),
+            vec![],
+            store_files(
+                ctx.clone(),
+                btreemap! {"a" => Some("old a"), "b" => Some("b"), "dir/c" => Some("c")},
+                repo.clone(),
+            )
+            .await,
+        )
+        .await;
+        let child = create_commit(
+            ctx.clone(),
+            repo.clone(),
+            vec![root],
+            store_files(
+                ctx.clone(),
+                btreemap! {"a" => Some("new a"), "b" => None, "dir/d" => Some("d")},
+                repo.clone(),
+            )
+            .await,
+        )
+        .await;
+
+        let mut hook_manager = hook_manager_blobrepo(fb, repo.clone());
+        let changeset_hooks: Vec<(&str, Box<dyn Hook<HookChangeset>>)> = vec![
+            (
+                "lengths",
+                length_matching_changeset_hook(hashmap! {
+                    "a".to_string() => 5,
+                    "b".to_string() => 1,
+                    "dir/c".to_string() => 1,
+                }),
+            ),
+            (
+                "other",
+                other_file_matching_changeset_hook("dir/c".to_string(), Some("c".to_string())),
+            ),
+            (
+                "missing",
+                other_file_matching_changeset_hook("no/such/path".to_string(), None),
+            ),
+            ("reject", always_rejecting_changeset_hook()),
+        ];
+        let file_hooks: Vec<(&str, Box<dyn Hook<HookFile>>)> = vec![
+            (
+                "text",
+                file_text_matching_file_hook(Some("new a".to_string())),
+            ),
+            ("len", length_matching_file_hook(1)),
+            ("symlink", is_symlink_matching_file_hook(false)),
+            (
+                "added",
+                changed_file_type_matching_file_hook(ChangedFileType::Added),
+            ),
+            (
+                "path",
+                path_matching_file_hook(hashset! {"dir/d".to_string()}),
+            ),
+        ];
+        let mut hook_names = vec![];
+        for (name, hook) in changeset_hooks {
+            hook_manager.register_changeset_hook(name, hook.into(), Default::default());
+            hook_names.push(name.to_string());
+        }
+        for (name, hook) in file_hooks {
+            hook_manager.register_file_hook(name, hook.into(), Default::default());
+            hook_names.push(name.to_string());
+        }
+        hook_manager
+            .set_hooks_for_bookmark(BookmarkName::new("bm1").unwrap().into(), hook_names)
+            .unwrap();
+
+        let bookmark = BookmarkName::new("bm1").unwrap();
+        let by_hook_and_path = |outcomes: Vec<HookOutcome>| -> HashMap<_, _> {
+            outcomes
+                .into_iter()
+                .map(|outcome| {
+                    (
+                        (
+                            outcome.get_hook_name().to_string(),
+                            outcome.get_file_path().map(String::from),
+                        ),
+                        HookExecution::from(outcome),
+                    )
+                })
+                .collect()
+        };
+        let mut child_outcomes = HashMap::new();
+        for bcs_id in vec![root, child] {
+            // Run on the bonsai changeset first, before its hg changeset is derived.
+            let bonsai_outcomes = hook_manager
+                .run_hooks_for_bookmark_bonsai(
+                    &ctx,
+                    vec![bcs_id],
+                    &bookmark,
+                    None,
+                    HookExecutionMode::CompleteResults,
+                )
+                .await
+                .unwrap();
+            for outcome in &bonsai_outcomes {
+                assert_eq!(outcome.get_cs_id(), HookChangesetId::Bonsai(bcs_id));
+                assert_eq!(outcome.get_bonsai_cs_id(), Some(bcs_id));
+            }
+
+            let hg_cs_id = repo
+                .get_hg_from_bonsai_changeset(ctx.clone(), bcs_id)
+                .compat()
+                .await
+                .unwrap();
+            let hg_outcomes = hook_manager
+                .run_hooks_for_bookmark(
+                    &ctx,
+                    vec![(hg_cs_id, None)],
+                    &bookmark,
+                    None,
+                    HookExecutionMode::CompleteResults,
+                )
+                .await
+                .unwrap();
+            for outcome in &hg_outcomes {
+                assert_eq!(outcome.get_cs_id(), HookChangesetId::Hg(hg_cs_id));
+                assert_eq!(outcome.get_bonsai_cs_id(), Some(bcs_id));
+            }
+
+            let bonsai_outcomes = by_hook_and_path(bonsai_outcomes);
+            assert_eq!(bonsai_outcomes, by_hook_and_path(hg_outcomes));
+            child_outcomes = bonsai_outcomes;
+        }
+
+        let file = |hook: &str, path: &str| (hook.to_string(), Some(path.to_string()));
+        let changeset = |hook: &str| (hook.to_string(), None);
+        let expected = hashmap! {
+            // The deleted file isn't expected by the hook.
+            changeset("lengths") => default_rejection(),
+            changeset("other") => HookExecution::Accepted,
+            changeset("missing") => HookExecution::Accepted,
+            changeset("reject") => default_rejection(),
+            file("text", "a") => HookExecution::Accepted,
+            file("text", "dir/d") => default_rejection(),
+            file("len", "a") => default_rejection(),
+            file("len", "dir/d") => HookExecution::Accepted,
+            file("symlink", "a") => HookExecution::Accepted,
+            file("symlink", "dir/d") => HookExecution::Accepted,
+            file("added", "a") => default_rejection(),
+            file("added", "dir/d") => HookExecution::Accepted,
+            file("path", "a") => default_rejection(),
+            file("path", "dir/d") => HookExecution::Accepted,
+        };
+        assert_eq!(child_outcomes, expected);
+    });
+}
+
+fn default_rejection() -> HookExecution {
+    HookExecution::Rejected(HookRejectionInfo::new_long(
+        "desc".into(),
+        "long_desc".to_string(),
+    ))
+}
+
+fn default_changeset_id() -> HgChangesetId {
+    HgChangesetId::from_str("d261bc7900818dea7c86935b3fb17a33b2e3a6b4").unwrap()
+}
+
+fn hook_manager_blobrepo(fb: FacebookInit, repo: BlobRepo) -> HookManager {
+    let ctx = CoreContext::test_mock(fb);
+    let repo_name = repo.name().clone();
+    let repo_id = repo.get_repoid();
+    let changeset_store = BlobRepoChangesetStore::new(repo.clone());
+    let content_store = BlobRepoFileContentStore::new(repo);
+    HookManager::new(
+        ctx.fb,
+        repo_name,
+        repo_id,
+        Box::new(changeset_store),
+        Arc::new(content_store),
+        Default::default(),
+        ScubaSampleBuilder::with_discard(),
+    )
+}
+
+async fn hook_manager_many_files_dirs_blobrepo(fb: FacebookInit) -> HookManager {
+    hook_manager_blobrepo(fb, many_files_dirs::getrepo(fb).await)
+}
+
+fn to_mpath(string: &str) -> MPath {
+    // Please... avert your eyes
+    MPath::new(string.to_string().as_bytes().to_vec()).unwrap()
+}
+
+async fn hook_manager_inmem(fb: FacebookInit) -> HookManager {
+    hook_manager_inmem_with_content_store(fb, |content_store| Arc::new(content_store)).await
+}
+
+async fn hook_manager_inmem_with_content_store(
+    fb: FacebookInit,
+    wrap_content_store: impl FnOnce(InMemoryFileContentStore) -> Arc<dyn FileContentStore>,
+) -> HookManager {
+    let ctx = CoreContext::test_mock(fb);
+    let repo = many_files_dirs::getrepo(fb).await;
+    // Load up an in memory store with a single commit from the many_files_dirs store
+    let cs_id = HgChangesetId::from_str("d261bc7900818dea7c86935b3fb17a33b2e3a6b4").unwrap();
+    let cs = cs_id
+        .load(ctx.clone(), repo.blobstore())
         .compat()
         .await
         .unwrap();
@@ -1277,57 +2694,859 @@ async fn hook_manager_inmem(fb: FacebookInit) -> HookManager {
         "eels",
     );
 
-    HookManager::new(
-        ctx.fb,
-        Box::new(changeset_store),
-        Arc::new(content_store),
-        Default::default(),
-        ScubaSampleBuilder::with_discard(),
-    )
+    HookManager::new(
+        ctx.fb,
+        INMEM_REPO_NAME.to_string(),
+        INMEM_REPO_ID,
+        Box::new(changeset_store),
+        wrap_content_store(content_store),
+        Default::default(),
+        ScubaSampleBuilder::with_discard(),
+    )
+}
+
+async fn hook_manager_inmem_many_files(fb: FacebookInit, num_files: usize) -> HookManager {
+    let ctx = CoreContext::test_mock(fb);
+    let repo = many_files_dirs::getrepo(fb).await;
+    let cs_id = default_changeset_id();
+    let cs = cs_id
+        .load(ctx.clone(), repo.blobstore())
+        .compat()
+        .await
+        .unwrap();
+    let mut changeset_store = InMemoryChangesetStore::new();
+    changeset_store.insert_changeset(cs_id, cs);
+    let files = (0..num_files)
+        .map(|i| {
+            (
+                format!("dir{}/file_{}", i % 100, i),
+                ChangedFileType::Added,
+                Some((ONES_FNID, FileType::Regular)),
+            )
+        })
+        .collect();
+    changeset_store.insert_files(cs_id, files);
+
+    HookManager::new(
+        ctx.fb,
+        INMEM_REPO_NAME.to_string(),
+        INMEM_REPO_ID,
+        Box::new(changeset_store),
+        Arc::new(InMemoryFileContentStore::new()),
+        Default::default(),
+        ScubaSampleBuilder::with_discard(),
+    )
+}
+
+fn default_repo_config() -> RepoConfig {
+    RepoConfig {
+        storage_config: StorageConfig {
+            blobstore: BlobConfig::Disabled,
+            dbconfig: MetadataDBConfig::LocalDB {
+                path: "/some/place".into(),
+            },
+        },
+        write_lock_db_address: None,
+        enabled: true,
+        generation_cache_size: 1,
+        repoid: RepositoryId::new(1),
+        scuba_table: None,
+        scuba_local_path: None,
+        scuba_table_hooks: None,
+        scuba_local_path_hooks: None,
+        cache_warmup: None,
+        hook_manager_params: None,
+        bookmarks_cache_ttl: None,
+        bookmarks: vec![],
+        hooks: vec![],
+        push: Default::default(),
+        pushrebase: Default::default(),
+        lfs: Default::default(),
+        wireproto_logging: Default::default(),
+        hash_validation_percentage: 0,
+        readonly: RepoReadOnly::ReadWrite,
+        redaction: Redaction::Enabled,
+        skiplist_index_blobstore_key: None,
+        bundle2_replay_params: Bundle2ReplayParams::default(),
+        infinitepush: InfinitepushParams::default(),
+        list_keys_patterns_max: 123,
+        filestore: None,
+        commit_sync_config: None,
+        hook_max_file_size: 456,
+        hipster_acl: None,
+        source_control_service: SourceControlServiceParams::default(),
+        source_control_service_monitoring: None,
+        derived_data_config: DerivedDataConfig::default(),
+    }
+}
+
+async fn run_changeset_hook_on_message(
+    ctx: &CoreContext,
+    hook: &dyn Hook<HookChangeset>,
+    hook_name: &str,
+    config: HookConfig,
+    comments: &str,
+) -> HookExecution {
+    run_changeset_hook_on_author_and_message(
+        ctx,
+        hook,
+        hook_name,
+        config,
+        "Stanislau Hlebik <stash@fb.com>",
+        comments,
+    )
+    .await
+}
+
+async fn run_changeset_hook_on_author_and_message(
+    ctx: &CoreContext,
+    hook: &dyn Hook<HookChangeset>,
+    hook_name: &str,
+    config: HookConfig,
+    author: &str,
+    comments: &str,
+) -> HookExecution {
+    let data = HookChangeset::new(
+        author.into(),
+        vec![],
+        comments.into(),
+        HookChangesetParents::None,
+        default_changeset_id().into(),
+        Arc::new(InMemoryFileContentStore::new()),
+        Arc::new(None),
+        DEFAULT_HOOK_MAX_FILE_SIZE,
+    );
+    let context = HookContext {
+        hook_name: hook_name.into(),
+        config,
+        data,
+        bookmark: BookmarkName::new("bm1").unwrap(),
+        repo_name: INMEM_REPO_NAME.to_string(),
+        repo_id: INMEM_REPO_ID,
+        dependency_outcomes: HashMap::new(),
+    };
+    hook.run(ctx, context).await.unwrap()
+}
+
+async fn run_reverts_need_task(
+    ctx: &CoreContext,
+    config: HookConfig,
+    comments: &str,
+) -> HookExecution {
+    let hook = RevertsNeedTask::new(&config).unwrap();
+    run_changeset_hook_on_message(ctx, &hook, "reverts_need_task", config, comments).await
+}
+
+#[fbinit::test]
+fn test_reverts_need_task(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let accepted = |exec: HookExecution| exec == HookExecution::Accepted;
+        assert!(accepted(
+            run_reverts_need_task(&ctx, Default::default(), "mononoke: fix bug\nSummary: fix")
+                .await
+        ));
+        assert!(accepted(
+            run_reverts_need_task(
+                &ctx,
+                Default::default(),
+                "Revert \"mononoke: fix bug\"\nSummary: broke the build\nTasks: T1234"
+            )
+            .await
+        ));
+        assert!(!accepted(
+            run_reverts_need_task(
+                &ctx,
+                Default::default(),
+                "Revert \"mononoke: fix bug\"\nSummary: broke the build"
+            )
+            .await
+        ));
+        assert!(!accepted(
+            run_reverts_need_task(
+                &ctx,
+                Default::default(),
+                "Revert \"mononoke: fix bug\"\nTasks: 1234"
+            )
+            .await
+        ));
+
+        let config = HookConfig {
+            strings: hashmap! {
+                "task_pattern".to_string() => r"^(T|JIRA-)\d+$".to_string(),
+            },
+            ..Default::default()
+        };
+        assert!(accepted(
+            run_reverts_need_task(
+                &ctx,
+                config,
+                "Revert \"mononoke: fix bug\"\nTasks: JIRA-1234"
+            )
+            .await
+        ));
+    });
+}
+
+#[fbinit::test]
+fn test_load_reverts_need_task(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let mut config = default_repo_config();
+        config.bookmarks = vec![BookmarkParams {
+            bookmark: BookmarkName::new("bm1").unwrap().into(),
+            hooks: vec!["rust:reverts_need_task".into()],
+            only_fast_forward: false,
+            allowed_users: None,
+            rewrite_dates: None,
+        }];
+        config.hooks = vec![HookParams {
+            name: "rust:reverts_need_task".into(),
+            hook_type: HookType::PerChangeset,
+            config: Default::default(),
+            depends_on: vec![],
+        }];
+
+        let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
+        load_hooks(fb, &mut hm, config, &hashset![]).expect("Failed to load hooks");
+    });
+}
+
+#[fbinit::test]
+fn test_max_message_length(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let config = HookConfig {
+            ints: hashmap! {"max_length".to_string() => 10},
+            ..Default::default()
+        };
+        let hook = MaxMessageLength::new(&config).unwrap();
+
+        let exec = run_changeset_hook_on_message(
+            &ctx,
+            &hook,
+            "max_message_length",
+            config.clone(),
+            "0123456789",
+        )
+        .await;
+        assert_eq!(exec, HookExecution::Accepted);
+
+        let exec = run_changeset_hook_on_message(
+            &ctx,
+            &hook,
+            "max_message_length",
+            config,
+            "0123456789\nmore",
+        )
+        .await;
+        match exec {
+            HookExecution::Rejected(info) => assert_eq!(
+                info.long_description,
+                "Commit message is 15 characters long, the limit is 10"
+            ),
+            exec => panic!("unexpected execution: {:?}", exec),
+        }
+    });
+}
+
+#[fbinit::test]
+fn test_max_message_length_missing_config(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let mut config = default_repo_config();
+        config.bookmarks = vec![BookmarkParams {
+            bookmark: Regex::new("bm2").unwrap().into(),
+            hooks: vec!["rust:max_message_length".into()],
+            only_fast_forward: false,
+            allowed_users: None,
+            rewrite_dates: None,
+        }];
+        config.hooks = vec![HookParams {
+            name: "rust:max_message_length".into(),
+            hook_type: HookType::PerChangeset,
+            config: Default::default(),
+            depends_on: vec![],
+        }];
+
+        let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
+        load_hooks(fb, &mut hm, config, &hashset![])
+            .expect_err("`max_message_length` hook loading should have failed");
+    });
+}
+
+async fn run_check_commit_message_format(
+    ctx: &CoreContext,
+    config: HookConfig,
+    comments: &str,
+) -> Option<String> {
+    let hook = CheckCommitMessageFormat::new(&config).unwrap();
+    let exec =
+        run_changeset_hook_on_message(ctx, &hook, "check_commit_message_format", config, comments)
+            .await;
+    match exec {
+        HookExecution::Accepted => None,
+        HookExecution::Rejected(info) => Some(info.long_description),
+    }
+}
+
+#[fbinit::test]
+fn test_check_commit_message_format_empty(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        for comments in &["", " \n\n "] {
+            let rejection =
+                run_check_commit_message_format(&ctx, Default::default(), comments).await;
+            assert!(rejection.unwrap().contains("Commit message is empty"));
+        }
+        assert_eq!(
+            run_check_commit_message_format(&ctx, Default::default(), "anything goes").await,
+            None
+        );
+    });
+}
+
+#[fbinit::test]
+fn test_check_commit_message_format_max_title_length(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let config = HookConfig {
+            ints: hashmap! {"max_title_length".to_string() => 20},
+            ..Default::default()
+        };
+        assert_eq!(
+            run_check_commit_message_format(
+                &ctx,
+                config.clone(),
+                "mononoke: fix bug\n\nA long description of the fix that is not in the title"
+            )
+            .await,
+            None
+        );
+
+        // the wrapped title is checked as a whole
+        let rejection = run_check_commit_message_format(
+            &ctx,
+            config,
+            "mononoke: fix bug\nin hooks\nSummary: fix",
+        )
+        .await;
+        assert!(rejection
+            .unwrap()
+            .contains("Title is 26 characters long, the limit is 20"));
+    });
+}
+
+#[fbinit::test]
+fn test_check_commit_message_format_title_pattern(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let config = HookConfig {
+            strings: hashmap! {"title_pattern".to_string() => r"^\[?[a-z_]+\]?:".to_string()},
+            ..Default::default()
+        };
+        for comments in &["mononoke: fix bug", "[hooks]: fix bug\nSummary: fix"] {
+            assert_eq!(
+                run_check_commit_message_format(&ctx, config.clone(), comments).await,
+                None
+            );
+        }
+        let rejection = run_check_commit_message_format(&ctx, config, "Fix bug").await;
+        assert!(rejection
+            .unwrap()
+            .contains(r"Title does not match '^\[?[a-z_]+\]?:'"));
+    });
+}
+
+#[fbinit::test]
+fn test_check_commit_message_format_forbidden_words(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let config = HookConfig {
+            strings: hashmap! {"forbidden_words".to_string() => "wip, donotcommit".to_string()},
+            ..Default::default()
+        };
+        assert_eq!(
+            run_check_commit_message_format(&ctx, config.clone(), "mononoke: wipe the cache").await,
+            None
+        );
+        let rejection = run_check_commit_message_format(
+            &ctx,
+            config.clone(),
+            "mononoke: WIP fix\nSummary: DoNotCommit yet",
+        )
+        .await;
+        assert!(rejection
+            .unwrap()
+            .contains("Commit message contains forbidden words: wip, donotcommit"));
+
+        // words are looked for in the whole message, not only in the title
+        let rejection =
+            run_check_commit_message_format(&ctx, config, "mononoke: fix\n\nstill wip").await;
+        assert!(rejection
+            .unwrap()
+            .contains("Commit message contains forbidden words: wip"));
+    });
+}
+
+#[fbinit::test]
+fn test_check_commit_message_format_no_title_period(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let config = HookConfig {
+            ints: hashmap! {"no_title_period".to_string() => 1},
+            ..Default::default()
+        };
+        assert_eq!(
+            run_check_commit_message_format(
+                &ctx,
+                config.clone(),
+                "mononoke: fix bug\n\nSentences in the body end with periods."
+            )
+            .await,
+            None
+        );
+        for comments in &[
+            "mononoke: fix bug.",
+            "mononoke: fix\nthe bug.\n\nSummary: fix",
+        ] {
+            let rejection = run_check_commit_message_format(&ctx, config.clone(), comments).await;
+            assert!(rejection.unwrap().contains("Title ends with a period"));
+        }
+
+        let config = HookConfig {
+            ints: hashmap! {"no_title_period".to_string() => 0},
+            ..Default::default()
+        };
+        assert_eq!(
+            run_check_commit_message_format(&ctx, config, "mononoke: fix bug.").await,
+            None
+        );
+    });
+}
+
+#[fbinit::test]
+fn test_check_commit_message_format_all_rules(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let config = HookConfig {
+            strings: hashmap! {
+                "title_pattern".to_string() => r"^\[?[a-z_]+\]?:".to_string(),
+                "forbidden_words".to_string() => "wip".to_string(),
+            },
+            ints: hashmap! {
+                "max_title_length".to_string() => 20,
+                "no_title_period".to_string() => 1,
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            run_check_commit_message_format(&ctx, config.clone(), "mononoke: fix bug").await,
+            None
+        );
+
+        // every violated rule is reported in the same rejection
+        let rejection = run_check_commit_message_format(
+            &ctx,
+            config,
+            "Fix a wip bug in\nthe hooks.\n\nSummary: fix",
+        )
+        .await;
+        assert_eq!(
+            rejection.unwrap(),
+            "Commit message does not follow the rules of this repository:\n\
+             \x20 - Title is 27 characters long, the limit is 20\n\
+             \x20 - Title does not match '^\\[?[a-z_]+\\]?:'\n\
+             \x20 - Commit message contains forbidden words: wip\n\
+             \x20 - Title ends with a period"
+        );
+    });
+}
+
+#[fbinit::test]
+fn test_load_check_commit_message_format(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let load = |title_pattern: &str| {
+            let mut config = default_repo_config();
+            config.bookmarks = vec![BookmarkParams {
+                bookmark: BookmarkName::new("bm1").unwrap().into(),
+                hooks: vec!["rust:check_commit_message_format".into()],
+                only_fast_forward: false,
+                allowed_users: None,
+                rewrite_dates: None,
+            }];
+            config.hooks = vec![HookParams {
+                name: "rust:check_commit_message_format".into(),
+                hook_type: HookType::PerChangeset,
+                config: HookConfig {
+                    strings: hashmap! {"title_pattern".to_string() => title_pattern.to_string()},
+                    ..Default::default()
+                },
+                depends_on: vec![],
+            }];
+            config
+        };
+
+        let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
+        load_hooks(fb, &mut hm, load(r"^[a-z]+:"), &hashset![]).expect("Failed to load hooks");
+
+        let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
+        load_hooks(fb, &mut hm, load(r"^[a-z+:"), &hashset![])
+            .expect_err("`check_commit_message_format` hook loading should have failed");
+    });
+}
+
+async fn run_limit_filesize_by_extension(
+    ctx: &CoreContext,
+    config: HookConfig,
+    path: &str,
+    ty: ChangedFileType,
+    size: u64,
+) -> HookExecution {
+    let cs_id = default_changeset_id();
+    let mut content_store = InMemoryFileContentStore::new();
+    content_store.insert(cs_id, to_mpath(path), ONES_FNID, size);
+    let hash_and_type = match ty {
+        ChangedFileType::Deleted => None,
+        _ => Some((ONES_FNID.into(), FileType::Regular)),
+    };
+    let data = HookFile::new(
+        path.to_string(),
+        Arc::new(content_store),
+        cs_id.into(),
+        ty,
+        hash_and_type,
+        DEFAULT_HOOK_MAX_FILE_SIZE,
+    );
+    let hook = LimitFilesizeByExtension::new(&config).unwrap();
+    let context = HookContext {
+        hook_name: "limit_filesize_by_extension".into(),
+        config,
+        data,
+        bookmark: BookmarkName::new("bm1").unwrap(),
+        repo_name: INMEM_REPO_NAME.to_string(),
+        repo_id: INMEM_REPO_ID,
+        dependency_outcomes: HashMap::new(),
+    };
+    hook.run(ctx, context).await.unwrap()
+}
+
+fn limit_filesize_by_extension_config() -> HookConfig {
+    HookConfig {
+        ints: hashmap! {"max_size".to_string() => 100},
+        strings: hashmap! {
+            "override:png".to_string() => "1000".to_string(),
+            "banned_extensions".to_string() => "exe, .dll".to_string(),
+        },
+        ..Default::default()
+    }
+}
+
+#[fbinit::test]
+fn test_limit_filesize_by_extension(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let run = |path: &'static str, size| {
+            run_limit_filesize_by_extension(
+                &ctx,
+                limit_filesize_by_extension_config(),
+                path,
+                ChangedFileType::Added,
+                size,
+            )
+        };
+        let assert_rejected = |exec: HookExecution, path: &str, expected: &str| match exec {
+            HookExecution::Rejected(info) => {
+                assert_eq!(info.long_description, expected);
+                assert_eq!(
+                    info.suggestions,
+                    vec![HookSuggestion::new(
+                        "Remove the file, or store it outside the repository"
+                    )
+                    .with_paths(vec![path])]
+                );
+            }
+            exec => panic!("unexpected execution: {:?}", exec),
+        };
+
+        // Default limit
+        assert_eq!(run("dir/file.txt", 100).await, HookExecution::Accepted);
+        assert_rejected(
+            run("dir/file.txt", 101).await,
+            "dir/file.txt",
+            "File dir/file.txt is 101 bytes, the limit is 100 bytes (default limit)",
+        );
+
+        // Per-extension override, matched case-insensitively
+        assert_eq!(run("dir/image.PNG", 1000).await, HookExecution::Accepted);
+        assert_rejected(
+            run("dir/image.png", 1001).await,
+            "dir/image.png",
+            "File dir/image.png is 1001 bytes, the limit is 1000 bytes (override for .png files)",
+        );
+
+        // Banned extensions are rejected whatever their size
+        assert_rejected(
+            run("bin/tool.exe", 0).await,
+            "bin/tool.exe",
+            "File bin/tool.exe is 0 bytes, the limit is 0 bytes (.exe files are banned)",
+        );
+        assert_rejected(
+            run("bin/lib.dll", 10).await,
+            "bin/lib.dll",
+            "File bin/lib.dll is 10 bytes, the limit is 0 bytes (.dll files are banned)",
+        );
+    });
+}
+
+#[fbinit::test]
+fn test_limit_filesize_by_extension_skips_deleted(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        for path in &["dir/file.txt", "bin/tool.exe"] {
+            let exec = run_limit_filesize_by_extension(
+                &ctx,
+                limit_filesize_by_extension_config(),
+                path,
+                ChangedFileType::Deleted,
+                1_000_000,
+            )
+            .await;
+            assert_eq!(exec, HookExecution::Accepted);
+        }
+    });
+}
+
+#[test]
+fn test_limit_filesize_by_extension_bad_config() {
+    let config = |ints: HashMap<String, i32>, strings: HashMap<String, String>| HookConfig {
+        ints,
+        strings,
+        ..Default::default()
+    };
+    let max_size = || hashmap! {"max_size".to_string() => 100};
+
+    assert!(LimitFilesizeByExtension::new(&config(hashmap! {}, hashmap! {})).is_err());
+    assert!(LimitFilesizeByExtension::new(&config(
+        hashmap! {"max_size".to_string() => -1},
+        hashmap! {}
+    ))
+    .is_err());
+    assert!(LimitFilesizeByExtension::new(&config(
+        max_size(),
+        hashmap! {"override:png".to_string() => "big".to_string()}
+    ))
+    .is_err());
+    assert!(LimitFilesizeByExtension::new(&config(
+        max_size(),
+        hashmap! {"override:".to_string() => "10".to_string()}
+    ))
+    .is_err());
+    assert!(LimitFilesizeByExtension::new(&config(max_size(), hashmap! {})).is_ok());
+}
+
+fn sha256_of(content: &str) -> Sha256 {
+    Sha256::from_byte_array(sha2::Sha256::digest(content.as_bytes()).into())
+}
+
+#[fbinit::test]
+fn test_deny_known_bad_blobs(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let text_fetches = Arc::new(Mutex::new(HashMap::new()));
+        let mut hook_manager = hook_manager_inmem_with_content_store(fb, {
+            let text_fetches = text_fetches.clone();
+            move |inner| {
+                Arc::new(CountingFileContentStore {
+                    inner,
+                    text_fetches,
+                    size_fetches: Default::default(),
+                })
+            }
+        })
+        .await;
+        let config = HookConfig {
+            strings: hashmap! {
+                "bad_sha256s".to_string() => format!("{}, {}", sha256_of("eels"), sha256_of("bad")),
+            },
+            ..Default::default()
+        };
+        hook_manager.register_file_hook(
+            "deny_known_bad_blobs",
+            Arc::new(DenyKnownBadBlobs::new(&config).unwrap()),
+            config,
+        );
+        hook_manager
+            .set_hooks_for_bookmark(
+                BookmarkName::new("bm1").unwrap().into(),
+                vec!["deny_known_bad_blobs".to_string()],
+            )
+            .unwrap();
+
+        let res = hook_manager
+            .run_hooks_for_bookmark(
+                &ctx,
+                vec![(default_changeset_id(), None)],
+                &BookmarkName::new("bm1").unwrap(),
+                None,
+                HookExecutionMode::CompleteResults,
+            )
+            .await
+            .unwrap();
+        let mut rejected: Vec<_> = res
+            .iter()
+            .filter(|outcome| outcome.is_rejection())
+            .filter_map(|outcome| outcome.get_file_path())
+            .collect();
+        rejected.sort();
+        assert_eq!(res.len(), 3);
+        assert_eq!(rejected, vec!["dir1/subdir1/subsubdir2/file_2"]);
+
+        let suggestions: Vec<_> = res
+            .iter()
+            .filter_map(|outcome| match outcome.get_execution() {
+                HookExecution::Rejected(info) => Some(info.suggestions.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            suggestions,
+            vec![vec![HookSuggestion::new(
+                "Remove or replace the content of the file"
+            )
+            .with_paths(vec!["dir1/subdir1/subsubdir2/file_2"])]]
+        );
+
+        // Hashes are compared without reading the file text.
+        assert!(text_fetches.lock().unwrap().is_empty());
+    });
+}
+
+#[test]
+fn test_deny_known_bad_blobs_bad_config() {
+    let config = |bad_sha256s: &str| HookConfig {
+        strings: hashmap! {"bad_sha256s".to_string() => bad_sha256s.to_string()},
+        ..Default::default()
+    };
+
+    assert!(DenyKnownBadBlobs::new(&Default::default()).is_err());
+    assert!(DenyKnownBadBlobs::new(&config("not a hash")).is_err());
+    assert!(DenyKnownBadBlobs::new(&config(&format!("{},abc", sha256_of("bad")))).is_err());
+    assert!(DenyKnownBadBlobs::new(&config(&format!("{},", sha256_of("bad")))).is_ok());
+}
+
+#[fbinit::test]
+fn test_inmem_content_hashes(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let cs_id = default_changeset_id();
+        let mut content_store = InMemoryFileContentStore::new();
+        content_store.insert(cs_id, to_mpath("file"), ONES_FNID, "foobar");
+        content_store.insert(cs_id, to_mpath("elided"), TWOS_FNID, 100);
+
+        let mut context = ContentIdContext::new();
+        context.update("foobar".as_bytes());
+        assert_eq!(
+            content_store
+                .get_file_content_id(&ctx, ONES_FNID.into())
+                .await
+                .unwrap(),
+            context.finish()
+        );
+        assert_eq!(
+            content_store
+                .get_file_sha256(&ctx, ONES_FNID.into())
+                .await
+                .unwrap(),
+            sha256_of("foobar")
+        );
+        assert!(content_store
+            .get_file_sha256(&ctx, TWOS_FNID.into())
+            .await
+            .is_err());
+    });
+}
+
+/// Counts fetches of file content blobs from the blobstore.
+#[derive(Clone, Debug)]
+struct ContentFetchCountingBlobstore {
+    inner: Arc<dyn Blobstore>,
+    content_gets: Arc<AtomicUsize>,
 }
 
-fn default_repo_config() -> RepoConfig {
-    RepoConfig {
-        storage_config: StorageConfig {
-            blobstore: BlobConfig::Disabled,
-            dbconfig: MetadataDBConfig::LocalDB {
-                path: "/some/place".into(),
-            },
-        },
-        write_lock_db_address: None,
-        enabled: true,
-        generation_cache_size: 1,
-        repoid: RepositoryId::new(1),
-        scuba_table: None,
-        scuba_local_path: None,
-        scuba_table_hooks: None,
-        scuba_local_path_hooks: None,
-        cache_warmup: None,
-        hook_manager_params: None,
-        bookmarks_cache_ttl: None,
-        bookmarks: vec![],
-        hooks: vec![],
-        push: Default::default(),
-        pushrebase: Default::default(),
-        lfs: Default::default(),
-        wireproto_logging: Default::default(),
-        hash_validation_percentage: 0,
-        readonly: RepoReadOnly::ReadWrite,
-        redaction: Redaction::Enabled,
-        skiplist_index_blobstore_key: None,
-        bundle2_replay_params: Bundle2ReplayParams::default(),
-        infinitepush: InfinitepushParams::default(),
-        list_keys_patterns_max: 123,
-        filestore: None,
-        commit_sync_config: None,
-        hook_max_file_size: 456,
-        hipster_acl: None,
-        source_control_service: SourceControlServiceParams::default(),
-        source_control_service_monitoring: None,
-        derived_data_config: DerivedDataConfig::default(),
+impl Blobstore for ContentFetchCountingBlobstore {
+    fn get(&self, ctx: CoreContext, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        if key.contains("content.blake2.") {
+            self.content_gets.fetch_add(1, Ordering::SeqCst);
+        }
+        self.inner.get(ctx, key)
+    }
+
+    fn put(&self, ctx: CoreContext, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        self.inner.put(ctx, key, value)
     }
 }
 
+#[fbinit::test]
+fn test_blobrepo_content_sha256_uses_metadata(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let content_gets = Arc::new(AtomicUsize::new(0));
+        let repo = blobrepo_factory::new_memblob_empty(None)
+            .unwrap()
+            .dangerous_override({
+                let content_gets = content_gets.clone();
+                move |inner| -> Arc<dyn Blobstore> {
+                    Arc::new(ContentFetchCountingBlobstore {
+                        inner,
+                        content_gets,
+                    })
+                }
+            });
+        let bcs_id = create_commit(
+            ctx.clone(),
+            repo.clone(),
+            vec![],
+            store_files(
+                ctx.clone(),
+                btreemap! {"file" => Some("foobar")},
+                repo.clone(),
+            )
+            .await,
+        )
+        .await;
+        let hg_cs_id = repo
+            .get_hg_from_bonsai_changeset(ctx.clone(), bcs_id)
+            .compat()
+            .await
+            .unwrap();
+
+        let content_store = BlobRepoFileContentStore::new(repo);
+        let filenode_id = content_store
+            .resolve_path(&ctx, hg_cs_id.into(), to_mpath("file"))
+            .await
+            .unwrap()
+            .unwrap();
+        let file = HookFile::new(
+            "file".to_string(),
+            Arc::new(content_store),
+            hg_cs_id.into(),
+            ChangedFileType::Added,
+            Some((filenode_id, FileType::Regular)),
+            DEFAULT_HOOK_MAX_FILE_SIZE,
+        );
+
+        let before = content_gets.load(Ordering::SeqCst);
+        assert_eq!(
+            file.content_sha256(&ctx).await.unwrap(),
+            Sha256::from_str("c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2")
+                .unwrap()
+        );
+        let mut context = ContentIdContext::new();
+        context.update("foobar".as_bytes());
+        assert_eq!(file.content_id(&ctx).await.unwrap(), context.finish());
+        assert_eq!(content_gets.load(Ordering::SeqCst), before);
+    });
+}
+
 #[fbinit::test]
 fn test_verify_integrity_fast_failure(fb: FacebookInit) {
     async_unit::tokio_unit_test(async move {
@@ -1346,6 +3565,7 @@ fn test_verify_integrity_fast_failure(fb: FacebookInit) {
                 strings: hashmap! {String::from("verify_integrity_path") => String::from("bad_nonexisting_filename")},
                 ..Default::default()
             },
+            depends_on: vec![],
         }];
 
         let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
@@ -1370,6 +3590,7 @@ fn test_load_hooks_bad_rust_hook(fb: FacebookInit) {
             name: "rust:hook1".into(),
             hook_type: HookType::PerChangeset,
             config: Default::default(),
+            depends_on: vec![],
         }];
 
         let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
@@ -1397,6 +3618,7 @@ fn test_load_disabled_hooks(fb: FacebookInit) {
             name: "hook1".into(),
             hook_type: HookType::PerChangeset,
             config: Default::default(),
+            depends_on: vec![],
         }];
 
         let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
@@ -1423,6 +3645,7 @@ fn test_load_disabled_hooks_referenced_by_bookmark(fb: FacebookInit) {
             name: "hook1".into(),
             hook_type: HookType::PerChangeset,
             config: Default::default(),
+            depends_on: vec![],
         }];
 
         let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
@@ -1453,3 +3676,367 @@ fn test_load_disabled_hooks_hook_does_not_exist(fb: FacebookInit) {
         };
     });
 }
+
+#[fbinit::test]
+fn test_load_hooks_no_such_bookmark_hook(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let mut config = default_repo_config();
+        config.bookmarks = vec![BookmarkParams {
+            bookmark: BookmarkName::new("bm1").unwrap().into(),
+            hooks: vec!["block_empty_commit".into(), "block_empty_comit".into()],
+            only_fast_forward: false,
+            allowed_users: None,
+            rewrite_dates: None,
+        }];
+        config.hooks = vec![changeset_hook_params("block_empty_commit", &[])];
+
+        let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
+
+        match load_hooks(fb, &mut hm, config, &hashset![])
+            .unwrap_err()
+            .downcast::<ErrorKind>()
+        {
+            Ok(ErrorKind::NoSuchBookmarkHook(bookmark, hooks)) => {
+                assert_eq!(
+                    bookmark,
+                    BookmarkOrRegex::Bookmark(BookmarkName::new("bm1").unwrap())
+                );
+                assert_eq!(hooks, hashset!["block_empty_comit".to_string()]);
+            }
+            _ => assert!(false, "Unexpected err type"),
+        };
+    });
+}
+
+#[fbinit::test]
+fn test_strict_hooks_for_bookmark(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let mut hm = hook_manager_inmem(fb).await;
+        hm.register_changeset_hook(
+            "hook1",
+            always_accepting_changeset_hook().into(),
+            Default::default(),
+        );
+        let bookmark: BookmarkOrRegex = BookmarkName::new("bm1").unwrap().into();
+
+        // Dangling references are allowed unless the manager is strict.
+        hm.set_hooks_for_bookmark(
+            bookmark.clone(),
+            vec!["hook1".to_string(), "hook2".to_string()],
+        )
+        .unwrap();
+
+        hm.set_strict(true);
+        match hm
+            .set_hooks_for_bookmark(
+                bookmark.clone(),
+                vec!["hook1".to_string(), "hook2".to_string()],
+            )
+            .unwrap_err()
+            .downcast::<ErrorKind>()
+        {
+            Ok(ErrorKind::NoSuchBookmarkHook(_, hooks)) => {
+                assert_eq!(hooks, hashset!["hook2".to_string()]);
+            }
+            _ => assert!(false, "Unexpected err type"),
+        };
+        hm.set_hooks_for_bookmark(bookmark, vec!["hook1".to_string()])
+            .unwrap();
+    });
+}
+
+#[fbinit::test]
+fn test_bookmarks_for_hook(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let mut hm = hook_manager_inmem(fb).await;
+        hm.register_changeset_hook(
+            "hook1",
+            always_accepting_changeset_hook().into(),
+            Default::default(),
+        );
+        hm.register_file_hook(
+            "hook2",
+            always_accepting_file_hook().into(),
+            Default::default(),
+        );
+        hm.register_changeset_hook(
+            "unused",
+            always_accepting_changeset_hook().into(),
+            Default::default(),
+        );
+        hm.set_hooks_for_bookmark(
+            BookmarkName::new("bm2").unwrap().into(),
+            vec!["hook1".to_string()],
+        )
+        .unwrap();
+        hm.set_hooks_for_bookmark(
+            BookmarkName::new("bm1").unwrap().into(),
+            vec!["hook1".to_string(), "hook2".to_string()],
+        )
+        .unwrap();
+        hm.set_hooks_for_bookmark(
+            Regex::new("^release/").unwrap().into(),
+            vec!["hook2".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(
+            hm.bookmarks_for_hook("hook1"),
+            vec![
+                BookmarkOrRegex::Bookmark(BookmarkName::new("bm1").unwrap()),
+                BookmarkOrRegex::Bookmark(BookmarkName::new("bm2").unwrap()),
+            ]
+        );
+        assert_eq!(
+            hm.bookmarks_for_hook("hook2"),
+            vec![
+                BookmarkOrRegex::Bookmark(BookmarkName::new("bm1").unwrap()),
+                BookmarkOrRegex::Regex(Regex::new("^release/").unwrap()),
+            ]
+        );
+        assert_eq!(
+            hm.bookmarks_for_hook("unused"),
+            Vec::<BookmarkOrRegex>::new()
+        );
+
+        assert_eq!(
+            hm.hooks_for_bookmark_name(&BookmarkName::new("bm1").unwrap()),
+            vec!["hook1".to_string(), "hook2".to_string()]
+        );
+        assert_eq!(
+            hm.hooks_for_bookmark_name(&BookmarkName::new("release/1").unwrap()),
+            vec!["hook2".to_string()]
+        );
+        assert_eq!(
+            hm.hooks_for_bookmark_name(&BookmarkName::new("other").unwrap()),
+            Vec::<String>::new()
+        );
+    });
+}
+
+fn changeset_hook_params(name: &str, depends_on: &[&str]) -> HookParams {
+    HookParams {
+        name: name.into(),
+        hook_type: HookType::PerChangeset,
+        config: Default::default(),
+        depends_on: depends_on.iter().map(|dep| dep.to_string()).collect(),
+    }
+}
+
+#[fbinit::test]
+fn test_load_hooks_dependency_cycle(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let mut config = default_repo_config();
+        config.hooks = vec![
+            changeset_hook_params("block_empty_commit", &["always_fail_changeset"]),
+            changeset_hook_params("always_fail_changeset", &["block_empty_commit"]),
+        ];
+
+        let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
+
+        match load_hooks(fb, &mut hm, config, &hashset![])
+            .unwrap_err()
+            .downcast::<ErrorKind>()
+        {
+            Ok(ErrorKind::HookDependencyCycle(cycle)) => {
+                assert_eq!(
+                    cycle,
+                    vec![
+                        "always_fail_changeset".to_string(),
+                        "block_empty_commit".to_string(),
+                        "always_fail_changeset".to_string(),
+                    ]
+                );
+            }
+            _ => assert!(false, "Unexpected err type"),
+        };
+    });
+}
+
+#[fbinit::test]
+fn test_load_hooks_bad_dependency(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let mut config = default_repo_config();
+        config.hooks = vec![changeset_hook_params("block_empty_commit", &["hook1"])];
+
+        let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
+
+        match load_hooks(fb, &mut hm, config, &hashset![])
+            .unwrap_err()
+            .downcast::<ErrorKind>()
+        {
+            Ok(ErrorKind::NoSuchHookDependency(hook_name, dependency)) => {
+                assert_eq!(hook_name, "block_empty_commit".to_string());
+                assert_eq!(dependency, "hook1".to_string());
+            }
+            _ => assert!(false, "Unexpected err type"),
+        };
+
+        let mut config = default_repo_config();
+        config.hooks = vec![
+            changeset_hook_params("block_empty_commit", &["conflict_markers"]),
+            HookParams {
+                name: "conflict_markers".into(),
+                hook_type: HookType::PerAddedOrModifiedFile,
+                config: Default::default(),
+                depends_on: vec![],
+            },
+        ];
+
+        let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
+
+        match load_hooks(fb, &mut hm, config, &hashset![])
+            .unwrap_err()
+            .downcast::<ErrorKind>()
+        {
+            Ok(ErrorKind::IncompatibleHookDependency(hook_name, dependency)) => {
+                assert_eq!(hook_name, "block_empty_commit".to_string());
+                assert_eq!(dependency, "conflict_markers".to_string());
+            }
+            _ => assert!(false, "Unexpected err type"),
+        };
+
+        // Depending on a disabled hook is fine, the dependency is just never run.
+        let mut config = default_repo_config();
+        config.hooks = vec![
+            changeset_hook_params("block_empty_commit", &["always_fail_changeset"]),
+            changeset_hook_params("always_fail_changeset", &[]),
+        ];
+
+        let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
+
+        load_hooks(
+            fb,
+            &mut hm,
+            config,
+            &hashset!["always_fail_changeset".to_string()],
+        )
+        .expect("depending on a disabled hook should allow loading to succeed");
+    });
+}
+
+/// Identity verifier that knows a fixed set of identities
+struct SetIdentityVerifier(HashSet<String>);
+
+#[async_trait]
+impl IdentityVerifier for SetIdentityVerifier {
+    async fn invalid_identities(
+        &self,
+        _ctx: &CoreContext,
+        emails: &[String],
+    ) -> Result<HashSet<String>, Error> {
+        Ok(emails
+            .iter()
+            .filter(|email| !self.0.contains(*email))
+            .cloned()
+            .collect())
+    }
+}
+
+fn set_identity_verifier() -> Arc<dyn IdentityVerifier> {
+    Arc::new(SetIdentityVerifier(hashset! {
+        "stash@fb.com".to_string(),
+        "jsgf@fb.com".to_string(),
+    }))
+}
+
+async fn run_verify_author(ctx: &CoreContext, config: HookConfig, author: &str) -> HookExecution {
+    let hook = VerifyAuthor::new(&config, set_identity_verifier()).unwrap();
+    run_changeset_hook_on_author_and_message(ctx, &hook, "verify_author", config, author, "msg")
+        .await
+}
+
+#[fbinit::test]
+fn test_verify_author(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let ctx = CoreContext::test_mock(fb);
+        let rejection = |exec: HookExecution| match exec {
+            HookExecution::Rejected(info) => Some(info.description),
+            _ => None,
+        };
+        let config = HookConfig {
+            strings: hashmap! {
+                "exempt_authors".to_string() => "svcscm@fb.com, Build Bot".to_string(),
+            },
+            ..Default::default()
+        };
+
+        // Valid
+        assert_eq!(
+            run_verify_author(&ctx, config.clone(), "Stanislau Hlebik <stash@fb.com>").await,
+            HookExecution::Accepted
+        );
+        assert_eq!(
+            run_verify_author(&ctx, config.clone(), "  jsgf <jsgf@fb.com>  ").await,
+            HookExecution::Accepted
+        );
+
+        // Invalid
+        assert_eq!(
+            rejection(run_verify_author(&ctx, config.clone(), "Mallory <mallory@fb.com>").await),
+            Some("Unknown author")
+        );
+
+        // Exempt, by email or by the whole author when there's no email
+        assert_eq!(
+            run_verify_author(&ctx, config.clone(), "svcscm <SVCSCM@fb.com>").await,
+            HookExecution::Accepted
+        );
+        assert_eq!(
+            run_verify_author(&ctx, config.clone(), "build bot").await,
+            HookExecution::Accepted
+        );
+
+        // Malformed
+        for author in &[
+            "stash@fb.com",
+            "Stanislau Hlebik",
+            "Stanislau Hlebik <>",
+            "",
+        ] {
+            assert_eq!(
+                rejection(run_verify_author(&ctx, config.clone(), author).await),
+                Some("Malformed author"),
+                "{}",
+                author
+            );
+        }
+
+        // Only logged in warn only mode
+        let config = HookConfig {
+            ints: hashmap! {"warn_only".to_string() => 1},
+            ..Default::default()
+        };
+        assert_eq!(
+            run_verify_author(&ctx, config.clone(), "Mallory <mallory@fb.com>").await,
+            HookExecution::Accepted
+        );
+        assert_eq!(
+            run_verify_author(&ctx, config, "Mallory").await,
+            HookExecution::Accepted
+        );
+    });
+}
+
+#[fbinit::test]
+fn test_load_verify_author(fb: FacebookInit) {
+    async_unit::tokio_unit_test(async move {
+        let mut config = default_repo_config();
+        config.hooks = vec![changeset_hook_params("verify_author", &[])];
+
+        let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
+        match load_hooks(fb, &mut hm, config.clone(), &hashset![])
+            .unwrap_err()
+            .downcast::<ErrorKind>()
+        {
+            Ok(ErrorKind::MissingIdentityVerifier(hook_name)) => {
+                assert_eq!(hook_name, "verify_author".to_string());
+            }
+            _ => assert!(false, "Unexpected err type"),
+        };
+
+        let mut hm = hook_manager_many_files_dirs_blobrepo(fb).await;
+        hm.set_identity_verifier(set_identity_verifier());
+        load_hooks(fb, &mut hm, config, &hashset![]).expect("Failed to load hooks");
+    });
+}
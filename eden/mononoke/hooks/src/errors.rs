@@ -8,6 +8,8 @@
 use std::collections::HashSet;
 use thiserror::Error;
 
+use bookmarks::BookmarkName;
+use hooks_content_stores::HookChangesetId;
 pub use mercurial_types::HgChangesetId;
 use metaconfig_types::BookmarkOrRegex;
 pub use mononoke_types::MPath;
@@ -28,7 +30,13 @@ pub enum ErrorKind {
     InvalidPath(MPath),
 
     #[error("Missing file for cs '{0}' path '{1}'")]
-    MissingFile(HgChangesetId, MPath),
+    MissingFile(HookChangesetId, MPath),
+
+    #[error("File '{1}' in cs '{0}' is {2} bytes, larger than the {3} bytes hooks may read")]
+    FileTooLarge(HookChangesetId, MPath, u64, u64),
+
+    #[error("Changeset '{0}' changes more than the {1} files that can be listed at once")]
+    TooManyFiles(HookChangesetId, usize),
 
     #[error("Hook(s) referenced in bookmark {0:#?} do not exist: {1:?}")]
     NoSuchBookmarkHook(BookmarkOrRegex, HashSet<String>),
@@ -36,6 +44,21 @@ pub enum ErrorKind {
     #[error("invalid rust hook: {0}")]
     InvalidRustHook(String),
 
+    #[error("Hook '{0}' needs an identity verifier, but none is set")]
+    MissingIdentityVerifier(String),
+
+    #[error("Hook(s) not configured for bookmark {0}: {1:?}")]
+    HooksNotConfiguredForBookmark(BookmarkName, Vec<String>),
+
     #[error("Disabled hook(s) do(es) not exist: {0:?}")]
     NoSuchHookToDisable(HashSet<String>),
+
+    #[error("Hook '{0}' depends on hook '{1}', which does not exist")]
+    NoSuchHookDependency(String, String),
+
+    #[error("Hook '{0}' depends on hook '{1}', which is not of the same type")]
+    IncompatibleHookDependency(String, String),
+
+    #[error("Hook dependencies form a cycle: {0:?}")]
+    HookDependencyCycle(Vec<String>),
 }
@@ -23,11 +23,16 @@ use crate::facebook::rust_hooks::{
     tp2_symlinks_only::TP2SymlinksOnly, verify_integrity::VerifyIntegrityHook,
     verify_reviewedby_info::VerifyReviewedbyInfo,
 };
+use crate::rust_hooks::{
+    check_commit_message_format::CheckCommitMessageFormat, deny_known_bad_blobs::DenyKnownBadBlobs,
+    limit_filesize_by_extension::LimitFilesizeByExtension, max_message_length::MaxMessageLength,
+    reverts_need_task::RevertsNeedTask, verify_author::VerifyAuthor,
+};
 use crate::{Hook, HookChangeset, HookFile, HookManager};
 use anyhow::Error;
 use fbinit::FacebookInit;
 use metaconfig_types::RepoConfig;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 enum LoadedRustHook {
@@ -43,7 +48,10 @@ pub fn load_hooks(
 ) -> Result<(), Error> {
     let mut hooks_not_disabled = disabled_hooks.clone();
 
-    let mut hook_set = HashSet::new();
+    let all_hooks: HashSet<_> = config.hooks.iter().map(|hook| hook.name.clone()).collect();
+    // Whether each loaded hook is a changeset hook, for checking dependencies
+    let mut is_changeset_hook = HashMap::new();
+    let mut dependencies = HashMap::new();
     for hook in config.hooks {
         use LoadedRustHook::*;
         let name = hook.name;
@@ -64,10 +72,14 @@ pub fn load_hooks(
             "always_fail_changeset" => ChangesetHook(Arc::new(AlwaysFailChangeset::new())),
             "block_cross_repo_commits" => FileHook(Arc::new(BlockCrossRepoCommits::new())),
             "block_empty_commit" => ChangesetHook(Arc::new(BlockEmptyCommit::new())),
+            "check_commit_message_format" => {
+                ChangesetHook(Arc::new(CheckCommitMessageFormat::new(&hook.config)?))
+            }
             "check_nocommit" => FileHook(Arc::new(CheckNocommitHook::new(&hook.config)?)),
             "check_unittests" => ChangesetHook(Arc::new(CheckUnittestsHook::new(&hook.config)?)),
             "conflict_markers" => FileHook(Arc::new(ConflictMarkers::new())),
             "deny_files" => FileHook(Arc::new(DenyFiles::new()?)),
+            "deny_known_bad_blobs" => FileHook(Arc::new(DenyKnownBadBlobs::new(&hook.config)?)),
             "ensure_valid_email" => {
                 ChangesetHook(Arc::new(EnsureValidEmailHook::new(fb, &hook.config)?))
             }
@@ -79,12 +91,26 @@ pub fn load_hooks(
             }
             "limit_commitsize" => ChangesetHook(Arc::new(LimitCommitsize::new(&hook.config))),
             "limit_filesize" => FileHook(Arc::new(LimitFilesize::new(&hook.config))),
+            "limit_filesize_by_extension" => {
+                FileHook(Arc::new(LimitFilesizeByExtension::new(&hook.config)?))
+            }
             "limit_path_length" => FileHook(Arc::new(LimitPathLengthHook::new(&hook.config)?)),
+            "max_message_length" => ChangesetHook(Arc::new(MaxMessageLength::new(&hook.config)?)),
             "no_bad_filenames" => FileHook(Arc::new(NoBadFilenames::new()?)),
             "no_insecure_filenames" => FileHook(Arc::new(NoInsecureFilenames::new()?)),
             "no_questionable_filenames" => FileHook(Arc::new(NoQuestionableFilenames::new()?)),
+            "reverts_need_task" => ChangesetHook(Arc::new(RevertsNeedTask::new(&hook.config)?)),
             "signed_source" => FileHook(Arc::new(SignedSourceHook::new(&hook.config)?)),
             "tp2_symlinks_only" => FileHook(Arc::new(TP2SymlinksOnly::new())),
+            "verify_author" => {
+                let identity_verifier = hook_manager
+                    .identity_verifier()
+                    .ok_or_else(|| ErrorKind::MissingIdentityVerifier(name.clone()))?;
+                ChangesetHook(Arc::new(VerifyAuthor::new(
+                    &hook.config,
+                    identity_verifier,
+                )?))
+            }
             "verify_integrity" => ChangesetHook(Arc::new(VerifyIntegrityHook::new(&hook.config)?)),
             "verify_reviewedby_info" => {
                 ChangesetHook(Arc::new(VerifyReviewedbyInfo::new(&hook.config)?))
@@ -93,38 +119,98 @@ pub fn load_hooks(
         };
 
         match rust_hook {
-            FileHook(rust_hook) => hook_manager.register_file_hook(&name, rust_hook, hook.config),
+            FileHook(rust_hook) => {
+                hook_manager.register_file_hook(&name, rust_hook, hook.config);
+                is_changeset_hook.insert(name.clone(), false);
+            }
             ChangesetHook(rust_hook) => {
-                hook_manager.register_changeset_hook(&name, rust_hook, hook.config)
+                hook_manager.register_changeset_hook(&name, rust_hook, hook.config);
+                is_changeset_hook.insert(name.clone(), true);
             }
         }
 
-        hook_set.insert(name);
+        dependencies.insert(name, hook.depends_on);
     }
 
     if hooks_not_disabled.len() > 0 {
         return Err(ErrorKind::NoSuchHookToDisable(hooks_not_disabled).into());
     }
 
+    // Disabled dependencies are dropped, as they never run.
+    for (name, depends_on) in dependencies.iter_mut() {
+        for dep in depends_on.iter() {
+            if !all_hooks.contains(dep) {
+                return Err(ErrorKind::NoSuchHookDependency(name.clone(), dep.clone()).into());
+            }
+        }
+        depends_on.retain(|dep| !disabled_hooks.contains(dep));
+        for dep in depends_on.iter() {
+            if is_changeset_hook.get(dep) != is_changeset_hook.get(name) {
+                return Err(
+                    ErrorKind::IncompatibleHookDependency(name.clone(), dep.clone()).into(),
+                );
+            }
+        }
+    }
+    if let Some(cycle) = find_dependency_cycle(&dependencies) {
+        return Err(ErrorKind::HookDependencyCycle(cycle).into());
+    }
+    for (name, depends_on) in dependencies {
+        hook_manager.set_hook_dependencies(&name, depends_on);
+    }
+
+    // Catch bookmarks referencing hooks that don't exist.
+    hook_manager.set_strict(true);
     for bookmark_hook in config.bookmarks {
-        let bookmark = bookmark_hook.bookmark;
         let hooks: Vec<_> = bookmark_hook
             .hooks
             .into_iter()
             .filter(|h| !disabled_hooks.contains(h))
             .collect();
-        let bm_hook_set: HashSet<String> = hooks.clone().into_iter().collect();
-        let diff: HashSet<_> = bm_hook_set.difference(&hook_set).collect();
-        if diff.len() != 0 {
-            return Err(ErrorKind::NoSuchBookmarkHook(
-                bookmark,
-                diff.into_iter().cloned().collect(),
-            )
-            .into());
-        } else {
-            hook_manager.set_hooks_for_bookmark(bookmark, hooks);
-        }
+        hook_manager.set_hooks_for_bookmark(bookmark_hook.bookmark, hooks)?;
     }
 
     Ok(())
 }
+
+/// Find a cycle in the hook dependencies, returned as the hooks in the cycle, with the first
+/// one repeated at the end.
+fn find_dependency_cycle(dependencies: &HashMap<String, Vec<String>>) -> Option<Vec<String>> {
+    // Hooks are absent until visited, false while their dependencies are visited, and true
+    // once all of them are.
+    fn visit<'a>(
+        name: &'a str,
+        dependencies: &'a HashMap<String, Vec<String>>,
+        visited: &mut HashMap<&'a str, bool>,
+        path: &mut Vec<&'a str>,
+    ) -> Option<Vec<String>> {
+        match visited.get(name) {
+            Some(true) => return None,
+            Some(false) => {
+                let start = path.iter().position(|n| *n == name)?;
+                let mut cycle: Vec<_> = path[start..].iter().map(|n| n.to_string()).collect();
+                cycle.push(name.to_string());
+                return Some(cycle);
+            }
+            None => {}
+        }
+
+        visited.insert(name, false);
+        path.push(name);
+        for dep in dependencies.get(name).into_iter().flatten() {
+            if let Some(cycle) = visit(dep, dependencies, visited, path) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        visited.insert(name, true);
+        None
+    }
+
+    let mut names: Vec<_> = dependencies.keys().collect();
+    names.sort();
+    let mut visited = HashMap::new();
+    names
+        .into_iter()
+        .find_map(|name| visit(name, dependencies, &mut visited, &mut Vec::new()))
+}
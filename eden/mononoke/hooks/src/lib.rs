@@ -17,8 +17,10 @@
 pub mod errors;
 mod facebook;
 pub mod hook_loader;
-mod phabricator_message_parser;
+pub mod phabricator_message_parser;
+mod rejections;
 pub mod rust_hook;
+pub mod rust_hooks;
 
 use aclchecker::{AclChecker, Identity};
 use anyhow::{bail, Error};
@@ -31,44 +33,90 @@ pub use errors::*;
 use fbinit::FacebookInit;
 use futures::{
     future::{try_join, try_join_all},
-    stream::{futures_unordered::FuturesUnordered, TryStreamExt},
+    stream::{self, futures_unordered::FuturesUnordered, BoxStream, StreamExt, TryStreamExt},
     Future, TryFutureExt,
 };
 use futures_stats::TimedFutureExt;
-use hooks_content_stores::{ChangedFileType, ChangesetStore, FileContentStore};
+use hooks_content_stores::{
+    ChangedFileType, ChangesetStore, FileContentId, FileContentStore, HookChangesetId,
+    PrefetchedFileContentStore,
+};
 use mercurial_types::{FileBytes, HgChangesetId, HgFileNodeId, HgParents, MPath};
 use metaconfig_types::{BookmarkOrRegex, HookBypass, HookConfig, HookManagerParams};
-use mononoke_types::FileType;
+use mononoke_types::{
+    hash::Sha256, BonsaiChangeset, ChangesetId, ContentId, FileType, RepositoryId,
+};
 use regex::Regex;
+pub use rejections::format_rejections;
 use scuba::builder::ServerData;
 use scuba_ext::ScubaSampleBuilder;
-use slog::debug;
+use serde_derive::{Deserialize, Serialize};
+use slog::{debug, warn};
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+/// Number of changed files to run file hooks on at once
+pub const DEFAULT_FILE_HOOKS_CHUNK_SIZE: usize = 1000;
+
+/// Maximum number of files `HookChangeset::files` will hand out in one go. Hooks that need
+/// to look at bigger changesets should use `HookChangeset::files_chunked`.
+pub const MAX_MATERIALIZED_FILES: usize = 100_000;
+
+/// Files larger than this aren't prefetched for hooks, and their text isn't handed out to hooks
+/// (`file_text` returns `None`, `file_text_checked` fails with `ErrorKind::FileTooLarge`),
+/// unless changed with `HookManager::set_hook_max_file_size`
+pub const DEFAULT_HOOK_MAX_FILE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Whether `HookManager::run_hooks_for_bookmark` runs every hook, or stops running hooks for
+/// a changeset once one of them has rejected it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookExecutionMode {
+    /// Run all hooks, so that the results are complete. Used by the tailer.
+    CompleteResults,
+    /// Once a hook rejects a changeset, skip that changeset's hooks that haven't started yet.
+    /// Hooks that are already running are allowed to finish. Skipped hooks are reported as
+    /// `HookExecution::Skipped`.
+    StopAtFirstFailure,
+}
+
 type ChangesetHooks = HashMap<String, (Arc<dyn Hook<HookChangeset>>, HookConfig)>;
 type FileHooks = HashMap<String, (Arc<dyn Hook<HookFile>>, HookConfig)>;
+/// A file changed by a changeset: its path, how it changed and, unless it was deleted, how to
+/// find its content
+type ChangedFile = (String, ChangedFileType, Option<(FileContentId, FileType)>);
 
 /// Manages hooks and allows them to be installed and uninstalled given a name
 /// Knows how to run hooks
 
 pub struct HookManager {
+    repo_name: String,
+    repo_id: RepositoryId,
     changeset_hooks: ChangesetHooks,
     file_hooks: FileHooks,
+    hook_dependencies: HashMap<String, Vec<String>>,
     bookmark_hooks: HashMap<BookmarkName, Vec<String>>,
     regex_hooks: Vec<(Regex, Vec<String>)>,
-    changeset_store: Box<dyn ChangesetStore>,
+    changeset_store: Arc<dyn ChangesetStore>,
     content_store: Arc<dyn FileContentStore>,
     reviewers_acl_checker: Arc<Option<AclChecker>>,
     scuba: ScubaSampleBuilder,
+    file_hooks_chunk_size: usize,
+    hook_max_file_size: u64,
+    strict: bool,
+    identity_verifier: Option<Arc<dyn IdentityVerifier>>,
+    file_hook_filter: Option<Arc<dyn FileHookFilter>>,
+    run_hooks_on_bonsai: bool,
 }
 
 impl HookManager {
     pub fn new(
         fb: FacebookInit,
+        repo_name: String,
+        repo_id: RepositoryId,
         changeset_store: Box<dyn ChangesetStore>,
         content_store: Arc<dyn FileContentStore>,
         hook_manager_params: HookManagerParams,
@@ -103,17 +151,75 @@ impl HookManager {
         };
 
         HookManager {
+            repo_name,
+            repo_id,
             changeset_hooks,
             file_hooks,
+            hook_dependencies: HashMap::new(),
             bookmark_hooks: HashMap::new(),
             regex_hooks: Vec::new(),
-            changeset_store,
+            changeset_store: Arc::from(changeset_store),
             content_store,
             reviewers_acl_checker: Arc::new(reviewers_acl_checker),
             scuba,
+            file_hooks_chunk_size: DEFAULT_FILE_HOOKS_CHUNK_SIZE,
+            hook_max_file_size: DEFAULT_HOOK_MAX_FILE_SIZE,
+            strict: false,
+            identity_verifier: None,
+            file_hook_filter: None,
+            run_hooks_on_bonsai: hook_manager_params.run_hooks_on_bonsai,
+        }
+    }
+
+    fn hook_repo(&self) -> HookRepo {
+        HookRepo {
+            name: self.repo_name.clone(),
+            id: self.repo_id,
         }
     }
 
+    /// Set how many changed files file hooks are run on at once. Changed files are fetched
+    /// and processed in chunks of this size, which bounds memory usage for huge changesets.
+    pub fn set_file_hooks_chunk_size(&mut self, chunk_size: usize) {
+        self.file_hooks_chunk_size = chunk_size.max(1);
+    }
+
+    /// Set the size of the largest file whose content is prefetched and cached for hooks.
+    /// This should match the size limit of the content store, as hooks don't get the content
+    /// of larger files anyway.
+    pub fn set_hook_max_file_size(&mut self, hook_max_file_size: u64) {
+        self.hook_max_file_size = hook_max_file_size;
+    }
+
+    /// In strict mode, `set_hooks_for_bookmark` fails for hooks that haven't been registered,
+    /// so that typos in configs are caught instead of the hooks silently never running.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Set the verifier that hooks checking identities, such as `verify_author`, use. Those
+    /// hooks fail to load without one.
+    pub fn set_identity_verifier(&mut self, identity_verifier: Arc<dyn IdentityVerifier>) {
+        self.identity_verifier = Some(identity_verifier);
+    }
+
+    pub fn identity_verifier(&self) -> Option<Arc<dyn IdentityVerifier>> {
+        self.identity_verifier.clone()
+    }
+
+    /// Set the filter choosing which changed files file hooks run on. Changeset hooks still
+    /// see all the changed files.
+    pub fn set_file_hook_filter(&mut self, file_hook_filter: Arc<dyn FileHookFilter>) {
+        self.file_hook_filter = Some(file_hook_filter);
+    }
+
+    /// Whether pushes should run hooks on the pushed bonsai changesets with
+    /// `run_hooks_for_bookmark_bonsai` rather than on their hg changesets, as set by
+    /// `HookManagerParams::run_hooks_on_bonsai`
+    pub fn runs_hooks_on_bonsai(&self) -> bool {
+        self.run_hooks_on_bonsai
+    }
+
     pub fn register_changeset_hook(
         &mut self,
         hook_name: &str,
@@ -134,7 +240,36 @@ impl HookManager {
             .insert(hook_name.to_string(), (hook, config));
     }
 
-    pub fn set_hooks_for_bookmark(&mut self, bookmark: BookmarkOrRegex, hooks: Vec<String>) {
+    /// Make `hook_name` run after the hooks it depends on, when they run on the same changeset
+    /// (or file, for file hooks). The hook is skipped unless all of them accepted.
+    pub fn set_hook_dependencies(&mut self, hook_name: &str, depends_on: Vec<String>) {
+        if depends_on.is_empty() {
+            self.hook_dependencies.remove(hook_name);
+        } else {
+            self.hook_dependencies
+                .insert(hook_name.to_string(), depends_on);
+        }
+    }
+
+    pub fn set_hooks_for_bookmark(
+        &mut self,
+        bookmark: BookmarkOrRegex,
+        hooks: Vec<String>,
+    ) -> Result<(), Error> {
+        if self.strict {
+            let unknown: HashSet<_> = hooks
+                .iter()
+                .filter(|name| {
+                    !self.changeset_hooks.contains_key(*name)
+                        && !self.file_hooks.contains_key(*name)
+                })
+                .cloned()
+                .collect();
+            if !unknown.is_empty() {
+                return Err(ErrorKind::NoSuchBookmarkHook(bookmark, unknown).into());
+            }
+        }
+
         match bookmark {
             BookmarkOrRegex::Bookmark(bookmark) => {
                 self.bookmark_hooks.insert(bookmark, hooks);
@@ -143,6 +278,37 @@ impl HookManager {
                 self.regex_hooks.push((regex, hooks));
             }
         }
+        Ok(())
+    }
+
+    /// The bookmarks and regexes whose configuration lists `hook_name`, i.e. the bookmarks
+    /// that would run it. Bookmarks come first, sorted, followed by regexes in the order they
+    /// were set.
+    pub fn bookmarks_for_hook(&self, hook_name: &str) -> Vec<BookmarkOrRegex> {
+        let mut bookmarks: Vec<_> = self
+            .bookmark_hooks
+            .iter()
+            .filter(|(_, hooks)| hooks.iter().any(|name| name == hook_name))
+            .map(|(bookmark, _)| bookmark.clone())
+            .collect();
+        bookmarks.sort();
+
+        let regexes = self
+            .regex_hooks
+            .iter()
+            .filter(|(_, hooks)| hooks.iter().any(|name| name == hook_name))
+            .map(|(regex, _)| BookmarkOrRegex::Regex(regex.clone()));
+        bookmarks
+            .into_iter()
+            .map(BookmarkOrRegex::Bookmark)
+            .chain(regexes)
+            .collect()
+    }
+
+    /// The hooks that run for `bookmark`, whether they're configured for it directly or through
+    /// regexes matching it
+    pub fn hooks_for_bookmark_name(&self, bookmark: &BookmarkName) -> Vec<String> {
+        self.hooks_for_bookmark(bookmark)
     }
 
     pub fn changeset_hook_names(&self) -> HashSet<String> {
@@ -159,6 +325,68 @@ impl HookManager {
             .collect()
     }
 
+    /// Only run the hooks in `only_hooks` for `bookmark`, failing if any of them isn't
+    /// configured for it. Hooks configured through a regex are restricted for every bookmark
+    /// that the regex matches.
+    pub fn restrict_hooks_for_bookmark(
+        &mut self,
+        bookmark: &BookmarkName,
+        only_hooks: &HashSet<String>,
+    ) -> Result<(), Error> {
+        let configured: HashSet<_> = self.hooks_for_bookmark(bookmark).into_iter().collect();
+        let mut missing: Vec<_> = only_hooks.difference(&configured).cloned().collect();
+        if !missing.is_empty() {
+            missing.sort();
+            return Err(ErrorKind::HooksNotConfiguredForBookmark(bookmark.clone(), missing).into());
+        }
+
+        if let Some(hooks) = self.bookmark_hooks.get_mut(bookmark) {
+            hooks.retain(|name| only_hooks.contains(name));
+        }
+        let bookmark_str = bookmark.to_string();
+        for (regex, r_hooks) in &mut self.regex_hooks {
+            if regex.is_match(&bookmark_str) {
+                r_hooks.retain(|name| only_hooks.contains(name));
+            }
+        }
+        Ok(())
+    }
+
+    /// The configs of the hooks that run for `bookmark`, by hook name
+    pub fn hook_configs_for_bookmark(
+        &self,
+        bookmark: &BookmarkName,
+    ) -> HashMap<String, HookConfig> {
+        self.hooks_for_bookmark(bookmark)
+            .into_iter()
+            .filter_map(|name| {
+                let config = match self.changeset_hooks.get(&name) {
+                    Some((_, config)) => config,
+                    None => &self.file_hooks.get(&name)?.1,
+                };
+                Some((name, config.clone()))
+            })
+            .collect()
+    }
+
+    /// A changed file of `changeset_id` as file hooks see it, for outcomes that didn't come
+    /// from running hooks, e.g. cached ones. Its content isn't known.
+    pub fn hook_file(
+        &self,
+        path: String,
+        changeset_id: HookChangesetId,
+        ty: ChangedFileType,
+    ) -> HookFile {
+        HookFile::new(
+            path,
+            self.content_store.clone(),
+            changeset_id,
+            ty,
+            None,
+            self.hook_max_file_size,
+        )
+    }
+
     fn hooks_for_bookmark(&self, bookmark: &BookmarkName) -> Vec<String> {
         let mut hooks: Vec<_> = match self.bookmark_hooks.get(bookmark) {
             Some(hooks) => hooks.clone().into_iter().collect(),
@@ -189,12 +417,77 @@ impl HookManager {
             .collect()
     }
 
+    /// Run the hooks for `bookmark` on hg `changesets`. Each comes with the bonsai changeset
+    /// it was generated from, which callers already know, for the outcomes to report. It's None
+    /// for changeset stores without bonsai changesets, like the in-memory store used in tests.
     pub async fn run_hooks_for_bookmark(
         &self,
         ctx: &CoreContext,
-        changesets: impl IntoIterator<Item = HgChangesetId>,
+        changesets: impl IntoIterator<Item = (HgChangesetId, Option<ChangesetId>)>,
+        bookmark: &BookmarkName,
+        maybe_pushvars: Option<&HashMap<String, Bytes>>,
+        mode: HookExecutionMode,
+    ) -> Result<Vec<HookOutcome>, Error> {
+        let (changesets, bonsai_cs_ids): (Vec<_>, HashMap<_, _>) = changesets
+            .into_iter()
+            .map(|(hg_cs_id, bonsai_cs_id)| {
+                let cs_id = HookChangesetId::Hg(hg_cs_id);
+                (cs_id, (cs_id, bonsai_cs_id))
+            })
+            .unzip();
+
+        self.run_hooks_for_changesets(
+            ctx,
+            changesets,
+            bonsai_cs_ids,
+            bookmark,
+            maybe_pushvars,
+            mode,
+        )
+        .await
+    }
+
+    /// Same as `run_hooks_for_bookmark`, but runs the hooks on bonsai changesets, so that
+    /// their hg changesets don't have to be derived first. Hooks see the same changesets as
+    /// they would through the hg changesets, except that changesets and their parents are
+    /// referred to by their bonsai ids.
+    pub async fn run_hooks_for_bookmark_bonsai(
+        &self,
+        ctx: &CoreContext,
+        changesets: impl IntoIterator<Item = ChangesetId>,
+        bookmark: &BookmarkName,
+        maybe_pushvars: Option<&HashMap<String, Bytes>>,
+        mode: HookExecutionMode,
+    ) -> Result<Vec<HookOutcome>, Error> {
+        let changesets: Vec<_> = changesets.into_iter().collect();
+        let bonsai_cs_ids = changesets
+            .iter()
+            .map(|cs_id| (HookChangesetId::Bonsai(*cs_id), Some(*cs_id)))
+            .collect();
+
+        let changesets = changesets
+            .into_iter()
+            .map(HookChangesetId::Bonsai)
+            .collect();
+        self.run_hooks_for_changesets(
+            ctx,
+            changesets,
+            bonsai_cs_ids,
+            bookmark,
+            maybe_pushvars,
+            mode,
+        )
+        .await
+    }
+
+    async fn run_hooks_for_changesets(
+        &self,
+        ctx: &CoreContext,
+        changesets: Vec<HookChangesetId>,
+        bonsai_cs_ids: HashMap<HookChangesetId, Option<ChangesetId>>,
         bookmark: &BookmarkName,
         maybe_pushvars: Option<&HashMap<String, Bytes>>,
+        mode: HookExecutionMode,
     ) -> Result<Vec<HookOutcome>, Error> {
         debug!(ctx.logger(), "Running hooks for bookmark {:?}", bookmark);
 
@@ -205,19 +498,39 @@ impl HookManager {
         let file_futs = FuturesUnordered::new();
 
         for cs_id in changesets {
+            let bonsai_cs_id = bonsai_cs_ids.get(&cs_id).cloned().flatten();
+            // Shared by all hooks for this changeset, so that a rejection by any of them
+            // skips the rest.
+            let rejected = match mode {
+                HookExecutionMode::CompleteResults => None,
+                HookExecutionMode::StopAtFirstFailure => Some(Arc::new(AtomicBool::new(false))),
+            };
+            // Also shared, so that file sizes are fetched once per changeset. Only content
+            // prefetched for the current page of file hooks is kept, so that changeset hooks
+            // reading every file don't hold the whole changeset in memory.
+            let content_store = Arc::new(PrefetchedFileContentStore::new(
+                self.content_store.clone(),
+                self.hook_max_file_size,
+            ));
             cs_futs.push(self.run_changeset_hooks_for_changeset_id(
                 ctx,
-                cs_id.clone(),
+                cs_id,
+                bonsai_cs_id,
+                content_store.clone(),
                 &cs_hooks,
                 maybe_pushvars,
                 bookmark,
+                rejected.clone(),
             ));
             file_futs.push(self.run_file_hooks_for_changeset_id(
                 ctx,
                 cs_id,
+                bonsai_cs_id,
+                content_store,
                 &file_hooks,
                 maybe_pushvars,
                 bookmark,
+                rejected,
             ));
         }
 
@@ -235,10 +548,13 @@ impl HookManager {
     async fn run_changeset_hooks_for_changeset_id(
         &self,
         ctx: &CoreContext,
-        changeset_id: HgChangesetId,
+        changeset_id: HookChangesetId,
+        bonsai_cs_id: Option<ChangesetId>,
+        content_store: Arc<PrefetchedFileContentStore>,
         hooks: &Vec<String>,
         maybe_pushvars: Option<&HashMap<String, Bytes>>,
         bookmark: &BookmarkName,
+        rejected: Option<Arc<AtomicBool>>,
     ) -> Result<Vec<HookOutcome>, Error> {
         debug!(
             ctx.logger(),
@@ -256,20 +572,33 @@ impl HookManager {
             })
             .collect::<Result<_, Error>>()?;
         cloned!(mut self.scuba);
-        scuba.add("hash", changeset_id.to_hex().to_string());
+        add_changeset_id(&mut scuba, changeset_id, bonsai_cs_id);
 
-        let hcs = self.get_hook_changeset(&ctx, changeset_id).await?;
+        let hcs = self
+            .get_hook_changeset(&ctx, changeset_id, content_store)
+            .await?;
         let hooks = HookManager::filter_bypassed_hooks(hooks, &hcs.comments, maybe_pushvars);
 
-        let res = HookManager::run_changeset_hooks_for_changeset(ctx, hcs, hooks, bookmark, scuba)
-            .await?;
+        let res = HookManager::run_hooks_in_dependency_order(
+            ctx,
+            &self.hook_repo(),
+            hcs,
+            hooks,
+            &self.hook_dependencies,
+            bookmark,
+            scuba,
+            rejected,
+        )
+        .await?;
         Ok(res
             .into_iter()
             .map(|(hook_name, exec)| {
                 HookOutcome::ChangesetHook(
                     ChangesetHookExecutionID {
                         cs_id: changeset_id,
+                        bonsai_cs_id,
                         hook_name,
+                        bookmark: bookmark.clone(),
                     },
                     exec,
                 )
@@ -277,22 +606,79 @@ impl HookManager {
             .collect())
     }
 
-    async fn run_changeset_hooks_for_changeset<'book, 'ctx: 'book>(
-        ctx: &'ctx CoreContext,
-        changeset: HookChangeset,
-        hooks: Vec<(String, Arc<dyn Hook<HookChangeset>>, HookConfig)>,
-        bookmark: &'book BookmarkName,
+    /// Run `hooks` on `data`. Hooks without dependencies among `hooks` run concurrently,
+    /// the others start once all of their dependencies have finished, and get their
+    /// outcomes. A hook is skipped if any of its dependencies did not accept. Outcomes are
+    /// returned in the order the hooks finished in, which matches the order of `hooks` if
+    /// there are no dependencies.
+    async fn run_hooks_in_dependency_order<T: Clone>(
+        ctx: &CoreContext,
+        repo: &HookRepo,
+        data: T,
+        hooks: Vec<(String, Arc<dyn Hook<T>>, HookConfig)>,
+        dependencies: &HashMap<String, Vec<String>>,
+        bookmark: &BookmarkName,
         scuba: ScubaSampleBuilder,
+        rejected: Option<Arc<AtomicBool>>,
     ) -> Result<Vec<(String, HookExecution)>, Error> {
-        try_join_all(hooks.into_iter().map(|(hook_name, hook, config)| {
-            HookManager::run_hook(
-                ctx,
-                hook,
-                HookContext::new(hook_name, config, changeset.clone(), bookmark),
-                scuba.clone(),
-            )
-        }))
-        .await
+        let no_dependencies = Vec::new();
+        let dependencies_of =
+            |hook_name: &str| dependencies.get(hook_name).unwrap_or(&no_dependencies);
+        // Dependencies that don't run (e.g. aren't enabled for this bookmark, or are
+        // bypassed) don't hold back the hooks depending on them.
+        let to_run: HashSet<String> = hooks.iter().map(|(name, _, _)| name.clone()).collect();
+
+        let mut outcomes: HashMap<String, HookExecution> = HashMap::new();
+        let mut results = Vec::with_capacity(hooks.len());
+        let mut pending = hooks;
+        while !pending.is_empty() {
+            let (ready, not_ready): (Vec<_>, Vec<_>) =
+                pending.into_iter().partition(|(hook_name, _, _)| {
+                    dependencies_of(hook_name)
+                        .iter()
+                        .all(|dep| !to_run.contains(dep) || outcomes.contains_key(dep))
+                });
+            if ready.is_empty() {
+                // load_hooks rejects dependency cycles, so this shouldn't happen.
+                let names: Vec<_> = not_ready.into_iter().map(|(name, _, _)| name).collect();
+                bail!("Hooks {:?} depend on each other", names);
+            }
+
+            let execs = try_join_all(ready.into_iter().map(|(hook_name, hook, config)| {
+                let dependency_outcomes: HashMap<_, _> = dependencies_of(&hook_name)
+                    .iter()
+                    .filter_map(|dep| outcomes.get(dep).map(|exec| (dep.clone(), exec.clone())))
+                    .collect();
+                let dependency_failed = dependency_outcomes.values().any(|exec| match exec {
+                    HookExecution::Accepted => false,
+                    HookExecution::Rejected(_) | HookExecution::Skipped => true,
+                });
+                let mut hook_context =
+                    HookContext::new(hook_name, config, repo, data.clone(), bookmark);
+                hook_context.dependency_outcomes = dependency_outcomes;
+                cloned!(scuba, rejected);
+                async move {
+                    if dependency_failed {
+                        debug!(
+                            ctx.logger(),
+                            "Skipping hook {:?}, a dependency did not accept",
+                            hook_context.hook_name
+                        );
+                        Ok((hook_context.hook_name, HookExecution::Skipped))
+                    } else {
+                        HookManager::run_hook(ctx, hook, hook_context, scuba, rejected).await
+                    }
+                }
+            }))
+            .await?;
+
+            for (hook_name, exec) in execs {
+                outcomes.insert(hook_name.clone(), exec.clone());
+                results.push((hook_name, exec));
+            }
+            pending = not_ready;
+        }
+        Ok(results)
     }
 
     // File hooks
@@ -300,10 +686,13 @@ impl HookManager {
     async fn run_file_hooks_for_changeset_id(
         &self,
         ctx: &CoreContext,
-        changeset_id: HgChangesetId,
+        changeset_id: HookChangesetId,
+        bonsai_cs_id: Option<ChangesetId>,
+        content_store: Arc<PrefetchedFileContentStore>,
         hooks: &Vec<String>,
         maybe_pushvars: Option<&HashMap<String, Bytes>>,
         bookmark: &BookmarkName,
+        rejected: Option<Arc<AtomicBool>>,
     ) -> Result<Vec<HookOutcome>, Error> {
         debug!(
             ctx.logger(),
@@ -320,38 +709,126 @@ impl HookManager {
                 Ok((hook_name.clone(), hook.clone()))
             })
             .collect::<Result<_, Error>>()?;
+        if hooks.is_empty() {
+            return Ok(vec![]);
+        }
         cloned!(mut self.scuba);
-        scuba.add("hash", changeset_id.to_hex().to_string());
-
-        let hcs = self.get_hook_changeset(ctx, changeset_id).await?;
-        let hooks = HookManager::filter_bypassed_hooks(hooks, &hcs.comments, maybe_pushvars);
+        add_changeset_id(&mut scuba, changeset_id, bonsai_cs_id);
+
+        let comments = match changeset_id {
+            HookChangesetId::Hg(hg_cs_id) => {
+                let changeset = self
+                    .changeset_store
+                    .get_changeset_by_changesetid(ctx, hg_cs_id)
+                    .await?;
+                str::from_utf8(changeset.comments())?.to_string()
+            }
+            HookChangesetId::Bonsai(bonsai_cs_id) => self
+                .changeset_store
+                .get_bonsai_changeset(ctx, bonsai_cs_id)
+                .await?
+                .message()
+                .to_string(),
+        };
+        // Fetch and process changed files one chunk at a time, so that the number of
+        // outstanding futures (and HookFiles in memory) is bounded by the chunk size.
+        let loader = self.changed_files_loader(changeset_id);
+        let mut pages = loader.pages(ctx, self.file_hooks_chunk_size);
+        let hooks = HookManager::filter_bypassed_hooks(hooks, &comments, maybe_pushvars);
+        let prefetch = hooks.iter().any(|(_, hook, _)| hook.reads_file_text());
+        let mut outcomes = Vec::new();
+        while let Some(page) = pages.try_next().await? {
+            let page: Vec<_> = match &self.file_hook_filter {
+                Some(filter) => page
+                    .into_iter()
+                    .filter(|(path, _, _)| !filter.skips(changeset_id, path))
+                    .collect(),
+                None => page,
+            };
+            let page_ids: Vec<_> = page
+                .iter()
+                .filter_map(|(_, ty, hash_and_type)| match ty {
+                    ChangedFileType::Added | ChangedFileType::Modified => {
+                        hash_and_type.as_ref().map(|(id, _)| *id)
+                    }
+                    ChangedFileType::Deleted => None,
+                })
+                .collect();
+            // Fetch the content of the files up front if hooks read it, so that each hook
+            // reading it is served from memory rather than fetching it again.
+            if prefetch {
+                if let Err(e) = content_store.prefetch(ctx, page_ids.clone()).await {
+                    // Not fatal: hooks that need the content will fetch it, and report the error.
+                    warn!(
+                        ctx.logger(),
+                        "Failed to prefetch file content for hooks on {}: {:#}", changeset_id, e
+                    );
+                }
+            }
 
-        HookManager::run_file_hooks_for_changeset(ctx, changeset_id, &hcs, hooks, bookmark, scuba)
-            .await
+            let files: Vec<_> = page
+                .into_iter()
+                .map(|(path, ty, hash_and_type)| {
+                    HookFile::new(
+                        path,
+                        content_store.clone(),
+                        changeset_id,
+                        ty,
+                        hash_and_type,
+                        self.hook_max_file_size,
+                    )
+                })
+                .collect();
+            outcomes.extend(
+                HookManager::run_file_hooks_for_files(
+                    ctx,
+                    self.hook_repo(),
+                    changeset_id,
+                    bonsai_cs_id,
+                    files,
+                    hooks.clone(),
+                    &self.hook_dependencies,
+                    bookmark,
+                    scuba.clone(),
+                    rejected.clone(),
+                )
+                .await?,
+            );
+            // Only one page of content is kept in memory at a time.
+            content_store.evict_texts(page_ids);
+        }
+        Ok(outcomes)
     }
 
-    fn run_file_hooks_for_changeset<'cs, 'book: 'cs, 'ctx: 'cs>(
+    fn run_file_hooks_for_files<'book, 'ctx: 'book>(
         ctx: &'ctx CoreContext,
-        changeset_id: HgChangesetId,
-        changeset: &'cs HookChangeset,
+        repo: HookRepo,
+        changeset_id: HookChangesetId,
+        bonsai_cs_id: Option<ChangesetId>,
+        files: Vec<HookFile>,
         hooks: Vec<(String, Arc<dyn Hook<HookFile>>, HookConfig)>,
+        dependencies: &'book HashMap<String, Vec<String>>,
         bookmark: &'book BookmarkName,
         scuba: ScubaSampleBuilder,
-    ) -> impl Future<Output = Result<Vec<HookOutcome>, Error>> + 'cs {
-        let v: Vec<_> = changeset
-            .files
-            .iter()
+        rejected: Option<Arc<AtomicBool>>,
+    ) -> impl Future<Output = Result<Vec<HookOutcome>, Error>> + 'book {
+        let v: Vec<_> = files
+            .into_iter()
             // Do not run file hooks for deleted files
             .filter_map(move |file| {
                 match file.ty {
                     ChangedFileType::Added | ChangedFileType::Modified => Some(
                         HookManager::run_file_hooks(
                             ctx,
+                            repo.clone(),
                             changeset_id,
-                            file.clone(),
+                            bonsai_cs_id,
+                            file,
                             hooks.clone(),
+                            dependencies,
                             bookmark,
                             scuba.clone(),
+                            rejected.clone(),
                         )
                     ),
                     ChangedFileType::Deleted => None,
@@ -363,35 +840,43 @@ impl HookManager {
 
     async fn run_file_hooks<'book, 'ctx: 'book>(
         ctx: &'ctx CoreContext,
-        cs_id: HgChangesetId,
+        repo: HookRepo,
+        cs_id: HookChangesetId,
+        bonsai_cs_id: Option<ChangesetId>,
         file: HookFile,
         hooks: Vec<(String, Arc<dyn Hook<HookFile>>, HookConfig)>,
+        dependencies: &'book HashMap<String, Vec<String>>,
         bookmark: &'book BookmarkName,
-        scuba: ScubaSampleBuilder,
+        mut scuba: ScubaSampleBuilder,
+        rejected: Option<Arc<AtomicBool>>,
     ) -> Result<Vec<HookOutcome>, Error> {
-        let hook_futs = hooks.into_iter().map(move |(hook_name, hook, config)| {
-            let hook_context =
-                HookContext::new(hook_name.to_string(), config, file.clone(), bookmark);
-
-            cloned!(mut scuba);
-            scuba.add("hash", cs_id.to_hex().to_string());
-
-            HookManager::run_hook(ctx, hook, hook_context, scuba).map_ok({
-                cloned!(file, bookmark);
-                move |(hook_name, exec)| {
-                    HookOutcome::FileHook(
-                        FileHookExecutionID {
-                            cs_id,
-                            hook_name,
-                            file,
-                            bookmark,
-                        },
-                        exec,
-                    )
-                }
+        scuba.add("path", file.path.clone());
+        let res = HookManager::run_hooks_in_dependency_order(
+            ctx,
+            &repo,
+            file.clone(),
+            hooks,
+            dependencies,
+            bookmark,
+            scuba,
+            rejected,
+        )
+        .await?;
+        Ok(res
+            .into_iter()
+            .map(|(hook_name, exec)| {
+                HookOutcome::FileHook(
+                    FileHookExecutionID {
+                        cs_id,
+                        bonsai_cs_id,
+                        hook_name,
+                        file: file.clone(),
+                        bookmark: bookmark.clone(),
+                    },
+                    exec,
+                )
             })
-        });
-        try_join_all(hook_futs).await
+            .collect())
     }
 
     async fn run_hook<T: Clone>(
@@ -399,8 +884,15 @@ impl HookManager {
         hook: Arc<dyn Hook<T>>,
         hook_context: HookContext<T>,
         mut scuba: ScubaSampleBuilder,
+        rejected: Option<Arc<AtomicBool>>,
     ) -> Result<(String, HookExecution), Error> {
         let hook_name = hook_context.hook_name.clone();
+        if let Some(rejected) = &rejected {
+            if rejected.load(Ordering::Relaxed) {
+                debug!(ctx.logger(), "Skipping hook {:?}", hook_name);
+                return Ok((hook_name, HookExecution::Skipped));
+            }
+        }
         debug!(ctx.logger(), "Running hook {:?}", hook_context.hook_name);
 
         // Try getting the source hostname, otherwise use the unix name.
@@ -414,12 +906,27 @@ impl HookManager {
             scuba.add("user", user);
         }
 
-        scuba.add("hook", hook_name.clone());
+        scuba
+            .add("hook", hook_name.clone())
+            .add("bookmark", hook_context.bookmark.to_string());
 
         let (stats, result) = hook.run(ctx, hook_context).timed().await;
 
-        if let Err(e) = result.as_ref() {
-            scuba.add("stderr", e.to_string());
+        match result.as_ref() {
+            Err(e) => {
+                scuba.add("stderr", e.to_string());
+            }
+            Ok(exec) => {
+                add_execution(&mut scuba, exec);
+                match exec {
+                    HookExecution::Rejected(info) if !info.suggestions.is_empty() => {
+                        if let Ok(suggestions) = serde_json::to_string(&info.suggestions) {
+                            scuba.add("suggestions", suggestions);
+                        }
+                    }
+                    _ => {}
+                }
+            }
         }
 
         let elapsed = stats.completion_time.as_millis() as i64;
@@ -432,47 +939,61 @@ impl HookManager {
             .log();
 
         let he = result.map_err(|e| e.context(format!("while executing hook {}", hook_name)))?;
+        if let (Some(rejected), HookExecution::Rejected(_)) = (&rejected, &he) {
+            rejected.store(true, Ordering::Relaxed);
+        }
         Ok((hook_name, he))
     }
 
     async fn get_hook_changeset(
         &self,
         ctx: &CoreContext,
-        changeset_id: HgChangesetId,
+        changeset_id: HookChangesetId,
+        content_store: Arc<dyn FileContentStore>,
     ) -> Result<HookChangeset, Error> {
-        let content_store = self.content_store.clone();
-        let hg_changeset = self
-            .changeset_store
-            .get_changeset_by_changesetid(ctx, changeset_id);
-        let changed_files = self.changeset_store.get_changed_files(ctx, changeset_id);
-        let reviewers_acl_checker = self.reviewers_acl_checker.clone();
-
-        let (changeset, changed_files) = try_join(hg_changeset, changed_files).await?;
-
-        let author = str::from_utf8(changeset.user())?.into();
-        let files = changed_files
-            .into_iter()
-            .map(|(path, ty, hash_and_type)| {
-                HookFile::new(
-                    path,
-                    content_store.clone(),
-                    changeset_id.clone(),
-                    ty,
-                    hash_and_type,
+        let (author, comments, parents) = match changeset_id {
+            HookChangesetId::Hg(hg_cs_id) => {
+                let changeset = self
+                    .changeset_store
+                    .get_changeset_by_changesetid(ctx, hg_cs_id)
+                    .await?;
+                (
+                    str::from_utf8(changeset.user())?.to_string(),
+                    str::from_utf8(changeset.comments())?.to_string(),
+                    HookChangesetParents::from(changeset.parents()),
                 )
-            })
-            .collect();
-        let comments = str::from_utf8(changeset.comments())?.into();
-        let parents = HookChangesetParents::from(changeset.parents());
-        Ok(HookChangeset::new(
+            }
+            HookChangesetId::Bonsai(bonsai_cs_id) => {
+                let changeset = self
+                    .changeset_store
+                    .get_bonsai_changeset(ctx, bonsai_cs_id)
+                    .await?;
+                (
+                    changeset.author().to_string(),
+                    changeset.message().to_string(),
+                    HookChangesetParents::from(&changeset),
+                )
+            }
+        };
+
+        // The changed files are only loaded when hooks ask for them.
+        Ok(HookChangeset {
             author,
-            files,
+            files: HookChangesetFiles::Paged(self.changed_files_loader(changeset_id)),
             comments,
             parents,
-            changeset_id,
             content_store,
-            reviewers_acl_checker,
-        ))
+            changeset_id,
+            reviewers_acl_checker: self.reviewers_acl_checker.clone(),
+            max_file_size: self.hook_max_file_size,
+        })
+    }
+
+    fn changed_files_loader(&self, changeset_id: HookChangesetId) -> ChangedFilesLoader {
+        ChangedFilesLoader {
+            changeset_store: self.changeset_store.clone(),
+            changeset_id,
+        }
     }
 
     fn filter_bypassed_hooks<T: Clone>(
@@ -533,6 +1054,67 @@ where
         ctx: &'b CoreContext,
         hook_context: HookContext<T>,
     ) -> Result<HookExecution, Error>;
+
+    /// Whether the hook reads the text of files. File content is only prefetched for hooks
+    /// that do, so hooks that only look at file sizes or hashes should return false.
+    fn reads_file_text(&self) -> bool {
+        true
+    }
+}
+
+/// Checks identities against a directory of valid users and bot accounts, such as LDAP
+#[async_trait]
+pub trait IdentityVerifier: Send + Sync {
+    /// The identities among `emails` that aren't valid. Identities are checked in batches, as
+    /// each lookup in the directory is usually a remote call.
+    async fn invalid_identities(
+        &self,
+        ctx: &CoreContext,
+        emails: &[String],
+    ) -> Result<HashSet<String>, Error>;
+}
+
+/// Chooses which changed files file hooks run on, e.g. to leave out generated files
+pub trait FileHookFilter: Send + Sync {
+    /// Whether file hooks should not run on `path`, changed by `changeset_id`
+    fn skips(&self, changeset_id: HookChangesetId, path: &str) -> bool;
+}
+
+/// Loads the files changed by a changeset from the changeset store a page at a time, so that
+/// changesets changing many files are never held in memory all at once.
+#[derive(Clone)]
+struct ChangedFilesLoader {
+    changeset_store: Arc<dyn ChangesetStore>,
+    changeset_id: HookChangesetId,
+}
+
+impl ChangedFilesLoader {
+    fn pages<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        page_size: usize,
+    ) -> BoxStream<'a, Result<Vec<ChangedFile>, Error>> {
+        let page_size = page_size.max(1);
+        match self.changeset_id {
+            HookChangesetId::Hg(hg_cs_id) => self
+                .changeset_store
+                .get_changed_files_paged(ctx, hg_cs_id, page_size)
+                .map_ok(|page| page.into_iter().map(hg_changed_file).collect())
+                .boxed(),
+            HookChangesetId::Bonsai(bonsai_cs_id) => self
+                .changeset_store
+                .get_bonsai_changed_files_paged(ctx, bonsai_cs_id, page_size)
+                .map_ok(|page| page.into_iter().map(bonsai_changed_file).collect())
+                .boxed(),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum HookChangesetFiles {
+    /// Files listed up front, by `HookChangeset::new`
+    Listed(Arc<Vec<HookFile>>),
+    Paged(ChangedFilesLoader),
 }
 
 /// Represents a changeset - more user friendly than the blob changeset
@@ -540,20 +1122,21 @@ where
 #[derive(Clone)]
 pub struct HookChangeset {
     pub author: String,
-    pub files: Vec<HookFile>,
+    files: HookChangesetFiles,
     pub comments: String,
     pub parents: HookChangesetParents,
     content_store: Arc<dyn FileContentStore>,
-    changeset_id: HgChangesetId,
+    changeset_id: HookChangesetId,
     reviewers_acl_checker: Arc<Option<AclChecker>>,
+    max_file_size: u64,
 }
 
 impl fmt::Debug for HookChangeset {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "HookChangeset changeset_id: {:?} files: {:?}, comments: {:?}",
-            self.changeset_id, self.files, self.comments
+            "HookChangeset changeset_id: {:?}, comments: {:?}",
+            self.changeset_id, self.comments
         )
     }
 }
@@ -568,9 +1151,10 @@ impl PartialEq for HookChangeset {
 pub struct HookFile {
     pub path: String,
     content_store: Arc<dyn FileContentStore>,
-    changeset_id: HgChangesetId,
+    changeset_id: HookChangesetId,
     ty: ChangedFileType,
-    hash_and_type: Option<(HgFileNodeId, FileType)>,
+    hash_and_type: Option<(FileContentId, FileType)>,
+    max_file_size: u64,
 }
 
 impl fmt::Debug for HookFile {
@@ -602,9 +1186,10 @@ impl HookFile {
     pub fn new(
         path: String,
         content_store: Arc<dyn FileContentStore>,
-        changeset_id: HgChangesetId,
+        changeset_id: HookChangesetId,
         ty: ChangedFileType,
-        hash_and_type: Option<(HgFileNodeId, FileType)>,
+        hash_and_type: Option<(FileContentId, FileType)>,
+        max_file_size: u64,
     ) -> HookFile {
         HookFile {
             path,
@@ -612,6 +1197,7 @@ impl HookFile {
             changeset_id,
             ty,
             hash_and_type,
+            max_file_size,
         }
     }
 
@@ -623,10 +1209,47 @@ impl HookFile {
         }
     }
 
+    /// Canonical id of the file content
+    pub async fn content_id(&self, ctx: &CoreContext) -> Result<ContentId, Error> {
+        let path = MPath::new(self.path.as_bytes())?;
+        match self.hash_and_type {
+            Some((entry_id, _)) => self.content_store.get_file_content_id(ctx, entry_id).await,
+            None => Err(ErrorKind::MissingFile(self.changeset_id, path.into()).into()),
+        }
+    }
+
+    /// SHA-256 of the file content, looked up without fetching the content where the content
+    /// store allows it.
+    pub async fn content_sha256(&self, ctx: &CoreContext) -> Result<Sha256, Error> {
+        let path = MPath::new(self.path.as_bytes())?;
+        match self.hash_and_type {
+            Some((entry_id, _)) => self.content_store.get_file_sha256(ctx, entry_id).await,
+            None => Err(ErrorKind::MissingFile(self.changeset_id, path.into()).into()),
+        }
+    }
+
+    /// Returns `None` for files larger than the hook max file size, without fetching their
+    /// content.
     pub async fn file_text(&self, ctx: &CoreContext) -> Result<Option<FileBytes>, Error> {
+        too_large_as_none(self.file_text_checked(ctx).await)
+    }
+
+    /// Same as `file_text`, but fails with `ErrorKind::FileTooLarge` for files larger than the
+    /// hook max file size, for hooks that treat those files specially.
+    pub async fn file_text_checked(&self, ctx: &CoreContext) -> Result<Option<FileBytes>, Error> {
         let path = MPath::new(self.path.as_bytes())?;
         match self.hash_and_type {
-            Some((id, _)) => self.content_store.get_file_text(ctx, id).await,
+            Some((id, _)) => {
+                get_file_text_within_limit(
+                    ctx,
+                    self.content_store.as_ref(),
+                    self.changeset_id,
+                    path,
+                    id,
+                    self.max_file_size,
+                )
+                .await
+            }
             None => Err(ErrorKind::MissingFile(self.changeset_id, path.into()).into()),
         }
     }
@@ -650,38 +1273,148 @@ impl HookChangeset {
         files: Vec<HookFile>,
         comments: String,
         parents: HookChangesetParents,
-        changeset_id: HgChangesetId,
+        changeset_id: HookChangesetId,
         content_store: Arc<dyn FileContentStore>,
         reviewers_acl_checker: Arc<Option<AclChecker>>,
+        max_file_size: u64,
     ) -> HookChangeset {
         HookChangeset {
             author,
-            files,
+            files: HookChangesetFiles::Listed(Arc::new(files)),
             comments,
             parents,
             content_store,
             changeset_id,
             reviewers_acl_checker,
+            max_file_size,
+        }
+    }
+
+    /// Number of files changed in this changeset. The changed files are paged through to
+    /// count them.
+    pub async fn files_count(&self, ctx: &CoreContext) -> Result<usize, Error> {
+        self.files_chunked(ctx, DEFAULT_FILE_HOOKS_CHUNK_SIZE)
+            .try_fold(0, |count, chunk| async move { Ok(count + chunk.len()) })
+            .await
+    }
+
+    /// All the files changed in this changeset. Fails for changesets with more than
+    /// `MAX_MATERIALIZED_FILES` changed files, use `files_chunked` for those.
+    pub async fn files(&self, ctx: &CoreContext) -> Result<Vec<HookFile>, Error> {
+        let mut files = Vec::new();
+        let mut chunks = self.files_chunked(ctx, DEFAULT_FILE_HOOKS_CHUNK_SIZE);
+        while let Some(chunk) = chunks.try_next().await? {
+            files.extend(chunk);
+            if files.len() > MAX_MATERIALIZED_FILES {
+                return Err(
+                    ErrorKind::TooManyFiles(self.changeset_id, MAX_MATERIALIZED_FILES).into(),
+                );
+            }
+        }
+        Ok(files)
+    }
+
+    /// The files changed in this changeset, in chunks of at most `chunk_size` files. Chunks
+    /// are loaded as the stream is polled.
+    pub fn files_chunked<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        chunk_size: usize,
+    ) -> BoxStream<'a, Result<Vec<HookFile>, Error>> {
+        match &self.files {
+            HookChangesetFiles::Listed(files) => {
+                let chunks: Vec<_> = files
+                    .chunks(chunk_size.max(1))
+                    .map(|chunk| Ok(chunk.to_vec()))
+                    .collect();
+                stream::iter(chunks).boxed()
+            }
+            HookChangesetFiles::Paged(loader) => loader
+                .pages(ctx, chunk_size)
+                .map_ok(move |page| {
+                    page.into_iter()
+                        .map(|(path, ty, hash_and_type)| {
+                            HookFile::new(
+                                path,
+                                self.content_store.clone(),
+                                self.changeset_id,
+                                ty,
+                                hash_and_type,
+                                self.max_file_size,
+                            )
+                        })
+                        .collect()
+                })
+                .boxed(),
         }
     }
 
+    /// Returns `None` for files larger than the hook max file size, without fetching their
+    /// content.
     pub async fn file_text(
         &self,
         ctx: &CoreContext,
         path: String,
+    ) -> Result<Option<FileBytes>, Error> {
+        too_large_as_none(self.file_text_checked(ctx, path).await)
+    }
+
+    /// Same as `file_text`, but fails with `ErrorKind::FileTooLarge` for files larger than the
+    /// hook max file size, for hooks that treat those files specially.
+    pub async fn file_text_checked(
+        &self,
+        ctx: &CoreContext,
+        path: String,
     ) -> Result<Option<FileBytes>, Error> {
         let path = MPath::new(path.as_bytes())?;
         let id = self
             .content_store
-            .resolve_path(ctx, self.changeset_id, path)
+            .resolve_path(ctx, self.changeset_id, path.clone())
             .await?;
         match id {
-            Some(id) => self.content_store.get_file_text(ctx, id).await,
+            Some(id) => {
+                get_file_text_within_limit(
+                    ctx,
+                    self.content_store.as_ref(),
+                    self.changeset_id,
+                    path,
+                    id,
+                    self.max_file_size,
+                )
+                .await
+            }
             None => Ok(None),
         }
     }
 }
 
+/// Check the size of the file first, so that hooks never load files larger than
+/// `max_file_size` into memory.
+async fn get_file_text_within_limit(
+    ctx: &CoreContext,
+    content_store: &dyn FileContentStore,
+    changeset_id: HookChangesetId,
+    path: MPath,
+    id: FileContentId,
+    max_file_size: u64,
+) -> Result<Option<FileBytes>, Error> {
+    let size = content_store.get_file_size(ctx, id).await?;
+    if size > max_file_size {
+        return Err(ErrorKind::FileTooLarge(changeset_id, path.into(), size, max_file_size).into());
+    }
+    content_store.get_file_text(ctx, id).await
+}
+
+fn too_large_as_none(res: Result<Option<FileBytes>, Error>) -> Result<Option<FileBytes>, Error> {
+    match res {
+        Err(e) => match e.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::FileTooLarge(..)) => Ok(None),
+            _ => Err(e),
+        },
+        res => res,
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum HookOutcome {
     ChangesetHook(ChangesetHookExecutionID, HookExecution),
@@ -692,13 +1425,19 @@ impl fmt::Display for HookOutcome {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             HookOutcome::ChangesetHook(id, exec) => {
-                write!(f, "{} for {}: {}", id.hook_name, id.cs_id, exec)
+                write!(f, "{} for {}", id.hook_name, id.cs_id)?;
+                if let (HookChangesetId::Hg(_), Some(bonsai_cs_id)) = (id.cs_id, id.bonsai_cs_id) {
+                    write!(f, " (bonsai {})", bonsai_cs_id)?;
+                }
+                write!(f, ": {}", exec)
+            }
+            HookOutcome::FileHook(id, exec) => {
+                write!(f, "{} for {}", id.hook_name, id.cs_id)?;
+                if let (HookChangesetId::Hg(_), Some(bonsai_cs_id)) = (id.cs_id, id.bonsai_cs_id) {
+                    write!(f, " (bonsai {})", bonsai_cs_id)?;
+                }
+                write!(f, " file {}: {}", id.file.path, exec)
             }
-            HookOutcome::FileHook(id, exec) => write!(
-                f,
-                "{} for {} file {}: {}",
-                id.hook_name, id.cs_id, id.file.path, exec
-            ),
         }
     }
 }
@@ -710,7 +1449,7 @@ impl HookOutcome {
             HookOutcome::FileHook(_, exec) => exec,
         };
         match exec {
-            HookExecution::Accepted => false,
+            HookExecution::Accepted | HookExecution::Skipped => false,
             HookExecution::Rejected(_) => true,
         }
     }
@@ -729,25 +1468,63 @@ impl HookOutcome {
         }
     }
 
-    pub fn get_cs_id(&self) -> HgChangesetId {
+    pub fn get_cs_id(&self) -> HookChangesetId {
         match self {
             HookOutcome::ChangesetHook(id, _) => id.cs_id,
             HookOutcome::FileHook(id, _) => id.cs_id,
         }
     }
 
+    /// The bonsai changeset the hook ran against, also for hooks run on hg changesets. None if
+    /// the changeset store has no bonsai changesets, like the in-memory store used in tests.
+    pub fn get_bonsai_cs_id(&self) -> Option<ChangesetId> {
+        match self {
+            HookOutcome::ChangesetHook(id, _) => id.bonsai_cs_id,
+            HookOutcome::FileHook(id, _) => id.bonsai_cs_id,
+        }
+    }
+
     pub fn get_execution(&self) -> &HookExecution {
         match self {
             HookOutcome::ChangesetHook(_, exec) => exec,
             HookOutcome::FileHook(_, exec) => exec,
         }
     }
+
+    pub fn get_bookmark(&self) -> &BookmarkName {
+        match self {
+            HookOutcome::ChangesetHook(id, _) => &id.bookmark,
+            HookOutcome::FileHook(id, _) => &id.bookmark,
+        }
+    }
+
+    /// Log a sample for this outcome to a copy of `scuba`, with the standard hook outcome
+    /// columns added: `hook`, `bookmark`, `hash` (hg changesets only), `bonsai_hash` (if
+    /// known), `path` (file hooks only), `outcome`, `code` (0 for accepted, 1 for rejected, 2
+    /// for skipped), and the `reason` for rejections. The samples `HookManager` logs for each
+    /// hook it runs have the same columns.
+    pub fn log_to_scuba(&self, scuba: &ScubaSampleBuilder) {
+        let mut scuba = scuba.clone();
+        scuba
+            .add("hook", self.get_hook_name())
+            .add("bookmark", self.get_bookmark().to_string());
+        add_changeset_id(&mut scuba, self.get_cs_id(), self.get_bonsai_cs_id());
+        if let Some(path) = self.get_file_path() {
+            scuba.add("path", path);
+        }
+        add_execution(&mut scuba, self.get_execution());
+        scuba.log();
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum HookExecution {
     Accepted,
     Rejected(HookRejectionInfo),
+    /// The hook wasn't run, either because an earlier hook rejected the changeset in
+    /// `HookExecutionMode::StopAtFirstFailure`, or because one of its dependencies did not
+    /// accept.
+    Skipped,
 }
 
 impl From<HookOutcome> for HookExecution {
@@ -763,7 +1540,14 @@ impl fmt::Display for HookExecution {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             HookExecution::Accepted => write!(f, "Accepted"),
-            HookExecution::Rejected(reason) => write!(f, "Rejected: {}", reason.long_description),
+            HookExecution::Skipped => write!(f, "Skipped"),
+            HookExecution::Rejected(reason) => {
+                write!(f, "Rejected: {}", reason.long_description)?;
+                for suggestion in &reason.suggestions {
+                    write!(f, "\n  * {}", suggestion)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -775,6 +1559,10 @@ pub struct HookRejectionInfo {
     pub description: &'static str,
     /// A full explanation of what went wrong, suitable for presenting to the user (should include guidance for fixing this failure, where possible)
     pub long_description: String,
+    /// Exact remediations the user can apply to fix this failure
+    pub suggestions: Vec<HookSuggestion>,
+    /// A link to the policy this hook enforces
+    pub policy_url: Option<String>,
 }
 
 impl HookRejectionInfo {
@@ -795,13 +1583,77 @@ impl HookRejectionInfo {
         Self {
             description,
             long_description,
+            suggestions: Vec::new(),
+            policy_url: None,
         }
     }
+
+    /// Add a suggested fix for this failure
+    pub fn with_suggestion(mut self, suggestion: HookSuggestion) -> Self {
+        self.suggestions.push(suggestion);
+        self
+    }
+
+    /// Point to the policy this hook enforces
+    pub fn with_policy_url(mut self, policy_url: impl Into<String>) -> Self {
+        self.policy_url = Some(policy_url.into());
+        self
+    }
+}
+
+/// A fix for a hook failure that can be displayed to the user
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HookSuggestion {
+    /// What the fix does
+    pub title: String,
+    /// A command that applies the fix
+    pub command: Option<String>,
+    /// The paths that the fix applies to
+    pub paths: Vec<String>,
+}
+
+impl HookSuggestion {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            command: None,
+            paths: Vec::new(),
+        }
+    }
+
+    pub fn with_command(mut self, command: impl Into<String>) -> Self {
+        self.command = Some(command.into());
+        self
+    }
+
+    pub fn with_paths<I, S>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.paths.extend(paths.into_iter().map(Into::into));
+        self
+    }
+}
+
+impl fmt::Display for HookSuggestion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.title)?;
+        if let Some(command) = &self.command {
+            write!(f, ": run `{}`", command)?;
+        }
+        if !self.paths.is_empty() {
+            write!(f, " ({})", self.paths.join(", "))?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Hash, Eq)]
 pub struct FileHookExecutionID {
-    pub cs_id: HgChangesetId,
+    pub cs_id: HookChangesetId,
+    /// None if the changeset store has no bonsai changesets
+    pub bonsai_cs_id: Option<ChangesetId>,
     pub hook_name: String,
     pub file: HookFile,
     pub bookmark: BookmarkName,
@@ -809,8 +1661,11 @@ pub struct FileHookExecutionID {
 
 #[derive(Clone, Debug, PartialEq, Hash, Eq)]
 pub struct ChangesetHookExecutionID {
-    pub cs_id: HgChangesetId,
+    pub cs_id: HookChangesetId,
+    /// None if the changeset store has no bonsai changesets
+    pub bonsai_cs_id: Option<ChangesetId>,
     pub hook_name: String,
+    pub bookmark: BookmarkName,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -832,6 +1687,65 @@ impl From<HgParents> for HookChangesetParents {
     }
 }
 
+/// Only the first two parents of bonsai changesets with more parents are kept.
+impl From<&BonsaiChangeset> for HookChangesetParents {
+    fn from(changeset: &BonsaiChangeset) -> Self {
+        let mut parents = changeset.parents().map(|parent| parent.to_string());
+        match (parents.next(), parents.next()) {
+            (None, _) => HookChangesetParents::None,
+            (Some(p1), None) => HookChangesetParents::One(p1),
+            (Some(p1), Some(p2)) => HookChangesetParents::Two(p1, p2),
+        }
+    }
+}
+
+fn hg_changed_file(
+    (path, ty, hash_and_type): (String, ChangedFileType, Option<(HgFileNodeId, FileType)>),
+) -> ChangedFile {
+    let hash_and_type = hash_and_type.map(|(filenode, file_type)| (filenode.into(), file_type));
+    (path, ty, hash_and_type)
+}
+
+fn bonsai_changed_file(
+    (path, ty, hash_and_type): (String, ChangedFileType, Option<(ContentId, FileType)>),
+) -> ChangedFile {
+    let hash_and_type = hash_and_type.map(|(content_id, file_type)| (content_id.into(), file_type));
+    (path, ty, hash_and_type)
+}
+
+/// Identify the changeset hooks run on in `scuba`: by `hash` for hg changesets, and by
+/// `bonsai_hash` for bonsai changesets, or hg changesets whose bonsai changeset is known.
+fn add_changeset_id(
+    scuba: &mut ScubaSampleBuilder,
+    changeset_id: HookChangesetId,
+    bonsai_cs_id: Option<ChangesetId>,
+) {
+    let bonsai_cs_id = match changeset_id {
+        HookChangesetId::Hg(cs_id) => {
+            scuba.add("hash", cs_id.to_hex().to_string());
+            bonsai_cs_id
+        }
+        HookChangesetId::Bonsai(cs_id) => Some(cs_id),
+    };
+    if let Some(bonsai_cs_id) = bonsai_cs_id {
+        scuba.add("bonsai_hash", bonsai_cs_id.to_string());
+    }
+}
+
+/// Add the `outcome` of a hook to `scuba`, with its `code` (0 for accepted, 1 for rejected, 2
+/// for skipped), and the `reason` for rejections.
+fn add_execution(scuba: &mut ScubaSampleBuilder, exec: &HookExecution) {
+    let (outcome, code) = match exec {
+        HookExecution::Accepted => ("accepted", 0),
+        HookExecution::Rejected(info) => {
+            scuba.add("reason", info.description);
+            ("rejected", 1)
+        }
+        HookExecution::Skipped => ("skipped", 2),
+    };
+    scuba.add("outcome", outcome).add("code", code);
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct HookContext<T>
 where
@@ -841,6 +1755,10 @@ where
     pub config: HookConfig,
     pub data: T,
     pub bookmark: BookmarkName,
+    pub repo_name: String,
+    pub repo_id: RepositoryId,
+    /// Outcomes of the hooks this hook depends on that ran on the same data
+    pub dependency_outcomes: HashMap<String, HookExecution>,
 }
 
 impl<T> HookContext<T>
@@ -850,6 +1768,7 @@ where
     fn new(
         hook_name: String,
         config: HookConfig,
+        repo: &HookRepo,
         data: T,
         bookmark: &BookmarkName,
     ) -> HookContext<T> {
@@ -858,6 +1777,16 @@ where
             config,
             data,
             bookmark: bookmark.clone(),
+            repo_name: repo.name.clone(),
+            repo_id: repo.id,
+            dependency_outcomes: HashMap::new(),
         }
     }
 }
+
+/// The repo hooks are being run for
+#[derive(Clone, Debug)]
+struct HookRepo {
+    name: String,
+    id: RepositoryId,
+}
@@ -9,9 +9,11 @@ use lazy_static::lazy_static;
 use regex::{Regex, RegexBuilder};
 use std::collections::HashSet;
 use std::mem;
+use thiserror::Error;
 
 const TITLE: &'static str = "title";
 const CC: &'static str = "cc";
+const CO_AUTHORED_BY: &'static str = "co-authored-by";
 const SUBSCRIBERS: &'static str = "subscribers";
 const DIFFERENTIAL_REVISION: &'static str = "differential revision";
 const REVERT_PLAN: &'static str = "revert plan";
@@ -29,8 +31,10 @@ lazy_static! {
         let PhabricatorMessage {
             title,
             cc,
+            co_authors,
             subscribers,
             differential_revision,
+            differential_revisions,
             revert_plan,
             reviewed_by,
             reviewers,
@@ -48,10 +52,13 @@ lazy_static! {
         if cc.is_none() {
             tags.insert(CC);
         }
+        if co_authors.is_none() {
+            tags.insert(CO_AUTHORED_BY);
+        }
         if subscribers.is_none() {
             tags.insert(SUBSCRIBERS);
         }
-        if differential_revision.is_none() {
+        if differential_revision.is_none() && differential_revisions.is_none() {
             tags.insert(DIFFERENTIAL_REVISION);
         }
         if revert_plan.is_none() {
@@ -83,14 +90,42 @@ lazy_static! {
         .case_insensitive(true)
         .build()
         .unwrap();
+
+    static ref CO_AUTHOR: Regex = Regex::new(r"^(.*?)\s*<([^<>]*)>$").unwrap();
+
+    static ref REVERT_TITLE: Regex = RegexBuilder::new(r"^\s*(revert|back(ed)?\s*out)\b")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+
+    static ref ACCEPT_TAGS: Regex = RegexBuilder::new(r"(?:^|\s)(#accept\w*)")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+}
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum SignatureError {
+    #[error("Signature is missing")]
+    Missing,
+    #[error("Signature '{0}' does not have the form nnn:nnn:hex")]
+    Malformed(String),
+    #[error("Signature '{0}' does not end with a hex string")]
+    NotHex(String),
 }
 
 #[derive(Clone, Default, Debug, Eq, PartialEq)]
 pub struct PhabricatorMessage {
     pub title: Option<String>,
     pub cc: Option<Vec<String>>,
+    /// `Co-authored-by` trailers as `Name <email>`. Unlike other tags, this one may be repeated.
+    pub co_authors: Option<Vec<String>>,
     pub subscribers: Option<Vec<String>>,
+    /// The first `Differential Revision` URL
     pub differential_revision: Option<String>,
+    /// All the `Differential Revision` URLs. Like `Co-authored-by`, this tag may be repeated,
+    /// e.g. by stacked diffs.
+    pub differential_revisions: Option<Vec<String>>,
     pub revert_plan: Option<String>,
     pub reviewed_by: Option<Vec<String>>,
     pub reviewers: Option<Vec<String>>,
@@ -102,6 +137,16 @@ pub struct PhabricatorMessage {
 
 impl PhabricatorMessage {
     pub fn parse_message(msg: &str) -> Self {
+        Self::parse(msg, false)
+    }
+
+    /// Same as `parse_message`, but the title is kept exactly as written up to the first
+    /// recognized tag, i.e. leading and trailing whitespace is not trimmed.
+    pub fn parse_message_preserving_title(msg: &str) -> Self {
+        Self::parse(msg, true)
+    }
+
+    fn parse(msg: &str, preserve_title: bool) -> Self {
         let lines = msg.lines();
         let mut parsed = PhabricatorMessage::default();
 
@@ -123,17 +168,61 @@ impl PhabricatorMessage {
                 Some(ref tag) if PHABRICATOR_TAGS.contains(tag.as_str()) => parsed.add(
                     mem::replace(&mut current_tag, tag.to_string()),
                     mem::replace(&mut current_value, vec![maybe_value.unwrap_or("")]),
+                    preserve_title,
                 ),
                 _ => current_value.push(line),
             }
         }
-        parsed.add(current_tag, current_value);
+        parsed.add(current_tag, current_value, preserve_title);
 
         parsed
     }
 
-    fn add(&mut self, tag: String, value: Vec<&str>) {
-        let value = itertools::join(value, "\n").trim().to_string();
+    /// All the `#accept...` hashtags (e.g. `#accept2ship`) found in the summary
+    pub fn accept_tags(&self) -> Vec<String> {
+        match self.summary {
+            Some(ref summary) => ACCEPT_TAGS
+                .captures_iter(summary)
+                .map(|captures| captures[1].to_string())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Whether this commit reverts another one, i.e. its title starts with "Revert" or
+    /// "Back out"/"Backed out"
+    pub fn is_revert(&self) -> bool {
+        match self.title {
+            Some(ref title) => REVERT_TITLE.is_match(title),
+            None => false,
+        }
+    }
+
+    /// Check that the signature has the expected `nnn:nnn:hex` shape
+    pub fn validate_signature(&self) -> Result<(), SignatureError> {
+        let signature = self.signature.as_ref().ok_or(SignatureError::Missing)?;
+
+        let parts: Vec<_> = signature.split(':').collect();
+        let is_number = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+        match parts.as_slice() {
+            [first, second, hex] if is_number(first) && is_number(second) => {
+                if !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    Ok(())
+                } else {
+                    Err(SignatureError::NotHex(signature.clone()))
+                }
+            }
+            _ => Err(SignatureError::Malformed(signature.clone())),
+        }
+    }
+
+    fn add(&mut self, tag: String, value: Vec<&str>, preserve_title: bool) {
+        let value = itertools::join(value, "\n");
+        let value = if preserve_title && tag == TITLE {
+            value
+        } else {
+            value.trim().to_string()
+        };
 
         let to_vec = |value: String| -> Vec<String> {
             SPLIT_USERNAMES
@@ -151,8 +240,18 @@ impl PhabricatorMessage {
         match tag.as_str() {
             TITLE => self.title = Some(value),
             CC => self.cc = Some(to_vec(value)),
+            CO_AUTHORED_BY => self
+                .co_authors
+                .get_or_insert_with(Vec::new)
+                .push(normalize_co_author(value)),
             SUBSCRIBERS => self.subscribers = Some(to_vec(value)),
-            DIFFERENTIAL_REVISION => self.differential_revision = Some(value),
+            DIFFERENTIAL_REVISION => {
+                self.differential_revision
+                    .get_or_insert_with(|| value.clone());
+                self.differential_revisions
+                    .get_or_insert_with(Vec::new)
+                    .push(value)
+            }
             REVERT_PLAN => self.revert_plan = Some(value),
             REVIEWED_BY => self.reviewed_by = Some(to_vec(value)),
             REVIEWERS => self.reviewers = Some(to_vec(value)),
@@ -165,6 +264,14 @@ impl PhabricatorMessage {
     }
 }
 
+/// Normalize a `Name <email>` pair, leaving values that don't look like one untouched
+fn normalize_co_author(value: String) -> String {
+    match CO_AUTHOR.captures(&value) {
+        Some(captures) => format!("{} <{}>", &captures[1], captures[2].trim()),
+        None => value,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -306,6 +413,7 @@ Differential Revision: https://url/D123
                 cc: Some(vec![s("jsgf")]),
                 tasks: Some(vec![s("T1234")]),
                 differential_revision: ss("https://url/D123"),
+                differential_revisions: Some(vec![s("https://url/D123")]),
                 ..Default::default()
             },
         );
@@ -343,9 +451,170 @@ Signature: 111111111:1111111111:bbbbbbbbbbbbbbbb",
                 reviewers: Some(vec![s("simonfar"), s("#mononoke")]),
                 subscribers: Some(vec![s("jsgf")]),
                 differential_revision: ss("https://phabricator.intern.facebook.com/D1111111"),
+                differential_revisions: Some(vec![s(
+                    "https://phabricator.intern.facebook.com/D1111111",
+                )]),
                 signature: ss("111111111:1111111111:bbbbbbbbbbbbbbbb"),
                 ..Default::default()
             },
         );
     }
+
+    #[test]
+    fn test_parse_commit_msg_preserving_title() {
+        let msg = "  indented title  \n\tsecond line\t\nSummary: fix\nTest Plan: testinprod";
+
+        assert_eq!(
+            PhabricatorMessage::parse_message_preserving_title(msg),
+            PhabricatorMessage {
+                title: ss("  indented title  \n\tsecond line\t"),
+                summary: ss("fix"),
+                test_plan: ss("testinprod"),
+                ..Default::default()
+            },
+        );
+
+        // Other fields are still trimmed, and the default parser trims the title
+        assert_eq!(
+            PhabricatorMessage::parse_message(msg),
+            PhabricatorMessage {
+                title: ss("indented title  \n\tsecond line"),
+                summary: ss("fix"),
+                test_plan: ss("testinprod"),
+                ..Default::default()
+            },
+        );
+    }
+
+    #[test]
+    fn test_validate_signature() {
+        let msg = PhabricatorMessage::parse_message(
+            "mononoke: fix bug\nSignature: 111111111:1111111111:bbbbbbbbbbbbbbbb",
+        );
+        assert_eq!(msg.validate_signature(), Ok(()));
+
+        let msg = PhabricatorMessage::parse_message("mononoke: fix bug\nSignature: 1111:bbbb");
+        assert_eq!(
+            msg.validate_signature(),
+            Err(SignatureError::Malformed(s("1111:bbbb")))
+        );
+
+        let msg = PhabricatorMessage::parse_message(
+            "mononoke: fix bug\nSignature: 111111111:1111111111:notahexstring",
+        );
+        assert_eq!(
+            msg.validate_signature(),
+            Err(SignatureError::NotHex(s(
+                "111111111:1111111111:notahexstring"
+            )))
+        );
+
+        let msg = PhabricatorMessage::parse_message("mononoke: fix bug");
+        assert_eq!(msg.validate_signature(), Err(SignatureError::Missing));
+    }
+
+    #[test]
+    fn test_accept_tags() {
+        let msg = PhabricatorMessage::parse_message(
+            "mononoke: log error only once
+
+Summary:
+Previously `log_with_msg()` was logged twice if msg wasn't None - with and
+without the message. This diff fixes it.
+
+#accept2ship
+Test Plan: buck check
+
+Reviewers: simonfar, #mononoke
+
+Signature: 111111111:1111111111:bbbbbbbbbbbbbbbb",
+        );
+        assert_eq!(msg.accept_tags(), vec![s("#accept2ship")]);
+
+        let msg = PhabricatorMessage::parse_message(
+            "mononoke: fix bug\nSummary: fix #acceptonred and#acceptnot\n#notaccept #ACCEPT2SHIP",
+        );
+        assert_eq!(
+            msg.accept_tags(),
+            vec![s("#acceptonred"), s("#ACCEPT2SHIP")]
+        );
+
+        let msg = PhabricatorMessage::parse_message("mononoke: fix bug #accept2ship");
+        assert_eq!(msg.accept_tags(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_is_revert() {
+        let is_revert = |msg: &str| PhabricatorMessage::parse_message(msg).is_revert();
+
+        assert!(is_revert(
+            "Revert \"mononoke: fix bug\"\n\nThis reverts commit abc."
+        ));
+        assert!(is_revert("revert D1234\nSummary: broke the build"));
+        assert!(is_revert("Back out \"mononoke: fix bug\""));
+        assert!(is_revert("Backed out changeset abcdef"));
+        assert!(is_revert("backout D1234"));
+        assert!(!is_revert("mononoke: revert to the old behavior"));
+        assert!(!is_revert("Reverted state is now handled\nSummary: fix"));
+        assert!(!is_revert("Summary: Revert D1234"));
+    }
+
+    #[test]
+    fn test_parse_co_authors() {
+        let msg = PhabricatorMessage::parse_message(
+            "mononoke: pair programmed fix
+
+Summary: fix
+Test Plan: testinprod
+
+Co-authored-by: Jane Doe <jane@example.com>
+Co-authored-by:   John Smith   < john:smith@example.com >
+",
+        );
+        assert_eq!(
+            msg,
+            PhabricatorMessage {
+                title: ss("mononoke: pair programmed fix"),
+                summary: ss("fix"),
+                test_plan: ss("testinprod"),
+                co_authors: Some(vec![
+                    s("Jane Doe <jane@example.com>"),
+                    s("John Smith <john:smith@example.com>"),
+                ]),
+                ..Default::default()
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_differential_revisions() {
+        let msg = PhabricatorMessage::parse_message(
+            "mononoke: stacked fix
+
+Summary: fix
+Test Plan: testinprod
+
+Differential Revision: https://url/D123
+Differential Revision: https://url/D456
+",
+        );
+        assert_eq!(
+            msg,
+            PhabricatorMessage {
+                title: ss("mononoke: stacked fix"),
+                summary: ss("fix"),
+                test_plan: ss("testinprod"),
+                differential_revision: ss("https://url/D123"),
+                differential_revisions: Some(vec![s("https://url/D123"), s("https://url/D456")]),
+                ..Default::default()
+            },
+        );
+
+        let msg = PhabricatorMessage::parse_message(
+            "mononoke: fix bug
+Summary: fix",
+        );
+        assert_eq!(msg.differential_revision, None);
+        assert_eq!(msg.differential_revisions, None);
+    }
 }
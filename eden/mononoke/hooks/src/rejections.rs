@@ -0,0 +1,75 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::BTreeMap;
+
+use hooks_content_stores::HookChangesetId;
+
+use crate::{HookExecution, HookOutcome, HookRejectionInfo};
+
+/// Build the message shown to the user when hooks reject a push, or `None` if none of
+/// `outcomes` is a rejection.
+///
+/// Rejections are grouped by hook, ordered by hook name and then by path, with changeset
+/// rejections first. At most `max_per_hook` rejections are listed for each hook, followed by
+/// the fixes and policies the hook pointed to.
+pub fn format_rejections(outcomes: &[HookOutcome], max_per_hook: usize) -> Option<String> {
+    let mut by_hook: BTreeMap<&str, Vec<(Option<&str>, HookChangesetId, &HookRejectionInfo)>> =
+        BTreeMap::new();
+    for outcome in outcomes {
+        if let HookExecution::Rejected(info) = outcome.get_execution() {
+            by_hook.entry(outcome.get_hook_name()).or_default().push((
+                outcome.get_file_path(),
+                outcome.get_cs_id(),
+                info,
+            ));
+        }
+    }
+    if by_hook.is_empty() {
+        return None;
+    }
+
+    let mut lines = Vec::new();
+    for (hook_name, mut rejections) in by_hook {
+        rejections.sort_by_key(|(path, cs_id, _)| (*path, *cs_id));
+
+        lines.push(format!("{}:", hook_name));
+        for (path, cs_id, info) in rejections.iter().take(max_per_hook) {
+            match path {
+                Some(path) => {
+                    lines.push(format!("  {} {}: {}", cs_id, path, info.long_description))
+                }
+                None => lines.push(format!("  {}: {}", cs_id, info.long_description)),
+            }
+        }
+        if rejections.len() > max_per_hook {
+            lines.push(format!("  and {} more", rejections.len() - max_per_hook));
+        }
+
+        let mut suggestions = Vec::new();
+        let mut policy_urls = Vec::new();
+        for (_, _, info) in &rejections {
+            for suggestion in &info.suggestions {
+                if !suggestions.contains(&suggestion) {
+                    suggestions.push(suggestion);
+                }
+            }
+            if let Some(policy_url) = &info.policy_url {
+                if !policy_urls.contains(&policy_url) {
+                    policy_urls.push(policy_url);
+                }
+            }
+        }
+        for suggestion in suggestions {
+            lines.push(format!("  * {}", suggestion));
+        }
+        for policy_url in policy_urls {
+            lines.push(format!("  Policy: {}", policy_url));
+        }
+    }
+    Some(lines.join("\n"))
+}
@@ -0,0 +1,174 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use crate::phabricator_message_parser::PhabricatorMessage;
+use crate::{Hook, HookChangeset, HookContext, HookExecution, HookRejectionInfo};
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use context::CoreContext;
+use metaconfig_types::HookConfig;
+use regex::{Regex, RegexBuilder};
+
+/// Name of the `HookConfig.ints` entry holding the maximum number of characters in the title
+const MAX_TITLE_LENGTH: &str = "max_title_length";
+/// Name of the `HookConfig.strings` entry holding a regex the title must match,
+/// e.g. `^\[?[a-z_]+\]?:`
+const TITLE_PATTERN: &str = "title_pattern";
+/// Name of the `HookConfig.strings` entry holding a comma-separated list of words that must
+/// not appear anywhere in the message. Words are matched case-insensitively.
+const FORBIDDEN_WORDS: &str = "forbidden_words";
+/// Name of the `HookConfig.ints` entry that, when non-zero, rejects titles ending with a period
+const NO_TITLE_PERIOD: &str = "no_title_period";
+
+/// Checks commit messages against the configured rules. Every rule is optional, but empty
+/// messages are always rejected. All the violated rules are listed in a single rejection.
+///
+/// The title is the first paragraph of the Phabricator title, with its lines joined by spaces,
+/// so that titles wrapped over several lines are checked as a whole.
+pub struct CheckCommitMessageFormat {
+    max_title_length: Option<usize>,
+    title_pattern: Option<Regex>,
+    forbidden_words: Vec<(String, Regex)>,
+    no_title_period: bool,
+}
+
+impl CheckCommitMessageFormat {
+    pub fn new(config: &HookConfig) -> Result<Self, Error> {
+        let max_title_length = match config.ints.get(MAX_TITLE_LENGTH) {
+            Some(max_title_length) if *max_title_length < 0 => {
+                return Err(format_err!(
+                    "{} config for check_commit_message_format must not be negative, got {}",
+                    MAX_TITLE_LENGTH,
+                    max_title_length
+                ));
+            }
+            Some(max_title_length) => Some(*max_title_length as usize),
+            None => None,
+        };
+
+        let title_pattern = config
+            .strings
+            .get(TITLE_PATTERN)
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| {
+                    format_err!(
+                        "Invalid {} config for check_commit_message_format: {}",
+                        TITLE_PATTERN,
+                        e
+                    )
+                })
+            })
+            .transpose()?;
+
+        let forbidden_words = match config.strings.get(FORBIDDEN_WORDS) {
+            Some(words) => words
+                .split(',')
+                .map(str::trim)
+                .filter(|word| !word.is_empty())
+                .map(|word| {
+                    let regex = RegexBuilder::new(&format!(r"\b{}\b", regex::escape(word)))
+                        .case_insensitive(true)
+                        .build()?;
+                    Ok((word.to_string(), regex))
+                })
+                .collect::<Result<_, Error>>()?,
+            None => vec![],
+        };
+
+        let no_title_period = config
+            .ints
+            .get(NO_TITLE_PERIOD)
+            .map_or(false, |toggle| *toggle != 0);
+
+        Ok(Self {
+            max_title_length,
+            title_pattern,
+            forbidden_words,
+            no_title_period,
+        })
+    }
+
+    /// Descriptions of all the rules the message violates
+    fn violations(&self, message: &str) -> Vec<String> {
+        if message.trim().is_empty() {
+            return vec!["Commit message is empty".to_string()];
+        }
+
+        let mut violations = vec![];
+        let title = title(message);
+
+        if let Some(max_title_length) = self.max_title_length {
+            let length = title.chars().count();
+            if length > max_title_length {
+                violations.push(format!(
+                    "Title is {} characters long, the limit is {}",
+                    length, max_title_length
+                ));
+            }
+        }
+
+        if let Some(ref title_pattern) = self.title_pattern {
+            if !title_pattern.is_match(&title) {
+                violations.push(format!("Title does not match '{}'", title_pattern));
+            }
+        }
+
+        let forbidden: Vec<_> = self
+            .forbidden_words
+            .iter()
+            .filter(|(_, regex)| regex.is_match(message))
+            .map(|(word, _)| word.as_str())
+            .collect();
+        if !forbidden.is_empty() {
+            violations.push(format!(
+                "Commit message contains forbidden words: {}",
+                forbidden.join(", ")
+            ));
+        }
+
+        if self.no_title_period && title.ends_with('.') {
+            violations.push("Title ends with a period".to_string());
+        }
+
+        violations
+    }
+}
+
+/// The first paragraph of the Phabricator title with its lines joined by spaces
+fn title(message: &str) -> String {
+    let title = PhabricatorMessage::parse_message(message)
+        .title
+        .unwrap_or_default();
+    title
+        .lines()
+        .map(str::trim)
+        .take_while(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[async_trait]
+impl Hook<HookChangeset> for CheckCommitMessageFormat {
+    async fn run<'a, 'b: 'a>(
+        &'a self,
+        _ctx: &'b CoreContext,
+        context: HookContext<HookChangeset>,
+    ) -> Result<HookExecution, Error> {
+        let violations = self.violations(&context.data.comments);
+        if violations.is_empty() {
+            return Ok(HookExecution::Accepted);
+        }
+
+        Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+            "Bad commit message format",
+            format!(
+                "Commit message does not follow the rules of this repository:\n{}",
+                itertools::join(violations.iter().map(|v| format!("  - {}", v)), "\n")
+            ),
+        )))
+    }
+}
@@ -0,0 +1,87 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use crate::{Hook, HookContext, HookExecution, HookFile, HookRejectionInfo, HookSuggestion};
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use context::CoreContext;
+use hooks_content_stores::ChangedFileType;
+use metaconfig_types::HookConfig;
+use mononoke_types::hash::Sha256;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Name of the `HookConfig.strings` entry holding a comma-separated list of hex sha256 hashes
+const BAD_SHA256S: &str = "bad_sha256s";
+
+/// Rejects files whose content has one of the configured sha256 hashes. The hashes are looked
+/// up in the content metadata, so file content is not fetched. Deleted files are accepted.
+pub struct DenyKnownBadBlobs {
+    bad_sha256s: HashSet<Sha256>,
+}
+
+impl DenyKnownBadBlobs {
+    pub fn new(config: &HookConfig) -> Result<Self, Error> {
+        let hashes = config.strings.get(BAD_SHA256S).ok_or_else(|| {
+            format_err!("Missing {} config for deny_known_bad_blobs", BAD_SHA256S)
+        })?;
+
+        let bad_sha256s = hashes
+            .split(',')
+            .map(str::trim)
+            .filter(|hash| !hash.is_empty())
+            .map(|hash| {
+                Sha256::from_str(hash).map_err(|e| {
+                    format_err!(
+                        "Invalid sha256 {:?} in {} config for deny_known_bad_blobs: {}",
+                        hash,
+                        BAD_SHA256S,
+                        e
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { bad_sha256s })
+    }
+}
+
+#[async_trait]
+impl Hook<HookFile> for DenyKnownBadBlobs {
+    async fn run<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        context: HookContext<HookFile>,
+    ) -> Result<HookExecution, Error> {
+        if context.data.changed_file_type() == ChangedFileType::Deleted {
+            return Ok(HookExecution::Accepted);
+        }
+
+        let sha256 = context.data.content_sha256(ctx).await?;
+        if !self.bad_sha256s.contains(&sha256) {
+            return Ok(HookExecution::Accepted);
+        }
+
+        Ok(HookExecution::Rejected(
+            HookRejectionInfo::new_long(
+                "Known bad file content",
+                format!(
+                    "File {} has content with sha256 {}, which is not allowed in this repository",
+                    context.data.path, sha256
+                ),
+            )
+            .with_suggestion(
+                HookSuggestion::new("Remove or replace the content of the file")
+                    .with_paths(vec![context.data.path.clone()]),
+            ),
+        ))
+    }
+
+    fn reads_file_text(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,188 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use crate::{Hook, HookContext, HookExecution, HookFile, HookRejectionInfo, HookSuggestion};
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use context::CoreContext;
+use hooks_content_stores::ChangedFileType;
+use metaconfig_types::HookConfig;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Name of the `HookConfig.ints` entry holding the default maximum file size in bytes
+const MAX_SIZE: &str = "max_size";
+/// Prefix of the `HookConfig.strings` entries overriding the maximum size for an extension,
+/// e.g. `"override:png" => "52428800"`
+const OVERRIDE_PREFIX: &str = "override:";
+/// Name of the `HookConfig.strings` entry holding a comma-separated list of banned extensions
+const BANNED_EXTENSIONS: &str = "banned_extensions";
+
+/// The rule that decided the size limit of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rule {
+    Default,
+    Override(String),
+    Banned(String),
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rule::Default => write!(f, "default limit"),
+            Rule::Override(ext) => write!(f, "override for .{} files", ext),
+            Rule::Banned(ext) => write!(f, ".{} files are banned", ext),
+        }
+    }
+}
+
+/// Rejects files larger than the configured maximum size, which can be overridden per
+/// extension. Files with a banned extension are rejected whatever their size. Only the size
+/// of files is fetched, never their content. Deleted files are accepted.
+///
+/// This is `limit_filesize_by_extension`, as `limit_filesize` is the older hook configured with
+/// a single `filesizelimit`.
+pub struct LimitFilesizeByExtension {
+    max_size: u64,
+    overrides: HashMap<String, u64>,
+    banned: HashSet<String>,
+}
+
+impl LimitFilesizeByExtension {
+    pub fn new(config: &HookConfig) -> Result<Self, Error> {
+        let max_size = config.ints.get(MAX_SIZE).ok_or_else(|| {
+            format_err!(
+                "Missing {} config for limit_filesize_by_extension",
+                MAX_SIZE
+            )
+        })?;
+        if *max_size < 0 {
+            return Err(format_err!(
+                "{} config for limit_filesize_by_extension must not be negative, got {}",
+                MAX_SIZE,
+                max_size
+            ));
+        }
+
+        let mut overrides = HashMap::new();
+        for (key, value) in &config.strings {
+            if let Some(ext) = key.strip_prefix(OVERRIDE_PREFIX) {
+                if ext.is_empty() {
+                    return Err(format_err!(
+                        "Empty extension in {} config for limit_filesize_by_extension",
+                        key
+                    ));
+                }
+                let limit = value.parse::<u64>().map_err(|e| {
+                    format_err!(
+                        "Invalid {} config for limit_filesize_by_extension: {}",
+                        key,
+                        e
+                    )
+                })?;
+                overrides.insert(ext.to_lowercase(), limit);
+            }
+        }
+
+        let banned = config
+            .strings
+            .get(BANNED_EXTENSIONS)
+            .map(|exts| {
+                exts.split(',')
+                    .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+                    .filter(|ext| !ext.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            max_size: *max_size as u64,
+            overrides,
+            banned,
+        })
+    }
+
+    /// The size limit for `path` and the rule it comes from. Files with a banned extension
+    /// have a limit of 0 bytes.
+    fn limit(&self, path: &str) -> (u64, Rule) {
+        if let Some(ext) = extension(path) {
+            if self.banned.contains(&ext) {
+                return (0, Rule::Banned(ext));
+            }
+            if let Some(limit) = self.overrides.get(&ext) {
+                return (*limit, Rule::Override(ext));
+            }
+        }
+        (self.max_size, Rule::Default)
+    }
+}
+
+/// The lowercased extension of the last component of `path`, if any
+fn extension(path: &str) -> Option<String> {
+    let basename = path.rsplit('/').next().unwrap_or(path);
+    match basename.rfind('.') {
+        Some(idx) if idx > 0 && idx + 1 < basename.len() => {
+            Some(basename[idx + 1..].to_lowercase())
+        }
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl Hook<HookFile> for LimitFilesizeByExtension {
+    async fn run<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        context: HookContext<HookFile>,
+    ) -> Result<HookExecution, Error> {
+        if context.data.changed_file_type() == ChangedFileType::Deleted {
+            return Ok(HookExecution::Accepted);
+        }
+
+        let (limit, rule) = self.limit(&context.data.path);
+        let size = context.data.len(ctx).await?;
+        let accepted = match rule {
+            Rule::Banned(_) => false,
+            _ => size <= limit,
+        };
+        if accepted {
+            return Ok(HookExecution::Accepted);
+        }
+
+        Ok(HookExecution::Rejected(
+            HookRejectionInfo::new_long(
+                "File too large",
+                format!(
+                    "File {} is {} bytes, the limit is {} bytes ({})",
+                    context.data.path, size, limit, rule
+                ),
+            )
+            .with_suggestion(
+                HookSuggestion::new("Remove the file, or store it outside the repository")
+                    .with_paths(vec![context.data.path.clone()]),
+            ),
+        ))
+    }
+
+    fn reads_file_text(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(extension("dir/image.PNG"), Some("png".to_string()));
+        assert_eq!(extension("dir.d/archive.tar.gz"), Some("gz".to_string()));
+        assert_eq!(extension("dir.d/Makefile"), None);
+        assert_eq!(extension(".gitignore"), None);
+        assert_eq!(extension("trailing."), None);
+    }
+}
@@ -0,0 +1,61 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use crate::{Hook, HookChangeset, HookContext, HookExecution, HookRejectionInfo};
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use context::CoreContext;
+use metaconfig_types::HookConfig;
+
+/// Name of the `HookConfig.ints` entry holding the maximum number of characters
+const MAX_LENGTH: &str = "max_length";
+
+/// Rejects changesets whose commit message is longer than the configured number of characters.
+pub struct MaxMessageLength {
+    max_length: usize,
+}
+
+impl MaxMessageLength {
+    pub fn new(config: &HookConfig) -> Result<Self, Error> {
+        let max_length = config
+            .ints
+            .get(MAX_LENGTH)
+            .ok_or_else(|| format_err!("Missing {} config for max_message_length", MAX_LENGTH))?;
+        if *max_length < 0 {
+            return Err(format_err!(
+                "{} config for max_message_length must not be negative, got {}",
+                MAX_LENGTH,
+                max_length
+            ));
+        }
+        Ok(Self {
+            max_length: *max_length as usize,
+        })
+    }
+}
+
+#[async_trait]
+impl Hook<HookChangeset> for MaxMessageLength {
+    async fn run<'a, 'b: 'a>(
+        &'a self,
+        _ctx: &'b CoreContext,
+        context: HookContext<HookChangeset>,
+    ) -> Result<HookExecution, Error> {
+        let length = context.data.comments.chars().count();
+        if length <= self.max_length {
+            return Ok(HookExecution::Accepted);
+        }
+
+        Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+            "Commit message too long",
+            format!(
+                "Commit message is {} characters long, the limit is {}",
+                length, self.max_length
+            ),
+        )))
+    }
+}
@@ -0,0 +1,15 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Rust hooks that are available in every build
+
+pub mod check_commit_message_format;
+pub mod deny_known_bad_blobs;
+pub mod limit_filesize_by_extension;
+pub mod max_message_length;
+pub mod reverts_need_task;
+pub mod verify_author;
@@ -0,0 +1,69 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use crate::phabricator_message_parser::PhabricatorMessage;
+use crate::{Hook, HookChangeset, HookContext, HookExecution, HookRejectionInfo};
+use anyhow::Error;
+use async_trait::async_trait;
+use context::CoreContext;
+use metaconfig_types::HookConfig;
+use regex::Regex;
+
+/// Name of the `HookConfig.strings` entry overriding `DEFAULT_TASK_PATTERN`
+const TASK_PATTERN: &str = "task_pattern";
+const DEFAULT_TASK_PATTERN: &str = r"^T\d+$";
+
+/// Rejects reverts, as detected by `PhabricatorMessage::is_revert`, unless one of their
+/// `Tasks:` matches the task pattern. Other commits are accepted.
+pub struct RevertsNeedTask {
+    task_pattern: Regex,
+}
+
+impl RevertsNeedTask {
+    pub fn new(config: &HookConfig) -> Result<Self, Error> {
+        let task_pattern = config
+            .strings
+            .get(TASK_PATTERN)
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_TASK_PATTERN);
+        Ok(Self {
+            task_pattern: Regex::new(task_pattern)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Hook<HookChangeset> for RevertsNeedTask {
+    async fn run<'a, 'b: 'a>(
+        &'a self,
+        _ctx: &'b CoreContext,
+        context: HookContext<HookChangeset>,
+    ) -> Result<HookExecution, Error> {
+        let message = PhabricatorMessage::parse_message(&context.data.comments);
+        if !message.is_revert() {
+            return Ok(HookExecution::Accepted);
+        }
+
+        let has_task = message
+            .tasks
+            .iter()
+            .flatten()
+            .any(|task| self.task_pattern.is_match(task));
+        if has_task {
+            Ok(HookExecution::Accepted)
+        } else {
+            Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                "Revert without a task",
+                format!(
+                    "Reverts must reference the task tracking the reverted change, \
+                     with a 'Tasks:' line matching '{}'",
+                    self.task_pattern
+                ),
+            )))
+        }
+    }
+}
@@ -0,0 +1,132 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use crate::{Hook, HookChangeset, HookContext, HookExecution, HookRejectionInfo, IdentityVerifier};
+use anyhow::Error;
+use async_trait::async_trait;
+use context::CoreContext;
+use lazy_static::lazy_static;
+use metaconfig_types::HookConfig;
+use regex::Regex;
+use slog::warn;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Name of the `HookConfig.strings` entry holding a comma-separated list of bot accounts that
+/// aren't verified. Entries are matched case-insensitively against the author's email, or the
+/// whole author for authors without one.
+const EXEMPT_AUTHORS: &str = "exempt_authors";
+/// Name of the `HookConfig.ints` entry that, when non-zero, only logs invalid authors instead
+/// of rejecting them, e.g. while rolling the hook out
+const WARN_ONLY: &str = "warn_only";
+
+lazy_static! {
+    static ref AUTHOR: Regex = Regex::new(r"^.*<([^<>]+)>$").unwrap();
+}
+
+/// Rejects changesets whose author isn't a valid identity according to the hook manager's
+/// `IdentityVerifier`. Authors must have the form `Name <email>`, and it's the email that's
+/// verified.
+pub struct VerifyAuthor {
+    identity_verifier: Arc<dyn IdentityVerifier>,
+    exempt_authors: HashSet<String>,
+    warn_only: bool,
+}
+
+impl VerifyAuthor {
+    pub fn new(
+        config: &HookConfig,
+        identity_verifier: Arc<dyn IdentityVerifier>,
+    ) -> Result<Self, Error> {
+        let exempt_authors = match config.strings.get(EXEMPT_AUTHORS) {
+            Some(authors) => authors
+                .split(',')
+                .map(str::trim)
+                .filter(|author| !author.is_empty())
+                .map(str::to_lowercase)
+                .collect(),
+            None => HashSet::new(),
+        };
+        let warn_only = config
+            .ints
+            .get(WARN_ONLY)
+            .map_or(false, |toggle| *toggle != 0);
+
+        Ok(Self {
+            identity_verifier,
+            exempt_authors,
+            warn_only,
+        })
+    }
+
+    fn is_exempt(&self, author: &str, email: Option<&str>) -> bool {
+        let identity = email.unwrap_or(author);
+        self.exempt_authors.contains(&identity.to_lowercase())
+    }
+
+    /// The rejection for `author`, if any
+    async fn check(
+        &self,
+        ctx: &CoreContext,
+        author: &str,
+    ) -> Result<Option<HookRejectionInfo>, Error> {
+        let author = author.trim();
+        let email = AUTHOR
+            .captures(author)
+            .map(|captures| captures[1].trim().to_string());
+        if self.is_exempt(author, email.as_ref().map(String::as_str)) {
+            return Ok(None);
+        }
+
+        let email = match email {
+            Some(email) if !email.is_empty() => email,
+            _ => {
+                return Ok(Some(HookRejectionInfo::new_long(
+                    "Malformed author",
+                    format!("Author '{}' does not have the form 'Name <email>'", author),
+                )));
+            }
+        };
+
+        let invalid = self
+            .identity_verifier
+            .invalid_identities(ctx, &[email.clone()])
+            .await?;
+        if invalid.contains(&email) {
+            Ok(Some(HookRejectionInfo::new_long(
+                "Unknown author",
+                format!(
+                    "Author '{}' is not a valid user or an allowed bot account",
+                    email
+                ),
+            )))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[async_trait]
+impl Hook<HookChangeset> for VerifyAuthor {
+    async fn run<'a, 'b: 'a>(
+        &'a self,
+        ctx: &'b CoreContext,
+        context: HookContext<HookChangeset>,
+    ) -> Result<HookExecution, Error> {
+        match self.check(ctx, &context.data.author).await? {
+            None => Ok(HookExecution::Accepted),
+            Some(info) if self.warn_only => {
+                warn!(
+                    ctx.logger(),
+                    "{} would reject a changeset: {}", context.hook_name, info.long_description
+                );
+                Ok(HookExecution::Accepted)
+            }
+            Some(info) => Ok(HookExecution::Rejected(info)),
+        }
+    }
+}
@@ -9,6 +9,7 @@
 
 use anyhow::{Error, Result};
 use bytes_old::{Bytes, BytesMut};
+use mercurial_types::HgChangesetId;
 use tokio_io::codec::Decoder;
 
 use crate::utils::BytesExt;
@@ -54,3 +55,133 @@ impl Decoder for InfinitepushBookmarksUnpacker {
         }
     }
 }
+
+/// Version of the format of `b2x:infinitepushmutation` parts
+const MUTATION_BUNDLE_VERSION: u8 = 1;
+/// Version of the format of the entries in them, which store the timestamp as an f64
+const MUTATION_ENTRY_VERSION: u8 = 1;
+
+/// A commit mutation entry, as recorded by Mercurial clients: the commits that `successor` was
+/// created from by an amend, rebase, fold, etc.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HgMutationEntry {
+    pub successor: HgChangesetId,
+    pub predecessors: Vec<HgChangesetId>,
+    /// The other commits created by splitting the predecessor, if it was split
+    pub split: Vec<HgChangesetId>,
+    pub op: String,
+    pub user: String,
+    pub timestamp: i64,
+    pub timezone: i32,
+    pub extra: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl HgMutationEntry {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(MUTATION_ENTRY_VERSION);
+        buf.extend_from_slice(self.successor.as_bytes());
+        write_vlq(buf, self.predecessors.len() as u64);
+        for predecessor in &self.predecessors {
+            buf.extend_from_slice(predecessor.as_bytes());
+        }
+        write_vlq(buf, self.split.len() as u64);
+        for split in &self.split {
+            buf.extend_from_slice(split.as_bytes());
+        }
+        write_vlq_bytes(buf, self.op.as_bytes());
+        write_vlq_bytes(buf, self.user.as_bytes());
+        buf.extend_from_slice(&(self.timestamp as f64).to_bits().to_be_bytes());
+        // Signed numbers are zigzag encoded.
+        write_vlq(
+            buf,
+            ((self.timezone << 1) ^ (self.timezone >> 31)) as u32 as u64,
+        );
+        write_vlq(buf, self.extra.len() as u64);
+        for (key, value) in &self.extra {
+            write_vlq_bytes(buf, key);
+            write_vlq_bytes(buf, value);
+        }
+    }
+}
+
+/// Encode mutation entries as the payload of a `b2x:infinitepushmutation` part, in the same
+/// format as Mercurial's `mutationstore.bundle`.
+pub fn encode_mutation_entries(entries: &[HgMutationEntry]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.push(MUTATION_BUNDLE_VERSION);
+    write_vlq(&mut buf, entries.len() as u64);
+    for entry in entries {
+        entry.encode(&mut buf);
+    }
+    buf
+}
+
+fn write_vlq(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn write_vlq_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_vlq(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mercurial_types_mocks::nodehash::{ONES_CSID, TWOS_CSID};
+
+    #[test]
+    fn test_write_vlq() {
+        let vlq = |value| {
+            let mut buf = Vec::new();
+            write_vlq(&mut buf, value);
+            buf
+        };
+        assert_eq!(vlq(0), vec![0]);
+        assert_eq!(vlq(127), vec![127]);
+        assert_eq!(vlq(128), vec![0x80, 1]);
+        assert_eq!(vlq(300), vec![0xac, 2]);
+    }
+
+    #[test]
+    fn test_encode_mutation_entries() {
+        let entry = HgMutationEntry {
+            successor: TWOS_CSID,
+            predecessors: vec![ONES_CSID],
+            split: vec![],
+            op: "amend".to_string(),
+            user: "test".to_string(),
+            timestamp: 1,
+            timezone: -1,
+            extra: vec![(b"k".to_vec(), b"v".to_vec())],
+        };
+
+        let mut expected = vec![MUTATION_BUNDLE_VERSION, 1, MUTATION_ENTRY_VERSION];
+        expected.extend_from_slice(TWOS_CSID.as_bytes());
+        expected.push(1);
+        expected.extend_from_slice(ONES_CSID.as_bytes());
+        expected.push(0);
+        expected.push(5);
+        expected.extend_from_slice(b"amend");
+        expected.push(4);
+        expected.extend_from_slice(b"test");
+        expected.extend_from_slice(&1f64.to_bits().to_be_bytes());
+        // Zigzag encoded -1
+        expected.push(1);
+        expected.extend_from_slice(&[1, 1, b'k', 1, b'v']);
+        assert_eq!(encode_mutation_entries(&[entry]), expected);
+
+        assert_eq!(
+            encode_mutation_entries(&[]),
+            vec![MUTATION_BUNDLE_VERSION, 0]
+        );
+    }
+}
@@ -76,6 +76,11 @@ impl PartEncodeBuilder {
         })
     }
 
+    #[inline]
+    pub fn part_type(&self) -> &PartHeaderType {
+        self.headerb.part_type()
+    }
+
     #[inline]
     pub fn add_mparam<S, B>(&mut self, key: S, val: B) -> Result<&mut Self>
     where
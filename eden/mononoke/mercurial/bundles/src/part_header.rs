@@ -48,6 +48,8 @@ pub enum PartHeaderType {
     /// Contains bookmarks for infinitepush backups (won't be used in Mononoke,
     /// but they needs to be parsed).
     B2xInfinitepushBookmarks,
+    /// Contains commit mutation entries (amends, rebases, etc.) for infinitepush commits
+    B2xInfinitepushMutation,
     /// Pushrebase part with changegroup
     B2xRebase,
     /// Pushrebase part that contains packs
@@ -92,6 +94,7 @@ impl PartHeaderType {
             "b2x:treegroup2" => Ok(B2xTreegroup2),
             "b2x:infinitepush" => Ok(B2xInfinitepush),
             "b2x:infinitepushscratchbookmarks" => Ok(B2xInfinitepushBookmarks),
+            "b2x:infinitepushmutation" => Ok(B2xInfinitepushMutation),
             "b2x:commonheads" => Ok(B2xCommonHeads),
             "b2x:rebase" => Ok(B2xRebase),
             "b2x:rebasepackpart" => Ok(B2xRebasePack),
@@ -116,6 +119,7 @@ impl PartHeaderType {
             B2xCommonHeads => "b2x:commonheads",
             B2xInfinitepush => "b2x:infinitepush",
             B2xInfinitepushBookmarks => "b2x:infinitepushscratchbookmarks",
+            B2xInfinitepushMutation => "b2x:infinitepushmutation",
             B2xRebase => "b2x:rebase",
             B2xRebasePack => "b2x:rebasepackpart",
             CheckHeads => "check:heads",
@@ -8,6 +8,7 @@
 use super::changegroup::{packer::CgPacker, unpacker::CgVersion};
 use super::changegroup::{CgDeltaChunk, Part, Section};
 use super::chunk::Chunk;
+use super::infinitepush::{encode_mutation_entries, HgMutationEntry};
 use super::obsmarkers::packer::obsmarkers_packer_stream;
 use super::obsmarkers::MetadataEntry;
 use super::wirepack;
@@ -270,6 +271,14 @@ pub fn common_heads_part(heads: Vec<HgChangesetId>) -> Result<PartEncodeBuilder>
     Ok(builder)
 }
 
+/// Mutation entries of draft commits. The part is advisory, so clients that don't know about
+/// mutation ignore it.
+pub fn infinitepush_mutation_part(entries: Vec<HgMutationEntry>) -> Result<PartEncodeBuilder> {
+    let mut builder = PartEncodeBuilder::advisory(PartHeaderType::B2xInfinitepushMutation)?;
+    builder.set_data_fixed(Chunk::new(encode_mutation_entries(&entries))?);
+    Ok(builder)
+}
+
 pub struct TreepackPartInput {
     pub node: HgNodeHash,
     pub p1: Option<HgNodeHash>,
@@ -5,6 +5,9 @@
  * GNU General Public License version 2.
  */
 
+use std::cmp::max;
+use std::ops::Range;
+
 use anyhow::{bail, ensure, format_err, Context, Result};
 use heapsize_derive::HeapSizeOf;
 use quickcheck::{Arbitrary, Gen};
@@ -12,6 +15,8 @@ use rand::Rng;
 use rand_distr::{Distribution, LogNormal};
 
 use crate::errors::ErrorKind;
+use crate::hash::Sha1;
+use crate::nodehash::HgNodeHash;
 
 use super::delta_apply::{mpatch_fold, wrap_deltas};
 
@@ -59,6 +64,97 @@ impl Delta {
         }
     }
 
+    /// Describe the changes this Delta makes to `base` in terms of whole lines, as needed to
+    /// display it as a diff. Fragments are extended to the lines they touch, and fragments
+    /// touching the same line end up in the same hunk. Lines keep their trailing newline, if
+    /// any, and hunks that don't change anything are omitted.
+    pub fn to_line_diff(&self, base: &[u8]) -> Result<Vec<LineHunk>> {
+        self.applied_len(base.len())?;
+        let lines = LineStarts::new(base);
+        let mut hunks = Vec::new();
+        // Difference between the number of lines in the new and the old text so far.
+        let mut shift: isize = 0;
+
+        let mut idx = 0;
+        while idx < self.frags.len() {
+            let first = idx;
+            let old_start = lines.expand_start(self.frags[idx].start);
+            let mut old_end = old_start;
+            // Whether the new text of the hunk so far runs into whatever follows it.
+            let mut runs_on = false;
+            let new_text = loop {
+                while idx < self.frags.len()
+                    && (idx == first
+                        || lines.expand_start(self.frags[idx].start) < old_end
+                        || (runs_on && self.frags[idx].start == old_end))
+                {
+                    old_end = max(old_end, lines.expand_end(self.frags[idx].end));
+                    idx += 1;
+                }
+
+                let mut new_text = Vec::new();
+                let mut off = old_start;
+                for frag in &self.frags[first..idx] {
+                    new_text.extend_from_slice(&base[off..frag.start]);
+                    new_text.extend_from_slice(&frag.content);
+                    off = frag.end;
+                }
+                new_text.extend_from_slice(&base[off..old_end]);
+
+                // Content that doesn't end with a newline runs into the next line, or into
+                // content inserted right after it.
+                runs_on = !new_text.is_empty() && !new_text.ends_with(b"\n");
+                if runs_on && old_end < base.len() {
+                    old_end = lines.expand_end(old_end + 1);
+                } else if !(runs_on && idx < self.frags.len() && self.frags[idx].start == old_end) {
+                    break new_text;
+                }
+            };
+
+            let old_text = &base[old_start..old_end];
+            if old_text == new_text.as_slice() {
+                continue;
+            }
+            let removed = split_lines(old_text);
+            let added = split_lines(&new_text);
+
+            let old_line = lines.line(old_start);
+            let new_line = (old_line as isize + shift) as usize;
+            shift += added.len() as isize - removed.len() as isize;
+            hunks.push(LineHunk {
+                old_range: old_line..old_line + removed.len(),
+                new_range: new_line..new_line + added.len(),
+                removed,
+                added,
+            });
+        }
+        Ok(hunks)
+    }
+
+    /// Return the length of a text of length `len` after this Delta is applied to it, or an
+    /// error if any fragment is out of bounds for such a text.
+    fn applied_len(&self, len: usize) -> Result<usize> {
+        let mut removed: usize = 0;
+        let mut inserted: usize = 0;
+        for frag in &self.frags {
+            ensure!(
+                frag.end <= len,
+                ErrorKind::InvalidFragmentList(format!(
+                    "fragment end {} is out of bounds for text of length {}",
+                    frag.end, len
+                ))
+            );
+            // Fragments are verified to be sorted and non-overlapping, so this can't exceed len.
+            removed += frag.end - frag.start;
+            inserted = inserted
+                .checked_add(frag.content.len())
+                .ok_or_else(|| format_err!("Invalid delta, inserted content is too large"))?;
+        }
+        (len - removed)
+            .checked_add(inserted)
+            .ok_or_else(|| format_err!("Invalid delta, resulting text is too large"))
+    }
+
     fn verify(frags: &[Fragment]) -> Result<()> {
         let mut prev_frag: Option<&Fragment> = None;
         for (i, frag) in frags.iter().enumerate() {
@@ -209,6 +305,83 @@ fn arbitrary_frag_content<G: Gen>(g: &mut G) -> Vec<u8> {
     v
 }
 
+/// A change to a contiguous range of lines, with line numbers starting at 0.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LineHunk {
+    /// Lines replaced in the old text
+    pub old_range: Range<usize>,
+    /// Lines replacing them in the new text
+    pub new_range: Range<usize>,
+    pub removed: Vec<Vec<u8>>,
+    pub added: Vec<Vec<u8>>,
+}
+
+/// Offsets at which the lines of a text start
+struct LineStarts {
+    starts: Vec<usize>,
+    len: usize,
+    /// Whether the end of the text is the start of a (missing) line, i.e. the text is empty
+    /// or ends with a newline
+    ends_with_newline: bool,
+}
+
+impl LineStarts {
+    fn new(text: &[u8]) -> Self {
+        let starts = (0..text.len())
+            .filter(|&off| off == 0 || text[off - 1] == b'\n')
+            .collect();
+        Self {
+            starts,
+            len: text.len(),
+            ends_with_newline: text.last().map_or(true, |&c| c == b'\n'),
+        }
+    }
+
+    /// The start of the line containing `off`, or `off` itself if a line starts there
+    fn expand_start(&self, off: usize) -> usize {
+        if off == self.len && self.ends_with_newline {
+            return off;
+        }
+        match self.starts.binary_search(&off) {
+            Ok(_) => off,
+            Err(idx) => self.starts[idx - 1],
+        }
+    }
+
+    /// The end of the line containing `off`, or `off` itself if a line starts there
+    fn expand_end(&self, off: usize) -> usize {
+        if off >= self.len {
+            return self.len;
+        }
+        match self.starts.binary_search(&off) {
+            Ok(_) => off,
+            Err(idx) => self.starts.get(idx).cloned().unwrap_or(self.len),
+        }
+    }
+
+    /// The number of the line starting at `off`, which is the number of lines if `off` is the
+    /// end of the text
+    fn line(&self, off: usize) -> usize {
+        self.starts.binary_search(&off).unwrap_or_else(|idx| idx)
+    }
+}
+
+/// Split `text` into lines, keeping their newlines.
+fn split_lines(text: &[u8]) -> Vec<Vec<u8>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (off, &c) in text.iter().enumerate() {
+        if c == b'\n' {
+            lines.push(text[start..=off].to_vec());
+            start = off + 1;
+        }
+    }
+    if start < text.len() {
+        lines.push(text[start..].to_vec());
+    }
+    lines
+}
+
 /// Apply a Delta to an input text, returning the result.
 pub fn apply(text: &[u8], delta: &Delta) -> Result<Vec<u8>> {
     let mut chunks = Vec::with_capacity(delta.frags.len() * 2);
@@ -221,6 +394,12 @@ pub fn apply(text: &[u8], delta: &Delta) -> Result<Vec<u8>> {
             frag.start,
             off
         );
+        ensure!(
+            frag.start <= frag.end,
+            "Invalid delta, fragment start is greater than its end ({} > {})",
+            frag.start,
+            frag.end
+        );
         if off < frag.start {
             chunks.push(text.get(off..frag.start).ok_or_else(|| {
                 format_err!(
@@ -245,6 +424,8 @@ pub fn apply(text: &[u8], delta: &Delta) -> Result<Vec<u8>> {
         );
     }
 
+    // Every chunk is a slice of the text or of a fragment's content, so the sum of their lengths
+    // is bounded by memory that's already allocated and can't overflow.
     let size = chunks.iter().map(|c| c.len()).sum::<usize>();
     let mut output = Vec::with_capacity(size);
     for c in chunks {
@@ -253,25 +434,58 @@ pub fn apply(text: &[u8], delta: &Delta) -> Result<Vec<u8>> {
     Ok(output)
 }
 
+/// Apply a Delta to an input text after checking that the sha-1 of the text is
+/// `expected_base_sha1`, so that a delta is never applied to the wrong base.
+pub fn apply_checked(
+    text: &[u8],
+    expected_base_sha1: HgNodeHash,
+    delta: &Delta,
+) -> Result<Vec<u8>> {
+    let actual = HgNodeHash::new(Sha1::from(text));
+    if actual != expected_base_sha1 {
+        bail!(ErrorKind::DeltaBaseMismatch {
+            expected: expected_base_sha1,
+            actual,
+        });
+    }
+    apply(text, delta)
+}
+
 /// Apply a chain of Deltas to an input text, returning the result.
 /// Pack all deltas into one delta, and apply a pack to input text.
 pub fn apply_chain<I: IntoIterator<Item = Delta>>(text: &[u8], deltas: I) -> Result<Vec<u8>> {
-    let mut res = Vec::from(text);
+    let folded_delta = fold_chain(text.len(), deltas.into_iter().collect())?;
+    apply(text, &folded_delta)
+}
 
-    let (wrapped_deltas, data) = wrap_deltas(deltas);
+/// Compose a chain of Deltas into a single Delta that has the same effect on a text of length
+/// `base_len` as applying the whole chain to it one by one.
+pub fn compose_chain(base_len: usize, deltas: &[Delta]) -> Result<Delta> {
+    fold_chain(base_len, deltas.to_vec())
+}
+
+fn fold_chain(base_len: usize, deltas: Vec<Delta>) -> Result<Delta> {
+    // Deltas usually come from the network, so check that each one is well-formed and in bounds
+    // of the text it applies to before folding them. This also bounds every offset the fold sees
+    // by the base length plus the total inserted bytes.
+    let mut len = base_len;
+    for (i, delta) in deltas.iter().enumerate() {
+        Delta::verify(&delta.frags)
+            .and_then(|()| delta.applied_len(len))
+            .map(|new_len| len = new_len)
+            .with_context(|| ErrorKind::InvalidFragmentList(format!("invalid delta {}", i)))?;
+    }
+
+    let (wrapped_deltas, data) = wrap_deltas(deltas)?;
 
     if wrapped_deltas.len() == 0 {
-        Ok(res)
+        Ok(Delta::default())
     } else {
         // fold all deltas into one delta using logarithmic algorithm
-        let folded_wrapped_delta = mpatch_fold(&wrapped_deltas, 0, wrapped_deltas.len());
+        let folded_wrapped_delta = mpatch_fold(&wrapped_deltas, 0, wrapped_deltas.len())?;
 
         // convert into Revlog Delta
-        let folded_delta = folded_wrapped_delta.into_delta(data)?;
-
-        // apply folded delta
-        res = apply(&res, &folded_delta)?;
-        Ok(res)
+        folded_wrapped_delta.into_delta(data)
     }
 }
 
@@ -396,6 +610,32 @@ mod tests {
         assert_eq!(&res[..], b"aaaa\nxxxx\ncccc\n");
     }
 
+    #[test]
+    fn test_apply_checked() {
+        let text = b"aaaa\nbbbb\ncccc\n";
+        let base_sha1 = HgNodeHash::new(Sha1::from(&text[..]));
+        let delta = Delta {
+            frags: vec![Fragment {
+                start: 5,
+                end: 10,
+                content: (&b"xxxx\n"[..]).into(),
+            }],
+        };
+
+        let res = apply_checked(text, base_sha1, &delta).unwrap();
+        assert_eq!(&res[..], b"aaaa\nxxxx\ncccc\n");
+
+        let tampered = b"aaaa\nbbbb\ncccC\n";
+        let err = apply_checked(tampered, base_sha1, &delta).unwrap_err();
+        match err.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::DeltaBaseMismatch { expected, actual }) => {
+                assert_eq!(*expected, base_sha1);
+                assert_eq!(*actual, HgNodeHash::new(Sha1::from(&tampered[..])));
+            }
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
     #[test]
     fn test_apply_2() {
         let text = b"bbbb\ncccc\n";
@@ -623,4 +863,298 @@ mod tests {
 
         assert_eq!(&res[..], b"axxxzzz");
     }
+
+    /// A list of fragments that doesn't maintain any of the Delta invariants, and favours
+    /// offsets that are likely to trip up overflow and bounds checks.
+    #[derive(Clone, Debug)]
+    struct AdversarialDelta(Delta);
+
+    fn adversarial_offset<G: Gen>(g: &mut G) -> usize {
+        let size = g.size();
+        match g.gen_range(0, 8) {
+            0 => usize::MAX,
+            1 => usize::MAX - g.gen_range(0, size),
+            2 => i64::MAX as usize,
+            3 => i64::MAX as usize + g.gen_range(0, size),
+            _ => g.gen_range(0, size),
+        }
+    }
+
+    impl Arbitrary for AdversarialDelta {
+        fn arbitrary<G: Gen>(g: &mut G) -> Self {
+            let nfrags = g.gen_range(0, 5);
+            let frags = (0..nfrags)
+                .map(|_| Fragment {
+                    start: adversarial_offset(g),
+                    end: adversarial_offset(g),
+                    content: arbitrary_frag_content(g),
+                })
+                .collect();
+            AdversarialDelta(Delta { frags })
+        }
+    }
+
+    quickcheck! {
+        fn apply_adversarial(text: Vec<u8>, delta: AdversarialDelta) -> bool {
+            let out_of_bounds = delta.0.frags.iter().any(|frag| frag.end > text.len());
+            let res = apply(&text, &delta.0);
+            !out_of_bounds || res.is_err()
+        }
+
+        fn apply_chain_adversarial(text: Vec<u8>, deltas: Vec<AdversarialDelta>) -> bool {
+            let deltas: Vec<_> = deltas.into_iter().map(|d| d.0).collect();
+            match apply_chain(&text, deltas.clone()) {
+                // If the chain applied, then applying the deltas one by one must give the same
+                // result.
+                Ok(res) => deltas
+                    .iter()
+                    .try_fold(text, |text, delta| apply(&text, delta))
+                    .map_or(false, |expected| expected == res),
+                Err(_) => true,
+            }
+        }
+
+        fn apply_chain_valid(deltas: Vec<Delta>) -> bool {
+            // Make the text long enough for all the generated fragments.
+            let max_end = deltas
+                .iter()
+                .flat_map(|d| d.frags.iter().map(|f| f.end))
+                .max()
+                .unwrap_or(0);
+            let text = vec![b'a'; max_end];
+            let sequential = deltas
+                .iter()
+                .try_fold(text.clone(), |text, delta| apply(&text, delta));
+            match (apply_chain(&text, deltas.clone()), sequential) {
+                (Ok(res), Ok(expected)) => res == expected,
+                (Err(_), Err(_)) => true,
+                _ => false,
+            }
+        }
+
+        fn compose_chain_valid(deltas: Vec<Delta>) -> bool {
+            let max_end = deltas
+                .iter()
+                .flat_map(|d| d.frags.iter().map(|f| f.end))
+                .max()
+                .unwrap_or(0);
+            let text = vec![b'a'; max_end];
+            match (apply_chain(&text, deltas.clone()), compose_chain(text.len(), &deltas)) {
+                (Ok(res), Ok(composed)) => {
+                    Delta::verify(&composed.frags).is_ok()
+                        && apply(&text, &composed).map_or(false, |composed_res| composed_res == res)
+                }
+                (Err(_), Err(_)) => true,
+                _ => false,
+            }
+        }
+
+        fn compose_chain_adversarial(text: Vec<u8>, deltas: Vec<AdversarialDelta>) -> bool {
+            let deltas: Vec<_> = deltas.into_iter().map(|d| d.0).collect();
+            match compose_chain(text.len(), &deltas) {
+                // If the chain composed, then applying the deltas one by one must give the same
+                // result as applying the composed delta.
+                Ok(composed) => deltas
+                    .iter()
+                    .try_fold(text.clone(), |text, delta| apply(&text, delta))
+                    .map_or(false, |expected| apply(&text, &composed).ok() == Some(expected)),
+                Err(_) => true,
+            }
+        }
+    }
+
+    fn line_diff(base: &str, frags: Vec<(usize, usize, &str)>) -> Vec<LineHunk> {
+        let delta = Delta::new(
+            frags
+                .into_iter()
+                .map(|(start, end, content)| Fragment {
+                    start,
+                    end,
+                    content: content.as_bytes().to_vec(),
+                })
+                .collect(),
+        )
+        .unwrap();
+        delta.to_line_diff(base.as_bytes()).unwrap()
+    }
+
+    fn hunk(
+        old_range: Range<usize>,
+        new_range: Range<usize>,
+        removed: &[&str],
+        added: &[&str],
+    ) -> LineHunk {
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.as_bytes().to_vec()).collect();
+        LineHunk {
+            old_range,
+            new_range,
+            removed: lines(removed),
+            added: lines(added),
+        }
+    }
+
+    #[test]
+    fn test_line_diff_whole_lines() {
+        let base = "a\nb\nc\n";
+        // Insertion
+        assert_eq!(
+            line_diff(base, vec![(2, 2, "x\ny\n")]),
+            vec![hunk(1..1, 1..3, &[], &["x\n", "y\n"])]
+        );
+        // Deletion
+        assert_eq!(
+            line_diff(base, vec![(2, 4, "")]),
+            vec![hunk(1..2, 1..1, &["b\n"], &[])]
+        );
+        // Replacement
+        assert_eq!(
+            line_diff(base, vec![(2, 6, "x\n")]),
+            vec![hunk(1..3, 1..2, &["b\n", "c\n"], &["x\n"])]
+        );
+        // Appending, and several hunks shifting line numbers
+        assert_eq!(
+            line_diff(base, vec![(0, 0, "x\ny\n"), (2, 4, ""), (6, 6, "d\n")]),
+            vec![
+                hunk(0..0, 0..2, &[], &["x\n", "y\n"]),
+                hunk(1..2, 3..3, &["b\n"], &[]),
+                hunk(3..3, 4..5, &[], &["d\n"]),
+            ]
+        );
+        // Fragments that change nothing
+        assert_eq!(line_diff(base, vec![(2, 4, "b\n"), (6, 6, "")]), vec![]);
+        // Fulltext
+        assert_eq!(
+            line_diff("", vec![(0, 0, "a\nb")]),
+            vec![hunk(0..0, 0..2, &[], &["a\n", "b"])]
+        );
+    }
+
+    #[test]
+    fn test_line_diff_mid_line() {
+        let base = "abc\ndef\nghi\n";
+        // Edit within a line
+        assert_eq!(
+            line_diff(base, vec![(5, 6, "X")]),
+            vec![hunk(1..2, 1..2, &["def\n"], &["dXf\n"])]
+        );
+        // Edit across a line boundary
+        assert_eq!(
+            line_diff(base, vec![(2, 5, "Y")]),
+            vec![hunk(0..2, 0..1, &["abc\n", "def\n"], &["abYef\n"])]
+        );
+        // Insertion without a newline runs into the following line
+        assert_eq!(
+            line_diff(base, vec![(4, 4, "x")]),
+            vec![hunk(1..2, 1..2, &["def\n"], &["xdef\n"])]
+        );
+        // Fragments touching the same line are merged
+        assert_eq!(
+            line_diff(base, vec![(0, 1, "A"), (2, 3, "C"), (8, 9, "G")]),
+            vec![
+                hunk(0..1, 0..1, &["abc\n"], &["AbC\n"]),
+                hunk(2..3, 2..3, &["ghi\n"], &["Ghi\n"]),
+            ]
+        );
+        // Fragments merged through a removed newline
+        assert_eq!(
+            line_diff(base, vec![(3, 4, ""), (7, 8, "")]),
+            vec![hunk(
+                0..3,
+                0..1,
+                &["abc\n", "def\n", "ghi\n"],
+                &["abcdefghi\n"]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_line_diff_no_trailing_newline() {
+        let base = "a\nb";
+        assert_eq!(
+            line_diff(base, vec![(3, 3, "c")]),
+            vec![hunk(1..2, 1..2, &["b"], &["bc"])]
+        );
+        assert_eq!(
+            line_diff(base, vec![(3, 3, "\n")]),
+            vec![hunk(1..2, 1..2, &["b"], &["b\n"])]
+        );
+        assert_eq!(
+            line_diff(base, vec![(1, 2, "")]),
+            vec![hunk(0..2, 0..1, &["a\n", "b"], &["ab"])]
+        );
+        // Removing the last newline, then appending to the last line
+        assert_eq!(
+            line_diff("a\nb\n", vec![(3, 4, ""), (4, 4, "c\n")]),
+            vec![hunk(1..2, 1..2, &["b\n"], &["bc\n"])]
+        );
+    }
+
+    #[test]
+    fn test_line_diff_out_of_bounds() {
+        let delta = Delta::new(vec![Fragment {
+            start: 2,
+            end: 10,
+            content: vec![],
+        }])
+        .unwrap();
+        assert!(delta.to_line_diff(b"a\nb\n").is_err());
+    }
+
+    #[test]
+    fn test_compose_chain() {
+        let text = b"aaaaabbbbbccccc";
+        let deltas = vec![
+            Delta::new(vec![Fragment {
+                start: 5,
+                end: 10,
+                content: (&b"xxx"[..]).into(),
+            }])
+            .unwrap(),
+            Delta::new(vec![Fragment {
+                start: 0,
+                end: 0,
+                content: (&b"yy"[..]).into(),
+            }])
+            .unwrap(),
+        ];
+
+        let composed = compose_chain(text.len(), &deltas).unwrap();
+        assert_eq!(&apply(text, &composed).unwrap()[..], b"yyaaaaaxxxccccc");
+        assert_eq!(compose_chain(text.len(), &[]).unwrap(), Delta::default());
+        // The first delta is out of bounds for a shorter text.
+        assert!(compose_chain(4, &deltas).is_err());
+    }
+
+    #[test]
+    fn test_apply_adversarial_fragments() {
+        let text = b"aaaa";
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        let test_cases = vec![
+            // Fragment end past the end of the text.
+            vec![Fragment { start: 0, end: usize::MAX, content: vec![] }],
+            vec![Fragment { start: usize::MAX, end: usize::MAX, content: vec![b'x'] }],
+            vec![Fragment { start: i64::MAX as usize + 1, end: usize::MAX, content: vec![] }],
+            // Start after end.
+            vec![Fragment { start: 3, end: 1, content: vec![] }],
+            // Overlapping fragments.
+            vec![Fragment { start: 0, end: 3, content: vec![] },
+                 Fragment { start: 2, end: 4, content: vec![] }],
+            // Unsorted fragments.
+            vec![Fragment { start: 2, end: 3, content: vec![] },
+                 Fragment { start: 0, end: 1, content: vec![] }],
+        ];
+
+        for frags in test_cases {
+            let delta = Delta { frags };
+            assert!(apply(text, &delta).is_err(), "{:?}", delta);
+            assert!(
+                apply_chain(text, vec![delta.clone()]).is_err(),
+                "{:?}",
+                delta
+            );
+            // Adversarial deltas must also be rejected when they're not the first in the chain.
+            let chain = vec![Delta::new_fulltext(&b"bbbb"[..]), delta.clone()];
+            assert!(apply_chain(text, chain).is_err(), "{:?}", delta);
+        }
+    }
 }
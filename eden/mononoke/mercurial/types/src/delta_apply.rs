@@ -5,12 +5,14 @@
  * GNU General Public License version 2.
  */
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use bytes::{Bytes, BytesMut};
 use heapsize_derive::HeapSizeOf;
 use std::cmp;
+use std::convert::TryFrom;
 
 use crate::delta::{Delta, Fragment};
+use crate::errors::ErrorKind;
 
 /*
 * Algorithm is taken from fbcode/scm/hg/mercurial/mpatch.c
@@ -20,13 +22,13 @@ use crate::delta::{Delta, Fragment};
 /// Gather all contents hold fragments contents in one vector.
 pub fn wrap_deltas<I: IntoIterator<Item = Delta>>(
     deltas: I,
-) -> (Vec<FragmentWrapperIterator>, Bytes) {
+) -> Result<(Vec<FragmentWrapperIterator>, Bytes)> {
     let mut wrapped_deltas = Vec::new();
     let mut data = BytesMut::new();
     let mut content_offset = 0;
 
     for delta in deltas {
-        let wrapped_delta = FragmentWrapperIterator::new(&delta, content_offset as i64);
+        let wrapped_delta = FragmentWrapperIterator::new(&delta, to_i64(content_offset)?)?;
         for frag in delta.fragments() {
             data.extend_from_slice(frag.content.as_slice());
             content_offset += frag.content.len();
@@ -35,7 +37,13 @@ pub fn wrap_deltas<I: IntoIterator<Item = Delta>>(
         wrapped_deltas.push(wrapped_delta);
     }
 
-    (wrapped_deltas, data.freeze())
+    Ok((wrapped_deltas, data.freeze()))
+}
+
+fn to_i64(offset: usize) -> Result<i64> {
+    i64::try_from(offset).map_err(|_| {
+        ErrorKind::InvalidFragmentList(format!("offset {} is too large", offset)).into()
+    })
 }
 
 // Fragment Wrapper, it does not have actual data, only references to real data
@@ -55,26 +63,27 @@ pub struct FragmentWrapperIterator {
 }
 
 impl FragmentWrapperIterator {
-    pub fn new(delta: &Delta, content_offset: i64) -> FragmentWrapperIterator {
+    pub fn new(delta: &Delta, content_offset: i64) -> Result<FragmentWrapperIterator> {
         // Convert Delta to Vec<FragmentWrapper>, using global offset of the content in Content Bytes
         let mut frag_wrappers = Vec::new();
         let mut offset = content_offset;
 
         for frag in delta.fragments() {
+            let len = to_i64(frag.content_length())?;
             let frag_wrapper = FragmentWrapper {
-                start: frag.start as i64,
-                end: frag.end as i64,
-                len: frag.content_length() as i64,
-                content_start: offset as i64,
+                start: to_i64(frag.start)?,
+                end: to_i64(frag.end)?,
+                len,
+                content_start: offset,
             };
-            offset += frag.content_length() as i64;
+            offset += len;
             frag_wrappers.push(frag_wrapper);
         }
 
-        FragmentWrapperIterator {
+        Ok(FragmentWrapperIterator {
             frags: frag_wrappers,
             cur_pointer: 0,
-        }
+        })
     }
 
     pub fn content_length(&self) -> i64 {
@@ -117,8 +126,27 @@ impl FragmentWrapperIterator {
         let mut frags = Vec::new();
 
         for frag_wrapper in self.frags.as_slice() {
+            ensure!(
+                frag_wrapper.start >= 0
+                    && frag_wrapper.start <= frag_wrapper.end
+                    && frag_wrapper.len >= 0
+                    && frag_wrapper.content_start >= 0,
+                ErrorKind::InvalidFragmentList(format!(
+                    "invalid folded fragment {:?}",
+                    frag_wrapper
+                ))
+            );
             let content_start = frag_wrapper.content_start as usize;
-            let content_end = (frag_wrapper.content_start + frag_wrapper.len) as usize;
+            let content_end = content_start + frag_wrapper.len as usize;
+            ensure!(
+                content_end <= data.len(),
+                ErrorKind::InvalidFragmentList(format!(
+                    "content {}..{} is out of bounds ({})",
+                    content_start,
+                    content_end,
+                    data.len()
+                ))
+            );
 
             let frag = Fragment {
                 start: frag_wrapper.start as usize,
@@ -250,16 +278,22 @@ pub fn mpatch_fold(
     deltas: &Vec<FragmentWrapperIterator>,
     start: usize,
     end: usize,
-) -> FragmentWrapperIterator {
-    assert!(start < end);
+) -> Result<FragmentWrapperIterator> {
+    ensure!(
+        start < end && end <= deltas.len(),
+        "Invalid fold range {}..{} for {} deltas",
+        start,
+        end,
+        deltas.len()
+    );
 
     if start + 1 == end {
-        deltas[start].clone()
+        Ok(deltas[start].clone())
     } else {
         let half_deltas_cnt = (end - start) / 2;
-        combine(
-            &mut mpatch_fold(deltas, start, start + half_deltas_cnt),
-            &mut mpatch_fold(deltas, start + half_deltas_cnt, end),
-        )
+        Ok(combine(
+            &mut mpatch_fold(deltas, start, start + half_deltas_cnt)?,
+            &mut mpatch_fold(deltas, start + half_deltas_cnt, end)?,
+        ))
     }
 }
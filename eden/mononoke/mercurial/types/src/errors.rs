@@ -7,6 +7,8 @@
 
 use thiserror::Error;
 
+use crate::nodehash::HgNodeHash;
+
 #[derive(Debug, Error)]
 pub enum ErrorKind {
     #[error("invalid sha-1 input: {0}")]
@@ -19,4 +21,9 @@ pub enum ErrorKind {
     BlobDeserializeError(String),
     #[error("imposssible to parse unknown rev flags")]
     UnknownRevFlags,
+    #[error("delta base mismatch: expected sha-1 {expected}, got {actual}")]
+    DeltaBaseMismatch {
+        expected: HgNodeHash,
+        actual: HgNodeHash,
+    },
 }
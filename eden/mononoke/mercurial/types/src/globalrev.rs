@@ -5,9 +5,10 @@
  * GNU General Public License version 2.
  */
 
-use anyhow::{bail, Error, Result};
+use anyhow::{bail, format_err, Error, Result};
 use mononoke_types::BonsaiChangeset;
-use std::str;
+use std::fmt;
+use std::str::{self, FromStr};
 
 pub const GLOBALREV_EXTRA: &str = "global_rev";
 
@@ -71,3 +72,103 @@ impl Globalrev {
         }
     }
 }
+
+/// A prefix of the decimal representation of a Globalrev
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct GlobalrevPrefix(u64);
+
+impl GlobalrevPrefix {
+    /// The inclusive ranges of the Globalrevs starting with this prefix, from the range of
+    /// Globalrevs with as many digits as the prefix up to the longest Globalrevs.
+    pub fn ranges(&self) -> Vec<(Globalrev, Globalrev)> {
+        if self.0 == 0 {
+            return vec![(Globalrev::new(0), Globalrev::new(0))];
+        }
+        let mut ranges = vec![];
+        let mut scale: u64 = 1;
+        while let Some(min) = self.0.checked_mul(scale) {
+            let max = min.saturating_add(scale - 1);
+            ranges.push((Globalrev::new(min), Globalrev::new(max)));
+            scale = match scale.checked_mul(10) {
+                Some(scale) => scale,
+                None => break,
+            };
+        }
+        ranges
+    }
+}
+
+impl FromStr for GlobalrevPrefix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            bail!("Globalrev prefix must be a decimal number, got {:?}", s);
+        }
+        if s.len() > 1 && s.starts_with('0') {
+            bail!("Globalrevs don't start with 0, got prefix {:?}", s);
+        }
+        s.parse::<u64>()
+            .map(GlobalrevPrefix)
+            .map_err(|e| format_err!("Invalid Globalrev prefix {:?}: {}", s, e))
+    }
+}
+
+impl fmt::Display for GlobalrevPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The type for resolving Globalrevs by prefix
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum GlobalrevsResolvedFromPrefix {
+    /// Found single Globalrev
+    Single(Globalrev),
+    /// Found several Globalrevs within the limit provided
+    Multiple(Vec<Globalrev>),
+    /// Found too many Globalrevs exceeding the limit provided
+    TooMany(Vec<Globalrev>),
+    /// Globalrev was not found
+    NoMatch,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_globalrev_prefix() -> Result<()> {
+        let ranges = GlobalrevPrefix::from_str("12")?.ranges();
+        assert_eq!(ranges.len(), 19);
+        assert_eq!(ranges[0], (Globalrev::new(12), Globalrev::new(12)));
+        assert_eq!(ranges[1], (Globalrev::new(120), Globalrev::new(129)));
+        assert_eq!(ranges[2], (Globalrev::new(1200), Globalrev::new(1299)));
+        assert_eq!(
+            ranges[18],
+            (
+                Globalrev::new(12_000_000_000_000_000_000),
+                Globalrev::new(12_999_999_999_999_999_999)
+            )
+        );
+
+        let ranges = GlobalrevPrefix::from_str("1")?.ranges();
+        assert_eq!(
+            ranges.last(),
+            Some(&(
+                Globalrev::new(10_000_000_000_000_000_000),
+                Globalrev::new(u64::MAX)
+            ))
+        );
+
+        assert_eq!(
+            GlobalrevPrefix::from_str("0")?.ranges(),
+            vec![(Globalrev::new(0), Globalrev::new(0))]
+        );
+        assert!(GlobalrevPrefix::from_str("").is_err());
+        assert!(GlobalrevPrefix::from_str("01").is_err());
+        assert!(GlobalrevPrefix::from_str("12a").is_err());
+        assert!(GlobalrevPrefix::from_str("99999999999999999999").is_err());
+        Ok(())
+    }
+}
@@ -84,7 +84,7 @@ pub use fsencode::{fncache_fsencode, simple_fsencode};
 // Re-exports from mononoke_types. Eventually these should go away and everything should depend
 // directly on mononoke_types;
 pub use file::FileBytes;
-pub use globalrev::Globalrev;
+pub use globalrev::{Globalrev, GlobalrevPrefix, GlobalrevsResolvedFromPrefix};
 pub use mononoke_types::{FileType, MPath, MPathElement, RepoPath};
 pub use node::Node;
 pub use nodehash::{
@@ -388,6 +388,7 @@ impl RepoConfigs {
                 name: raw_hook_config.name,
                 hook_type: HookType::from_str(&raw_hook_config.hook_type)?,
                 config,
+                depends_on: raw_hook_config.depends_on.unwrap_or_default(),
             };
 
             all_hook_params.push(hook_params);
@@ -533,6 +534,7 @@ impl RepoConfigs {
 
         let hook_manager_params = this.hook_manager_params.map(|params| HookManagerParams {
             disable_acl_checker: params.disable_acl_checker,
+            run_hooks_on_bonsai: params.run_hooks_on_bonsai,
         });
         let bookmarks = {
             let mut bookmark_params = Vec::new();
@@ -641,6 +643,7 @@ impl RepoConfigs {
                 generate_lfs_blob_in_hg_sync_job: lfs_params
                     .generate_lfs_blob_in_hg_sync_job
                     .unwrap_or(false),
+                always_lfs: lfs_params.always_lfs.unwrap_or(false),
             },
             None => LfsParams::default(),
         };
@@ -714,9 +717,17 @@ impl RepoConfigs {
 
         let source_control_service = this
             .source_control_service
-            .map(|source_control_service| SourceControlServiceParams {
-                permit_writes: source_control_service.permit_writes,
+            .map(|source_control_service| -> Result<_> {
+                Ok(SourceControlServiceParams {
+                    permit_writes: source_control_service.permit_writes,
+                    resolve_cache_size: source_control_service
+                        .resolve_cache_size
+                        .map(|v| v.try_into())
+                        .transpose()?
+                        .unwrap_or(0),
+                })
             })
+            .transpose()?
             .unwrap_or(SourceControlServiceParams::default());
 
         let source_control_service_monitoring = this
@@ -1353,6 +1364,7 @@ mod test {
             commit_limit=100
             [hook_manager_params]
             disable_acl_checker=false
+            run_hooks_on_bonsai=true
 
             [derived_data_config]
             derived_data_types=["fsnodes"]
@@ -1396,6 +1408,7 @@ mod test {
             name="rust:rusthook"
             hook_type="PerChangeset"
             config_ints={ int1 = 44 }
+            depends_on=["rust:otherhook"]
 
             [push]
             pure_push_allowed = false
@@ -1411,6 +1424,7 @@ mod test {
             threshold = 1000
             rollout_percentage = 56
             generate_lfs_blob_in_hg_sync_job = true
+            always_lfs = true
 
             [bundle2_replay_params]
             preserve_raw_bundle2 = true
@@ -1423,6 +1437,10 @@ mod test {
             chunk_size = 768
             concurrency = 48
 
+            [source_control_service]
+            permit_writes = false
+            resolve_cache_size = 1000
+
             [source_control_service_monitoring]
             bookmarks_to_report_age= ["master", "master2"]
         "#;
@@ -1517,6 +1535,7 @@ mod test {
                 }),
                 hook_manager_params: Some(HookManagerParams {
                     disable_acl_checker: false,
+                    run_hooks_on_bonsai: true,
                 }),
                 bookmarks_cache_ttl: Some(Duration::from_millis(5000)),
                 bookmarks: vec![
@@ -1544,6 +1563,7 @@ mod test {
                             strings: hashmap! {},
                             ints: hashmap! {},
                         },
+                        depends_on: vec![],
                     },
                     HookParams {
                         name: "rust:rusthook".to_string(),
@@ -1555,6 +1575,7 @@ mod test {
                                 "int1".into() => 44,
                             },
                         },
+                        depends_on: vec!["rust:otherhook".to_string()],
                     },
                 ],
                 push: PushParams {
@@ -1578,6 +1599,7 @@ mod test {
                     threshold: Some(1000),
                     rollout_percentage: 56,
                     generate_lfs_blob_in_hg_sync_job: true,
+                    always_lfs: true,
                 },
                 wireproto_logging: WireprotoLoggingConfig {
                     scribe_category: Some("category".to_string()),
@@ -1609,6 +1631,7 @@ mod test {
                 hipster_acl: Some("foo/test".to_string()),
                 source_control_service: SourceControlServiceParams {
                     permit_writes: false,
+                    resolve_cache_size: 1000,
                 },
                 source_control_service_monitoring: Some(SourceControlServiceMonitoring {
                     bookmarks_to_report_age: vec![
@@ -1991,6 +2014,41 @@ mod test {
         )
     }
 
+    #[fbinit::test]
+    fn test_gcs_storage(fb: FacebookInit) {
+        const REPO: &str = r#"
+        repoid = 123
+        storage_config = "gcs_store"
+
+        [storage.gcs_store.db.remote]
+        db_address = "some_db"
+
+        [storage.gcs_store.blobstore.gcs]
+        gcs_bucket = "some-bucket"
+        gcs_prefix = "repo123/"
+        gcs_credentials = "/etc/mononoke/gcs_token"
+        "#;
+
+        let paths = btreemap! {
+            "common/commitsyncmap.toml" => "",
+            "repos/test/server.toml" => REPO,
+        };
+
+        let tmp_dir = write_files(&paths);
+        let res = RepoConfigs::read_configs(fb, tmp_dir.path()).expect("read configs failed");
+
+        let blobstore = &res.repos["test"].storage_config.blobstore;
+        assert_eq!(
+            blobstore,
+            &BlobConfig::Gcs {
+                bucket: "some-bucket".into(),
+                prefix: "repo123/".into(),
+                credentials: Some("/etc/mononoke/gcs_token".into()),
+            }
+        );
+        assert!(!blobstore.is_local());
+    }
+
     #[fbinit::test]
     fn test_stray_fields(fb: FacebookInit) {
         const REPO: &str = r#"
@@ -217,12 +217,16 @@ pub struct CacheWarmupParams {
 pub struct HookManagerParams {
     /// Wether to disable the acl checker or not (intended for testing purposes)
     pub disable_acl_checker: bool,
+    /// Whether pushes run hooks on the pushed bonsai changesets rather than on their hg
+    /// changesets. The hook tailer always runs hooks on hg changesets.
+    pub run_hooks_on_bonsai: bool,
 }
 
 impl Default for HookManagerParams {
     fn default() -> Self {
         Self {
             disable_acl_checker: false,
+            run_hooks_on_bonsai: false,
         }
     }
 }
@@ -437,6 +441,9 @@ pub struct HookParams {
     pub hook_type: HookType,
     /// Configs that should be passed to hook
     pub config: HookConfig,
+    /// Hooks of the same type that have to finish before this one runs. Their outcomes are
+    /// passed to this hook, which is skipped unless they all accepted.
+    pub depends_on: Vec<String>,
 }
 
 /// Push configuration options
@@ -520,6 +527,9 @@ pub struct LfsParams {
     pub rollout_percentage: u32,
     /// Whether hg sync job should generate lfs blobs
     pub generate_lfs_blob_in_hg_sync_job: bool,
+    /// If set, clients that receive lfs pointers receive them for all files, including empty
+    /// ones. Otherwise only files larger than `threshold` are served as lfs.
+    pub always_lfs: bool,
 }
 
 /// Id used to discriminate diffirent underlying blobstore instances
@@ -708,6 +718,8 @@ pub enum BlobConfig {
         blobstores: Vec<(BlobstoreId, BlobConfig)>,
         /// Whether to attempt repair
         scrub_action: ScrubAction,
+        /// Maximum number of repairs in flight at once, across all reads. Unbounded if None.
+        scrub_repair_concurrency: Option<NonZeroUsize>,
         /// 1 in scuba_sample_rate samples will be logged.
         scuba_sample_rate: NonZeroU64,
         /// DB config to use for the sync queue
@@ -722,6 +734,16 @@ pub enum BlobConfig {
         /// TTL for each object we put in Manifold
         ttl: Duration,
     },
+    /// Store in a Google Cloud Storage bucket
+    Gcs {
+        /// Bucket of the backing GCS blobstore to connect to
+        bucket: String,
+        /// Prefix to be prepended to all the keys
+        prefix: String,
+        /// Path to a file holding the OAuth2 access token to authenticate with. Requests are
+        /// anonymous if None.
+        credentials: Option<PathBuf>,
+    },
 }
 
 impl BlobConfig {
@@ -732,7 +754,7 @@ impl BlobConfig {
 
         match self {
             Disabled | Files { .. } | Sqlite { .. } => true,
-            Manifold { .. } | Mysql { .. } | ManifoldWithTtl { .. } => false,
+            Manifold { .. } | Mysql { .. } | ManifoldWithTtl { .. } | Gcs { .. } => false,
             Multiplexed { blobstores, .. } | Scrub { blobstores, .. } => blobstores
                 .iter()
                 .map(|(_, config)| config)
@@ -765,6 +787,7 @@ impl BlobConfig {
                 scuba_sample_rate: *scuba_sample_rate,
                 blobstores,
                 scrub_action,
+                scrub_repair_concurrency: None,
                 queue_db: queue_db.clone(),
             };
         }
@@ -837,6 +860,11 @@ impl TryFrom<RawBlobstoreConfig> for BlobConfig {
                     ttl,
                 }
             }
+            RawBlobstoreConfig::gcs(def) => BlobConfig::Gcs {
+                bucket: def.gcs_bucket,
+                prefix: def.gcs_prefix,
+                credentials: def.gcs_credentials.map(PathBuf::from),
+            },
             RawBlobstoreConfig::UnknownField(_) => {
                 return Err(anyhow!("unsupported blobstore configuration"));
             }
@@ -1091,12 +1119,15 @@ impl Default for WireprotoLoggingConfig {
 pub struct SourceControlServiceParams {
     /// whether writes are permitted
     pub permit_writes: bool,
+    /// number of resolved changeset specifiers to cache in memory, or 0 to disable the cache
+    pub resolve_cache_size: usize,
 }
 
 impl Default for SourceControlServiceParams {
     fn default() -> Self {
         SourceControlServiceParams {
             permit_writes: false,
+            resolve_cache_size: 0,
         }
     }
 }
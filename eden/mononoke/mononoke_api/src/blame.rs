@@ -0,0 +1,50 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use bytes::Bytes;
+use mononoke_types::blame::Blame;
+
+use crate::specifiers::ChangesetId;
+
+/// Attribution of a single line of a file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BlameLine {
+    /// The changeset that introduced the line.
+    pub changeset_id: ChangesetId,
+    /// The number of the line (starting from 1) in the file as it was in that changeset.
+    pub origin_line_number: u32,
+}
+
+/// Blame of a file: the attribution of each of its lines, in order.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BlameData {
+    pub lines: Vec<BlameLine>,
+}
+
+impl BlameData {
+    pub(crate) fn new(blame: &Blame) -> Self {
+        let lines = blame
+            .lines()
+            .map(|(changeset_id, _path, origin_offset)| BlameLine {
+                changeset_id,
+                origin_line_number: origin_offset + 1,
+            })
+            .collect();
+        Self { lines }
+    }
+
+    /// Pairs each line of `content`, the content the blame was computed for, with its
+    /// attribution.  Lines that are not valid UTF-8 are converted lossily.
+    pub(crate) fn zip_content(self, content: &Bytes) -> Vec<(String, BlameLine)> {
+        content
+            .as_ref()
+            .split(|c| *c == b'\n')
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .zip(self.lines)
+            .collect()
+    }
+}
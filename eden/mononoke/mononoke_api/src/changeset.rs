@@ -11,31 +11,52 @@ use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
 
+use blame::{fetch_blame, BlameError, BlameRoot};
 use blobstore::Loadable;
+use bytes::Bytes;
 use changeset_info::ChangesetInfo;
 use chrono::{DateTime, FixedOffset};
 use cloned::cloned;
 use context::CoreContext;
 use derived_data::BonsaiDerived;
+use derived_data_utils::{derived_data_utils, POSSIBLE_DERIVED_TYPES};
 use fsnodes::RootFsnodeId;
 use futures::compat::{Future01CompatExt, Stream01CompatExt};
 use futures::stream::Stream;
 use futures_util::future::{self, try_join, try_join_all, FutureExt, Shared};
-use futures_util::stream::{StreamExt, TryStreamExt};
+use futures_util::stream::{self, StreamExt, TryStreamExt};
 use manifest::{Diff as ManifestDiff, Entry as ManifestEntry, ManifestOps, PathOrPrefix};
 use mercurial_types::Globalrev;
 pub use mononoke_types::Generation;
-use mononoke_types::{BonsaiChangeset, FileChange, MPath, MPathElement};
+use mononoke_types::{blame::Blame, BonsaiChangeset, FileChange, MPath, MPathElement};
+pub use phases::Phase;
 use reachabilityindex::ReachabilityIndex;
+use revset::AncestorsNodeStream;
 use unodes::RootUnodeManifestId;
 
-use crate::changeset_path::ChangesetPathContext;
+use crate::blame::{BlameData, BlameLine};
+use crate::changeset_path::{ChangesetPathContext, PathEntry};
 use crate::changeset_path_diff::ChangesetPathDiffContext;
 use crate::errors::MononokeError;
 use crate::path::MononokePath;
 use crate::repo::RepoContext;
 use crate::specifiers::{ChangesetId, GitSha1, HgChangesetId};
 
+/// Maximum number of derived data types that `ChangesetContext::derive`
+/// will derive concurrently.
+const DERIVE_CONCURRENCY: usize = 4;
+
+/// Options for `ChangesetContext::ancestors`.
+#[derive(Clone, Debug, Default)]
+pub struct AncestorsOpts {
+    /// Number of ancestors to leave out at the start of the listing.
+    pub skip: u64,
+    /// Return at most this many ancestors.
+    pub limit: u64,
+    /// Only follow the first parent of each changeset.
+    pub first_parent_only: bool,
+}
+
 #[derive(Clone)]
 pub struct ChangesetContext {
     repo: RepoContext,
@@ -48,6 +69,8 @@ pub struct ChangesetContext {
         Shared<Pin<Box<dyn Future<Output = Result<RootFsnodeId, MononokeError>> + Send>>>,
     root_unode_manifest_id:
         Shared<Pin<Box<dyn Future<Output = Result<RootUnodeManifestId, MononokeError>> + Send>>>,
+    generation: Shared<Pin<Box<dyn Future<Output = Result<Generation, MononokeError>> + Send>>>,
+    phase: Shared<Pin<Box<dyn Future<Output = Result<Phase, MononokeError>> + Send>>>,
 }
 
 impl fmt::Debug for ChangesetContext {
@@ -106,6 +129,32 @@ impl ChangesetContext {
             }
         };
         let root_unode_manifest_id = root_unode_manifest_id.boxed().shared();
+        let generation = {
+            cloned!(repo);
+            async move {
+                repo.blob_repo()
+                    .get_generation_number(repo.ctx().clone(), id)
+                    .compat()
+                    .await?
+                    .ok_or_else(|| {
+                        MononokeError::NotAvailable(format!(
+                            "Generation number missing for {:?}",
+                            &id
+                        ))
+                    })
+            }
+        };
+        let generation = generation.boxed().shared();
+        let phase = {
+            cloned!(repo);
+            async move {
+                let mut phases = repo.changeset_phases(vec![id]).await?;
+                phases.remove(&id).ok_or_else(|| {
+                    MononokeError::NotAvailable(format!("Phase missing for {:?}", &id))
+                })
+            }
+        };
+        let phase = phase.boxed().shared();
         Self {
             repo,
             id,
@@ -113,6 +162,8 @@ impl ChangesetContext {
             bonsai_changeset,
             root_fsnode_id,
             root_unode_manifest_id,
+            generation,
+            phase,
         }
     }
 
@@ -173,6 +224,54 @@ impl ChangesetContext {
         self.root_unode_manifest_id.clone().await
     }
 
+    /// Blame of the file at `path`: for each line, the changeset that introduced it and the
+    /// number of the line in that changeset.
+    ///
+    /// This uses derived blame data, so it fails with `NotAvailable` if blame is not enabled
+    /// for the repo.  Directories, binary files and paths that don't exist in this changeset
+    /// are errors too.
+    pub async fn blame(&self, path: MPath) -> Result<BlameData, MononokeError> {
+        let (_content, blame) = self.fetch_blame(path).await?;
+        Ok(BlameData::new(&blame))
+    }
+
+    /// Same as `blame`, but each line of the file is returned along with its attribution.
+    pub async fn blame_with_content(
+        &self,
+        path: MPath,
+    ) -> Result<Vec<(String, BlameLine)>, MononokeError> {
+        let (content, blame) = self.fetch_blame(path).await?;
+        Ok(BlameData::new(&blame).zip_content(&content))
+    }
+
+    async fn fetch_blame(&self, path: MPath) -> Result<(Bytes, Blame), MononokeError> {
+        let blob_repo = self.repo().blob_repo();
+        if !blob_repo
+            .get_derived_data_config()
+            .derived_data_types
+            .contains(BlameRoot::NAME)
+        {
+            return Err(MononokeError::NotAvailable(format!(
+                "blame is not enabled for repo {}",
+                self.repo().name()
+            )));
+        }
+
+        fetch_blame(self.ctx().clone(), blob_repo.clone(), self.id, path.clone())
+            .compat()
+            .await
+            .map_err(|error| match error {
+                BlameError::NoSuchPath(_) => MononokeError::PathNotFound(path.to_string()),
+                BlameError::IsDirectory(_) => MononokeError::InvalidRequest(error.to_string()),
+                BlameError::Rejected(reason) => MononokeError::BlameRejected {
+                    path: path.to_string(),
+                    reason,
+                },
+                BlameError::DeriveError(e) => MononokeError::from(e),
+                BlameError::Error(e) => MononokeError::from(e),
+            })
+    }
+
     /// Query the root directory in the repository at this changeset revision.
     pub fn root(&self) -> ChangesetPathContext {
         ChangesetPathContext::new(self.clone(), None)
@@ -188,6 +287,18 @@ impl ChangesetContext {
         Ok(ChangesetPathContext::new(self.clone(), path.try_into()?))
     }
 
+    /// Get what is at a path within the repository: a file along with its
+    /// type (symlinks are files whose content is the link target), a
+    /// directory, or nothing.  The path is looked up with a single traversal
+    /// of the changeset's fsnodes.
+    pub async fn path_content<P>(&self, path: P) -> Result<PathEntry, MononokeError>
+    where
+        P: TryInto<MononokePath>,
+        MononokeError: From<P::Error>,
+    {
+        self.path(path)?.entry().await
+    }
+
     pub async fn paths(
         &self,
         paths: impl Iterator<Item = MononokePath>,
@@ -278,14 +389,81 @@ impl ChangesetContext {
 
     /// The generation number of the given changeset
     pub async fn generation(&self) -> Result<Generation, MononokeError> {
-        self.repo()
-            .blob_repo()
-            .get_generation_number(self.ctx().clone(), self.id)
-            .compat()
-            .await?
-            .ok_or_else(|| {
-                MononokeError::NotAvailable(format!("Generation number missing for {:?}", &self.id))
+        self.generation.clone().await
+    }
+
+    /// Whether the changeset is public or draft
+    pub async fn phase(&self) -> Result<Phase, MononokeError> {
+        self.phase.clone().await
+    }
+
+    /// Whether each type of derived data that is enabled for this repo has
+    /// been derived for the changeset.
+    pub async fn derived_data_status(&self) -> Result<HashMap<String, bool>, MononokeError> {
+        let blob_repo = self.repo().blob_repo();
+        let statuses = blob_repo
+            .get_derived_data_config()
+            .derived_data_types
+            .iter()
+            .filter(|name| POSSIBLE_DERIVED_TYPES.contains(&name.as_str()))
+            .map(|name| async move {
+                let utils = derived_data_utils(blob_repo.clone(), name)?;
+                let pending = utils
+                    .pending(self.ctx().clone(), blob_repo.clone(), vec![self.id])
+                    .compat()
+                    .await?;
+                Ok::<_, MononokeError>((name.clone(), pending.is_empty()))
+            });
+        Ok(try_join_all(statuses).await?.into_iter().collect())
+    }
+
+    /// Derive the named types of derived data for the changeset.
+    ///
+    /// Requires write access to the repo.  Returns the outcome of derivation
+    /// for each requested type.
+    pub async fn derive(
+        &self,
+        types: Vec<String>,
+    ) -> Result<HashMap<String, Result<(), MononokeError>>, MononokeError> {
+        // Check the user is permitted to write to this repo.
+        self.repo().clone().write().await?;
+
+        let blob_repo = self.repo().blob_repo();
+        let enabled = &blob_repo.get_derived_data_config().derived_data_types;
+        let utils = types
+            .into_iter()
+            .map(|name| {
+                if !POSSIBLE_DERIVED_TYPES.contains(&name.as_str()) {
+                    return Err(MononokeError::InvalidRequest(format!(
+                        "unknown derived data type: {}",
+                        name
+                    )));
+                }
+                if !enabled.contains(&name) {
+                    return Err(MononokeError::InvalidRequest(format!(
+                        "derived data type {} is not enabled for repo {}",
+                        name,
+                        self.repo().name()
+                    )));
+                }
+                Ok(derived_data_utils(blob_repo.clone(), name)?)
             })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let results = stream::iter(utils)
+            .map(|utils| async move {
+                let result = utils
+                    .derive(self.ctx().clone(), blob_repo.clone(), self.id)
+                    .compat()
+                    .await
+                    .map(|_| ())
+                    .map_err(MononokeError::from);
+                (utils.name().to_string(), result)
+            })
+            .buffer_unordered(DERIVE_CONCURRENCY)
+            .collect()
+            .await;
+        Ok(results)
     }
 
     /// All commit extras as (name, value) pairs.
@@ -321,6 +499,70 @@ impl ChangesetContext {
         Ok(is_ancestor_of)
     }
 
+    /// List a page of the ancestors of this changeset, starting with the changeset itself.
+    ///
+    /// The first `opts.skip` ancestors are left out, and at most `opts.limit` are returned.
+    /// Ancestors are listed in order of decreasing generation number, or in parent order for
+    /// `first_parent_only`, so that consecutive pages don't overlap.
+    pub async fn ancestors(
+        &self,
+        opts: AncestorsOpts,
+    ) -> Result<Vec<ChangesetContext>, MononokeError> {
+        let skip = opts.skip.try_into().unwrap_or(usize::MAX);
+        let limit = opts.limit.try_into().unwrap_or(usize::MAX);
+        let ids = if opts.first_parent_only {
+            self.first_parent_ancestors(skip, limit).await?
+        } else {
+            AncestorsNodeStream::new(
+                self.ctx().clone(),
+                &self.repo().blob_repo().get_changeset_fetcher(),
+                self.id,
+            )
+            .compat()
+            .skip(skip)
+            .take(limit)
+            .try_collect::<Vec<_>>()
+            .await?
+        };
+        Ok(ids
+            .into_iter()
+            .map(|id| ChangesetContext::new(self.repo.clone(), id))
+            .collect())
+    }
+
+    /// Walk the first parents of this changeset. The generic ancestors stream can't be used
+    /// here, as it follows all the parents of merges.
+    async fn first_parent_ancestors(
+        &self,
+        skip: usize,
+        limit: usize,
+    ) -> Result<Vec<ChangesetId>, MononokeError> {
+        let changeset_fetcher = self.repo().blob_repo().get_changeset_fetcher();
+        let mut ids = Vec::new();
+        if limit == 0 {
+            return Ok(ids);
+        }
+        let mut skipped = 0;
+        let mut next = Some(self.id);
+        while let Some(cs_id) = next {
+            if skipped < skip {
+                skipped += 1;
+            } else {
+                ids.push(cs_id);
+                if ids.len() == limit {
+                    break;
+                }
+            }
+            next = changeset_fetcher
+                .get_parents(self.ctx().clone(), cs_id)
+                .compat()
+                .await?
+                .first()
+                .cloned();
+        }
+        Ok(ids)
+    }
+
     /// Returns differences between this changeset and some other changeset.
     ///
     /// `self` is considered the "new" changeset (so files missing there are "Removed")
@@ -172,8 +172,9 @@ impl ChangesetPathContext {
         self.fsnode_id.clone().await
     }
 
-    #[allow(dead_code)]
-    async fn unode_id(&self) -> Result<Option<Entry<ManifestUnodeId, FileUnodeId>>, MononokeError> {
+    pub(crate) async fn unode_id(
+        &self,
+    ) -> Result<Option<Entry<ManifestUnodeId, FileUnodeId>>, MononokeError> {
         self.unode_id.clone().await
     }
 
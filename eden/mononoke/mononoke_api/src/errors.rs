@@ -7,6 +7,7 @@
 
 use blobstore::LoadableError;
 use derived_data::DeriveError;
+use mononoke_types::blame::BlameRejected;
 use std::backtrace::Backtrace;
 use std::convert::Infallible;
 use std::error::Error as StdError;
@@ -53,6 +54,12 @@ pub enum MononokeError {
     },
     #[error("not available: {0}")]
     NotAvailable(String),
+    #[error("path not found: {0}")]
+    PathNotFound(String),
+    #[error("bookmark not found: {0}")]
+    BookmarkNotFound(String),
+    #[error("blame is not available for {path}: {reason}")]
+    BlameRejected { path: String, reason: BlameRejected },
     #[error("internal error: {0}")]
     InternalError(#[source] InternalError),
 }
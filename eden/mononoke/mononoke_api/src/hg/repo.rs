@@ -6,10 +6,15 @@
  */
 
 use blobrepo::BlobRepo;
+use blobstore::Loadable;
 use context::CoreContext;
-use futures::{compat::Stream01CompatExt, TryStream, TryStreamExt};
+use futures::{
+    compat::{Future01CompatExt, Stream01CompatExt},
+    stream, StreamExt, TryStream, TryStreamExt,
+};
 use hgproto::GettreepackArgs;
-use mercurial_types::{HgFileNodeId, HgManifestId};
+use manifest::{Entry, ManifestOps};
+use mercurial_types::{HgChangesetId, HgFileNodeId, HgManifestId};
 use mononoke_types::MPath;
 use repo_client::gettreepack_entries;
 
@@ -19,6 +24,9 @@ use crate::repo::RepoContext;
 
 use super::{HgFileContext, HgTreeContext};
 
+/// Number of paths `HgRepoContext::file_nodes` looks up at once
+const FILE_NODE_CONCURRENCY: usize = 100;
+
 #[derive(Clone)]
 pub struct HgRepoContext {
     repo: RepoContext,
@@ -60,6 +68,54 @@ impl HgRepoContext {
         HgTreeContext::new_check_exists(self.clone(), manifest_id).await
     }
 
+    /// Look up the `HgManifestId` of the root tree manifest of a changeset.
+    pub async fn manifest_id(
+        &self,
+        hg_cs_id: HgChangesetId,
+    ) -> Result<HgManifestId, MononokeError> {
+        let hg_cs = hg_cs_id
+            .load(self.ctx().clone(), self.blob_repo().blobstore())
+            .compat()
+            .await?;
+        Ok(hg_cs.manifestid())
+    }
+
+    /// Look up the `HgFileNodeId` of the file at `path` in a changeset, by walking the
+    /// changeset's manifest. Returns `None` if there is no file at that path, including when
+    /// the path is a directory.
+    pub async fn file_node(
+        &self,
+        path: MPath,
+        hg_cs_id: HgChangesetId,
+    ) -> Result<Option<HgFileNodeId>, MononokeError> {
+        let manifest_id = self.manifest_id(hg_cs_id).await?;
+        let entry = manifest_id
+            .find_entry(
+                self.ctx().clone(),
+                self.blob_repo().get_blobstore(),
+                Some(path),
+            )
+            .compat()
+            .await?;
+        Ok(match entry {
+            Some(Entry::Leaf((_, filenode_id))) => Some(filenode_id),
+            Some(Entry::Tree(_)) | None => None,
+        })
+    }
+
+    /// Look up the `HgFileNodeId`s of several `(path, changeset)` pairs, as `file_node` does.
+    /// The results are in the same order as the requests.
+    pub async fn file_nodes(
+        &self,
+        requests: Vec<(MPath, HgChangesetId)>,
+    ) -> Result<Vec<Option<HgFileNodeId>>, MononokeError> {
+        stream::iter(requests)
+            .map(|(path, hg_cs_id)| self.file_node(path, hg_cs_id))
+            .buffered(FILE_NODE_CONCURRENCY)
+            .try_collect()
+            .await
+    }
+
     /// Request all of the tree nodes in the repo under a given path.
     ///
     /// The caller must specify a list of desired versions of the subtree for
@@ -116,10 +172,11 @@ mod tests {
     use std::collections::BTreeSet;
     use std::sync::Arc;
 
+    use std::str::FromStr;
+
     use anyhow::Error;
-    use blobstore::Loadable;
     use fbinit::FacebookInit;
-    use futures::compat::Future01CompatExt;
+    use fixtures::{linear, many_files_dirs};
     use mononoke_types::ChangesetId;
     use tests_utils::CreateCommitContext;
 
@@ -188,6 +245,62 @@ mod tests {
         Ok(())
     }
 
+    #[fbinit::compat_test]
+    async fn test_manifest_id(fb: FacebookInit) -> Result<(), MononokeError> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = Repo::new_test(ctx.clone(), linear::getrepo(fb).await).await?;
+        let repo_ctx = RepoContext::new(ctx, Arc::new(repo))?;
+        let hg = repo_ctx.hg();
+
+        // The root manifest of this commit contains the files `1` and `files`.
+        let hg_cs_id = HgChangesetId::from_str("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536")?;
+        let expected = HgManifestId::from_str("81864b9c0ad7b524ea7dc662ab0318ac3b3ce3ea")?;
+        assert_eq!(hg.manifest_id(hg_cs_id).await?, expected);
+
+        let missing = HgChangesetId::from_str("0000000000000000000000000000000000000001")?;
+        assert!(hg.manifest_id(missing).await.is_err());
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_file_nodes(fb: FacebookInit) -> Result<(), MononokeError> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = Repo::new_test(ctx.clone(), many_files_dirs::getrepo(fb).await).await?;
+        let repo_ctx = RepoContext::new(ctx, Arc::new(repo))?;
+        let hg = repo_ctx.hg();
+
+        // The second commit of `many_files_dirs`, which adds `dir2/file_1_in_dir2`.
+        let hg_cs_id = HgChangesetId::from_str("2f866e7e549760934e31bf0420a873f65100ad63")?;
+        let file_1 = HgFileNodeId::from_str("b8e02f6433738021a065f94175c7cd23db5f05be")?;
+        let file_1_in_dir2 = HgFileNodeId::from_str("0eb86721b74ed44cf176ee48b5e95f0192dc2824")?;
+
+        assert_eq!(
+            hg.file_node(MPath::new("1")?, hg_cs_id).await?,
+            Some(file_1)
+        );
+        assert_eq!(
+            hg.file_node(MPath::new("dir2/file_1_in_dir2")?, hg_cs_id)
+                .await?,
+            Some(file_1_in_dir2)
+        );
+
+        let file_nodes = hg
+            .file_nodes(vec![
+                (MPath::new("dir2/file_1_in_dir2")?, hg_cs_id),
+                (MPath::new("dir2")?, hg_cs_id),
+                (MPath::new("missing")?, hg_cs_id),
+                (MPath::new("1")?, hg_cs_id),
+            ])
+            .await?;
+        assert_eq!(
+            file_nodes,
+            vec![Some(file_1_in_dir2), None, None, Some(file_1)]
+        );
+
+        Ok(())
+    }
+
     /// Get the HgManifestId of the root tree manifest for the given commit.
     async fn root_manifest_id(
         ctx: CoreContext,
@@ -29,6 +29,7 @@ use metaconfig_types::{CommitSyncConfig, SourceControlServiceMonitoring};
 
 use crate::repo::Repo;
 
+pub mod blame;
 pub mod changeset;
 pub mod changeset_path;
 pub mod changeset_path_diff;
@@ -39,6 +40,7 @@ pub mod legacy;
 pub mod path;
 pub mod repo;
 pub mod repo_write;
+mod resolve_cache;
 pub mod specifiers;
 pub mod tree;
 
@@ -47,7 +49,8 @@ mod test;
 
 pub use crate::legacy::get_content_by_path;
 
-pub use crate::changeset::{ChangesetContext, Generation};
+pub use crate::blame::{BlameData, BlameLine};
+pub use crate::changeset::{AncestorsOpts, ChangesetContext, Generation, Phase};
 pub use crate::changeset_path::{
     unified_diff, ChangesetPathContext, CopyInfo, PathEntry, UnifiedDiff, UnifiedDiffMode,
 };
@@ -55,11 +58,14 @@ pub use crate::changeset_path_diff::ChangesetPathDiffContext;
 pub use crate::errors::MononokeError;
 pub use crate::file::{FileContext, FileId, FileMetadata, FileType};
 pub use crate::path::MononokePath;
-pub use crate::repo::RepoContext;
+pub use crate::repo::{
+    BookmarkComparison, HistoryOpts, ManifestDiffEntry, RepoContext, RepoHealth, RepoInfo,
+    RepoStats,
+};
 pub use crate::repo_write::{CreateChange, CreateCopyInfo, RepoWriteContext};
 pub use crate::specifiers::{
     ChangesetId, ChangesetIdPrefix, ChangesetPrefixSpecifier, ChangesetSpecifier,
-    ChangesetSpecifierPrefixResolution, HgChangesetId, HgChangesetIdPrefix,
+    ChangesetSpecifierPrefixResolution, GlobalrevPrefix, HgChangesetId, HgChangesetIdPrefix,
 };
 pub use crate::tree::{TreeContext, TreeEntry, TreeId, TreeSummary};
 
@@ -7,55 +7,65 @@
 
 use std::fmt;
 use std::{
+    convert::TryFrom,
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use aclchecker::AclChecker;
 use anyhow::{bail, format_err, Error};
 use blobrepo::BlobRepo;
 use blobrepo_factory::{BlobrepoBuilder, BlobstoreOptions, Caching, ReadOnlyStorage};
-use blobstore::Loadable;
+use blobstore::{Blobstore, Loadable};
 use blobstore_factory::make_sql_factory;
-use bookmarks::{BookmarkName, BookmarkPrefix};
+use bookmarks::{BookmarkName, BookmarkPrefix, BookmarkUpdateReason, Freshness};
 use changeset_info::ChangesetInfo;
+use cloned::cloned;
 use context::CoreContext;
 use cross_repo_sync::{CommitSyncRepos, CommitSyncer};
 use derived_data::BonsaiDerived;
+use fastlog::{list_file_history, RootFastlog};
 use fbinit::FacebookInit;
 use filestore::{Alias, FetchKey};
 use futures::compat::{Future01CompatExt, Stream01CompatExt};
-use futures::future::{self, try_join, try_join_all, TryFutureExt};
-use futures::StreamExt as NewStreamExt;
+use futures::future::{self, try_join, try_join3, try_join_all, TryFutureExt};
+use futures::stream::{BoxStream, Stream as NewStream};
+use futures::{StreamExt as NewStreamExt, TryStreamExt};
 use futures_ext::StreamExt;
 use futures_old::stream::{self, Stream};
 use identity::Identity;
 use itertools::Itertools;
+use manifest::{find_intersection_of_diffs, Entry};
 use mercurial_types::Globalrev;
 use metaconfig_types::{
-    CommitSyncConfig, CommonConfig, RepoConfig, SourceControlServiceMonitoring,
-    SourceControlServiceParams,
+    BookmarkOrRegex, CommitSyncConfig, CommonConfig, RepoConfig, RepoReadOnly,
+    SourceControlServiceMonitoring, SourceControlServiceParams,
 };
 use mononoke_types::{
-    hash::{GitSha1, Sha1, Sha256},
-    Generation,
+    hash::{Blake2, GitSha1, Sha1, Sha256},
+    Generation, MPath, MononokeId, Timestamp,
+};
+use reachabilityindex::LeastCommonAncestorsHint;
+use revset::{
+    greatest_common_ancestor, AncestorsNodeStream, DifferenceOfUnionsOfAncestorsNodeStream,
 };
-use revset::AncestorsNodeStream;
 use skiplist::{fetch_skiplist_index, SkiplistIndex};
 use slog::{debug, error, Logger};
 use sql_ext::facebook::MysqlOptions;
 #[cfg(test)]
 use sql_ext::SqlConstructors;
 use stats_facebook::service_data::{get_service_data_singleton, ServiceData};
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use synced_commit_mapping::{SqlSyncedCommitMapping, SyncedCommitMapping};
+use unodes::RootUnodeManifestId;
 use warm_bookmarks_cache::WarmBookmarksCache;
 
-use crate::changeset::ChangesetContext;
+use crate::changeset::{ChangesetContext, Phase};
 use crate::errors::MononokeError;
-use crate::file::{FileContext, FileId};
+use crate::file::{FileContext, FileId, FileType};
 use crate::hg::HgRepoContext;
 use crate::repo_write::RepoWriteContext;
+use crate::resolve_cache::{ResolveCache, NEGATIVE_RESOLVE_CACHE_TTL};
 use crate::specifiers::{
     ChangesetId, ChangesetPrefixSpecifier, ChangesetSpecifier, ChangesetSpecifierPrefixResolution,
     HgChangesetId,
@@ -67,6 +77,11 @@ const STALENESS_INFIX: &'static str = "staleness.secs";
 const MISSING_FROM_CACHE_INFIX: &'static str = "missing_from_cache";
 const MISSING_FROM_REPO_INFIX: &'static str = "missing_from_repo";
 const ACL_CHECKER_TIMEOUT_MS: u32 = 10_000;
+const EXISTENCE_CHECK_BATCH_SIZE: usize = 100;
+const HEALTHCHECK_PROBE_KEY: &'static str = "healthcheck.probe";
+const RESOLVE_CACHE_HITS_INFIX: &'static str = "resolve_cache.hits";
+const RESOLVE_CACHE_MISSES_INFIX: &'static str = "resolve_cache.misses";
+const SERVICE_WRITES_DISABLED: &'static str = "service writes are not enabled for this repo";
 
 pub(crate) struct Repo {
     pub(crate) name: String,
@@ -76,10 +91,82 @@ pub(crate) struct Repo {
     // This doesn't really belong here, but until we have production mappings, we can't do a better job
     pub(crate) synced_commit_mapping: Arc<dyn SyncedCommitMapping>,
     pub(crate) service_config: SourceControlServiceParams,
+    pub(crate) readonly: RepoReadOnly,
     // Needed to report stats
     pub(crate) monitoring_config: Option<SourceControlServiceMonitoring>,
     pub(crate) acl_checker: Option<Arc<AclChecker>>,
     pub(crate) commit_sync_config: Option<CommitSyncConfig>,
+    pub(crate) resolve_cache: Option<ResolveCache>,
+    pub(crate) info: RepoInfo,
+}
+
+/// The result of `RepoContext::healthcheck`. Checks that failed with an error are `false`, and
+/// their errors are in `errors`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepoHealth {
+    /// The blobstore could be queried.
+    pub blobstore_ok: bool,
+    /// The SQL database could be queried.
+    pub sql_ok: bool,
+    /// The warm bookmarks cache has been populated, or the repo has no publishing bookmarks.
+    pub warm_cache_populated: bool,
+    /// The skiplist index has been loaded.
+    pub skiplist_loaded: bool,
+    /// Errors from any checks that failed.
+    pub errors: Vec<String>,
+}
+
+/// The result of `RepoContext::repo_info`: the parts of the repo's config that are safe to show
+/// to service clients. Storage, ACL and logging config, e.g. database addresses and scuba
+/// tables, is deliberately left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepoInfo {
+    /// Files larger than this many bytes are stored in LFS, if LFS is enabled.
+    pub lfs_threshold: Option<u64>,
+    /// Whether the repo can be written to through the source control service.
+    pub writes_permitted: bool,
+    /// How long bookmarks are cached for, if they are cached.
+    pub bookmarks_cache_ttl: Option<Duration>,
+    /// The types of derived data that are derived for this repo.
+    pub derived_data_types: BTreeSet<String>,
+    /// The hooks run for each configured bookmark or bookmark regex, in config order.
+    pub bookmark_hooks: Vec<(String, Vec<String>)>,
+    /// Bookmarks and bookmark regexes that only allow fast-forward moves, in config order.
+    pub only_fast_forward_bookmarks: Vec<String>,
+}
+
+impl RepoInfo {
+    pub(crate) fn from_config(config: &RepoConfig) -> Self {
+        let bookmark_name = |bookmark: &BookmarkOrRegex| match bookmark {
+            BookmarkOrRegex::Bookmark(name) => name.to_string(),
+            BookmarkOrRegex::Regex(regex) => regex.as_str().to_string(),
+        };
+        Self {
+            lfs_threshold: config.lfs.threshold,
+            writes_permitted: config.source_control_service.permit_writes
+                && config.readonly == RepoReadOnly::ReadWrite,
+            bookmarks_cache_ttl: config.bookmarks_cache_ttl,
+            derived_data_types: config.derived_data_config.derived_data_types.clone(),
+            bookmark_hooks: config
+                .bookmarks
+                .iter()
+                .filter(|params| !params.hooks.is_empty())
+                .map(|params| (bookmark_name(&params.bookmark), params.hooks.clone()))
+                .collect(),
+            only_fast_forward_bookmarks: config
+                .bookmarks
+                .iter()
+                .filter(|params| params.only_fast_forward)
+                .map(|params| bookmark_name(&params.bookmark))
+                .collect(),
+        }
+    }
+}
+
+impl RepoHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.blobstore_ok && self.sql_ok && self.warm_cache_populated && self.errors.is_empty()
+    }
 }
 
 #[derive(Clone)]
@@ -106,6 +193,7 @@ pub async fn open_synced_commit_mapping(
         config.storage_config.dbconfig,
         mysql_options,
         readonly_storage,
+        None,
         logger.clone(),
     )
     .compat()
@@ -127,6 +215,7 @@ impl Repo {
         blobstore_options: BlobstoreOptions,
     ) -> Result<Self, Error> {
         let skiplist_index_blobstore_key = config.skiplist_index_blobstore_key.clone();
+        let info = RepoInfo::from_config(&config);
 
         let synced_commit_mapping = open_synced_commit_mapping(
             fb,
@@ -137,6 +226,11 @@ impl Repo {
         )
         .await?;
         let service_config = config.source_control_service.clone();
+        let readonly = config.readonly.clone();
+        let resolve_cache = match service_config.resolve_cache_size {
+            0 => None,
+            size => Some(ResolveCache::new(size, NEGATIVE_RESOLVE_CACHE_TTL)),
+        };
         let monitoring_config = config.source_control_service_monitoring.clone();
 
         let builder = BlobrepoBuilder::new(
@@ -194,9 +288,12 @@ impl Repo {
             warm_bookmarks_cache,
             synced_commit_mapping,
             service_config,
+            readonly,
             monitoring_config,
             acl_checker,
             commit_sync_config: config.commit_sync_config,
+            resolve_cache,
+            info,
         })
     }
 
@@ -218,10 +315,14 @@ impl Repo {
             synced_commit_mapping,
             service_config: SourceControlServiceParams {
                 permit_writes: false,
+                resolve_cache_size: 0,
             },
+            readonly: RepoReadOnly::ReadWrite,
             monitoring_config,
             acl_checker: None,
             commit_sync_config,
+            resolve_cache: None,
+            info: RepoInfo::default(),
         }
     }
 
@@ -275,14 +376,22 @@ impl Repo {
             synced_commit_mapping,
             service_config: SourceControlServiceParams {
                 permit_writes: true,
+                resolve_cache_size: 0,
             },
+            readonly: RepoReadOnly::ReadWrite,
             monitoring_config: None,
             acl_checker: None,
             commit_sync_config,
+            resolve_cache: None,
+            info: RepoInfo {
+                writes_permitted: true,
+                ..Default::default()
+            },
         })
     }
 
     pub async fn report_monitoring_stats(&self, ctx: &CoreContext) -> Result<(), MononokeError> {
+        self.report_resolve_cache_stats(ctx);
         match self.monitoring_config.as_ref() {
             None => Ok(()),
             Some(monitoring_config) => {
@@ -299,6 +408,25 @@ impl Repo {
         get_service_data_singleton(ctx.fb).set_counter(name, value);
     }
 
+    fn report_resolve_cache_stats(&self, ctx: &CoreContext) {
+        if let Some(resolve_cache) = self.resolve_cache.as_ref() {
+            for (infix, value) in [
+                (RESOLVE_CACHE_HITS_INFIX, resolve_cache.hits()),
+                (RESOLVE_CACHE_MISSES_INFIX, resolve_cache.misses()),
+            ]
+            .iter()
+            {
+                let counter_name = format!(
+                    "{}.{}.{}",
+                    COMMON_COUNTER_PREFIX,
+                    infix,
+                    self.blob_repo.get_repoid(),
+                );
+                self.set_counter(ctx, &counter_name, *value as i64);
+            }
+        }
+    }
+
     fn report_bookmark_missing_from_cache(&self, ctx: &CoreContext, bookmark: &BookmarkName) {
         error!(
             ctx.logger(),
@@ -379,19 +507,6 @@ impl Repo {
         if let (Some(service_bcs_id), Some(blobrepo_bcs_id)) =
             (maybe_bcs_id_from_service, maybe_bcs_id_from_blobrepo)
         {
-            // We report the difference between current time (i.e. SystemTime::now())
-            // and timestamp of the first child of bookmark value from cache (see graph below)
-            //
-            //       O <- bookmark value from blobrepo
-            //       |
-            //      ...
-            //       |
-            //       O <- first child of bookmark value from cache.
-            //       |
-            //       O <- bookmark value from cache, it's outdated
-            //
-            // This way of reporting shows for how long the oldest commit not in cache hasn't been
-            // imported, and it should work correctly both for high and low commit rates.
             debug!(
                 ctx.logger(),
                 "Reporting bookmark age difference for {}: latest {} value is {}, cache points to {}",
@@ -401,39 +516,65 @@ impl Repo {
                 service_bcs_id,
             );
 
-            let difference = if blobrepo_bcs_id == service_bcs_id {
-                0
-            } else {
-                let limit = 100;
-                let maybe_child = self
-                    .try_find_child(ctx, service_bcs_id, blobrepo_bcs_id, limit)
-                    .await?;
-
-                // If we can't find a child of a bookmark value from cache, then it might mean
-                // that either cache is too far behind or there was a non-forward bookmark move.
-                // Either way, we can't really do much about it here, so let's just find difference
-                // between current timestamp and bookmark value from cache.
-                let compare_bcs_id = maybe_child.unwrap_or(service_bcs_id);
-
-                let compare_timestamp = compare_bcs_id
-                    .load(ctx.clone(), repo.blobstore())
-                    .compat()
-                    .await?
-                    .author_date()
-                    .timestamp_secs();
-
-                let current_timestamp = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .map_err(Error::from)?;
-                let current_timestamp = current_timestamp.as_secs() as i64;
-                current_timestamp - compare_timestamp
-            };
+            let difference = self
+                .bookmark_staleness(ctx, service_bcs_id, blobrepo_bcs_id)
+                .await?;
             self.report_bookmark_staleness(ctx, bookmark, difference);
         }
 
         Ok(())
     }
 
+    /// How many seconds the bookmark value from the warm bookmarks cache is behind the
+    /// latest value from blobrepo.
+    async fn bookmark_staleness(
+        &self,
+        ctx: &CoreContext,
+        service_bcs_id: ChangesetId,
+        blobrepo_bcs_id: ChangesetId,
+    ) -> Result<i64, MononokeError> {
+        // This is the difference between current time (i.e. SystemTime::now())
+        // and timestamp of the first child of bookmark value from cache (see graph below)
+        //
+        //       O <- bookmark value from blobrepo
+        //       |
+        //      ...
+        //       |
+        //       O <- first child of bookmark value from cache.
+        //       |
+        //       O <- bookmark value from cache, it's outdated
+        //
+        // This shows for how long the oldest commit not in cache hasn't been imported,
+        // and it should work correctly both for high and low commit rates.
+        if blobrepo_bcs_id == service_bcs_id {
+            return Ok(0);
+        }
+
+        let limit = 100;
+        let maybe_child = self
+            .try_find_child(ctx, service_bcs_id, blobrepo_bcs_id, limit)
+            .await?;
+
+        // If we can't find a child of a bookmark value from cache, then it might mean
+        // that either cache is too far behind or there was a non-forward bookmark move.
+        // Either way, we can't really do much about it here, so let's just find difference
+        // between current timestamp and bookmark value from cache.
+        let compare_bcs_id = maybe_child.unwrap_or(service_bcs_id);
+
+        let compare_timestamp = compare_bcs_id
+            .load(ctx.clone(), self.blob_repo.blobstore())
+            .compat()
+            .await?
+            .author_date()
+            .timestamp_secs();
+
+        let current_timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(Error::from)?;
+        let current_timestamp = current_timestamp.as_secs() as i64;
+        Ok(current_timestamp - compare_timestamp)
+    }
+
     /// Try to find a changeset that's ancestor of `descendant` and direct child of
     /// `ancestor`. Returns None if this commit doesn't exist (for example if `ancestor` is not
     /// actually an ancestor of `descendant`) or if child is too far away from descendant.
@@ -521,12 +662,63 @@ impl Repo {
     }
 }
 
+/// The result of `RepoContext::stats`. Only stats that are cheap to compute are included, so
+/// that they can be polled, e.g. by dashboards.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepoStats {
+    /// The number of publishing bookmarks.
+    pub publishing_bookmarks: u64,
+    /// The highest generation number of the changesets that publishing bookmarks point to, as a
+    /// proxy for the depth of the history. Commits aren't counted, as that takes a traversal of
+    /// the whole history. None if there are no publishing bookmarks.
+    pub max_generation: Option<u64>,
+}
+
+/// The result of `RepoContext::compare_bookmarks`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BookmarkComparison {
+    /// The number of commits reachable from the first bookmark but not from the second.
+    pub ahead: u64,
+    /// The number of commits reachable from the second bookmark but not from the first.
+    pub behind: u64,
+    /// The nearest common ancestor of the two bookmarks, if their histories meet.
+    pub merge_base: Option<ChangesetId>,
+}
+
 #[derive(Default)]
 pub struct Stack {
     pub draft: HashSet<ChangesetId>,
     pub public: HashSet<ChangesetId>,
 }
 
+/// Options for `RepoContext::file_history`.
+#[derive(Clone, Debug, Default)]
+pub struct HistoryOpts {
+    /// Return at most this many changesets.
+    pub limit: Option<usize>,
+    /// Once the history of the path runs out at a changeset that copied or moved the file
+    /// there, continue with the history of the path it was copied from.
+    pub follow_renames: bool,
+    /// Leave merge changesets out of the history.
+    pub skip_merges: bool,
+}
+
+/// A tree or file in the output of `RepoContext::manifest_diff`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestDiffEntry {
+    Tree(TreeId),
+    Leaf(FileId, FileType),
+}
+
+/// State of the `RepoContext::file_history` stream.
+struct FileHistoryState {
+    /// History of the path currently being followed.
+    segment: BoxStream<'static, Result<ChangesetContext, MononokeError>>,
+    path: MPath,
+    /// Where the file at `path` was copied from, if this has been found in `segment`.
+    copied_from: Option<(ChangesetId, MPath)>,
+}
+
 /// A context object representing a query to a particular repo.
 impl RepoContext {
     pub(crate) fn new(ctx: CoreContext, repo: Arc<Repo>) -> Result<Self, MononokeError> {
@@ -572,11 +764,54 @@ impl RepoContext {
             .contains(ChangesetInfo::NAME)
     }
 
+    /// Force derivation of the named types of derived data for a changeset, so that later
+    /// requests don't pay for it. Only changeset info, unodes and fastlog can be derived this
+    /// way. The derivation itself is done by `ChangesetContext::derive`, so each type must be
+    /// enabled for the repo, and write access to the repo is required.
+    pub async fn derive(&self, cs: ChangesetId, kinds: &[&str]) -> Result<(), MononokeError> {
+        let warmup_types = [
+            ChangesetInfo::NAME,
+            RootUnodeManifestId::NAME,
+            RootFastlog::NAME,
+        ];
+        if let Some(kind) = kinds.iter().find(|kind| !warmup_types.contains(kind)) {
+            return Err(MononokeError::InvalidRequest(format!(
+                "derived data type {} can't be force-derived",
+                kind
+            )));
+        }
+
+        let results = ChangesetContext::new(self.clone(), cs)
+            .derive(kinds.iter().map(|kind| kind.to_string()).collect())
+            .await?;
+        for (_kind, result) in results {
+            result?;
+        }
+        Ok(())
+    }
+
     /// Look up a changeset specifier to find the canonical bonsai changeset
     /// ID for a changeset.
     pub async fn resolve_specifier(
         &self,
         specifier: ChangesetSpecifier,
+    ) -> Result<Option<ChangesetId>, MononokeError> {
+        match self.repo.resolve_cache.as_ref() {
+            Some(resolve_cache) => {
+                if let Some(id) = resolve_cache.get(&specifier) {
+                    return Ok(id);
+                }
+                let id = self.resolve_specifier_uncached(specifier).await?;
+                resolve_cache.insert(specifier, id);
+                Ok(id)
+            }
+            None => self.resolve_specifier_uncached(specifier).await,
+        }
+    }
+
+    async fn resolve_specifier_uncached(
+        &self,
+        specifier: ChangesetSpecifier,
     ) -> Result<Option<ChangesetId>, MononokeError> {
         let id = match specifier {
             ChangesetSpecifier::Bonsai(cs_id) => {
@@ -612,6 +847,26 @@ impl RepoContext {
         Ok(id)
     }
 
+    /// Check which of the given bonsai changesets exist in the repo, with a single query.
+    pub async fn changesets_exist(
+        &self,
+        ids: Vec<ChangesetId>,
+    ) -> Result<HashMap<ChangesetId, bool>, MononokeError> {
+        let existing: HashSet<_> = self
+            .blob_repo()
+            .get_changesets_object()
+            .get_many(self.ctx.clone(), self.blob_repo().get_repoid(), ids.clone())
+            .compat()
+            .await?
+            .into_iter()
+            .map(|cs_entry| cs_entry.cs_id)
+            .collect();
+        Ok(ids
+            .into_iter()
+            .map(|id| (id, existing.contains(&id)))
+            .collect())
+    }
+
     /// Resolve a bookmark to a changeset.
     pub async fn resolve_bookmark(
         &self,
@@ -634,6 +889,91 @@ impl RepoContext {
         Ok(cs_id.map(|cs_id| ChangesetContext::new(self.clone(), cs_id)))
     }
 
+    /// Resolve a bookmark to a changeset, only using the value from the warm
+    /// bookmarks cache if it is no more than `max_staleness` behind the value
+    /// in blobrepo.  Otherwise the value from blobrepo is used.
+    pub async fn resolve_bookmark_fresh(
+        &self,
+        bookmark: impl AsRef<str>,
+        max_staleness: Duration,
+    ) -> Result<Option<ChangesetContext>, MononokeError> {
+        let bookmark = BookmarkName::new(bookmark.as_ref())?;
+        let cached_cs_id = self.warm_bookmarks_cache().get(&bookmark);
+        let latest_cs_id = self
+            .blob_repo()
+            .get_bonsai_bookmark(self.ctx.clone(), &bookmark)
+            .compat()
+            .await?;
+
+        let cs_id = match (cached_cs_id, latest_cs_id) {
+            (Some(cached_cs_id), Some(latest_cs_id)) => {
+                let staleness = self
+                    .repo
+                    .bookmark_staleness(&self.ctx, cached_cs_id, latest_cs_id)
+                    .await?;
+                let max_staleness = i64::try_from(max_staleness.as_secs()).unwrap_or(i64::MAX);
+                if staleness <= max_staleness {
+                    Some(cached_cs_id)
+                } else {
+                    Some(latest_cs_id)
+                }
+            }
+            (_, latest_cs_id) => latest_cs_id,
+        };
+
+        Ok(cs_id.map(|cs_id| ChangesetContext::new(self.clone(), cs_id)))
+    }
+
+    /// Compare two bookmarks, e.g. to tell how far one branch is ahead of another.
+    pub async fn compare_bookmarks(
+        &self,
+        a: &str,
+        b: &str,
+    ) -> Result<BookmarkComparison, MononokeError> {
+        let (a, b) = try_join(
+            self.resolve_existing_bookmark(a),
+            self.resolve_existing_bookmark(b),
+        )
+        .await?;
+
+        let changeset_fetcher = self.blob_repo().get_changeset_fetcher();
+        let lca_hint: Arc<dyn LeastCommonAncestorsHint> = self.repo.skiplist_index.clone();
+        let count_only_in = |only: ChangesetId, excluded: ChangesetId| {
+            DifferenceOfUnionsOfAncestorsNodeStream::new_with_excludes(
+                self.ctx.clone(),
+                &changeset_fetcher,
+                lca_hint.clone(),
+                vec![only],
+                vec![excluded],
+            )
+            .compat()
+            .try_fold(0, |count, _| future::ok(count + 1))
+        };
+        let merge_base =
+            greatest_common_ancestor(self.ctx.clone(), changeset_fetcher.clone(), vec![a, b])
+                .compat()
+                .try_next();
+
+        let (ahead, behind, merge_base) =
+            try_join3(count_only_in(a, b), count_only_in(b, a), merge_base).await?;
+        Ok(BookmarkComparison {
+            ahead,
+            behind,
+            merge_base,
+        })
+    }
+
+    /// Resolve a bookmark to a changeset id, failing if it doesn't exist.
+    async fn resolve_existing_bookmark(
+        &self,
+        bookmark: &str,
+    ) -> Result<ChangesetId, MononokeError> {
+        self.resolve_bookmark(bookmark)
+            .await?
+            .map(|changeset| changeset.id())
+            .ok_or_else(|| MononokeError::BookmarkNotFound(bookmark.to_string()))
+    }
+
     /// Resolve a changeset id by its prefix
     pub async fn resolve_changeset_id_prefix(
         &self,
@@ -665,6 +1005,14 @@ impl RepoContext {
                     .compat()
                     .await?,
             ),
+            ChangesetPrefixSpecifier::Globalrev(prefix) => {
+                ChangesetSpecifierPrefixResolution::from(
+                    self.blob_repo()
+                        .get_globalrevs_by_prefix(prefix, MAX_LIMIT_AMBIGUOUS_IDS)
+                        .compat()
+                        .await?,
+                )
+            }
         };
         Ok(resolved)
     }
@@ -796,6 +1144,55 @@ impl RepoContext {
         }
     }
 
+    /// Get the log of moves of a bookmark, newest first, up to `limit` entries. The changeset
+    /// is `None` for entries where the bookmark was deleted.
+    pub fn bookmark_log(
+        &self,
+        name: &str,
+        limit: u32,
+    ) -> impl Stream<Item = (Option<ChangesetId>, BookmarkUpdateReason, Timestamp), Error = MononokeError>
+    {
+        let name = match BookmarkName::new(name) {
+            Ok(name) => name,
+            Err(e) => {
+                return stream::once(Err(MononokeError::InvalidRequest(format!(
+                    "invalid bookmark name: {}",
+                    e
+                ))))
+                .boxify()
+            }
+        };
+        self.blob_repo()
+            .list_bookmark_log_entries(self.ctx.clone(), name, limit, None, Freshness::MaybeStale)
+            .map_err(MononokeError::from)
+            .boxify()
+    }
+
+    /// Get the phases of multiple changesets. Changesets reachable from publishing bookmarks
+    /// are public, all others are draft.
+    pub async fn changeset_phases(
+        &self,
+        changesets: Vec<ChangesetId>,
+    ) -> Result<HashMap<ChangesetId, Phase>, MononokeError> {
+        let public = self
+            .blob_repo()
+            .get_phases()
+            .get_public(self.ctx.clone(), changesets.clone(), false)
+            .compat()
+            .await?;
+        Ok(changesets
+            .into_iter()
+            .map(|cs_id| {
+                let phase = if public.contains(&cs_id) {
+                    Phase::Public
+                } else {
+                    Phase::Draft
+                };
+                (cs_id, phase)
+            })
+            .collect())
+    }
+
     /// Get a stack for the list of heads (up to the first public commit).
     ///
     /// Limit represents the max depth to go into the stacks.
@@ -871,6 +1268,209 @@ impl RepoContext {
         Ok(Stack { draft, public })
     }
 
+    /// Stream the history of the file or directory at `path` in changeset `cs_id`, as
+    /// traversed by fastlog, starting with the most recent changeset that modified it.
+    pub fn file_history(
+        &self,
+        cs_id: ChangesetId,
+        path: MPath,
+        opts: HistoryOpts,
+    ) -> impl NewStream<Item = Result<ChangesetContext, MononokeError>> {
+        let HistoryOpts {
+            limit,
+            follow_renames,
+            skip_merges,
+        } = opts;
+        let state = FileHistoryState {
+            segment: self.path_history(cs_id, path.clone()),
+            path,
+            copied_from: None,
+        };
+        let repo = self.clone();
+
+        futures::stream::try_unfold(state, move |mut state| {
+            cloned!(repo);
+            async move {
+                loop {
+                    match state.segment.try_next().await? {
+                        Some(changeset) => {
+                            if follow_renames {
+                                if let Some(Some(file_change)) =
+                                    changeset.file_changes().await?.get(&state.path)
+                                {
+                                    if let Some((from_path, from_cs_id)) = file_change.copy_from() {
+                                        state.copied_from = Some((*from_cs_id, from_path.clone()));
+                                    }
+                                }
+                            }
+                            return Ok(Some((changeset, state)));
+                        }
+                        None => match state.copied_from.take() {
+                            Some((from_cs_id, from_path)) => {
+                                state.segment = repo.path_history(from_cs_id, from_path.clone());
+                                state.path = from_path;
+                            }
+                            None => return Ok(None),
+                        },
+                    }
+                }
+            }
+        })
+        .try_filter_map(move |changeset| async move {
+            if skip_merges && changeset.parents().await?.len() > 1 {
+                Ok(None)
+            } else {
+                Ok(Some(changeset))
+            }
+        })
+        .take(limit.unwrap_or(usize::MAX))
+    }
+
+    /// History of `path` in changeset `cs_id`, without following renames.
+    fn path_history(
+        &self,
+        cs_id: ChangesetId,
+        path: MPath,
+    ) -> BoxStream<'static, Result<ChangesetContext, MononokeError>> {
+        let changeset = ChangesetContext::new(self.clone(), cs_id);
+        async move {
+            let unode_entry = changeset
+                .path(path.clone())?
+                .unode_id()
+                .await?
+                .ok_or_else(|| {
+                    MononokeError::InvalidRequest(format!(
+                        "path '{}' does not exist in the given commit",
+                        path,
+                    ))
+                })?;
+            let repo = changeset.repo().clone();
+            let history = list_file_history(
+                repo.ctx().clone(),
+                repo.blob_repo().clone(),
+                Some(path),
+                unode_entry,
+            )
+            .compat()
+            .map_err(MononokeError::from)
+            .map_ok(move |cs_id| ChangesetContext::new(repo.clone(), cs_id));
+            Ok::<_, MononokeError>(history)
+        }
+        .try_flatten_stream()
+        .boxed()
+    }
+
+    /// Get the trees and files of changeset `other` that were added or changed relative to
+    /// changeset `base`, as raw manifest entries. Entries removed in `other` are not included.
+    pub async fn manifest_diff(
+        &self,
+        base: ChangesetId,
+        other: ChangesetId,
+    ) -> Result<Vec<(Option<MPath>, ManifestDiffEntry)>, MononokeError> {
+        let base = ChangesetContext::new(self.clone(), base);
+        let other = ChangesetContext::new(self.clone(), other);
+        let (base_root, other_root) =
+            try_join(base.root_fsnode_id(), other.root_fsnode_id()).await?;
+
+        let entries = find_intersection_of_diffs(
+            self.ctx.clone(),
+            self.blob_repo().get_blobstore(),
+            *other_root.fsnode_id(),
+            vec![*base_root.fsnode_id()],
+        )
+        .compat()
+        .map_ok(|(path, entry)| {
+            let entry = match entry {
+                Entry::Tree(tree_id) => ManifestDiffEntry::Tree(tree_id),
+                Entry::Leaf(file) => ManifestDiffEntry::Leaf(*file.content_id(), *file.file_type()),
+            };
+            (path, entry)
+        })
+        .try_collect()
+        .await?;
+        Ok(entries)
+    }
+
+    /// Check the health of the repo's storage and caches. Failures of individual checks are
+    /// reported in the result rather than failing the whole call.
+    pub async fn healthcheck(&self) -> Result<RepoHealth, MononokeError> {
+        let mut health = RepoHealth::default();
+
+        match self
+            .blob_repo()
+            .blobstore()
+            .is_present(self.ctx.clone(), HEALTHCHECK_PROBE_KEY.to_string())
+            .compat()
+            .await
+        {
+            Ok(_) => health.blobstore_ok = true,
+            Err(e) => health.errors.push(format!("blobstore: {:#}", e)),
+        }
+
+        // Any changeset id will do, we only care that the query succeeds.
+        match self
+            .blob_repo()
+            .get_changesets_object()
+            .get(
+                self.ctx.clone(),
+                self.blob_repo().get_repoid(),
+                ChangesetId::new(Blake2::from_byte_array([0; 32])),
+            )
+            .compat()
+            .await
+        {
+            Ok(_) => health.sql_ok = true,
+            Err(e) => health.errors.push(format!("sql: {:#}", e)),
+        }
+
+        match self
+            .blob_repo()
+            .get_bonsai_publishing_bookmarks_maybe_stale(self.ctx.clone())
+            .take(1)
+            .collect()
+            .compat()
+            .await
+        {
+            Ok(bookmarks) => {
+                health.warm_cache_populated =
+                    bookmarks.is_empty() || !self.warm_bookmarks_cache().get_all().is_empty();
+            }
+            Err(e) => health.errors.push(format!("bookmarks: {:#}", e)),
+        }
+
+        health.skiplist_loaded = self.skiplist_index().indexed_node_count() > 0;
+
+        Ok(health)
+    }
+
+    /// Get cheap stats about the repo, see `RepoStats`. Publishing bookmarks are read from the
+    /// warm bookmarks cache, unless it hasn't been populated yet.
+    pub async fn stats(&self) -> Result<RepoStats, MononokeError> {
+        let mut bookmarks = self.warm_bookmarks_cache().get_all();
+        if bookmarks.is_empty() {
+            bookmarks = self
+                .blob_repo()
+                .get_bonsai_publishing_bookmarks_maybe_stale(self.ctx.clone())
+                .map(|(bookmark, cs_id)| (bookmark.into_name(), cs_id))
+                .collect_to()
+                .compat()
+                .await?;
+        }
+
+        let changeset_fetcher = self.blob_repo().get_changeset_fetcher();
+        let generations = try_join_all(bookmarks.values().map(|cs_id| {
+            changeset_fetcher
+                .get_generation_number(self.ctx.clone(), *cs_id)
+                .compat()
+        }))
+        .await?;
+
+        Ok(RepoStats {
+            publishing_bookmarks: bookmarks.len() as u64,
+            max_generation: generations.iter().map(Generation::value).max(),
+        })
+    }
+
     /// Get a Tree by id.  Returns `None` if the tree doesn't exist.
     pub async fn tree(&self, tree_id: TreeId) -> Result<Option<TreeContext>, MononokeError> {
         TreeContext::new_check_exists(self.clone(), tree_id).await
@@ -881,6 +1481,47 @@ impl RepoContext {
         FileContext::new_check_exists(self.clone(), FetchKey::Canonical(file_id)).await
     }
 
+    /// Check which of the given trees exist in the repo, without loading them.
+    pub async fn trees_exist(
+        &self,
+        tree_ids: Vec<TreeId>,
+    ) -> Result<HashMap<TreeId, bool>, MononokeError> {
+        self.ids_exist(tree_ids).await
+    }
+
+    /// Check which of the given files exist in the repo, without loading them.
+    pub async fn files_exist(
+        &self,
+        file_ids: Vec<FileId>,
+    ) -> Result<HashMap<FileId, bool>, MononokeError> {
+        self.ids_exist(file_ids).await
+    }
+
+    async fn ids_exist<Id>(&self, ids: Vec<Id>) -> Result<HashMap<Id, bool>, MononokeError>
+    where
+        Id: MononokeId + Eq + std::hash::Hash,
+    {
+        let mut ids_by_key: HashMap<_, _> =
+            ids.into_iter().map(|id| (id.blobstore_key(), id)).collect();
+        let keys: Vec<_> = ids_by_key.keys().cloned().collect();
+        let blobstore = self.blob_repo().blobstore();
+        let mut exist = HashMap::new();
+        // Blobstores that support it (e.g. multiplexed ones) check a whole batch in fewer round
+        // trips, the others fall back to checking the keys one by one.
+        for batch in keys.chunks(EXISTENCE_CHECK_BATCH_SIZE) {
+            let present = blobstore
+                .is_present_many(self.ctx.clone(), batch.to_vec())
+                .compat()
+                .await?;
+            for (key, is_present) in present {
+                if let Some(id) = ids_by_key.remove(&key) {
+                    exist.insert(id, is_present);
+                }
+            }
+        }
+        Ok(exist)
+    }
+
     /// Get a File by content sha-1.  Returns `None` if the file doesn't exist.
     pub async fn file_by_content_sha1(
         &self,
@@ -931,11 +1572,28 @@ impl RepoContext {
         Ok(maybe_cs_id.map(|cs_id| ChangesetContext::new(other.clone(), cs_id)))
     }
 
+    /// A snapshot of the parts of this repo's config that are safe to show to clients.
+    pub fn repo_info(&self) -> RepoInfo {
+        self.repo.info.clone()
+    }
+
+    /// The reason this repo is read-only, if it is. A repo is read-only if it is configured as
+    /// such, or if it doesn't permit writes through the source control service.
+    pub fn is_read_only(&self) -> Option<String> {
+        match &self.repo.readonly {
+            RepoReadOnly::ReadOnly(reason) => Some(reason.clone()),
+            RepoReadOnly::ReadWrite if !self.repo.service_config.permit_writes => {
+                Some(String::from(SERVICE_WRITES_DISABLED))
+            }
+            RepoReadOnly::ReadWrite => None,
+        }
+    }
+
     /// Get a write context to make changes to this repository.
     pub async fn write(self) -> Result<RepoWriteContext, MononokeError> {
         if !self.repo.service_config.permit_writes {
             return Err(MononokeError::InvalidRequest(String::from(
-                "service writes are not enabled for this repo",
+                SERVICE_WRITES_DISABLED,
             )));
         }
 
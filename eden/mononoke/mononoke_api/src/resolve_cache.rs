@@ -0,0 +1,117 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+
+use crate::specifiers::{ChangesetId, ChangesetSpecifier};
+
+/// How long a specifier that didn't resolve to a changeset is cached for. This is kept short
+/// so that newly-landed commits can be found promptly.
+pub(crate) const NEGATIVE_RESOLVE_CACHE_TTL: Duration = Duration::from_secs(10);
+
+struct CacheEntry {
+    cs_id: Option<ChangesetId>,
+    inserted: Instant,
+}
+
+/// An in-memory LRU cache of the results of `RepoContext::resolve_specifier`.
+pub(crate) struct ResolveCache {
+    entries: Mutex<LruCache<ChangesetSpecifier, CacheEntry>>,
+    negative_ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResolveCache {
+    pub(crate) fn new(size: usize, negative_ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(size)),
+            negative_ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the cached result of resolving `specifier`, if there is one.
+    pub(crate) fn get(&self, specifier: &ChangesetSpecifier) -> Option<Option<ChangesetId>> {
+        let mut entries = self.entries.lock().expect("poisoned lock");
+        let cached = match entries.get(specifier) {
+            Some(entry)
+                if entry.cs_id.is_some() || entry.inserted.elapsed() < self.negative_ttl =>
+            {
+                Some(entry.cs_id)
+            }
+            Some(_) => {
+                entries.pop(specifier);
+                None
+            }
+            None => None,
+        };
+        let counter = match cached {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+
+    pub(crate) fn insert(&self, specifier: ChangesetSpecifier, cs_id: Option<ChangesetId>) {
+        let entry = CacheEntry {
+            cs_id,
+            inserted: Instant::now(),
+        };
+        self.entries
+            .lock()
+            .expect("poisoned lock")
+            .put(specifier, entry);
+    }
+
+    pub(crate) fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mononoke_types_mocks::changesetid::{ONES_CSID, TWOS_CSID};
+
+    #[test]
+    fn test_resolve_cache() {
+        let cache = ResolveCache::new(1, Duration::from_secs(3600));
+        let ones = ChangesetSpecifier::Bonsai(ONES_CSID);
+        let twos = ChangesetSpecifier::Bonsai(TWOS_CSID);
+        assert_eq!(cache.get(&ones), None);
+        cache.insert(ones, Some(ONES_CSID));
+        assert_eq!(cache.get(&ones), Some(Some(ONES_CSID)));
+        // The cache only has room for one entry.
+        cache.insert(twos, None);
+        assert_eq!(cache.get(&ones), None);
+        assert_eq!(cache.get(&twos), Some(None));
+        assert_eq!((cache.hits(), cache.misses()), (2, 2));
+    }
+
+    #[test]
+    fn test_resolve_cache_negative_expiry() {
+        let cache = ResolveCache::new(10, Duration::from_secs(0));
+        let ones = ChangesetSpecifier::Bonsai(ONES_CSID);
+        let twos = ChangesetSpecifier::Bonsai(TWOS_CSID);
+        cache.insert(ones, Some(ONES_CSID));
+        cache.insert(twos, None);
+        // Only results that didn't find a changeset expire.
+        assert_eq!(cache.get(&ones), Some(Some(ONES_CSID)));
+        assert_eq!(cache.get(&twos), None);
+    }
+}
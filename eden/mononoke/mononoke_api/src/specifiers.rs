@@ -38,11 +38,15 @@ pub type ChangesetIdPrefix = mononoke_types::ChangesetIdPrefix;
 /// A prefix of a Mercurial changeset ID.
 pub type HgChangesetIdPrefix = mercurial_types::HgChangesetIdPrefix;
 
+/// A prefix of the decimal representation of a Globalrev.
+pub type GlobalrevPrefix = mercurial_types::GlobalrevPrefix;
+
 /// This is prefix that may be used to resolve a changeset
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
 pub enum ChangesetPrefixSpecifier {
     Bonsai(ChangesetIdPrefix),
     Hg(HgChangesetIdPrefix),
+    Globalrev(GlobalrevPrefix),
 }
 
 impl From<HgChangesetIdPrefix> for ChangesetPrefixSpecifier {
@@ -57,6 +61,12 @@ impl From<ChangesetIdPrefix> for ChangesetPrefixSpecifier {
     }
 }
 
+impl From<GlobalrevPrefix> for ChangesetPrefixSpecifier {
+    fn from(prefix: GlobalrevPrefix) -> Self {
+        Self::Globalrev(prefix)
+    }
+}
+
 /// This is the result of resolving changesets by prefix
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
 pub enum ChangesetSpecifierPrefixResolution {
@@ -94,6 +104,19 @@ impl From<mononoke_types::ChangesetIdsResolvedFromPrefix> for ChangesetSpecifier
     }
 }
 
+impl From<mercurial_types::GlobalrevsResolvedFromPrefix> for ChangesetSpecifierPrefixResolution {
+    fn from(resolved: mercurial_types::GlobalrevsResolvedFromPrefix) -> Self {
+        use mercurial_types::GlobalrevsResolvedFromPrefix::*;
+        use ChangesetSpecifier::Globalrev as Rev;
+        match resolved {
+            Single(rev) => Self::Single(Rev(rev)),
+            Multiple(revs) => Self::Multiple(revs.into_iter().map(|rev| Rev(rev)).collect()),
+            TooMany(revs) => Self::TooMany(revs.into_iter().map(|rev| Rev(rev)).collect()),
+            NoMatch => Self::NoMatch,
+        }
+    }
+}
+
 impl fmt::Display for ChangesetSpecifier {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
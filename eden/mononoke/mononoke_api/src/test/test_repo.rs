@@ -5,34 +5,55 @@
  * GNU General Public License version 2.
  */
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Error;
-use blobstore::Loadable;
+use blobrepo::DangerousOverride;
+use blobstore::{DisabledBlob, Loadable};
+use bonsai_globalrev_mapping::{BonsaiGlobalrevMapping, BonsaiGlobalrevMappingEntry};
+use bonsai_hg_mapping::{BonsaiHgMapping, BonsaiHgMappingEntry, BonsaiOrHgChangesetIds};
+use bookmarks::{BookmarkName, BookmarkUpdateReason};
 use bytes::Bytes;
+use changesets::{ChangesetEntry, ChangesetInsert, Changesets};
 use chrono::{FixedOffset, TimeZone};
 use fbinit::FacebookInit;
-use fixtures::{branch_uneven, linear, many_files_dirs};
+use fixtures::{branch_uneven, linear, many_files_dirs, merge_even};
 use futures::compat::Future01CompatExt;
-use futures_old::Future;
+use futures_ext::BoxFuture;
+use futures_old::{Future, Stream};
 use futures_util::stream::TryStreamExt;
 
+use crate::repo::{Repo, RepoContext};
+use crate::resolve_cache::ResolveCache;
 use crate::{
-    ChangesetId, ChangesetIdPrefix, ChangesetPrefixSpecifier, ChangesetSpecifier,
-    ChangesetSpecifierPrefixResolution, CoreContext, FileId, FileMetadata, FileType, HgChangesetId,
-    HgChangesetIdPrefix, Mononoke, MononokePath, TreeEntry, TreeId,
+    AncestorsOpts, BlameLine, BookmarkComparison, ChangesetContext, ChangesetId, ChangesetIdPrefix,
+    ChangesetPrefixSpecifier, ChangesetSpecifier, ChangesetSpecifierPrefixResolution, CoreContext,
+    FileId, FileMetadata, FileType, GlobalrevPrefix, HgChangesetId, HgChangesetIdPrefix,
+    HistoryOpts, ManifestDiffEntry, Mononoke, MononokeError, MononokePath, PathEntry, Phase,
+    RepoInfo, RepoStats, TreeEntry, TreeId,
 };
 use cross_repo_sync_test_utils::init_small_large_repo;
+use derived_data::BonsaiDerived;
+use mercurial_types::{Globalrev, HgChangesetIdsResolvedFromPrefix};
+use metaconfig_types::{
+    BookmarkParams, DerivedDataConfig, LfsParams, RepoConfig, RepoReadOnly,
+    SourceControlServiceParams,
+};
 use mononoke_types::{
+    blame::BlameRejected,
     hash::{GitSha1, RichGitSha1, Sha1, Sha256},
-    MPath,
+    ChangesetIdsResolvedFromPrefix, MPath, RepositoryId,
 };
 use slog::info;
 use synced_commit_mapping::SyncedCommitMapping;
 use tests_utils::{bookmark, resolve_cs_id, CreateCommitContext};
+use unodes::RootUnodeManifestId;
+use warm_bookmarks_cache::WarmBookmarksCache;
 
 #[fbinit::compat_test]
 async fn commit_info_by_hash(fb: FacebookInit) -> Result<(), Error> {
@@ -61,6 +82,34 @@ async fn commit_info_by_hash(fb: FacebookInit) -> Result<(), Error> {
     Ok(())
 }
 
+#[fbinit::compat_test]
+async fn changesets_exist(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mononoke = Mononoke::new_test(
+        ctx.clone(),
+        vec![("test".to_string(), linear::getrepo(fb).await)],
+    )
+    .await?;
+    let repo = mononoke.repo(ctx, "test")?.expect("repo exists");
+    let existing =
+        ChangesetId::from_str("7785606eb1f26ff5722c831de402350cf97052dc44bc175da6ac0d715a3dbbf6")?;
+    let other_existing =
+        ChangesetId::from_str("2cb6d2d3052bfbdd6a95a61f2816d81130033b5f5a99e8d8fc24d9238d85bb48")?;
+    let missing =
+        ChangesetId::from_str("1111111111111111111111111111111111111111111111111111111111111111")?;
+
+    let exist = repo
+        .changesets_exist(vec![existing, missing, other_existing])
+        .await?;
+    let expected: HashMap<_, _> = vec![(existing, true), (missing, false), (other_existing, true)]
+        .into_iter()
+        .collect();
+    assert_eq!(exist, expected);
+    assert!(repo.changesets_exist(vec![]).await?.is_empty());
+
+    Ok(())
+}
+
 #[fbinit::compat_test]
 async fn commit_info_by_hg_hash(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
@@ -211,6 +260,110 @@ async fn commit_is_ancestor_of(fb: FacebookInit) -> Result<(), Error> {
     Ok(())
 }
 
+async fn set_bookmark(
+    ctx: &CoreContext,
+    repo: &RepoContext,
+    name: &str,
+    hg_hash: &str,
+) -> Result<ChangesetId, Error> {
+    let cs_id = repo
+        .changeset(ChangesetSpecifier::Hg(HgChangesetId::from_str(hg_hash)?))
+        .await?
+        .expect("changeset exists")
+        .id();
+    bookmark(ctx, repo.blob_repo(), name).set_to(cs_id).await?;
+    Ok(cs_id)
+}
+
+#[fbinit::compat_test]
+async fn compare_bookmarks(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mononoke = Mononoke::new_test(
+        ctx.clone(),
+        vec![
+            ("linear".to_string(), linear::getrepo(fb).await),
+            (
+                "merge".to_string(),
+                merge_even::getrepo_with_id(fb, RepositoryId::new(1)).await,
+            ),
+        ],
+    )
+    .await?;
+
+    let repo = mononoke.repo(ctx.clone(), "linear")?.expect("repo exists");
+    let root = set_bookmark(
+        &ctx,
+        &repo,
+        "root",
+        "2d7d4ba9ce0a6ffd222de7785b249ead9c51c536",
+    )
+    .await?;
+    assert_eq!(
+        repo.compare_bookmarks("master", "root").await?,
+        BookmarkComparison {
+            ahead: 10,
+            behind: 0,
+            merge_base: Some(root),
+        }
+    );
+    assert_eq!(
+        repo.compare_bookmarks("root", "master").await?,
+        BookmarkComparison {
+            ahead: 0,
+            behind: 10,
+            merge_base: Some(root),
+        }
+    );
+    match repo.compare_bookmarks("master", "missing").await {
+        Err(MononokeError::BookmarkNotFound(name)) => assert_eq!(name, "missing"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    let repo = mononoke.repo(ctx.clone(), "merge")?.expect("repo exists");
+    let base = repo
+        .changeset(ChangesetSpecifier::Hg(HgChangesetId::from_str(
+            "15c40d0abc36d47fb51c8eaec51ac7aad31f669c",
+        )?))
+        .await?
+        .expect("base exists")
+        .id();
+    // "I think 4 is a nice number", 3 commits into the first branch.
+    let branch1 = set_bookmark(
+        &ctx,
+        &repo,
+        "branch1",
+        "16839021e338500b3cf7c9b871c8a07351697d68",
+    )
+    .await?;
+    // "Replace the base", 3 commits into the second branch.
+    set_bookmark(
+        &ctx,
+        &repo,
+        "branch2",
+        "4f7f3fd428bec1a48f9314414b063c706d9c1aed",
+    )
+    .await?;
+    assert_eq!(
+        repo.compare_bookmarks("branch1", "branch2").await?,
+        BookmarkComparison {
+            ahead: 3,
+            behind: 3,
+            merge_base: Some(base),
+        }
+    );
+    // The merge has the second branch and itself on top of the first branch.
+    assert_eq!(
+        repo.compare_bookmarks("master", "branch1").await?,
+        BookmarkComparison {
+            ahead: 4,
+            behind: 0,
+            merge_base: Some(branch1),
+        }
+    );
+
+    Ok(())
+}
+
 #[fbinit::compat_test]
 async fn commit_find_files(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
@@ -336,6 +489,301 @@ async fn commit_path_exists_and_type(fb: FacebookInit) -> Result<(), Error> {
     Ok(())
 }
 
+#[fbinit::compat_test]
+async fn commit_path_content(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = blobrepo_factory::new_memblob_empty(None)?;
+    let cs_id = CreateCommitContext::new_root(&ctx, &blob_repo)
+        .add_file("dir/file", "content")
+        .add_file_with_type("link", "dir/file", FileType::Symlink)
+        .commit()
+        .await?;
+    let mononoke = Mononoke::new_test(ctx.clone(), vec![("test".to_string(), blob_repo)]).await?;
+    let repo = mononoke.repo(ctx, "test")?.expect("repo exists");
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(cs_id))
+        .await?
+        .expect("changeset exists");
+
+    match cs.path_content("dir/file").await? {
+        PathEntry::File(file, file_type) => {
+            assert_eq!(file_type, FileType::Regular);
+            assert_eq!(file.content_concat().await?, Bytes::from("content"));
+        }
+        _ => panic!("dir/file should be a file"),
+    }
+
+    match cs.path_content("link").await? {
+        PathEntry::File(file, file_type) => {
+            assert_eq!(file_type, FileType::Symlink);
+            assert_eq!(file.content_concat().await?, Bytes::from("dir/file"));
+        }
+        _ => panic!("link should be a file"),
+    }
+
+    match cs.path_content("dir").await? {
+        PathEntry::Tree(tree) => {
+            let entries: Vec<_> = tree.list().await?.map(|(name, _)| name).collect();
+            assert_eq!(entries, vec!["file".to_string()]);
+        }
+        _ => panic!("dir should be a tree"),
+    }
+
+    assert!(match cs.path_content("nonexistent").await? {
+        PathEntry::NotPresent => true,
+        _ => false,
+    });
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn file_history(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mononoke = Mononoke::new_test(
+        ctx.clone(),
+        vec![("test".to_string(), linear::getrepo(fb).await)],
+    )
+    .await?;
+    let repo = mononoke.repo(ctx, "test")?.expect("repo exists");
+    let cs_id = repo
+        .resolve_bookmark("master")
+        .await?
+        .expect("bookmark exists")
+        .id();
+
+    let opts = HistoryOpts {
+        limit: Some(3),
+        ..Default::default()
+    };
+    let history: Vec<_> = repo
+        .file_history(cs_id, MPath::new("files")?, opts)
+        .try_collect()
+        .await?;
+    let mut messages = Vec::new();
+    for cs in history {
+        messages.push(cs.message().await?);
+    }
+    assert_eq!(messages, vec!["added 10", "added 9", "added 8"]);
+
+    let history: Vec<_> = repo
+        .file_history(cs_id, MPath::new("10")?, HistoryOpts::default())
+        .try_collect()
+        .await?;
+    let mut messages = Vec::new();
+    for cs in history {
+        messages.push(cs.message().await?);
+    }
+    assert_eq!(messages, vec!["modified 10", "added 10"]);
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn file_history_renames_and_merges(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = blobrepo_factory::new_memblob_empty(None)?;
+    let root = CreateCommitContext::new_root(&ctx, &blob_repo)
+        .add_file("a", "1")
+        .commit()
+        .await?;
+    let modify_a = CreateCommitContext::new(&ctx, &blob_repo, vec![root])
+        .add_file("a", "2")
+        .commit()
+        .await?;
+    let move_a = CreateCommitContext::new(&ctx, &blob_repo, vec![modify_a])
+        .add_file_with_copy_info("b", "2", (modify_a, "a"))?
+        .delete_file("a")
+        .commit()
+        .await?;
+    let left = CreateCommitContext::new(&ctx, &blob_repo, vec![move_a])
+        .add_file("b", "left")
+        .commit()
+        .await?;
+    let right = CreateCommitContext::new(&ctx, &blob_repo, vec![move_a])
+        .add_file("b", "right")
+        .commit()
+        .await?;
+    let merge = CreateCommitContext::new(&ctx, &blob_repo, vec![left, right])
+        .add_file("b", "merged")
+        .commit()
+        .await?;
+    let mononoke = Mononoke::new_test(ctx.clone(), vec![("test".to_string(), blob_repo)]).await?;
+    let repo = mononoke.repo(ctx, "test")?.expect("repo exists");
+
+    let history: Vec<_> = repo
+        .file_history(merge, MPath::new("b")?, HistoryOpts::default())
+        .map_ok(|cs| cs.id())
+        .try_collect()
+        .await?;
+    assert_eq!(history.len(), 4);
+    assert_eq!(history[0], merge);
+    assert_eq!(
+        history[1..3].iter().cloned().collect::<HashSet<_>>(),
+        vec![left, right].into_iter().collect()
+    );
+    assert_eq!(history[3], move_a);
+
+    let opts = HistoryOpts {
+        follow_renames: true,
+        skip_merges: true,
+        ..Default::default()
+    };
+    let history: Vec<_> = repo
+        .file_history(merge, MPath::new("b")?, opts)
+        .map_ok(|cs| cs.id())
+        .try_collect()
+        .await?;
+    assert_eq!(history.len(), 5);
+    assert_eq!(
+        history[0..2].iter().cloned().collect::<HashSet<_>>(),
+        vec![left, right].into_iter().collect()
+    );
+    assert_eq!(history[2..], [move_a, modify_a, root]);
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn blame(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = blobrepo_factory::new_memblob_empty(None)?;
+    let first = CreateCommitContext::new_root(&ctx, &blob_repo)
+        .add_file("file", "a\nb\nc\n")
+        .add_file("binary", "bin\0ary\n")
+        .commit()
+        .await?;
+    let second = CreateCommitContext::new(&ctx, &blob_repo, vec![first])
+        .add_file("file", "a\nB\nc\nd\n")
+        .commit()
+        .await?;
+    let third = CreateCommitContext::new(&ctx, &blob_repo, vec![second])
+        .add_file("file", "x\na\nB\nd\n")
+        .commit()
+        .await?;
+    let no_blame_repo = blob_repo.dangerous_override(|mut config: DerivedDataConfig| {
+        config.derived_data_types.remove("blame");
+        config
+    });
+    let mononoke = Mononoke::new_test(
+        ctx.clone(),
+        vec![
+            ("test".to_string(), blob_repo),
+            ("no_blame".to_string(), no_blame_repo),
+        ],
+    )
+    .await?;
+    let repo = mononoke.repo(ctx.clone(), "test")?.expect("repo exists");
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(third))
+        .await?
+        .expect("changeset exists");
+
+    let line = |changeset_id, origin_line_number| BlameLine {
+        changeset_id,
+        origin_line_number,
+    };
+    let expected = vec![
+        line(third, 1),
+        line(first, 1),
+        line(second, 2),
+        line(second, 4),
+    ];
+    assert_eq!(cs.blame(MPath::new("file")?).await?.lines, expected);
+
+    let with_content = cs.blame_with_content(MPath::new("file")?).await?;
+    let expected_content = vec!["x", "a", "B", "d"];
+    assert_eq!(
+        with_content
+            .iter()
+            .map(|(content, _)| content.as_str())
+            .collect::<Vec<_>>(),
+        expected_content
+    );
+    assert_eq!(
+        with_content
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect::<Vec<_>>(),
+        expected
+    );
+
+    match cs.blame(MPath::new("missing")?).await {
+        Err(MononokeError::PathNotFound(path)) => assert_eq!(path, "missing"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    match cs.blame(MPath::new("binary")?).await {
+        Err(MononokeError::BlameRejected { reason, .. }) => {
+            assert_eq!(reason, BlameRejected::Binary)
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    let repo = mononoke.repo(ctx, "no_blame")?.expect("repo exists");
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(third))
+        .await?
+        .expect("changeset exists");
+    match cs.blame(MPath::new("file")?).await {
+        Err(MononokeError::NotAvailable(_)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn manifest_diff(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = blobrepo_factory::new_memblob_empty(None)?;
+    let base = CreateCommitContext::new_root(&ctx, &blob_repo)
+        .add_file("dir/a", "a")
+        .add_file("dir/b", "b")
+        .add_file("c", "c")
+        .commit()
+        .await?;
+    let other = CreateCommitContext::new(&ctx, &blob_repo, vec![base])
+        .add_file("dir/a", "modified a")
+        .add_file("e/f", "f")
+        .delete_file("c")
+        .commit()
+        .await?;
+    let mononoke = Mononoke::new_test(ctx.clone(), vec![("test".to_string(), blob_repo)]).await?;
+    let repo = mononoke.repo(ctx, "test")?.expect("repo exists");
+
+    let mut diff: Vec<_> = repo
+        .manifest_diff(base, other)
+        .await?
+        .into_iter()
+        .map(|(path, entry)| {
+            let is_tree = match entry {
+                ManifestDiffEntry::Tree(_) => true,
+                ManifestDiffEntry::Leaf(_, file_type) => {
+                    assert_eq!(file_type, FileType::Regular);
+                    false
+                }
+            };
+            (MononokePath::new(path).to_string(), is_tree)
+        })
+        .collect();
+    diff.sort();
+    assert_eq!(
+        diff,
+        vec![
+            (String::from(""), true),
+            (String::from("dir"), true),
+            (String::from("dir/a"), false),
+            (String::from("e"), true),
+            (String::from("e/f"), false),
+        ]
+    );
+
+    // Diffing a changeset against itself finds nothing.
+    assert!(repo.manifest_diff(other, other).await?.is_empty());
+
+    Ok(())
+}
+
 #[fbinit::compat_test]
 async fn tree_list(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
@@ -531,6 +979,115 @@ async fn file_metadata(fb: FacebookInit) -> Result<(), Error> {
     Ok(())
 }
 
+#[fbinit::compat_test]
+async fn files_and_trees_exist(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mononoke = Mononoke::new_test(
+        ctx.clone(),
+        vec![("test".to_string(), many_files_dirs::getrepo(fb).await)],
+    )
+    .await?;
+    let repo = mononoke.repo(ctx, "test")?.expect("repo exists");
+
+    let present_file =
+        FileId::from_str("9d9cf646b38852094ec48ab401eea6f4481cc89a80589331845dc08f75a652d2")?;
+    let absent_file =
+        FileId::from_str("0000000000000000000000000000000000000000000000000000000000000000")?;
+    let files = repo
+        .files_exist(vec![absent_file, present_file, absent_file])
+        .await?;
+    let mut expected = HashMap::new();
+    expected.insert(present_file, true);
+    expected.insert(absent_file, false);
+    assert_eq!(files, expected);
+
+    let hash = "b0d1bf77898839595ee0f0cba673dd6e3be9dadaaa78bc6dd2dea97ca6bee77e";
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(ChangesetId::from_str(hash)?))
+        .await?
+        .expect("changeset exists");
+    let present_tree = *cs.root().tree().await?.expect("root exists").id();
+    let absent_tree =
+        TreeId::from_str("0000000000000000000000000000000000000000000000000000000000000000")?;
+    let trees = repo
+        .trees_exist(vec![present_tree, absent_tree, present_tree])
+        .await?;
+    let mut expected = HashMap::new();
+    expected.insert(present_tree, true);
+    expected.insert(absent_tree, false);
+    assert_eq!(trees, expected);
+
+    assert!(repo.files_exist(vec![]).await?.is_empty());
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn healthcheck(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mononoke = Mononoke::new_test(
+        ctx.clone(),
+        vec![("test".to_string(), linear::getrepo(fb).await)],
+    )
+    .await?;
+    let repo = mononoke.repo(ctx, "test")?.expect("repo exists");
+
+    let health = repo.healthcheck().await?;
+    assert!(health.blobstore_ok);
+    assert!(health.sql_ok);
+    assert!(health.warm_cache_populated);
+    // Test repos are created with an empty skiplist index.
+    assert!(!health.skiplist_loaded);
+    assert!(health.errors.is_empty());
+    assert!(health.is_healthy());
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn healthcheck_disabled_blobstore(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo =
+        blobrepo_factory::new_memblob_empty(Some(Arc::new(DisabledBlob::new("disabled"))))?;
+    let mononoke = Mononoke::new_test(ctx.clone(), vec![("test".to_string(), blob_repo)]).await?;
+    let repo = mononoke.repo(ctx, "test")?.expect("repo exists");
+
+    let health = repo.healthcheck().await?;
+    assert!(!health.blobstore_ok);
+    assert!(health.sql_ok);
+    assert_eq!(health.errors.len(), 1);
+    assert!(health.errors[0].starts_with("blobstore:"));
+    assert!(!health.is_healthy());
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn stats(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mononoke = Mononoke::new_test(
+        ctx.clone(),
+        vec![
+            ("test".to_string(), linear::getrepo(fb).await),
+            (
+                "empty".to_string(),
+                blobrepo_factory::new_memblob_empty_with_id(None, RepositoryId::new(1))?,
+            ),
+        ],
+    )
+    .await?;
+
+    let repo = mononoke.repo(ctx.clone(), "test")?.expect("repo exists");
+    let stats = repo.stats().await?;
+    assert_eq!(stats.publishing_bookmarks, 1);
+    assert!(stats.max_generation.expect("master has a generation") > 0);
+
+    let repo = mononoke.repo(ctx, "empty")?.expect("repo exists");
+    assert_eq!(repo.stats().await?, RepoStats::default());
+
+    Ok(())
+}
+
 #[fbinit::compat_test]
 async fn file_contents(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
@@ -803,3 +1360,599 @@ async fn resolve_changeset_id_prefix(fb: FacebookInit) -> Result<(), Error> {
 
     Ok(())
 }
+
+#[fbinit::compat_test]
+async fn resolve_changeset_id_globalrev_prefix(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = linear::getrepo(fb).await;
+    let mut cs_id = resolve_cs_id(&ctx, &blob_repo, "master").await?;
+    let mut entries = vec![];
+    for globalrev in &[1000, 1001, 2000] {
+        entries.push(BonsaiGlobalrevMappingEntry::new(
+            blob_repo.get_repoid(),
+            cs_id,
+            Globalrev::new(*globalrev),
+        ));
+        cs_id = blob_repo
+            .get_changeset_parents_by_bonsai(ctx.clone(), cs_id)
+            .compat()
+            .await?[0];
+    }
+    blob_repo
+        .bonsai_globalrev_mapping()
+        .bulk_import(&entries)
+        .compat()
+        .await?;
+
+    let mononoke = Mononoke::new_test(ctx.clone(), vec![("test".to_string(), blob_repo)]).await?;
+    let repo = mononoke.repo(ctx, "test")?.expect("repo exists");
+    let resolve = |prefix: &str| {
+        let repo = repo.clone();
+        let prefix = GlobalrevPrefix::from_str(prefix);
+        async move { repo.resolve_changeset_id_prefix(prefix?.into()).await }
+    };
+
+    let globalrev = |rev| ChangesetSpecifier::Globalrev(Globalrev::new(rev));
+    for prefix in &["2", "20", "2000"] {
+        assert_eq!(
+            resolve(prefix).await?,
+            ChangesetSpecifierPrefixResolution::Single(globalrev(2000))
+        );
+    }
+    for prefix in &["1", "10", "100"] {
+        assert_eq!(
+            resolve(prefix).await?,
+            ChangesetSpecifierPrefixResolution::Multiple(vec![globalrev(1000), globalrev(1001)])
+        );
+    }
+    assert_eq!(
+        resolve("3").await?,
+        ChangesetSpecifierPrefixResolution::NoMatch
+    );
+
+    // not a decimal number
+    assert!(GlobalrevPrefix::from_str("10a").is_err());
+
+    Ok(())
+}
+
+/// Counts lookups in the bonsai-hg mapping.
+struct CountingBonsaiHgMapping {
+    inner: Arc<dyn BonsaiHgMapping>,
+    gets: Arc<AtomicUsize>,
+}
+
+impl BonsaiHgMapping for CountingBonsaiHgMapping {
+    fn add(&self, ctx: CoreContext, entry: BonsaiHgMappingEntry) -> BoxFuture<bool, Error> {
+        self.inner.add(ctx, entry)
+    }
+
+    fn get(
+        &self,
+        ctx: CoreContext,
+        repo_id: RepositoryId,
+        cs_id: BonsaiOrHgChangesetIds,
+    ) -> BoxFuture<Vec<BonsaiHgMappingEntry>, Error> {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        self.inner.get(ctx, repo_id, cs_id)
+    }
+
+    fn get_many_hg_by_prefix(
+        &self,
+        ctx: CoreContext,
+        repo_id: RepositoryId,
+        cs_prefix: HgChangesetIdPrefix,
+        limit: usize,
+    ) -> BoxFuture<HgChangesetIdsResolvedFromPrefix, Error> {
+        self.inner
+            .get_many_hg_by_prefix(ctx, repo_id, cs_prefix, limit)
+    }
+}
+
+/// Returns a `linear` repo with a resolve cache, and a count of lookups in its bonsai-hg
+/// mapping.
+async fn repo_with_resolve_cache(
+    fb: FacebookInit,
+    negative_ttl: Duration,
+) -> Result<(RepoContext, Arc<AtomicUsize>), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let gets = Arc::new(AtomicUsize::new(0));
+    let blob_repo = linear::getrepo(fb).await.dangerous_override({
+        let gets = gets.clone();
+        move |inner| -> Arc<dyn BonsaiHgMapping> {
+            Arc::new(CountingBonsaiHgMapping { inner, gets })
+        }
+    });
+    let mut repo = Repo::new_test(ctx.clone(), blob_repo).await?;
+    repo.resolve_cache = Some(ResolveCache::new(10, negative_ttl));
+    Ok((RepoContext::new(ctx, Arc::new(repo))?, gets))
+}
+
+#[fbinit::compat_test]
+async fn resolve_specifier_cache(fb: FacebookInit) -> Result<(), Error> {
+    let (repo, gets) = repo_with_resolve_cache(fb, Duration::from_secs(3600)).await?;
+    let hg_cs_id = HgChangesetId::from_str("79a13814c5ce7330173ec04d279bf95ab3f652fb")?;
+    let cs_id =
+        ChangesetId::from_str("7785606eb1f26ff5722c831de402350cf97052dc44bc175da6ac0d715a3dbbf6")?;
+    let missing = HgChangesetId::from_str("1111111111111111111111111111111111111111")?;
+
+    let before = gets.load(Ordering::Relaxed);
+    for _ in 0..3 {
+        assert_eq!(
+            repo.resolve_specifier(ChangesetSpecifier::Hg(hg_cs_id))
+                .await?,
+            Some(cs_id)
+        );
+        assert_eq!(
+            repo.resolve_specifier(ChangesetSpecifier::Hg(missing))
+                .await?,
+            None
+        );
+    }
+    // Only the first lookup of each went to the mapping.
+    assert_eq!(gets.load(Ordering::Relaxed) - before, 2);
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn resolve_specifier_cache_negative_expiry(fb: FacebookInit) -> Result<(), Error> {
+    let (repo, gets) = repo_with_resolve_cache(fb, Duration::from_secs(0)).await?;
+    let missing = HgChangesetId::from_str("1111111111111111111111111111111111111111")?;
+
+    let before = gets.load(Ordering::Relaxed);
+    for _ in 0..3 {
+        assert_eq!(
+            repo.resolve_specifier(ChangesetSpecifier::Hg(missing))
+                .await?,
+            None
+        );
+    }
+    // Negative results expire immediately, so every lookup went to the mapping.
+    assert_eq!(gets.load(Ordering::Relaxed) - before, 3);
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn resolve_bookmark_fresh(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = linear::getrepo(fb).await;
+    let master = BookmarkName::new("master")?;
+    let latest = resolve_cs_id(&ctx, &blob_repo, "master").await?;
+
+    // Stub a cache that is stuck on the parent of master.
+    let repo = RepoContext::new(
+        ctx.clone(),
+        Arc::new(Repo::new_test(ctx.clone(), blob_repo.clone()).await?),
+    )?;
+    let parents = repo
+        .changeset(ChangesetSpecifier::Bonsai(latest))
+        .await?
+        .expect("changeset exists")
+        .parents()
+        .await?;
+    let stale = parents[0];
+    let mut stale_repo = Repo::new_test(ctx.clone(), blob_repo).await?;
+    stale_repo.warm_bookmarks_cache = Arc::new(WarmBookmarksCache::new_static(
+        vec![(master, stale)].into_iter().collect(),
+    ));
+    let repo = RepoContext::new(ctx, Arc::new(stale_repo))?;
+
+    // The unbounded lookup uses whatever the cache holds.
+    let cs = repo
+        .resolve_bookmark("master")
+        .await?
+        .expect("bookmark exists");
+    assert_eq!(cs.id(), stale);
+
+    // The commits in the fixture are years old, so the cached value is far too stale.
+    let cs = repo
+        .resolve_bookmark_fresh("master", Duration::from_secs(3600))
+        .await?
+        .expect("bookmark exists");
+    assert_eq!(cs.id(), latest);
+
+    // A generous enough bound accepts the cached value.
+    let cs = repo
+        .resolve_bookmark_fresh("master", Duration::from_secs(u64::MAX))
+        .await?
+        .expect("bookmark exists");
+    assert_eq!(cs.id(), stale);
+
+    assert!(repo
+        .resolve_bookmark_fresh("missing", Duration::from_secs(3600))
+        .await?
+        .is_none());
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn is_read_only(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+
+    let repo = Repo::new_test(ctx.clone(), linear::getrepo(fb).await).await?;
+    let repo = RepoContext::new(ctx.clone(), Arc::new(repo))?;
+    assert_eq!(repo.is_read_only(), None);
+
+    let mut repo = Repo::new_test(ctx.clone(), linear::getrepo(fb).await).await?;
+    repo.readonly = RepoReadOnly::ReadOnly(String::from("repo is locked for migration"));
+    let repo = RepoContext::new(ctx.clone(), Arc::new(repo))?;
+    assert_eq!(
+        repo.is_read_only(),
+        Some(String::from("repo is locked for migration"))
+    );
+
+    let mut repo = Repo::new_test(ctx.clone(), linear::getrepo(fb).await).await?;
+    repo.service_config.permit_writes = false;
+    let repo = RepoContext::new(ctx.clone(), Arc::new(repo))?;
+    assert_eq!(
+        repo.is_read_only(),
+        Some(String::from("service writes are not enabled for this repo"))
+    );
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn bookmark_log(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = linear::getrepo(fb).await;
+    let first =
+        ChangesetId::from_str("7785606eb1f26ff5722c831de402350cf97052dc44bc175da6ac0d715a3dbbf6")?;
+    let second =
+        ChangesetId::from_str("2cb6d2d3052bfbdd6a95a61f2816d81130033b5f5a99e8d8fc24d9238d85bb48")?;
+
+    let name = BookmarkName::new("audited")?;
+    let mut txn = blob_repo.update_bookmark_transaction(ctx.clone());
+    txn.force_set(
+        &name,
+        first,
+        BookmarkUpdateReason::TestMove {
+            bundle_replay_data: None,
+        },
+    )?;
+    txn.commit().compat().await?;
+    let mut txn = blob_repo.update_bookmark_transaction(ctx.clone());
+    txn.force_set(&name, second, BookmarkUpdateReason::ManualMove)?;
+    txn.commit().compat().await?;
+
+    let mononoke = Mononoke::new_test(ctx.clone(), vec![("test".to_string(), blob_repo)]).await?;
+    let repo = mononoke.repo(ctx, "test")?.expect("repo exists");
+
+    let log: Vec<_> = repo
+        .bookmark_log("audited", 10)
+        .collect()
+        .compat()
+        .await?
+        .into_iter()
+        .map(|(cs_id, reason, _timestamp)| (cs_id, reason))
+        .collect();
+    assert_eq!(
+        log,
+        vec![
+            (Some(second), BookmarkUpdateReason::ManualMove),
+            (
+                Some(first),
+                BookmarkUpdateReason::TestMove {
+                    bundle_replay_data: None
+                }
+            ),
+        ]
+    );
+
+    let log: Vec<_> = repo.bookmark_log("audited", 1).collect().compat().await?;
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].0, Some(second));
+
+    Ok(())
+}
+
+/// Counts lookups in the changesets table.
+struct CountingChangesets {
+    inner: Arc<dyn Changesets>,
+    gets: Arc<AtomicUsize>,
+}
+
+impl Changesets for CountingChangesets {
+    fn add(&self, ctx: CoreContext, cs: ChangesetInsert) -> BoxFuture<bool, Error> {
+        self.inner.add(ctx, cs)
+    }
+
+    fn get(
+        &self,
+        ctx: CoreContext,
+        repo_id: RepositoryId,
+        cs_id: ChangesetId,
+    ) -> BoxFuture<Option<ChangesetEntry>, Error> {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        self.inner.get(ctx, repo_id, cs_id)
+    }
+
+    fn get_many(
+        &self,
+        ctx: CoreContext,
+        repo_id: RepositoryId,
+        cs_ids: Vec<ChangesetId>,
+    ) -> BoxFuture<Vec<ChangesetEntry>, Error> {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        self.inner.get_many(ctx, repo_id, cs_ids)
+    }
+
+    fn get_many_by_prefix(
+        &self,
+        ctx: CoreContext,
+        repo_id: RepositoryId,
+        cs_prefix: ChangesetIdPrefix,
+        limit: usize,
+    ) -> BoxFuture<ChangesetIdsResolvedFromPrefix, Error> {
+        self.inner
+            .get_many_by_prefix(ctx, repo_id, cs_prefix, limit)
+    }
+
+    fn prime_cache(&self, ctx: &CoreContext, changesets: &[ChangesetEntry]) {
+        self.inner.prime_cache(ctx, changesets)
+    }
+}
+
+#[fbinit::compat_test]
+async fn changeset_generation_and_phase(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let gets = Arc::new(AtomicUsize::new(0));
+    let blob_repo = linear::getrepo(fb).await.dangerous_override({
+        let gets = gets.clone();
+        move |inner| -> Arc<dyn Changesets> { Arc::new(CountingChangesets { inner, gets }) }
+    });
+    let master = resolve_cs_id(&ctx, &blob_repo, "master").await?;
+    let draft = CreateCommitContext::new(&ctx, &blob_repo, vec![master])
+        .add_file("draft", "draft")
+        .commit()
+        .await?;
+
+    let mononoke =
+        Mononoke::new_test(ctx.clone(), vec![("test".to_string(), blob_repo.clone())]).await?;
+    let repo = mononoke.repo(ctx.clone(), "test")?.expect("repo exists");
+
+    let phases = repo.changeset_phases(vec![master, draft]).await?;
+    assert_eq!(phases.len(), 2);
+    assert_eq!(phases[&master], Phase::Public);
+    assert_eq!(phases[&draft], Phase::Draft);
+
+    let master_cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(master))
+        .await?
+        .expect("changeset exists");
+    let draft_cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(draft))
+        .await?
+        .expect("changeset exists");
+    assert_eq!(master_cs.phase().await?, Phase::Public);
+    assert_eq!(draft_cs.phase().await?, Phase::Draft);
+    assert_eq!(
+        draft_cs.generation().await?.value(),
+        master_cs.generation().await?.value() + 1
+    );
+
+    // Both are cached in the changeset context after the first fetch.
+    let before = gets.load(Ordering::Relaxed);
+    for _ in 0..3 {
+        draft_cs.generation().await?;
+        draft_cs.phase().await?;
+    }
+    assert_eq!(gets.load(Ordering::Relaxed), before);
+
+    // Making the commit public doesn't change the phase cached in the existing context.
+    bookmark(&ctx, &blob_repo, "master").set_to(draft).await?;
+    assert_eq!(draft_cs.phase().await?, Phase::Draft);
+    let draft_cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(draft))
+        .await?
+        .expect("changeset exists");
+    assert_eq!(draft_cs.phase().await?, Phase::Public);
+
+    Ok(())
+}
+
+async fn ancestor_messages(
+    cs: &ChangesetContext,
+    opts: AncestorsOpts,
+) -> Result<Vec<String>, MononokeError> {
+    let mut messages = Vec::new();
+    for ancestor in cs.ancestors(opts).await? {
+        messages.push(ancestor.message().await?);
+    }
+    Ok(messages)
+}
+
+#[fbinit::compat_test]
+async fn ancestors(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mononoke = Mononoke::new_test(
+        ctx.clone(),
+        vec![("test".to_string(), merge_even::getrepo(fb).await)],
+    )
+    .await?;
+    let repo = mononoke.repo(ctx, "test")?.expect("repo exists");
+    let merge = repo
+        .changeset(ChangesetSpecifier::Hg(HgChangesetId::from_str(
+            "4dcf230cd2f20577cb3e88ba52b73b376a2b3f69",
+        )?))
+        .await?
+        .expect("merge exists");
+
+    // First-parent walk follows "Replace the base" and skips the other branch entirely.
+    let first_parent = |skip, limit| AncestorsOpts {
+        skip,
+        limit,
+        first_parent_only: true,
+    };
+    assert_eq!(
+        ancestor_messages(&merge, first_parent(0, 100)).await?,
+        vec!["Merge", "Replace the base", "Doubled", "Branch 2", "base"],
+    );
+    assert_eq!(
+        ancestor_messages(&merge, first_parent(1, 2)).await?,
+        vec!["Replace the base", "Doubled"],
+    );
+    assert!(ancestor_messages(&merge, first_parent(5, 100))
+        .await?
+        .is_empty());
+    assert!(ancestor_messages(&merge, first_parent(0, 0))
+        .await?
+        .is_empty());
+
+    // All ancestors, paged through in pages of 3.
+    let all_parents = |skip, limit| AncestorsOpts {
+        skip,
+        limit,
+        first_parent_only: false,
+    };
+    let all = ancestor_messages(&merge, all_parents(0, 100)).await?;
+    let mut sorted = all.clone();
+    sorted.sort();
+    assert_eq!(
+        sorted,
+        vec![
+            "Add one",
+            "Branch 1",
+            "Branch 2",
+            "Doubled",
+            "I think 4 is a nice number",
+            "Merge",
+            "Replace the base",
+            "base",
+        ],
+    );
+    let mut paged = Vec::new();
+    for page in 0..3 {
+        paged.extend(ancestor_messages(&merge, all_parents(page * 3, 3)).await?);
+    }
+    assert_eq!(paged, all);
+    assert_eq!(all.first().map(String::as_str), Some("Merge"));
+    assert_eq!(all.last().map(String::as_str), Some("base"));
+    assert!(ancestor_messages(&merge, all_parents(8, 100))
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn derive(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = linear::getrepo(fb).await;
+    let mononoke =
+        Mononoke::new_test(ctx.clone(), vec![("test".to_string(), blob_repo.clone())]).await?;
+    let repo = mononoke.repo(ctx.clone(), "test")?.expect("repo exists");
+    let cs_id =
+        ChangesetId::from_str("7785606eb1f26ff5722c831de402350cf97052dc44bc175da6ac0d715a3dbbf6")?;
+
+    let underived = RootUnodeManifestId::count_underived(&ctx, &blob_repo, &cs_id, 100)
+        .compat()
+        .await?;
+    assert!(underived > 0);
+
+    repo.derive(cs_id, &[RootUnodeManifestId::NAME]).await?;
+
+    // Nothing is left to derive, so a unode lookup is just a mapping fetch.
+    let underived = RootUnodeManifestId::count_underived(&ctx, &blob_repo, &cs_id, 100)
+        .compat()
+        .await?;
+    assert_eq!(underived, 0);
+
+    match repo.derive(cs_id, &["fsnodes"]).await {
+        Err(MononokeError::InvalidRequest(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match repo.derive(cs_id, &["not_a_derived_data_type"]).await {
+        Err(MononokeError::InvalidRequest(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Deriving needs write access to the repo.
+    let mut read_only = Repo::new_test(ctx.clone(), blob_repo).await?;
+    read_only.service_config.permit_writes = false;
+    let read_only = RepoContext::new(ctx.clone(), Arc::new(read_only))?;
+    match read_only.derive(cs_id, &[RootUnodeManifestId::NAME]).await {
+        Err(MononokeError::InvalidRequest(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn repo_info(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let bookmark_params = |name: &str, hooks: &[&str], only_fast_forward| BookmarkParams {
+        bookmark: BookmarkName::new(name).unwrap().into(),
+        hooks: hooks.iter().map(|hook| hook.to_string()).collect(),
+        only_fast_forward,
+        rewrite_dates: None,
+        allowed_users: None,
+    };
+    let config = RepoConfig {
+        write_lock_db_address: Some("secret-db-address".to_string()),
+        scuba_table: Some("secret_scuba_table".to_string()),
+        scuba_table_hooks: Some("secret_scuba_table_hooks".to_string()),
+        hipster_acl: Some("secret_acl".to_string()),
+        lfs: LfsParams {
+            threshold: Some(1000),
+            ..Default::default()
+        },
+        bookmarks_cache_ttl: Some(Duration::from_secs(20)),
+        derived_data_config: DerivedDataConfig {
+            derived_data_types: vec!["fsnodes".to_string(), "unodes".to_string()]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        },
+        bookmarks: vec![
+            bookmark_params("master", &["verify_author", "limit_filesize"], true),
+            bookmark_params("release", &[], true),
+            bookmark_params("stable", &["verify_author"], false),
+        ],
+        source_control_service: SourceControlServiceParams {
+            permit_writes: true,
+            resolve_cache_size: 0,
+        },
+        readonly: RepoReadOnly::ReadWrite,
+        ..Default::default()
+    };
+
+    let mut repo = Repo::new_test(ctx.clone(), linear::getrepo(fb).await).await?;
+    repo.info = RepoInfo::from_config(&config);
+    let repo = RepoContext::new(ctx.clone(), Arc::new(repo))?;
+    let info = repo.repo_info();
+    assert_eq!(
+        info,
+        RepoInfo {
+            lfs_threshold: Some(1000),
+            writes_permitted: true,
+            bookmarks_cache_ttl: Some(Duration::from_secs(20)),
+            derived_data_types: vec!["fsnodes".to_string(), "unodes".to_string()]
+                .into_iter()
+                .collect(),
+            bookmark_hooks: vec![
+                (
+                    "master".to_string(),
+                    vec!["verify_author".to_string(), "limit_filesize".to_string()]
+                ),
+                ("stable".to_string(), vec!["verify_author".to_string()]),
+            ],
+            only_fast_forward_bookmarks: vec!["master".to_string(), "release".to_string()],
+        }
+    );
+    let debug = format!("{:?}", info);
+    assert!(!debug.contains("secret"), "{}", debug);
+
+    // A read-only repo doesn't permit writes, even if the service does.
+    let info = RepoInfo::from_config(&RepoConfig {
+        readonly: RepoReadOnly::ReadOnly("maintenance".to_string()),
+        ..config
+    });
+    assert!(!info.writes_permitted);
+
+    Ok(())
+}
@@ -177,3 +177,59 @@ async fn create_commit_bad_changes(fb: FacebookInit) -> Result<(), Error> {
 
     Ok(())
 }
+
+#[fbinit::compat_test]
+async fn derive_data(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mononoke = Mononoke::new_test(
+        ctx.clone(),
+        vec![("test".to_string(), linear::getrepo(fb).await)],
+    )
+    .await?;
+    let repo = mononoke
+        .repo(ctx, "test")?
+        .expect("repo exists")
+        .write()
+        .await?;
+    let parent_hash = "7785606eb1f26ff5722c831de402350cf97052dc44bc175da6ac0d715a3dbbf6";
+    let mut changes: BTreeMap<MononokePath, CreateChange> = BTreeMap::new();
+    changes.insert(
+        MononokePath::try_from("TEST_DERIVE")?,
+        CreateChange::NewContent(Bytes::from("TEST DERIVE\n"), FileType::Regular, None),
+    );
+    let cs = repo
+        .create_changeset(
+            vec![ChangesetId::from_str(parent_hash)?],
+            String::from("Test Author <test@example.com>"),
+            FixedOffset::east(0).ymd(2000, 2, 1).and_hms(12, 0, 0),
+            None,
+            None,
+            String::from("Test Derive"),
+            BTreeMap::new(),
+            changes,
+        )
+        .await?;
+
+    let status = cs.derived_data_status().await?;
+    assert_eq!(status.get("fastlog"), Some(&false));
+    assert_eq!(status.get("blame"), Some(&false));
+
+    let results = cs
+        .derive(vec![String::from("fastlog"), String::from("blame")])
+        .await?;
+    assert_eq!(results.len(), 2);
+    assert!(results.values().all(|result| result.is_ok()));
+
+    let status = cs.derived_data_status().await?;
+    assert_eq!(status.get("fastlog"), Some(&true));
+    assert_eq!(status.get("blame"), Some(&true));
+
+    // Unknown derived data types are rejected.
+    assert_matches!(
+        cs.derive(vec![String::from("not_a_derived_data_type")])
+            .await,
+        Err(MononokeError::InvalidRequest(_))
+    );
+
+    Ok(())
+}
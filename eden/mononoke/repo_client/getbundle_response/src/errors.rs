@@ -7,13 +7,25 @@
 
 use thiserror::Error;
 
+use crate::DraftsInBundlesPolicy;
 use filestore::FetchKey;
 use mercurial_types::HgChangesetId;
+use mononoke_types::ChangesetId;
 
 #[derive(Debug, Error)]
 pub enum ErrorKind {
     #[error("Bonsai not found for hg changeset: {0:?}")]
     BonsaiNotFoundForHgChangeset(HgChangesetId),
+    #[error("Hg changeset not found for bonsai changeset: {0:?}")]
+    MissingHgMapping(ChangesetId),
     #[error("missing content {0:?}")]
     MissingContent(FetchKey),
+    #[error("LFS entries can't be sent in a cg2 changegroup, but an LFS threshold is set")]
+    LfsRequiresCg3,
+    #[error("A path filter produces partial bundles, but they are not allowed")]
+    PartialBundlesNotAllowed,
+    #[error("A path filter requires draft trees and files, but the policy is {0:?}")]
+    PathFilterWithoutDraftFiles(DraftsInBundlesPolicy),
+    #[error("Request was cancelled: its deadline has passed")]
+    Cancelled,
 }
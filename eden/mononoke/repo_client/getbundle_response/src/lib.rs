@@ -8,11 +8,15 @@
 #![deny(warnings)]
 
 use crate::errors::ErrorKind;
+use crate::manifest_cache::ManifestIdCache;
+use crate::mapping_cache::HgBonsaiMappingCache;
 use anyhow::{bail, Error, Result};
+use async_trait::async_trait;
 use blobrepo::BlobRepo;
 use blobstore::Loadable;
 use bytes::Bytes;
 use bytes_old::Bytes as BytesOld;
+use changeset_fetcher::ChangesetFetcher;
 use cloned::cloned;
 use context::{CoreContext, PerfCounterType};
 use derived_data::BonsaiDerived;
@@ -33,6 +37,7 @@ use futures_old::{
 use futures_util::try_join;
 use load_limiter::Metric;
 use manifest::{find_intersection_of_diffs, Entry};
+pub use mercurial_bundles::infinitepush::HgMutationEntry;
 use mercurial_bundles::{
     changegroup::CgVersion,
     part_encode::PartEncodeBuilder,
@@ -44,23 +49,29 @@ use mercurial_types::{
     FileBytes, HgBlobNode, HgChangesetId, HgFileNodeId, HgManifestId, HgParents, HgPhase, MPath,
     RevFlags, NULL_CSID,
 };
-use mononoke_types::{hash::Sha256, ChangesetId, ContentId};
+use mononoke_types::{hash::Sha256, ChangesetId, ContentId, Generation};
 use phases::Phases;
-use reachabilityindex::LeastCommonAncestorsHint;
+use reachabilityindex::{LeastCommonAncestorsHint, NodeFrontier};
 use repo_blobstore::RepoBlobstore;
 use revset::DifferenceOfUnionsOfAncestorsNodeStream;
 use slog::debug;
 use stats::prelude::*;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     iter::FromIterator,
     sync::Arc,
+    time::Instant,
 };
 
 mod errors;
+mod manifest_cache;
+mod mapping_cache;
 
 pub const MAX_FILENODE_BYTES_IN_MEMORY: u64 = 100_000_000;
 
+/// How many commits `find_commits_to_send` discovers between checks of the context deadline
+const CANCELLATION_CHECK_INTERVAL: usize = 100;
+
 define_stats! {
     prefix = "mononoke.getbundle_response";
     manifests_returned: dynamic_timeseries("manifests_returned.{}", (reponame: String); Rate, Sum),
@@ -85,11 +96,89 @@ pub enum DraftsInBundlesPolicy {
     WithTreesAndFiles,
 }
 
+/// Decides whether the filenodes of a path are sent for draft commits
+pub type PathFilter = Arc<dyn Fn(&MPath) -> bool + Send + Sync>;
+
+/// Which filenodes to send for draft commits with `DraftsInBundlesPolicy::WithTreesAndFiles`
+#[derive(Clone, Default)]
+pub struct DraftFilenodesParams {
+    /// Filenodes of paths this returns false for are left out. Manifests are still sent in
+    /// full, so the client has to fetch the files that were left out separately.
+    pub path_filter: Option<PathFilter>,
+    /// Leaving filenodes out produces partial bundles, so a `path_filter` is rejected unless
+    /// this is set.
+    pub allow_partial_bundles: bool,
+}
+
+impl DraftFilenodesParams {
+    /// The path filter to apply, or an error if it would produce partial bundles without
+    /// them being allowed.
+    fn checked_path_filter(&self) -> Result<Option<&PathFilter>, Error> {
+        match &self.path_filter {
+            Some(_) if !self.allow_partial_bundles => {
+                Err(ErrorKind::PartialBundlesNotAllowed.into())
+            }
+            path_filter => Ok(path_filter.as_ref()),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SessionLfsParams {
+    /// LFS is disabled for the session if None
     pub threshold: Option<u64>,
+    /// Serve every file as LFS, including those no larger than the threshold
+    pub always_lfs: bool,
+}
+
+impl SessionLfsParams {
+    /// Whether a file of `file_size` bytes should be sent as an LFS pointer. With a threshold
+    /// set, files strictly larger than it are sent as LFS, so a threshold of 0 still sends empty
+    /// files inline. `always_lfs` sends every file as LFS, empty ones included.
+    pub fn use_lfs(&self, file_size: u64) -> bool {
+        match self.threshold {
+            None => false,
+            Some(_) if self.always_lfs => true,
+            Some(threshold) => file_size > threshold,
+        }
+    }
+}
+
+/// Fetches the mutation entries of draft commits (which commits they were amended, rebased,
+/// etc. from), so that clients can hide the commits they obsolete.
+#[async_trait]
+pub trait MutationFetcher: Send + Sync {
+    /// The mutation entries for `changesets` and their predecessors
+    async fn fetch_for(
+        &self,
+        ctx: &CoreContext,
+        changesets: Vec<HgChangesetId>,
+    ) -> Result<Vec<HgMutationEntry>, Error>;
+}
+
+/// The optional parameters of `create_getbundle_response`
+#[derive(Clone, Default)]
+pub struct GetbundleParams {
+    /// The changegroup version asked for by the client. Without one, it's picked based on
+    /// whether LFS is enabled.
+    pub requested_cg_version: Option<CgVersion>,
+    /// Which filenodes to send for draft commits
+    pub draft_filenodes_params: DraftFilenodesParams,
+    /// Only send the commits at most this many commits away from the requested heads
+    pub shallow_depth: Option<u64>,
+    /// Fetches the mutation entries of the draft commits that are sent
+    pub mutation_fetcher: Option<Arc<dyn MutationFetcher>>,
 }
 
+/// Build the parts of a `getbundle` response.
+///
+/// With a `shallow_depth`, only the commits at most that many commits away from the
+/// requested heads are sent, along with the trees and files of all of them. The client
+/// doesn't have the parents of the oldest commits, so their trees and files are sent in
+/// full rather than as a diff against those parents.
+///
+/// With a `mutation_fetcher`, the mutation entries of all the draft commits that are sent,
+/// not only the draft heads, are included in a part of their own.
 pub async fn create_getbundle_response(
     ctx: CoreContext,
     blobrepo: BlobRepo,
@@ -100,29 +189,241 @@ pub async fn create_getbundle_response(
     return_phases: PhasesPart,
     lfs_params: SessionLfsParams,
     drafts_in_bundles_policy: DraftsInBundlesPolicy,
+    params: GetbundleParams,
 ) -> Result<Vec<PartEncodeBuilder>, Error> {
+    let GetbundleParams {
+        requested_cg_version,
+        draft_filenodes_params,
+        shallow_depth,
+        mutation_fetcher,
+    } = params;
+    let cg_version = select_cg_version(requested_cg_version, &lfs_params)?;
+    if draft_filenodes_params.path_filter.is_some()
+        && drafts_in_bundles_policy != DraftsInBundlesPolicy::WithTreesAndFiles
+    {
+        return Err(ErrorKind::PathFilterWithoutDraftFiles(drafts_in_bundles_policy).into());
+    }
+    draft_filenodes_params.checked_path_filter()?;
     let return_phases = return_phases == PhasesPart::Yes;
     debug!(ctx.logger(), "Return phases is: {:?}", return_phases);
 
     let heads_len = heads.len();
     let common: HashSet<_> = common.into_iter().collect();
-    let commits_to_send = find_commits_to_send(&ctx, &blobrepo, &common, &heads, &lca_hint);
+
+    // Heads and common heads are mapped to bonsai by several of the steps below, some of
+    // which run concurrently, so map them all at once up front.
+    let mapping_cache = HgBonsaiMappingCache::new();
+    mapping_cache
+        .get_bonsais(
+            &ctx,
+            &blobrepo,
+            heads.iter().chain(common.iter()).cloned().collect(),
+        )
+        .await?;
+
+    let commits_to_send = find_commits_to_send(
+        &ctx,
+        &blobrepo,
+        &mapping_cache,
+        &common,
+        &heads,
+        &lca_hint,
+        shallow_depth,
+    );
+
+    // Calculate phases only for heads that will be sent back to client (i.e. only
+    // for heads that are not in "common"). Note that this is different from
+    // "phases" part below, where we want to return phases for all heads.
+    let filtered_heads: Vec<_> = heads
+        .iter()
+        .filter(|head| !common.contains(head))
+        .cloned()
+        .collect();
+    let heads_filtered = filtered_heads.len() != heads_len;
 
     let phases = async {
-        // Calculate phases only for heads that will be sent back to client (i.e. only
-        // for heads that are not in "common"). Note that this is different from
-        // "phases" part below, where we want to return phases for all heads.
-        let filtered_heads = heads.iter().filter(|head| !common.contains(&head));
-        let phases = prepare_phases(&ctx, &blobrepo, filtered_heads, &blobrepo.get_phases())
-            .compat()
-            .await?;
+        let start = Instant::now();
+        let phases = prepare_phases(
+            &ctx,
+            &blobrepo,
+            &mapping_cache,
+            &filtered_heads,
+            &blobrepo.get_phases(),
+        )
+        .await?;
+        let elapsed = start.elapsed();
         report_draft_commits(&ctx, phases.iter());
-        derive_filenodes_for_public_heads(&ctx, &blobrepo, &common, &phases).await?;
-        Ok(phases)
+        derive_filenodes_for_public_heads(&ctx, &blobrepo, &mapping_cache, &common, &phases)
+            .await?;
+        Ok((phases, elapsed))
+    };
+
+    let ((phases, phases_elapsed), commits_to_send) = try_join!(phases, commits_to_send)?;
+
+    // The phases part has to come after the changegroup part, but its phases can be
+    // calculated while the changegroup part is being built.
+    let phases_for_part = async {
+        let start = Instant::now();
+        let phases_for_part = if return_phases {
+            Some(
+                prepare_phases_for_part(
+                    &ctx,
+                    &blobrepo,
+                    &mapping_cache,
+                    &heads,
+                    &filtered_heads,
+                    &phases,
+                )
+                .await?,
+            )
+        } else {
+            None
+        };
+        Ok::<_, Error>((phases_for_part, start.elapsed()))
+    };
+
+    // Only the mutation part needs its own copy of the commits to send.
+    let mutation_commits = match mutation_fetcher {
+        Some(_) if heads_len != 0 => commits_to_send.clone(),
+        _ => vec![],
+    };
+
+    let changegroup_parts = async {
+        let start = Instant::now();
+        let parts = create_changegroup_parts(
+            &ctx,
+            &blobrepo,
+            &mapping_cache,
+            reponame,
+            heads_len,
+            &phases,
+            commits_to_send,
+            &lfs_params,
+            cg_version,
+            drafts_in_bundles_policy,
+            &draft_filenodes_params,
+            shallow_depth.is_some(),
+        )
+        .await?;
+        Ok::<_, Error>((parts, start.elapsed()))
+    };
+
+    let mutation_part = async {
+        match mutation_fetcher {
+            Some(mutation_fetcher) if heads_len != 0 => {
+                create_mutation_part(
+                    &ctx,
+                    &blobrepo,
+                    &mapping_cache,
+                    mutation_fetcher.as_ref(),
+                    &phases,
+                    mutation_commits,
+                )
+                .await
+            }
+            _ => Ok(None),
+        }
     };
 
-    let (phases, commits_to_send) = try_join!(phases, commits_to_send)?;
+    let ((mut parts, parts_elapsed), (phases_for_part, phases_for_part_elapsed), mutation_part) =
+        try_join!(changegroup_parts, phases_for_part, mutation_part)?;
+
+    if let Some(mutation_part) = mutation_part {
+        parts.push(mutation_part);
+    }
+
+    if let Some(phases_for_part) = phases_for_part {
+        // Without reuse or concurrency, calculating these phases would have added its full
+        // latency after the changegroup part was built.
+        let saved = if heads_filtered {
+            std::cmp::min(parts_elapsed, phases_for_part_elapsed)
+        } else {
+            phases_elapsed
+        };
+        ctx.perf_counters().add_to_counter(
+            PerfCounterType::GetbundlePhasesSavedMs,
+            saved.as_millis() as i64,
+        );
+
+        parts.push(parts::phases_part(
+            ctx.clone(),
+            old_stream::iter_ok(phases_for_part),
+        )?);
+    }
 
+    Ok(parts)
+}
+
+/// Build the part with the mutation entries of the draft commits among `commits_to_send`,
+/// unless there are none. Ancestors of public commits are public, so draft commits are only
+/// sent if some of the heads among `phases` are draft.
+async fn create_mutation_part(
+    ctx: &CoreContext,
+    blobrepo: &BlobRepo,
+    mapping_cache: &HgBonsaiMappingCache,
+    mutation_fetcher: &dyn MutationFetcher,
+    phases: &[(HgChangesetId, HgPhase)],
+    commits_to_send: Vec<ChangesetId>,
+) -> Result<Option<PartEncodeBuilder>, Error> {
+    if !phases.iter().any(|(_, phase)| *phase == HgPhase::Draft) {
+        return Ok(None);
+    }
+    let public = blobrepo
+        .get_phases()
+        .get_public(ctx.clone(), commits_to_send.clone(), false)
+        .compat()
+        .await?;
+    let draft_bcs_ids: Vec<_> = commits_to_send
+        .into_iter()
+        .filter(|bcs_id| !public.contains(bcs_id))
+        .collect();
+    let hg_cs_ids = mapping_cache
+        .get_hgs(ctx, blobrepo, draft_bcs_ids.clone())
+        .await?;
+    let drafts: Vec<_> = draft_bcs_ids
+        .iter()
+        .filter_map(|bcs_id| hg_cs_ids.get(bcs_id).cloned())
+        .collect();
+    if drafts.is_empty() {
+        return Ok(None);
+    }
+    let entries = mutation_fetcher.fetch_for(ctx, drafts).await?;
+    Ok(Some(parts::infinitepush_mutation_part(entries)?))
+}
+
+/// Calculate phases for the phases part, which covers all `heads` rather than only the
+/// `filtered_heads` that are not in "common". If no heads were filtered out, the phases
+/// already calculated for them are reused.
+async fn prepare_phases_for_part(
+    ctx: &CoreContext,
+    blobrepo: &BlobRepo,
+    mapping_cache: &HgBonsaiMappingCache,
+    heads: &[HgChangesetId],
+    filtered_heads: &[HgChangesetId],
+    filtered_phases: &[(HgChangesetId, HgPhase)],
+) -> Result<Vec<(HgChangesetId, HgPhase)>, Error> {
+    if heads.len() == filtered_heads.len() {
+        return Ok(filtered_phases.to_vec());
+    }
+    prepare_phases(ctx, blobrepo, mapping_cache, heads, &blobrepo.get_phases()).await
+}
+
+/// Build the changegroup part, followed by the treepack part if trees and files are
+/// included for draft commits, or for all the commits of a shallow pull.
+async fn create_changegroup_parts(
+    ctx: &CoreContext,
+    blobrepo: &BlobRepo,
+    mapping_cache: &HgBonsaiMappingCache,
+    reponame: String,
+    heads_len: usize,
+    phases: &[(HgChangesetId, HgPhase)],
+    commits_to_send: Vec<ChangesetId>,
+    lfs_params: &SessionLfsParams,
+    cg_version: CgVersion,
+    drafts_in_bundles_policy: DraftsInBundlesPolicy,
+    draft_filenodes_params: &DraftFilenodesParams,
+    shallow: bool,
+) -> Result<Vec<PartEncodeBuilder>, Error> {
     let mut parts = vec![];
     if heads_len != 0 {
         // no heads means bookmark-only pushrebase, and the client
@@ -141,24 +442,44 @@ pub async fn create_getbundle_response(
             .collect();
 
         let should_include_trees_and_files =
-            drafts_in_bundles_policy == DraftsInBundlesPolicy::WithTreesAndFiles;
+            shallow || drafts_in_bundles_policy == DraftsInBundlesPolicy::WithTreesAndFiles;
         let (maybe_manifests, maybe_filenodes): (Option<_>, Option<_>) =
             if should_include_trees_and_files {
-                let (manifests, filenodes) =
-                    get_manifests_and_filenodes(&ctx, &blobrepo, draft_hg_cs_ids, &lfs_params)
-                        .await?;
-                report_manifests_and_filenodes(&ctx, reponame, manifests.len(), filenodes.iter());
+                let (commits, shallow_commits) = if shallow {
+                    let commits: Vec<_> =
+                        map_to_hg_changesets(ctx, blobrepo, mapping_cache, commits_to_send.clone())
+                            .await?
+                            .into_iter()
+                            .map(|(hg_cs_id, _)| hg_cs_id)
+                            .collect();
+                    let shallow_commits: HashSet<_> = commits.iter().cloned().collect();
+                    (commits, Some(shallow_commits))
+                } else {
+                    (draft_hg_cs_ids, None)
+                };
+                let (manifests, filenodes) = get_manifests_and_filenodes(
+                    ctx,
+                    blobrepo,
+                    commits,
+                    lfs_params,
+                    draft_filenodes_params,
+                    cg_version,
+                    shallow_commits.as_ref(),
+                )
+                .await?;
+                report_manifests_and_filenodes(ctx, reponame, manifests.len(), filenodes.iter());
                 (Some(manifests), Some(filenodes))
             } else {
                 (None, None)
             };
 
         let cg_part = create_hg_changeset_part(
-            &ctx,
-            &blobrepo,
-            commits_to_send.clone(),
+            ctx,
+            blobrepo,
+            mapping_cache,
+            commits_to_send,
             maybe_filenodes,
-            &lfs_params,
+            cg_version,
         )
         .await?;
         parts.push(cg_part);
@@ -172,19 +493,22 @@ pub async fn create_getbundle_response(
         }
     }
 
-    // Phases part has to be after the changegroup part.
-    if return_phases {
-        let phases = prepare_phases(&ctx, &blobrepo, heads.iter(), &blobrepo.get_phases())
-            .compat()
-            .await?;
+    Ok(parts)
+}
 
-        parts.push(parts::phases_part(
-            ctx.clone(),
-            old_stream::iter_ok(phases),
-        )?);
+/// Pick the changegroup version to respond with. An explicitly requested version wins,
+/// otherwise cg3 is only used if LFS is enabled for the session. LFS entries can't be
+/// represented in cg2, so requesting cg2 with an LFS threshold set is an error.
+fn select_cg_version(
+    requested_cg_version: Option<CgVersion>,
+    lfs_params: &SessionLfsParams,
+) -> Result<CgVersion, Error> {
+    match (requested_cg_version, lfs_params.threshold) {
+        (Some(CgVersion::Cg2Version), Some(_)) => Err(ErrorKind::LfsRequiresCg3.into()),
+        (Some(cg_version), _) => Ok(cg_version),
+        (None, Some(_)) => Ok(CgVersion::Cg3Version),
+        (None, None) => Ok(CgVersion::Cg2Version),
     }
-
-    Ok(parts)
 }
 
 fn report_draft_commits<'a, I: IntoIterator<Item = &'a (HgChangesetId, HgPhase)>>(
@@ -250,6 +574,7 @@ fn report_manifests_and_filenodes<
 async fn derive_filenodes_for_public_heads(
     ctx: &CoreContext,
     blobrepo: &BlobRepo,
+    mapping_cache: &HgBonsaiMappingCache,
     common_heads: &HashSet<HgChangesetId>,
     phases: &Vec<(HgChangesetId, HgPhase)>,
 ) -> Result<(), Error> {
@@ -261,7 +586,7 @@ async fn derive_filenodes_for_public_heads(
     }
 
     let to_derive_filenodes_bonsai =
-        hg_to_bonsai_stream(&ctx, &blobrepo, to_derive_filenodes).await?;
+        hg_to_bonsais(&ctx, &blobrepo, mapping_cache, to_derive_filenodes).await?;
     Ok(stream::iter(to_derive_filenodes_bonsai)
         .map(move |bcs_id| {
             FilenodesOnlyPublic::derive(ctx.clone(), blobrepo.clone(), bcs_id).compat()
@@ -274,29 +599,42 @@ async fn derive_filenodes_for_public_heads(
 async fn find_commits_to_send(
     ctx: &CoreContext,
     blobrepo: &BlobRepo,
+    mapping_cache: &HgBonsaiMappingCache,
     common: &HashSet<HgChangesetId>,
     heads: &Vec<HgChangesetId>,
     lca_hint: &Arc<dyn LeastCommonAncestorsHint>,
+    shallow_depth: Option<u64>,
 ) -> Result<Vec<ChangesetId>, Error> {
+    // A null head has no ancestors, so there is nothing to send for it.
+    let heads: Vec<_> = heads
+        .iter()
+        .filter(|head| head.into_nodehash() != NULL_CSID.into_nodehash())
+        .cloned()
+        .collect();
+    if heads.is_empty() {
+        return Ok(vec![]);
+    }
+
     if common.is_empty() {
         bail!("no 'common' heads specified. Pull will be very inefficient. Please use hg clone instead");
     }
 
     let common_heads: HashSet<_> = HashSet::from_iter(common.iter());
 
-    let heads = hg_to_bonsai_stream(
+    let heads = hg_to_bonsais(
         &ctx,
         &blobrepo,
+        mapping_cache,
         heads
-            .iter()
+            .into_iter()
             .filter(|head| !common_heads.contains(head))
-            .cloned()
             .collect(),
     );
 
-    let excludes = hg_to_bonsai_stream(
+    let excludes = hg_to_bonsais(
         &ctx,
         &blobrepo,
+        mapping_cache,
         common
             .iter()
             .map(|node| node.clone())
@@ -305,18 +643,59 @@ async fn find_commits_to_send(
     );
 
     let (heads, excludes) = try_join!(heads, excludes)?;
-
     let changeset_fetcher = blobrepo.get_changeset_fetcher();
-    let nodes_to_send = DifferenceOfUnionsOfAncestorsNodeStream::new_with_excludes(
+    let excludes = prune_excludes(ctx, &changeset_fetcher, lca_hint, &heads, excludes).await?;
+
+    // Depth of the commits that a shallow pull can still reach, heads being at depth 1.
+    let mut shallow_depths: HashMap<ChangesetId, u64> = match shallow_depth {
+        Some(depth) if depth > 0 => heads.iter().map(|head| (*head, 1)).collect(),
+        _ => HashMap::new(),
+    };
+
+    let mut nodes = DifferenceOfUnionsOfAncestorsNodeStream::new_with_excludes(
         ctx.clone(),
         &changeset_fetcher,
         lca_hint.clone(),
         heads,
         excludes,
     )
-    .collect()
-    .compat()
-    .await?;
+    .compat();
+
+    // The client may have given up on a long pull, so stop walking the history once the
+    // deadline of the request has passed.
+    let mut nodes_to_send = vec![];
+    while let Some(node) = nodes.try_next().await? {
+        if nodes_to_send.len() % CANCELLATION_CHECK_INTERVAL == 0 && ctx.is_expired() {
+            return Err(ErrorKind::Cancelled.into());
+        }
+        let max_depth = match shallow_depth {
+            Some(max_depth) => max_depth,
+            None => {
+                nodes_to_send.push(node);
+                continue;
+            }
+        };
+
+        // Nodes come in topological order, so all the children of a node have been seen
+        // before it, and its depth is final.
+        if let Some(depth) = shallow_depths.remove(&node) {
+            nodes_to_send.push(node);
+            if depth < max_depth {
+                let parents = changeset_fetcher
+                    .get_parents(ctx.clone(), node)
+                    .compat()
+                    .await?;
+                for parent in parents {
+                    let parent_depth = shallow_depths.entry(parent).or_insert(depth + 1);
+                    *parent_depth = std::cmp::min(*parent_depth, depth + 1);
+                }
+            }
+        }
+        if shallow_depths.is_empty() {
+            // No node left within the depth.
+            break;
+        }
+    }
 
     ctx.session().bump_load(Metric::EgressCommits, 1.0);
     ctx.perf_counters().add_to_counter(
@@ -327,12 +706,85 @@ async fn find_commits_to_send(
     Ok(nodes_to_send.into_iter().rev().collect())
 }
 
+/// Drop the `excludes` that are ancestors of other `excludes`, since walking the history
+/// excludes them anyway. Clients with many common heads mostly send heads of the same history,
+/// and each of them would otherwise stay in the exclude frontier of the walk.
+///
+/// The excludes are visited from the highest generation down, moving the frontier of the kept
+/// ones along with `lca_hint`, so that their ancestors are skipped in bulk. This stops at the
+/// lowest generation of `heads`, which the walk reaches anyway, and excludes below it are kept.
+async fn prune_excludes(
+    ctx: &CoreContext,
+    changeset_fetcher: &Arc<dyn ChangesetFetcher>,
+    lca_hint: &Arc<dyn LeastCommonAncestorsHint>,
+    heads: &[ChangesetId],
+    excludes: Vec<ChangesetId>,
+) -> Result<Vec<ChangesetId>, Error> {
+    if excludes.len() < 2 {
+        return Ok(excludes);
+    }
+
+    let with_generation = |cs_id: ChangesetId| {
+        changeset_fetcher
+            .get_generation_number(ctx.clone(), cs_id)
+            .compat()
+            .map_ok(move |gen| (cs_id, gen))
+    };
+    let head_generations =
+        future::try_join_all(heads.iter().map(|head| with_generation(*head))).await?;
+    let min_head_gen = match head_generations.into_iter().map(|(_, gen)| gen).min() {
+        Some(gen) => gen,
+        None => return Ok(excludes),
+    };
+    let mut by_generation: BTreeMap<Generation, Vec<ChangesetId>> = BTreeMap::new();
+    for (cs_id, gen) in
+        future::try_join_all(excludes.iter().map(|cs_id| with_generation(*cs_id))).await?
+    {
+        by_generation
+            .entry(gen)
+            .or_insert_with(Vec::new)
+            .push(cs_id);
+    }
+
+    let mut pruned = vec![];
+    let mut frontier = NodeFrontier::default();
+    for (gen, cs_ids) in by_generation.into_iter().rev() {
+        if gen < min_head_gen {
+            pruned.extend(cs_ids);
+            continue;
+        }
+        if !frontier.is_empty() {
+            frontier = lca_hint
+                .lca_hint(ctx.clone(), changeset_fetcher.clone(), frontier, gen)
+                .compat()
+                .await?;
+        }
+        // The frontier now has all the ancestors of the kept excludes at this generation.
+        for cs_id in cs_ids {
+            let is_ancestor = frontier
+                .get(&gen)
+                .map_or(false, |ancestors| ancestors.contains(&cs_id));
+            if !is_ancestor {
+                pruned.push(cs_id);
+                frontier.insert((cs_id, gen));
+            }
+        }
+    }
+
+    ctx.perf_counters().add_to_counter(
+        PerfCounterType::GetbundlePrunedExcludes,
+        (excludes.len() - pruned.len()) as i64,
+    );
+    Ok(pruned)
+}
+
 async fn create_hg_changeset_part(
     ctx: &CoreContext,
     blobrepo: &BlobRepo,
+    mapping_cache: &HgBonsaiMappingCache,
     nodes_to_send: Vec<ChangesetId>,
     maybe_prepared_filenode_entries: Option<HashMap<MPath, Vec<PreparedFilenodeEntry>>>,
-    lfs_params: &SessionLfsParams,
+    cg_version: CgVersion,
 ) -> Result<PartEncodeBuilder> {
     let map_chunk_size = 100;
     let load_buffer_size = 1000;
@@ -340,30 +792,16 @@ async fn create_hg_changeset_part(
     let changelogentries = stream::iter(nodes_to_send)
         .chunks(map_chunk_size)
         .then({
-            cloned!(ctx, blobrepo);
+            cloned!(ctx, blobrepo, mapping_cache);
             move |bonsais| {
-                cloned!(ctx, blobrepo);
+                cloned!(ctx, blobrepo, mapping_cache);
                 async move {
-                    let mapping = blobrepo
-                        .get_hg_bonsai_mapping(ctx.clone(), bonsais.clone())
-                        .compat()
-                        .await?
-                        .into_iter()
-                        .map(|(hg_cs_id, bonsai_cs_id)| (bonsai_cs_id, hg_cs_id))
-                        .collect::<HashMap<_, _>>();
-
-                    // We need to preserve ordering of the Bonsais for Mercurial on the client-side.
-
-                    let ordered_mapping = bonsais
-                        .into_iter()
-                        .map(|bcs_id| {
-                            let hg_cs_id = mapping.get(&bcs_id).ok_or_else(|| {
-                                anyhow::format_err!("cs_id was missing from mapping: {:?}", bcs_id)
-                            })?;
-                            Ok((*hg_cs_id, bcs_id))
-                        })
-                        .collect::<Vec<_>>();
-
+                    let ordered_mapping =
+                        map_to_hg_changesets(&ctx, &blobrepo, &mapping_cache, bonsais)
+                            .await?
+                            .into_iter()
+                            .map(Ok::<_, Error>)
+                            .collect::<Vec<_>>();
                     Result::<_, Error>::Ok(ordered_mapping)
                 }
             }
@@ -420,101 +858,140 @@ async fn create_hg_changeset_part(
         None => None,
     };
 
-    let cg_version = if lfs_params.threshold.is_some() {
-        CgVersion::Cg3Version
-    } else {
-        CgVersion::Cg2Version
-    };
-
     parts::changegroup_part(changelogentries, maybe_filenode_entries, cg_version)
 }
 
-async fn hg_to_bonsai_stream(
+/// Map bonsai changesets to hg changesets, preserving their order (Mercurial relies on it on
+/// the client side). The mapping can lag behind the bonsai changesets, so hg changesets
+/// missing from it are derived on demand. Fails with `ErrorKind::MissingHgMapping` if that
+/// is not possible either.
+async fn map_to_hg_changesets(
+    ctx: &CoreContext,
+    blobrepo: &BlobRepo,
+    mapping_cache: &HgBonsaiMappingCache,
+    bonsais: Vec<ChangesetId>,
+) -> Result<Vec<(HgChangesetId, ChangesetId)>, Error> {
+    let mut mapping = mapping_cache
+        .get_hgs(ctx, blobrepo, bonsais.clone())
+        .await?;
+
+    let missing: Vec<_> = bonsais
+        .iter()
+        .filter(|bcs_id| !mapping.contains_key(bcs_id))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        ctx.perf_counters().add_to_counter(
+            PerfCounterType::GetbundleHgMappingFallbacks,
+            missing.len() as i64,
+        );
+        let derived = future::try_join_all(missing.into_iter().map(|bcs_id| async move {
+            let hg_cs_id = blobrepo
+                .get_hg_from_bonsai_changeset(ctx.clone(), bcs_id)
+                .compat()
+                .await
+                .map_err(|e| e.context(ErrorKind::MissingHgMapping(bcs_id)))?;
+            mapping_cache.insert(hg_cs_id, bcs_id);
+            Ok::<_, Error>((bcs_id, hg_cs_id))
+        }))
+        .await?;
+        mapping.extend(derived);
+    }
+
+    bonsais
+        .into_iter()
+        .map(|bcs_id| {
+            let hg_cs_id = mapping
+                .get(&bcs_id)
+                .ok_or(ErrorKind::MissingHgMapping(bcs_id))?;
+            Ok((*hg_cs_id, bcs_id))
+        })
+        .collect()
+}
+
+/// Map hg changesets to bonsai changesets, preserving their order. Fails with
+/// `ErrorKind::BonsaiNotFoundForHgChangeset` for hg changesets unknown to the server.
+async fn hg_to_bonsais(
     ctx: &CoreContext,
     repo: &BlobRepo,
+    mapping_cache: &HgBonsaiMappingCache,
     nodes: Vec<HgChangesetId>,
 ) -> Result<Vec<ChangesetId>, Error> {
-    stream::iter(nodes)
-        .map({
-            move |node| {
-                repo.get_bonsai_from_hg(ctx.clone(), node)
-                    .and_then(move |maybe_bonsai| {
-                        maybe_bonsai.ok_or(ErrorKind::BonsaiNotFoundForHgChangeset(node).into())
-                    })
-                    .compat()
-            }
+    let mapping = mapping_cache.get_bonsais(ctx, repo, nodes.clone()).await?;
+    nodes
+        .into_iter()
+        .map(|node| {
+            mapping
+                .get(&node)
+                .cloned()
+                .ok_or_else(|| ErrorKind::BonsaiNotFoundForHgChangeset(node).into())
         })
-        .buffered(100)
-        .try_collect()
-        .await
+        .collect()
 }
 
 /// Calculate phases for the heads.
 /// If client is pulling non-public changesets phases for public roots should be included.
-fn prepare_phases<'a>(
+async fn prepare_phases<'a>(
     ctx: &CoreContext,
     repo: &BlobRepo,
+    mapping_cache: &HgBonsaiMappingCache,
     heads: impl IntoIterator<Item = &'a HgChangesetId>,
     phases: &Arc<dyn Phases>,
-) -> impl OldFuture<Item = Vec<(HgChangesetId, HgPhase)>, Error = Error> {
+) -> Result<Vec<(HgChangesetId, HgPhase)>, Error> {
     // create 'bonsai changesetid' => 'hg changesetid' hash map that will be later used
     // heads that are not known by the server will be skipped
     let heads: Vec<_> = heads.into_iter().cloned().collect();
-    repo.get_hg_bonsai_mapping(ctx.clone(), heads)
-        .map(move |hg_bonsai_mapping| {
-            hg_bonsai_mapping
-                .into_iter()
-                .map(|(hg_cs_id, bonsai)| (bonsai, hg_cs_id))
-                .collect::<HashMap<ChangesetId, HgChangesetId>>()
-        })
-        .and_then({
-            // calculate phases for the heads
-            cloned!(ctx, phases);
-            move |bonsai_node_mapping| {
-                phases
-                    .get_public(ctx, bonsai_node_mapping.keys().cloned().collect(), false)
-                    .map(move |public| (public, bonsai_node_mapping))
-            }
-        })
-        .and_then({
-            cloned!(ctx, repo, phases);
-            move |(public, bonsai_node_mapping)| {
-                // select draft heads
-                let drafts = bonsai_node_mapping
-                    .keys()
-                    .filter(|csid| !public.contains(csid))
-                    .cloned()
-                    .collect();
-
-                // find the public roots for the draft heads
-                calculate_public_roots(ctx.clone(), repo.clone(), drafts, phases)
-                    .and_then({
-                        cloned!(ctx);
-                        move |bonsais| {
-                            repo.get_hg_bonsai_mapping(ctx, bonsais.into_iter().collect::<Vec<_>>())
-                        }
-                    })
-                    .map(move |public_roots| {
-                        let phases = bonsai_node_mapping
-                            .into_iter()
-                            .map(move |(csid, hg_csid)| {
-                                let phase = if public.contains(&csid) {
-                                    HgPhase::Public
-                                } else {
-                                    HgPhase::Draft
-                                };
-                                (hg_csid, phase)
-                            })
-                            .chain(
-                                public_roots
-                                    .into_iter()
-                                    .map(|(hg_csid, _)| (hg_csid, HgPhase::Public)),
-                            )
-                            .collect();
-                        phases
-                    })
-            }
+    let bonsai_node_mapping: HashMap<ChangesetId, HgChangesetId> = mapping_cache
+        .get_bonsais(ctx, repo, heads)
+        .await?
+        .into_iter()
+        .map(|(hg_cs_id, bonsai)| (bonsai, hg_cs_id))
+        .collect();
+
+    // calculate phases for the heads
+    let public = phases
+        .get_public(
+            ctx.clone(),
+            bonsai_node_mapping.keys().cloned().collect(),
+            false,
+        )
+        .compat()
+        .await?;
+
+    // select draft heads
+    let drafts = bonsai_node_mapping
+        .keys()
+        .filter(|csid| !public.contains(csid))
+        .cloned()
+        .collect();
+
+    // find the public roots for the draft heads
+    let public_roots = calculate_public_roots(ctx.clone(), repo.clone(), drafts, phases.clone())
+        .compat()
+        .await?;
+    let public_roots = mapping_cache
+        .get_hgs(ctx, repo, public_roots.into_iter().collect())
+        .await?;
+
+    let mut phases: Vec<_> = bonsai_node_mapping
+        .into_iter()
+        .map(move |(csid, hg_csid)| {
+            let phase = if public.contains(&csid) {
+                HgPhase::Public
+            } else {
+                HgPhase::Draft
+            };
+            (hg_csid, phase)
         })
+        .chain(
+            public_roots
+                .into_iter()
+                .map(|(_, hg_csid)| (hg_csid, HgPhase::Public)),
+        )
+        .collect();
+    // Sorted so that the phases part doesn't depend on the iteration order of the maps above.
+    phases.sort_by_key(|(hg_csid, _)| *hg_csid);
+    Ok(phases)
 }
 
 /// Calculate public roots for the set of draft changesets
@@ -667,6 +1144,7 @@ fn prepare_filenode_entries_stream<'a>(
     repo: &'a BlobRepo,
     filenodes: Vec<(MPath, HgFileNodeId, HgChangesetId)>,
     lfs_session: &'a SessionLfsParams,
+    cg_version: CgVersion,
 ) -> impl Stream<Item = Result<(MPath, Vec<PreparedFilenodeEntry>), Error>> + 'a {
     stream::iter(filenodes.into_iter())
         .map({
@@ -678,12 +1156,20 @@ fn prepare_filenode_entries_stream<'a>(
 
                 let file_size = envelope.content_size();
 
-                let content = match lfs_session.threshold {
-                    None => FilenodeEntryContent::InlineV2(envelope.content_id()),
-                    Some(lfs_threshold) if file_size <= lfs_threshold => {
+                let content = match (lfs_session.threshold, cg_version) {
+                    (None, CgVersion::Cg2Version) => {
+                        FilenodeEntryContent::InlineV2(envelope.content_id())
+                    }
+                    (None, CgVersion::Cg3Version) => {
                         FilenodeEntryContent::InlineV3(envelope.content_id())
                     }
-                    _ => {
+                    (Some(_), _) if !lfs_session.use_lfs(file_size) => {
+                        FilenodeEntryContent::InlineV3(envelope.content_id())
+                    }
+                    (Some(_), CgVersion::Cg2Version) => {
+                        return Err(ErrorKind::LfsRequiresCg3.into());
+                    }
+                    (Some(_), CgVersion::Cg3Version) => {
                         let key = FetchKey::from(envelope.content_id());
                         let meta = filestore::get_metadata(repo.blobstore(), ctx.clone(), &key)
                             .compat()
@@ -782,10 +1268,15 @@ pub fn create_manifest_entries_stream(
         .boxify()
 }
 
+/// Find the manifests and filenodes that are new in `hg_cs_id` compared to its parents.
+/// Parents outside of `shallow_commits` are treated as absent, so the commit is diffed
+/// against an empty tree if none of its parents are in it.
 async fn diff_with_parents(
     ctx: CoreContext,
     repo: BlobRepo,
+    manifest_cache: &ManifestIdCache,
     hg_cs_id: HgChangesetId,
+    shallow_commits: Option<&HashSet<HgChangesetId>>,
 ) -> Result<
     (
         Vec<(Option<MPath>, HgManifestId, HgChangesetId)>,
@@ -793,7 +1284,7 @@ async fn diff_with_parents(
     ),
     Error,
 > {
-    let (mf_id, parent_mf_ids) = try_join!(fetch_manifest(ctx.clone(), &repo, &hg_cs_id), async {
+    let (mf_id, parent_mf_ids) = try_join!(manifest_cache.get(&ctx, &repo, hg_cs_id), async {
         let parents = repo
             .get_changeset_parents(ctx.clone(), hg_cs_id)
             .compat()
@@ -802,7 +1293,8 @@ async fn diff_with_parents(
         future::try_join_all(
             parents
                 .iter()
-                .map(|p| fetch_manifest(ctx.clone(), &repo, p)),
+                .filter(|p| shallow_commits.map_or(true, |commits| commits.contains(p)))
+                .map(|p| manifest_cache.get(&ctx, &repo, *p)),
         )
         .await
     })?;
@@ -886,6 +1378,9 @@ pub async fn get_manifests_and_filenodes(
     repo: &BlobRepo,
     commits: Vec<HgChangesetId>,
     lfs_params: &SessionLfsParams,
+    draft_filenodes_params: &DraftFilenodesParams,
+    cg_version: CgVersion,
+    shallow_commits: Option<&HashSet<HgChangesetId>>,
 ) -> Result<
     (
         Vec<(Option<MPath>, HgManifestId, HgChangesetId)>,
@@ -893,16 +1388,36 @@ pub async fn get_manifests_and_filenodes(
     ),
     Error,
 > {
+    let path_filter = draft_filenodes_params.checked_path_filter()?;
+    let manifest_cache = &ManifestIdCache::new();
     let entries: Vec<_> = stream::iter(commits)
         .then({
             |hg_cs_id| async move {
-                let (manifests, filenodes) =
-                    diff_with_parents(ctx.clone(), repo.clone(), hg_cs_id).await?;
+                let (manifests, filenodes) = diff_with_parents(
+                    ctx.clone(),
+                    repo.clone(),
+                    manifest_cache,
+                    hg_cs_id,
+                    shallow_commits,
+                )
+                .await?;
+                let filenodes = match path_filter {
+                    Some(path_filter) => {
+                        skip_filtered_filenodes(ctx, repo, path_filter, filenodes).await?
+                    }
+                    None => filenodes,
+                };
 
                 let filenodes: Vec<(MPath, Vec<PreparedFilenodeEntry>)> =
-                    prepare_filenode_entries_stream(&ctx, &repo, filenodes, &lfs_params)
-                        .try_collect()
-                        .await?;
+                    prepare_filenode_entries_stream(
+                        &ctx,
+                        &repo,
+                        filenodes,
+                        &lfs_params,
+                        cg_version,
+                    )
+                    .try_collect()
+                    .await?;
                 Result::<_, Error>::Ok((manifests, filenodes))
             }
         })
@@ -924,6 +1439,37 @@ pub async fn get_manifests_and_filenodes(
     Ok((all_mf_entries, all_filenode_entries))
 }
 
+/// Drop the filenodes whose path is rejected by `path_filter`, counting the bytes of the
+/// files that won't be sent.
+async fn skip_filtered_filenodes(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    path_filter: &PathFilter,
+    filenodes: Vec<(MPath, HgFileNodeId, HgChangesetId)>,
+) -> Result<Vec<(MPath, HgFileNodeId, HgChangesetId)>, Error> {
+    let (included, skipped): (Vec<_>, Vec<_>) = filenodes
+        .into_iter()
+        .partition(|(path, _, _)| path_filter(path));
+
+    let skipped_bytes: u64 = stream::iter(skipped)
+        .map(|(_, filenode, _)| async move {
+            let envelope = filenode
+                .load(ctx.clone(), repo.blobstore())
+                .compat()
+                .await?;
+            Ok::<_, Error>(envelope.content_size())
+        })
+        .buffer_unordered(100)
+        .try_fold(0, |acc, size| future::ready(Ok(acc + size)))
+        .await?;
+    ctx.perf_counters().add_to_counter(
+        PerfCounterType::GetbundleFilenodesSkippedBytes,
+        skipped_bytes as i64,
+    );
+
+    Ok(included)
+}
+
 async fn fetch_manifest(
     ctx: CoreContext,
     repo: &BlobRepo,
@@ -932,3 +1478,1092 @@ async fn fetch_manifest(
     let blob_cs = hg_cs_id.load(ctx, repo.blobstore()).compat().await?;
     Ok(blob_cs.manifestid())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blobrepo::DangerousOverride;
+    use blobrepo_factory::new_memblob_empty;
+    use blobstore::{Blobstore, BlobstoreBytes};
+    use bonsai_hg_mapping::{BonsaiHgMapping, BonsaiHgMappingEntry, BonsaiOrHgChangesetIds};
+    use fbinit::FacebookInit;
+    use fixtures::linear;
+    use futures_ext::BoxFuture;
+    use mercurial_bundles::{create_bundle_stream, PartHeaderType};
+    use mercurial_types::{HgChangesetIdPrefix, HgChangesetIdsResolvedFromPrefix};
+    use mercurial_types_mocks::nodehash::ONES_CSID as HG_ONES_CSID;
+    use mononoke_types::RepositoryId;
+    use mononoke_types_mocks::changesetid::ONES_CSID;
+    use skiplist::SkiplistIndex;
+    use std::str::FromStr;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tests_utils::CreateCommitContext;
+
+    /// Counts how many times each hg changeset is looked up in the bonsai-hg mapping.
+    struct CountingBonsaiHgMapping {
+        inner: Arc<dyn BonsaiHgMapping>,
+        hg_gets: Arc<Mutex<HashMap<HgChangesetId, usize>>>,
+    }
+
+    impl BonsaiHgMapping for CountingBonsaiHgMapping {
+        fn add(&self, ctx: CoreContext, entry: BonsaiHgMappingEntry) -> BoxFuture<bool, Error> {
+            self.inner.add(ctx, entry)
+        }
+
+        fn get(
+            &self,
+            ctx: CoreContext,
+            repo_id: RepositoryId,
+            cs_id: BonsaiOrHgChangesetIds,
+        ) -> BoxFuture<Vec<BonsaiHgMappingEntry>, Error> {
+            if let BonsaiOrHgChangesetIds::Hg(hg_cs_ids) = &cs_id {
+                let mut hg_gets = self.hg_gets.lock().unwrap();
+                for hg_cs_id in hg_cs_ids {
+                    *hg_gets.entry(*hg_cs_id).or_insert(0) += 1;
+                }
+            }
+            self.inner.get(ctx, repo_id, cs_id)
+        }
+
+        fn get_many_hg_by_prefix(
+            &self,
+            ctx: CoreContext,
+            repo_id: RepositoryId,
+            cs_prefix: HgChangesetIdPrefix,
+            limit: usize,
+        ) -> BoxFuture<HgChangesetIdsResolvedFromPrefix, Error> {
+            self.inner
+                .get_many_hg_by_prefix(ctx, repo_id, cs_prefix, limit)
+        }
+    }
+
+    /// Counts how many times each key is fetched from the blobstore.
+    #[derive(Debug)]
+    struct CountingBlobstore {
+        inner: Arc<dyn Blobstore>,
+        gets: Arc<Mutex<HashMap<String, usize>>>,
+    }
+
+    impl Blobstore for CountingBlobstore {
+        fn get(&self, ctx: CoreContext, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+            *self.gets.lock().unwrap().entry(key.clone()).or_insert(0) += 1;
+            self.inner.get(ctx, key)
+        }
+
+        fn put(
+            &self,
+            ctx: CoreContext,
+            key: String,
+            value: BlobstoreBytes,
+        ) -> BoxFuture<(), Error> {
+            self.inner.put(ctx, key, value)
+        }
+    }
+
+    #[fbinit::compat_test]
+    async fn test_cg3_without_lfs(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let hg_cs_id = HgChangesetId::from_str("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")?;
+
+        let lfs_params = SessionLfsParams {
+            threshold: None,
+            always_lfs: false,
+        };
+        assert_eq!(
+            select_cg_version(Some(CgVersion::Cg3Version), &lfs_params)?,
+            CgVersion::Cg3Version
+        );
+
+        let (_, filenodes) = get_manifests_and_filenodes(
+            &ctx,
+            &repo,
+            vec![hg_cs_id],
+            &lfs_params,
+            &DraftFilenodesParams::default(),
+            CgVersion::Cg3Version,
+            None,
+        )
+        .await?;
+        assert!(!filenodes.is_empty());
+
+        for entry in filenodes.into_iter().flat_map(|(_, entries)| entries) {
+            let (_, _, _, flags) = entry.into_filenode(ctx.clone(), repo.clone()).await?;
+            assert_eq!(flags, Some(RevFlags::REVIDX_DEFAULT_FLAGS));
+        }
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_phases_part(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let root = HgChangesetId::from_str("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536")?;
+        let middle = HgChangesetId::from_str("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")?;
+        let tip = HgChangesetId::from_str("79a13814c5ce7330173ec04d279bf95ab3f652fb")?;
+        let common = vec![root];
+
+        // The first set of heads has none in common, so its phases are reused. The second
+        // includes a common head, so phases for all heads are calculated separately.
+        for heads in vec![vec![tip, middle], vec![tip, root]] {
+            let filtered_heads: Vec<_> = heads
+                .iter()
+                .filter(|head| !common.contains(head))
+                .cloned()
+                .collect();
+            let mapping_cache = HgBonsaiMappingCache::new();
+            let filtered_phases = prepare_phases(
+                &ctx,
+                &repo,
+                &mapping_cache,
+                &filtered_heads,
+                &repo.get_phases(),
+            )
+            .await?;
+            let phases_for_part = prepare_phases_for_part(
+                &ctx,
+                &repo,
+                &mapping_cache,
+                &heads,
+                &filtered_heads,
+                &filtered_phases,
+            )
+            .await?;
+            let sequential = prepare_phases(
+                &ctx,
+                &repo,
+                &HgBonsaiMappingCache::new(),
+                &heads,
+                &repo.get_phases(),
+            )
+            .await?;
+            assert_eq!(phases_for_part, sequential);
+
+            let mut parts = create_getbundle_response(
+                ctx.clone(),
+                repo.clone(),
+                "repo".to_string(),
+                common.clone(),
+                heads,
+                Arc::new(SkiplistIndex::new()),
+                PhasesPart::Yes,
+                SessionLfsParams {
+                    threshold: None,
+                    always_lfs: false,
+                },
+                DraftsInBundlesPolicy::CommitsOnly,
+                GetbundleParams::default(),
+            )
+            .await?;
+            let part_types: Vec<_> = parts.iter().map(|part| *part.part_type()).collect();
+            assert_eq!(
+                part_types,
+                vec![PartHeaderType::Changegroup, PartHeaderType::PhaseHeads]
+            );
+
+            // The phases part is encoded the same as one built after the changegroup part,
+            // from phases calculated for all heads.
+            let phases_part = parts.pop().expect("phases part is missing");
+            let sequential_part = parts::phases_part(ctx.clone(), old_stream::iter_ok(sequential))?;
+            assert_eq!(
+                encode_part(phases_part).await?,
+                encode_part(sequential_part).await?
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn encode_part(part: PartEncodeBuilder) -> Result<BytesOld, Error> {
+        create_bundle_stream(vec![part], None)
+            .concat2()
+            .compat()
+            .await
+    }
+
+    #[fbinit::compat_test]
+    async fn test_hg_mapping_cached(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let hg_gets = Arc::new(Mutex::new(HashMap::new()));
+        let repo = linear::getrepo(fb).await.dangerous_override({
+            cloned!(hg_gets);
+            move |inner| -> Arc<dyn BonsaiHgMapping> {
+                Arc::new(CountingBonsaiHgMapping { inner, hg_gets })
+            }
+        });
+        let root = HgChangesetId::from_str("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536")?;
+        let tip = HgChangesetId::from_str("79a13814c5ce7330173ec04d279bf95ab3f652fb")?;
+
+        // The tip is needed to find the commits to send, and for both phases calculations.
+        create_getbundle_response(
+            ctx.clone(),
+            repo,
+            "repo".to_string(),
+            vec![root],
+            vec![tip],
+            Arc::new(SkiplistIndex::new()),
+            PhasesPart::Yes,
+            SessionLfsParams {
+                threshold: None,
+                always_lfs: false,
+            },
+            DraftsInBundlesPolicy::CommitsOnly,
+            GetbundleParams::default(),
+        )
+        .await?;
+
+        let hg_gets = hg_gets.lock().unwrap();
+        assert_eq!(hg_gets.get(&tip), Some(&1));
+        assert_eq!(hg_gets.get(&root), Some(&1));
+        assert!(
+            ctx.perf_counters()
+                .get_counter(PerfCounterType::GetbundleHgMappingCacheHits)
+                > 0
+        );
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_hg_mapping_fallback(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let root = HgChangesetId::from_str("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536")?;
+        let root_bcs_id = repo
+            .get_bonsai_from_hg(ctx.clone(), root)
+            .compat()
+            .await?
+            .expect("root should have a bonsai changeset");
+
+        // Hg changesets aren't derived for new bonsai changesets.
+        let bcs_id = CreateCommitContext::new(&ctx, &repo, vec![root_bcs_id])
+            .add_file("new_file", "content")
+            .commit()
+            .await?;
+        let mapping = repo
+            .get_hg_bonsai_mapping(ctx.clone(), vec![bcs_id])
+            .compat()
+            .await?;
+        assert!(mapping.is_empty());
+
+        let mapped = map_to_hg_changesets(
+            &ctx,
+            &repo,
+            &HgBonsaiMappingCache::new(),
+            vec![root_bcs_id, bcs_id],
+        )
+        .await?;
+        let hg_cs_id = repo
+            .get_hg_from_bonsai_changeset(ctx.clone(), bcs_id)
+            .compat()
+            .await?;
+        assert_eq!(mapped, vec![(root, root_bcs_id), (hg_cs_id, bcs_id)]);
+        assert_eq!(
+            ctx.perf_counters()
+                .get_counter(PerfCounterType::GetbundleHgMappingFallbacks),
+            1
+        );
+
+        // No such changeset, so it can't be derived either.
+        let err = map_to_hg_changesets(&ctx, &repo, &HgBonsaiMappingCache::new(), vec![ONES_CSID])
+            .await
+            .expect_err("unknown changesets can't be mapped");
+        match err.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::MissingHgMapping(bcs_id)) => assert_eq!(*bcs_id, ONES_CSID),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_find_commits_to_send_null_heads(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let lca_hint: Arc<dyn LeastCommonAncestorsHint> = Arc::new(SkiplistIndex::new());
+        let root = HgChangesetId::from_str("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536")?;
+        let middle = HgChangesetId::from_str("3e0e761030db6e479a7fb58b12881883f9f8c63f")?;
+        let common: HashSet<_> = vec![root].into_iter().collect();
+        let mapping_cache = HgBonsaiMappingCache::new();
+
+        // A null head next to real heads is ignored.
+        let expected = find_commits_to_send(
+            &ctx,
+            &repo,
+            &mapping_cache,
+            &common,
+            &vec![middle],
+            &lca_hint,
+            None,
+        )
+        .await?;
+        assert_eq!(expected.len(), 1);
+        let with_null = find_commits_to_send(
+            &ctx,
+            &repo,
+            &mapping_cache,
+            &common,
+            &vec![NULL_CSID, middle, NULL_CSID],
+            &lca_hint,
+            None,
+        )
+        .await?;
+        assert_eq!(with_null, expected);
+
+        // Only null heads means there is nothing to send, even with no common heads.
+        let only_null = find_commits_to_send(
+            &ctx,
+            &repo,
+            &mapping_cache,
+            &common,
+            &vec![NULL_CSID],
+            &lca_hint,
+            None,
+        )
+        .await?;
+        assert!(only_null.is_empty());
+        let only_null = find_commits_to_send(
+            &ctx,
+            &repo,
+            &mapping_cache,
+            &HashSet::new(),
+            &vec![NULL_CSID],
+            &lca_hint,
+            None,
+        )
+        .await?;
+        assert!(only_null.is_empty());
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_find_commits_to_send_cancelled(fb: FacebookInit) -> Result<(), Error> {
+        let repo = linear::getrepo(fb).await;
+        let lca_hint: Arc<dyn LeastCommonAncestorsHint> = Arc::new(SkiplistIndex::new());
+        let root = HgChangesetId::from_str("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536")?;
+        let middle = HgChangesetId::from_str("3e0e761030db6e479a7fb58b12881883f9f8c63f")?;
+        let common: HashSet<_> = vec![root].into_iter().collect();
+        let mapping_cache = HgBonsaiMappingCache::new();
+
+        // A context whose deadline has already passed is cancelled.
+        let ctx = CoreContext::test_mock(fb).with_deadline(Instant::now());
+        let res = find_commits_to_send(
+            &ctx,
+            &repo,
+            &mapping_cache,
+            &common,
+            &vec![middle],
+            &lca_hint,
+            None,
+        )
+        .await;
+        match res {
+            Err(e) => match e.downcast_ref::<ErrorKind>() {
+                Some(ErrorKind::Cancelled) => {}
+                _ => panic!("unexpected error: {:?}", e),
+            },
+            Ok(nodes) => panic!("expected cancellation, got {:?}", nodes),
+        }
+
+        // A fresh context completes normally.
+        let ctx =
+            CoreContext::test_mock(fb).with_deadline(Instant::now() + Duration::from_secs(3600));
+        let nodes = find_commits_to_send(
+            &ctx,
+            &repo,
+            &mapping_cache,
+            &common,
+            &vec![middle],
+            &lca_hint,
+            None,
+        )
+        .await?;
+        assert_eq!(nodes.len(), 1);
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_find_commits_to_send_many_common_heads(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = new_memblob_empty(None)?;
+        let changeset_fetcher = repo.get_changeset_fetcher();
+
+        // A trunk of 20 commits, with a branch off every other one of them.
+        let mut trunk: Vec<ChangesetId> = vec![];
+        let mut branches = vec![];
+        for i in 0..20 {
+            let parents: Vec<_> = trunk.last().cloned().into_iter().collect();
+            let bcs_id = CreateCommitContext::new(&ctx, &repo, parents)
+                .add_file("trunk", format!("{}", i))
+                .commit()
+                .await?;
+            trunk.push(bcs_id);
+            if i % 2 == 0 {
+                let branch = CreateCommitContext::new(&ctx, &repo, vec![bcs_id])
+                    .add_file("branch", format!("{}", i))
+                    .commit()
+                    .await?;
+                branches.push(branch);
+            }
+        }
+        // The client has all of them, and pulls new commits on the trunk and on an old commit.
+        let tip = CreateCommitContext::new(&ctx, &repo, vec![trunk[19]])
+            .add_file("trunk", "tip")
+            .commit()
+            .await?;
+        let old = CreateCommitContext::new(&ctx, &repo, vec![trunk[3]])
+            .add_file("old", "old")
+            .commit()
+            .await?;
+
+        let sli = SkiplistIndex::with_skip_edge_count(4);
+        for head in branches.iter().chain(vec![&tip, &old]) {
+            sli.add_node(ctx.clone(), changeset_fetcher.clone(), *head, 100)
+                .compat()
+                .await?;
+        }
+        let lca_hint: Arc<dyn LeastCommonAncestorsHint> = Arc::new(sli);
+
+        let excludes: Vec<_> = trunk.iter().chain(branches.iter()).cloned().collect();
+        let mut common = HashSet::new();
+        for bcs_id in &excludes {
+            common.insert(
+                repo.get_hg_from_bonsai_changeset(ctx.clone(), *bcs_id)
+                    .compat()
+                    .await?,
+            );
+        }
+        let mut heads = vec![];
+        for bcs_id in &[tip, old] {
+            heads.push(
+                repo.get_hg_from_bonsai_changeset(ctx.clone(), *bcs_id)
+                    .compat()
+                    .await?,
+            );
+        }
+
+        // The trunk commits down to the lowest head are ancestors of the trunk tip.
+        let pruned = prune_excludes(
+            &ctx,
+            &changeset_fetcher,
+            &lca_hint,
+            &[tip, old],
+            excludes.clone(),
+        )
+        .await?;
+        let expected: HashSet<_> = trunk[..4]
+            .iter()
+            .chain(branches.iter())
+            .chain(vec![&trunk[19]])
+            .cloned()
+            .collect();
+        assert_eq!(pruned.len(), expected.len());
+        assert_eq!(pruned.into_iter().collect::<HashSet<_>>(), expected);
+        assert_eq!(
+            ctx.perf_counters()
+                .get_counter(PerfCounterType::GetbundlePrunedExcludes),
+            15
+        );
+
+        // The same commits are sent as without pruning the common heads.
+        let commits = find_commits_to_send(
+            &ctx,
+            &repo,
+            &HgBonsaiMappingCache::new(),
+            &common,
+            &heads,
+            &lca_hint,
+            None,
+        )
+        .await?;
+        let unpruned: Vec<_> = DifferenceOfUnionsOfAncestorsNodeStream::new_with_excludes(
+            ctx.clone(),
+            &changeset_fetcher,
+            lca_hint.clone(),
+            vec![tip, old],
+            excludes,
+        )
+        .collect()
+        .compat()
+        .await?;
+        assert_eq!(commits, unpruned.into_iter().rev().collect::<Vec<_>>());
+        assert_eq!(commits, vec![old, tip]);
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_shallow_pull(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = new_memblob_empty(None)?;
+        let lca_hint: Arc<dyn LeastCommonAncestorsHint> = Arc::new(SkiplistIndex::new());
+        let lfs_params = SessionLfsParams {
+            threshold: None,
+            always_lfs: false,
+        };
+
+        // A linear history of 10 commits, each adding a file and modifying a shared one.
+        let mut bcs_ids: Vec<ChangesetId> = vec![];
+        for i in 0..10 {
+            let parents: Vec<_> = bcs_ids.last().cloned().into_iter().collect();
+            let bcs_id = CreateCommitContext::new(&ctx, &repo, parents)
+                .add_file(format!("files/{}", i), format!("{}", i))
+                .add_file("shared", format!("shared {}", i))
+                .commit()
+                .await?;
+            bcs_ids.push(bcs_id);
+        }
+        let mut hg_cs_ids = vec![];
+        for bcs_id in &bcs_ids {
+            hg_cs_ids.push(
+                repo.get_hg_from_bonsai_changeset(ctx.clone(), *bcs_id)
+                    .compat()
+                    .await?,
+            );
+        }
+        let common: HashSet<_> = vec![NULL_CSID].into_iter().collect();
+        let heads = vec![hg_cs_ids[9]];
+
+        let commits = find_commits_to_send(
+            &ctx,
+            &repo,
+            &HgBonsaiMappingCache::new(),
+            &common,
+            &heads,
+            &lca_hint,
+            Some(3),
+        )
+        .await?;
+        assert_eq!(commits, bcs_ids[7..].to_vec());
+
+        // The oldest commit sent gets its whole tree, the others are diffed against their
+        // parents as usual.
+        let shallow_commits: HashSet<_> = hg_cs_ids[7..].iter().cloned().collect();
+        let oldest = hg_cs_ids[7];
+        let (manifests, filenodes) = get_manifests_and_filenodes(
+            &ctx,
+            &repo,
+            hg_cs_ids[7..].to_vec(),
+            &lfs_params,
+            &DraftFilenodesParams::default(),
+            CgVersion::Cg3Version,
+            Some(&shallow_commits),
+        )
+        .await?;
+        let oldest_root_mf = fetch_manifest(ctx.clone(), &repo, &oldest).await?;
+        assert!(manifests
+            .iter()
+            .any(|(path, mf_id, linknode)| path.is_none()
+                && *mf_id == oldest_root_mf
+                && *linknode == oldest));
+        let paths_linked_to = |hg_cs_id: HgChangesetId| {
+            let mut paths: Vec<_> = filenodes
+                .iter()
+                .filter(|(_, entries)| entries.iter().any(|entry| entry.linknode == hg_cs_id))
+                .map(|(path, _)| path.to_string())
+                .collect();
+            paths.sort();
+            paths
+        };
+        let mut oldest_tree: Vec<_> = (0..8).map(|i| format!("files/{}", i)).collect();
+        oldest_tree.push("shared".to_string());
+        assert_eq!(paths_linked_to(oldest), oldest_tree);
+        assert_eq!(
+            paths_linked_to(hg_cs_ids[8]),
+            vec!["files/8".to_string(), "shared".to_string()]
+        );
+
+        // Without the shallow boundary, the oldest commit is diffed against its parent.
+        let (_, filenodes) = get_manifests_and_filenodes(
+            &ctx,
+            &repo,
+            vec![oldest],
+            &lfs_params,
+            &DraftFilenodesParams::default(),
+            CgVersion::Cg3Version,
+            None,
+        )
+        .await?;
+        let mut paths: Vec<_> = filenodes.keys().map(|path| path.to_string()).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["files/7".to_string(), "shared".to_string()]);
+
+        // A shallow pull sends trees and files even if drafts are sent without them.
+        let parts = create_getbundle_response(
+            ctx.clone(),
+            repo.clone(),
+            "repo".to_string(),
+            vec![NULL_CSID],
+            heads,
+            lca_hint,
+            PhasesPart::No,
+            lfs_params,
+            DraftsInBundlesPolicy::CommitsOnly,
+            GetbundleParams {
+                shallow_depth: Some(3),
+                ..GetbundleParams::default()
+            },
+        )
+        .await?;
+        let part_types: Vec<_> = parts.iter().map(|part| *part.part_type()).collect();
+        assert_eq!(
+            part_types,
+            vec![PartHeaderType::Changegroup, PartHeaderType::B2xTreegroup2]
+        );
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_manifests_memoized_in_stack(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = new_memblob_empty(None)?;
+        let lfs_params = SessionLfsParams {
+            threshold: None,
+            always_lfs: false,
+        };
+
+        // A stack of 5 commits, each the parent of the next.
+        let mut bcs_ids: Vec<ChangesetId> = vec![];
+        for i in 0..5 {
+            let parents: Vec<_> = bcs_ids.last().cloned().into_iter().collect();
+            let bcs_id = CreateCommitContext::new(&ctx, &repo, parents)
+                .add_file(format!("files/{}", i), format!("{}", i))
+                .add_file("shared", format!("shared {}", i))
+                .commit()
+                .await?;
+            bcs_ids.push(bcs_id);
+        }
+        let mut hg_cs_ids = vec![];
+        for bcs_id in &bcs_ids {
+            hg_cs_ids.push(
+                repo.get_hg_from_bonsai_changeset(ctx.clone(), *bcs_id)
+                    .compat()
+                    .await?,
+            );
+        }
+
+        // The changesets and manifests the stack needs without memoization, diffing each commit
+        // on its own.
+        let mut expected_manifests = vec![];
+        let mut expected_files = vec![];
+        for hg_cs_id in &hg_cs_ids {
+            let (manifests, files) = diff_with_parents(
+                ctx.clone(),
+                repo.clone(),
+                &ManifestIdCache::new(),
+                *hg_cs_id,
+                None,
+            )
+            .await?;
+            expected_manifests.extend(manifests);
+            expected_files.extend(files);
+        }
+
+        let gets = Arc::new(Mutex::new(HashMap::new()));
+        let counting_repo = repo.dangerous_override({
+            cloned!(gets);
+            move |inner| -> Arc<dyn Blobstore> { Arc::new(CountingBlobstore { inner, gets }) }
+        });
+        let ctx = CoreContext::test_mock(fb);
+        let (manifests, filenodes) = get_manifests_and_filenodes(
+            &ctx,
+            &counting_repo,
+            hg_cs_ids.clone(),
+            &lfs_params,
+            &DraftFilenodesParams::default(),
+            CgVersion::Cg3Version,
+            None,
+        )
+        .await?;
+
+        // Each changeset is loaded once, although all but the last one are also parents.
+        let changeset_gets: Vec<_> = gets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.contains("hgchangeset."))
+            .map(|(_, count)| *count)
+            .collect();
+        assert_eq!(changeset_gets, vec![1; 5]);
+        assert_eq!(
+            ctx.perf_counters()
+                .get_counter(PerfCounterType::GetbundleManifestCacheHits),
+            4
+        );
+
+        // The results are the same as without memoization.
+        assert_eq!(manifests, expected_manifests);
+        let mut files: Vec<_> = filenodes
+            .into_iter()
+            .flat_map(|(path, entries)| {
+                entries
+                    .into_iter()
+                    .map(move |entry| (path.clone(), entry.filenode, entry.linknode))
+            })
+            .collect();
+        files.sort();
+        expected_files.sort();
+        assert_eq!(files, expected_files);
+
+        Ok(())
+    }
+
+    async fn lfs_paths(
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        hg_cs_id: HgChangesetId,
+        lfs_params: SessionLfsParams,
+    ) -> Result<Vec<MPath>, Error> {
+        let (_, filenodes) = get_manifests_and_filenodes(
+            ctx,
+            repo,
+            vec![hg_cs_id],
+            &lfs_params,
+            &DraftFilenodesParams::default(),
+            CgVersion::Cg3Version,
+            None,
+        )
+        .await?;
+        let mut paths: Vec<_> = filenodes
+            .into_iter()
+            .filter(|(_, entries)| {
+                entries
+                    .iter()
+                    .all(|entry| entry.maybe_get_lfs_pointer().is_some())
+            })
+            .map(|(path, _)| path)
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    #[fbinit::compat_test]
+    async fn test_lfs_threshold_boundaries(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let root = HgChangesetId::from_str("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536")?;
+        let root_bcs_id = repo
+            .get_bonsai_from_hg(ctx.clone(), root)
+            .compat()
+            .await?
+            .expect("root should have a bonsai changeset");
+        let bcs_id = CreateCommitContext::new(&ctx, &repo, vec![root_bcs_id])
+            .add_file("empty", "")
+            .add_file("one_byte", "a")
+            .commit()
+            .await?;
+        let hg_cs_id = repo
+            .get_hg_from_bonsai_changeset(ctx.clone(), bcs_id)
+            .compat()
+            .await?;
+        let empty = MPath::new("empty")?;
+        let one_byte = MPath::new("one_byte")?;
+
+        // A threshold of 0 sends only non-empty files as LFS.
+        let lfs_params = SessionLfsParams {
+            threshold: Some(0),
+            always_lfs: false,
+        };
+        assert!(!lfs_params.use_lfs(0));
+        assert!(lfs_params.use_lfs(1));
+        assert_eq!(
+            lfs_paths(&ctx, &repo, hg_cs_id, lfs_params).await?,
+            vec![one_byte.clone()]
+        );
+
+        // Always LFS sends empty files as LFS too.
+        let lfs_params = SessionLfsParams {
+            threshold: Some(0),
+            always_lfs: true,
+        };
+        assert!(lfs_params.use_lfs(0));
+        assert!(lfs_params.use_lfs(1));
+        assert_eq!(
+            lfs_paths(&ctx, &repo, hg_cs_id, lfs_params).await?,
+            vec![empty, one_byte]
+        );
+
+        // Always LFS has no effect when LFS is disabled.
+        let lfs_params = SessionLfsParams {
+            threshold: None,
+            always_lfs: true,
+        };
+        assert!(!lfs_params.use_lfs(1));
+        assert!(lfs_paths(&ctx, &repo, hg_cs_id, lfs_params)
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_draft_path_filter(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let root = HgChangesetId::from_str("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536")?;
+        let root_bcs_id = repo
+            .get_bonsai_from_hg(ctx.clone(), root)
+            .compat()
+            .await?
+            .expect("root should have a bonsai changeset");
+        let bcs_id = CreateCommitContext::new(&ctx, &repo, vec![root_bcs_id])
+            .add_file("src/main.rs", "main")
+            .add_file("vendor/lib.rs", "vendored")
+            .commit()
+            .await?;
+        let hg_cs_id = repo
+            .get_hg_from_bonsai_changeset(ctx.clone(), bcs_id)
+            .compat()
+            .await?;
+        let lfs_params = SessionLfsParams {
+            threshold: None,
+            always_lfs: false,
+        };
+        let vendor = MPath::new("vendor")?;
+        let path_filter: PathFilter = Arc::new({
+            cloned!(vendor);
+            move |path: &MPath| !vendor.is_prefix_of(path)
+        });
+
+        // The filter has to be explicitly allowed to produce partial bundles.
+        let params = DraftFilenodesParams {
+            path_filter: Some(path_filter.clone()),
+            allow_partial_bundles: false,
+        };
+        assert!(get_manifests_and_filenodes(
+            &ctx,
+            &repo,
+            vec![hg_cs_id],
+            &lfs_params,
+            &params,
+            CgVersion::Cg3Version,
+            None,
+        )
+        .await
+        .is_err());
+
+        let params = DraftFilenodesParams {
+            path_filter: Some(path_filter),
+            allow_partial_bundles: true,
+        };
+        let (manifests, filenodes) = get_manifests_and_filenodes(
+            &ctx,
+            &repo,
+            vec![hg_cs_id],
+            &lfs_params,
+            &params,
+            CgVersion::Cg3Version,
+            None,
+        )
+        .await?;
+        assert!(filenodes.contains_key(&MPath::new("src/main.rs")?));
+        assert!(!filenodes.contains_key(&MPath::new("vendor/lib.rs")?));
+        assert!(manifests
+            .iter()
+            .any(|(path, _, _)| path.as_ref() == Some(&vendor)));
+        assert_eq!(
+            ctx.perf_counters()
+                .get_counter(PerfCounterType::GetbundleFilenodesSkippedBytes),
+            "vendored".len() as i64
+        );
+
+        // Filtering is only possible when draft trees and files are sent.
+        let res = create_getbundle_response(
+            ctx.clone(),
+            repo.clone(),
+            "repo".to_string(),
+            vec![],
+            vec![hg_cs_id],
+            Arc::new(SkiplistIndex::new()),
+            PhasesPart::No,
+            lfs_params,
+            DraftsInBundlesPolicy::CommitsOnly,
+            GetbundleParams {
+                draft_filenodes_params: params,
+                ..GetbundleParams::default()
+            },
+        )
+        .await;
+        assert!(res.is_err());
+
+        Ok(())
+    }
+
+    /// Returns an entry for each of the changesets it's asked about, and remembers them
+    struct TestMutationFetcher {
+        requested: Mutex<Vec<HgChangesetId>>,
+    }
+
+    #[async_trait]
+    impl MutationFetcher for TestMutationFetcher {
+        async fn fetch_for(
+            &self,
+            _ctx: &CoreContext,
+            changesets: Vec<HgChangesetId>,
+        ) -> Result<Vec<HgMutationEntry>, Error> {
+            self.requested
+                .lock()
+                .unwrap()
+                .extend(changesets.iter().cloned());
+            Ok(changesets
+                .into_iter()
+                .map(|successor| HgMutationEntry {
+                    successor,
+                    predecessors: vec![HG_ONES_CSID],
+                    split: vec![],
+                    op: "amend".to_string(),
+                    user: "test".to_string(),
+                    timestamp: 0,
+                    timezone: 0,
+                    extra: vec![],
+                })
+                .collect())
+        }
+    }
+
+    #[fbinit::compat_test]
+    async fn test_mutation_part(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let public = HgChangesetId::from_str("79a13814c5ce7330173ec04d279bf95ab3f652fb")?;
+        let root = HgChangesetId::from_str("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536")?;
+        let root_bcs_id = repo
+            .get_bonsai_from_hg(ctx.clone(), root)
+            .compat()
+            .await?
+            .expect("root should have a bonsai changeset");
+        let first_bcs_id = CreateCommitContext::new(&ctx, &repo, vec![root_bcs_id])
+            .add_file("amended", "amended")
+            .commit()
+            .await?;
+        let first_draft = repo
+            .get_hg_from_bonsai_changeset(ctx.clone(), first_bcs_id)
+            .compat()
+            .await?;
+        let bcs_id = CreateCommitContext::new(&ctx, &repo, vec![first_bcs_id])
+            .add_file("rebased", "rebased")
+            .commit()
+            .await?;
+        let draft = repo
+            .get_hg_from_bonsai_changeset(ctx.clone(), bcs_id)
+            .compat()
+            .await?;
+
+        let get_part_types =
+            |heads: Vec<HgChangesetId>, fetcher: Option<Arc<TestMutationFetcher>>| {
+                cloned!(ctx, repo);
+                async move {
+                    let parts = create_getbundle_response(
+                        ctx,
+                        repo,
+                        "repo".to_string(),
+                        vec![],
+                        heads,
+                        Arc::new(SkiplistIndex::new()),
+                        PhasesPart::Yes,
+                        SessionLfsParams {
+                            threshold: None,
+                            always_lfs: false,
+                        },
+                        DraftsInBundlesPolicy::CommitsOnly,
+                        GetbundleParams {
+                            mutation_fetcher: fetcher
+                                .map(|fetcher| fetcher as Arc<dyn MutationFetcher>),
+                            ..GetbundleParams::default()
+                        },
+                    )
+                    .await?;
+                    Ok::<_, Error>(
+                        parts
+                            .iter()
+                            .map(|part| *part.part_type())
+                            .collect::<Vec<_>>(),
+                    )
+                }
+            };
+
+        let fetcher = Arc::new(TestMutationFetcher {
+            requested: Mutex::new(vec![]),
+        });
+        assert_eq!(
+            get_part_types(vec![draft, public], Some(fetcher.clone())).await?,
+            vec![
+                PartHeaderType::Changegroup,
+                PartHeaderType::B2xInfinitepushMutation,
+                PartHeaderType::PhaseHeads,
+            ]
+        );
+        // All the draft commits that are sent have mutation entries fetched, not only the
+        // draft heads, and none of the public ones do.
+        assert_eq!(
+            fetcher
+                .requested
+                .lock()
+                .unwrap()
+                .iter()
+                .cloned()
+                .collect::<HashSet<_>>(),
+            vec![first_draft, draft].into_iter().collect::<HashSet<_>>()
+        );
+
+        // Without draft heads, there is no mutation part.
+        let fetcher = Arc::new(TestMutationFetcher {
+            requested: Mutex::new(vec![]),
+        });
+        assert_eq!(
+            get_part_types(vec![public], Some(fetcher.clone())).await?,
+            vec![PartHeaderType::Changegroup, PartHeaderType::PhaseHeads]
+        );
+        assert!(fetcher.requested.lock().unwrap().is_empty());
+
+        // Nor is there one unless mutation entries are requested.
+        assert_eq!(
+            get_part_types(vec![draft], None).await?,
+            vec![PartHeaderType::Changegroup, PartHeaderType::PhaseHeads]
+        );
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_cg2_with_lfs_fails(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+        let hg_cs_id = HgChangesetId::from_str("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")?;
+
+        let res = create_getbundle_response(
+            ctx,
+            repo,
+            "repo".to_string(),
+            vec![],
+            vec![hg_cs_id],
+            Arc::new(SkiplistIndex::new()),
+            PhasesPart::No,
+            SessionLfsParams {
+                threshold: Some(10),
+                always_lfs: false,
+            },
+            DraftsInBundlesPolicy::CommitsOnly,
+            GetbundleParams {
+                requested_cg_version: Some(CgVersion::Cg2Version),
+                ..GetbundleParams::default()
+            },
+        )
+        .await;
+        assert!(res.is_err());
+
+        // Without an explicit request, the LFS threshold still selects cg3.
+        let lfs_params = SessionLfsParams {
+            threshold: Some(10),
+            always_lfs: false,
+        };
+        assert_eq!(select_cg_version(None, &lfs_params)?, CgVersion::Cg3Version);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Error;
+use blobrepo::BlobRepo;
+use context::{CoreContext, PerfCounterType};
+use mercurial_types::{HgChangesetId, HgManifestId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::fetch_manifest;
+
+/// Memoizes the root manifests of hg changesets for a single getbundle request. The commits of
+/// a stack are each other's parents, so without it most of them would be loaded twice: once to
+/// be diffed against their parents, and once to diff their children against them.
+#[derive(Clone, Default)]
+pub(crate) struct ManifestIdCache {
+    inner: Arc<Mutex<HashMap<HgChangesetId, HgManifestId>>>,
+}
+
+impl ManifestIdCache {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// The root manifest of `hg_cs_id`, loading the changeset unless it was loaded before.
+    pub(crate) async fn get(
+        &self,
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        hg_cs_id: HgChangesetId,
+    ) -> Result<HgManifestId, Error> {
+        let cached = self
+            .inner
+            .lock()
+            .expect("poisoned lock")
+            .get(&hg_cs_id)
+            .cloned();
+        if let Some(mf_id) = cached {
+            ctx.perf_counters()
+                .increment_counter(PerfCounterType::GetbundleManifestCacheHits);
+            return Ok(mf_id);
+        }
+
+        let mf_id = fetch_manifest(ctx.clone(), repo, &hg_cs_id).await?;
+        self.inner
+            .lock()
+            .expect("poisoned lock")
+            .insert(hg_cs_id, mf_id);
+        Ok(mf_id)
+    }
+}
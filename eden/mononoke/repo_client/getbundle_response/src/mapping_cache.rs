@@ -0,0 +1,158 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Error;
+use blobrepo::BlobRepo;
+use context::{CoreContext, PerfCounterType};
+use futures::{
+    compat::Future01CompatExt,
+    stream::{self, StreamExt, TryStreamExt},
+};
+use mercurial_types::HgChangesetId;
+use mononoke_types::ChangesetId;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+/// Number of changesets looked up in the mapping at once
+const MAPPING_CHUNK_SIZE: usize = 100;
+/// Number of chunks looked up in the mapping concurrently
+const MAPPING_CONCURRENCY: usize = 10;
+
+/// Memoizes hg <-> bonsai changeset mapping lookups for a single getbundle request, so that
+/// changesets needed by several of its steps are only looked up once. Changesets that are not
+/// in the mapping are remembered as well.
+#[derive(Clone, Default)]
+pub(crate) struct HgBonsaiMappingCache {
+    inner: Arc<Mutex<MappingCacheInner>>,
+}
+
+#[derive(Default)]
+struct MappingCacheInner {
+    hg_to_bonsai: HashMap<HgChangesetId, Option<ChangesetId>>,
+    bonsai_to_hg: HashMap<ChangesetId, Option<HgChangesetId>>,
+}
+
+impl HgBonsaiMappingCache {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Map hg changesets to bonsai changesets. Hg changesets unknown to the server are
+    /// missing from the result.
+    pub(crate) async fn get_bonsais(
+        &self,
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        hg_cs_ids: Vec<HgChangesetId>,
+    ) -> Result<HashMap<HgChangesetId, ChangesetId>, Error> {
+        let (mut result, uncached) = self.lookup(ctx, hg_cs_ids, |inner| &inner.hg_to_bonsai);
+        let fetched = fetch_mapping(uncached.clone(), |ids| {
+            repo.get_hg_bonsai_mapping(ctx.clone(), ids)
+        })
+        .await?;
+
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        for hg_cs_id in uncached {
+            inner.hg_to_bonsai.insert(hg_cs_id, None);
+        }
+        for (hg_cs_id, bcs_id) in fetched {
+            inner.hg_to_bonsai.insert(hg_cs_id, Some(bcs_id));
+            inner.bonsai_to_hg.insert(bcs_id, Some(hg_cs_id));
+            result.insert(hg_cs_id, bcs_id);
+        }
+        Ok(result)
+    }
+
+    /// Map bonsai changesets to hg changesets. Bonsai changesets missing from the mapping are
+    /// missing from the result.
+    pub(crate) async fn get_hgs(
+        &self,
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        bcs_ids: Vec<ChangesetId>,
+    ) -> Result<HashMap<ChangesetId, HgChangesetId>, Error> {
+        let (mut result, uncached) = self.lookup(ctx, bcs_ids, |inner| &inner.bonsai_to_hg);
+        let fetched = fetch_mapping(uncached.clone(), |ids| {
+            repo.get_hg_bonsai_mapping(ctx.clone(), ids)
+        })
+        .await?;
+
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        for bcs_id in uncached {
+            inner.bonsai_to_hg.insert(bcs_id, None);
+        }
+        for (hg_cs_id, bcs_id) in fetched {
+            inner.hg_to_bonsai.insert(hg_cs_id, Some(bcs_id));
+            inner.bonsai_to_hg.insert(bcs_id, Some(hg_cs_id));
+            result.insert(bcs_id, hg_cs_id);
+        }
+        Ok(result)
+    }
+
+    /// Record a mapping that was found without looking up the mapping, e.g. by deriving the
+    /// hg changeset.
+    pub(crate) fn insert(&self, hg_cs_id: HgChangesetId, bcs_id: ChangesetId) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        inner.hg_to_bonsai.insert(hg_cs_id, Some(bcs_id));
+        inner.bonsai_to_hg.insert(bcs_id, Some(hg_cs_id));
+    }
+
+    /// Split `ids` into the ones mapped in the cache, and the ones that aren't cached yet.
+    fn lookup<K, V>(
+        &self,
+        ctx: &CoreContext,
+        ids: Vec<K>,
+        map: impl Fn(&MappingCacheInner) -> &HashMap<K, Option<V>>,
+    ) -> (HashMap<K, V>, Vec<K>)
+    where
+        K: Copy + Eq + Hash,
+        V: Copy,
+    {
+        let inner = self.inner.lock().expect("poisoned lock");
+        let map = map(&inner);
+        let mut found = HashMap::new();
+        let mut uncached = Vec::new();
+        let mut hits = 0;
+        for id in ids {
+            match map.get(&id) {
+                Some(cached) => {
+                    hits += 1;
+                    if let Some(value) = cached {
+                        found.insert(id, *value);
+                    }
+                }
+                None => uncached.push(id),
+            }
+        }
+        ctx.perf_counters()
+            .add_to_counter(PerfCounterType::GetbundleHgMappingCacheHits, hits);
+        (found, uncached)
+    }
+}
+
+/// Look up `ids` in the mapping in chunks.
+async fn fetch_mapping<Id, F, Fut>(
+    ids: Vec<Id>,
+    get: F,
+) -> Result<Vec<(HgChangesetId, ChangesetId)>, Error>
+where
+    Id: Clone,
+    F: Fn(Vec<Id>) -> Fut,
+    Fut: futures_old::Future<Item = Vec<(HgChangesetId, ChangesetId)>, Error = Error>,
+{
+    let chunks: Vec<Vec<Id>> = ids
+        .chunks(MAPPING_CHUNK_SIZE)
+        .map(|chunk| chunk.to_vec())
+        .collect();
+    let fetched: Vec<Vec<_>> = stream::iter(chunks)
+        .map(|chunk| get(chunk).compat())
+        .buffered(MAPPING_CONCURRENCY)
+        .try_collect()
+        .await?;
+    Ok(fetched.into_iter().flatten().collect())
+}
@@ -126,9 +126,13 @@ impl MononokeRepo {
         if allowed {
             SessionLfsParams {
                 threshold: self.lfs_params.threshold,
+                always_lfs: self.lfs_params.always_lfs,
             }
         } else {
-            SessionLfsParams { threshold: None }
+            SessionLfsParams {
+                threshold: None,
+                always_lfs: false,
+            }
         }
     }
 
@@ -158,7 +158,10 @@ pub fn create_getpack_v1_blob(
         ctx,
         repo,
         node,
-        SessionLfsParams { threshold: None },
+        SessionLfsParams {
+            threshold: None,
+            always_lfs: false,
+        },
         validate_hash,
     )
     .map(move |RemotefilelogBlob { kind, data }| {
@@ -240,7 +243,10 @@ pub fn create_raw_filenode_blob(
         ctx,
         repo,
         node,
-        SessionLfsParams { threshold: None },
+        SessionLfsParams {
+            threshold: None,
+            always_lfs: false,
+        },
         validate_hash,
     )
     .and_then(|RemotefilelogBlob { kind, data }| {
@@ -308,10 +314,7 @@ fn prepare_blob(
         move |envelope| {
             let file_size = envelope.content_size();
 
-            let inline_file = match lfs_params.threshold {
-                Some(lfs_threshold) => (file_size <= lfs_threshold),
-                None => true,
-            };
+            let inline_file = !lfs_params.use_lfs(file_size);
 
             // NOTE: It'd be nice if we could hoist up redaction checks to this point. Doing so
             // would let us return a different kind based on whether the content is redacted or
@@ -443,7 +446,10 @@ mod test {
             ctx.clone(),
             repo.clone(),
             filenode,
-            SessionLfsParams { threshold: None },
+            SessionLfsParams {
+                threshold: None,
+                always_lfs: false,
+            },
             true,
         )
         .compat()
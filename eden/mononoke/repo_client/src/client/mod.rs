@@ -27,7 +27,7 @@ use futures_old::{future, stream, try_ready, Async, Future, IntoFuture, Poll, St
 use futures_stats::{Timed, TimedStreamTrait};
 use futures_util::{FutureExt, TryFutureExt};
 use getbundle_response::{
-    create_getbundle_response, DraftsInBundlesPolicy, PhasesPart, SessionLfsParams,
+    create_getbundle_response, DraftsInBundlesPolicy, GetbundleParams, PhasesPart, SessionLfsParams,
 };
 use hgproto::{GetbundleArgs, GettreepackArgs, HgCommandRes, HgCommands};
 use hostname::get_hostname;
@@ -63,7 +63,7 @@ use std::fmt::Write;
 use std::mem;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use streaming_clone::RevlogStreamingChunks;
 use time_ext::DurationExt;
 use tokio_old::timer::timeout::Error as TimeoutError;
@@ -441,6 +441,7 @@ impl RepoClient {
                 },
                 lfs_params,
                 drafts_in_bundles_policy,
+                GetbundleParams::default(),
             )
             .await
         }
@@ -652,17 +653,14 @@ impl RepoClient {
                                 length as i64,
                             );
 
-                            if let Some(lfs_threshold) = lfs_params.threshold {
-                                if length >= lfs_threshold {
-                                    ctx.perf_counters().add_to_counter(
-                                        PerfCounterType::GetpackPossibleLFSFilesSumSize,
-                                        length as i64,
-                                    );
+                            if lfs_params.use_lfs(length) {
+                                ctx.perf_counters().add_to_counter(
+                                    PerfCounterType::GetpackPossibleLFSFilesSumSize,
+                                    length as i64,
+                                );
 
-                                    ctx.perf_counters().increment_counter(
-                                        PerfCounterType::GetpackNumPossibleLFSFiles,
-                                    );
-                                }
+                                ctx.perf_counters()
+                                    .increment_counter(PerfCounterType::GetpackNumPossibleLFSFiles);
                             }
 
                             res.push(wirepack::Part::Data(wirepack::DataEntry {
@@ -1211,6 +1209,12 @@ impl HgCommands for RepoClient {
     // @wireprotocommand('getbundle', '*')
     fn getbundle(&self, args: GetbundleArgs) -> BoxStream<BytesOld, Error> {
         let (ctx, command_logger) = self.start_command(ops::GETBUNDLE);
+        // The response is abandoned once it times out, or once the session's own deadline has
+        // passed, so stop building it then.
+        let timeout_deadline = Instant::now() + *GETBUNDLE_TIMEOUT;
+        let ctx = ctx.with_deadline(ctx.deadline().map_or(timeout_deadline, |deadline| {
+            std::cmp::min(deadline, timeout_deadline)
+        }));
 
         let value = json!({
             "bundlecaps": format_utf8_bytes_list(&args.bundlecaps),
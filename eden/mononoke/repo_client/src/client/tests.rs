@@ -138,7 +138,7 @@ fn test_pushredirect_config() {
         maybe_pushvars: None,
         commonheads: CommonHeads { heads: Vec::new() },
         uploaded_bonsais: HashSet::new(),
-        uploaded_hg_changeset_ids: HashSet::new(),
+        uploaded_hg_changeset_ids: HashMap::new(),
     });
     let bookmark_only_action =
         PostResolveAction::BookmarkOnlyPushRebase(PostResolveBookmarkOnlyPushRebase {
@@ -462,6 +462,45 @@ async fn test_lfs_rollout(fb: FacebookInit) -> Result<(), Error> {
     Ok(())
 }
 
+#[fbinit::compat_test]
+async fn test_getbundle_deadline(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let repo = blobrepo_factory::new_memblob_empty(None)?;
+    let commit = CreateCommitContext::new_root(&ctx, &repo)
+        .add_file("file", "content")
+        .commit()
+        .await?;
+    let hg_cs_id = repo
+        .get_hg_from_bonsai_changeset(ctx.clone(), commit)
+        .compat()
+        .await?;
+    let args = || GetbundleArgs {
+        heads: vec![hg_cs_id],
+        common: vec![],
+        bundlecaps: HashSet::new(),
+        listkeys: vec![],
+        phases: false,
+    };
+
+    // Without a deadline of its own, the session gets the bundle.
+    let repo_client = create_repo_client(&ctx, &repo, ctx.session().clone(), LfsParams::default())?;
+    repo_client.getbundle(args()).concat2().compat().await?;
+
+    // Once the session's deadline has passed, getbundle stops building the bundle.
+    let session = ctx.session().clone().with_deadline(Instant::now());
+    let repo_client = create_repo_client(&ctx, &repo, session, LfsParams::default())?;
+    match repo_client.getbundle(args()).concat2().compat().await {
+        Err(e) => assert!(
+            format!("{:#}", e).contains("deadline has passed"),
+            "unexpected error: {:#}",
+            e
+        ),
+        Ok(_) => panic!("expected getbundle to be cancelled"),
+    }
+
+    Ok(())
+}
+
 async fn run_and_check_if_lfs(
     ctx: &CoreContext,
     repo: &BlobRepo,
@@ -469,6 +508,27 @@ async fn run_and_check_if_lfs(
     filenode_id: &HgFileNodeId,
     lfs_params: LfsParams,
 ) -> Result<bool, Error> {
+    let repo_client = create_repo_client(ctx, repo, ctx.session().clone(), lfs_params)?;
+
+    let bytes = repo_client
+        .getpackv2(stream::iter_ok(vec![(path.clone(), vec![*filenode_id])]).boxify())
+        .concat2()
+        .compat()
+        .await?;
+
+    let lfs_url: &[u8] = b"version https://git-lfs.github.com/spec/v1";
+
+    let found = bytes.windows(lfs_url.len()).any(|w| w == lfs_url);
+
+    Ok(found)
+}
+
+fn create_repo_client(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    session: SessionContainer,
+    lfs_params: LfsParams,
+) -> Result<RepoClient, Error> {
     let pushrebase_params = PushrebaseParams::default();
 
     let mononoke_repo = MononokeRepo::new(
@@ -477,10 +537,13 @@ async fn run_and_check_if_lfs(
         vec![],
         Arc::new(HookManager::new(
             ctx.fb,
+            repo.name().clone(),
+            repo.get_repoid(),
             Box::new(InMemoryChangesetStore::new()),
             Arc::new(InMemoryFileContentStore::new()),
             HookManagerParams {
                 disable_acl_checker: true,
+                run_hooks_on_bonsai: false,
             },
             ScubaSampleBuilder::with_discard(),
         )),
@@ -502,9 +565,9 @@ async fn run_and_check_if_lfs(
     let noop_wireproto =
         WireprotoLogging::new(ctx.fb, mononoke_repo.reponame().clone(), None, None, None)?;
 
-    let repo_client = RepoClient::new(
+    Ok(RepoClient::new(
         mononoke_repo,
-        ctx.session().clone(),
+        session,
         logging,
         100,   // hash validation percentage
         false, // Don't preserve raw bundle 2 (we don't push)
@@ -513,19 +576,7 @@ async fn run_and_check_if_lfs(
         Arc::new(noop_wireproto),
         None, // Don't push redirect (we don't push)
         None, // Don't push redirect (we don't push)
-    );
-
-    let bytes = repo_client
-        .getpackv2(stream::iter_ok(vec![(path.clone(), vec![*filenode_id])]).boxify())
-        .concat2()
-        .compat()
-        .await?;
-
-    let lfs_url: &[u8] = b"version https://git-lfs.github.com/spec/v1";
-
-    let found = bytes.windows(lfs_url.len()).any(|w| w == lfs_url);
-
-    Ok(found)
+    ))
 }
 
 async fn fetch_mfs(
@@ -12,7 +12,7 @@ use context::CoreContext;
 use futures::{FutureExt, TryFutureExt};
 use futures_ext::{BoxFuture, FutureExt as _};
 use futures_old::future::ok;
-use hooks::{HookManager, HookOutcome};
+use hooks::{HookExecutionMode, HookManager, HookOutcome};
 use std::sync::Arc;
 
 pub fn run_hooks(
@@ -34,14 +34,42 @@ fn run_pushrebase_hooks(
     action: &PostResolvePushRebase,
     hook_manager: Arc<HookManager>,
 ) -> BoxFuture<(), BundleResolverError> {
-    let changesets = action.uploaded_hg_changeset_ids.clone();
+    let changesets: Vec<_> = action
+        .uploaded_hg_changeset_ids
+        .iter()
+        .map(|(hg_cs_id, bcs_id)| (*hg_cs_id, Some(*bcs_id)))
+        .collect();
+    let bonsai_changesets: Vec<_> = action
+        .uploaded_bonsais
+        .iter()
+        .map(|bcs| bcs.get_changeset_id())
+        .collect();
     let maybe_pushvars = action.maybe_pushvars.clone();
     let bookmark = action.bookmark_spec.get_bookmark_name();
 
     async move {
-        let hook_failures: Vec<_> = hook_manager
-            .run_hooks_for_bookmark(&ctx, changesets, &bookmark, maybe_pushvars.as_ref())
-            .await?
+        let outcomes = if hook_manager.runs_hooks_on_bonsai() {
+            hook_manager
+                .run_hooks_for_bookmark_bonsai(
+                    &ctx,
+                    bonsai_changesets,
+                    &bookmark,
+                    maybe_pushvars.as_ref(),
+                    HookExecutionMode::StopAtFirstFailure,
+                )
+                .await?
+        } else {
+            hook_manager
+                .run_hooks_for_bookmark(
+                    &ctx,
+                    changesets,
+                    &bookmark,
+                    maybe_pushvars.as_ref(),
+                    HookExecutionMode::StopAtFirstFailure,
+                )
+                .await?
+        };
+        let hook_failures: Vec<_> = outcomes
             .into_iter()
             .filter(HookOutcome::is_rejection)
             .collect();
@@ -27,7 +27,7 @@ use futures_ext::{try_boxfuture, BoxFuture, BoxStream, FutureExt, StreamExt};
 use futures_old::future::{self, err, ok, Shared};
 use futures_old::stream;
 use futures_old::{Future, IntoFuture, Stream};
-use hooks::{HookExecution, HookOutcome};
+use hooks::{format_rejections, HookOutcome};
 use lazy_static::lazy_static;
 use limits::types::RateLimit;
 use mercurial_bundles::{Bundle2Item, PartHeader, PartHeaderInner, PartHeaderType, PartId};
@@ -51,7 +51,11 @@ type Filelogs = HashMap<HgNodeKey, Shared<BoxFuture<(HgBlobEntry, RepoPath), Com
 type ContentBlobs = HashMap<HgNodeKey, ContentBlobInfo>;
 type Manifests = HashMap<HgNodeKey, <TreemanifestEntry as UploadableHgBlob>::Value>;
 pub type UploadedBonsais = HashSet<BonsaiChangeset>;
-pub type UploadedHgChangesetIds = HashSet<HgChangesetId>;
+/// The hg changesets uploaded by a push, with the bonsai changesets they were generated from
+pub type UploadedHgChangesetIds = HashMap<HgChangesetId, ChangesetId>;
+
+/// Maximum number of rejections of each hook that are shown to the user.
+const MAX_REJECTIONS_PER_HOOK: usize = 20;
 
 // This is to match the core hg behavior from https://fburl.com/jf3iyl7y
 // Mercurial substitutes the `onto` parameter with this bookmark name when
@@ -101,22 +105,9 @@ impl From<BundleResolverError> for Error {
         use BundleResolverError::*;
         match error {
             HookError(hook_outcomes) => {
-                let err_msgs: Vec<_> = hook_outcomes
-                    .into_iter()
-                    .filter_map(|outcome| {
-                        let exec = outcome.get_execution();
-                        match exec {
-                            HookExecution::Accepted => None,
-                            HookExecution::Rejected(info) => Some(format!(
-                                "{} for {}: {}",
-                                outcome.get_hook_name(),
-                                outcome.get_cs_id(),
-                                info.long_description
-                            )),
-                        }
-                    })
-                    .collect();
-                format_err!("hooks failed:\n{}", err_msgs.join("\n"))
+                let err_msg =
+                    format_rejections(&hook_outcomes, MAX_REJECTIONS_PER_HOOK).unwrap_or_default();
+                format_err!("hooks failed:\n{}", err_msg)
             }
             PushrebaseConflicts(conflicts) => {
                 format_err!("pushrebase failed Conflicts({:?})", conflicts)
@@ -1168,7 +1159,10 @@ impl Bundle2Resolver {
                                                     let bcs = shared_item.0.clone();
                                                     bcs
                                                 })
-                                                .map(move |bcs| (bcs, hg_cs_id))
+                                                .map(move |bcs| {
+                                                    let bcs_id = bcs.get_changeset_id();
+                                                    (bcs, (hg_cs_id, bcs_id))
+                                                })
                                         },
                                     ))
                                     .buffered(chunk_size)
@@ -17,7 +17,7 @@ use futures_old::{Future, Stream};
 use futures_stats::Timed;
 use futures_util::{FutureExt, TryFutureExt};
 use getbundle_response::{
-    create_getbundle_response, DraftsInBundlesPolicy, PhasesPart, SessionLfsParams,
+    create_getbundle_response, DraftsInBundlesPolicy, GetbundleParams, PhasesPart, SessionLfsParams,
 };
 use mercurial_bundles::{create_bundle_stream, parts, Bundle2EncodeBuilder, PartId};
 use metaconfig_types::PushrebaseParams;
@@ -180,6 +180,7 @@ impl UnbundleResponse {
                         // with public commits atm, so the value we are passing
                         // here is inconsequential.
                         DraftsInBundlesPolicy::CommitsOnly,
+                        GetbundleParams::default(),
                     )
                     .await
                 }
@@ -19,7 +19,7 @@ use failure_ext::chain::ChainExt;
 use futures_ext::StreamExt;
 use futures_old::future::Future;
 use futures_old::stream::{iter_ok, Stream};
-use futures_old::{Async, Poll};
+use futures_old::{try_ready, Async, Poll};
 use maplit::hashset;
 
 use crate::UniqueHeap;
@@ -37,6 +37,10 @@ pub struct AncestorsNodeStream {
     next_generation: BTreeMap<Generation, HashSet<ChangesetId>>,
     pending_changesets: Box<dyn Stream<Item = (ChangesetId, Generation), Error = Error> + Send>,
     drain: IntoIter<ChangesetId>,
+    // generation of the nodes in `drain`, unknown for the node the walk starts from
+    drain_generation: Option<Generation>,
+    // ancestors below this generation are neither output nor walked through
+    min_generation: Generation,
 
     // max heap of all relevant unique generation numbers
     sorted_unique_generations: UniqueHeap<Generation>,
@@ -89,6 +93,8 @@ impl AncestorsNodeStream {
                 node_set.clone().into_iter(),
             ),
             drain: node_set.into_iter(),
+            drain_generation: None,
+            min_generation: Generation::new(0),
             sorted_unique_generations: UniqueHeap::new(),
         }
     }
@@ -109,6 +115,9 @@ impl Stream for AncestorsNodeStream {
         loop {
             match self.pending_changesets.poll()? {
                 Async::Ready(Some((hash, generation))) => {
+                    if generation < self.min_generation {
+                        continue;
+                    }
                     self.next_generation
                         .entry(generation)
                         .or_insert_with(HashSet::new)
@@ -140,12 +149,69 @@ impl Stream for AncestorsNodeStream {
             current_generation.clone().into_iter(),
         );
         self.drain = current_generation.into_iter();
+        self.drain_generation = Some(highest_generation);
         Ok(Async::Ready(Some(self.drain.next().expect(
             "Cannot create a generation without at least one node hash",
         ))))
     }
 }
 
+/// The ancestors of a changeset (inclusive) whose generation numbers are within
+/// `min_generation..=max_generation`, in descending generation order, e.g. for a page of a
+/// log. Ancestors below `min_generation` aren't fetched at all, so the walk stops at the bottom
+/// of the window rather than going through the whole history. To get the ancestors between two
+/// commits, use the generation of the older one as `min_generation`.
+pub struct GenerationBoundedAncestorsNodeStream {
+    inner: AncestorsNodeStream,
+    max_generation: Generation,
+}
+
+impl GenerationBoundedAncestorsNodeStream {
+    pub fn new(
+        ctx: CoreContext,
+        changeset_fetcher: &Arc<dyn ChangesetFetcher>,
+        hash: ChangesetId,
+        min_generation: Generation,
+        max_generation: Generation,
+    ) -> Self {
+        // Unlike in AncestorsNodeStream, the starting node goes through `pending_changesets`, so
+        // that its generation is known and it is bounded like the other nodes.
+        let pending_changesets = changeset_fetcher
+            .get_generation_number(ctx.clone(), hash)
+            .map(move |gen_id| (hash, gen_id))
+            .map_err(|err| err.chain_err(ErrorKind::GenerationFetchFailed).into())
+            .into_stream();
+        let inner = AncestorsNodeStream {
+            ctx,
+            changeset_fetcher: changeset_fetcher.clone(),
+            next_generation: BTreeMap::new(),
+            pending_changesets: Box::new(pending_changesets),
+            drain: HashSet::new().into_iter(),
+            drain_generation: None,
+            min_generation,
+            sorted_unique_generations: UniqueHeap::new(),
+        };
+        GenerationBoundedAncestorsNodeStream {
+            inner,
+            max_generation,
+        }
+    }
+}
+
+impl Stream for GenerationBoundedAncestorsNodeStream {
+    type Item = ChangesetId;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match try_ready!(self.inner.poll()) {
+                // Above the window, but its ancestors may be in it.
+                Some(_) if self.inner.drain_generation > Some(self.max_generation) => {}
+                next => return Ok(Async::Ready(next)),
+            }
+        }
+    }
+}
+
 pub fn common_ancestors<I>(
     ctx: CoreContext,
     changeset_fetcher: Arc<dyn ChangesetFetcher>,
@@ -221,6 +287,70 @@ mod test {
         });
     }
 
+    #[fbinit::test]
+    fn linear_generation_bounded_ancestors(fb: FacebookInit) {
+        async_unit::tokio_unit_test(async move {
+            let ctx = CoreContext::test_mock(fb);
+            let repo = Arc::new(linear::getrepo(fb).await);
+            let changeset_fetcher: Arc<dyn ChangesetFetcher> =
+                Arc::new(TestChangesetFetcher::new(repo.clone()));
+            // Generation 8
+            let head =
+                string_to_bonsai(fb, &repo, "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157").await;
+
+            let nodestream = GenerationBoundedAncestorsNodeStream::new(
+                ctx.clone(),
+                &changeset_fetcher,
+                head,
+                Generation::new(3),
+                Generation::new(6),
+            )
+            .boxify();
+            assert_changesets_sequence(
+                ctx.clone(),
+                &repo,
+                vec![
+                    string_to_bonsai(fb, &repo, "eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b").await,
+                    string_to_bonsai(fb, &repo, "cb15ca4a43a59acff5388cea9648c162afde8372").await,
+                    string_to_bonsai(fb, &repo, "d0a361e9022d226ae52f689667bd7d212a19cfe0").await,
+                    string_to_bonsai(fb, &repo, "607314ef579bd2407752361ba1b0c1729d08b281").await,
+                ],
+                nodestream,
+            )
+            .await;
+
+            // The starting commit is bounded like its ancestors.
+            let nodestream = GenerationBoundedAncestorsNodeStream::new(
+                ctx.clone(),
+                &changeset_fetcher,
+                head,
+                Generation::new(7),
+                Generation::max_gen(),
+            )
+            .boxify();
+            assert_changesets_sequence(
+                ctx.clone(),
+                &repo,
+                vec![
+                    head,
+                    string_to_bonsai(fb, &repo, "0ed509bf086fadcb8a8a5384dc3b550729b0fc17").await,
+                ],
+                nodestream,
+            )
+            .await;
+
+            let nodestream = GenerationBoundedAncestorsNodeStream::new(
+                ctx.clone(),
+                &changeset_fetcher,
+                head,
+                Generation::new(9),
+                Generation::new(10),
+            )
+            .boxify();
+            assert_changesets_sequence(ctx.clone(), &repo, vec![], nodestream).await;
+        });
+    }
+
     #[fbinit::test]
     fn merge_ancestors_from_merge(fb: FacebookInit) {
         async_unit::tokio_unit_test(async move {
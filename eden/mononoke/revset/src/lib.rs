@@ -31,7 +31,10 @@ mod validation;
 pub use crate::validation::ValidateNodeStream;
 
 mod ancestors;
-pub use crate::ancestors::{common_ancestors, greatest_common_ancestor, AncestorsNodeStream};
+pub use crate::ancestors::{
+    common_ancestors, greatest_common_ancestor, AncestorsNodeStream,
+    GenerationBoundedAncestorsNodeStream,
+};
 
 mod ancestorscombinators;
 pub use crate::ancestorscombinators::DifferenceOfUnionsOfAncestorsNodeStream;
@@ -44,6 +44,12 @@ impl From<MononokeError> for ServiceError {
                 kind: thrift::RequestErrorKind::NOT_AVAILABLE,
                 reason: error.to_string(),
             }),
+            error @ MononokeError::PathNotFound(_)
+            | error @ MononokeError::BookmarkNotFound(_)
+            | error @ MononokeError::BlameRejected { .. } => Self::Request(thrift::RequestError {
+                kind: thrift::RequestErrorKind::INVALID_REQUEST,
+                reason: error.to_string(),
+            }),
             MononokeError::InternalError(error) => {
                 let reason = error.to_string();
                 let backtrace = error
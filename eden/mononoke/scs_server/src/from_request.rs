@@ -12,7 +12,7 @@ use std::str::FromStr;
 
 use chrono::{DateTime, FixedOffset, TimeZone};
 use faster_hex::hex_string;
-use mononoke_api::specifiers::{GitSha1, Globalrev};
+use mononoke_api::specifiers::{GitSha1, Globalrev, GlobalrevPrefix};
 use mononoke_api::{
     ChangesetId, ChangesetIdPrefix, ChangesetPrefixSpecifier, ChangesetSpecifier, CopyInfo,
     CreateCopyInfo, FileId, FileType, HgChangesetId, HgChangesetIdPrefix, MononokePath, TreeId,
@@ -116,6 +116,15 @@ impl FromRequest<thrift::RepoResolveCommitPrefixParams> for ChangesetPrefixSpeci
                 })?;
                 Ok(ChangesetPrefixSpecifier::from(prefix))
             }
+            thrift::CommitIdentityScheme::GLOBALREV => {
+                let prefix = GlobalrevPrefix::from_str(&params.prefix).map_err(|e| {
+                    errors::invalid_request(format!(
+                        "invalid commit id prefix (scheme={} {}): {}",
+                        params.prefix_scheme, params.prefix, e
+                    ))
+                })?;
+                Ok(ChangesetPrefixSpecifier::from(prefix))
+            }
             _ => Err(errors::invalid_request(format!(
                 "unsupported prefix identity scheme ({})",
                 params.prefix_scheme
@@ -8,9 +8,10 @@
 use fbinit::FacebookInit;
 use scuba_ext::ScubaSampleBuilder;
 use session_id::SessionId;
-use slog::{o, Drain, Level, Logger};
+use slog::{o, Discard, Drain, Level, Logger};
 use slog_glog_fmt::default_drain;
 use sshrelay::SshEnvVars;
+use std::time::Instant;
 use tracing::TraceContext;
 
 use crate::logging::{LoggingContainer, SamplingKey};
@@ -22,25 +23,32 @@ pub struct CoreContext {
     pub fb: FacebookInit,
     session: SessionContainer,
     logging: LoggingContainer,
+    deadline: Option<Instant>,
 }
 
 impl CoreContext {
+    pub fn builder(fb: FacebookInit) -> CoreContextBuilder {
+        CoreContextBuilder::new(fb)
+    }
+
     pub fn new_with_logger(fb: FacebookInit, logger: Logger) -> Self {
-        let session = SessionContainer::new_with_defaults(fb);
-        session.new_context(logger, ScubaSampleBuilder::with_discard())
+        Self::builder(fb).logger(logger).build()
     }
 
     pub fn test_mock(fb: FacebookInit) -> Self {
-        let session = SessionContainer::new_with_defaults(fb);
-
         let drain = default_drain().filter_level(Level::Debug).ignore_res();
         let logger = Logger::root(drain, o![]);
-        session.new_context(logger, ScubaSampleBuilder::with_discard())
+        Self::builder(fb).logger(logger).build()
     }
 
     pub fn clone_and_reset(&self) -> Self {
-        self.session
-            .new_context(self.logger().clone(), self.scuba().clone())
+        let ctx = self
+            .session
+            .new_context(self.logger().clone(), self.scuba().clone());
+        Self {
+            deadline: self.deadline,
+            ..ctx
+        }
     }
 
     pub fn clone_and_sample(&self, sampling_key: SamplingKey) -> Self {
@@ -48,6 +56,7 @@ impl CoreContext {
             fb: self.fb,
             session: self.session.clone(),
             logging: self.logging.clone_and_sample(sampling_key),
+            deadline: self.deadline,
         }
     }
 
@@ -55,8 +64,23 @@ impl CoreContext {
         &self,
         sample: impl FnOnce(ScubaSampleBuilder) -> ScubaSampleBuilder,
     ) -> Self {
-        self.session
-            .new_context(self.logger().clone(), sample(self.scuba().clone()))
+        let ctx = self
+            .session
+            .new_context(self.logger().clone(), sample(self.scuba().clone()));
+        Self {
+            deadline: self.deadline,
+            ..ctx
+        }
+    }
+
+    /// A copy of this context whose work should be abandoned once `deadline` has passed, e.g.
+    /// because the client has given up on the request. Long running operations check
+    /// `is_expired` and bail out early.
+    pub fn with_deadline(&self, deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..self.clone()
+        }
     }
 
     pub(crate) fn new_with_containers(
@@ -67,6 +91,7 @@ impl CoreContext {
         Self {
             fb,
             logging,
+            deadline: session.deadline(),
             session,
         }
     }
@@ -87,6 +112,28 @@ impl CoreContext {
         &self.logging.scuba()
     }
 
+    /// Whether this context's session was selected for full scuba logging. Call sites can skip
+    /// building expensive columns when it wasn't.
+    pub fn scuba_sampled(&self) -> bool {
+        self.logging.scuba_sampled()
+    }
+
+    /// A scuba builder that logs every sample even if this context's session wasn't selected
+    /// for scuba logging. Use it for error samples.
+    pub fn forced_scuba(&self) -> ScubaSampleBuilder {
+        self.logging.forced_scuba()
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Whether the deadline of this context has passed. Contexts without a deadline never expire.
+    pub fn is_expired(&self) -> bool {
+        self.deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+    }
+
     pub fn perf_counters(&self) -> &PerfCounters {
         &self.logging.perf_counters()
     }
@@ -116,3 +163,146 @@ impl CoreContext {
         &self.session
     }
 }
+
+/// Builds a `CoreContext` from its session, logger and seed scuba sample, so that the columns
+/// every sample of the context should have are set in one place. By default, the context gets a
+/// new session, discards its logs and doesn't log to scuba.
+pub struct CoreContextBuilder {
+    fb: FacebookInit,
+    session: Option<SessionContainer>,
+    logger: Option<Logger>,
+    scuba: ScubaSampleBuilder,
+    deadline: Option<Instant>,
+}
+
+impl CoreContextBuilder {
+    pub fn new(fb: FacebookInit) -> Self {
+        Self {
+            fb,
+            session: None,
+            logger: None,
+            scuba: ScubaSampleBuilder::with_discard(),
+            deadline: None,
+        }
+    }
+
+    pub fn session(mut self, value: SessionContainer) -> Self {
+        self.session = Some(value);
+        self
+    }
+
+    pub fn logger(mut self, value: Logger) -> Self {
+        self.logger = Some(value);
+        self
+    }
+
+    /// The scuba sample every sample of the context starts from. Columns set by the other
+    /// methods of the builder are added to it.
+    pub fn scuba(mut self, value: ScubaSampleBuilder) -> Self {
+        self.scuba = value;
+        self
+    }
+
+    /// Tag the samples of the context with the name of the repo it works on.
+    pub fn repo(mut self, reponame: impl Into<String>) -> Self {
+        self.scuba.add("reponame", reponame.into());
+        self
+    }
+
+    /// Tag the samples of the context with the hostname of the client it serves.
+    pub fn client_hostname(mut self, client_hostname: impl Into<String>) -> Self {
+        self.scuba.add("client_hostname", client_hostname.into());
+        self
+    }
+
+    /// Tag the samples of the context with an id shared by the contexts of related work, e.g.
+    /// the requests of a single client operation.
+    pub fn correlation_id(mut self, correlation_id: impl Into<String>) -> Self {
+        self.scuba.add("correlation_id", correlation_id.into());
+        self
+    }
+
+    /// See `CoreContext::with_deadline`.
+    pub fn deadline(mut self, value: Instant) -> Self {
+        self.deadline = Some(value);
+        self
+    }
+
+    pub fn build(self) -> CoreContext {
+        let fb = self.fb;
+        let session = self
+            .session
+            .unwrap_or_else(|| SessionContainer::new_with_defaults(fb));
+        let logger = self.logger.unwrap_or_else(|| Logger::root(Discard, o!()));
+        let ctx = session.new_context(logger, self.scuba);
+        CoreContext {
+            deadline: self.deadline.or(ctx.deadline),
+            ..ctx
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use scuba_ext::ScubaSampleBuilderExt;
+    use std::fs;
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    #[fbinit::test]
+    fn test_builder(fb: FacebookInit) {
+        let dir = TempDir::new("core_context_builder").unwrap();
+        let log_file = dir.path().join("scuba.json");
+        let mut scuba = ScubaSampleBuilder::with_discard()
+            .with_log_file(&log_file)
+            .unwrap();
+        scuba.add("seed", "column");
+        let session_id = SessionId::from_string("builder_session");
+        let deadline = Instant::now() + Duration::from_secs(60);
+
+        let ctx = CoreContext::builder(fb)
+            .session(
+                SessionContainer::builder(fb)
+                    .session_id(session_id.clone())
+                    .build(),
+            )
+            .scuba(scuba)
+            .repo("repo0")
+            .client_hostname("client.example.com")
+            .correlation_id("corr0")
+            .deadline(deadline)
+            .build();
+        assert_eq!(ctx.session_id(), &session_id);
+        assert_eq!(ctx.deadline(), Some(deadline));
+
+        // The preset columns are on every sample, including those of derived contexts.
+        ctx.scuba().clone().log_with_msg("first", None);
+        ctx.clone_and_reset()
+            .scuba()
+            .clone()
+            .log_with_msg("second", None);
+        let logged = fs::read_to_string(&log_file).unwrap();
+        let samples: Vec<serde_json::Value> = logged
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(samples.len(), 2);
+        for sample in samples {
+            assert_eq!(sample["normal"]["seed"], "column");
+            assert_eq!(sample["normal"]["reponame"], "repo0");
+            assert_eq!(sample["normal"]["client_hostname"], "client.example.com");
+            assert_eq!(sample["normal"]["correlation_id"], "corr0");
+        }
+
+        // Without any settings, the context gets a fresh session and no deadline.
+        let ctx = CoreContext::builder(fb).build();
+        assert_ne!(ctx.session_id(), &session_id);
+        assert_eq!(ctx.deadline(), None);
+
+        // Unless the builder sets one, the context gets the deadline of its session.
+        let session = SessionContainer::new_with_defaults(fb).with_deadline(deadline);
+        let ctx = CoreContext::builder(fb).session(session).build();
+        assert_eq!(ctx.deadline(), Some(deadline));
+    }
+}
@@ -11,7 +11,7 @@
 
 pub use session_id::SessionId;
 
-pub use crate::core::CoreContext;
+pub use crate::core::{CoreContext, CoreContextBuilder};
 #[cfg(fbcode_build)]
 pub use crate::facebook::prelude::*;
 pub use crate::logging::{LoggingContainer, SamplingKey};
@@ -37,6 +37,7 @@ pub struct LoggingContainer {
     scuba: Arc<ScubaSampleBuilder>,
     perf_counters: Arc<PerfCounters>,
     sampling_key: Option<SamplingKey>,
+    scuba_sampled: bool,
 }
 
 impl LoggingContainer {
@@ -46,15 +47,31 @@ impl LoggingContainer {
             scuba: Arc::new(scuba),
             perf_counters: Arc::new(PerfCounters::default()),
             sampling_key: None,
+            scuba_sampled: true,
         }
     }
 
+    /// Mark whether the session of this container was selected for full scuba logging.
+    pub fn with_scuba_sampled(self, scuba_sampled: bool) -> Self {
+        Self {
+            scuba_sampled,
+            ..self
+        }
+    }
+
+    /// Clone the container with an explicit sampling key. Sampling keys are used to follow
+    /// an action through all the samples it logs, so they override session scuba sampling.
     pub fn clone_and_sample(&self, sampling_key: SamplingKey) -> Self {
         Self {
             logger: self.logger.clone(),
-            scuba: self.scuba.clone(),
+            scuba: if self.scuba_sampled {
+                self.scuba.clone()
+            } else {
+                Arc::new(self.forced_scuba())
+            },
             perf_counters: self.perf_counters.clone(),
             sampling_key: Some(sampling_key),
+            scuba_sampled: true,
         }
     }
 
@@ -73,4 +90,18 @@ impl LoggingContainer {
     pub fn sampling_key(&self) -> Option<&SamplingKey> {
         self.sampling_key.as_ref()
     }
+
+    pub fn scuba_sampled(&self) -> bool {
+        self.scuba_sampled
+    }
+
+    /// A scuba builder that isn't subject to session sampling, for samples that must always be
+    /// logged, such as errors.
+    pub fn forced_scuba(&self) -> ScubaSampleBuilder {
+        let mut scuba = (*self.scuba).clone();
+        if !self.scuba_sampled {
+            scuba.unsampled();
+        }
+        scuba
+    }
 }
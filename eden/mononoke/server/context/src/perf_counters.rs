@@ -61,6 +61,12 @@ define_perf_counters! {
         GetbundleNumManifests,
         GetbundleNumFilenodes,
         GetbundleFilenodesTotalWeight,
+        GetbundlePhasesSavedMs,
+        GetbundleHgMappingFallbacks,
+        GetbundleHgMappingCacheHits,
+        GetbundleManifestCacheHits,
+        GetbundleFilenodesSkippedBytes,
+        GetbundlePrunedExcludes,
         GetfilesMaxFileSize,
         GetfilesMaxLatency,
         GetfilesNumFiles,
@@ -87,6 +93,7 @@ define_perf_counters! {
         SqlWrites,
         SumManifoldPollTime,
         NullLinknode,
+        FastlogBackfilledChangesets,
         NumKnown,
         NumUnknown,
     }
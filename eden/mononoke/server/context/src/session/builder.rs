@@ -32,6 +32,9 @@ impl SessionContainerBuilder {
         SessionContainer {
             fb: self.fb,
             inner: Arc::new(self.inner),
+            scuba_sample_rate: None,
+            forced_debug_logger: None,
+            deadline: None,
         }
     }
 
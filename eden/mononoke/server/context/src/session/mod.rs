@@ -8,9 +8,14 @@
 use fbinit::FacebookInit;
 use scuba_ext::ScubaSampleBuilder;
 use session_id::SessionId;
-use slog::Logger;
+use slog::{o, Drain, Filter, Level, Logger};
+use slog_glog_fmt::default_drain;
 use sshrelay::SshEnvVars;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroU64;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Semaphore;
 use tracing::TraceContext;
 
@@ -26,6 +31,9 @@ mod builder;
 pub struct SessionContainer {
     fb: FacebookInit,
     inner: Arc<SessionContainerInner>,
+    scuba_sample_rate: Option<NonZeroU64>,
+    forced_debug_logger: Option<Logger>,
+    deadline: Option<Instant>,
 }
 
 #[derive(Debug)]
@@ -49,12 +57,92 @@ impl SessionContainer {
         Self::builder(fb).build()
     }
 
-    pub fn new_context(&self, logger: Logger, scuba: ScubaSampleBuilder) -> CoreContext {
-        let logging = LoggingContainer::new(logger, scuba);
+    /// Select 1 in `sample_rate` sessions for full scuba logging. Contexts of the sessions that
+    /// aren't selected get a scuba builder sampled at `sample_rate`, and report `false` from
+    /// `CoreContext::scuba_sampled` so that call sites can skip expensive columns.
+    pub fn with_scuba_sampling(self, sample_rate: NonZeroU64) -> Self {
+        Self {
+            scuba_sample_rate: Some(sample_rate),
+            ..self
+        }
+    }
+
+    /// Log debug records of this session's contexts to stderr, whatever the level of the loggers
+    /// the contexts are created with, e.g. to debug the requests of a single user without
+    /// raising the level of the whole server. Scuba samples of these contexts are tagged with
+    /// `forced_debug`.
+    pub fn with_forced_debug(self) -> Self {
+        let drain = default_drain().filter_level(Level::Debug).ignore_res();
+        self.with_forced_debug_logger(Logger::root(drain, o!()))
+    }
+
+    /// Same as `with_forced_debug`, but debug records are logged to `debug_logger`.
+    pub fn with_forced_debug_logger(self, debug_logger: Logger) -> Self {
+        Self {
+            forced_debug_logger: Some(debug_logger),
+            ..self
+        }
+    }
+
+    pub fn is_forced_debug(&self) -> bool {
+        self.forced_debug_logger.is_some()
+    }
+
+    /// Give the contexts of this session a deadline, e.g. the time by which the client gives up
+    /// on its connection. See `CoreContext::with_deadline`.
+    pub fn with_deadline(self, deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn new_context(&self, logger: Logger, mut scuba: ScubaSampleBuilder) -> CoreContext {
+        let scuba_sampled = self.is_scuba_sampled();
+        match self.scuba_sample_rate {
+            Some(sample_rate) if !scuba_sampled => {
+                scuba.sampled(sample_rate);
+            }
+            _ => {}
+        }
+        let logger = match self.forced_debug_logger {
+            Some(ref debug_logger) => {
+                // Records below info bypass the level filter of `logger` by going to the debug
+                // logger instead, so that they aren't logged twice.
+                let drain = slog::Duplicate::new(
+                    Filter::new(logger, |record| record.level().is_at_least(Level::Info)),
+                    Filter::new(debug_logger.clone(), |record| {
+                        !record.level().is_at_least(Level::Info)
+                    }),
+                )
+                .ignore_res();
+                scuba.add("forced_debug", 1);
+                Logger::root(drain, o!())
+            }
+            None => logger,
+        };
+        let logging = LoggingContainer::new(logger, scuba).with_scuba_sampled(scuba_sampled);
 
         CoreContext::new_with_containers(self.fb, logging, self.clone())
     }
 
+    /// Whether this session is selected for full scuba logging. The selection is deterministic on
+    /// the session id. All sessions are selected unless `with_scuba_sampling` was used.
+    pub fn is_scuba_sampled(&self) -> bool {
+        match self.scuba_sample_rate {
+            Some(sample_rate) => {
+                let mut hasher = DefaultHasher::new();
+                self.session_id().to_string().hash(&mut hasher);
+                hasher.finish() % sample_rate.get() == 0
+            }
+            None => true,
+        }
+    }
+
     pub fn fb(&self) -> FacebookInit {
         self.fb
     }
@@ -88,3 +176,143 @@ impl SessionContainer {
         &self.inner.facebook_data
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logging::SamplingKey;
+    use scuba_ext::ScubaSampleBuilderExt;
+    use slog::{debug, info, Discard, Never, OwnedKVList, Record};
+    use std::fs;
+    use std::sync::Mutex;
+    use tempdir::TempDir;
+
+    fn session(fb: FacebookInit, session_id: &str, sample_rate: u64) -> SessionContainer {
+        SessionContainer::builder(fb)
+            .session_id(SessionId::from_string(session_id))
+            .build()
+            .with_scuba_sampling(NonZeroU64::new(sample_rate).unwrap())
+    }
+
+    #[fbinit::test]
+    fn test_scuba_sampling_is_deterministic(fb: FacebookInit) {
+        let sampled: Vec<bool> = (0..100)
+            .map(|i| session(fb, &format!("session{}", i), 4).is_scuba_sampled())
+            .collect();
+        let sampled_again: Vec<bool> = (0..100)
+            .map(|i| session(fb, &format!("session{}", i), 4).is_scuba_sampled())
+            .collect();
+        assert_eq!(sampled, sampled_again);
+        assert!(sampled.iter().any(|s| *s));
+        assert!(sampled.iter().any(|s| !*s));
+
+        // The context reports the decision of its session.
+        for (i, s) in sampled.iter().enumerate() {
+            let ctx = session(fb, &format!("session{}", i), 4).new_context(
+                Logger::root(Discard, o!()),
+                ScubaSampleBuilder::with_discard(),
+            );
+            assert_eq!(ctx.scuba_sampled(), *s);
+        }
+
+        // Without sampling, every session is selected.
+        assert!(SessionContainer::new_with_defaults(fb).is_scuba_sampled());
+        // A rate of 1 selects every session as well.
+        assert!(session(fb, "session0", 1).is_scuba_sampled());
+    }
+
+    #[fbinit::test]
+    fn test_unsampled_context_logs_forced_samples(fb: FacebookInit) {
+        let dir = TempDir::new("scuba_sampling").unwrap();
+        let log_file = dir.path().join("scuba.json");
+
+        // Pick a session that is not selected at the highest possible rate, so that samples of
+        // its contexts are all but guaranteed to be dropped unless forced.
+        let session = (0..)
+            .map(|i| session(fb, &format!("session{}", i), u64::max_value()))
+            .find(|session| !session.is_scuba_sampled())
+            .unwrap();
+        let scuba = ScubaSampleBuilder::with_discard()
+            .with_log_file(&log_file)
+            .unwrap();
+        let ctx = session.new_context(Logger::root(Discard, o!()), scuba);
+        assert!(!ctx.scuba_sampled());
+
+        ctx.scuba().clone().log_with_msg("minimal", None);
+        ctx.forced_scuba().log_with_msg("error", None);
+        let logged = fs::read_to_string(&log_file).unwrap();
+        assert_eq!(logged.lines().count(), 1);
+        assert!(logged.contains("\"error\""));
+
+        // An explicit sampling key overrides the session sample rate.
+        let ctx = ctx.clone_and_sample(SamplingKey::new());
+        assert!(ctx.scuba_sampled());
+        ctx.scuba().clone().log_with_msg("sampled", None);
+        let logged = fs::read_to_string(&log_file).unwrap();
+        assert_eq!(logged.lines().count(), 2);
+    }
+
+    /// Keeps the messages of the records logged to it
+    #[derive(Clone, Default)]
+    struct CaptureDrain(Arc<Mutex<Vec<String>>>);
+
+    impl CaptureDrain {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    impl Drain for CaptureDrain {
+        type Ok = ();
+        type Err = Never;
+
+        fn log(&self, record: &Record, _values: &OwnedKVList) -> Result<(), Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    #[fbinit::test]
+    fn test_forced_debug(fb: FacebookInit) {
+        let dir = TempDir::new("forced_debug").unwrap();
+        let log_file = dir.path().join("scuba.json");
+        let capture = CaptureDrain::default();
+        let info_logger =
+            Logger::root(capture.clone().filter_level(Level::Info).ignore_res(), o!());
+
+        let session = SessionContainer::new_with_defaults(fb);
+        assert!(!session.is_forced_debug());
+        let ctx = session.new_context(info_logger.clone(), ScubaSampleBuilder::with_discard());
+        debug!(ctx.logger(), "normal debug");
+        info!(ctx.logger(), "normal info");
+        assert_eq!(capture.take(), vec!["normal info".to_string()]);
+
+        let session = SessionContainer::new_with_defaults(fb)
+            .with_forced_debug_logger(Logger::root(capture.clone(), o!()));
+        assert!(session.is_forced_debug());
+        let scuba = ScubaSampleBuilder::with_discard()
+            .with_log_file(&log_file)
+            .unwrap();
+        let ctx = session.new_context(info_logger, scuba);
+        debug!(ctx.logger(), "forced debug");
+        info!(ctx.logger(), "forced info");
+        assert_eq!(
+            capture.take(),
+            vec!["forced debug".to_string(), "forced info".to_string()]
+        );
+
+        // Derived contexts keep logging debug records, still without logging anything twice.
+        let ctx = ctx.clone_and_reset();
+        debug!(ctx.logger(), "reset debug");
+        info!(ctx.logger(), "reset info");
+        assert_eq!(
+            capture.take(),
+            vec!["reset debug".to_string(), "reset info".to_string()]
+        );
+
+        ctx.scuba().clone().log_with_msg("sample", None);
+        let logged: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&log_file).unwrap()).unwrap();
+        assert_eq!(logged["int"]["forced_debug"], 1);
+    }
+}
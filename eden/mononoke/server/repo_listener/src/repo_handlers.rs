@@ -282,11 +282,14 @@ pub fn repo_handlers(
                 info!(logger, "Creating HookManager");
                 let mut hook_manager = HookManager::new(
                     ctx.fb,
+                    reponame.clone(),
+                    blobrepo.get_repoid(),
                     Box::new(BlobRepoChangesetStore::new(blobrepo.clone())),
                     blobrepo_text_only_store(blobrepo.clone(), hook_max_file_size),
                     hook_manager_params.unwrap_or_default(),
                     hooks_scuba,
                 );
+                hook_manager.set_hook_max_file_size(hook_max_file_size);
 
                 info!(logger, "Loading hooks");
                 load_hooks(fb, &mut hook_manager, hook_config, &disabled_hooks)?;
@@ -95,9 +95,10 @@ Send large file
   remote: Command failed
   remote:   Error:
   remote:     hooks failed:
-  remote:     limit_filesize for 3e0db158edcc82d93b971f44c13ac74836db5714: File size limit is 10 bytes. You tried to push file largefile that is over the limit (12 bytes).  See https://fburl.com/landing_big_diffs for instructions.
+  remote:     limit_filesize:
+  remote:       3e0db158edcc82d93b971f44c13ac74836db5714 largefile: File size limit is 10 bytes. You tried to push file largefile that is over the limit (12 bytes).  See https://fburl.com/landing_big_diffs for instructions.
   remote:   Root cause:
-  remote:     "hooks failed:\nlimit_filesize for 3e0db158edcc82d93b971f44c13ac74836db5714: File size limit is 10 bytes. You tried to push file largefile that is over the limit (12 bytes).  See https://fburl.com/landing_big_diffs for instructions."
+  remote:     "hooks failed:\nlimit_filesize:\n  3e0db158edcc82d93b971f44c13ac74836db5714 largefile: File size limit is 10 bytes. You tried to push file largefile that is over the limit (12 bytes).  See https://fburl.com/landing_big_diffs for instructions."
   abort: stream ended unexpectedly (got 0 bytes, expected 4)
   [255]
 
@@ -123,8 +124,9 @@ Send large file inside a directory
   remote: Command failed
   remote:   Error:
   remote:     hooks failed:
-  remote:     limit_filesize for cbc62a724366fbea4663ca3e1f1a834af9f2f992: File size limit is 10 bytes. You tried to push file dir/largefile that is over the limit (12 bytes).  See https://fburl.com/landing_big_diffs for instructions.
+  remote:     limit_filesize:
+  remote:       cbc62a724366fbea4663ca3e1f1a834af9f2f992 dir/largefile: File size limit is 10 bytes. You tried to push file dir/largefile that is over the limit (12 bytes).  See https://fburl.com/landing_big_diffs for instructions.
   remote:   Root cause:
-  remote:     "hooks failed:\nlimit_filesize for cbc62a724366fbea4663ca3e1f1a834af9f2f992: File size limit is 10 bytes. You tried to push file dir/largefile that is over the limit (12 bytes).  See https://fburl.com/landing_big_diffs for instructions."
+  remote:     "hooks failed:\nlimit_filesize:\n  cbc62a724366fbea4663ca3e1f1a834af9f2f992 dir/largefile: File size limit is 10 bytes. You tried to push file dir/largefile that is over the limit (12 bytes).  See https://fburl.com/landing_big_diffs for instructions."
   abort: stream ended unexpectedly (got 0 bytes, expected 4)
   [255]
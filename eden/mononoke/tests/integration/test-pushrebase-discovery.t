@@ -83,9 +83,10 @@ Unsuccessful push creates a draft commit on the server
   remote: Command failed
   remote:   Error:
   remote:     hooks failed:
-  remote:     always_fail_changeset for 812eca0823f97743f8d85cdef5cf338b54cebb01: This hook always fails
+  remote:     always_fail_changeset:
+  remote:       812eca0823f97743f8d85cdef5cf338b54cebb01: This hook always fails
   remote:   Root cause:
-  remote:     "hooks failed:\nalways_fail_changeset for 812eca0823f97743f8d85cdef5cf338b54cebb01: This hook always fails"
+  remote:     "hooks failed:\nalways_fail_changeset:\n  812eca0823f97743f8d85cdef5cf338b54cebb01: This hook always fails"
   abort: stream ended unexpectedly (got 0 bytes, expected 4)
   [255]
 
@@ -0,0 +1,205 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::{bail, format_err, Error};
+use blobrepo::BlobRepo;
+use context::CoreContext;
+use maplit::btreemap;
+use mononoke_types::ChangesetId;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{create_commit, store_files};
+
+/// A commit graph parsed from a compact description. The description is a list of chains
+/// separated by `;` or newlines. Each chain is a list of groups of commit names separated by
+/// `-`, and each group is a list of commit names separated by `,`. Every commit in a group is a
+/// parent of every commit in the next group, so "A-B-C; B-D; C,D-E" describes:
+///
+/// ```text
+///       E
+///      / \
+///     C   D
+///      \ /
+///       B
+///       |
+///       A
+/// ```
+///
+/// Merge parents are ordered by their first appearance in the description.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dag {
+    parents: BTreeMap<String, Vec<String>>,
+}
+
+impl Dag {
+    pub fn parse(desc: &str) -> Result<Self, Error> {
+        let mut parents: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        for chain in desc.split(|c| c == ';' || c == '\n') {
+            if chain.trim().is_empty() {
+                continue;
+            }
+
+            let mut prev_group: Vec<String> = vec![];
+            for group in chain.split('-') {
+                let group = group
+                    .split(',')
+                    .map(|name| parse_name(name, chain))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                for name in &group {
+                    let commit_parents = parents.entry(name.clone()).or_default();
+                    for parent in &prev_group {
+                        if parent == name {
+                            bail!("Commit {} is its own parent in {:?}", name, chain.trim());
+                        }
+                        if !commit_parents.contains(parent) {
+                            commit_parents.push(parent.clone());
+                        }
+                    }
+                }
+                prev_group = group;
+            }
+        }
+
+        let dag = Self { parents };
+        dag.topo_order()?;
+        Ok(dag)
+    }
+
+    /// Parents of the commit `name`, or None if there is no such commit
+    pub fn parents(&self, name: &str) -> Option<&[String]> {
+        self.parents.get(name).map(|parents| parents.as_slice())
+    }
+
+    /// Names of all the commits, sorted
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.parents.keys().map(|name| name.as_str())
+    }
+
+    /// Commit names ordered so that parents come before their children. Commits that could go
+    /// in any order are sorted by name.
+    pub fn topo_order(&self) -> Result<Vec<&str>, Error> {
+        let mut order = vec![];
+        let mut done = BTreeSet::new();
+        while order.len() < self.parents.len() {
+            let ready: Vec<_> = self
+                .parents
+                .iter()
+                .filter(|(name, parents)| {
+                    !done.contains(name.as_str())
+                        && parents.iter().all(|p| done.contains(p.as_str()))
+                })
+                .map(|(name, _)| name.as_str())
+                .collect();
+
+            if ready.is_empty() {
+                let cycle: Vec<_> = self.names().filter(|name| !done.contains(name)).collect();
+                bail!("Commit graph has a cycle among {}", cycle.join(", "));
+            }
+            done.extend(ready.iter().cloned());
+            order.extend(ready);
+        }
+        Ok(order)
+    }
+}
+
+fn parse_name(name: &str, chain: &str) -> Result<String, Error> {
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("Empty commit name in {:?}", chain.trim());
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        bail!("Invalid commit name {:?} in {:?}", name, chain.trim());
+    }
+    Ok(name.to_string())
+}
+
+/// Create the commits described by `desc` (see `Dag`) and return their ids by name. `files`
+/// holds the file changes of each commit, with None deleting the file. Commits missing from it
+/// add a file named after themselves, so that commits with the same parents differ.
+pub async fn create_dag(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    desc: &str,
+    mut files: BTreeMap<&str, BTreeMap<&str, Option<&str>>>,
+) -> Result<BTreeMap<String, ChangesetId>, Error> {
+    let dag = Dag::parse(desc)?;
+    if let Some(unknown) = files.keys().find(|name| dag.parents(name).is_none()) {
+        bail!("Files given for unknown commit {}", unknown);
+    }
+
+    let mut ids: BTreeMap<String, ChangesetId> = BTreeMap::new();
+    for name in dag.topo_order()? {
+        let parents = dag
+            .parents(name)
+            .unwrap_or_default()
+            .iter()
+            .map(|parent| {
+                ids.get(parent)
+                    .cloned()
+                    .ok_or_else(|| format_err!("Parent {} of {} was not created", parent, name))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let commit_files = files
+            .remove(name)
+            .unwrap_or_else(|| btreemap! { name => Some(name) });
+        let file_changes = store_files(ctx.clone(), commit_files, repo.clone()).await;
+        let cs_id = create_commit(ctx.clone(), repo.clone(), parents, file_changes).await;
+        ids.insert(name.to_string(), cs_id);
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parents(dag: &Dag, name: &str) -> Vec<String> {
+        dag.parents(name).unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_parse_linear_and_merges() -> Result<(), Error> {
+        let dag = Dag::parse("A-B-C; B-D; C,D-E")?;
+        assert_eq!(
+            dag.names().collect::<Vec<_>>(),
+            vec!["A", "B", "C", "D", "E"]
+        );
+        assert!(parents(&dag, "A").is_empty());
+        assert_eq!(parents(&dag, "B"), vec!["A"]);
+        assert_eq!(parents(&dag, "D"), vec!["B"]);
+        assert_eq!(parents(&dag, "E"), vec!["C", "D"]);
+        assert_eq!(dag.topo_order()?, vec!["A", "B", "C", "D", "E"]);
+        assert!(dag.parents("F").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_merge_parent_order() -> Result<(), Error> {
+        let dag = Dag::parse("Z-M\nA-M\n\nroot_1-Z")?;
+        assert_eq!(parents(&dag, "M"), vec!["Z", "A"]);
+        assert_eq!(dag.topo_order()?, vec!["A", "root_1", "Z", "M"]);
+
+        // Repeated edges don't duplicate parents.
+        let dag = Dag::parse("A-B; A-B; A,A-C")?;
+        assert_eq!(parents(&dag, "B"), vec!["A"]);
+        assert_eq!(parents(&dag, "C"), vec!["A"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Dag::parse("A-B-A").is_err());
+        assert!(Dag::parse("A-B; B-C; C-A").is_err());
+        assert!(Dag::parse("A-A").is_err());
+        assert!(Dag::parse("A--B").is_err());
+        assert!(Dag::parse("A,-B").is_err());
+        assert!(Dag::parse("A-B C").is_err());
+        assert!(Dag::parse("").unwrap().names().next().is_none());
+    }
+}
@@ -24,6 +24,9 @@ use mononoke_types::{
 };
 use std::{collections::BTreeMap, convert::TryInto, str::FromStr};
 
+mod dag_builder;
+pub use crate::dag_builder::{create_dag, Dag};
+
 /// Helper to create bonsai changesets in a BlobRepo
 pub struct CreateCommitContext<'a> {
     ctx: &'a CoreContext,
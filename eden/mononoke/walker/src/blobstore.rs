@@ -169,6 +169,7 @@ pub async fn open_blobstore(
                 scuba_sample_rate,
                 blobstores,
                 scrub_action,
+                scrub_repair_concurrency,
                 queue_db,
             },
         ) => {
@@ -190,7 +191,7 @@ pub async fn open_blobstore(
                 blobstores,
                 mysql_options,
                 readonly_storage,
-                Some((scrub_handler, scrub_action)),
+                Some((scrub_handler, scrub_action, scrub_repair_concurrency)),
                 blobstore_options,
                 logger,
             )
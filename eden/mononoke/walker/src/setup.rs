@@ -745,6 +745,7 @@ pub fn setup_common(
         storage_config.dbconfig,
         mysql_options,
         readonly_storage,
+        None,
         logger.clone(),
     )
     .boxify();
@@ -28,6 +28,8 @@ use crate::pythonutil::{
 pub trait HgIdDataStorePyExt {
     fn get_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyBytes>;
     fn get_delta_chain_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyList>;
+    fn get_delta_chain_len_py(&self, py: Python, name: &PyPath, node: &PyBytes)
+        -> PyResult<PyList>;
     fn get_delta_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyObject>;
     fn get_meta_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyDict>;
     fn get_missing_py(&self, py: Python, keys: &mut PyIterator) -> PyResult<PyList>;
@@ -112,6 +114,25 @@ impl<T: HgIdDataStore + ?Sized> HgIdDataStorePyExt for T {
         Ok(PyList::new(py, &pychain[..]))
     }
 
+    fn get_delta_chain_len_py(
+        &self,
+        py: Python,
+        name: &PyPath,
+        node: &PyBytes,
+    ) -> PyResult<PyList> {
+        let key = to_key(py, name, node)?;
+        let deltachain = self
+            .get_delta_chain(&key)
+            .map_pyerr(py)?
+            .ok_or_else(|| key_error(py, &key))?;
+
+        let lengths = deltachain
+            .iter()
+            .map(|d| d.data.len().to_py_object(py).into_object())
+            .collect::<Vec<PyObject>>();
+        Ok(PyList::new(py, &lengths[..]))
+    }
+
     fn get_meta_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyDict> {
         let key = to_key(py, name, node)?;
         let metadata = self
@@ -10,6 +10,7 @@
 #![allow(non_camel_case_types)]
 
 use std::{
+    cell::{Ref, RefCell},
     convert::TryInto,
     fs::read_dir,
     path::{Path, PathBuf},
@@ -23,6 +24,7 @@ use parking_lot::RwLock;
 use cpython_ext::{PyErr, PyPath, PyPathBuf, ResultPyErrExt, Str};
 use pyconfigparser::config;
 use revisionstore::{
+    mutablepack::MutablePack,
     repack::{filter_incrementalpacks, list_packs, repack_datapacks, repack_historypacks},
     ContentStore, ContentStoreBuilder, CorruptionPolicy, DataPack, DataPackStore, DataPackVersion,
     Delta, HgIdDataStore, HgIdHistoryStore, HgIdMutableDeltaStore, HgIdMutableHistoryStore,
@@ -42,7 +44,7 @@ use crate::{
         HgIdHistoryStorePyExt, HgIdMutableHistoryStorePyExt, IterableHgIdHistoryStorePyExt,
         RemoteHistoryStorePyExt,
     },
-    pythonutil::from_key,
+    pythonutil::{from_key, from_key_to_tuple, from_tuple_to_key, key_error, to_key},
 };
 
 mod datastorepyext;
@@ -52,7 +54,7 @@ mod pythonutil;
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
-pub use crate::pythondatastore::PythonHgIdDataStore;
+pub use crate::pythondatastore::{PythonHgIdDataStore, ThreadSafeHgIdDataStore};
 
 pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
     let name = [package, "revisionstore"].join(".");
@@ -64,6 +66,8 @@ pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
     m.add_class::<indexedlogdatastore>(py)?;
     m.add_class::<indexedloghistorystore>(py)?;
     m.add_class::<mutabledeltastore>(py)?;
+    m.add_class::<mutabledatapack>(py)?;
+    m.add_class::<threadsafedatastore>(py)?;
     m.add_class::<mutablehistorystore>(py)?;
     m.add_class::<pyremotestore>(py)?;
     m.add_class::<contentstore>(py)?;
@@ -154,7 +158,7 @@ fn incremental_repackhist(
 }
 
 py_class!(class datapack |py| {
-    data store: Box<DataPack>;
+    data store: RefCell<Option<Box<DataPack>>>;
 
     def __new__(
         _cls,
@@ -162,53 +166,138 @@ py_class!(class datapack |py| {
     ) -> PyResult<datapack> {
         datapack::create_instance(
             py,
-            Box::new(DataPack::new(path).map_pyerr(py)?),
+            RefCell::new(Some(Box::new(DataPack::new(path).map_pyerr(py)?))),
         )
     }
 
+    def __enter__(&self) -> PyResult<datapack> {
+        self.pack(py)?;
+        Ok(self.clone_ref(py))
+    }
+
+    def __exit__(&self, _ty: Option<PyType>, _value: PyObject, _traceback: PyObject) -> PyResult<bool> {
+        self.store(py).borrow_mut().take();
+        Ok(false)
+    }
+
     def path(&self) -> PyResult<PyPathBuf> {
-        self.store(py).base_path().try_into().map_pyerr(py)
+        self.pack(py)?.base_path().try_into().map_pyerr(py)
     }
 
     def packpath(&self) -> PyResult<PyPathBuf> {
-        self.store(py).pack_path().try_into().map_pyerr(py)
+        self.pack(py)?.pack_path().try_into().map_pyerr(py)
     }
 
     def indexpath(&self) -> PyResult<PyPathBuf> {
-        self.store(py).index_path().try_into().map_pyerr(py)
+        self.pack(py)?.index_path().try_into().map_pyerr(py)
     }
 
     def get(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyBytes> {
-        let store = self.store(py);
+        let store = self.pack(py)?;
         store.get_py(py, &name, node)
     }
 
     def getdelta(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyObject> {
-        let store = self.store(py);
+        let store = self.pack(py)?;
         store.get_delta_py(py, &name, node)
     }
 
     def getdeltachain(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyList> {
-        let store = self.store(py);
+        let store = self.pack(py)?;
         store.get_delta_chain_py(py, &name, node)
     }
 
+    def getdeltachainlen(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyList> {
+        let store = self.pack(py)?;
+        store.get_delta_chain_len_py(py, &name, node)
+    }
+
     def getmeta(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyDict> {
-        let store = self.store(py);
+        let store = self.pack(py)?;
         store.get_meta_py(py, &name, node)
     }
 
     def getmissing(&self, keys: &PyObject) -> PyResult<PyList> {
-        let store = self.store(py);
+        let store = self.pack(py)?;
         store.get_missing_py(py, &mut keys.iter(py)?)
     }
 
     def iterentries(&self) -> PyResult<Vec<PyTuple>> {
-        let store = self.store(py);
+        let store = self.pack(py)?;
         store.iter_py(py)
     }
+
+    /// Read the pack file regions for `keys` so that subsequent reads of them are faster.
+    /// Returns the keys that aren't in this pack.
+    def prefetch(&self, keys: &PyList) -> PyResult<PyList> {
+        let keys = keys
+            .iter(py)
+            .map(|key| from_tuple_to_key(py, &key))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let missing = self.pack(py)?.prefetch(&keys).map_pyerr(py)?;
+
+        let results = PyList::new(py, &[]);
+        for key in missing {
+            results.append(py, from_key_to_tuple(py, &key).into_object());
+        }
+        Ok(results)
+    }
 });
 
+impl datapack {
+    /// The underlying `DataPack`, or an error if the pack was closed by leaving a `with` block.
+    fn pack(&self, py: Python) -> PyResult<Ref<DataPack>> {
+        let store = self.store(py).borrow();
+        if store.is_none() {
+            return Err(format_err!("datapack is closed")).map_pyerr(py);
+        }
+        Ok(Ref::map(store, |store| store.as_deref().unwrap()))
+    }
+}
+
+// Wraps a Python datastore so that it can be read from multiple threads. The GIL is released
+// while waiting for other readers to finish.
+py_class!(class threadsafedatastore |py| {
+    data store: Arc<ThreadSafeHgIdDataStore>;
+
+    def __new__(_cls, store: PyObject) -> PyResult<threadsafedatastore> {
+        threadsafedatastore::create_instance(py, Arc::new(ThreadSafeHgIdDataStore::new(store)))
+    }
+
+    def get(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyBytes> {
+        let key = to_key(py, &name, node)?;
+        let store = self.store(py);
+        let data = py
+            .allow_threads(|| store.get(&key))
+            .map_pyerr(py)?
+            .ok_or_else(|| key_error(py, &key))?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    def getmissing(&self, keys: &PyObject) -> PyResult<PyList> {
+        let keys = keys
+            .iter(py)?
+            .map(|key| Ok(StoreKey::from(from_tuple_to_key(py, &key?)?)))
+            .collect::<PyResult<Vec<StoreKey>>>()?;
+        let store = self.store(py);
+        let missing = py.allow_threads(|| store.get_missing(&keys)).map_pyerr(py)?;
+
+        let results = PyList::new(py, &[]);
+        for key in missing {
+            if let StoreKey::HgId(key) = key {
+                results.append(py, from_key_to_tuple(py, &key).into_object());
+            }
+        }
+        Ok(results)
+    }
+});
+
+impl threadsafedatastore {
+    pub fn to_inner(&self, py: Python) -> Arc<ThreadSafeHgIdDataStore> {
+        self.store(py).clone()
+    }
+}
+
 /// Scan the filesystem for files with `extensions`, and compute their size.
 fn compute_store_size<P: AsRef<Path>>(
     storepath: P,
@@ -272,6 +361,10 @@ py_class!(class datapackstore |py| {
         self.store(py).get_delta_chain_py(py, &name, node)
     }
 
+    def getdeltachainlen(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyList> {
+        self.store(py).get_delta_chain_len_py(py, &name, node)
+    }
+
     def getmissing(&self, keys: &PyObject) -> PyResult<PyList> {
         self.store(py).get_missing_py(py, &mut keys.iter(py)?)
     }
@@ -400,6 +493,11 @@ py_class!(class indexedlogdatastore |py| {
         store.get_delta_chain_py(py, name, node)
     }
 
+    def getdeltachainlen(&self, name: &PyPath, node: &PyBytes) -> PyResult<PyList> {
+        let store = self.store(py);
+        store.get_delta_chain_len_py(py, name, node)
+    }
+
     def getmeta(&self, name: &PyPath, node: &PyBytes) -> PyResult<PyDict> {
         let store = self.store(py);
         store.get_meta_py(py, name, node)
@@ -504,6 +602,11 @@ py_class!(pub class mutabledeltastore |py| {
         store.get_delta_chain_py(py, &name, node)
     }
 
+    def getdeltachainlen(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyList> {
+        let store = self.store(py);
+        store.get_delta_chain_len_py(py, &name, node)
+    }
+
     def getmeta(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyDict> {
         let store = self.store(py);
         store.get_meta_py(py, &name, node)
@@ -570,6 +673,48 @@ impl HgIdMutableDeltaStore for mutabledeltastore {
     }
 }
 
+py_class!(class mutabledatapack |py| {
+    data store: RefCell<Option<MutableDataPack>>;
+
+    def __new__(_cls, dir: &PyPath) -> PyResult<mutabledatapack> {
+        let store = MutableDataPack::new(dir, DataPackVersion::One).map_pyerr(py)?;
+        mutabledatapack::create_instance(py, RefCell::new(Some(store)))
+    }
+
+    def add(&self, name: PyPathBuf, node: &PyBytes, delta: &PyBytes, base: &PyBytes, metadata: Option<PyDict> = None) -> PyResult<PyObject> {
+        let store = self.pack(py)?;
+        store.add_py(py, &name, node, base, delta, metadata)
+    }
+
+    /// Write the pack and its index to disk, returning the path of the pack without its
+    /// extension. The pack can't be added to after it's closed.
+    def close(&self) -> PyResult<PyString> {
+        let store = self.store(py).borrow_mut().take();
+        let store = match store {
+            Some(store) => store,
+            None => return Err(format_err!("mutabledatapack is closed")).map_pyerr(py),
+        };
+        let path = store
+            .close_pack()
+            .map_pyerr(py)?
+            .ok_or_else(|| format_err!("cannot close an empty mutabledatapack"))
+            .map_pyerr(py)?;
+        let path: PyPathBuf = path.try_into().map_pyerr(py)?;
+        Ok(PyString::new(py, path.as_str()))
+    }
+});
+
+impl mutabledatapack {
+    /// The underlying `MutableDataPack`, or an error if the pack was closed.
+    fn pack(&self, py: Python) -> PyResult<Ref<MutableDataPack>> {
+        let store = self.store(py).borrow();
+        if store.is_none() {
+            return Err(format_err!("mutabledatapack is closed")).map_pyerr(py);
+        }
+        Ok(Ref::map(store, |store| store.as_ref().unwrap()))
+    }
+}
+
 fn make_mutablehistorystore(
     packfilepath: Option<PyPathBuf>,
 ) -> Result<Arc<dyn HgIdMutableHistoryStore + Send>> {
@@ -858,6 +1003,11 @@ py_class!(pub class contentstore |py| {
         store.get_delta_chain_py(py, &name, node)
     }
 
+    def getdeltachainlen(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyList> {
+        let store = self.store(py);
+        store.get_delta_chain_len_py(py, &name, node)
+    }
+
     def getmeta(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyDict> {
         let store = self.store(py);
         store.get_meta_py(py, &name, node)
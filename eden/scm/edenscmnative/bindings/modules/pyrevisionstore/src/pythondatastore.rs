@@ -12,6 +12,7 @@ use cpython::{
 };
 
 use cpython_ext::{PyErr, PyPathBuf};
+use parking_lot::Mutex;
 use revisionstore::{Delta, HgIdDataStore, LocalStore, Metadata, RemoteDataStore, StoreKey};
 use types::Key;
 
@@ -202,3 +203,42 @@ impl LocalStore for PythonHgIdDataStore {
         Ok(missing)
     }
 }
+
+/// A `PythonHgIdDataStore` that can be shared by multiple Rust threads. Accesses to the Python
+/// store are serialized, and the GIL is only acquired once the lock is held, so callers must not
+/// hold the GIL when calling into this store or they may deadlock with other readers.
+pub struct ThreadSafeHgIdDataStore {
+    inner: Mutex<PythonHgIdDataStore>,
+}
+
+impl ThreadSafeHgIdDataStore {
+    pub fn new(py_store: PyObject) -> Self {
+        ThreadSafeHgIdDataStore {
+            inner: Mutex::new(PythonHgIdDataStore::new(py_store)),
+        }
+    }
+}
+
+impl HgIdDataStore for ThreadSafeHgIdDataStore {
+    fn get(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        self.inner.lock().get(key)
+    }
+
+    fn get_delta(&self, key: &Key) -> Result<Option<Delta>> {
+        self.inner.lock().get_delta(key)
+    }
+
+    fn get_delta_chain(&self, key: &Key) -> Result<Option<Vec<Delta>>> {
+        self.inner.lock().get_delta_chain(key)
+    }
+
+    fn get_meta(&self, key: &Key) -> Result<Option<Metadata>> {
+        self.inner.lock().get_meta(key)
+    }
+}
+
+impl LocalStore for ThreadSafeHgIdDataStore {
+    fn get_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.inner.lock().get_missing(keys)
+    }
+}
@@ -14,7 +14,7 @@ use indexedlog::rotate::{OpenOptions, RotateLog, RotateLowLevelExt};
 use lazy_static::lazy_static;
 use serde_json::Value;
 use std::cell::Cell;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::io::{Cursor, Write};
 use std::path::Path;
@@ -33,12 +33,46 @@ pub struct Blackbox {
     // The on-disk files are considered bad (ex. no permissions, or no disk space)
     // and further write attempts will be ignored.
     is_broken: Cell<bool>,
+
+    // Source of timestamps written to entry headers.
+    clock: Box<dyn Clock>,
+
+    // The largest timestamp written so far. Used to keep the timestamp index
+    // monotonic if the wall clock jumps backwards.
+    pub(crate) last_timestamp: u64,
+
+    // Number of entries whose timestamp was adjusted due to clock skew.
+    clock_skew_corrections: u64,
+
+    // Key/value annotations of the current session.
+    pub(crate) annotations: BTreeMap<String, Value>,
+
+    // Whether `annotations` changed since they were last written.
+    pub(crate) annotations_dirty: bool,
+}
+
+/// Source of the current time used by [`Blackbox`].
+///
+/// This exists so tests can simulate clock jumps.
+pub trait Clock: Send {
+    fn now(&self) -> SystemTime;
+}
+
+/// [`Clock`] backed by the system wall clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
 }
 
 #[derive(Copy, Clone)]
 pub struct BlackboxOptions {
     max_bytes_per_log: u64,
     max_log_count: u8,
+    max_custom_value_bytes: usize,
+    compress_threshold: usize,
 }
 
 /// A wrapper for some serializable data.
@@ -54,6 +88,18 @@ pub struct Entry {
     phantom: (),
 }
 
+/// Timing information about a session, aggregated from its entries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SessionSummary {
+    pub session_id: SessionId,
+    /// Timestamp of the first entry of the session, in milliseconds since epoch.
+    pub start_timestamp: u64,
+    /// Time between the first and the last entry of the session, in milliseconds.
+    pub duration_ms: u64,
+    /// Number of entries logged by the session.
+    pub event_count: usize,
+}
+
 /// Convert to JSON Value for pattern matching.
 pub trait ToValue {
     fn to_value(&self) -> Value;
@@ -61,17 +107,36 @@ pub trait ToValue {
 
 // The serialized format of `Entry` is:
 //
-// 8 Bytes: Milliseconds since epoch. Big-Endian.
+// 8 Bytes: Milliseconds since epoch. Big-Endian. Never decreases within a
+//          log, even if the wall clock goes backwards, except for entries
+//          copied by `Blackbox::import_from`, which keep their original
+//          timestamps. The highest bit is not part of the timestamp, but
+//          the COMPRESSED_FLAG.
 // 4 Bytes: Session ID. Big-Endian.
-// n Bytes: data.serialize() via serde-cbor.
+// 1 Byte:  Schema version of the payload, SCHEMA_VERSION when written.
+// n Bytes: data.serialize() via serde-cbor. Compressed by zstd if
+//          COMPRESSED_FLAG is set.
 //
-// In case the format changes in the future, a simple strategy will be just
-// renaming the directory used for logging.
+// Entries written before the schema version was added have no version byte.
+// Their payload starts with a CBOR map or string, or with the zstd magic
+// number, none of which is below MAX_SCHEMA_VERSION, so they are decoded as
+// version 0, which has the same layout as version 1. If the layout of `Event`
+// changes, bump SCHEMA_VERSION and keep decoding the older versions in
+// `Entry::from_slice`.
 
 const TIMESTAMP_BYTES: usize = 8;
 const SESSION_ID_BYTES: usize = 8;
 const HEADER_BYTES: usize = TIMESTAMP_BYTES + SESSION_ID_BYTES;
 
+const SCHEMA_VERSION: u8 = 1;
+/// Exclusive upper bound of version bytes. Anything from here up is the start
+/// of a legacy payload.
+const MAX_SCHEMA_VERSION: u8 = 0x10;
+
+/// Set in the timestamp field if the payload is compressed.
+const COMPRESSED_FLAG: u64 = 1 << 63;
+const COMPRESSION_LEVEL: i32 = 3;
+
 impl BlackboxOptions {
     /// Create a [`Blackbox`] instance at the given path using the specified options.
     pub fn open(self, path: impl AsRef<Path>) -> Result<Blackbox> {
@@ -86,12 +151,18 @@ impl BlackboxOptions {
             }
             Ok(log) => log,
         };
+        let last_timestamp = last_timestamp(&log);
         let blackbox = Blackbox {
             log,
             opts: self,
             // pid is used as an initial guess of "unique" session id
             session_id: new_session_id(),
             is_broken: Cell::new(false),
+            clock: Box::new(SystemClock),
+            last_timestamp,
+            clock_skew_corrections: 0,
+            annotations: BTreeMap::new(),
+            annotations_dirty: false,
         };
         Ok(blackbox)
     }
@@ -105,6 +176,11 @@ impl BlackboxOptions {
             // pid is used as an initial guess of "unique" session id
             session_id: new_session_id(),
             is_broken: Cell::new(false),
+            clock: Box::new(SystemClock),
+            last_timestamp: 0,
+            clock_skew_corrections: 0,
+            annotations: BTreeMap::new(),
+            annotations_dirty: false,
         })
     }
 
@@ -112,6 +188,8 @@ impl BlackboxOptions {
         Self {
             max_bytes_per_log: 100_000_000,
             max_log_count: 3,
+            max_custom_value_bytes: 32 * 1024,
+            compress_threshold: 4 * 1024,
         }
     }
 
@@ -125,6 +203,19 @@ impl BlackboxOptions {
         self
     }
 
+    /// Values passed to `Blackbox::log_custom` that are larger than this
+    /// when serialized to JSON get truncated.
+    pub fn max_custom_value_bytes(mut self, bytes: usize) -> Self {
+        self.max_custom_value_bytes = bytes;
+        self
+    }
+
+    /// Payloads larger than this when serialized get compressed.
+    pub fn compress_threshold(mut self, bytes: usize) -> Self {
+        self.compress_threshold = bytes;
+        self
+    }
+
     fn rotate_log_open_options(&self) -> OpenOptions {
         OpenOptions::new()
             .max_bytes_per_log(self.max_bytes_per_log)
@@ -160,6 +251,9 @@ impl BlackboxOptions {
                                 push(INDEX_EVENT_TAG_NAME, name.as_bytes());
                             }
                         }
+                        Event::Custom { kind, .. } => {
+                            push(INDEX_EVENT_CUSTOM_KIND, kind.as_bytes());
+                        }
                         _ => (),
                     }
                 }
@@ -170,12 +264,22 @@ impl BlackboxOptions {
                     TIMESTAMP_BYTES as u64..HEADER_BYTES as u64,
                 )]
             })
+            .index("timestamp", |bytes| {
+                match Entry::header_from_slice(bytes) {
+                    // The key must not include the flag, to keep range queries working.
+                    Some((timestamp, _)) if is_compressed(bytes) => vec![IndexOutput::Owned(
+                        u64_to_boxed_slice(timestamp),
+                    )],
+                    _ => vec![IndexOutput::Reference(0..TIMESTAMP_BYTES as u64)],
+                }
+            })
             .create(true)
     }
 }
 
 const INDEX_EVENT_MISC: usize = 0;
 const INDEX_SESSION_ID: usize = 1;
+const INDEX_TIMESTAMP: usize = 2;
 
 // Sub-index used by INDEX_EVENT_MISC.
 const INDEX_EVENT_START_TIME: u8 = 0;
@@ -183,6 +287,7 @@ const INDEX_EVENT_START_PID: u8 = 1;
 const INDEX_EVENT_FINISH_TIME: u8 = 2;
 const INDEX_EVENT_FINISH_DURATION: u8 = 3;
 const INDEX_EVENT_TAG_NAME: u8 = 4;
+const INDEX_EVENT_CUSTOM_KIND: u8 = 5;
 
 lazy_static! {
     static ref START_TIME_PATTERN: Value = json!(
@@ -220,12 +325,20 @@ lazy_static! {
           ["prefix", "and"],
           ["contain",
            {"tags": {"names": ["prefix", "contain", ["capture", "NAME", "_"]]}}]]]);
+    static ref CUSTOM_KIND_PATTERN: Value = json!(
+        ["or",
+         {"custom": {"kind": ["capture", "KIND", "_"]}},
+         ["and",
+          ["prefix", "and"],
+          ["contain",
+           {"custom": {"kind": ["capture", "KIND", "_"]}}]]]);
 }
 
 impl Blackbox {
     /// Assign a likely unused "Session ID".
     ///
     /// Events logged afterwards with be associated with this ID.
+    /// Annotations of the previous session are cleared.
     ///
     /// Currently, uniqueness is not guaranteed, but perhaps "good enough".
     pub fn refresh_session_id(&mut self) {
@@ -235,6 +348,30 @@ impl Blackbox {
         } else {
             self.session_id = session_id;
         }
+        self.annotations.clear();
+        self.annotations_dirty = false;
+    }
+
+    /// Use a fixed "Session ID" instead of an assigned one.
+    ///
+    /// Events logged afterwards will be associated with this ID, so tests can
+    /// query sessions by known IDs. Annotations of the previous session are
+    /// cleared. Unlike `refresh_session_id`, the ID is used as-is, even if
+    /// it was used before.
+    pub fn set_session_id(&mut self, session_id: SessionId) {
+        self.session_id = session_id.0;
+        self.annotations.clear();
+        self.annotations_dirty = false;
+    }
+
+    /// Annotate the current session with a key/value pair, replacing the
+    /// previous value of `key`.
+    ///
+    /// The annotations are written along with the next logged event, once,
+    /// rather than in every event. Use `session_annotations` to read them.
+    pub fn set_session_annotation(&mut self, key: &str, value: Value) {
+        self.annotations.insert(key.to_string(), value);
+        self.annotations_dirty = true;
     }
 
     /// Get the pid stored in session_id.
@@ -246,6 +383,17 @@ impl Blackbox {
         SessionId(self.session_id)
     }
 
+    /// Replace the clock used to timestamp entries.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Number of entries whose timestamp was moved forward because the clock
+    /// went backwards.
+    pub fn clock_skew_corrections(&self) -> u64 {
+        self.clock_skew_corrections
+    }
+
     /// Log an event. Maybe write it to disk immediately.
     ///
     /// If an error happens, `log` will try to rotate the bad logs and retry.
@@ -255,12 +403,145 @@ impl Blackbox {
             return;
         }
 
-        let now = time_to_u64(&SystemTime::now());
-        if let Some(buf) = Entry::to_vec(data, now, self.session_id) {
+        let now = self.now();
+        if self.annotations_dirty {
+            self.annotations_dirty = false;
+            let annotations = Event::SessionAnnotations {
+                annotations: self.annotations.clone(),
+            };
+            self.append(&annotations, now);
+        }
+        self.append(data, now);
+    }
+
+    fn append(&mut self, data: &Event, timestamp: u64) {
+        if let Some(buf) = Entry::to_vec(
+            data,
+            timestamp,
+            self.session_id,
+            self.opts.compress_threshold,
+        ) {
             let _ = self.log.append(&buf);
         }
     }
 
+    /// Log a group of related events, for example a `Start` with its
+    /// arguments and environment, so that they are written all together or
+    /// not at all.
+    ///
+    /// All events of the group share one timestamp, which is returned so the
+    /// group can be found with `entries_by_timestamp_range`. If any event
+    /// fails to serialize, nothing is written and `None` is returned.
+    pub fn log_batch(&mut self, events: &[Event]) -> Option<u64> {
+        let session_id = self.session_id;
+        let compress_threshold = self.opts.compress_threshold;
+        self.log_batch_with(events, |data, timestamp| {
+            Entry::to_vec(data, timestamp, session_id, compress_threshold)
+        })
+    }
+
+    fn log_batch_with(
+        &mut self,
+        events: &[Event],
+        serialize: impl Fn(&Event, u64) -> Option<Vec<u8>>,
+    ) -> Option<u64> {
+        if self.is_broken.get() || events.is_empty() {
+            return None;
+        }
+
+        let now = self.now();
+        let annotations = if self.annotations_dirty {
+            Some(Event::SessionAnnotations {
+                annotations: self.annotations.clone(),
+            })
+        } else {
+            None
+        };
+        // Serialize everything before appending anything.
+        let bufs = annotations
+            .iter()
+            .chain(events)
+            .map(|data| serialize(data, now))
+            .collect::<Option<Vec<_>>>()?;
+        self.annotations_dirty = false;
+        let _ = self.log.append_batch(bufs);
+        Some(now)
+    }
+
+    /// Log an event defined outside the `event` module as [`Event::Custom`].
+    ///
+    /// It can be queried like other events, for example with the pattern
+    /// `{"custom": {"kind": "pushrebase"}}`.
+    ///
+    /// If `value` serializes to more than `max_custom_value_bytes` of JSON, a
+    /// prefix of the serialized JSON is logged as a string instead, and the
+    /// event is marked as truncated.
+    pub fn log_custom(&mut self, kind: &str, value: &Value) {
+        let max_bytes = self.opts.max_custom_value_bytes;
+        let json = serde_json::to_string(value).unwrap_or_default();
+        let (value, truncated) = if json.len() > max_bytes {
+            let mut end = max_bytes;
+            while !json.is_char_boundary(end) {
+                end -= 1;
+            }
+            (Value::String(json[..end].to_string()), true)
+        } else {
+            (value.clone(), false)
+        };
+        self.log(&Event::Custom {
+            kind: kind.to_string(),
+            value,
+            truncated,
+        });
+    }
+
+    /// Append all entries of the blackbox at `other_path` to this one, keeping
+    /// their timestamps and session ids. Returns the number of entries
+    /// imported.
+    ///
+    /// This is meant for combining blackboxes collected from multiple
+    /// machines. `other_path` is only read, and entries that cannot be read
+    /// or deserialized are skipped.
+    pub fn import_from(&mut self, other_path: &Path) -> Result<usize> {
+        // No indexes are declared, so that none get built or updated in
+        // `other_path`.
+        let other = OpenOptions::new().create(false).open(other_path)?;
+        let mut count = 0;
+        for bytes in other.iter().flatten() {
+            let entry = match Entry::from_slice(bytes) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if let Some(buf) = Entry::to_vec(
+                &entry.data,
+                entry.timestamp,
+                entry.session_id,
+                self.opts.compress_threshold,
+            ) {
+                self.log.append(&buf)?;
+                // Entries logged afterwards must not be older than imported ones.
+                self.last_timestamp = self.last_timestamp.max(entry.timestamp);
+                count += 1;
+            }
+        }
+        self.log.sync()?;
+        Ok(count)
+    }
+
+    /// Timestamp for the next entry.
+    ///
+    /// If the clock went backwards, the last written timestamp is reused so
+    /// the timestamp index stays monotonic.
+    fn now(&mut self) -> u64 {
+        let now = time_to_u64(&self.clock.now());
+        if now < self.last_timestamp {
+            self.clock_skew_corrections += 1;
+        } else {
+            self.last_timestamp = now;
+        }
+        self.last_timestamp
+    }
+
     /// Write buffered data to disk.
     pub fn sync(&mut self) {
         if !self.is_broken.get() {
@@ -331,6 +612,17 @@ impl Blackbox {
                         Some((INDEX_EVENT_TAG_NAME, bytes.clone(), bytes))
                     }
                 })
+            })
+            .or_else(|| {
+                capture_pattern(pattern, &CUSTOM_KIND_PATTERN).and_then(|captured| {
+                    let kind = captured["KIND"].as_str().unwrap_or("");
+                    if kind.is_empty() {
+                        None
+                    } else {
+                        let bytes = kind.as_bytes().to_vec().into_boxed_slice();
+                        Some((INDEX_EVENT_CUSTOM_KIND, bytes.clone(), bytes))
+                    }
+                })
             });
 
         let mut result = BTreeSet::new();
@@ -430,13 +722,81 @@ impl Blackbox {
         result
     }
 
+    /// Summarize the last `n` sessions, newest (by start time) first.
+    ///
+    /// Only the entry headers are decoded, so this is cheaper than fetching
+    /// the entries. Entries that cannot be read are ignored silently.
+    pub fn recent_sessions(&self, n: usize) -> Vec<SessionSummary> {
+        let mut summaries: HashMap<SessionId, SessionSummary> = HashMap::new();
+        for bytes in self.log.iter().flatten() {
+            let (timestamp, session_id) = match Entry::header_from_slice(bytes) {
+                Some(header) => header,
+                None => continue,
+            };
+            let summary = summaries.entry(session_id).or_insert(SessionSummary {
+                session_id,
+                start_timestamp: timestamp,
+                duration_ms: 0,
+                event_count: 0,
+            });
+            let end_timestamp = (summary.start_timestamp + summary.duration_ms).max(timestamp);
+            summary.start_timestamp = summary.start_timestamp.min(timestamp);
+            summary.duration_ms = end_timestamp - summary.start_timestamp;
+            summary.event_count += 1;
+        }
+
+        let mut result: Vec<SessionSummary> = summaries.into_values().collect();
+        result.sort_unstable_by_key(|s| (s.start_timestamp, s.session_id));
+        result.reverse();
+        result.truncate(n);
+        result
+    }
+
     pub fn entries_by_session_id(&self, session_id: SessionId) -> Vec<Entry> {
         self.entries_by_session_ids(vec![session_id])
     }
+
+    /// Get the annotations set by `set_session_annotation` in a session.
+    ///
+    /// Annotations set after the last logged event of the session are not
+    /// included, since they were never written.
+    pub fn session_annotations(&self, session_id: SessionId) -> BTreeMap<String, Value> {
+        self.entries_by_session_id(session_id)
+            .into_iter()
+            .rev()
+            .find_map(|entry| match entry.data {
+                Event::SessionAnnotations { annotations } => Some(annotations),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get all [`Entry`]s with timestamps (in milliseconds since epoch)
+    /// within the `start..=end` range.
+    ///
+    /// Entries that cannot be read or deserialized are ignored silently.
+    pub fn entries_by_timestamp_range(&self, start: u64, end: u64) -> Vec<Entry> {
+        let start = u64_to_slice(start);
+        let end = u64_to_slice(end);
+        let mut result = Vec::new();
+        for log in self.log.logs().iter().rev() {
+            if let Ok(iter) = log.lookup_range(INDEX_TIMESTAMP, &start[..]..=&end[..]) {
+                for (_key, values) in iter.flatten() {
+                    let mut entries: Vec<Entry> = values
+                        .filter_map(|value| value.ok().and_then(Entry::from_slice))
+                        .collect();
+                    // Values for a key are returned newest first.
+                    entries.reverse();
+                    result.extend(entries);
+                }
+            }
+        }
+        result
+    }
 }
 
 /// Session Id used in public APIs.
-#[derive(Copy, Clone, Ord, Eq, PartialOrd, PartialEq, Debug)]
+#[derive(Copy, Clone, Ord, Eq, PartialOrd, PartialEq, Hash, Debug)]
 pub struct SessionId(pub u64);
 
 impl Drop for Blackbox {
@@ -451,27 +811,42 @@ impl Entry {
         match_pattern(&self.data.to_value(), pattern)
     }
 
-    /// Partially decode `bytes` into session_id and timestamp.
+    /// Partially decode `bytes` into session_id.
     fn session_id_from_slice(bytes: &[u8]) -> Option<SessionId> {
+        Self::header_from_slice(bytes).map(|(_timestamp, session_id)| session_id)
+    }
+
+    /// Partially decode `bytes` into timestamp and session_id.
+    fn header_from_slice(bytes: &[u8]) -> Option<(u64, SessionId)> {
         if bytes.len() >= HEADER_BYTES {
             let mut cur = Cursor::new(bytes);
-            let _timestamp = cur.read_u64::<BigEndian>().unwrap();
+            let timestamp = cur.read_u64::<BigEndian>().unwrap() & !COMPRESSED_FLAG;
             let session_id = cur.read_u64::<BigEndian>().unwrap();
-            Some(SessionId(session_id))
+            Some((timestamp, SessionId(session_id)))
         } else {
             None
         }
     }
 
     fn from_slice(bytes: &[u8]) -> Option<Self> {
-        if bytes.len() >= HEADER_BYTES {
-            let mut cur = Cursor::new(bytes);
-            let timestamp = cur.read_u64::<BigEndian>().unwrap();
-            let session_id = cur.read_u64::<BigEndian>().unwrap();
-            let pos = cur.position();
-            let bytes = cur.into_inner();
-            let bytes = &bytes[pos as usize..];
-            if let Ok(data) = serde_cbor::from_slice(bytes) {
+        if let Some((timestamp, SessionId(session_id))) = Self::header_from_slice(bytes) {
+            let payload = &bytes[HEADER_BYTES..];
+            let (version, payload) = match payload.first() {
+                Some(&version) if version < MAX_SCHEMA_VERSION => (version, &payload[1..]),
+                _ => (0, payload),
+            };
+            if version > SCHEMA_VERSION {
+                // Written by a newer version of this code.
+                return None;
+            }
+            let decompressed;
+            let payload = if is_compressed(bytes) {
+                decompressed = zstd::decode_all(payload).ok()?;
+                &decompressed[..]
+            } else {
+                payload
+            };
+            if let Ok(data) = serde_cbor::from_slice(payload) {
                 let entry = Entry {
                     timestamp,
                     session_id,
@@ -486,19 +861,36 @@ impl Entry {
 }
 
 impl Entry {
-    fn to_vec(data: &Event, timestamp: u64, session_id: u64) -> Option<Vec<u8>> {
-        let mut buf = Vec::with_capacity(32);
-        buf.write_u64::<BigEndian>(timestamp).unwrap();
-        buf.write_u64::<BigEndian>(session_id).unwrap();
-
-        if serde_cbor::to_writer(&mut buf, data).is_ok() {
-            Some(buf)
+    /// Serialize an entry, compressing the payload if it is larger than
+    /// `compress_threshold` bytes.
+    fn to_vec(
+        data: &Event,
+        timestamp: u64,
+        session_id: u64,
+        compress_threshold: usize,
+    ) -> Option<Vec<u8>> {
+        let payload = serde_cbor::to_vec(data).ok()?;
+        let (flag, payload) = if payload.len() > compress_threshold {
+            let compressed = zstd::encode_all(&payload[..], COMPRESSION_LEVEL).ok()?;
+            (COMPRESSED_FLAG, compressed)
         } else {
-            None
-        }
+            (0, payload)
+        };
+
+        let mut buf = Vec::with_capacity(HEADER_BYTES + 1 + payload.len());
+        buf.write_u64::<BigEndian>(timestamp | flag).unwrap();
+        buf.write_u64::<BigEndian>(session_id).unwrap();
+        buf.push(SCHEMA_VERSION);
+        buf.extend_from_slice(&payload);
+        Some(buf)
     }
 }
 
+/// Test the COMPRESSED_FLAG of serialized entry.
+fn is_compressed(bytes: &[u8]) -> bool {
+    !bytes.is_empty() && bytes[0] & 0x80 != 0
+}
+
 fn u64_to_slice(value: u64) -> [u8; 8] {
     // The field can be used for index range query. So it has to be BE.
     unsafe { std::mem::transmute(value.to_be()) }
@@ -509,9 +901,24 @@ fn u64_to_boxed_slice(value: u64) -> Box<[u8]> {
 }
 
 fn time_to_u64(time: &SystemTime) -> u64 {
+    // Times before the epoch are clamped to 0.
     time.duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Find the largest timestamp written to the newest log.
+fn last_timestamp(log: &RotateLog) -> u64 {
+    log.logs()
+        .first()
+        .and_then(|log| log.lookup_range(INDEX_TIMESTAMP, ..).ok())
+        .and_then(|mut iter| iter.next_back())
+        .and_then(|pair| pair.ok())
+        .map(|(key, _values)| {
+            let mut cur = Cursor::new(key.as_ref());
+            cur.read_u64::<BigEndian>().unwrap_or(0)
+        })
+        .unwrap_or(0)
 }
 
 // The session_id is intended to be:
@@ -536,6 +943,9 @@ fn new_session_id() -> u64 {
 pub(crate) mod tests {
     use super::*;
     use serde_json::json;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
     use tempfile::tempdir;
 
     #[test]
@@ -616,6 +1026,595 @@ pub(crate) mod tests {
         assert_eq!(query(2), &events[4..5]);
     }
 
+    #[test]
+    fn test_fixed_session_ids() {
+        let mut blackbox = BlackboxOptions::new().create_in_memory().unwrap();
+        let first = SessionId(1 << 24);
+        let second = SessionId(2 << 24);
+
+        blackbox.set_session_id(first);
+        blackbox.set_session_annotation("user", json!("alice"));
+        blackbox.log(&Event::Debug { value: json!(1) });
+        assert_eq!(blackbox.session_id(), first);
+
+        blackbox.set_session_id(second);
+        blackbox.log(&Event::Debug { value: json!(2) });
+        blackbox.log(&Event::Alias {
+            from: "x".to_string(),
+            to: "y".to_string(),
+        });
+        assert_eq!(blackbox.session_id(), second);
+
+        let values = |session_id: SessionId| -> Vec<Event> {
+            blackbox
+                .entries_by_session_id(session_id)
+                .into_iter()
+                .map(|e| e.data)
+                .collect()
+        };
+        assert_eq!(
+            values(first),
+            [
+                Event::SessionAnnotations {
+                    annotations: vec![("user".to_string(), json!("alice"))]
+                        .into_iter()
+                        .collect(),
+                },
+                Event::Debug { value: json!(1) },
+            ]
+        );
+        assert_eq!(
+            values(second),
+            [
+                Event::Debug { value: json!(2) },
+                Event::Alias {
+                    from: "x".to_string(),
+                    to: "y".to_string(),
+                },
+            ]
+        );
+        assert!(values(SessionId(3 << 24)).is_empty());
+
+        assert_eq!(
+            blackbox.session_ids_by_pattern(&json!({"debug": "_"})),
+            [first, second].iter().cloned().collect()
+        );
+        assert_eq!(
+            blackbox.session_ids_by_pattern(&json!({"alias": {"from": "x"}})),
+            [second].iter().cloned().collect()
+        );
+
+        // Annotations don't leak into the next session.
+        assert_eq!(blackbox.session_annotations(first)["user"], json!("alice"));
+        assert!(blackbox.session_annotations(second).is_empty());
+    }
+
+    #[test]
+    fn test_import_from() {
+        let dir = tempdir().unwrap();
+        let src_path = dir.path().join("src");
+        let dst_path = dir.path().join("dst");
+        let first = SessionId(1 << 24);
+        let second = SessionId(2 << 24);
+
+        {
+            let mut src = BlackboxOptions::new()
+                .compress_threshold(100)
+                .open(&src_path)
+                .unwrap();
+            src.set_session_id(first);
+            src.set_session_annotation("host", json!("a"));
+            src.log(&Event::Debug { value: json!(1) });
+            src.set_session_id(second);
+            src.log(&Event::Alias {
+                from: "x".to_string(),
+                to: "y".to_string(),
+            });
+            // Large enough to be compressed.
+            src.log(&Event::Debug {
+                value: json!("z".repeat(1000)),
+            });
+        }
+
+        let mut dst = BlackboxOptions::new().open(&dst_path).unwrap();
+        dst.log(&Event::Debug { value: json!(0) });
+        let own_session_id = dst.session_id();
+        assert_eq!(dst.import_from(&src_path).unwrap(), 4);
+
+        let src = BlackboxOptions::new().open(&src_path).unwrap();
+        let entries = |blackbox: &Blackbox, session_id: SessionId| -> Vec<(u64, u64, Event)> {
+            blackbox
+                .entries_by_session_id(session_id)
+                .into_iter()
+                .map(|e| (e.timestamp, e.session_id, e.data))
+                .collect()
+        };
+        for &session_id in &[first, second] {
+            let imported = entries(&dst, session_id);
+            assert!(!imported.is_empty());
+            assert_eq!(imported, entries(&src, session_id));
+        }
+        assert_eq!(dst.session_annotations(first)["host"], json!("a"));
+        assert_eq!(
+            dst.session_ids_by_pattern(&json!({"alias": {"from": "x"}})),
+            [second].iter().cloned().collect()
+        );
+        assert_eq!(
+            dst.session_ids_by_pattern(&json!({"debug": "_"})),
+            [own_session_id, first, second].iter().cloned().collect()
+        );
+
+        // Imported entries are still there after reopening.
+        drop(dst);
+        let dst = BlackboxOptions::new().open(&dst_path).unwrap();
+        assert_eq!(entries(&dst, second), entries(&src, second));
+
+        // A missing blackbox is an error rather than an empty import.
+        let mut dst = dst;
+        assert!(dst.import_from(&dir.path().join("missing")).is_err());
+        assert!(!dir.path().join("missing").exists());
+    }
+
+    /// Paths and contents of all files under `dir`.
+    fn dir_contents(dir: &Path) -> BTreeMap<std::path::PathBuf, Vec<u8>> {
+        let mut contents = BTreeMap::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                contents.extend(dir_contents(&path));
+            } else {
+                contents.insert(path.clone(), fs::read(&path).unwrap());
+            }
+        }
+        contents
+    }
+
+    #[test]
+    fn test_import_from_leaves_source_unchanged() {
+        let dir = tempdir().unwrap();
+        let src_path = dir.path().join("src");
+        {
+            let mut src = BlackboxOptions::new().open(&src_path).unwrap();
+            src.log(&Event::Debug { value: json!(1) });
+        }
+        // A blackbox copied from another machine might come without its
+        // indexes. They must not be rebuilt in the source.
+        for (path, _) in dir_contents(&src_path) {
+            let name = path.file_name().unwrap().to_string_lossy();
+            if name.starts_with("index") {
+                fs::remove_file(&path).unwrap();
+            }
+        }
+        let before = dir_contents(&src_path);
+
+        let mut dst = BlackboxOptions::new().open(dir.path().join("dst")).unwrap();
+        assert_eq!(dst.import_from(&src_path).unwrap(), 1);
+        assert_eq!(dir_contents(&src_path), before);
+    }
+
+    /// A [`Clock`] that returns whatever time the test sets.
+    struct FakeClock(Arc<AtomicU64>);
+
+    impl Clock for FakeClock {
+        fn now(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH + Duration::from_millis(self.0.load(Ordering::SeqCst))
+        }
+    }
+
+    #[test]
+    fn test_clock_skew() {
+        let dir = tempdir().unwrap();
+        let time = Arc::new(AtomicU64::new(1000));
+        let mut blackbox = BlackboxOptions::new().open(&dir.path()).unwrap();
+        blackbox.set_clock(Box::new(FakeClock(time.clone())));
+
+        let event = |i: u64| Event::Debug { value: json!(i) };
+        blackbox.log(&event(0));
+        time.store(2000, Ordering::SeqCst);
+        blackbox.log(&event(1));
+        // The clock jumps backwards.
+        time.store(500, Ordering::SeqCst);
+        blackbox.log(&event(2));
+        time.store(2500, Ordering::SeqCst);
+        blackbox.log(&event(3));
+        assert_eq!(blackbox.clock_skew_corrections(), 1);
+
+        let query = |start: u64, end: u64| -> Vec<(u64, Event)> {
+            blackbox
+                .entries_by_timestamp_range(start, end)
+                .into_iter()
+                .map(|e| (e.timestamp, e.data))
+                .collect()
+        };
+        assert_eq!(
+            query(0, 3000),
+            [
+                (1000, event(0)),
+                (2000, event(1)),
+                (2000, event(2)),
+                (2500, event(3))
+            ]
+        );
+        // The skewed entry is found in the range of the last good timestamp.
+        assert_eq!(query(1500, 2200), [(2000, event(1)), (2000, event(2))]);
+        assert!(query(0, 900).is_empty());
+
+        // The last timestamp is restored when the blackbox is reopened.
+        blackbox.sync();
+        drop(blackbox);
+        let mut blackbox = BlackboxOptions::new().open(&dir.path()).unwrap();
+        blackbox.set_clock(Box::new(FakeClock(time.clone())));
+        time.store(100, Ordering::SeqCst);
+        blackbox.log(&event(4));
+        assert_eq!(blackbox.clock_skew_corrections(), 1);
+        assert_eq!(
+            blackbox
+                .entries_by_timestamp_range(2500, 2500)
+                .into_iter()
+                .map(|e| e.data)
+                .collect::<Vec<_>>(),
+            [event(3), event(4)]
+        );
+    }
+
+    #[test]
+    fn test_log_batch() {
+        let dir = tempdir().unwrap();
+        let time = Arc::new(AtomicU64::new(1000));
+        let mut blackbox = BlackboxOptions::new().open(&dir.path()).unwrap();
+        blackbox.set_clock(Box::new(FakeClock(time.clone())));
+        let event = |i: u64| Event::Debug { value: json!(i) };
+        let query = |blackbox: &Blackbox, start: u64, end: u64| -> Vec<(u64, Event)> {
+            blackbox
+                .entries_by_timestamp_range(start, end)
+                .into_iter()
+                .map(|e| (e.timestamp, e.data))
+                .collect()
+        };
+
+        assert_eq!(blackbox.log_batch(&[event(0), event(1)]), Some(1000));
+        time.store(2000, Ordering::SeqCst);
+        blackbox.log(&event(2));
+        assert_eq!(
+            query(&blackbox, 1000, 1000),
+            [(1000, event(0)), (1000, event(1))]
+        );
+
+        // A serialization failure of one event drops the whole group,
+        // including the pending annotations, which are written later.
+        time.store(3000, Ordering::SeqCst);
+        blackbox.set_session_annotation("command", json!("log"));
+        let session_id = blackbox.session_id;
+        let compress_threshold = blackbox.opts.compress_threshold;
+        let batch = [event(3), event(4), event(5)];
+        let logged = blackbox.log_batch_with(&batch, |data, timestamp| {
+            if data == &event(4) {
+                None
+            } else {
+                Entry::to_vec(data, timestamp, session_id, compress_threshold)
+            }
+        });
+        assert_eq!(logged, None);
+        assert!(query(&blackbox, 3000, 3000).is_empty());
+
+        time.store(4000, Ordering::SeqCst);
+        assert_eq!(blackbox.log_batch(&batch), Some(4000));
+        let entries = query(&blackbox, 4000, 4000);
+        assert_eq!(entries.len(), 4);
+        match &entries[0].1 {
+            Event::SessionAnnotations { .. } => {}
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert_eq!(
+            entries[1..],
+            [(4000, event(3)), (4000, event(4)), (4000, event(5))]
+        );
+
+        // Nothing is logged for an empty group.
+        assert_eq!(blackbox.log_batch(&[]), None);
+    }
+
+    #[test]
+    fn test_log_custom() {
+        let dir = tempdir().unwrap();
+        let mut blackbox = BlackboxOptions::new()
+            .max_custom_value_bytes(20)
+            .open(&dir.path())
+            .unwrap();
+
+        let small = json!({"rebased": 3});
+        let large = json!({"rebased": ["aaaaaaaaaa", "bbbbbbbbbb"]});
+        let mut session_ids = Vec::new();
+        blackbox.log_custom("pushrebase", &small);
+        session_ids.push(blackbox.session_id());
+        blackbox.refresh_session_id();
+        blackbox.log_custom("land", &large);
+        session_ids.push(blackbox.session_id());
+
+        let query = |pattern: serde_json::Value| -> Vec<usize> {
+            let ids = blackbox.session_ids_by_pattern(&pattern);
+            session_ids
+                .iter()
+                .enumerate()
+                .filter(|(_i, session_id)| ids.contains(session_id))
+                .map(|(i, _)| i)
+                .collect()
+        };
+        assert_eq!(query(json!({"custom": "_"})), [0, 1]);
+        assert_eq!(query(json!({"custom": {"kind": "pushrebase"}})), [0]);
+        assert_eq!(
+            query(json!({"custom": {"kind": "pushrebase", "value": {"rebased": 3}}})),
+            [0]
+        );
+        assert_eq!(query(json!({"custom": {"kind": "land"}})), [1]);
+        assert_eq!(query(json!({"custom": {"truncated": true}})), [1]);
+        assert!(query(json!({"custom": {"kind": "other"}})).is_empty());
+
+        let entries = blackbox.entries_by_session_id(session_ids[0]);
+        assert_eq!(
+            entries[0].data,
+            Event::Custom {
+                kind: "pushrebase".to_string(),
+                value: small,
+                truncated: false,
+            }
+        );
+        let entries = blackbox.entries_by_session_id(session_ids[1]);
+        assert_eq!(
+            entries[0].data,
+            Event::Custom {
+                kind: "land".to_string(),
+                value: json!(&large.to_string()[..20]),
+                truncated: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_recent_sessions() {
+        let dir = tempdir().unwrap();
+        let time = Arc::new(AtomicU64::new(1000));
+        let mut blackbox = BlackboxOptions::new().open(&dir.path()).unwrap();
+        blackbox.set_clock(Box::new(FakeClock(time.clone())));
+
+        let event = |i: u64| Event::Debug { value: json!(i) };
+        let mut session_ids = Vec::new();
+
+        // Session 0: 3 events within 1000..1500.
+        blackbox.log(&event(0));
+        time.store(1200, Ordering::SeqCst);
+        blackbox.log(&event(1));
+        time.store(1500, Ordering::SeqCst);
+        blackbox.log(&event(2));
+        session_ids.push(blackbox.session_id());
+
+        // Session 1: a single event at 2000.
+        blackbox.refresh_session_id();
+        time.store(2000, Ordering::SeqCst);
+        blackbox.log(&event(3));
+        session_ids.push(blackbox.session_id());
+
+        // Session 2: 2 events within 3000..3100.
+        blackbox.refresh_session_id();
+        time.store(3000, Ordering::SeqCst);
+        blackbox.log(&event(4));
+        time.store(3100, Ordering::SeqCst);
+        blackbox.log(&event(5));
+        session_ids.push(blackbox.session_id());
+
+        let summary =
+            |i: usize, start_timestamp: u64, duration_ms: u64, event_count: usize| SessionSummary {
+                session_id: session_ids[i],
+                start_timestamp,
+                duration_ms,
+                event_count,
+            };
+        assert_eq!(
+            blackbox.recent_sessions(10),
+            [
+                summary(2, 3000, 100, 2),
+                summary(1, 2000, 0, 1),
+                summary(0, 1000, 500, 3)
+            ]
+        );
+        assert_eq!(
+            blackbox.recent_sessions(2),
+            [summary(2, 3000, 100, 2), summary(1, 2000, 0, 1)]
+        );
+        assert!(blackbox.recent_sessions(0).is_empty());
+    }
+
+    #[test]
+    fn test_session_annotations() {
+        let dir = tempdir().unwrap();
+        let mut blackbox = BlackboxOptions::new().open(&dir.path()).unwrap();
+        let event = |i: u64| Event::Debug { value: json!(i) };
+        let is_annotations = |entry: &Entry| matches!(entry.data, Event::SessionAnnotations { .. });
+
+        // Session 0: annotations are written once, before the next event.
+        blackbox.set_session_annotation("command", json!("log"));
+        blackbox.set_session_annotation("cwd", json!("/repo"));
+        blackbox.log(&event(0));
+        blackbox.log(&event(1));
+        blackbox.set_session_annotation("command", json!("status"));
+        blackbox.log(&event(2));
+        let session_0 = blackbox.session_id();
+
+        // Session 1: no annotations.
+        blackbox.refresh_session_id();
+        blackbox.log(&event(3));
+        let session_1 = blackbox.session_id();
+
+        // Session 2: annotations of its own.
+        blackbox.refresh_session_id();
+        blackbox.set_session_annotation("cwd", json!("/other"));
+        blackbox.log(&event(4));
+        let session_2 = blackbox.session_id();
+
+        let entries = blackbox.entries_by_session_id(session_0);
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries.iter().filter(|e| is_annotations(e)).count(), 2);
+        let events: Vec<_> = entries
+            .into_iter()
+            .filter(|e| !is_annotations(e))
+            .map(|e| e.data)
+            .collect();
+        assert_eq!(events, [event(0), event(1), event(2)]);
+
+        let annotations = |session_id| json!(blackbox.session_annotations(session_id)).to_string();
+        assert_eq!(
+            annotations(session_0),
+            r#"{"command":"status","cwd":"/repo"}"#
+        );
+        assert_eq!(annotations(session_1), "{}");
+        assert_eq!(annotations(session_2), r#"{"cwd":"/other"}"#);
+
+        // Annotations survive reopening the log.
+        blackbox.sync();
+        drop(blackbox);
+        let blackbox = BlackboxOptions::new().open(&dir.path()).unwrap();
+        assert_eq!(
+            json!(blackbox.session_annotations(session_0)).to_string(),
+            r#"{"command":"status","cwd":"/repo"}"#
+        );
+    }
+
+    #[test]
+    fn test_compression() {
+        let dir = tempdir().unwrap();
+        let time = Arc::new(AtomicU64::new(1000));
+        let mut blackbox = BlackboxOptions::new().open(&dir.path()).unwrap();
+        blackbox.set_clock(Box::new(FakeClock(time.clone())));
+
+        let huge = |i: u64| json!(vec![json!({"index": i, "name": "x".repeat(100)}); 1000]);
+        let events = [
+            Event::Debug { value: json!(0) },
+            Event::Debug { value: huge(1) },
+            Event::Custom {
+                kind: "small".to_string(),
+                value: json!("s"),
+                truncated: false,
+            },
+            Event::Custom {
+                kind: "huge".to_string(),
+                value: huge(3),
+                truncated: false,
+            },
+            Event::Debug { value: huge(4) },
+        ];
+        let mut raw_bytes = 0;
+        for (i, event) in events.iter().enumerate() {
+            time.store(1000 + i as u64, Ordering::SeqCst);
+            blackbox.log(event);
+            raw_bytes += serde_cbor::to_vec(event).unwrap().len() as u64;
+        }
+        blackbox.sync();
+
+        let disk_bytes: u64 = walk_file_sizes(dir.path());
+        assert!(
+            disk_bytes * 10 < raw_bytes,
+            "{} bytes on disk for {} bytes of payloads",
+            disk_bytes,
+            raw_bytes
+        );
+
+        let (timestamps, data): (Vec<u64>, Vec<Event>) = blackbox
+            .entries_by_session_id(blackbox.session_id())
+            .into_iter()
+            .map(|e| (e.timestamp, e.data))
+            .unzip();
+        assert_eq!(timestamps, [1000, 1001, 1002, 1003, 1004]);
+        assert_eq!(data, events);
+
+        let data: Vec<Event> = blackbox
+            .entries_by_timestamp_range(1001, 1003)
+            .into_iter()
+            .map(|e| e.data)
+            .collect();
+        assert_eq!(data, &events[1..4]);
+
+        // Both the indexed and the full-scan query paths see compressed payloads.
+        let session_ids = vec![blackbox.session_id()];
+        let query = |pattern| -> Vec<SessionId> {
+            blackbox
+                .session_ids_by_pattern(&pattern)
+                .into_iter()
+                .collect()
+        };
+        assert_eq!(query(json!({"custom": {"kind": "huge"}})), session_ids);
+        assert_eq!(
+            query(json!({"debug": {"value": ["contain", {"index": 4, "name": "_"}]}})),
+            session_ids
+        );
+        assert!(query(json!({"custom": {"kind": "missing"}})).is_empty());
+
+        drop(blackbox);
+        let blackbox = BlackboxOptions::new().open(&dir.path()).unwrap();
+        assert_eq!(blackbox.last_timestamp, 1004);
+        assert_eq!(all_entries(&blackbox).len(), events.len());
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let new_event = || Event::Custom {
+            kind: "kind".to_string(),
+            value: json!({"x": "y".repeat(100)}),
+            truncated: false,
+        };
+        let event = new_event();
+        let decode = |bytes: &[u8]| {
+            Entry::from_slice(bytes).map(|entry| (entry.timestamp, entry.session_id, entry.data))
+        };
+        let expected = || Some((1000, 7, new_event()));
+
+        // Versioned entries, with and without compression.
+        for &threshold in &[usize::MAX, 0] {
+            let bytes = Entry::to_vec(&event, 1000, 7, threshold).unwrap();
+            assert_eq!(bytes[HEADER_BYTES], SCHEMA_VERSION);
+            assert_eq!(is_compressed(&bytes), threshold == 0);
+            assert_eq!(decode(&bytes), expected());
+        }
+
+        // Legacy entries, written without the version byte.
+        let payload = serde_cbor::to_vec(&event).unwrap();
+        let legacy = |flag: u64, payload: &[u8]| {
+            let mut buf = Vec::new();
+            buf.write_u64::<BigEndian>(1000 | flag).unwrap();
+            buf.write_u64::<BigEndian>(7).unwrap();
+            buf.extend_from_slice(payload);
+            buf
+        };
+        assert_eq!(decode(&legacy(0, &payload)), expected());
+        let compressed = zstd::encode_all(&payload[..], COMPRESSION_LEVEL).unwrap();
+        assert_eq!(decode(&legacy(COMPRESSED_FLAG, &compressed)), expected());
+
+        // Entries from a future schema version can't be decoded.
+        let mut bytes = Entry::to_vec(&event, 1000, 7, usize::MAX).unwrap();
+        bytes[HEADER_BYTES] = SCHEMA_VERSION + 1;
+        assert_eq!(decode(&bytes), None);
+    }
+
+    fn walk_file_sizes(path: &Path) -> u64 {
+        fs::read_dir(path)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let metadata = entry.metadata().unwrap();
+                if metadata.is_dir() {
+                    walk_file_sizes(&entry.path())
+                } else {
+                    metadata.len()
+                }
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_time_before_epoch() {
+        let time = SystemTime::UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(time_to_u64(&time), 0);
+    }
+
     pub(crate) fn all_entries(blackbox: &Blackbox) -> Vec<Entry> {
         let session_ids = blackbox.session_ids_by_pattern(&json!("_"));
         session_ids
@@ -116,6 +116,28 @@ pub enum Event {
         peer_name: String,
     },
 
+    /// Structured event defined outside this module, logged via
+    /// `Blackbox::log_custom`.
+    ///
+    /// If the serialized `value` was too large, it is replaced by a string
+    /// holding a prefix of the serialized JSON, and `truncated` is set.
+    #[serde(rename = "CU", alias = "custom")]
+    Custom {
+        #[serde(rename = "K", alias = "kind")]
+        kind: String,
+
+        #[serde(rename = "V", alias = "value")]
+        value: Value,
+
+        #[serde(
+            rename = "X",
+            alias = "truncated",
+            default,
+            skip_serializing_if = "is_default"
+        )]
+        truncated: bool,
+    },
+
     /// Free-form debug message.
     #[serde(rename = "D", alias = "debug")]
     Debug {
@@ -334,6 +356,18 @@ pub enum Event {
         name: String,
     },
 
+    /// Key/value annotations of the session, set via
+    /// `Blackbox::set_session_annotation`.
+    ///
+    /// Holds all annotations of the session at the time it was written. It is
+    /// written once after the annotations change, instead of being repeated
+    /// in every event.
+    #[serde(rename = "SA", alias = "session_annotations")]
+    SessionAnnotations {
+        #[serde(rename = "A", alias = "annotations")]
+        annotations: BTreeMap<String, Value>,
+    },
+
     /// Immutable process environment.
     #[serde(rename = "S", alias = "start")]
     Start {
@@ -623,6 +657,20 @@ impl fmt::Display for Event {
             ClientTelemetry { peer_name } => {
                 write!(f, "[clienttelemetry] peer name: {}", peer_name)?
             }
+            Custom {
+                kind,
+                value,
+                truncated,
+            } => {
+                let truncated = if *truncated { " (truncated)" } else { "" };
+                write!(
+                    f,
+                    "[custom][{}] {}{}",
+                    kind,
+                    json_to_string(value),
+                    truncated
+                )?
+            }
             Debug { value } => write!(f, "[debug] {}", json_to_string(value))?,
             Exception { msg } => write!(f, "[command_exception] {}", msg)?,
             Finish {
@@ -688,6 +736,17 @@ impl fmt::Display for Event {
                     op, calls, duration_ms, latency_ms, read_bytes, write_bytes, session_id, url, result,
                 )?;
             }
+            SessionAnnotations { annotations } => {
+                write!(
+                    f,
+                    "[session_annotations] {}",
+                    annotations
+                        .iter()
+                        .map(|(k, v)| format!("{}={}", k, json_to_string(v)))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                )?;
+            }
             Start {
                 pid,
                 uid,
@@ -769,6 +828,16 @@ mod tests {
             "[config] non-interactive a.b=1 a.c=2"
         );
 
+        assert_eq!(
+            f(r#"{"custom":{"kind":"pushrebase","value":{"rebased":3}}}"#),
+            "[custom][pushrebase] {\"rebased\":3}"
+        );
+
+        assert_eq!(
+            f(r#"{"custom":{"kind":"pushrebase","value":"{\"reb","truncated":true}}"#),
+            "[custom][pushrebase] \"{\\\"reb\" (truncated)"
+        );
+
         assert_eq!(
             f(r#"{"debug":{"value":["debug","msg"]}}"#),
             "[debug] [\"debug\",\"msg\"]"
@@ -784,6 +853,11 @@ mod tests {
             "[process_tree] node (3) -> bash (2) -> systemd (1) -> (this process)"
         );
 
+        assert_eq!(
+            f(r#"{"session_annotations":{"annotations":{"command":"log","cwd":"/repo"}}}"#),
+            "[session_annotations] command=\"log\" cwd=\"/repo\""
+        );
+
         assert_eq!(
             f(r#"{"watchman":{"args":["state-enter","update",{"rev":"abcd"}],"duration_ms":42}}"#),
             "[watchman] command [\"state-enter\",\"update\",{\"rev\":\"abcd\"}] finished in 42 ms"
@@ -19,7 +19,9 @@ mod blackbox;
 mod match_pattern;
 mod singleton;
 
-pub use self::blackbox::{Blackbox, BlackboxOptions, Entry, SessionId, ToValue};
+pub use self::blackbox::{
+    Blackbox, BlackboxOptions, Clock, Entry, SessionId, SessionSummary, SystemClock, ToValue,
+};
 pub use self::singleton::{init, log, sync, SINGLETON};
 pub use match_pattern::{capture_pattern, match_pattern};
 pub use serde_json::{self, json, Value};
@@ -36,11 +36,16 @@ pub fn init(mut blackbox: Blackbox) {
         }
     }
 
-    // Perserve session_id if pid hasn't been changed.
+    // Perserve session_id and its annotations if pid hasn't been changed.
     if blackbox.session_pid() == old_blackbox.session_pid() {
         blackbox.session_id = old_blackbox.session_id;
+        blackbox.annotations = std::mem::take(&mut old_blackbox.annotations);
+        blackbox.annotations_dirty = old_blackbox.annotations_dirty;
     }
 
+    // Keep timestamps monotonic across the buffered and the new entries.
+    blackbox.last_timestamp = blackbox.last_timestamp.max(old_blackbox.last_timestamp);
+
     *singleton.deref_mut() = blackbox;
 }
 
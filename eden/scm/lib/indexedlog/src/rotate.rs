@@ -323,6 +323,32 @@ impl RotateLog {
         .context("in RotateLog::append")
     }
 
+    /// Append a group of entries to the writable [`Log`].
+    ///
+    /// Unlike calling [`RotateLog::append`] for each entry, the
+    /// `auto_sync_threshold` is only checked after all entries are appended,
+    /// so a sync never writes part of the group.
+    pub fn append_batch<T: AsRef<[u8]>>(
+        &mut self,
+        batch: impl IntoIterator<Item = T>,
+    ) -> crate::Result<()> {
+        (|| -> crate::Result<_> {
+            let threshold = self.open_options.auto_sync_threshold;
+            let log = self.writable_log();
+            for data in batch {
+                log.append(data)?;
+            }
+            if let Some(threshold) = threshold {
+                if log.mem_buf.len() as u64 >= threshold {
+                    self.sync()
+                        .context("sync triggered by auto_sync_threshold")?;
+                }
+            }
+            Ok(())
+        })()
+        .context("in RotateLog::append_batch")
+    }
+
     /// Look up an entry using the given index. The `index_id` is the index of
     /// `index_defs` stored in [`OpenOptions`].
     pub fn lookup(
@@ -1345,6 +1371,23 @@ mod tests {
         assert_eq!(rotate.logs()[0].iter_dirty().count(), 0);
     }
 
+    #[test]
+    fn test_append_batch() {
+        let dir = tempdir().unwrap();
+        let opts = OpenOptions::new().auto_sync_threshold(100).create(true);
+
+        let mut rotate = opts.clone().create(true).open(&dir).unwrap();
+        rotate.append_batch(vec![vec![b'x'; 20]; 2]).unwrap();
+        assert_eq!(rotate.logs()[0].iter_dirty().count(), 2);
+        // Crossing the threshold in the middle of the group does not sync it
+        // partially.
+        rotate.append_batch(vec![vec![b'y'; 50]; 3]).unwrap(); // trigger sync
+        assert_eq!(rotate.logs()[0].iter_dirty().count(), 0);
+
+        let rotate = opts.open(&dir).unwrap();
+        assert_eq!(rotate.iter().count(), 5);
+    }
+
     #[test]
     fn test_reindex_old_logs() {
         let dir = tempdir().unwrap();
@@ -85,7 +85,9 @@ use std::{
     fs::File,
     io::{Cursor, Read},
     mem::{drop, take},
+    ops::Range,
     path::{Path, PathBuf},
+    ptr,
     sync::Arc,
 };
 
@@ -108,6 +110,8 @@ use crate::{
     types::StoreKey,
 };
 
+const PAGE_SIZE: u64 = 4096;
+
 #[derive(Debug, Error)]
 #[error("Datapack Error: {0:?}")]
 struct DataPackError(String);
@@ -314,6 +318,44 @@ impl DataPack {
     pub fn index_path(&self) -> &Path {
         &self.index_path
     }
+
+    /// Read the pack regions holding the delta chains of `keys`, so that they are in the OS page
+    /// cache for subsequent reads. Returns the keys that aren't in this pack.
+    pub fn prefetch(&self, keys: &[Key]) -> Result<Vec<Key>> {
+        let mut missing = Vec::new();
+        for key in keys {
+            let mut next_entry = match self.index.get_entry(&key.hgid)? {
+                None => {
+                    missing.push(key.clone());
+                    continue;
+                }
+                Some(entry) => entry,
+            };
+            // Same bound as `get_delta_chain`, in case the delta chain is corrupted.
+            for _ in 0..1000 {
+                let data_entry = self.read_entry(next_entry.pack_entry_offset())?;
+                self.touch(data_entry.offset..data_entry.next_offset);
+
+                if let DeltaBaseOffset::Offset(offset) = next_entry.delta_base_offset() {
+                    next_entry = self.index.read_entry(offset as usize)?;
+                } else {
+                    break;
+                }
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Fault in the pages of the mmap covering `range`.
+    fn touch(&self, range: Range<u64>) {
+        let start = range.start - range.start % PAGE_SIZE;
+        for offset in (start..range.end).step_by(PAGE_SIZE as usize) {
+            if let Some(byte) = self.mmap.get(offset as usize) {
+                // Volatile, so that the read isn't optimized away.
+                unsafe { ptr::read_volatile(byte) };
+            }
+        }
+    }
 }
 
 impl HgIdDataStore for DataPack {
@@ -512,6 +554,40 @@ pub mod tests {
         assert_eq!(missing, vec![StoreKey::from(not)]);
     }
 
+    #[test]
+    fn test_prefetch() {
+        let tempdir = TempDir::new().unwrap();
+
+        let revisions = vec![
+            (
+                Delta {
+                    data: Bytes::from(&[1, 2, 3, 4][..]),
+                    base: None,
+                    key: key("a", "1"),
+                },
+                Default::default(),
+            ),
+            (
+                Delta {
+                    data: Bytes::from(&[5, 6, 7, 8][..]),
+                    base: Some(key("a", "1")),
+                    key: key("a", "2"),
+                },
+                Default::default(),
+            ),
+        ];
+        let pack = make_datapack(&tempdir, &revisions);
+
+        let not = key("b", "3");
+        let missing = pack
+            .prefetch(&[key("a", "2"), not.clone(), key("a", "1")])
+            .unwrap();
+        assert_eq!(missing, vec![not]);
+
+        let chain = pack.get_delta_chain(&key("a", "2")).unwrap().unwrap();
+        assert_eq!(chain, vec![revisions[1].0.clone(), revisions[0].0.clone()]);
+    }
+
     #[test]
     fn test_get_meta() {
         let tempdir = TempDir::new().unwrap();
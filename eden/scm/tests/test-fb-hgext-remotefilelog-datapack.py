@@ -8,6 +8,7 @@ import shutil
 import stat
 import struct
 import tempfile
+import threading
 import time
 import unittest
 
@@ -136,6 +137,27 @@ class datapacktestsbase(object):
             content = "abcdef%s" % i
             self.assertEqual(content, chain[-i - 1][4])
 
+    def testGetDeltaChainLen(self):
+        """Test that getdeltachainlen matches the content sizes from getdeltachain.
+        """
+        revisions = []
+        filename = "foo"
+        lastnode = nullid
+        for i in range(10):
+            content = "abcdef%s" % ("x" * i)
+            node = self.getHash(content)
+            revisions.append((filename, node, lastnode, content))
+            lastnode = node
+
+        pack = self.createPack(revisions)
+
+        for filename, node, base, content in revisions:
+            chain = pack.getdeltachain(filename, node)
+            lengths = pack.getdeltachainlen(filename, node)
+            self.assertEqual(lengths, [len(link[4]) for link in chain])
+
+        self.assertRaises(KeyError, pack.getdeltachainlen, filename, self.getFakeHash())
+
     def testPackMany(self):
         """Pack many related and unrelated objects.
         """
@@ -230,6 +252,119 @@ class datapacktestsbase(object):
         except error.RustError:
             pass
 
+    def testContextManager(self):
+        """Test using a datapack in a `with` block, and that it's closed on exit.
+        """
+        content = "abcdef"
+        node = self.getHash(content)
+        path = self.createPack([("foo", node, nullid, content)]).path()
+
+        with self.datapackreader(path) as pack:
+            chain = pack.getdeltachain("foo", node)
+            self.assertEqual(content, chain[0][4])
+
+        self.assertRaises(error.RustError, pack.getdeltachain, "foo", node)
+
+    def testMutableDataPack(self):
+        """Test building a pack with mutabledatapack and reading it back.
+        """
+        revisions = []
+        filename = "foo"
+        lastnode = nullid
+        for i in range(10):
+            content = "abcdef%s" % i
+            node = self.getHash(content)
+            revisions.append((filename, node, lastnode, content))
+            lastnode = node
+
+        packdir = self.makeTempDir()
+        packer = revisionstore.mutabledatapack(packdir)
+        for filename, node, base, content in revisions:
+            packer.add(filename, node, content, base)
+        path = packer.close()
+        self.assertTrue(path.startswith(packdir))
+
+        self.assertRaises(error.RustError, packer.close)
+        self.assertRaises(
+            error.RustError, packer.add, filename, self.getFakeHash(), "", nullid
+        )
+
+        pack = self.datapackreader(path)
+        for filename, node, base, content in revisions:
+            entry = pack.getdelta(filename, node)
+            self.assertEqual((content, filename, base, {}), entry)
+
+        chain = pack.getdeltachain(filename, lastnode)
+        self.assertEqual(len(revisions), len(chain))
+
+        # An empty pack has nothing to write.
+        empty = revisionstore.mutabledatapack(self.makeTempDir())
+        self.assertRaises(error.RustError, empty.close)
+
+    def testPrefetch(self):
+        """Test that prefetching keys doesn't change what's read, and reports missing keys.
+        """
+        revisions = []
+        filename = "foo"
+        lastnode = nullid
+        for i in range(10):
+            content = "abcdef%s" % i
+            node = self.getHash(content)
+            revisions.append((filename, node, lastnode, content))
+            lastnode = node
+
+        pack = self.createPack(revisions)
+
+        fakenode = self.getFakeHash()
+        keys = [(filename, node) for filename, node, base, content in revisions]
+        missing = pack.prefetch(keys + [(filename, fakenode)])
+        self.assertEqual(missing, [(filename, fakenode)])
+
+        for filename, node, base, content in revisions:
+            chain = pack.getdeltachain(filename, node)
+            self.assertEqual(content, chain[0][4])
+
+    def testThreadSafeDataStore(self):
+        """Test concurrent reads through a threadsafedatastore.
+        """
+
+        class dictstore(object):
+            def __init__(self, contents):
+                self.contents = contents
+
+            def get(self, name, node):
+                return self.contents[(name, node)]
+
+            def getmissing(self, keys):
+                return [key for key in keys if key not in self.contents]
+
+        contents = {}
+        for i in range(20):
+            content = "abcdef%s" % i
+            contents[("foo%s" % i, self.getHash(content))] = content
+        store = revisionstore.threadsafedatastore(dictstore(contents))
+
+        errors = []
+
+        def reader():
+            try:
+                for (filename, node), content in contents.items():
+                    self.assertEqual(content, store.get(filename, node))
+            except Exception as ex:
+                errors.append(ex)
+
+        threads = [threading.Thread(target=reader) for i in range(8)]
+        for thread in threads:
+            thread.start()
+        for thread in threads:
+            thread.join()
+        self.assertEqual(errors, [])
+
+        presentkey = next(iter(contents))
+        missingkey = ("foo0", self.getFakeHash())
+        self.assertEqual(store.getmissing([presentkey, missingkey]), [missingkey])
+        self.assertRaises(KeyError, store.get, *missingkey)
+
     def testMissingDeltabase(self):
         fakenode = self.getFakeHash()
         revisions = [("filename", fakenode, self.getFakeHash(), "content")]